@@ -32,7 +32,10 @@ use tonic::transport::ClientTlsConfig;
 use tracing::debug;
 #[cfg(madsim)]
 use utils::ClientTlsConfig;
-use utils::{build_endpoint, config::ClientConfig};
+use utils::{
+    build_endpoint,
+    config::{ClientConfig, MessageSizeLimits},
+};
 
 use self::{
     retry::{Retry, RetryConfig},
@@ -178,6 +181,8 @@ pub struct ClientBuilder {
     config: ClientConfig,
     /// Client tls config
     tls_config: Option<ClientTlsConfig>,
+    /// The message size limits of the connections
+    message_size: MessageSizeLimits,
 }
 
 /// A client builder with bypass with local server
@@ -251,6 +256,14 @@ impl ClientBuilder {
         self
     }
 
+    /// Set the message size limits of the connections to the servers
+    #[inline]
+    #[must_use]
+    pub fn message_size(mut self, message_size: MessageSizeLimits) -> Self {
+        self.message_size = message_size;
+        self
+    }
+
     /// Discover the initial states from some endpoints
     ///
     /// # Errors
@@ -308,6 +321,7 @@ impl ClientBuilder {
             }),
             self.tls_config.clone(),
         );
+        builder.set_message_size(self.message_size);
         if let Some(version) = self.cluster_version {
             builder.set_cluster_version(version);
         }
@@ -10,6 +10,7 @@ use tokio::sync::RwLock;
 #[cfg(not(madsim))]
 use tonic::transport::ClientTlsConfig;
 use tracing::{debug, info};
+use utils::config::MessageSizeLimits;
 #[cfg(madsim)]
 use utils::ClientTlsConfig;
 
@@ -44,6 +45,8 @@ struct StateStatic {
     leader_notifier: Arc<Event>,
     /// Client tls config
     tls_config: Option<ClientTlsConfig>,
+    /// The message size limits of the connections
+    message_size: MessageSizeLimits,
 }
 
 /// Mutable client state
@@ -91,6 +94,7 @@ impl State {
                 local_server,
                 leader_notifier: Arc::new(Event::new()),
                 tls_config,
+                message_size: MessageSizeLimits::default(),
                 is_raw_curp: true,
             },
             client_id: Arc::new(AtomicU64::new(0)),
@@ -273,7 +277,13 @@ impl State {
                     .remove(&diff)
                     .unwrap_or_else(|| unreachable!("{diff} must in new member addrs"));
                 debug!("client connects to a new server({diff}), address({addrs:?})");
-                let new_conn = rpc::connect(diff, addrs, self.immutable.tls_config.clone()).await?;
+                let new_conn = rpc::connect(
+                    diff,
+                    addrs,
+                    self.immutable.tls_config.clone(),
+                    self.immutable.message_size,
+                )
+                .await?;
                 let _ig = e.insert(new_conn);
             } else {
                 debug!("client removes old server({diff})");
@@ -306,6 +316,8 @@ pub(super) struct StateBuilder {
     cluster_version: Option<u64>,
     /// Client Tls config
     tls_config: Option<ClientTlsConfig>,
+    /// The message size limits of the connections (optional)
+    message_size: MessageSizeLimits,
     /// is current client send request to raw curp server
     is_raw_curp: bool,
 }
@@ -321,10 +333,16 @@ impl StateBuilder {
             leader_state: None,
             cluster_version: None,
             tls_config,
+            message_size: MessageSizeLimits::default(),
             is_raw_curp: false,
         }
     }
 
+    /// Set the message size limits of the connections (optional)
+    pub(super) fn set_message_size(&mut self, message_size: MessageSizeLimits) {
+        self.message_size = message_size;
+    }
+
     /// Set is raw curp
     pub(super) fn set_is_raw_curp(&mut self, is_raw_curp: bool) {
         self.is_raw_curp = is_raw_curp;
@@ -349,10 +367,13 @@ impl StateBuilder {
         debug!("client bypassed server({local_server_id})");
 
         let _ig = self.all_members.remove(&local_server_id);
-        let mut connects: HashMap<_, _> =
-            rpc::connects(self.all_members.clone(), self.tls_config.as_ref())
-                .await?
-                .collect();
+        let mut connects: HashMap<_, _> = rpc::connects(
+            self.all_members.clone(),
+            self.tls_config.as_ref(),
+            self.message_size,
+        )
+        .await?
+        .collect();
         let __ig = connects.insert(
             local_server_id,
             Arc::new(BypassedConnect::new(local_server_id, local_server)),
@@ -369,6 +390,7 @@ impl StateBuilder {
                 local_server: Some(local_server_id),
                 leader_notifier: Arc::new(Event::new()),
                 tls_config: self.tls_config.take(),
+                message_size: self.message_size,
                 is_raw_curp: self.is_raw_curp,
             },
             client_id: Arc::new(AtomicU64::new(0)),
@@ -377,10 +399,13 @@ impl StateBuilder {
 
     /// Build the state
     pub(super) async fn build(self) -> Result<State, tonic::transport::Error> {
-        let connects: HashMap<_, _> =
-            rpc::connects(self.all_members.clone(), self.tls_config.as_ref())
-                .await?
-                .collect();
+        let connects: HashMap<_, _> = rpc::connects(
+            self.all_members.clone(),
+            self.tls_config.as_ref(),
+            self.message_size,
+        )
+        .await?
+        .collect();
         Ok(State {
             mutable: RwLock::new(StateMut {
                 leader: self.leader_state.map(|state| state.0),
@@ -392,6 +417,7 @@ impl StateBuilder {
                 local_server: None,
                 leader_notifier: Arc::new(Event::new()),
                 tls_config: self.tls_config,
+                message_size: self.message_size,
                 is_raw_curp: self.is_raw_curp,
             },
             client_id: Arc::new(AtomicU64::new(0)),
@@ -8,12 +8,15 @@ use std::{
     time::Duration,
 };
 
+use async_stream::stream;
 use dashmap::{mapref::one::Ref, DashMap};
-use futures::{stream::FuturesUnordered, StreamExt};
+use event_listener::{Event, EventListener};
+use futures::{stream::FuturesUnordered, Stream, StreamExt};
 use itertools::Itertools;
 #[cfg(not(madsim))]
 use tonic::transport::ClientTlsConfig;
 use tracing::{debug, info};
+use utils::config::MessageSizeLimits;
 #[cfg(madsim)]
 use utils::ClientTlsConfig;
 
@@ -71,6 +74,43 @@ impl Member {
     }
 }
 
+/// An update of the cluster members
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum MemberUpdate {
+    /// All the members, which is the first update of a member watch
+    Snapshot(Vec<Member>),
+    /// A member is added
+    Add(Member),
+    /// A member is removed
+    Remove(ServerId),
+    /// A member is promoted, or its name or urls are updated
+    Update(Member),
+}
+
+impl MemberUpdate {
+    /// Get the updates from the previous members to the current ones, the updates
+    /// of every kind are ordered by the member ids
+    fn diff(prev: &HashMap<ServerId, Member>, curr: &HashMap<ServerId, Member>) -> Vec<Self> {
+        let removed = prev
+            .keys()
+            .filter(|id| !curr.contains_key(id))
+            .sorted()
+            .map(|id| Self::Remove(*id));
+        let added = curr
+            .values()
+            .filter(|member| !prev.contains_key(&member.id))
+            .sorted_by_key(|member| member.id)
+            .map(|member| Self::Add(member.clone()));
+        let updated = curr
+            .values()
+            .filter(|&member| prev.get(&member.id).is_some_and(|old| old != member))
+            .sorted_by_key(|member| member.id)
+            .map(|member| Self::Update(member.clone()));
+        removed.chain(added).chain(updated).collect()
+    }
+}
+
 /// cluster members information
 #[derive(Debug, Clone)]
 pub struct ClusterInfo {
@@ -82,6 +122,8 @@ pub struct ClusterInfo {
     members: DashMap<ServerId, Member>,
     /// cluster version
     cluster_version: Arc<AtomicU64>,
+    /// Notified when the members change
+    change_event: Arc<Event>,
 }
 
 impl ClusterInfo {
@@ -94,6 +136,7 @@ impl ClusterInfo {
             member_id,
             members: members.into_iter().map(|m| (m.id, m)).collect(),
             cluster_version: Arc::new(AtomicU64::new(0)),
+            change_event: Arc::new(Event::new()),
         }
     }
 
@@ -125,6 +168,7 @@ impl ClusterInfo {
             member_id,
             members,
             cluster_version: Arc::new(AtomicU64::new(0)),
+            change_event: Arc::new(Event::new()),
         };
         cluster_info.gen_cluster_id();
         cluster_info
@@ -166,6 +210,7 @@ impl ClusterInfo {
             member_id,
             members,
             cluster_version: Arc::new(AtomicU64::new(cluster.cluster_version)),
+            change_event: Arc::new(Event::new()),
         }
     }
 
@@ -347,6 +392,36 @@ impl ClusterInfo {
         let ver = hasher.finish();
         info!("cluster version updates to {ver}");
         self.cluster_version.store(ver, Ordering::Relaxed);
+        let _ignore = self.change_event.notify(usize::MAX);
+    }
+
+    /// Listen to the next change of the members
+    fn listen_changes(&self) -> EventListener {
+        self.change_event.listen()
+    }
+
+    /// Watch the members, a snapshot of all the members is yielded first, then the
+    /// updates of every change of them, including the member additions, removals,
+    /// promotions and url updates
+    #[inline]
+    pub fn watch_members(self: Arc<Self>) -> impl Stream<Item = MemberUpdate> {
+        stream! {
+            let mut listener = self.listen_changes();
+            let mut members = self.all_members();
+            yield MemberUpdate::Snapshot(
+                members.values().cloned().sorted_by_key(|member| member.id).collect(),
+            );
+            loop {
+                listener.await;
+                // listen before reading the members, so that no change is missed
+                listener = self.listen_changes();
+                let curr = self.all_members();
+                for update in MemberUpdate::diff(&members, &curr) {
+                    yield update;
+                }
+                members = curr;
+            }
+        }
     }
 
     /// Get peers
@@ -418,6 +493,7 @@ impl ClusterInfo {
             s.name = name;
             s.client_urls = client_urls;
         }
+        let _ignore = self.change_event.notify(usize::MAX);
     }
 }
 
@@ -429,10 +505,11 @@ pub async fn get_cluster_info_from_remote(
     self_name: &str,
     timeout: Duration,
     tls_config: Option<&ClientTlsConfig>,
+    message_size: MessageSizeLimits,
 ) -> Option<ClusterInfo> {
     let peers = init_cluster_info.peers_addrs();
     let self_client_urls = init_cluster_info.self_client_urls();
-    let connects = rpc::connects(peers, tls_config)
+    let connects = rpc::connects(peers, tls_config, message_size)
         .await
         .ok()?
         .map(|pair| pair.1)
@@ -511,4 +588,34 @@ mod tests {
         assert!(peer_urls.iter().find(|url| ***url == node1_url).is_none());
         assert!(peer_ids.iter().find(|id| **id == node1_id).is_none());
     }
+
+    #[tokio::test]
+    async fn member_watch_should_yield_snapshot_then_updates() {
+        let all_members = HashMap::from([
+            ("S1".to_owned(), vec!["S1".to_owned()]),
+            ("S2".to_owned(), vec!["S2".to_owned()]),
+        ]);
+        let cluster_info = Arc::new(ClusterInfo::from_members_map(all_members, [], "S1"));
+        let mut watch = Box::pin(Arc::clone(&cluster_info).watch_members());
+        let Some(MemberUpdate::Snapshot(members)) = watch.next().await else {
+            panic!("the first update should be a snapshot");
+        };
+        assert_eq!(members.len(), 2);
+
+        let learner = Member::new(3, "S3", ["S3".to_owned()], [], true);
+        assert!(cluster_info.insert(learner.clone()).is_none());
+        cluster_info.cluster_version_update();
+        assert_eq!(watch.next().await, Some(MemberUpdate::Add(learner)));
+
+        assert!(cluster_info.promote(3));
+        cluster_info.cluster_version_update();
+        let Some(MemberUpdate::Update(promoted)) = watch.next().await else {
+            panic!("the promotion should be an update");
+        };
+        assert!(!promoted.is_learner);
+
+        assert!(cluster_info.remove(&3).is_some());
+        cluster_info.cluster_version_update();
+        assert_eq!(watch.next().await, Some(MemberUpdate::Remove(3)));
+    }
 }
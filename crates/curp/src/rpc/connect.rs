@@ -21,7 +21,7 @@ use tonic::transport::{Channel, Endpoint};
 use tracing::{debug, error, info, instrument};
 #[cfg(madsim)]
 use utils::ClientTlsConfig;
-use utils::{build_endpoint, tracing::Inject};
+use utils::{build_endpoint, config::MessageSizeLimits, tracing::Inject};
 
 use crate::{
     members::ServerId,
@@ -49,18 +49,36 @@ const DEFAULT_BUFFER_SIZE: usize = 1024;
 
 /// For protocol client
 trait FromTonicChannel {
-    /// New from channel
-    fn from_channel(channel: Channel) -> Self;
+    /// New from channel, the messages sent and received are bounded by `message_size`
+    fn from_channel(channel: Channel, message_size: MessageSizeLimits) -> Self;
 }
 
 impl FromTonicChannel for ProtocolClient<Channel> {
-    fn from_channel(channel: Channel) -> Self {
+    #[cfg(not(madsim))]
+    fn from_channel(channel: Channel, message_size: MessageSizeLimits) -> Self {
+        ProtocolClient::new(channel)
+            .max_encoding_message_size(*message_size.max_send())
+            .max_decoding_message_size(*message_size.max_recv())
+    }
+
+    /// The simulated channels don't limit the message size
+    #[cfg(madsim)]
+    fn from_channel(channel: Channel, _message_size: MessageSizeLimits) -> Self {
         ProtocolClient::new(channel)
     }
 }
 
 impl FromTonicChannel for InnerProtocolClient<Channel> {
-    fn from_channel(channel: Channel) -> Self {
+    #[cfg(not(madsim))]
+    fn from_channel(channel: Channel, message_size: MessageSizeLimits) -> Self {
+        InnerProtocolClient::new(channel)
+            .max_encoding_message_size(*message_size.max_send())
+            .max_decoding_message_size(*message_size.max_recv())
+    }
+
+    /// The simulated channels don't limit the message size
+    #[cfg(madsim)]
+    fn from_channel(channel: Channel, _message_size: MessageSizeLimits) -> Self {
         InnerProtocolClient::new(channel)
     }
 }
@@ -70,6 +88,7 @@ async fn connect_to<Client: FromTonicChannel>(
     id: ServerId,
     addrs: Vec<String>,
     tls_config: Option<ClientTlsConfig>,
+    message_size: MessageSizeLimits,
 ) -> Result<Arc<Connect<Client>>, tonic::transport::Error> {
     let (channel, change_tx) = Channel::balance_channel(DEFAULT_BUFFER_SIZE);
     for addr in &addrs {
@@ -78,7 +97,7 @@ async fn connect_to<Client: FromTonicChannel>(
             .send(tower::discover::Change::Insert(addr.clone(), endpoint))
             .await;
     }
-    let client = Client::from_channel(channel);
+    let client = Client::from_channel(channel, message_size);
     let connect = Arc::new(Connect {
         id,
         rpc_connect: client,
@@ -94,11 +113,12 @@ async fn connect_to<Client: FromTonicChannel>(
 async fn connect_all<Client: FromTonicChannel>(
     members: HashMap<ServerId, Vec<String>>,
     tls_config: Option<&ClientTlsConfig>,
+    message_size: MessageSizeLimits,
 ) -> Result<Vec<(u64, Arc<Connect<Client>>)>, tonic::transport::Error> {
     let conns_to: FuturesUnordered<_> = members
         .into_iter()
         .map(|(id, addrs)| async move {
-            connect_to::<Client>(id, addrs, tls_config.cloned())
+            connect_to::<Client>(id, addrs, tls_config.cloned(), message_size)
                 .await
                 .map(|conn| (id, conn))
         })
@@ -114,8 +134,9 @@ pub(crate) async fn connect(
     id: ServerId,
     addrs: Vec<String>,
     tls_config: Option<ClientTlsConfig>,
+    message_size: MessageSizeLimits,
 ) -> Result<Arc<dyn ConnectApi>, tonic::transport::Error> {
-    let conn = connect_to::<ProtocolClient<Channel>>(id, addrs, tls_config).await?;
+    let conn = connect_to::<ProtocolClient<Channel>>(id, addrs, tls_config, message_size).await?;
     Ok(conn)
 }
 
@@ -123,11 +144,12 @@ pub(crate) async fn connect(
 pub(crate) async fn connects(
     members: HashMap<ServerId, Vec<String>>,
     tls_config: Option<&ClientTlsConfig>,
+    message_size: MessageSizeLimits,
 ) -> Result<impl Iterator<Item = (ServerId, Arc<dyn ConnectApi>)>, tonic::transport::Error> {
     // It seems that casting high-rank types cannot be inferred, so we allow trivial_casts to cast manually
     #[allow(trivial_casts)]
     #[allow(clippy::as_conversions)]
-    let conns = connect_all(members, tls_config)
+    let conns = connect_all(members, tls_config, message_size)
         .await?
         .into_iter()
         .map(|(id, conn)| (id, conn as Arc<dyn ConnectApi>));
@@ -138,8 +160,9 @@ pub(crate) async fn connects(
 pub(crate) async fn inner_connects(
     members: HashMap<ServerId, Vec<String>>,
     tls_config: Option<&ClientTlsConfig>,
+    message_size: MessageSizeLimits,
 ) -> Result<impl Iterator<Item = (ServerId, InnerConnectApiWrapper)>, tonic::transport::Error> {
-    let conns = connect_all(members, tls_config)
+    let conns = connect_all(members, tls_config, message_size)
         .await?
         .into_iter()
         .map(|(id, conn)| (id, InnerConnectApiWrapper::new_from_arc(conn)));
@@ -273,8 +296,10 @@ impl InnerConnectApiWrapper {
         id: ServerId,
         addrs: Vec<String>,
         tls_config: Option<ClientTlsConfig>,
+        message_size: MessageSizeLimits,
     ) -> Result<Self, tonic::transport::Error> {
-        let conn = connect_to::<InnerProtocolClient<Channel>>(id, addrs, tls_config).await?;
+        let conn =
+            connect_to::<InnerProtocolClient<Channel>>(id, addrs, tls_config, message_size).await?;
         Ok(InnerConnectApiWrapper::new_from_arc(conn))
     }
 }
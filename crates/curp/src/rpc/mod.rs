@@ -166,8 +166,9 @@ impl ProposeRequest {
 }
 
 impl ProposeResponse {
-    /// Create an ok propose response
-    pub(crate) fn new_result<C: Command>(result: &Result<C::ER, C::Error>) -> Self {
+    /// Create a propose response of an execute result
+    #[inline]
+    pub fn new_result<C: Command>(result: &Result<C::ER, C::Error>) -> Self {
         let result = match *result {
             Ok(ref er) => Some(CmdResult {
                 result: Some(CmdResultInner::Ok(er.encode())),
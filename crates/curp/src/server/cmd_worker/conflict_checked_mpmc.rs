@@ -201,17 +201,21 @@ struct Filter<C: Command, CE> {
     filter_tx: flume::Sender<Task<C>>,
     /// Command Executor
     cmd_executor: Arc<CE>,
+    /// Whether all msgs conflict each other, so that they are processed one by one
+    /// in the order they arrive
+    ordered: bool,
 }
 
 impl<C: Command, CE: CommandExecutor<C>> Filter<C, CE> {
     /// Create a new filter that checks conflict in between msgs
-    fn new(filter_tx: flume::Sender<Task<C>>, ce: Arc<CE>) -> Self {
+    fn new(filter_tx: flume::Sender<Task<C>>, ce: Arc<CE>, ordered: bool) -> Self {
         Self {
             cmd_vid: HashMap::new(),
             vs: HashMap::new(),
             next_id: 0,
             filter_tx,
             cmd_executor: ce,
+            ordered,
         }
     }
 
@@ -225,7 +229,7 @@ impl<C: Command, CE: CommandExecutor<C>> Filter<C, CE> {
     /// Insert a new vertex to inner graph
     fn insert_new_vertex(&mut self, new_vid: u64, mut new_v: Vertex<C>) {
         for v in self.vs.values_mut() {
-            if v.is_conflict(&new_v) {
+            if self.ordered || v.is_conflict(&new_v) {
                 assert!(v.successors.insert(new_vid), "cannot insert a vertex twice");
                 new_v.predecessor_cnt += 1;
             }
@@ -532,6 +536,7 @@ impl<C: Command, CE: CommandExecutor<C>> Filter<C, CE> {
 /// After the task is finished, the user should notify the channel by the done notifier.
 // Message flow:
 // send_tx -> filter_rx -> filter -> filter_tx -> recv_rx -> done_tx -> done_rx
+#[cfg(test)]
 #[allow(clippy::type_complexity)] // it's clear
 pub(in crate::server) fn channel<C: Command, CE: CommandExecutor<C>>(
     ce: Arc<CE>,
@@ -540,6 +545,21 @@ pub(in crate::server) fn channel<C: Command, CE: CommandExecutor<C>>(
     CEEventTx<C>,
     flume::Receiver<Task<C>>,
     flume::Sender<(Task<C>, bool)>,
+) {
+    channel_with_order(ce, task_manager, false)
+}
+
+/// Create conflict checked channel, all msgs are treated as conflicted with each other
+/// if `ordered` is set, so that they are processed one by one in the order they are sent
+#[allow(clippy::type_complexity)] // it's clear
+pub(in crate::server) fn channel_with_order<C: Command, CE: CommandExecutor<C>>(
+    ce: Arc<CE>,
+    task_manager: Arc<TaskManager>,
+    ordered: bool,
+) -> (
+    CEEventTx<C>,
+    flume::Receiver<Task<C>>,
+    flume::Sender<(Task<C>, bool)>,
 ) {
     // recv from user, insert it into filter
     let (send_tx, filter_rx) = flume::unbounded();
@@ -548,7 +568,7 @@ pub(in crate::server) fn channel<C: Command, CE: CommandExecutor<C>>(
     // recv from user to mark a msg done
     let (done_tx, done_rx) = flume::unbounded::<(Task<C>, bool)>();
     task_manager.spawn(TaskName::ConflictCheckedMpmc, |n| {
-        conflict_checked_mpmc_task(filter_tx, filter_rx, ce, done_rx, n)
+        conflict_checked_mpmc_task(filter_tx, filter_rx, ce, done_rx, ordered, n)
     });
     let ce_event_tx = CEEventTx(send_tx, task_manager);
     (ce_event_tx, recv_rx, done_tx)
@@ -560,9 +580,10 @@ async fn conflict_checked_mpmc_task<C: Command, CE: CommandExecutor<C>>(
     filter_rx: flume::Receiver<CEEvent<C>>,
     ce: Arc<CE>,
     done_rx: flume::Receiver<(Task<C>, bool)>,
+    ordered: bool,
     shutdown_listener: Listener,
 ) {
-    let mut filter = Filter::new(filter_tx, ce);
+    let mut filter = Filter::new(filter_tx, ce, ordered);
     let mut is_shutdown_state = false;
     // tokio internal triggers
     #[allow(clippy::arithmetic_side_effects, clippy::pattern_type_mismatch)]
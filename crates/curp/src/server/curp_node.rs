@@ -21,7 +21,7 @@ use tracing::{debug, error, info, trace, warn};
 #[cfg(madsim)]
 use utils::ClientTlsConfig;
 use utils::{
-    config::CurpConfig,
+    config::{CurpConfig, MessageSizeLimits},
     task_manager::{tasks::TaskName, Listener, State, TaskManager},
 };
 
@@ -458,6 +458,7 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
                         change.node_id,
                         change.address,
                         curp.client_tls_config().cloned(),
+                        curp.peer_message_size(),
                     )
                     .await
                     {
@@ -617,6 +618,7 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
         storage: Arc<DB<C>>,
         task_manager: Arc<TaskManager>,
         client_tls_config: Option<ClientTlsConfig>,
+        peer_message_size: MessageSizeLimits,
         sps: Vec<SpObject<C>>,
         ucps: Vec<UcpObject<C>>,
     ) -> Result<Self, CurpError> {
@@ -625,18 +627,25 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
             .into_iter()
             .map(|server_id| (server_id, Arc::new(Event::new())))
             .collect();
-        let connects = rpc::inner_connects(cluster_info.peers_addrs(), client_tls_config.as_ref())
-            .await
-            .map_err(|e| CurpError::internal(format!("parse peers addresses failed, err {e:?}")))?
-            .collect();
+        let connects = rpc::inner_connects(
+            cluster_info.peers_addrs(),
+            client_tls_config.as_ref(),
+            peer_message_size,
+        )
+        .await
+        .map_err(|e| CurpError::internal(format!("parse peers addresses failed, err {e:?}")))?
+        .collect();
         let (log_tx, log_rx) = mpsc::unbounded_channel();
         let cmd_board = Arc::new(RwLock::new(CommandBoard::new()));
         let lease_manager = Arc::new(RwLock::new(LeaseManager::new()));
         let last_applied = cmd_executor
             .last_applied()
             .map_err(|e| CurpError::internal(format!("get applied index error, {e}")))?;
-        let (ce_event_tx, task_rx, done_tx) =
-            conflict_checked_mpmc::channel(Arc::clone(&cmd_executor), Arc::clone(&task_manager));
+        let (ce_event_tx, task_rx, done_tx) = conflict_checked_mpmc::channel_with_order(
+            Arc::clone(&cmd_executor),
+            Arc::clone(&task_manager),
+            curp_cfg.ordered_execution,
+        );
         let ce_event_tx: Arc<dyn CEEventTxApi<C>> = Arc::new(ce_event_tx);
 
         // create curp state machine
@@ -659,6 +668,7 @@ impl<C: Command, RC: RoleChange> CurpNode<C, RC> {
                 .entries(entries)
                 .curp_storage(Arc::clone(&storage))
                 .client_tls_config(client_tls_config)
+                .peer_message_size(peer_message_size)
                 .spec_pool(Arc::new(Mutex::new(SpeculativePool::new(sps))))
                 .uncommitted_pool(Arc::new(Mutex::new(UncommittedPool::new(ucps))))
                 .build_raw_curp()
@@ -43,6 +43,9 @@ pub(super) async fn gc_cmd_board<C: Command>(
             let new_sync = board.sync.split_off(last_check_len_sync);
             board.sync = new_sync;
             last_check_len_sync = board.sync.len();
+        } else {
+            // the dedup cache has been cleared manually
+            last_check_len_sync = board.sync.len();
         }
 
         if last_check_len_conf <= board.conf_buffer.len() {
@@ -7,7 +7,11 @@ use tonic::transport::ClientTlsConfig;
 use tracing::instrument;
 #[cfg(madsim)]
 use utils::ClientTlsConfig;
-use utils::{config::CurpConfig, task_manager::TaskManager, tracing::Extract};
+use utils::{
+    config::{CurpConfig, MessageSizeLimits},
+    task_manager::TaskManager,
+    tracing::Extract,
+};
 
 use self::curp_node::CurpNode;
 pub use self::{
@@ -245,6 +249,7 @@ impl<C: Command, RC: RoleChange> Rpc<C, RC> {
         storage: Arc<DB<C>>,
         task_manager: Arc<TaskManager>,
         client_tls_config: Option<ClientTlsConfig>,
+        peer_message_size: MessageSizeLimits,
         sps: Vec<SpObject<C>>,
         ucps: Vec<UcpObject<C>>,
     ) -> Self {
@@ -259,6 +264,7 @@ impl<C: Command, RC: RoleChange> Rpc<C, RC> {
             storage,
             task_manager,
             client_tls_config,
+            peer_message_size,
             sps,
             ucps,
         )
@@ -294,6 +300,7 @@ impl<C: Command, RC: RoleChange> Rpc<C, RC> {
         storage: Arc<DB<C>>,
         task_manager: Arc<TaskManager>,
         client_tls_config: Option<ClientTlsConfig>,
+        peer_message_size: MessageSizeLimits,
         sps: Vec<SpObject<C>>,
         ucps: Vec<UcpObject<C>>,
     ) -> Result<(), crate::error::ServerError>
@@ -315,6 +322,7 @@ impl<C: Command, RC: RoleChange> Rpc<C, RC> {
             storage,
             task_manager,
             client_tls_config,
+            peer_message_size,
             sps,
             ucps,
         )
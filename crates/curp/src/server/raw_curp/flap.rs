@@ -0,0 +1,128 @@
+use std::{collections::VecDeque, time::Duration};
+
+use tokio::time::Instant;
+
+/// The max factor the election timeouts are multiplied by while the leader is flapping
+const MAX_BACKOFF_FACTOR: u8 = 8;
+
+/// Detector of the leader flapping, which is more elections than the threshold in a
+/// window.
+///
+/// Every new term is an election, no matter whether a leader is elected in the term.
+/// If the backoff is enabled, the election timeouts are doubled on each election while
+/// the leader is flapping, so the nodes back off from starting new elections, and they
+/// are restored once no flapping is detected in a whole window.
+#[derive(Debug)]
+pub(super) struct FlapDetector {
+    /// The number of elections allowed in the window, 0 means the detection is disabled
+    threshold: usize,
+    /// The window in which the elections are counted
+    window: Duration,
+    /// Whether the election timeouts back off while the leader is flapping
+    backoff: bool,
+    /// The times of the elections in the last window
+    elections: VecDeque<Instant>,
+    /// When the flapping is detected the last time
+    last_detected: Option<Instant>,
+    /// The factor the election timeouts are multiplied by while the leader is flapping
+    backoff_factor: u8,
+}
+
+impl FlapDetector {
+    /// Create a new `FlapDetector`
+    pub(super) fn new(threshold: usize, window: Duration, backoff: bool) -> Self {
+        Self {
+            threshold,
+            window,
+            backoff,
+            elections: VecDeque::new(),
+            last_detected: None,
+            backoff_factor: 1,
+        }
+    }
+
+    /// The window in which the elections are counted
+    pub(super) fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Record an election at `now`, return the number of the elections in the window
+    /// if the leader is flapping
+    pub(super) fn record_election(&mut self, now: Instant) -> Option<usize> {
+        if self.threshold == 0 {
+            return None;
+        }
+        while self
+            .elections
+            .front()
+            .is_some_and(|&t| now.saturating_duration_since(t) > self.window)
+        {
+            let _ignore = self.elections.pop_front();
+        }
+        self.elections.push_back(now);
+        if self.elections.len() <= self.threshold {
+            return None;
+        }
+        if self.backoff {
+            self.backoff_factor = if self.is_flapping(now) {
+                self.backoff_factor
+                    .saturating_mul(2)
+                    .min(MAX_BACKOFF_FACTOR)
+            } else {
+                2
+            };
+        }
+        self.last_detected = Some(now);
+        Some(self.elections.len())
+    }
+
+    /// Whether the leader is flapping, that is the flapping is detected in the last window
+    pub(super) fn is_flapping(&self, now: Instant) -> bool {
+        self.last_detected
+            .is_some_and(|t| now.saturating_duration_since(t) <= self.window)
+    }
+
+    /// The factor the election timeouts are multiplied by at `now`
+    pub(super) fn backoff_factor(&self, now: Instant) -> u8 {
+        if self.is_flapping(now) {
+            self.backoff_factor
+        } else {
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flapping_should_be_detected_when_elections_exceed_the_threshold() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut detector = FlapDetector::new(2, Duration::from_secs(10), true);
+        assert_eq!(detector.record_election(at(0)), None);
+        assert_eq!(detector.record_election(at(1)), None);
+        assert_eq!(detector.record_election(at(2)), Some(3));
+        assert!(detector.is_flapping(at(3)));
+        assert_eq!(detector.backoff_factor(at(3)), 2);
+        for secs in 3..6 {
+            assert!(detector.record_election(at(secs)).is_some());
+        }
+        assert_eq!(detector.backoff_factor(at(6)), MAX_BACKOFF_FACTOR);
+
+        assert!(!detector.is_flapping(at(16)));
+        assert_eq!(detector.backoff_factor(at(16)), 1);
+        assert_eq!(detector.record_election(at(16)), None);
+
+        let mut without_backoff = FlapDetector::new(1, Duration::from_secs(10), false);
+        let _ignore = without_backoff.record_election(at(0));
+        assert_eq!(without_backoff.record_election(at(1)), Some(2));
+        assert_eq!(without_backoff.backoff_factor(at(2)), 1);
+
+        let mut disabled = FlapDetector::new(0, Duration::from_secs(10), true);
+        for secs in 0..10 {
+            assert_eq!(disabled.record_election(at(secs)), None);
+        }
+    }
+}
@@ -17,6 +17,7 @@ use std::{
         atomic::{AtomicU64, AtomicU8, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 use clippy_utilities::{NumericCast, OverflowArithmetic};
@@ -26,7 +27,10 @@ use event_listener::Event;
 use itertools::Itertools;
 use opentelemetry::KeyValue;
 use parking_lot::{Mutex, RwLock, RwLockUpgradableReadGuard, RwLockWriteGuard};
-use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot},
+    time::Instant,
+};
 #[cfg(not(madsim))]
 use tonic::transport::ClientTlsConfig;
 use tracing::{
@@ -37,12 +41,13 @@ use tracing::{
 #[cfg(madsim)]
 use utils::ClientTlsConfig;
 use utils::{
-    config::CurpConfig,
+    config::{CurpConfig, MessageSizeLimits},
     parking_lot_lock::{MutexMap, RwLockMap},
     task_manager::TaskManager,
 };
 
 use self::{
+    flap::FlapDetector,
     log::Log,
     state::{CandidateState, LeaderState, State},
 };
@@ -79,6 +84,9 @@ mod state;
 /// Curp log
 mod log;
 
+/// Leader flapping detection
+mod flap;
+
 /// test utils
 #[cfg(test)]
 mod tests;
@@ -136,6 +144,9 @@ pub(super) struct RawCurpArgs<C: Command, RC: RoleChange> {
     /// client tls config
     #[builder(default)]
     client_tls_config: Option<ClientTlsConfig>,
+    /// The message size limits of the connections to the peers
+    #[builder(default)]
+    peer_message_size: MessageSizeLimits,
     /// Last applied index
     #[builder(setter(strip_option), default)]
     last_applied: Option<LogIndex>,
@@ -179,6 +190,7 @@ impl<C: Command, RC: RoleChange> RawCurpBuilder<C, RC> {
             .connects(args.connects)
             .curp_storage(args.curp_storage)
             .client_tls_config(args.client_tls_config)
+            .peer_message_size(args.peer_message_size)
             .spec_pool(args.spec_pool)
             .uncommitted_pool(args.uncommitted_pool)
             .build()
@@ -303,6 +315,8 @@ struct Context<C: Command, RC: RoleChange> {
     cfg: Arc<CurpConfig>,
     /// Client tls config
     client_tls_config: Option<ClientTlsConfig>,
+    /// The message size limits of the connections to the peers
+    peer_message_size: MessageSizeLimits,
     /// Cmd board for tracking the cmd sync results
     cb: CmdBoardRef<C>,
     /// The lease manager
@@ -313,6 +327,9 @@ struct Context<C: Command, RC: RoleChange> {
     /// Election tick
     #[builder(setter(skip))]
     election_tick: AtomicU8,
+    /// Leader flapping detector
+    #[builder(setter(skip))]
+    flap_detector: Mutex<FlapDetector>,
     /// Tx to send cmds to execute and do after sync
     cmd_tx: Arc<dyn CEEventTxApi<C>>,
     /// Followers sync event trigger
@@ -352,15 +369,20 @@ impl<C: Command, RC: RoleChange> ContextBuilder<C, RC> {
     /// Build the context from the builder
     pub(super) fn build(&mut self) -> Result<Context<C, RC>, ContextBuilderError> {
         let (change_tx, change_rx) = flume::bounded(CHANGE_CHANNEL_SIZE);
+        let Some(cfg) = self.cfg.take() else {
+            return Err(ContextBuilderError::UninitializedField("cfg"));
+        };
+        let flap_detector = Mutex::new(FlapDetector::new(
+            cfg.leader_flap_threshold,
+            cfg.leader_flap_window,
+            cfg.leader_flap_backoff,
+        ));
         Ok(Context {
             cluster_info: match self.cluster_info.take() {
                 Some(value) => value,
                 None => return Err(ContextBuilderError::UninitializedField("cluster_info")),
             },
-            cfg: match self.cfg.take() {
-                Some(value) => value,
-                None => return Err(ContextBuilderError::UninitializedField("cfg")),
-            },
+            cfg,
             cb: match self.cb.take() {
                 Some(value) => value,
                 None => return Err(ContextBuilderError::UninitializedField("cb")),
@@ -371,6 +393,7 @@ impl<C: Command, RC: RoleChange> ContextBuilder<C, RC> {
             },
             leader_tx: broadcast::channel(1).0,
             election_tick: AtomicU8::new(0),
+            flap_detector,
             cmd_tx: match self.cmd_tx.take() {
                 Some(value) => value,
                 None => return Err(ContextBuilderError::UninitializedField("cmd_tx")),
@@ -399,6 +422,10 @@ impl<C: Command, RC: RoleChange> ContextBuilder<C, RC> {
                 Some(value) => value,
                 None => return Err(ContextBuilderError::UninitializedField("client_tls_config")),
             },
+            peer_message_size: match self.peer_message_size.take() {
+                Some(value) => value,
+                None => return Err(ContextBuilderError::UninitializedField("peer_message_size")),
+            },
             spec_pool: match self.spec_pool.take() {
                 Some(value) => value,
                 None => return Err(ContextBuilderError::UninitializedField("spec_pool")),
@@ -435,6 +462,8 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             Role::Follower | Role::Leader => st_r.follower_timeout_ticks,
             Role::PreCandidate | Role::Candidate => st_r.candidate_timeout_ticks,
         };
+        let timeout =
+            timeout.saturating_mul(self.ctx.flap_detector.lock().backoff_factor(Instant::now()));
         let tick = self.ctx.election_tick.fetch_add(1, Ordering::AcqRel);
         if tick < timeout {
             return None;
@@ -470,6 +499,8 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             .spec_pool
             .map_lock(|mut sp_l| sp_l.insert(PoolEntry::new(propose_id, Arc::clone(&cmd))))
             .is_some();
+        // every command takes the slow path and is executed in the order of the log
+        conflict |= self.cfg().ordered_execution;
 
         let st_r = self.st.read();
         // Non-leader doesn't need to sync or execute
@@ -747,6 +778,7 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         if cur_role != Role::Leader {
             return Err(());
         }
+        self.lst.record_ack(follower_id, Instant::now());
 
         if !success {
             self.lst.update_next_index(follower_id, hint_index);
@@ -1049,6 +1081,7 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         if cur_role != Role::Leader {
             return Err(());
         }
+        self.lst.record_ack(follower_id, Instant::now());
         self.lst
             .update_match_index(follower_id, meta.last_included_index.numeric_cast());
         Ok(())
@@ -1150,6 +1183,68 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         self.log.read().commit_index
     }
 
+    /// Get the number of voters which have responded to the leader within an election
+    /// timeout, including the leader itself, return `None` if the current node is not
+    /// the leader
+    #[inline]
+    pub fn healthy_voters(&self) -> Option<usize> {
+        if !self.is_leader() {
+            return None;
+        }
+        let election_timeout = self
+            .cfg()
+            .heartbeat_interval
+            .checked_mul(self.cfg().follower_timeout_ticks.into())
+            .unwrap_or(Duration::MAX);
+        Some(
+            self.lst
+                .acked_voters(Instant::now(), election_timeout)
+                .overflow_add(1),
+        )
+    }
+
+    /// Whether the current node detects the leader flapping in the last flap window
+    #[inline]
+    pub fn leader_flapping(&self) -> bool {
+        self.ctx.flap_detector.lock().is_flapping(Instant::now())
+    }
+
+    /// Get the index of the last log entry replicated to a member in the view of the
+    /// leader, the leader itself has all its entries. Return `None` if the current
+    /// node is not the leader or the member is unknown.
+    #[inline]
+    pub fn match_index(&self, id: ServerId) -> Option<LogIndex> {
+        if !self.is_leader() {
+            return None;
+        }
+        if id == self.id() {
+            return Some(self.last_log_index());
+        }
+        self.get_match_index(id)
+    }
+
+    /// Get the number of propose ids in the dedup cache
+    #[inline]
+    pub fn dedup_cache_len(&self) -> usize {
+        self.ctx.cb.map_read(|cb_r| cb_r.sync.len())
+    }
+
+    /// Clear the dedup cache and return the number of removed propose ids
+    ///
+    /// WARNING: a retried proposal whose propose id has been removed is no longer
+    /// recognized as a duplicate, so it may be applied again. This should only be
+    /// used in recovery scenarios.
+    #[inline]
+    pub fn clear_dedup_cache(&self) -> usize {
+        let removed = self.ctx.cb.map_write(|mut cb_w| {
+            let len = cb_w.sync.len();
+            cb_w.sync.clear();
+            len
+        });
+        warn!("{} propose ids are removed from the dedup cache", removed);
+        removed
+    }
+
     /// Get cluster info
     pub(super) fn cluster(&self) -> &ClusterInfo {
         self.ctx.cluster_info.as_ref()
@@ -1161,7 +1256,9 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
     }
 
     /// Get a rx for leader changes
-    pub(super) fn leader_rx(&self) -> broadcast::Receiver<Option<ServerId>> {
+    #[inline]
+    #[must_use]
+    pub fn leader_rx(&self) -> broadcast::Receiver<Option<ServerId>> {
         self.ctx.leader_tx.subscribe()
     }
 
@@ -1521,6 +1618,11 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
     pub(super) fn client_tls_config(&self) -> Option<&ClientTlsConfig> {
         self.ctx.client_tls_config.as_ref()
     }
+
+    /// Get the message size limits of the connections to the peers
+    pub(super) fn peer_message_size(&self) -> MessageSizeLimits {
+        self.ctx.peer_message_size
+    }
 }
 
 // Utils
@@ -1581,6 +1683,7 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         assert_ne!(prev_role, Role::Leader, "leader can't start election");
 
         st.term += 1;
+        self.record_election(st.term);
         st.role = Role::Candidate;
         st.voted_for = Some(self.id());
         st.leader_id = None;
@@ -1625,6 +1728,7 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         metrics::get().leader_changes.add(1, &[]);
         st.role = Role::Leader;
         st.leader_id = Some(self.id());
+        self.lst.reset_acks();
         let _ig = self.ctx.leader_tx.send(Some(self.id())).ok();
         let _ignore = self.ctx.leader_event.notify(usize::MAX);
         self.ctx.role_change.on_election_win();
@@ -1646,6 +1750,9 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
             // a leader fallback into the follower
             metrics::get().leader_changes.add(1, &[]);
         }
+        if term > st.term {
+            self.record_election(term);
+        }
         st.term = term;
         self.lst.reset_transferee();
         st.role = Role::Follower;
@@ -1659,6 +1766,21 @@ impl<C: Command, RC: RoleChange> RawCurp<C, RC> {
         );
     }
 
+    /// Record the election of a new term, warn if the leader is flapping
+    fn record_election(&self, term: u64) {
+        let now = Instant::now();
+        let mut detector = self.ctx.flap_detector.lock();
+        if let Some(elections) = detector.record_election(now) {
+            warn!(
+                "{} detects leader flapping: {elections} elections in {:?} up to term {term}, \
+                the election timeouts are multiplied by {}",
+                self.id(),
+                detector.window(),
+                detector.backoff_factor(now)
+            );
+        }
+    }
+
     /// Reset election tick
     fn reset_election_tick(&self) {
         self.ctx.election_tick.store(0, Ordering::Relaxed);
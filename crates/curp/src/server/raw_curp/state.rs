@@ -1,6 +1,7 @@
 use std::{
     collections::{HashMap, HashSet},
     sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
 
 use dashmap::{
@@ -11,6 +12,7 @@ use dashmap::{
     DashMap,
 };
 use madsim::rand::{thread_rng, Rng};
+use tokio::time::Instant;
 use tracing::{debug, warn};
 
 use super::Role;
@@ -62,6 +64,8 @@ pub(super) struct FollowerStatus {
     pub(super) match_index: LogIndex,
     /// This node is a learner or not
     pub(super) is_learner: bool,
+    /// When the last response of that follower is received in the current term
+    pub(super) last_ack: Option<Instant>,
 }
 
 impl Default for FollowerStatus {
@@ -70,6 +74,7 @@ impl Default for FollowerStatus {
             next_index: 1,
             match_index: 0,
             is_learner: false,
+            last_ack: None,
         }
     }
 }
@@ -81,6 +86,7 @@ impl FollowerStatus {
             next_index,
             match_index,
             is_learner,
+            last_ack: None,
         }
     }
 }
@@ -196,6 +202,34 @@ impl LeaderState {
         debug!("follower {id}'s match_index updated to {index}");
     }
 
+    /// Record that a response of server is received
+    pub(super) fn record_ack(&self, id: ServerId, now: Instant) {
+        if let Some(mut status) = self.get_status_mut(id) {
+            status.last_ack = Some(now);
+        }
+    }
+
+    /// Forget the responses received in the previous terms
+    pub(super) fn reset_acks(&self) {
+        self.statuses
+            .iter_mut()
+            .for_each(|mut status| status.last_ack = None);
+    }
+
+    /// Get the number of voters other than the leader which have responded within
+    /// `within` before `now`
+    pub(super) fn acked_voters(&self, now: Instant, within: Duration) -> usize {
+        self.statuses
+            .iter()
+            .filter(|status| {
+                !status.is_learner
+                    && status
+                        .last_ack
+                        .is_some_and(|ack| now.saturating_duration_since(ack) <= within)
+            })
+            .count()
+    }
+
     /// Create a `Iterator` for all statuses
     pub(super) fn iter(&self) -> impl Iterator<Item = RefMulti<'_, ServerId, FollowerStatus>> {
         self.statuses.iter()
@@ -37,12 +37,26 @@ impl RawCurp<TestCommand, TestRoleChange> {
             && self.cst.lock().config.contains(id)
     }
 
-    #[allow(clippy::mem_forget)] // we should prevent the channel from being dropped
     pub(crate) fn new_test<Tx: CEEventTxApi<TestCommand>>(
         n: u64,
         exe_tx: Tx,
         role_change: TestRoleChange,
         task_manager: Arc<TaskManager>,
+    ) -> Self {
+        let curp_config = CurpConfigBuilder::default()
+            .log_entries_cap(10)
+            .build()
+            .unwrap();
+        Self::new_test_with_config(n, exe_tx, role_change, task_manager, curp_config)
+    }
+
+    #[allow(clippy::mem_forget)] // we should prevent the channel from being dropped
+    pub(crate) fn new_test_with_config<Tx: CEEventTxApi<TestCommand>>(
+        n: u64,
+        exe_tx: Tx,
+        role_change: TestRoleChange,
+        task_manager: Arc<TaskManager>,
+        curp_config: CurpConfig,
     ) -> Self {
         let all_members: HashMap<_, _> = (0..n)
             .map(|i| (format!("S{i}"), vec![format!("S{i}")]))
@@ -68,10 +82,6 @@ impl RawCurp<TestCommand, TestRoleChange> {
                 )
             })
             .collect();
-        let curp_config = CurpConfigBuilder::default()
-            .log_entries_cap(10)
-            .build()
-            .unwrap();
         let curp_storage = Arc::new(DB::open(&curp_config.engine_cfg).unwrap());
 
         // grant a infinity expiry lease for test client id
@@ -194,6 +204,33 @@ fn leader_handle_propose_will_reject_duplicated() {
     assert!(matches!(res, Err(CurpError::Duplicated(()))));
 }
 
+#[traced_test]
+#[test]
+fn cleared_dedup_cache_should_not_reject_duplicated() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx.expect_send_sp_exe().returning(|_| {});
+        RawCurp::new_test(3, exe_tx, mock_role_change(), task_manager)
+    };
+    let cmd = Arc::new(TestCommand::default());
+    for seq in 0..3 {
+        assert!(curp
+            .handle_propose(ProposeId(TEST_CLIENT_ID, seq), Arc::clone(&cmd))
+            .unwrap());
+    }
+    assert_eq!(curp.dedup_cache_len(), 3);
+    let res = curp.handle_propose(ProposeId(TEST_CLIENT_ID, 0), Arc::clone(&cmd));
+    assert!(matches!(res, Err(CurpError::Duplicated(()))));
+
+    assert_eq!(curp.clear_dedup_cache(), 3);
+    assert_eq!(curp.dedup_cache_len(), 0);
+    assert!(curp
+        .handle_propose(ProposeId(TEST_CLIENT_ID, 0), cmd)
+        .unwrap());
+    assert_eq!(curp.dedup_cache_len(), 1);
+}
+
 #[traced_test]
 #[test]
 fn follower_handle_propose_will_succeed() {
@@ -288,6 +325,41 @@ fn heartbeat_will_calibrate_next_index() {
     assert_eq!(curp.lst.get_next_index(s1_id), Some(1));
 }
 
+#[traced_test]
+#[test]
+fn healthy_voters_should_count_the_recently_acked_voters() {
+    let task_manager = Arc::new(TaskManager::new());
+    let curp = RawCurp::new_test(
+        5,
+        MockCEEventTxApi::<TestCommand>::default(),
+        mock_role_change(),
+        task_manager,
+    );
+    assert_eq!(curp.healthy_voters(), Some(1));
+
+    let s1_id = curp.cluster().get_id_by_name("S1").unwrap();
+    let s2_id = curp.cluster().get_id_by_name("S2").unwrap();
+    let s3_id = curp.cluster().get_id_by_name("S3").unwrap();
+    // a rejection is still a response of a healthy voter
+    assert_eq!(
+        curp.handle_append_entries_resp(s1_id, None, 0, false, 1),
+        Ok(false)
+    );
+    assert_eq!(
+        curp.handle_append_entries_resp(s2_id, None, 0, true, 1),
+        Ok(true)
+    );
+    curp.lst.demote(s3_id);
+    assert_eq!(
+        curp.handle_append_entries_resp(s3_id, None, 0, true, 1),
+        Ok(true)
+    );
+    assert_eq!(curp.healthy_voters(), Some(3));
+
+    let later = Instant::now() + Duration::from_secs(10);
+    assert_eq!(curp.lst.acked_voters(later, Duration::from_secs(1)), 0);
+}
+
 #[traced_test]
 #[test]
 fn handle_ae_will_calibrate_term() {
@@ -492,6 +564,49 @@ async fn follower_or_pre_candidate_will_start_election_if_timeout() {
     }
 }
 
+#[traced_test]
+#[tokio::test(start_paused = true)]
+async fn leader_flapping_should_back_off_the_election_timeouts() {
+    let task_manager = Arc::new(TaskManager::new());
+    let window = Duration::from_millis(500);
+    let curp = {
+        let mut exe_tx = MockCEEventTxApi::<TestCommand>::default();
+        exe_tx
+            .expect_send_reset()
+            .returning(|_| oneshot::channel().1);
+        let curp_config = CurpConfigBuilder::default()
+            .log_entries_cap(10)
+            .leader_flap_threshold(2)
+            .leader_flap_window(window)
+            .leader_flap_backoff(true)
+            .build()
+            .unwrap();
+        RawCurp::new_test_with_config(3, exe_tx, mock_role_change(), task_manager, curp_config)
+    };
+    let ticks_until_election = |curp: &RawCurp<TestCommand, TestRoleChange>| {
+        curp.reset_election_tick();
+        (1..).find(|_| curp.tick_election().is_some()).unwrap()
+    };
+    let max_ticks = default_follower_timeout_ticks() * 2;
+
+    // every new term is an election, the leader starts at term 1
+    for term in 2..=3 {
+        curp.update_to_term_and_become_follower(&mut *curp.st.write(), term);
+        assert!(!curp.leader_flapping());
+    }
+    assert!(ticks_until_election(&curp) <= max_ticks);
+    curp.update_to_term_and_become_follower(&mut *curp.st.write(), 4);
+    assert!(curp.leader_flapping());
+    assert!(ticks_until_election(&curp) > max_ticks);
+
+    // restored once no flapping is detected in a whole window
+    tokio::time::advance(window + Duration::from_millis(100)).await;
+    assert!(!curp.leader_flapping());
+    curp.update_to_term_and_become_follower(&mut *curp.st.write(), 5);
+    assert!(!curp.leader_flapping());
+    assert!(ticks_until_election(&curp) <= max_ticks);
+}
+
 #[traced_test]
 #[test]
 fn handle_vote_will_calibrate_term() {
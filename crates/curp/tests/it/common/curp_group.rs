@@ -40,7 +40,8 @@ use tracing::debug;
 use utils::{
     build_endpoint,
     config::{
-        default_quota, ClientConfig, CurpConfig, CurpConfigBuilder, EngineConfig, StorageConfig,
+        default_quota, ClientConfig, CurpConfig, CurpConfigBuilder, EngineConfig,
+        MessageSizeLimits, StorageConfig,
     },
     task_manager::{tasks::TaskName, Listener, TaskManager},
 };
@@ -88,6 +89,19 @@ impl CurpGroup {
         Self::new_with_configs(configs, "S0".to_owned()).await
     }
 
+    pub async fn new_with_curp_config(n_nodes: usize, config: CurpConfig) -> Self {
+        let config = Arc::new(config);
+        let configs = (0..n_nodes)
+            .map(|i| {
+                (
+                    format!("S{i}"),
+                    (Arc::clone(&config), EngineConfig::default()),
+                )
+            })
+            .collect();
+        Self::new_with_configs(configs, "S0".to_owned()).await
+    }
+
     pub async fn new_rocks(n_nodes: usize, path: PathBuf) -> Self {
         let configs = (0..n_nodes)
             .map(|i| {
@@ -151,6 +165,7 @@ impl CurpGroup {
                     curp_storage,
                     Arc::clone(&task_manager),
                     client_tls_config.clone(),
+                    MessageSizeLimits::default(),
                     vec![Box::<TestSpecPool>::default()],
                     vec![Box::<TestUncomPool>::default()],
                 )
@@ -283,6 +298,7 @@ impl CurpGroup {
                 curp_storage,
                 Arc::clone(&task_manager),
                 self.client_tls_config.clone(),
+                MessageSizeLimits::default(),
                 vec![],
                 vec![],
             )
@@ -15,7 +15,10 @@ use curp_test_utils::{
 use madsim::rand::{thread_rng, Rng};
 use test_macros::abort_on_panic;
 use tokio::net::TcpListener;
-use utils::{config::ClientConfig, timestamp};
+use utils::{
+    config::{ClientConfig, CurpConfigBuilder},
+    timestamp,
+};
 
 use crate::common::curp_group::{
     commandpb::ProposeId, CurpGroup, FetchClusterRequest, ProposeRequest, ProposeResponse,
@@ -228,6 +231,69 @@ async fn concurrent_cmd_order() {
     );
 }
 
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn ordered_execution_should_produce_deterministic_outcomes() {
+    init_logger();
+
+    // without the ordered execution, cmd2 is speculatively executed before cmd1,
+    // which conflicts with the slow cmd0, so it takes a smaller revision than cmd1
+    let cmds = vec![
+        TestCommand::new_put(vec![0], 0).set_exe_dur(Duration::from_millis(500)),
+        TestCommand::new_put(vec![0], 1),
+        TestCommand::new_put(vec![1], 2),
+    ];
+
+    for _ in 0..3 {
+        let config = CurpConfigBuilder::default()
+            .ordered_execution(true)
+            .build()
+            .unwrap();
+        let mut group = CurpGroup::new_with_curp_config(3, config).await;
+        let leader = group.get_leader().await.0;
+        let leader_connect = group.get_connect(&leader).await;
+
+        for (seq_num, cmd) in cmds.iter().enumerate() {
+            let mut c = leader_connect.clone();
+            let command = bincode::serialize(cmd).unwrap();
+            tokio::spawn(async move {
+                let response = c
+                    .propose(ProposeRequest {
+                        propose_id: Some(ProposeId {
+                            client_id: 0,
+                            seq_num: seq_num.numeric_cast(),
+                        }),
+                        command,
+                        cluster_version: 0,
+                    })
+                    .await;
+                // every command takes the slow path
+                assert!(response.is_err());
+            });
+            sleep_millis(20).await;
+        }
+
+        for as_rx in group.as_rxs() {
+            let mut synced = vec![];
+            for _ in 0..cmds.len() {
+                synced.push(as_rx.recv().await.unwrap().0);
+            }
+            assert_eq!(synced, cmds);
+        }
+
+        let client = group.new_client().await;
+        assert_eq!(
+            client
+                .propose(&TestCommand::new_get(vec![0, 1]), None, true)
+                .await
+                .unwrap()
+                .unwrap()
+                .0,
+            TestCommandResult::new(vec![1, 2], vec![2, 3])
+        );
+    }
+}
+
 /// This test case ensures that the issue 228 is fixed.
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
@@ -627,3 +693,41 @@ async fn move_leader_should_move_leadership_to_target_node() {
     assert_eq!(target, new_leader);
     assert_ne!(old_leader, new_leader);
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn flapping_leader_should_stabilize_with_the_election_backoff() {
+    init_logger();
+    let config = CurpConfigBuilder::default()
+        .leader_flap_threshold(2)
+        .leader_flap_window(Duration::from_secs(30))
+        .leader_flap_backoff(true)
+        .build()
+        .unwrap();
+    let group = CurpGroup::new_with_curp_config(3, config).await;
+    let client = group.new_client().await;
+
+    // every move is an election, the third one within the window is a flapping
+    let ids: Vec<_> = group.nodes.keys().copied().collect();
+    for _ in 0..3 {
+        let leader = group.get_leader().await.0;
+        let target = *ids.iter().find(|&&id| id != leader).unwrap();
+        client.move_leader(target).await.unwrap();
+        assert_eq!(group.get_leader().await.0, target);
+    }
+
+    // the backed off election timeouts don't keep the leader from holding its term
+    let (leader, term) = group.get_leader().await;
+    sleep_secs(5).await;
+    assert_eq!(group.get_leader().await, (leader, term));
+    assert_eq!(group.get_term_checked().await, term);
+    assert_eq!(
+        client
+            .propose(&TestCommand::new_put(vec![0], 0), None, true)
+            .await
+            .unwrap()
+            .unwrap()
+            .0,
+        TestCommandResult::new(vec![], vec![])
+    );
+}
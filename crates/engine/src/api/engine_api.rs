@@ -76,4 +76,17 @@ pub trait StorageEngine: Send + Sync + 'static + std::fmt::Debug {
     /// # Errors
     /// Return `EngineError` if met some errors when get file size
     fn file_size(&self) -> Result<u64, EngineError>;
+
+    /// Get the estimated size of the live data of the engine (Measured in bytes), the
+    /// rest of the file size is reclaimable by a defragmentation
+    ///
+    /// # Errors
+    /// Return `EngineError` if met some errors when get the live data size
+    fn live_data_size(&self) -> Result<u64, EngineError>;
+
+    /// Compact all the tables of the engine to reclaim the space of the deleted data
+    ///
+    /// # Errors
+    /// Return `EngineError` if met some errors when compacting the tables
+    fn defragment(&self) -> Result<(), EngineError>;
 }
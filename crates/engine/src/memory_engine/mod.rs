@@ -188,6 +188,14 @@ impl StorageEngine for MemoryEngine {
     fn file_size(&self) -> Result<u64, EngineError> {
         Ok(0)
     }
+
+    fn live_data_size(&self) -> Result<u64, EngineError> {
+        Ok(0)
+    }
+
+    fn defragment(&self) -> Result<(), EngineError> {
+        Ok(())
+    }
 }
 
 /// A snapshot of the `MemoryEngine`
@@ -158,6 +158,16 @@ where
     fn file_size(&self) -> Result<u64, EngineError> {
         self.engine.file_size()
     }
+
+    /// Get the estimated size of the live data of the engine
+    fn live_data_size(&self) -> Result<u64, EngineError> {
+        self.engine.live_data_size()
+    }
+
+    /// Compact all the tables of the engine
+    fn defragment(&self) -> Result<(), EngineError> {
+        self.engine.defragment()
+    }
 }
 
 #[async_trait]
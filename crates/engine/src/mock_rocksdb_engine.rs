@@ -139,6 +139,16 @@ impl StorageEngine for RocksEngine {
     fn file_size(&self) -> Result<u64, EngineError> {
         Ok(0)
     }
+
+    #[inline]
+    fn live_data_size(&self) -> Result<u64, EngineError> {
+        Ok(0)
+    }
+
+    #[inline]
+    fn defragment(&self) -> Result<(), EngineError> {
+        Ok(())
+    }
 }
 
 /// A mock snapshot of the `RocksEngine`
@@ -159,6 +159,22 @@ impl StorageEngine for Engine {
             Engine::Rocks(ref e) => e.file_size(),
         }
     }
+
+    #[inline]
+    fn live_data_size(&self) -> Result<u64, EngineError> {
+        match *self {
+            Engine::Memory(ref e) => e.live_data_size(),
+            Engine::Rocks(ref e) => e.live_data_size(),
+        }
+    }
+
+    #[inline]
+    fn defragment(&self) -> Result<(), EngineError> {
+        match *self {
+            Engine::Memory(ref e) => e.defragment(),
+            Engine::Rocks(ref e) => e.defragment(),
+        }
+    }
 }
 
 /// `Transaction` is designed to mask the different type of `MemoryTransaction` and `RocksTransaction`
@@ -5,7 +5,7 @@ use std::{
     cmp::Ordering,
     env::temp_dir,
     fs, io,
-    io::{Cursor, Error as IoError, ErrorKind},
+    io::{Cursor, Error as IoError, ErrorKind, Read},
     iter::repeat,
     path::{Path, PathBuf},
     sync::{atomic::AtomicU64, Arc},
@@ -15,7 +15,7 @@ use bytes::{Buf, Bytes, BytesMut};
 use clippy_utilities::{NumericCast, OverflowArithmetic};
 use rocksdb::{
     Direction, Error as RocksError, ErrorKind as RocksErrorKind, IteratorMode,
-    OptimisticTransactionDB, Options, SstFileWriter,
+    OptimisticTransactionDB, OptimisticTransactionOptions, Options, SstFileWriter, WriteOptions,
 };
 use serde::{Deserialize, Serialize};
 use tokio::{fs::File, io::AsyncWriteExt};
@@ -200,12 +200,16 @@ impl StorageEngine for RocksEngine {
     }
 
     #[inline]
-    fn write_batch(&self, wr_ops: Vec<WriteOperation<'_>>, _sync: bool) -> Result<(), EngineError> {
+    fn write_batch(&self, wr_ops: Vec<WriteOperation<'_>>, sync: bool) -> Result<(), EngineError> {
         let mut retry_interval = 10;
         let max_retry_count = 5;
         let mut retry_count = 0;
+        let mut write_opts = WriteOptions::default();
+        write_opts.set_sync(sync);
         loop {
-            let transaction = self.inner.transaction();
+            let transaction = self
+                .inner
+                .transaction_opt(&write_opts, &OptimisticTransactionOptions::default());
             let mut size = 0;
             #[allow(clippy::pattern_type_mismatch)] // can't be fixed
             for op in &wr_ops {
@@ -306,6 +310,10 @@ impl StorageEngine for RocksEngine {
                 sst_writer_option = None;
             }
         }
+        for entry in fs::read_dir(path.as_ref())? {
+            fs::File::open(entry?.path())?.sync_all()?;
+        }
+        sync_dir(path.as_ref())?;
         RocksSnapshot::new_for_sending(path.as_ref())
     }
 
@@ -339,15 +347,128 @@ impl StorageEngine for RocksEngine {
         self.size.store(size, std::sync::atomic::Ordering::Relaxed);
         Ok(size)
     }
+
+    /// Get the live data size estimated by rocksdb, the space of the overwritten and
+    /// deleted data is reclaimed by the compactions of the tables
+    fn live_data_size(&self) -> Result<u64, EngineError> {
+        let mut size: u64 = 0;
+        for table in &self.tables {
+            let cf = self
+                .inner
+                .cf_handle(table)
+                .ok_or_else(|| EngineError::TableNotFound(table.clone()))?;
+            size = self
+                .inner
+                .property_int_value_cf(&cf, rocksdb::properties::ESTIMATE_LIVE_DATA_SIZE)?
+                .ok_or(EngineError::UnderlyingError(
+                    "Got None when read ESTIMATE_LIVE_DATA_SIZE".to_owned(),
+                ))?
+                .overflow_add(size);
+        }
+        Ok(size)
+    }
+
+    /// Compact all the tables, the compaction rewrites the sst files so it is slow
+    /// and heavy on the disk
+    fn defragment(&self) -> Result<(), EngineError> {
+        for table in &self.tables {
+            let cf = self
+                .inner
+                .cf_handle(table)
+                .ok_or_else(|| EngineError::TableNotFound(table.clone()))?;
+            self.inner
+                .compact_range_cf(&cf, None::<&[u8]>, None::<&[u8]>);
+        }
+        let _size = self.file_size()?;
+        Ok(())
+    }
+}
+
+/// Sync a directory so that the creation and renaming of its entries are durable
+fn sync_dir(dir: impl AsRef<Path>) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Calculate the crc32 checksum of a file
+fn file_checksum(path: impl AsRef<Path>) -> io::Result<u32> {
+    let mut f = fs::File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0; SNAPSHOT_CHUNK_SIZE];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        #[allow(clippy::indexing_slicing)] // n is always less than or equal to buf.len()
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize())
 }
 
-/// Human readable format for `RocksEngine`
+/// Magic of the versioned snapshot meta, read as the file count of a legacy meta it's
+/// far more than the files a snapshot could have, so the two are told apart by it
+const SNAP_META_MAGIC: &[u8; 7] = b"XLSNAPM";
+
+/// Version of the snapshot meta with the checksums of the files
+const SNAP_META_VERSION: u8 = 1;
+
+/// Human readable format for `RocksEngine`, it's prefixed by the magic and the version
+/// when it is serialized
 #[derive(Debug, Default, Serialize, Deserialize)]
 struct SnapMeta {
+    /// filenames, sizes and crc32 checksums of the snapshot
+    files: Vec<(String, u64, u32)>,
+}
+
+/// Snapshot meta of the versions before the checksums, which has no header
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LegacySnapMeta {
     /// filenames and sizes of the snapshot
     files: Vec<(String, u64)>,
 }
 
+impl SnapMeta {
+    /// Serialize the meta with its header
+    fn encode(&self) -> bincode::Result<Vec<u8>> {
+        let mut buf = SNAP_META_MAGIC.to_vec();
+        buf.push(SNAP_META_VERSION);
+        bincode::serialize_into(&mut buf, self)?;
+        Ok(buf)
+    }
+
+    /// Deserialize the filenames, sizes and checksums of a meta, the files of a legacy
+    /// meta have no checksums
+    fn decode(buf: &[u8]) -> io::Result<Vec<(String, u64, Option<u32>)>> {
+        let invalid = |e| io::Error::new(ErrorKind::InvalidData, e);
+        let Some(rest) = buf.strip_prefix(SNAP_META_MAGIC.as_slice()) else {
+            let meta: LegacySnapMeta = bincode::deserialize(buf).map_err(invalid)?;
+            return Ok(meta
+                .files
+                .into_iter()
+                .map(|(filename, size)| (filename, size, None))
+                .collect());
+        };
+        match rest.split_first() {
+            Some((&SNAP_META_VERSION, meta)) => {
+                let meta: Self = bincode::deserialize(meta).map_err(invalid)?;
+                Ok(meta
+                    .files
+                    .into_iter()
+                    .map(|(filename, size, checksum)| (filename, size, Some(checksum)))
+                    .collect())
+            }
+            Some((version, _)) => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported snapshot meta version {version}"),
+            )),
+            None => Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "cannot read snapshot meta version",
+            )),
+        }
+    }
+}
+
 /// File info for snapshot
 #[derive(Debug, Default)]
 struct SnapFile {
@@ -357,6 +478,10 @@ struct SnapFile {
     written_size: u64,
     /// size of the file
     size: u64,
+    /// crc32 checksum of the file, `None` if it's received with a legacy meta
+    checksum: Option<u32>,
+    /// hasher of the data has been written
+    hasher: crc32fast::Hasher,
 }
 
 impl SnapFile {
@@ -452,10 +577,12 @@ impl RocksSnapshot {
                 EngineError::InvalidArgument("cannot convert filename to string".to_owned())
             })?;
             let size = entry.metadata()?.len();
+            let checksum = file_checksum(entry.path())?;
             s.snap_files.push(SnapFile {
                 filename: filename.clone(),
-                written_size: 0,
                 size,
+                checksum: Some(checksum),
+                ..Default::default()
             });
         }
         s.gen_snap_meta()?;
@@ -468,14 +595,14 @@ impl RocksSnapshot {
         self.dir.join(table).with_extension("sst")
     }
 
-    /// Apply snapshot meta
-    fn apply_snap_meta(&mut self, meta: SnapMeta) {
-        self.snap_files = meta
-            .files
+    /// Apply the filenames, sizes and checksums of the snapshot meta
+    fn apply_snap_meta(&mut self, files: Vec<(String, u64, Option<u32>)>) {
+        self.snap_files = files
             .into_iter()
-            .map(|(filename, size)| SnapFile {
+            .map(|(filename, size, checksum)| SnapFile {
                 filename,
                 size,
+                checksum,
                 ..Default::default()
             })
             .collect::<Vec<_>>();
@@ -487,10 +614,15 @@ impl RocksSnapshot {
             files: self
                 .snap_files
                 .iter()
-                .map(|sf| (sf.filename.clone(), sf.size))
+                .map(|sf| {
+                    let checksum = sf.checksum.unwrap_or_else(|| {
+                        unreachable!("the checksums of a snapshot for sending are computed")
+                    });
+                    (sf.filename.clone(), sf.size, checksum)
+                })
                 .collect::<Vec<_>>(),
         };
-        let meta_bytes = bincode::serialize(&files).map_err(|e| {
+        let meta_bytes = files.encode().map_err(|e| {
             EngineError::UnderlyingError(format!("cannot serialize snapshot meta: {e}"))
         })?;
         let len = meta_bytes.len().numeric_cast::<u64>();
@@ -570,10 +702,9 @@ impl RocksSnapshot {
             };
             let meta_data = buf.split_to(meta_len.numeric_cast::<usize>().overflow_add(8));
             #[allow(clippy::indexing_slicing)]
-            let meta = bincode::deserialize(&meta_data[8..])
-                .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
+            let files = SnapMeta::decode(&meta_data[8..])?;
 
-            self.apply_snap_meta(meta);
+            self.apply_snap_meta(files);
             *self.meta.data.get_mut() = meta_data;
             self.meta.is_current = false;
         }
@@ -598,6 +729,8 @@ impl RocksSnapshot {
             snap_file.written_size = snap_file
                 .written_size
                 .overflow_add(write_len.numeric_cast());
+            let buffer = buf.split_to(write_len);
+            snap_file.hasher.update(&buffer);
 
             let f = if let Some(ref mut f) = self.current_file {
                 f
@@ -609,16 +742,33 @@ impl RocksSnapshot {
                     .as_mut()
                     .unwrap_or_else(|| unreachable!("current_file must be `Some` here"))
             };
-            let buffer = buf.split_to(write_len);
             f.write_all(&buffer).await?;
 
             if switch {
                 let old = self.current_file.take();
                 if let Some(mut old_f) = old {
                     old_f.flush().await?;
+                    old_f.sync_all().await?;
                     let path = self.current_file_path(false);
                     let tmp_path = self.current_file_path(true);
+                    let snap_file = &self.snap_files[self.snap_file_idx];
+                    let checksum = snap_file.hasher.clone().finalize();
+                    // the files of a legacy meta can't be checked
+                    if let Some(expected) = snap_file.checksum {
+                        if checksum != expected {
+                            fs::remove_file(tmp_path)?;
+                            return Err(io::Error::new(
+                                ErrorKind::InvalidData,
+                                format!(
+                                    "snap file {} is corrupted, expected checksum {expected:x}, \
+                                     got {checksum:x}",
+                                    snap_file.filename
+                                ),
+                            ));
+                        }
+                    }
                     fs::rename(tmp_path, path)?;
+                    sync_dir(&self.dir)?;
                 }
                 self.snap_file_idx = self.snap_file_idx.overflow_add(1);
             }
@@ -719,6 +869,106 @@ mod test {
         fs::remove_dir_all(dir).unwrap();
     }
 
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_corrupted_snapshot_should_be_detected() {
+        let dir = temp_dir().join("test_corrupted_snapshot");
+        let engine = RocksEngine::new(dir.join("origin"), &TEST_TABLES).unwrap();
+        engine
+            .write_batch(
+                vec![WriteOperation::new_put(
+                    "t1",
+                    b"key".to_vec(),
+                    b"value".to_vec(),
+                )],
+                true,
+            )
+            .unwrap();
+        let mut snapshot = engine
+            .get_snapshot(dir.join("snapshot"), &TEST_TABLES)
+            .unwrap();
+        let mut data = BytesMut::with_capacity(snapshot.size().numeric_cast());
+        snapshot.read_buf_exact(&mut data).await.unwrap();
+        snapshot.clean().await.unwrap();
+
+        let snap_file = dir.join("snapshot_file");
+        fs::write(&snap_file, &data).unwrap();
+        let restored = RocksEngine::new(dir.join("restored"), &TEST_TABLES).unwrap();
+        restored
+            .apply_snapshot_from_file(&snap_file, &TEST_TABLES)
+            .await
+            .unwrap();
+        assert_eq!(restored.get("t1", "key").unwrap(), Some(b"value".to_vec()));
+
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        fs::write(&snap_file, &data).unwrap();
+        let corrupted = RocksEngine::new(dir.join("corrupted"), &TEST_TABLES).unwrap();
+        let res = corrupted
+            .apply_snapshot_from_file(&snap_file, &TEST_TABLES)
+            .await;
+        assert!(
+            matches!(res, Err(EngineError::IoError(ref e)) if e.kind() == ErrorKind::InvalidData),
+            "the corrupted snapshot should be refused, got {res:?}"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_legacy_snapshot_meta_should_be_decoded() {
+        let dir = temp_dir().join("test_legacy_snapshot_meta");
+        let engine = RocksEngine::new(dir.join("origin"), &TEST_TABLES).unwrap();
+        engine
+            .write_batch(
+                vec![WriteOperation::new_put(
+                    "t1",
+                    b"key".to_vec(),
+                    b"value".to_vec(),
+                )],
+                true,
+            )
+            .unwrap();
+        let mut snapshot = engine
+            .get_snapshot(dir.join("snapshot"), &TEST_TABLES)
+            .unwrap();
+        let mut data = BytesMut::with_capacity(snapshot.size().numeric_cast());
+        snapshot.read_buf_exact(&mut data).await.unwrap();
+        snapshot.clean().await.unwrap();
+
+        let meta_len: usize = data.as_ref().get_u64_le().numeric_cast();
+        let files = data.split_off(meta_len + 8);
+        let legacy = LegacySnapMeta {
+            files: SnapMeta::decode(&data[8..])
+                .unwrap()
+                .into_iter()
+                .map(|(filename, size, checksum)| {
+                    assert!(checksum.is_some());
+                    (filename, size)
+                })
+                .collect(),
+        };
+        let legacy = bincode::serialize(&legacy).unwrap();
+        let mut legacy_data = BytesMut::new();
+        legacy_data.extend_from_slice(&legacy.len().numeric_cast::<u64>().to_le_bytes());
+        legacy_data.extend_from_slice(&legacy);
+        legacy_data.extend_from_slice(&files);
+        let snap_file = dir.join("snapshot_file");
+        fs::write(&snap_file, &legacy_data).unwrap();
+        let restored = RocksEngine::new(dir.join("restored"), &TEST_TABLES).unwrap();
+        restored
+            .apply_snapshot_from_file(&snap_file, &TEST_TABLES)
+            .await
+            .unwrap();
+        assert_eq!(restored.get("t1", "key").unwrap(), Some(b"value".to_vec()));
+
+        let mut unknown = SNAP_META_MAGIC.to_vec();
+        unknown.push(SNAP_META_VERSION + 1);
+        let res = SnapMeta::decode(&unknown);
+        assert!(matches!(res, Err(ref e) if e.kind() == ErrorKind::InvalidData));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
     #[test]
     fn test_engine_size() {
         let path = temp_dir().join("test_engine_size");
@@ -29,7 +29,7 @@ use parking_lot::Mutex;
 use tokio::sync::mpsc;
 use tracing::debug;
 use utils::{
-    config::{ClientConfig, CurpConfigBuilder, EngineConfig},
+    config::{ClientConfig, CurpConfigBuilder, EngineConfig, MessageSizeLimits},
     task_manager::TaskManager,
 };
 
@@ -135,6 +135,7 @@ impl CurpGroup {
                             curp_storage,
                             task_manager,
                             None,
+                            MessageSizeLimits::default(),
                             vec![Box::<TestSpecPool>::default()],
                             vec![Box::<TestUncomPool>::default()],
                         )
@@ -6,7 +6,7 @@ use tonic::transport::Channel;
 use tracing::debug;
 use utils::config::{
     AuthConfig, ClientConfig, ClusterConfig, CompactConfig, CurpConfig, InitialClusterState,
-    ServerTimeout, StorageConfig, TlsConfig,
+    KvConfig, MessageSizeConfig, ServerTimeout, StorageConfig, TlsConfig, WatchConfig,
 };
 use xline::server::XlineServer;
 use xline_client::{
@@ -60,6 +60,10 @@ impl XlineGroup {
                     ClientConfig::default(),
                     ServerTimeout::default(),
                     InitialClusterState::New,
+                    MessageSizeConfig::default(),
+                    HashMap::new(),
+                    Vec::new(),
+                    Vec::new(),
                 );
 
                 let handle = handle
@@ -75,6 +79,8 @@ impl XlineGroup {
                                 CompactConfig::default(),
                                 AuthConfig::default(),
                                 TlsConfig::default(),
+                                WatchConfig::default(),
+                                KvConfig::default(),
                             )
                             .await
                             .unwrap();
@@ -1,5 +1,6 @@
-use std::{collections::HashMap, path::PathBuf, time::Duration};
+use std::{cmp::Ordering, collections::HashMap, path::PathBuf, time::Duration};
 
+use clippy_utilities::NumericCast;
 use derive_builder::Builder;
 use getset::Getters;
 use serde::Deserialize;
@@ -34,6 +35,14 @@ pub struct XlineServerConfig {
     #[getset(get = "pub")]
     #[serde(default = "MetricsConfig::default")]
     metrics: MetricsConfig,
+    /// Watch config
+    #[getset(get = "pub")]
+    #[serde(default = "WatchConfig::default")]
+    watch: WatchConfig,
+    /// KV config
+    #[getset(get = "pub")]
+    #[serde(default = "KvConfig::default")]
+    kv: KvConfig,
 }
 
 /// Cluster Range type alias
@@ -119,6 +128,25 @@ pub struct ClusterConfig {
     #[getset(get = "pub")]
     #[serde(with = "state_format", default = "InitialClusterState::default")]
     initial_cluster_state: InitialClusterState,
+    /// Grpc message size limits of the client and peer listeners
+    #[getset(get = "pub")]
+    #[serde(default = "MessageSizeConfig::default")]
+    message_size: MessageSizeConfig,
+    /// The tags of this member, eg: its region, zone and role, which are replicated to
+    /// the cluster and returned by the member list
+    #[getset(get = "pub")]
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    /// The urls of the client listeners which only serve the reads, the writes sent
+    /// to them are rejected
+    #[getset(get = "pub")]
+    #[serde(default)]
+    read_listen_urls: Vec<String>,
+    /// The urls of the client listeners which only serve the writes, the reads sent
+    /// to them are rejected
+    #[getset(get = "pub")]
+    #[serde(default)]
+    write_listen_urls: Vec<String>,
 }
 
 impl Default for ClusterConfig {
@@ -139,6 +167,10 @@ impl Default for ClusterConfig {
             client_config: ClientConfig::default(),
             server_timeout: ServerTimeout::default(),
             initial_cluster_state: InitialClusterState::default(),
+            message_size: MessageSizeConfig::default(),
+            tags: HashMap::new(),
+            read_listen_urls: Vec::new(),
+            write_listen_urls: Vec::new(),
         }
     }
 }
@@ -189,6 +221,10 @@ impl ClusterConfig {
         client_config: ClientConfig,
         server_timeout: ServerTimeout,
         initial_cluster_state: InitialClusterState,
+        message_size: MessageSizeConfig,
+        tags: HashMap<String, String>,
+        read_listen_urls: Vec<String>,
+        write_listen_urls: Vec<String>,
     ) -> Self {
         Self {
             name,
@@ -202,12 +238,16 @@ impl ClusterConfig {
             client_config,
             server_timeout,
             initial_cluster_state,
+            message_size,
+            tags,
+            read_listen_urls,
+            write_listen_urls,
         }
     }
 }
 
 /// Compaction configuration
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Getters)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Getters)]
 #[allow(clippy::module_name_repetitions)]
 pub struct CompactConfig {
     /// The max number of historical versions processed in a single compact operation
@@ -221,6 +261,36 @@ pub struct CompactConfig {
     /// The auto compactor config
     #[getset(get = "pub")]
     auto_compact_config: Option<AutoCompactConfig>,
+    /// The number of auth history revisions to retain, 0 means the auth history is disabled.
+    /// The auth history is compacted independently of the KV history.
+    #[getset(get = "pub")]
+    #[serde(default = "default_history_retention")]
+    auth_history_retention: usize,
+    /// The number of lease history revisions to retain, 0 means the lease history is disabled.
+    /// The lease history is compacted independently of the KV history.
+    #[getset(get = "pub")]
+    #[serde(default = "default_history_retention")]
+    lease_history_retention: usize,
+    /// The key prefixes whose history is exempt from the KV compaction, the history
+    /// of a protected key is readable below the compacted revision
+    #[getset(get = "pub")]
+    #[serde(default)]
+    protected_prefixes: Vec<String>,
+    /// The number of revisions for which the history of a deleted protected key is
+    /// retained after its deletion, 0 means it is retained forever
+    #[getset(get = "pub")]
+    #[serde(default = "default_protected_retention")]
+    protected_retention: usize,
+    /// Hold the auto-compaction below the minimum start revision of the active
+    /// historical watches, so that they are not canceled by the compaction
+    #[getset(get = "pub")]
+    #[serde(default)]
+    watch_safety_floor: bool,
+    /// The number of revisions the auto-compaction is held below the minimum start
+    /// revision of the active historical watches
+    #[getset(get = "pub")]
+    #[serde(default = "default_watch_safety_margin")]
+    watch_safety_margin: i64,
 }
 
 impl Default for CompactConfig {
@@ -230,6 +300,12 @@ impl Default for CompactConfig {
             compact_batch_size: default_compact_batch_size(),
             compact_sleep_interval: default_compact_sleep_interval(),
             auto_compact_config: None,
+            auth_history_retention: default_history_retention(),
+            lease_history_retention: default_history_retention(),
+            protected_prefixes: Vec::new(),
+            protected_retention: default_protected_retention(),
+            watch_safety_floor: false,
+            watch_safety_margin: default_watch_safety_margin(),
         }
     }
 }
@@ -238,19 +314,53 @@ impl CompactConfig {
     /// Create a new compact config
     #[must_use]
     #[inline]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         compact_batch_size: usize,
         compact_sleep_interval: Duration,
         auto_compact_config: Option<AutoCompactConfig>,
+        auth_history_retention: usize,
+        lease_history_retention: usize,
+        protected_prefixes: Vec<String>,
+        protected_retention: usize,
+        watch_safety_floor: bool,
+        watch_safety_margin: i64,
     ) -> Self {
         Self {
             compact_batch_size,
             compact_sleep_interval,
             auto_compact_config,
+            auth_history_retention,
+            lease_history_retention,
+            protected_prefixes,
+            protected_retention,
+            watch_safety_floor,
+            watch_safety_margin,
         }
     }
 }
 
+/// default auth and lease history retention
+#[must_use]
+#[inline]
+pub const fn default_history_retention() -> usize {
+    0
+}
+
+/// default retention of the history of deleted protected keys
+#[must_use]
+#[inline]
+pub const fn default_protected_retention() -> usize {
+    0
+}
+
+/// default margin of the auto-compaction below the active historical watches
+#[must_use]
+#[inline]
+pub const fn default_watch_safety_margin() -> i64 {
+    0
+}
+
 /// default compact batch size
 #[must_use]
 #[inline]
@@ -336,6 +446,30 @@ pub struct CurpConfig {
     #[builder(default = "default_log_entries_cap()")]
     #[serde(default = "default_log_entries_cap")]
     pub log_entries_cap: usize,
+
+    /// Whether every command is executed in the order of the log, the speculative
+    /// execution is disabled and every command takes the slow path, so that the
+    /// outcomes of concurrent commands are deterministic. It's intended for testing.
+    #[builder(default = "false")]
+    #[serde(default)]
+    pub ordered_execution: bool,
+
+    /// The number of elections allowed in `leader_flap_window`, more elections in the
+    /// window mean the leader is flapping. 0 means the detection is disabled
+    #[builder(default = "default_leader_flap_threshold()")]
+    #[serde(default = "default_leader_flap_threshold")]
+    pub leader_flap_threshold: usize,
+
+    /// The window in which the elections are counted to detect the leader flapping
+    #[builder(default = "default_leader_flap_window()")]
+    #[serde(with = "duration_format", default = "default_leader_flap_window")]
+    pub leader_flap_window: Duration,
+
+    /// Whether the election timeouts are doubled on each election while the leader is
+    /// flapping, up to 8 times, until no flapping is detected in a whole window
+    #[builder(default = "false")]
+    #[serde(default)]
+    pub leader_flap_backoff: bool,
 }
 
 /// default heartbeat interval
@@ -494,6 +628,58 @@ pub const fn default_watch_progress_notify_interval() -> Duration {
     Duration::from_secs(600)
 }
 
+/// The maximum grace period of lease expiry
+pub const MAX_LEASE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// default lease grace period
+#[must_use]
+#[inline]
+pub const fn default_lease_grace_period() -> Duration {
+    Duration::ZERO
+}
+
+/// default apply stall threshold
+#[must_use]
+#[inline]
+pub const fn default_apply_stall_threshold() -> Duration {
+    Duration::ZERO
+}
+
+/// default lease keepalive send timeout
+#[must_use]
+#[inline]
+pub const fn default_lease_keep_alive_send_timeout() -> Duration {
+    Duration::ZERO
+}
+
+/// default leader flap threshold
+#[must_use]
+#[inline]
+pub const fn default_leader_flap_threshold() -> usize {
+    0
+}
+
+/// default leader flap window
+#[must_use]
+#[inline]
+pub const fn default_leader_flap_window() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// default read index batch window
+#[must_use]
+#[inline]
+pub const fn default_read_index_batch_window() -> Duration {
+    Duration::ZERO
+}
+
+/// default catch-up read timeout
+#[must_use]
+#[inline]
+pub const fn default_catch_up_read_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
 impl Default for CurpConfig {
     #[inline]
     fn default() -> Self {
@@ -510,6 +696,10 @@ impl Default for CurpConfig {
             cmd_workers: default_cmd_workers(),
             gc_interval: default_gc_interval(),
             log_entries_cap: default_log_entries_cap(),
+            ordered_execution: false,
+            leader_flap_threshold: default_leader_flap_threshold(),
+            leader_flap_window: default_leader_flap_window(),
+            leader_flap_backoff: false,
         }
     }
 }
@@ -630,108 +820,1292 @@ pub struct ServerTimeout {
         default = "default_watch_progress_notify_interval"
     )]
     watch_progress_notify_interval: Duration,
+    /// Grace period added to the expiry of a lease before it is revoked, so that a
+    /// keepalive delayed by clock skew or a pause can still renew the lease. It
+    /// extends the lifetime of a lease after its last keepalive by the same amount,
+    /// and is capped at `MAX_LEASE_GRACE_PERIOD`.
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_lease_grace_period")]
+    lease_grace_period: Duration,
+    /// How long the applied index may stay behind the commit index without
+    /// advancing before the node reports itself as not serving, 0 disables the
+    /// apply watchdog
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_apply_stall_threshold")]
+    apply_stall_threshold: Duration,
+    /// How long a lease keepalive response may wait for a consumer that stops
+    /// reading its stream before the stream is closed, 0 disables the detection
+    #[getset(get = "pub")]
+    #[serde(
+        with = "duration_format",
+        default = "default_lease_keep_alive_send_timeout"
+    )]
+    lease_keep_alive_send_timeout: Duration,
+    /// The window in which the concurrent linearizable reads are coalesced into a
+    /// single read index, each read still waits for a read index fetched after it
+    /// arrives, 0 disables the coalescing
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_read_index_batch_window")]
+    read_index_batch_window: Duration,
+    /// The maximum time a catch-up read waits for the node to apply the commit index it
+    /// learns from the leader
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_catch_up_read_timeout")]
+    catch_up_read_timeout: Duration,
 }
 
 impl ServerTimeout {
     /// Create a new server timeout
     #[must_use]
     #[inline]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         range_retry_timeout: Duration,
         compact_timeout: Duration,
         sync_victims_interval: Duration,
         watch_progress_notify_interval: Duration,
+        lease_grace_period: Duration,
+        apply_stall_threshold: Duration,
+        lease_keep_alive_send_timeout: Duration,
+        read_index_batch_window: Duration,
+        catch_up_read_timeout: Duration,
+    ) -> Self {
+        Self {
+            range_retry_timeout,
+            compact_timeout,
+            sync_victims_interval,
+            watch_progress_notify_interval,
+            lease_grace_period,
+            apply_stall_threshold,
+            lease_keep_alive_send_timeout,
+            read_index_batch_window,
+            catch_up_read_timeout,
+        }
+    }
+}
+
+impl Default for ServerTimeout {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            range_retry_timeout: default_range_retry_timeout(),
+            compact_timeout: default_compact_timeout(),
+            sync_victims_interval: default_sync_victims_interval(),
+            watch_progress_notify_interval: default_watch_progress_notify_interval(),
+            lease_grace_period: default_lease_grace_period(),
+            apply_stall_threshold: default_apply_stall_threshold(),
+            lease_keep_alive_send_timeout: default_lease_keep_alive_send_timeout(),
+            read_index_batch_window: default_read_index_batch_window(),
+            catch_up_read_timeout: default_catch_up_read_timeout(),
+        }
+    }
+}
+
+/// Grpc message size limits in bytes, the client listener serves the clients
+/// and the peer listener serves the other members, 0 means unlimited
+///
+/// The limits of a listener also apply to the channels a member opens to the same
+/// listener of the others, e.g. the writes forwarded to the client listener of the
+/// leader, or the log entries sent to the peer listeners of the followers.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq, Getters)]
+pub struct MessageSizeConfig {
+    /// The maximum size of a message sent by the client listener or a client channel
+    #[getset(get = "pub")]
+    #[serde(default = "default_max_send_message_size")]
+    client_max_send: u64,
+    /// The maximum size of a message received by the client listener or a client
+    /// channel
+    #[getset(get = "pub")]
+    #[serde(default = "default_max_recv_message_size")]
+    client_max_recv: u64,
+    /// The maximum size of a message sent by the peer listener or a peer channel
+    #[getset(get = "pub")]
+    #[serde(default = "default_max_send_message_size")]
+    peer_max_send: u64,
+    /// The maximum size of a message received by the peer listener or a peer channel
+    #[getset(get = "pub")]
+    #[serde(default = "default_max_recv_message_size")]
+    peer_max_recv: u64,
+}
+
+impl MessageSizeConfig {
+    /// Create a new message size config
+    #[must_use]
+    #[inline]
+    pub fn new(
+        client_max_send: u64,
+        client_max_recv: u64,
+        peer_max_send: u64,
+        peer_max_recv: u64,
+    ) -> Self {
+        Self {
+            client_max_send,
+            client_max_recv,
+            peer_max_send,
+            peer_max_recv,
+        }
+    }
+
+    /// Get the limits of the client listener and the client channels
+    #[must_use]
+    #[inline]
+    pub fn client_limits(&self) -> MessageSizeLimits {
+        MessageSizeLimits::new(self.client_max_send, self.client_max_recv)
+    }
+
+    /// Get the limits of the peer listener and the peer channels
+    #[must_use]
+    #[inline]
+    pub fn peer_limits(&self) -> MessageSizeLimits {
+        MessageSizeLimits::new(self.peer_max_send, self.peer_max_recv)
+    }
+}
+
+impl Default for MessageSizeConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            client_max_send: default_max_send_message_size(),
+            client_max_recv: default_max_recv_message_size(),
+            peer_max_send: default_max_send_message_size(),
+            peer_max_recv: default_max_recv_message_size(),
+        }
+    }
+}
+
+/// The grpc message size limits of a service or a channel, in the form taken by tonic
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Getters)]
+pub struct MessageSizeLimits {
+    /// The maximum size of an encoded message
+    #[getset(get = "pub")]
+    max_send: usize,
+    /// The maximum size of a decoded message
+    #[getset(get = "pub")]
+    max_recv: usize,
+}
+
+impl MessageSizeLimits {
+    /// Create the limits from the configured sizes, 0 means unlimited
+    #[must_use]
+    #[inline]
+    pub fn new(max_send: u64, max_recv: u64) -> Self {
+        let limit = |size: u64| {
+            if size == 0 {
+                usize::MAX
+            } else {
+                size.numeric_cast()
+            }
+        };
+        Self {
+            max_send: limit(max_send),
+            max_recv: limit(max_recv),
+        }
+    }
+}
+
+impl Default for MessageSizeLimits {
+    #[inline]
+    fn default() -> Self {
+        Self::new(
+            default_max_send_message_size(),
+            default_max_recv_message_size(),
+        )
+    }
+}
+
+/// default max send message size, it's unlimited
+#[must_use]
+#[inline]
+pub const fn default_max_send_message_size() -> u64 {
+    0
+}
+
+/// default max recv message size: 4MB
+#[must_use]
+#[inline]
+pub const fn default_max_recv_message_size() -> u64 {
+    4 * 1024 * 1024
+}
+
+/// Watch configuration object
+#[allow(clippy::module_name_repetitions)]
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq, Getters)]
+pub struct WatchConfig {
+    /// Whether a watch created from the current revision should perform a
+    /// read index first, so that its start revision is linearizable
+    #[getset(get = "pub")]
+    #[serde(default = "default_linearizable_watch_create")]
+    linearizable_watch_create: bool,
+    /// How a watch created from a historical revision is handled
+    #[getset(get = "pub")]
+    #[serde(
+        with = "history_replay_format",
+        default = "WatchHistoryReplay::default"
+    )]
+    history_replay: WatchHistoryReplay,
+    /// How an event which alone exceeds the max send message size is delivered
+    #[getset(get = "pub")]
+    #[serde(
+        with = "oversized_event_format",
+        default = "OversizedWatchEvent::default"
+    )]
+    oversized_event: OversizedWatchEvent,
+    /// The lower bound of the buffer depth a watch can request
+    #[getset(get = "pub")]
+    #[serde(default = "default_min_watch_buffer_depth")]
+    min_buffer_depth: usize,
+    /// The upper bound of the buffer depth a watch can request
+    #[getset(get = "pub")]
+    #[serde(default = "default_max_watch_buffer_depth")]
+    max_buffer_depth: usize,
+}
+
+impl WatchConfig {
+    /// Create a new watch config
+    #[must_use]
+    #[inline]
+    pub fn new(
+        linearizable_watch_create: bool,
+        history_replay: WatchHistoryReplay,
+        oversized_event: OversizedWatchEvent,
+        min_buffer_depth: usize,
+        max_buffer_depth: usize,
     ) -> Self {
         Self {
-            range_retry_timeout,
-            compact_timeout,
-            sync_victims_interval,
-            watch_progress_notify_interval,
+            linearizable_watch_create,
+            history_replay,
+            oversized_event,
+            min_buffer_depth,
+            max_buffer_depth,
+        }
+    }
+}
+
+impl Default for WatchConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            linearizable_watch_create: default_linearizable_watch_create(),
+            history_replay: WatchHistoryReplay::default(),
+            oversized_event: OversizedWatchEvent::default(),
+            min_buffer_depth: default_min_watch_buffer_depth(),
+            max_buffer_depth: default_max_watch_buffer_depth(),
+        }
+    }
+}
+
+/// How a watch created from a historical revision, i.e. a start revision not
+/// greater than the current revision, is handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub enum WatchHistoryReplay {
+    /// Replay the historical events to the watch
+    #[default]
+    Allow,
+    /// Reject the watch, so that past data is never exposed through watch
+    Reject,
+    /// Start the watch from the current revision, so that only future events
+    /// are delivered
+    Current,
+}
+
+/// `WatchHistoryReplay` deserialization formatter
+pub mod history_replay_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::WatchHistoryReplay;
+    use crate::parse_watch_history_replay;
+
+    /// deserializes a watch history replay mode
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<WatchHistoryReplay, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_watch_history_replay(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// How a watch event which alone exceeds the max send message size to clients is
+/// delivered, instead of breaking the watch stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub enum OversizedWatchEvent {
+    /// Split the value of the event across several responses, which the client
+    /// reassembles
+    #[default]
+    Fragment,
+    /// Deliver the event without its values and flag it as truncated, so that the
+    /// client fetches the value by a range
+    Truncate,
+}
+
+/// `OversizedWatchEvent` deserialization formatter
+pub mod oversized_event_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::OversizedWatchEvent;
+    use crate::parse_oversized_watch_event;
+
+    /// deserializes an oversized watch event mode
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<OversizedWatchEvent, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_oversized_watch_event(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// default linearizable watch create
+#[must_use]
+#[inline]
+pub const fn default_linearizable_watch_create() -> bool {
+    false
+}
+
+/// default min watch buffer depth
+#[must_use]
+#[inline]
+pub const fn default_min_watch_buffer_depth() -> usize {
+    16
+}
+
+/// default max watch buffer depth
+#[must_use]
+#[inline]
+pub const fn default_max_watch_buffer_depth() -> usize {
+    65536
+}
+
+/// KV configuration object
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Getters)]
+pub struct KvConfig {
+    /// Whether a put whose value and lease equal the current ones is a no-op
+    /// that does not create a new revision, this breaks the etcd semantics
+    #[getset(get = "pub")]
+    #[serde(default = "default_noop_identical_put")]
+    noop_identical_put: bool,
+    /// Whether a put with an empty value is rejected, it catches puts which are
+    /// meant to be deletes
+    #[getset(get = "pub")]
+    #[serde(default = "default_reject_empty_value_put")]
+    reject_empty_value_put: bool,
+    /// Values of at least this many bytes are stored once by their content and
+    /// shared among keys and revisions, 0 disables the deduplication. It should be
+    /// the same on all members
+    #[getset(get = "pub")]
+    #[serde(default = "default_dedup_value_threshold")]
+    dedup_value_threshold: u64,
+    /// The memory budget in bytes of the key-value pairs in a range or txn response,
+    /// a request exceeding it is aborted, 0 means unlimited
+    #[getset(get = "pub")]
+    #[serde(default = "default_range_memory_budget")]
+    range_memory_budget: u64,
+    /// Whether a follower proxies the writes it receives to the leader and returns
+    /// the result, instead of proposing them itself
+    #[getset(get = "pub")]
+    #[serde(default = "default_forward_writes_to_leader")]
+    forward_writes_to_leader: bool,
+    /// The maximum window in which the puts hinted by the `write-coalescing-window`
+    /// metadata are coalesced into a single proposal, longer hints are capped to
+    /// it, 0 disables the coalescing
+    #[getset(get = "pub")]
+    #[serde(
+        with = "duration_format",
+        default = "default_max_write_coalescing_window"
+    )]
+    max_write_coalescing_window: Duration,
+    /// How a follower handles serializable reads while it is installing a snapshot
+    #[getset(get = "pub")]
+    #[serde(
+        with = "snapshot_install_reads_format",
+        default = "SnapshotInstallReads::default"
+    )]
+    snapshot_install_reads: SnapshotInstallReads,
+    /// The encoding that the keys and values of requests must be valid in
+    #[getset(get = "pub")]
+    #[serde(
+        with = "key_value_encoding_format",
+        default = "KeyValueEncoding::default"
+    )]
+    key_value_encoding: KeyValueEncoding,
+    /// The minimum number of healthy voters, including the leader, required to accept
+    /// writes, a voter is healthy if it has responded to the leader within an election
+    /// timeout. Writes below it are rejected while reads are still served, 0 means only
+    /// the quorum is required
+    #[getset(get = "pub")]
+    #[serde(default = "default_min_healthy_voters")]
+    min_healthy_voters: usize,
+    /// How linearizable reads are handled while the cluster has no known leader
+    #[getset(get = "pub")]
+    #[serde(with = "leaderless_reads_format", default = "LeaderlessReads::default")]
+    leaderless_reads: LeaderlessReads,
+    /// The maximum time a linearizable read waits for a leader to be elected when
+    /// the leaderless reads wait for it
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_leaderless_read_timeout")]
+    leaderless_read_timeout: Duration,
+    /// The key prefixes whose key count and value bytes are exported as metrics
+    #[getset(get = "pub")]
+    #[serde(default)]
+    tracked_prefixes: Vec<String>,
+    /// The key prefixes whose keys can only be put with an expected mod revision,
+    /// a blind put to them is rejected
+    #[getset(get = "pub")]
+    #[serde(default)]
+    guarded_prefixes: Vec<String>,
+    /// The key prefixes whose lease-attached keys can't be silently detached from
+    /// their leases, a put to them must keep the lease by `ignore_lease` or detach it
+    /// explicitly
+    #[getset(get = "pub")]
+    #[serde(default)]
+    lease_guarded_prefixes: Vec<String>,
+    /// Whether a node keeps the last time each key is read by its range requests, so
+    /// that the cold keys can be found. It is best effort, the times are only in the
+    /// memory of the node and not replicated
+    #[getset(get = "pub")]
+    #[serde(default = "default_track_last_access")]
+    track_last_access: bool,
+    /// A txn which only puts at least this many keys is applied as a bulk load, whose
+    /// keys are merged into the index at once instead of one by one, which speeds up
+    /// initial loads. The index is fully updated before the txn is acknowledged, so
+    /// the results are the same as the normal writes, 0 disables it
+    #[getset(get = "pub")]
+    #[serde(default = "default_bulk_load_threshold")]
+    bulk_load_threshold: usize,
+    /// Whether a write can ask for how long its command took from the proposal to
+    /// the apply, measured by the node serving it. It is meant for debugging, since
+    /// such a write always waits for its apply instead of returning by the fast path
+    #[getset(get = "pub")]
+    #[serde(default = "default_report_apply_latency")]
+    report_apply_latency: bool,
+    /// The maximum time a request numbered by the `request-seq` metadata waits for the
+    /// earlier requests of its connection to arrive, a request arriving after a later
+    /// one has stopped waiting for it is rejected, 0 disables the ordering
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_max_request_order_wait")]
+    max_request_order_wait: Duration,
+    /// The maximum number of keys a range returns after its filters, a range without
+    /// a smaller limit returning more is handled by the range result overflow, 0
+    /// means unlimited
+    #[getset(get = "pub")]
+    #[serde(default = "default_max_range_result_count")]
+    max_range_result_count: usize,
+    /// How a range returning more keys than the max range result count is handled
+    #[getset(get = "pub")]
+    #[serde(
+        with = "range_result_overflow_format",
+        default = "RangeResultOverflow::default"
+    )]
+    range_result_overflow: RangeResultOverflow,
+    /// The characters the keys under the key charset prefixes must consist of
+    #[getset(get = "pub")]
+    #[serde(with = "key_charset_format", default = "KeyCharset::default")]
+    key_charset: KeyCharset,
+    /// The key prefixes whose keys must consist of the characters of the key charset,
+    /// a put of a key with a disallowed byte to them is rejected
+    #[getset(get = "pub")]
+    #[serde(default)]
+    key_charset_prefixes: Vec<String>,
+    /// The maximum number of writes a node has proposed and not yet returned, a write
+    /// beyond it is rejected at once with `ResourceExhausted` and a retry-after hint,
+    /// instead of queuing and growing the latency of all writes, 0 means unlimited
+    #[getset(get = "pub")]
+    #[serde(default = "default_max_inflight_proposals")]
+    max_inflight_proposals: usize,
+    /// The key prefixes whose keys are write-once, an existing key under them can't be
+    /// overwritten or deleted unless an admin unlocks the write for a correction
+    #[getset(get = "pub")]
+    #[serde(default)]
+    immutable_prefixes: Vec<String>,
+    /// The maximum number of keys in a response of the streamed range, which yields a
+    /// large range in batches of up to it, 0 disables the streamed range
+    #[getset(get = "pub")]
+    #[serde(default = "default_range_stream_batch_size")]
+    range_stream_batch_size: usize,
+}
+
+impl KvConfig {
+    /// Create a new kv config
+    #[must_use]
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        noop_identical_put: bool,
+        reject_empty_value_put: bool,
+        dedup_value_threshold: u64,
+        range_memory_budget: u64,
+        forward_writes_to_leader: bool,
+        max_write_coalescing_window: Duration,
+        snapshot_install_reads: SnapshotInstallReads,
+        key_value_encoding: KeyValueEncoding,
+        min_healthy_voters: usize,
+        leaderless_reads: LeaderlessReads,
+        leaderless_read_timeout: Duration,
+        tracked_prefixes: Vec<String>,
+        guarded_prefixes: Vec<String>,
+        lease_guarded_prefixes: Vec<String>,
+        track_last_access: bool,
+        bulk_load_threshold: usize,
+        report_apply_latency: bool,
+        max_request_order_wait: Duration,
+        max_range_result_count: usize,
+        range_result_overflow: RangeResultOverflow,
+        key_charset: KeyCharset,
+        key_charset_prefixes: Vec<String>,
+        max_inflight_proposals: usize,
+        immutable_prefixes: Vec<String>,
+        range_stream_batch_size: usize,
+    ) -> Self {
+        Self {
+            noop_identical_put,
+            reject_empty_value_put,
+            dedup_value_threshold,
+            range_memory_budget,
+            forward_writes_to_leader,
+            max_write_coalescing_window,
+            snapshot_install_reads,
+            key_value_encoding,
+            min_healthy_voters,
+            leaderless_reads,
+            leaderless_read_timeout,
+            tracked_prefixes,
+            guarded_prefixes,
+            lease_guarded_prefixes,
+            track_last_access,
+            bulk_load_threshold,
+            report_apply_latency,
+            max_request_order_wait,
+            max_range_result_count,
+            range_result_overflow,
+            key_charset,
+            key_charset_prefixes,
+            max_inflight_proposals,
+            immutable_prefixes,
+            range_stream_batch_size,
+        }
+    }
+}
+
+impl Default for KvConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            noop_identical_put: default_noop_identical_put(),
+            reject_empty_value_put: default_reject_empty_value_put(),
+            dedup_value_threshold: default_dedup_value_threshold(),
+            range_memory_budget: default_range_memory_budget(),
+            forward_writes_to_leader: default_forward_writes_to_leader(),
+            max_write_coalescing_window: default_max_write_coalescing_window(),
+            snapshot_install_reads: SnapshotInstallReads::default(),
+            key_value_encoding: KeyValueEncoding::default(),
+            min_healthy_voters: default_min_healthy_voters(),
+            leaderless_reads: LeaderlessReads::default(),
+            leaderless_read_timeout: default_leaderless_read_timeout(),
+            tracked_prefixes: Vec::new(),
+            guarded_prefixes: Vec::new(),
+            lease_guarded_prefixes: Vec::new(),
+            track_last_access: default_track_last_access(),
+            bulk_load_threshold: default_bulk_load_threshold(),
+            report_apply_latency: default_report_apply_latency(),
+            max_request_order_wait: default_max_request_order_wait(),
+            max_range_result_count: default_max_range_result_count(),
+            range_result_overflow: RangeResultOverflow::default(),
+            key_charset: KeyCharset::default(),
+            key_charset_prefixes: Vec::new(),
+            max_inflight_proposals: default_max_inflight_proposals(),
+            immutable_prefixes: Vec::new(),
+            range_stream_batch_size: default_range_stream_batch_size(),
+        }
+    }
+}
+
+/// default noop identical put
+#[must_use]
+#[inline]
+pub const fn default_noop_identical_put() -> bool {
+    false
+}
+
+/// default reject empty value put
+#[must_use]
+#[inline]
+pub const fn default_reject_empty_value_put() -> bool {
+    false
+}
+
+/// default dedup value threshold
+#[must_use]
+#[inline]
+pub const fn default_dedup_value_threshold() -> u64 {
+    0
+}
+
+/// default range memory budget
+#[must_use]
+#[inline]
+pub const fn default_range_memory_budget() -> u64 {
+    0
+}
+
+/// default max range result count
+#[must_use]
+#[inline]
+pub const fn default_max_range_result_count() -> usize {
+    0
+}
+
+/// default max inflight proposals
+#[must_use]
+#[inline]
+pub const fn default_max_inflight_proposals() -> usize {
+    0
+}
+
+/// default range stream batch size
+#[must_use]
+#[inline]
+pub const fn default_range_stream_batch_size() -> usize {
+    1000
+}
+
+/// default forward writes to leader
+#[must_use]
+#[inline]
+pub const fn default_forward_writes_to_leader() -> bool {
+    false
+}
+
+/// default max write coalescing window
+#[must_use]
+#[inline]
+pub const fn default_max_write_coalescing_window() -> Duration {
+    Duration::ZERO
+}
+
+/// default min healthy voters
+#[must_use]
+#[inline]
+pub const fn default_min_healthy_voters() -> usize {
+    0
+}
+
+/// default leaderless read timeout
+#[must_use]
+#[inline]
+pub const fn default_leaderless_read_timeout() -> Duration {
+    Duration::from_secs(3)
+}
+
+/// default track last access
+#[must_use]
+#[inline]
+pub const fn default_track_last_access() -> bool {
+    false
+}
+
+/// default bulk load threshold, bulk loads are disabled
+#[must_use]
+#[inline]
+pub const fn default_bulk_load_threshold() -> usize {
+    0
+}
+
+/// default report apply latency
+#[must_use]
+#[inline]
+pub const fn default_report_apply_latency() -> bool {
+    false
+}
+
+/// default max request order wait, the ordering is disabled
+#[must_use]
+#[inline]
+pub const fn default_max_request_order_wait() -> Duration {
+    Duration::ZERO
+}
+
+/// How serializable reads are handled while a snapshot is being installed, an
+/// install waits for the in-flight reads and holds off the new ones, so that a read
+/// never observes a mix of the old and the new state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub enum SnapshotInstallReads {
+    /// Reject the reads with `Unavailable`, so that the clients retry on other
+    /// members
+    #[default]
+    Unavailable,
+    /// Wait for the install to finish and serve the reads from the installed
+    /// snapshot, the pre-install state is never served. The responses are marked by
+    /// the `stale-read` metadata since the node may still be catching up with the
+    /// log after the snapshot
+    WaitForInstall,
+}
+
+/// `SnapshotInstallReads` deserialization formatter
+pub mod snapshot_install_reads_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::SnapshotInstallReads;
+    use crate::parse_snapshot_install_reads;
+
+    /// deserializes a snapshot install reads mode
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<SnapshotInstallReads, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_snapshot_install_reads(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// How a range returning more keys than the max range result count is handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub enum RangeResultOverflow {
+    /// Return the first keys up to the max range result count with `more` set
+    #[default]
+    Truncate,
+    /// Reject the range, it should be retried with a limit
+    Reject,
+}
+
+/// `RangeResultOverflow` deserialization formatter
+pub mod range_result_overflow_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::RangeResultOverflow;
+    use crate::parse_range_result_overflow;
+
+    /// deserializes a range result overflow mode
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<RangeResultOverflow, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_range_result_overflow(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// How linearizable reads are handled while the cluster has no known leader, such
+/// as during an election
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub enum LeaderlessReads {
+    /// Wait for a leader to be elected within the leaderless read timeout, then
+    /// serve the reads, they fail with `Unavailable` if no leader is elected in time
+    #[default]
+    Wait,
+    /// Fail the reads with `Unavailable` immediately
+    Unavailable,
+}
+
+/// `LeaderlessReads` deserialization formatter
+pub mod leaderless_reads_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::LeaderlessReads;
+    use crate::parse_leaderless_reads;
+
+    /// deserializes a leaderless reads mode
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<LeaderlessReads, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_leaderless_reads(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// The encoding that the keys and values of requests must be valid in, the
+/// requests violating it are rejected before they are proposed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub enum KeyValueEncoding {
+    /// Keys and values are arbitrary bytes
+    #[default]
+    Raw,
+    /// Keys must be valid UTF-8, values are arbitrary bytes
+    Utf8Keys,
+    /// Both keys and values must be valid UTF-8
+    Utf8,
+}
+
+impl KeyValueEncoding {
+    /// Whether the keys must be valid UTF-8
+    #[must_use]
+    #[inline]
+    pub fn requires_utf8_keys(self) -> bool {
+        matches!(self, Self::Utf8Keys | Self::Utf8)
+    }
+
+    /// Whether the values must be valid UTF-8
+    #[must_use]
+    #[inline]
+    pub fn requires_utf8_values(self) -> bool {
+        matches!(self, Self::Utf8)
+    }
+}
+
+/// `KeyValueEncoding` deserialization formatter
+pub mod key_value_encoding_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::KeyValueEncoding;
+    use crate::parse_key_value_encoding;
+
+    /// deserializes a key value encoding
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<KeyValueEncoding, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_key_value_encoding(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// The characters that the keys under the key charset prefixes must consist of
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub enum KeyCharset {
+    /// Keys are arbitrary bytes
+    #[default]
+    Any,
+    /// Keys must not contain the ASCII control characters, other bytes are allowed
+    NoControl,
+    /// Keys must only contain the printable ASCII characters, from space to `~`
+    Printable,
+}
+
+impl KeyCharset {
+    /// Whether a byte is allowed in a key
+    #[must_use]
+    #[inline]
+    pub fn allows(self, byte: u8) -> bool {
+        match self {
+            Self::Any => true,
+            Self::NoControl => !byte.is_ascii_control(),
+            Self::Printable => byte.is_ascii_graphic() || byte == b' ',
+        }
+    }
+}
+
+/// `KeyCharset` deserialization formatter
+pub mod key_charset_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::KeyCharset;
+    use crate::parse_key_charset;
+
+    /// deserializes a key charset
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<KeyCharset, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_key_charset(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Auto Compactor Configuration
+#[allow(clippy::module_name_repetitions)]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(
+    tag = "mode",
+    content = "retention",
+    rename_all(deserialize = "lowercase")
+)]
+pub enum AutoCompactConfig {
+    /// auto periodic compactor
+    #[serde(with = "duration_format")]
+    Periodic(Duration),
+    /// auto revision compactor
+    Revision(i64),
+}
+
+/// Engine Configuration
+#[allow(clippy::module_name_repetitions)]
+#[non_exhaustive]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(
+    tag = "type",
+    content = "data_dir",
+    rename_all(deserialize = "lowercase")
+)]
+pub enum EngineConfig {
+    /// Memory Storage Engine
+    Memory,
+    /// RocksDB Storage Engine
+    RocksDB(PathBuf),
+}
+
+impl Default for EngineConfig {
+    #[inline]
+    fn default() -> Self {
+        Self::Memory
+    }
+}
+
+/// /// Storage Configuration
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+#[non_exhaustive]
+pub struct StorageConfig {
+    /// Engine Configuration
+    #[serde(default = "EngineConfig::default")]
+    pub engine: EngineConfig,
+    /// Quota
+    #[serde(default = "default_quota")]
+    pub quota: u64,
+    /// How the heavy maintenance operations of the storage, such as compactions,
+    /// snapshots and defragmentations, run alongside each other
+    #[serde(
+        with = "maintenance_policy_format",
+        default = "MaintenancePolicy::default"
+    )]
+    pub maintenance_policy: MaintenancePolicy,
+    /// The order in which the queued maintenance operations run under the serial
+    /// policy, the operations not listed run after the listed ones
+    #[serde(with = "maintenance_priority_format", default)]
+    pub maintenance_priority: Vec<MaintenanceOp>,
+    /// Whether the batches written to the storage are synced to the disk before they
+    /// are acknowledged. They are not by default, the writes are still replicated but
+    /// the latest ones may be lost from the storage of a node on a crash.
+    #[serde(default)]
+    pub sync_writes: bool,
+    /// The key prefixes whose writes are not durable if the writes are synced. A batch
+    /// of writes only to these prefixes is acknowledged without syncing it to the disk,
+    /// it is still replicated but may be lost from the storage of a node on a crash. It
+    /// trades the crash durability of the keys for the write latency, and has no effect
+    /// unless `sync_writes` is set.
+    #[serde(default)]
+    pub non_durable_prefixes: Vec<String>,
+    /// Automatic defragmentation of the members, `None` means disabled
+    #[serde(default)]
+    pub auto_defrag: Option<AutoDefragConfig>,
+    /// Encryption at rest of the key-value records, `None` means disabled
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+    /// Periodic check of the in-memory index against the backend, `None` means disabled
+    #[serde(default)]
+    pub index_check: Option<IndexCheckConfig>,
+}
+
+impl StorageConfig {
+    /// Create a new storage config
+    #[inline]
+    #[must_use]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        engine: EngineConfig,
+        quota: u64,
+        maintenance_policy: MaintenancePolicy,
+        maintenance_priority: Vec<MaintenanceOp>,
+        sync_writes: bool,
+        non_durable_prefixes: Vec<String>,
+        auto_defrag: Option<AutoDefragConfig>,
+        encryption: Option<EncryptionConfig>,
+        index_check: Option<IndexCheckConfig>,
+    ) -> Self {
+        Self {
+            engine,
+            quota,
+            maintenance_policy,
+            maintenance_priority,
+            sync_writes,
+            non_durable_prefixes,
+            auto_defrag,
+            encryption,
+            index_check,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            engine: EngineConfig::default(),
+            quota: default_quota(),
+            maintenance_policy: MaintenancePolicy::default(),
+            maintenance_priority: Vec::new(),
+            sync_writes: false,
+            non_durable_prefixes: Vec::new(),
+            auto_defrag: None,
+            encryption: None,
+            index_check: None,
+        }
+    }
+}
+
+/// Encryption at rest configuration.
+///
+/// The key-value records are encrypted with AES-256-GCM by a data key of their own,
+/// which is encrypted by the active key. A record remembers the id of the key it's
+/// encrypted with, so the key can be rotated by adding a new key and making it active,
+/// the old keys are kept to decrypt the records written before. All the members must
+/// have the same keys, since the snapshots are sent between them as they are stored.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Getters)]
+pub struct EncryptionConfig {
+    /// The id of the key the new records are encrypted with
+    #[getset(get = "pub")]
+    active_key: String,
+    /// The paths of the files of the keys by their ids, a key file holds 32 bytes
+    #[getset(get = "pub")]
+    key_files: HashMap<String, PathBuf>,
+}
+
+impl EncryptionConfig {
+    /// Create a new encryption config
+    #[must_use]
+    #[inline]
+    pub fn new(active_key: String, key_files: HashMap<String, PathBuf>) -> Self {
+        Self {
+            active_key,
+            key_files,
+        }
+    }
+}
+
+/// Automatic defragmentation configuration.
+///
+/// The leader defragments the members whose free space exceeds the threshold one at
+/// a time, and only starts within the maintenance window. The leader itself is never
+/// defragmented while leading, it transfers the leadership first and is defragmented
+/// by the new leader.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Getters)]
+pub struct AutoDefragConfig {
+    /// A member is defragmented when the percentage of the free space in its storage
+    /// file exceeds it
+    #[getset(get = "pub")]
+    #[serde(default = "default_defrag_free_space_percent")]
+    free_space_percent: u64,
+    /// The daily window in UTC the defragmentations start within, e.g. `02:00-04:00`
+    #[getset(get = "pub")]
+    #[serde(with = "maintenance_window_format", default)]
+    window: MaintenanceWindow,
+    /// How often the free space of the members is checked
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_defrag_check_interval")]
+    check_interval: Duration,
+}
+
+impl AutoDefragConfig {
+    /// Create a new auto defrag config
+    #[must_use]
+    #[inline]
+    pub fn new(
+        free_space_percent: u64,
+        window: MaintenanceWindow,
+        check_interval: Duration,
+    ) -> Self {
+        Self {
+            free_space_percent,
+            window,
+            check_interval,
+        }
+    }
+}
+
+impl Default for AutoDefragConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            free_space_percent: default_defrag_free_space_percent(),
+            window: MaintenanceWindow::default(),
+            check_interval: default_defrag_check_interval(),
+        }
+    }
+}
+
+/// Periodic check of the in-memory index against the backend.
+///
+/// Each interval a bounded sample of the keys spread over the index is checked, the
+/// latest version of a sampled key in the index must be the one stored at its revision
+/// in the backend. A mismatch is logged and raises a CORRUPT alarm of the member, it
+/// doesn't rebuild the index.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Getters)]
+pub struct IndexCheckConfig {
+    /// How often a sample of the keys is checked
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_index_check_interval")]
+    interval: Duration,
+    /// The maximum number of the keys checked each interval
+    #[getset(get = "pub")]
+    #[serde(default = "default_index_check_sample_size")]
+    sample_size: usize,
+}
+
+impl IndexCheckConfig {
+    /// Create a new index check config
+    #[must_use]
+    #[inline]
+    pub fn new(interval: Duration, sample_size: usize) -> Self {
+        Self {
+            interval,
+            sample_size,
         }
     }
 }
 
-impl Default for ServerTimeout {
+impl Default for IndexCheckConfig {
     #[inline]
     fn default() -> Self {
         Self {
-            range_retry_timeout: default_range_retry_timeout(),
-            compact_timeout: default_compact_timeout(),
-            sync_victims_interval: default_sync_victims_interval(),
-            watch_progress_notify_interval: default_watch_progress_notify_interval(),
+            interval: default_index_check_interval(),
+            sample_size: default_index_check_sample_size(),
         }
     }
 }
 
-/// Auto Compactor Configuration
-#[allow(clippy::module_name_repetitions)]
-#[non_exhaustive]
-#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
-#[serde(
-    tag = "mode",
-    content = "retention",
-    rename_all(deserialize = "lowercase")
-)]
-pub enum AutoCompactConfig {
-    /// auto periodic compactor
-    #[serde(with = "duration_format")]
-    Periodic(Duration),
-    /// auto revision compactor
-    Revision(i64),
+/// default index check interval
+#[must_use]
+#[inline]
+pub const fn default_index_check_interval() -> Duration {
+    Duration::from_secs(600)
 }
 
-/// Engine Configuration
-#[allow(clippy::module_name_repetitions)]
-#[non_exhaustive]
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
-#[serde(
-    tag = "type",
-    content = "data_dir",
-    rename_all(deserialize = "lowercase")
-)]
-pub enum EngineConfig {
-    /// Memory Storage Engine
-    Memory,
-    /// RocksDB Storage Engine
-    RocksDB(PathBuf),
+/// default index check sample size
+#[must_use]
+#[inline]
+pub const fn default_index_check_sample_size() -> usize {
+    100
 }
 
-impl Default for EngineConfig {
+/// default defrag free space percent
+#[must_use]
+#[inline]
+pub const fn default_defrag_free_space_percent() -> u64 {
+    50
+}
+
+/// default defrag check interval
+#[must_use]
+#[inline]
+pub const fn default_defrag_check_interval() -> Duration {
+    Duration::from_secs(600)
+}
+
+/// A daily time window in UTC, a window ending before it starts spans midnight and
+/// a window ending when it starts spans the whole day
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    /// The minute of the day the window starts at
+    start: u32,
+    /// The minute of the day the window ends at, exclusive
+    end: u32,
+}
+
+impl MaintenanceWindow {
+    /// Create a new maintenance window from the minutes of the day
+    #[must_use]
     #[inline]
-    fn default() -> Self {
-        Self::Memory
+    pub fn new(start: u32, end: u32) -> Self {
+        Self { start, end }
+    }
+
+    /// Check whether a minute of the day is within the window
+    #[must_use]
+    #[inline]
+    pub fn contains(&self, minute_of_day: u32) -> bool {
+        match self.start.cmp(&self.end) {
+            Ordering::Equal => true,
+            Ordering::Less => (self.start..self.end).contains(&minute_of_day),
+            Ordering::Greater => minute_of_day >= self.start || minute_of_day < self.end,
+        }
     }
 }
 
-/// /// Storage Configuration
-#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
-#[allow(clippy::module_name_repetitions)]
+/// `MaintenanceWindow` deserialization formatter
+pub mod maintenance_window_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::MaintenanceWindow;
+    use crate::parse_maintenance_window;
+
+    /// deserializes a maintenance window
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<MaintenanceWindow, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_maintenance_window(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// How the heavy maintenance operations of the storage run alongside each other
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
 #[non_exhaustive]
-pub struct StorageConfig {
-    /// Engine Configuration
-    #[serde(default = "EngineConfig::default")]
-    pub engine: EngineConfig,
-    /// Quota
-    #[serde(default = "default_quota")]
-    pub quota: u64,
+pub enum MaintenancePolicy {
+    /// The operations run as soon as they are triggered
+    #[default]
+    Concurrent,
+    /// At most one operation runs at a time on a node, the others are queued in the
+    /// maintenance priority, this bounds the disk contention
+    Serial,
 }
 
-impl StorageConfig {
-    /// Create a new storage config
-    #[inline]
-    #[must_use]
-    pub fn new(engine: EngineConfig, quota: u64) -> Self {
-        Self { engine, quota }
+/// `MaintenancePolicy` deserialization formatter
+pub mod maintenance_policy_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::MaintenancePolicy;
+    use crate::parse_maintenance_policy;
+
+    /// deserializes a maintenance policy
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<MaintenancePolicy, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_maintenance_policy(&s).map_err(serde::de::Error::custom)
     }
 }
 
-impl Default for StorageConfig {
-    #[inline]
-    fn default() -> Self {
-        Self {
-            engine: EngineConfig::default(),
-            quota: default_quota(),
-        }
+/// A heavy maintenance operation of the storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[non_exhaustive]
+pub enum MaintenanceOp {
+    /// Compaction of the kv history
+    Compaction,
+    /// Snapshot of the storage
+    Snapshot,
+    /// Defragmentation of the storage
+    Defrag,
+}
+
+/// Maintenance priority deserialization formatter
+pub mod maintenance_priority_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::MaintenanceOp;
+    use crate::parse_maintenance_op;
+
+    /// deserializes a maintenance priority
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Vec<MaintenanceOp>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|s| parse_maintenance_op(s).map_err(serde::de::Error::custom))
+            .collect()
     }
 }
 
@@ -759,6 +2133,28 @@ pub struct LogConfig {
     #[getset(get = "pub")]
     #[serde(with = "level_format", default = "default_log_level")]
     level: LevelConfig,
+    /// Replace the values of the kv store by their sizes and hashes in the error
+    /// messages, logs and traces
+    #[getset(get = "pub")]
+    #[serde(default)]
+    redact_values: bool,
+    /// The keys under these prefixes are redacted like the values, except the prefixes
+    #[getset(get = "pub")]
+    #[serde(default)]
+    redacted_key_prefixes: Vec<String>,
+    /// The sampling of the successful requests in the request log
+    #[getset(get = "pub")]
+    #[serde(with = "request_log_sampling_format", default)]
+    request_sampling: RequestLogSampling,
+    /// Log every failed request regardless of the sampling
+    #[getset(get = "pub")]
+    #[serde(default)]
+    request_errors: bool,
+    /// Log every request taking longer than it regardless of the sampling, 0 means
+    /// the slow requests are sampled like the others
+    #[getset(get = "pub")]
+    #[serde(with = "duration_format", default = "default_slow_request_threshold")]
+    slow_request_threshold: Duration,
 }
 
 impl Default for LogConfig {
@@ -768,10 +2164,64 @@ impl Default for LogConfig {
             path: None,
             rotation: default_rotation(),
             level: default_log_level(),
+            redact_values: false,
+            redacted_key_prefixes: Vec::new(),
+            request_sampling: RequestLogSampling::default(),
+            request_errors: false,
+            slow_request_threshold: default_slow_request_threshold(),
+        }
+    }
+}
+
+/// default slow request threshold
+#[must_use]
+#[inline]
+pub const fn default_slow_request_threshold() -> Duration {
+    Duration::ZERO
+}
+
+/// The sampling of the successful requests in the request log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RequestLogSampling {
+    /// No successful request is logged
+    #[default]
+    Off,
+    /// One in every `n` requests is logged, e.g. `1/100`
+    OneIn(u64),
+    /// At most `n` requests are logged in every second, e.g. `10/s`
+    PerSecond(u64),
+}
+
+impl std::fmt::Display for RequestLogSampling {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            RequestLogSampling::Off => write!(f, "off"),
+            RequestLogSampling::OneIn(n) => write!(f, "1/{n}"),
+            RequestLogSampling::PerSecond(n) => write!(f, "{n}/s"),
         }
     }
 }
 
+/// `RequestLogSampling` deserialization formatter
+pub mod request_log_sampling_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::RequestLogSampling;
+    use crate::parse_request_log_sampling;
+
+    /// deserializes a request log sampling
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<RequestLogSampling, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_request_log_sampling(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 /// `LevelConfig` deserialization formatter
 pub mod level_format {
     use serde::{Deserialize, Deserializer};
@@ -801,11 +2251,26 @@ impl LogConfig {
     /// Generate a new `LogConfig` object
     #[must_use]
     #[inline]
-    pub fn new(path: Option<PathBuf>, rotation: RotationConfig, level: LevelConfig) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        path: Option<PathBuf>,
+        rotation: RotationConfig,
+        level: LevelConfig,
+        redact_values: bool,
+        redacted_key_prefixes: Vec<String>,
+        request_sampling: RequestLogSampling,
+        request_errors: bool,
+        slow_request_threshold: Duration,
+    ) -> Self {
         Self {
             path,
             rotation,
             level,
+            redact_values,
+            redacted_key_prefixes,
+            request_sampling,
+            request_errors,
+            slow_request_threshold,
         }
     }
 }
@@ -936,7 +2401,7 @@ impl TraceConfig {
 
 /// Xline tracing configuration object
 #[allow(clippy::module_name_repetitions)]
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Getters, Default)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Getters)]
 pub struct AuthConfig {
     /// The public key file
     #[getset(get = "pub")]
@@ -944,18 +2409,132 @@ pub struct AuthConfig {
     /// The private key file
     #[getset(get = "pub")]
     auth_private_key: Option<PathBuf>,
+    /// The maximum number of validated tokens cached, 0 disables the cache
+    #[getset(get = "pub")]
+    #[serde(default = "default_token_cache_size")]
+    token_cache_size: usize,
+    /// The quotas of roles, keyed by the role name
+    #[getset(get = "pub")]
+    #[serde(default)]
+    role_quotas: HashMap<String, RoleQuota>,
+    /// The pbkdf2 rounds of the password hashes made by the server
+    #[getset(get = "pub")]
+    #[serde(default = "default_password_hash_rounds")]
+    password_hash_rounds: u32,
+    /// Whether the stored password hash of a user is re-hashed at the configured rounds
+    /// on a successful login if it is hashed at other rounds, so that the stored hashes
+    /// are upgraded over time. Each re-hash is a password change, which invalidates
+    /// the tokens issued before it.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    rehash_passwords_on_login: bool,
+    /// The maximum number of roles granted to a user, 0 means unlimited
+    #[getset(get = "pub")]
+    #[serde(default)]
+    max_roles_per_user: usize,
+    /// The maximum number of permissions granted to a role, 0 means unlimited
+    #[getset(get = "pub")]
+    #[serde(default)]
+    max_permissions_per_role: usize,
 }
 
 impl AuthConfig {
     /// Generate a new `AuthConfig` object
     #[must_use]
     #[inline]
-    pub fn new(auth_public_key: Option<PathBuf>, auth_private_key: Option<PathBuf>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        auth_public_key: Option<PathBuf>,
+        auth_private_key: Option<PathBuf>,
+        token_cache_size: usize,
+        role_quotas: HashMap<String, RoleQuota>,
+        password_hash_rounds: u32,
+        rehash_passwords_on_login: bool,
+        max_roles_per_user: usize,
+        max_permissions_per_role: usize,
+    ) -> Self {
         Self {
             auth_public_key,
             auth_private_key,
+            token_cache_size,
+            role_quotas,
+            password_hash_rounds,
+            rehash_passwords_on_login,
+            max_roles_per_user,
+            max_permissions_per_role,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            auth_public_key: None,
+            auth_private_key: None,
+            token_cache_size: default_token_cache_size(),
+            role_quotas: HashMap::new(),
+            password_hash_rounds: default_password_hash_rounds(),
+            rehash_passwords_on_login: false,
+            max_roles_per_user: 0,
+            max_permissions_per_role: 0,
+        }
+    }
+}
+
+/// default token cache size
+#[must_use]
+#[inline]
+pub const fn default_token_cache_size() -> usize {
+    1024
+}
+
+/// default pbkdf2 rounds of the password hashes
+#[must_use]
+#[inline]
+pub const fn default_password_hash_rounds() -> u32 {
+    200_000
+}
+
+/// The quota of a role, a limit of 0 means unlimited.
+///
+/// The keys and bytes are counted over the keys that the role is permitted to
+/// write, so the keys shared by several roles count against each of them.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Getters)]
+pub struct RoleQuota {
+    /// The maximum number of keys
+    #[getset(get = "pub")]
+    #[serde(default)]
+    max_keys: u64,
+    /// The maximum bytes of keys and values
+    #[getset(get = "pub")]
+    #[serde(default)]
+    max_bytes: u64,
+    /// The maximum number of write requests per second, it is limited by every
+    /// serving node separately
+    #[getset(get = "pub")]
+    #[serde(default)]
+    max_write_rate: u64,
+}
+
+impl RoleQuota {
+    /// Generate a new `RoleQuota` object
+    #[must_use]
+    #[inline]
+    pub fn new(max_keys: u64, max_bytes: u64, max_write_rate: u64) -> Self {
+        Self {
+            max_keys,
+            max_bytes,
+            max_write_rate,
         }
     }
+
+    /// Whether the quota limits nothing
+    #[must_use]
+    #[inline]
+    pub fn is_unlimited(&self) -> bool {
+        self.max_keys == 0 && self.max_bytes == 0 && self.max_write_rate == 0
+    }
 }
 
 /// Xline tls configuration object
@@ -981,12 +2560,23 @@ pub struct TlsConfig {
     /// The private key file used by client
     #[getset(get = "pub")]
     pub client_key_path: Option<PathBuf>,
+    /// The minimum tls version accepted by the client and peer listeners
+    #[getset(get = "pub")]
+    #[serde(with = "tls_version_format", default = "TlsVersion::default")]
+    pub min_version: TlsVersion,
+    /// The cipher suites accepted by the client and peer listeners by their IANA
+    /// names, e.g. `TLS13_AES_256_GCM_SHA384`, empty means the default ones. The
+    /// unknown or weak suites are rejected at startup.
+    #[getset(get = "pub")]
+    #[serde(default)]
+    pub cipher_suites: Vec<String>,
 }
 
 impl TlsConfig {
     /// Create a new `TlsConfig` object
     #[must_use]
     #[inline]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         peer_ca_cert_path: Option<PathBuf>,
         peer_cert_path: Option<PathBuf>,
@@ -994,6 +2584,8 @@ impl TlsConfig {
         client_ca_cert_path: Option<PathBuf>,
         client_cert_path: Option<PathBuf>,
         client_key_path: Option<PathBuf>,
+        min_version: TlsVersion,
+        cipher_suites: Vec<String>,
     ) -> Self {
         Self {
             peer_ca_cert_path,
@@ -1002,6 +2594,8 @@ impl TlsConfig {
             client_ca_cert_path,
             client_cert_path,
             client_key_path,
+            min_version,
+            cipher_suites,
         }
     }
 
@@ -1011,6 +2605,42 @@ impl TlsConfig {
     pub fn server_tls_enabled(&self) -> bool {
         self.peer_cert_path.is_some() && self.peer_key_path.is_some()
     }
+
+    /// Whether the handshakes of the listeners are restricted beyond the defaults
+    #[must_use]
+    #[inline]
+    pub fn restricts_handshakes(&self) -> bool {
+        self.min_version != TlsVersion::default() || !self.cipher_suites.is_empty()
+    }
+}
+
+/// The tls protocol versions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[non_exhaustive]
+pub enum TlsVersion {
+    /// TLS 1.2
+    #[default]
+    Tls12,
+    /// TLS 1.3
+    Tls13,
+}
+
+/// `TlsVersion` deserialization formatter
+pub mod tls_version_format {
+    use serde::{Deserialize, Deserializer};
+
+    use super::TlsVersion;
+    use crate::parse_tls_version;
+
+    /// deserializes a tls version
+    #[allow(single_use_lifetimes)]
+    pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<TlsVersion, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse_tls_version(&s).map_err(serde::de::Error::custom)
+    }
 }
 
 /// Xline metrics push protocol
@@ -1176,6 +2806,8 @@ impl XlineServerConfig {
         compact: CompactConfig,
         tls: TlsConfig,
         metrics: MetricsConfig,
+        watch: WatchConfig,
+        kv: KvConfig,
     ) -> Self {
         Self {
             cluster,
@@ -1186,6 +2818,8 @@ impl XlineServerConfig {
             compact,
             tls,
             metrics,
+            watch,
+            kv,
         }
     }
 }
@@ -1206,12 +2840,27 @@ mod tests {
             peer_advertise_urls = ['127.0.0.1:2380']
             client_listen_urls = ['127.0.0.1:2379']
             client_advertise_urls = ['127.0.0.1:2379']
+            read_listen_urls = ['127.0.0.1:2377']
+            write_listen_urls = ['127.0.0.1:2376']
 
             [cluster.server_timeout]
             range_retry_timeout = '3s'
             compact_timeout = '5s'
             sync_victims_interval = '20ms'
             watch_progress_notify_interval = '1s'
+            lease_grace_period = '500ms'
+            apply_stall_threshold = '30s'
+            lease_keep_alive_send_timeout = '10s'
+            read_index_batch_window = '1ms'
+            catch_up_read_timeout = '200ms'
+
+            [cluster.message_size]
+            client_max_send = 1048576
+            peer_max_recv = 67108864
+
+            [cluster.tags]
+            region = 'us-east-1'
+            zone = 'a'
 
             [cluster.peers]
             node1 = ['127.0.0.1:2378', '127.0.0.1:2379']
@@ -1223,6 +2872,10 @@ mod tests {
             wait_synced_timeout = '100ms'
             rpc_timeout = '100ms'
             retry_timeout = '100ms'
+            ordered_execution = true
+            leader_flap_threshold = 5
+            leader_flap_window = '30s'
+            leader_flap_backoff = true
 
             [cluster.client_config]
             initial_retry_timeout = '5s'
@@ -1230,10 +2883,32 @@ mod tests {
 
             [storage]
             engine = { type = 'memory'}
+            maintenance_policy = 'serial'
+            maintenance_priority = ['snapshot', 'compaction']
+            sync_writes = true
+            non_durable_prefixes = ['session/']
+
+            [storage.auto_defrag]
+            free_space_percent = 30
+            window = '02:00-04:00'
+            check_interval = '1m'
+
+            [storage.encryption]
+            active_key = 'k2'
+            key_files = { k1 = '/etc/xline/k1.key', k2 = '/etc/xline/k2.key' }
+
+            [storage.index_check]
+            interval = '5m'
+            sample_size = 50
 
             [compact]
             compact_batch_size = 123
             compact_sleep_interval = '5ms'
+            auth_history_retention = 1000
+            protected_prefixes = ['legal/']
+            protected_retention = 100
+            watch_safety_floor = true
+            watch_safety_margin = 50
 
             [compact.auto_compact_config]
             mode = 'periodic'
@@ -1243,6 +2918,11 @@ mod tests {
             path = '/var/log/xline'
             rotation = 'daily'
             level = 'info'
+            redact_values = true
+            redacted_key_prefixes = ['secrets/']
+            request_sampling = '1/100'
+            request_errors = true
+            slow_request_threshold = '500ms'
 
             [trace]
             jaeger_online = false
@@ -1253,11 +2933,22 @@ mod tests {
             [auth]
             auth_public_key = './public_key.pem'
             auth_private_key = './private_key.pem'
+            token_cache_size = 64
+            password_hash_rounds = 600000
+            rehash_passwords_on_login = true
+            max_roles_per_user = 8
+            max_permissions_per_role = 32
+
+            [auth.role_quotas.tenant]
+            max_keys = 100
+            max_write_rate = 10
 
             [tls]
             peer_cert_path = './cert.pem'
             peer_key_path = './key.pem'
             client_ca_cert_path = './ca.pem'
+            min_version = '1.3'
+            cipher_suites = ['TLS13_AES_256_GCM_SHA384']
 
             [metrics]
             enable = true
@@ -1266,6 +2957,40 @@ mod tests {
             push = true
             push_endpoint = 'http://some-endpoint.com:4396'
             push_protocol = 'http'
+
+            [watch]
+            linearizable_watch_create = true
+            history_replay = 'reject'
+            oversized_event = 'truncate'
+            min_buffer_depth = 8
+            max_buffer_depth = 4096
+
+            [kv]
+            noop_identical_put = true
+            reject_empty_value_put = true
+            dedup_value_threshold = 4096
+            range_memory_budget = 1048576
+            forward_writes_to_leader = true
+            max_write_coalescing_window = '10ms'
+            snapshot_install_reads = 'wait-for-install'
+            key_value_encoding = 'utf8-keys'
+            min_healthy_voters = 3
+            leaderless_reads = 'unavailable'
+            leaderless_read_timeout = '1s'
+            tracked_prefixes = ['app/', 'jobs/']
+            guarded_prefixes = ['config/']
+            lease_guarded_prefixes = ['services/']
+            track_last_access = true
+            bulk_load_threshold = 1000
+            report_apply_latency = true
+            max_request_order_wait = '2s'
+            max_range_result_count = 10000
+            range_result_overflow = 'reject'
+            key_charset = 'printable'
+            key_charset_prefixes = ['names/']
+            max_inflight_proposals = 256
+            immutable_prefixes = ['audit/']
+            range_stream_batch_size = 500
             "#,
         )
         .unwrap();
@@ -1274,6 +2999,10 @@ mod tests {
             .heartbeat_interval(Duration::from_millis(200))
             .wait_synced_timeout(Duration::from_millis(100))
             .rpc_timeout(Duration::from_millis(100))
+            .ordered_execution(true)
+            .leader_flap_threshold(5)
+            .leader_flap_window(Duration::from_secs(30))
+            .leader_flap_backoff(true)
             .build()
             .unwrap();
 
@@ -1292,6 +3021,11 @@ mod tests {
             Duration::from_secs(5),
             Duration::from_millis(20),
             Duration::from_secs(1),
+            Duration::from_millis(500),
+            Duration::from_secs(30),
+            Duration::from_secs(10),
+            Duration::from_millis(1),
+            Duration::from_millis(200),
         );
 
         assert_eq!(
@@ -1314,13 +3048,45 @@ mod tests {
                 curp_config,
                 client_config,
                 server_timeout,
-                InitialClusterState::New
+                InitialClusterState::New,
+                MessageSizeConfig::new(
+                    1_048_576,
+                    default_max_recv_message_size(),
+                    default_max_send_message_size(),
+                    67_108_864
+                ),
+                HashMap::from([
+                    ("region".to_owned(), "us-east-1".to_owned()),
+                    ("zone".to_owned(), "a".to_owned()),
+                ]),
+                vec!["127.0.0.1:2377".to_owned()],
+                vec!["127.0.0.1:2376".to_owned()],
             )
         );
 
         assert_eq!(
             config.storage,
-            StorageConfig::new(EngineConfig::Memory, default_quota())
+            StorageConfig::new(
+                EngineConfig::Memory,
+                default_quota(),
+                MaintenancePolicy::Serial,
+                vec![MaintenanceOp::Snapshot, MaintenanceOp::Compaction],
+                true,
+                vec!["session/".to_owned()],
+                Some(AutoDefragConfig::new(
+                    30,
+                    MaintenanceWindow::new(120, 240),
+                    Duration::from_secs(60)
+                )),
+                Some(EncryptionConfig::new(
+                    "k2".to_owned(),
+                    HashMap::from([
+                        ("k1".to_owned(), PathBuf::from("/etc/xline/k1.key")),
+                        ("k2".to_owned(), PathBuf::from("/etc/xline/k2.key")),
+                    ])
+                )),
+                Some(IndexCheckConfig::new(Duration::from_secs(300), 50))
+            )
         );
 
         assert_eq!(
@@ -1328,7 +3094,12 @@ mod tests {
             LogConfig::new(
                 Some(PathBuf::from("/var/log/xline")),
                 RotationConfig::Daily,
-                LevelConfig::INFO
+                LevelConfig::INFO,
+                true,
+                vec!["secrets/".to_owned()],
+                RequestLogSampling::OneIn(100),
+                true,
+                Duration::from_millis(500)
             )
         );
         assert_eq!(
@@ -1348,7 +3119,13 @@ mod tests {
                 compact_sleep_interval: Duration::from_millis(5),
                 auto_compact_config: Some(AutoCompactConfig::Periodic(Duration::from_secs(
                     10 * 60 * 60
-                )))
+                ))),
+                auth_history_retention: 1000,
+                lease_history_retention: default_history_retention(),
+                protected_prefixes: vec!["legal/".to_owned()],
+                protected_retention: 100,
+                watch_safety_floor: true,
+                watch_safety_margin: 50,
             }
         );
 
@@ -1357,6 +3134,12 @@ mod tests {
             AuthConfig {
                 auth_private_key: Some(PathBuf::from("./private_key.pem")),
                 auth_public_key: Some(PathBuf::from("./public_key.pem")),
+                token_cache_size: 64,
+                role_quotas: HashMap::from([("tenant".to_owned(), RoleQuota::new(100, 0, 10))]),
+                password_hash_rounds: 600_000,
+                rehash_passwords_on_login: true,
+                max_roles_per_user: 8,
+                max_permissions_per_role: 32,
             }
         );
 
@@ -1366,6 +3149,8 @@ mod tests {
                 peer_cert_path: Some(PathBuf::from("./cert.pem")),
                 peer_key_path: Some(PathBuf::from("./key.pem")),
                 client_ca_cert_path: Some(PathBuf::from("./ca.pem")),
+                min_version: TlsVersion::Tls13,
+                cipher_suites: vec!["TLS13_AES_256_GCM_SHA384".to_owned()],
                 ..Default::default()
             }
         );
@@ -1381,6 +3166,47 @@ mod tests {
                 push_protocol: MetricsPushProtocol::HTTP,
             },
         );
+
+        assert_eq!(
+            config.watch,
+            WatchConfig::new(
+                true,
+                WatchHistoryReplay::Reject,
+                OversizedWatchEvent::Truncate,
+                8,
+                4096
+            )
+        );
+        assert_eq!(
+            config.kv,
+            KvConfig::new(
+                true,
+                true,
+                4096,
+                1_048_576,
+                true,
+                Duration::from_millis(10),
+                SnapshotInstallReads::WaitForInstall,
+                KeyValueEncoding::Utf8Keys,
+                3,
+                LeaderlessReads::Unavailable,
+                Duration::from_secs(1),
+                vec!["app/".to_owned(), "jobs/".to_owned()],
+                vec!["config/".to_owned()],
+                vec!["services/".to_owned()],
+                true,
+                1000,
+                true,
+                Duration::from_secs(2),
+                10000,
+                RangeResultOverflow::Reject,
+                KeyCharset::Printable,
+                vec!["names/".to_owned()],
+                256,
+                vec!["audit/".to_owned()],
+                500,
+            )
+        );
     }
 
     #[test]
@@ -1439,7 +3265,11 @@ mod tests {
                 CurpConfigBuilder::default().build().unwrap(),
                 ClientConfig::default(),
                 ServerTimeout::default(),
-                InitialClusterState::default()
+                InitialClusterState::default(),
+                MessageSizeConfig::default(),
+                HashMap::new(),
+                Vec::new(),
+                Vec::new(),
             )
         );
 
@@ -1454,7 +3284,12 @@ mod tests {
             LogConfig::new(
                 Some(PathBuf::from("/var/log/xline")),
                 RotationConfig::Never,
-                LevelConfig::INFO
+                LevelConfig::INFO,
+                false,
+                Vec::new(),
+                RequestLogSampling::Off,
+                false,
+                Duration::ZERO
             )
         );
         assert_eq!(
@@ -1470,6 +3305,8 @@ mod tests {
         assert_eq!(config.auth, AuthConfig::default());
         assert_eq!(config.tls, TlsConfig::default());
         assert_eq!(config.metrics, MetricsConfig::default());
+        assert_eq!(config.watch, WatchConfig::default());
+        assert_eq!(config.kv, KvConfig::default());
     }
 
     #[test]
@@ -195,6 +195,10 @@ pub mod metrics;
 pub mod parking_lot_lock;
 /// utils for parse config
 pub mod parser;
+/// Redaction of keys and values in the outputs
+pub mod redaction;
+/// Sampling of the request log
+pub mod request_log;
 /// utils of `std` lock
 #[cfg(feature = "std")]
 pub mod std_lock;
@@ -211,7 +215,7 @@ pub mod tracing;
 use ::tracing::debug;
 pub use parser::*;
 use pbkdf2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString},
     Params, Pbkdf2,
 };
 
@@ -290,14 +294,35 @@ pub fn build_endpoint(
 /// return `Error` when hash password failed
 #[inline]
 pub fn hash_password(password: &[u8]) -> Result<String, pbkdf2::password_hash::errors::Error> {
+    // The recommended rounds is 600,000 or more
+    // [OWASP cheat sheet]: https://cheatsheetseries.owasp.org/cheatsheets/Password_Storage_Cheat_Sheet.html
+    hash_password_with_rounds(password, config::default_password_hash_rounds())
+}
+
+/// Hash password with the given pbkdf2 rounds
+///
+/// # Errors
+///
+/// return `Error` when hash password failed
+#[inline]
+pub fn hash_password_with_rounds(
+    password: &[u8],
+    rounds: u32,
+) -> Result<String, pbkdf2::password_hash::errors::Error> {
     let salt = SaltString::generate(&mut OsRng);
     let simple_para = Params {
-        // The recommended rounds is 600,000 or more
-        // [OWASP cheat sheet]: https://cheatsheetseries.owasp.org/cheatsheets/Password_Storage_Cheat_Sheet.html
-        rounds: 200_000,
+        rounds,
         output_length: 32,
     };
     let hashed_password =
         Pbkdf2.hash_password_customized(password, None, None, simple_para, &salt)?;
     Ok(hashed_password.to_string())
 }
+
+/// Get the pbkdf2 rounds of a password hash, `None` if it is not a valid pbkdf2 hash
+#[inline]
+#[must_use]
+pub fn password_hash_rounds(hashed_password: &str) -> Option<u32> {
+    let hash = PasswordHash::new(hashed_password).ok()?;
+    Params::try_from(&hash).ok().map(|params| params.rounds)
+}
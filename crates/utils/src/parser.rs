@@ -5,7 +5,10 @@ use regex::Regex;
 use thiserror::Error;
 
 use crate::config::{
-    ClusterRange, InitialClusterState, LevelConfig, MetricsPushProtocol, RotationConfig,
+    ClusterRange, InitialClusterState, KeyCharset, KeyValueEncoding, LeaderlessReads, LevelConfig,
+    MaintenanceOp, MaintenancePolicy, MaintenanceWindow, MetricsPushProtocol, OversizedWatchEvent,
+    RangeResultOverflow, RequestLogSampling, RoleQuota, RotationConfig, SnapshotInstallReads,
+    TlsVersion, WatchHistoryReplay,
 };
 
 /// seconds per minute
@@ -163,6 +166,286 @@ pub fn parse_state(s: &str) -> Result<InitialClusterState, ConfigParseError> {
     }
 }
 
+/// Parse `WatchHistoryReplay` from string
+/// # Errors
+/// Return error when parsing the given string to `WatchHistoryReplay` failed
+#[inline]
+pub fn parse_watch_history_replay(s: &str) -> Result<WatchHistoryReplay, ConfigParseError> {
+    match s {
+        "allow" => Ok(WatchHistoryReplay::Allow),
+        "reject" => Ok(WatchHistoryReplay::Reject),
+        "current" => Ok(WatchHistoryReplay::Current),
+        _ => Err(ConfigParseError::InvalidValue(format!(
+            "the watch history replay should be one of 'allow', 'reject' or 'current' ({s})"
+        ))),
+    }
+}
+
+/// Parse `OversizedWatchEvent` from string
+/// # Errors
+/// Return error when parsing the given string to `OversizedWatchEvent` failed
+#[inline]
+pub fn parse_oversized_watch_event(s: &str) -> Result<OversizedWatchEvent, ConfigParseError> {
+    match s {
+        "fragment" => Ok(OversizedWatchEvent::Fragment),
+        "truncate" => Ok(OversizedWatchEvent::Truncate),
+        _ => Err(ConfigParseError::InvalidValue(format!(
+            "the oversized watch event should be one of 'fragment' or 'truncate' ({s})"
+        ))),
+    }
+}
+
+/// Parse `LeaderlessReads` from string
+/// # Errors
+/// Return error when parsing the given string to `LeaderlessReads` failed
+#[inline]
+pub fn parse_leaderless_reads(s: &str) -> Result<LeaderlessReads, ConfigParseError> {
+    match s {
+        "wait" => Ok(LeaderlessReads::Wait),
+        "unavailable" => Ok(LeaderlessReads::Unavailable),
+        _ => Err(ConfigParseError::InvalidValue(format!(
+            "the leaderless reads should be one of 'wait' or 'unavailable' ({s})"
+        ))),
+    }
+}
+
+/// Parse `RangeResultOverflow` from string
+/// # Errors
+/// Return error when parsing the given string to `RangeResultOverflow` failed
+#[inline]
+pub fn parse_range_result_overflow(s: &str) -> Result<RangeResultOverflow, ConfigParseError> {
+    match s {
+        "truncate" => Ok(RangeResultOverflow::Truncate),
+        "reject" => Ok(RangeResultOverflow::Reject),
+        _ => Err(ConfigParseError::InvalidValue(format!(
+            "the range result overflow should be one of 'truncate' or 'reject' ({s})"
+        ))),
+    }
+}
+
+/// Parse `RequestLogSampling` from string, e.g. `off`, `1/100` or `10/s`
+/// # Errors
+/// Return error when parsing the given string to `RequestLogSampling` failed
+#[inline]
+pub fn parse_request_log_sampling(s: &str) -> Result<RequestLogSampling, ConfigParseError> {
+    let invalid = || {
+        ConfigParseError::InvalidValue(format!(
+            "the request log sampling should be like 'off', '1/N' or 'N/s' ({s})"
+        ))
+    };
+    if s == "off" {
+        return Ok(RequestLogSampling::Off);
+    }
+    let (left, right) = s.split_once('/').ok_or_else(invalid)?;
+    match (left.trim(), right.trim()) {
+        ("1", n) => match n.parse()? {
+            0 => Err(invalid()),
+            n => Ok(RequestLogSampling::OneIn(n)),
+        },
+        (n, "s") => Ok(RequestLogSampling::PerSecond(n.parse()?)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse `MaintenancePolicy` from string
+/// # Errors
+/// Return error when parsing the given string to `MaintenancePolicy` failed
+#[inline]
+pub fn parse_maintenance_policy(s: &str) -> Result<MaintenancePolicy, ConfigParseError> {
+    match s {
+        "concurrent" => Ok(MaintenancePolicy::Concurrent),
+        "serial" => Ok(MaintenancePolicy::Serial),
+        _ => Err(ConfigParseError::InvalidValue(format!(
+            "the maintenance policy should be one of 'concurrent' or 'serial' ({s})"
+        ))),
+    }
+}
+
+/// Parse `MaintenanceOp` from string
+/// # Errors
+/// Return error when parsing the given string to `MaintenanceOp` failed
+#[inline]
+pub fn parse_maintenance_op(s: &str) -> Result<MaintenanceOp, ConfigParseError> {
+    match s {
+        "compaction" => Ok(MaintenanceOp::Compaction),
+        "snapshot" => Ok(MaintenanceOp::Snapshot),
+        "defrag" => Ok(MaintenanceOp::Defrag),
+        _ => Err(ConfigParseError::InvalidValue(format!(
+            "the maintenance operation should be one of 'compaction', 'snapshot' or 'defrag' ({s})"
+        ))),
+    }
+}
+
+/// Parse `MaintenanceWindow` from string, e.g. `02:00-04:30` in UTC
+/// # Errors
+/// Return error when parsing the given string to `MaintenanceWindow` failed
+#[inline]
+pub fn parse_maintenance_window(s: &str) -> Result<MaintenanceWindow, ConfigParseError> {
+    let invalid = || {
+        ConfigParseError::InvalidValue(format!(
+            "the maintenance window should be like 'HH:MM-HH:MM' ({s})"
+        ))
+    };
+    let minute_of_day = |time: &str| -> Result<u32, ConfigParseError> {
+        let (hour, minute) = time.trim().split_once(':').ok_or_else(invalid)?;
+        let (hour, minute): (u32, u32) = (hour.parse()?, minute.parse()?);
+        if hour >= 24 || minute >= 60 {
+            return Err(invalid());
+        }
+        Ok(hour.overflow_mul(60).overflow_add(minute))
+    };
+    let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+    Ok(MaintenanceWindow::new(
+        minute_of_day(start)?,
+        minute_of_day(end)?,
+    ))
+}
+
+/// Parse `SnapshotInstallReads` from string
+/// # Errors
+/// Return error when parsing the given string to `SnapshotInstallReads` failed
+#[inline]
+pub fn parse_snapshot_install_reads(s: &str) -> Result<SnapshotInstallReads, ConfigParseError> {
+    match s {
+        "unavailable" => Ok(SnapshotInstallReads::Unavailable),
+        "wait-for-install" => Ok(SnapshotInstallReads::WaitForInstall),
+        _ => Err(ConfigParseError::InvalidValue(format!(
+            "the snapshot install reads should be one of 'unavailable' or 'wait-for-install' \
+             ({s})"
+        ))),
+    }
+}
+
+/// Parse `TlsVersion` from string
+/// # Errors
+/// Return error when parsing the given string to `TlsVersion` failed
+#[inline]
+pub fn parse_tls_version(s: &str) -> Result<TlsVersion, ConfigParseError> {
+    match s {
+        "1.2" => Ok(TlsVersion::Tls12),
+        "1.3" => Ok(TlsVersion::Tls13),
+        _ => Err(ConfigParseError::InvalidValue(format!(
+            "the tls version should be one of '1.2' or '1.3' ({s})"
+        ))),
+    }
+}
+
+/// Parse `KeyValueEncoding` from string
+/// # Errors
+/// Return error when parsing the given string to `KeyValueEncoding` failed
+#[inline]
+pub fn parse_key_value_encoding(s: &str) -> Result<KeyValueEncoding, ConfigParseError> {
+    match s {
+        "raw" => Ok(KeyValueEncoding::Raw),
+        "utf8-keys" => Ok(KeyValueEncoding::Utf8Keys),
+        "utf8" => Ok(KeyValueEncoding::Utf8),
+        _ => Err(ConfigParseError::InvalidValue(format!(
+            "the key value encoding should be one of 'raw', 'utf8-keys' or 'utf8' ({s})"
+        ))),
+    }
+}
+
+/// Parse `KeyCharset` from string
+/// # Errors
+/// Return error when parsing the given string to `KeyCharset` failed
+#[inline]
+pub fn parse_key_charset(s: &str) -> Result<KeyCharset, ConfigParseError> {
+    match s {
+        "any" => Ok(KeyCharset::Any),
+        "no-control" => Ok(KeyCharset::NoControl),
+        "printable" => Ok(KeyCharset::Printable),
+        _ => Err(ConfigParseError::InvalidValue(format!(
+            "the key charset should be one of 'any', 'no-control' or 'printable' ({s})"
+        ))),
+    }
+}
+
+/// Parse role quotas from string like "role1=max_keys:max_bytes:max_write_rate,role2=100:0:10",
+/// a limit of 0 means unlimited
+/// # Errors
+/// Return error when parsing the given string to role quotas failed
+#[inline]
+pub fn parse_role_quotas(s: &str) -> Result<HashMap<String, RoleQuota>, ConfigParseError> {
+    let mut quotas = HashMap::new();
+    for quota in s.split(',') {
+        let Some((role, limits)) = quota.split_once('=') else {
+            return Err(ConfigParseError::InvalidValue(format!(
+                "the role quota should be like 'role=max_keys:max_bytes:max_write_rate' ({quota})"
+            )));
+        };
+        let limits = limits
+            .split(':')
+            .map(str::parse::<u64>)
+            .collect::<Result<Vec<_>, _>>()?;
+        let [max_keys, max_bytes, max_write_rate] = limits.as_slice() else {
+            return Err(ConfigParseError::InvalidValue(format!(
+                "the role quota should have three limits ({quota})"
+            )));
+        };
+        if role.is_empty()
+            || quotas
+                .insert(
+                    role.to_owned(),
+                    RoleQuota::new(*max_keys, *max_bytes, *max_write_rate),
+                )
+                .is_some()
+        {
+            return Err(ConfigParseError::InvalidValue(format!(
+                "the role of a quota should be non-empty and unique ({quota})"
+            )));
+        }
+    }
+    Ok(quotas)
+}
+
+/// Parse member tags from string like "region=us-east-1,zone=a"
+/// # Errors
+/// Return error when parsing the given string to member tags failed
+#[inline]
+pub fn parse_member_tags(s: &str) -> Result<HashMap<String, String>, ConfigParseError> {
+    let mut tags = HashMap::new();
+    for tag in s.split(',') {
+        let Some((key, value)) = tag.split_once('=') else {
+            return Err(ConfigParseError::InvalidValue(format!(
+                "the member tag should be like 'key=value' ({tag})"
+            )));
+        };
+        if key.is_empty() || tags.insert(key.to_owned(), value.to_owned()).is_some() {
+            return Err(ConfigParseError::InvalidValue(format!(
+                "the key of a member tag should be non-empty and unique ({tag})"
+            )));
+        }
+    }
+    Ok(tags)
+}
+
+/// Parse the key files of the encryption at rest from string like
+/// "k1=/etc/xline/k1.key,k2=/etc/xline/k2.key"
+/// # Errors
+/// Return error when parsing the given string to key files failed
+#[inline]
+pub fn parse_encryption_key_files(s: &str) -> Result<HashMap<String, PathBuf>, ConfigParseError> {
+    let mut key_files = HashMap::new();
+    for key_file in s.split(',') {
+        let Some((id, path)) = key_file.split_once('=') else {
+            return Err(ConfigParseError::InvalidValue(format!(
+                "the key file should be like 'id=path' ({key_file})"
+            )));
+        };
+        if id.is_empty()
+            || path.is_empty()
+            || key_files
+                .insert(id.to_owned(), PathBuf::from(path))
+                .is_some()
+        {
+            return Err(ConfigParseError::InvalidValue(format!(
+                "the id and path of a key file should be non-empty and the id unique ({key_file})"
+            )));
+        }
+    }
+    Ok(key_files)
+}
+
 /// Parse `LOG_PATH` from string
 /// # Errors
 /// Return error when parsing the given string to `PathBuf` failed
@@ -399,6 +682,214 @@ mod test {
         assert!(parse_metrics_push_protocol("thrift").is_err());
     }
 
+    #[test]
+    fn test_parse_watch_history_replay() {
+        assert_eq!(
+            parse_watch_history_replay("allow").unwrap(),
+            WatchHistoryReplay::Allow
+        );
+        assert_eq!(
+            parse_watch_history_replay("reject").unwrap(),
+            WatchHistoryReplay::Reject
+        );
+        assert_eq!(
+            parse_watch_history_replay("current").unwrap(),
+            WatchHistoryReplay::Current
+        );
+        assert!(parse_watch_history_replay("ignore").is_err());
+    }
+
+    #[test]
+    fn test_parse_oversized_watch_event() {
+        assert_eq!(
+            parse_oversized_watch_event("fragment").unwrap(),
+            OversizedWatchEvent::Fragment
+        );
+        assert_eq!(
+            parse_oversized_watch_event("truncate").unwrap(),
+            OversizedWatchEvent::Truncate
+        );
+        assert!(parse_oversized_watch_event("drop").is_err());
+    }
+
+    #[test]
+    fn test_parse_snapshot_install_reads() {
+        assert_eq!(
+            parse_snapshot_install_reads("unavailable").unwrap(),
+            SnapshotInstallReads::Unavailable
+        );
+        assert_eq!(
+            parse_snapshot_install_reads("wait-for-install").unwrap(),
+            SnapshotInstallReads::WaitForInstall
+        );
+        assert!(parse_snapshot_install_reads("stale").is_err());
+    }
+
+    #[test]
+    fn test_parse_tls_version() {
+        assert_eq!(parse_tls_version("1.2").unwrap(), TlsVersion::Tls12);
+        assert_eq!(parse_tls_version("1.3").unwrap(), TlsVersion::Tls13);
+        assert!(parse_tls_version("1.1").is_err());
+    }
+
+    #[test]
+    fn test_parse_leaderless_reads() {
+        assert_eq!(
+            parse_leaderless_reads("wait").unwrap(),
+            LeaderlessReads::Wait
+        );
+        assert_eq!(
+            parse_leaderless_reads("unavailable").unwrap(),
+            LeaderlessReads::Unavailable
+        );
+        assert!(parse_leaderless_reads("stale").is_err());
+    }
+
+    #[test]
+    fn test_parse_range_result_overflow() {
+        assert_eq!(
+            parse_range_result_overflow("truncate").unwrap(),
+            RangeResultOverflow::Truncate
+        );
+        assert_eq!(
+            parse_range_result_overflow("reject").unwrap(),
+            RangeResultOverflow::Reject
+        );
+        assert!(parse_range_result_overflow("drop").is_err());
+    }
+
+    #[test]
+    fn test_parse_request_log_sampling() {
+        assert_eq!(
+            parse_request_log_sampling("off").unwrap(),
+            RequestLogSampling::Off
+        );
+        assert_eq!(
+            parse_request_log_sampling("1/100").unwrap(),
+            RequestLogSampling::OneIn(100)
+        );
+        assert_eq!(
+            parse_request_log_sampling("10/s").unwrap(),
+            RequestLogSampling::PerSecond(10)
+        );
+        for invalid in ["1/0", "2/100", "10/m", "100", "x/s"] {
+            assert!(parse_request_log_sampling(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_parse_maintenance_policy_and_op() {
+        assert_eq!(
+            parse_maintenance_policy("serial").unwrap(),
+            MaintenancePolicy::Serial
+        );
+        assert_eq!(
+            parse_maintenance_policy("concurrent").unwrap(),
+            MaintenancePolicy::Concurrent
+        );
+        assert!(parse_maintenance_policy("parallel").is_err());
+        assert_eq!(
+            parse_maintenance_op("defrag").unwrap(),
+            MaintenanceOp::Defrag
+        );
+        assert!(parse_maintenance_op("backup").is_err());
+    }
+
+    #[test]
+    fn test_parse_key_value_encoding() {
+        assert_eq!(
+            parse_key_value_encoding("raw").unwrap(),
+            KeyValueEncoding::Raw
+        );
+        assert_eq!(
+            parse_key_value_encoding("utf8-keys").unwrap(),
+            KeyValueEncoding::Utf8Keys
+        );
+        assert_eq!(
+            parse_key_value_encoding("utf8").unwrap(),
+            KeyValueEncoding::Utf8
+        );
+        assert!(parse_key_value_encoding("ascii").is_err());
+    }
+
+    #[test]
+    fn test_parse_key_charset() {
+        assert_eq!(parse_key_charset("any").unwrap(), KeyCharset::Any);
+        assert_eq!(
+            parse_key_charset("no-control").unwrap(),
+            KeyCharset::NoControl
+        );
+        assert_eq!(
+            parse_key_charset("printable").unwrap(),
+            KeyCharset::Printable
+        );
+        assert!(parse_key_charset("ascii").is_err());
+    }
+
+    #[test]
+    fn test_parse_role_quotas() {
+        assert_eq!(
+            parse_role_quotas("tenant=100:0:10,guest=1:64:0").unwrap(),
+            HashMap::from([
+                ("tenant".to_owned(), RoleQuota::new(100, 0, 10)),
+                ("guest".to_owned(), RoleQuota::new(1, 64, 0)),
+            ])
+        );
+        for invalid in [
+            "",
+            "tenant",
+            "tenant=1:2",
+            "=1:2:3",
+            "a=1:2:x",
+            "a=1:2:3,a=1:2:3",
+        ] {
+            assert!(parse_role_quotas(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_parse_member_tags() {
+        assert_eq!(
+            parse_member_tags("region=us-east-1,zone=a,rack=").unwrap(),
+            HashMap::from([
+                ("region".to_owned(), "us-east-1".to_owned()),
+                ("zone".to_owned(), "a".to_owned()),
+                ("rack".to_owned(), String::new()),
+            ])
+        );
+        for invalid in ["", "region", "=a", "zone=a,zone=b"] {
+            assert!(parse_member_tags(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_parse_encryption_key_files() {
+        assert_eq!(
+            parse_encryption_key_files("k1=/etc/xline/k1.key,k2=k2.key").unwrap(),
+            HashMap::from([
+                ("k1".to_owned(), PathBuf::from("/etc/xline/k1.key")),
+                ("k2".to_owned(), PathBuf::from("k2.key")),
+            ])
+        );
+        for invalid in ["", "k1", "=a", "k1=", "k1=a,k1=b"] {
+            assert!(parse_encryption_key_files(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_parse_maintenance_window() {
+        let window = parse_maintenance_window("23:30-01:00").unwrap();
+        assert_eq!(window, MaintenanceWindow::new(1410, 60));
+        assert!(window.contains(1439) && window.contains(0) && window.contains(59));
+        assert!(!window.contains(60) && !window.contains(720));
+        assert!(parse_maintenance_window("00:00-00:00")
+            .unwrap()
+            .contains(720));
+        for invalid in ["", "02:00", "24:00-01:00", "02:60-03:00", "a:00-b:00"] {
+            assert!(parse_maintenance_window(invalid).is_err(), "{invalid}");
+        }
+    }
+
     #[test]
     fn test_parse_log_file() {
         // Test case 1: Valid log file path
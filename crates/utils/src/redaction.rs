@@ -0,0 +1,187 @@
+use std::{
+    fmt::{Debug, Display, Formatter, Result},
+    sync::OnceLock,
+};
+
+/// The redaction policy of the process
+static POLICY: OnceLock<RedactionPolicy> = OnceLock::new();
+
+/// The offset basis of the 64-bit FNV-1a hash
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// The prime of the 64-bit FNV-1a hash
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// The policy of redacting the keys and values of the kv store in the error
+/// messages, logs and traces.
+///
+/// The policy is installed once for the process, and every surface formatting a key
+/// or a value goes through `redact_key` or `redact_value`, so that the redacted bytes
+/// never reach the output. A redacted part is replaced by its size and its FNV-1a
+/// hash, which is enough to tell whether two occurrences are the same bytes.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RedactionPolicy {
+    /// Whether the values are redacted
+    redact_values: bool,
+    /// The keys under these prefixes are redacted except the prefixes
+    sensitive_prefixes: Vec<Vec<u8>>,
+}
+
+impl RedactionPolicy {
+    /// New `RedactionPolicy`
+    #[must_use]
+    #[inline]
+    pub fn new(redact_values: bool, sensitive_prefixes: &[String]) -> Self {
+        Self {
+            redact_values,
+            sensitive_prefixes: sensitive_prefixes
+                .iter()
+                .map(|prefix| prefix.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    /// Install the policy for the process, returns `false` if a policy has already
+    /// been installed, in which case the installed one is kept
+    #[inline]
+    pub fn install(self) -> bool {
+        POLICY.set(self).is_ok()
+    }
+
+    /// Get the installed policy, which redacts nothing if no policy is installed
+    #[must_use]
+    #[inline]
+    pub fn current() -> &'static Self {
+        /// The policy used before one is installed
+        static NONE: RedactionPolicy = RedactionPolicy {
+            redact_values: false,
+            sensitive_prefixes: Vec::new(),
+        };
+        POLICY.get().unwrap_or(&NONE)
+    }
+
+    /// Redact a key by the policy
+    #[must_use]
+    #[inline]
+    pub fn key<'a>(&self, key: &'a [u8]) -> Redacted<'a> {
+        let prefix_len = self
+            .sensitive_prefixes
+            .iter()
+            .filter(|prefix| key.starts_with(prefix))
+            .map(Vec::len)
+            .max();
+        match prefix_len {
+            Some(len) => {
+                let (shown, hidden) = key.split_at(len);
+                Redacted { shown, hidden }
+            }
+            None => Redacted::plain(key),
+        }
+    }
+
+    /// Redact a value by the policy
+    #[must_use]
+    #[inline]
+    pub fn value<'a>(&self, value: &'a [u8]) -> Redacted<'a> {
+        if self.redact_values {
+            Redacted {
+                shown: &[],
+                hidden: value,
+            }
+        } else {
+            Redacted::plain(value)
+        }
+    }
+}
+
+/// Redact a key by the installed policy
+#[must_use]
+#[inline]
+pub fn redact_key(key: &[u8]) -> Redacted<'_> {
+    RedactionPolicy::current().key(key)
+}
+
+/// Redact a value by the installed policy
+#[must_use]
+#[inline]
+pub fn redact_value(value: &[u8]) -> Redacted<'_> {
+    RedactionPolicy::current().value(value)
+}
+
+/// The bytes of a key or a value formatted by a redaction policy
+#[derive(Clone, Copy)]
+pub struct Redacted<'a> {
+    /// The bytes formatted as they are
+    shown: &'a [u8],
+    /// The bytes replaced by their size and hash
+    hidden: &'a [u8],
+}
+
+impl<'a> Redacted<'a> {
+    /// The bytes which are not redacted
+    fn plain(bytes: &'a [u8]) -> Self {
+        Self {
+            shown: bytes,
+            hidden: &[],
+        }
+    }
+}
+
+impl Display for Redacted<'_> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        if !self.shown.is_empty() || self.hidden.is_empty() {
+            write!(f, "{:?}", String::from_utf8_lossy(self.shown))?;
+        }
+        if !self.hidden.is_empty() {
+            write!(
+                f,
+                "<redacted {} bytes, hash {:016x}>",
+                self.hidden.len(),
+                fnv1a(self.hidden)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for Redacted<'_> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// The 64-bit FNV-1a hash of the bytes
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redacted_bytes_should_be_replaced_by_size_and_hash() {
+        let policy = RedactionPolicy::new(true, &["secret/".to_owned(), "secret/deep/".to_owned()]);
+        let value = policy.value(b"password").to_string();
+        assert!(!value.contains("password"));
+        assert!(value.starts_with("<redacted 8 bytes, hash "));
+        assert_eq!(value, policy.value(b"password").to_string());
+        assert_ne!(value, policy.value(b"passwore").to_string());
+
+        let key = policy.key(b"secret/deep/token").to_string();
+        assert!(key.starts_with("\"secret/deep/\"<redacted 5 bytes"));
+        assert!(!key.contains("token"));
+        assert_eq!(policy.key(b"public/token").to_string(), "\"public/token\"");
+        assert_eq!(policy.value(b"").to_string(), "\"\"");
+
+        let plain = RedactionPolicy::default();
+        assert_eq!(plain.value(b"password").to_string(), "\"password\"");
+        let debug = format!("{:?}", policy.key(b"secret/token"));
+        assert_eq!(debug, policy.key(b"secret/token").to_string());
+    }
+}
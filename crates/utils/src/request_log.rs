@@ -0,0 +1,176 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::config::{LogConfig, RequestLogSampling};
+
+/// The request log sampler of the process
+static SAMPLER: OnceLock<RequestLogSampler> = OnceLock::new();
+
+/// The sampler choosing the requests to log.
+///
+/// The sampler is installed once for the process. A request is sampled before it is
+/// handled by the configured sampling, a sampled request is logged with its outcome,
+/// and the failed or slow requests are logged regardless of the sampling if
+/// configured so.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug)]
+pub struct RequestLogSampler {
+    /// The sampling of the successful requests
+    sampling: RequestLogSampling,
+    /// Whether the failed requests are always logged
+    log_errors: bool,
+    /// The requests taking longer than it are always logged, 0 means disabled
+    slow_threshold: Duration,
+    /// The number of the requests sampled by `OneIn`
+    seen: AtomicU64,
+    /// The time the windows of `PerSecond` are counted from
+    base: Instant,
+    /// The second of the current window of `PerSecond`
+    window: AtomicU64,
+    /// The number of the requests sampled in the current window of `PerSecond`
+    window_sampled: AtomicU64,
+}
+
+impl RequestLogSampler {
+    /// New `RequestLogSampler`
+    #[must_use]
+    #[inline]
+    pub fn new(sampling: RequestLogSampling, log_errors: bool, slow_threshold: Duration) -> Self {
+        Self {
+            sampling,
+            log_errors,
+            slow_threshold,
+            seen: AtomicU64::new(0),
+            base: Instant::now(),
+            window: AtomicU64::new(0),
+            window_sampled: AtomicU64::new(0),
+        }
+    }
+
+    /// New `RequestLogSampler` from the log config
+    #[must_use]
+    #[inline]
+    pub fn from_config(log_config: &LogConfig) -> Self {
+        Self::new(
+            *log_config.request_sampling(),
+            *log_config.request_errors(),
+            *log_config.slow_request_threshold(),
+        )
+    }
+
+    /// Install the sampler for the process, returns `false` if a sampler has already
+    /// been installed, in which case the installed one is kept
+    #[inline]
+    pub fn install(self) -> bool {
+        SAMPLER.set(self).is_ok()
+    }
+
+    /// Get the installed sampler, which logs nothing if no sampler is installed
+    #[must_use]
+    #[inline]
+    pub fn current() -> &'static Self {
+        /// The sampler used before one is installed
+        static NONE: OnceLock<RequestLogSampler> = OnceLock::new();
+        SAMPLER.get().unwrap_or_else(|| {
+            NONE.get_or_init(|| Self::new(RequestLogSampling::Off, false, Duration::ZERO))
+        })
+    }
+
+    /// Whether any request may be logged
+    #[must_use]
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.sampling != RequestLogSampling::Off
+            || self.log_errors
+            || !self.slow_threshold.is_zero()
+    }
+
+    /// Sample a request before it is handled
+    #[must_use]
+    #[inline]
+    pub fn sample(&self) -> bool {
+        match self.sampling {
+            RequestLogSampling::OneIn(n) => {
+                let seen = self.seen.fetch_add(1, Ordering::Relaxed);
+                seen.checked_rem(n).map_or(true, |rem| rem == 0)
+            }
+            RequestLogSampling::PerSecond(n) => {
+                let second = self.base.elapsed().as_secs();
+                let window = self.window.load(Ordering::Relaxed);
+                if window != second
+                    && self
+                        .window
+                        .compare_exchange(window, second, Ordering::Relaxed, Ordering::Relaxed)
+                        .is_ok()
+                {
+                    self.window_sampled.store(0, Ordering::Relaxed);
+                }
+                self.window_sampled.fetch_add(1, Ordering::Relaxed) < n
+            }
+            RequestLogSampling::Off => false,
+        }
+    }
+
+    /// Whether a handled request is logged, a sampled request is always logged
+    #[must_use]
+    #[inline]
+    pub fn should_log(&self, sampled: bool, failed: bool, elapsed: Duration) -> bool {
+        sampled
+            || (failed && self.log_errors)
+            || (!self.slow_threshold.is_zero() && elapsed >= self.slow_threshold)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sampling_rate_should_be_respected() {
+        let one_in = RequestLogSampler::new(RequestLogSampling::OneIn(100), false, Duration::ZERO);
+        let sampled = (0..10_000).filter(|_| one_in.sample()).count();
+        assert_eq!(sampled, 100);
+
+        let per_second =
+            RequestLogSampler::new(RequestLogSampling::PerSecond(10), false, Duration::ZERO);
+        let sampled = (0..10_000).filter(|_| per_second.sample()).count();
+        // the requests may span two windows
+        assert!((10..=20).contains(&sampled), "{sampled}");
+
+        let off = RequestLogSampler::new(RequestLogSampling::Off, false, Duration::ZERO);
+        assert!(!off.is_enabled());
+        assert!((0..10_000).all(|_| !off.sample()));
+    }
+
+    #[test]
+    fn errors_and_slow_requests_should_always_be_logged() {
+        let sampler = RequestLogSampler::new(
+            RequestLogSampling::OneIn(1000),
+            true,
+            Duration::from_millis(100),
+        );
+        let _first = sampler.sample();
+        let sampled: Vec<_> = (0..1000).map(|_| sampler.sample()).collect();
+        let logged = |failed, elapsed| {
+            sampled
+                .iter()
+                .filter(|&&sampled| sampler.should_log(sampled, failed, elapsed))
+                .count()
+        };
+        let logged = (
+            logged(true, Duration::ZERO),
+            logged(false, Duration::from_millis(100)),
+            logged(false, Duration::from_millis(1)),
+        );
+        assert_eq!(logged, (1000, 1000, 1));
+
+        let sampled_only =
+            RequestLogSampler::new(RequestLogSampling::OneIn(1000), false, Duration::ZERO);
+        assert!(!sampled_only.should_log(false, true, Duration::from_secs(10)));
+    }
+}
@@ -12,9 +12,11 @@ pub const USER_TABLE: &str = "user";
 pub const ROLE_TABLE: &str = "role";
 /// Alarm table name
 pub const ALARM_TABLE: &str = "alarm";
+/// Deduplicated value table name
+pub const VALUE_TABLE: &str = "value";
 
 /// Xline Server Storage Table
-pub const XLINE_TABLES: [&str; 7] = [
+pub const XLINE_TABLES: [&str; 8] = [
     META_TABLE,
     KV_TABLE,
     LEASE_TABLE,
@@ -22,4 +24,5 @@ pub const XLINE_TABLES: [&str; 7] = [
     USER_TABLE,
     ROLE_TABLE,
     ALARM_TABLE,
+    VALUE_TABLE,
 ];
@@ -51,6 +51,9 @@ enum_with_iter! {
     RevokeExpiredLeases,
     SyncVictims,
     AutoCompactor,
+    ApplyWatchdog,
+    AutoDefrag,
+    IndexCheck,
 }
 
 /// All edges of task graph, the first item in each pair must be shut down before the second item
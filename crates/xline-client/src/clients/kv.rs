@@ -1,14 +1,23 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{collections::HashSet, fmt::Debug, sync::Arc};
 
+use clippy_utilities::{NumericCast, OverflowArithmetic};
 use tonic::transport::Channel;
 use xlineapi::{
-    command::Command, CompactionResponse, DeleteRangeResponse, PutResponse, RangeResponse,
-    RequestWrapper, TxnResponse,
+    command::Command,
+    server_op::{ServerOp, ServerOpResult},
+    CompactionResponse, DeleteRangeResponse, PutResponse, RangeResponse, RequestWrapper, Response,
+    TxnResponse,
 };
 
 use crate::{
-    error::Result,
-    types::kv::{CompactionRequest, DeleteRangeRequest, PutRequest, RangeRequest, TxnRequest},
+    error::{Result, XlineClientError},
+    types::kv::{
+        AppendRequest, AppendResponse, CompactionRequest, CompareAndSwapRequest,
+        CompareAndSwapResponse, ConditionalDeleteRequest, ConditionalDeleteResponse,
+        DeleteRangeRequest, IncrementRequest, IncrementResponse, MovePrefixRequest,
+        MovePrefixResponse, PutRequest, RangeRequest, ReserveRevisionsRequest,
+        ReserveRevisionsResponse, SwapRequest, SwapResponse, TxnRequest,
+    },
     AuthService, CurpClient,
 };
 
@@ -212,7 +221,12 @@ impl KvClient {
     /// ```
     #[inline]
     pub async fn txn(&self, request: TxnRequest) -> Result<TxnResponse> {
-        let request = RequestWrapper::from(xlineapi::TxnRequest::from(request));
+        self.propose_txn(request.into()).await
+    }
+
+    /// Propose a txn and get its response with the revision it is synced at
+    async fn propose_txn(&self, request: xlineapi::TxnRequest) -> Result<TxnResponse> {
+        let request = RequestWrapper::from(request);
         let cmd = Command::new(request.keys(), request);
         let (cmd_res, Some(sync_res)) = self
             .curp_client
@@ -226,6 +240,392 @@ impl KvClient {
         Ok(res_wrapper.into())
     }
 
+    /// Swaps multiple keys to new values only if all of them hold the expected values.
+    ///
+    /// The swap is executed as a single transaction, so either all keys are changed or
+    /// none of them is. When it fails, the current values of all keys are returned.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the request is empty or contains duplicate
+    /// keys, or the inner CURP client encountered a propose failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{types::kv::CompareAndSwapRequest, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let mut client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .kv_client();
+    ///
+    ///     let request = CompareAndSwapRequest::new()
+    ///         .with_swap("key1", "old1", "new1")
+    ///         .with_swap("key2", "old2", "new2")
+    ///         .with_create("key3", "new3");
+    ///     let resp = client.compare_and_swap(request).await?;
+    ///     if !resp.succeeded {
+    ///         println!("current values: {:?}", resp.current);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn compare_and_swap(
+        &self,
+        request: CompareAndSwapRequest,
+    ) -> Result<CompareAndSwapResponse> {
+        let keys: Vec<Vec<u8>> = request.keys().into_iter().map(<[u8]>::to_vec).collect();
+        if keys.is_empty() {
+            return Err(XlineClientError::InvalidArgs(String::from(
+                "no key to compare and swap",
+            )));
+        }
+        if keys.iter().collect::<HashSet<_>>().len() != keys.len() {
+            return Err(XlineClientError::InvalidArgs(String::from(
+                "duplicate keys to compare and swap",
+            )));
+        }
+        let resp = self.txn(request.into()).await?;
+        let revision = resp.header.as_ref().map_or(0, |header| header.revision);
+        let current = if resp.succeeded {
+            Vec::new()
+        } else {
+            keys.into_iter()
+                .zip(resp.responses)
+                .map(|(key, op)| {
+                    let kv = if let Some(Response::ResponseRange(range)) = op.response {
+                        range.kvs.into_iter().next()
+                    } else {
+                        None
+                    };
+                    (key, kv)
+                })
+                .collect()
+        };
+        Ok(CompareAndSwapResponse {
+            revision,
+            succeeded: resp.succeeded,
+            current,
+        })
+    }
+
+    /// Atomically adds a delta to the counter stored at a key and returns the new value,
+    /// a missing key is treated as a counter of 0.
+    ///
+    /// The increment is applied by the server as a single command, which reads the
+    /// counter and writes it back, so concurrent increments never lose updates and
+    /// never retry. The counter keeps its lease.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the current value is not a counter in the
+    /// requested encoding, the new value overflows, the server doesn't support the
+    /// increments, or the inner CURP client encountered a propose failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{
+    ///     types::kv::{CounterEncoding, IncrementRequest},
+    ///     Client, ClientOptions,
+    /// };
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let mut client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .kv_client();
+    ///
+    ///     let request =
+    ///         IncrementRequest::new("counter", 1).with_encoding(CounterEncoding::BigEndian);
+    ///     let resp = client.increment(request).await?;
+    ///     println!("counter: {}", resp.value);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn increment(&self, request: IncrementRequest) -> Result<IncrementResponse> {
+        let (revision, result) = self.server_op(request.into()).await?;
+        let ServerOpResult::Increment(value) = result else {
+            return Err(Self::unexpected_result(&result));
+        };
+        Ok(IncrementResponse { revision, value })
+    }
+
+    /// Atomically appends bytes to the value of a key and returns the new length of the
+    /// value, a missing key is created with the appended bytes.
+    ///
+    /// The append is applied by the server as a single command, which also checks the
+    /// maximum value size of the request against the value it appends to, so concurrent
+    /// appends are never lost and never exceed the limit. The key keeps its lease.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the new value exceeds the maximum value
+    /// size of the request, the server doesn't support the appends, or the inner CURP
+    /// client encountered a propose failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{types::kv::AppendRequest, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .kv_client();
+    ///
+    ///     let resp = client.append(AppendRequest::new("log", "entry\n")).await?;
+    ///     println!("length: {}", resp.length);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn append(&self, request: AppendRequest) -> Result<AppendResponse> {
+        let (revision, result) = self.server_op(request.into()).await?;
+        let ServerOpResult::Append(length) = result else {
+            return Err(Self::unexpected_result(&result));
+        };
+        Ok(AppendResponse {
+            revision,
+            length: length.numeric_cast(),
+        })
+    }
+
+    /// Atomically swaps the values of two keys, and optionally their leases.
+    ///
+    /// The swap is applied by the server as a single command, which reads both keys and
+    /// puts the value of each key to the other one at a single revision, so a watcher
+    /// of either key sees both changes at that revision, and the swap never retries.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the keys are the same, either key is
+    /// missing and `create_missing` is not set, the server doesn't support the swaps,
+    /// or the inner CURP client encountered a propose failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{types::kv::SwapRequest, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .kv_client();
+    ///
+    ///     let resp = client.swap(SwapRequest::new("lb/active", "lb/standby")).await?;
+    ///     println!("swapped at revision {}", resp.revision);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn swap(&self, request: SwapRequest) -> Result<SwapResponse> {
+        if request.first() == request.second() {
+            return Err(XlineClientError::InvalidArgs(String::from(
+                "the keys to swap are the same",
+            )));
+        }
+        let (revision, result) = self.server_op(request.into()).await?;
+        let ServerOpResult::Swap(_) = result else {
+            return Err(Self::unexpected_result(&result));
+        };
+        Ok(SwapResponse { revision })
+    }
+
+    /// Atomically moves all the keys under a prefix to another prefix.
+    ///
+    /// The move is applied by the server as a single command, which puts every key under
+    /// the destination prefix with its value and lease and deletes the source prefix at
+    /// a single revision, so no reader ever sees a key under both prefixes or neither,
+    /// and the move never retries.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if either prefix is empty, the prefixes
+    /// overlap, the destination prefix is not empty, the moved keys and values exceed
+    /// `MAX_MOVED_BYTES`, the server doesn't support the moves, or the inner CURP client
+    /// encountered a propose failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{types::kv::MovePrefixRequest, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .kv_client();
+    ///
+    ///     let resp = client.move_prefix(MovePrefixRequest::new("app/v1/", "app/v2/")).await?;
+    ///     println!("moved {} keys at revision {}", resp.moved, resp.revision);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn move_prefix(&self, request: MovePrefixRequest) -> Result<MovePrefixResponse> {
+        let (revision, result) = self.server_op(request.into()).await?;
+        let ServerOpResult::MovePrefix(res) = result else {
+            return Err(Self::unexpected_result(&result));
+        };
+        Ok(MovePrefixResponse {
+            revision,
+            moved: res.moved,
+        })
+    }
+
+    /// Atomically deletes a range only if there are fewer keys in it than a threshold.
+    ///
+    /// The delete is applied by the server as a single command, which counts the keys
+    /// and deletes them at one revision, so it never deletes a key it hasn't counted,
+    /// and it never retries. A skipped delete has no change on the store.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the threshold is 0, the server doesn't
+    /// support the conditional deletes, or the inner CURP client encountered a propose
+    /// failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{types::kv::ConditionalDeleteRequest, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .kv_client();
+    ///
+    ///     let resp = client
+    ///         .conditional_delete(ConditionalDeleteRequest::new("tmp/", 100).with_prefix())
+    ///         .await?;
+    ///     println!("counted {} keys, deleted: {}", resp.count, resp.deleted);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn conditional_delete(
+        &self,
+        request: ConditionalDeleteRequest,
+    ) -> Result<ConditionalDeleteResponse> {
+        if request.threshold() == 0 {
+            return Err(XlineClientError::InvalidArgs(String::from(
+                "the threshold of a conditional delete should be positive",
+            )));
+        }
+        let (revision, result) = self.server_op(request.into()).await?;
+        let ServerOpResult::ConditionalDelete(res) = result else {
+            return Err(Self::unexpected_result(&result));
+        };
+        Ok(ConditionalDeleteResponse {
+            revision,
+            count: res.count,
+            deleted: res.deleted,
+        })
+    }
+
+    /// Reserves a block of revisions, i.e. advances the revision of the store by the
+    /// count of the request without writing any key.
+    ///
+    /// This is an advanced feature for ordering the events of an external system with
+    /// the ones of the store: no key is ever modified at a reserved revision, so the
+    /// reserved revisions can be assigned to the external events, and all writes after
+    /// the reservation are at revisions above the block. The reservation is replicated
+    /// and persisted, so it is kept across restarts. Reads and watches are unaffected,
+    /// a watch simply sees no events at the reserved revisions. It requires the root
+    /// role when the auth is enabled, and a reservation is bounded by
+    /// `MAX_RESERVED_REVISIONS`.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the count is out of bounds, the user is
+    /// not the root, the server doesn't support the reservations, or the inner CURP
+    /// client encountered a propose failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{types::kv::ReserveRevisionsRequest, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .kv_client();
+    ///
+    ///     let resp = client.reserve_revisions(ReserveRevisionsRequest::new(100)).await?;
+    ///     println!("reserved revisions {}..={}", resp.first, resp.last);
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn reserve_revisions(
+        &self,
+        request: ReserveRevisionsRequest,
+    ) -> Result<ReserveRevisionsResponse> {
+        let (last, result) = self.server_op(request.into()).await?;
+        let ServerOpResult::ReserveRevisions(count) = result else {
+            return Err(Self::unexpected_result(&result));
+        };
+        let count: i64 = count.numeric_cast();
+        Ok(ReserveRevisionsResponse {
+            first: last.overflow_sub(count).overflow_add(1),
+            last,
+        })
+    }
+
+    /// Apply an operation by the server in a single txn, and get the revision it is
+    /// applied at and its result
+    async fn server_op(&self, op: ServerOp) -> Result<(i64, ServerOpResult)> {
+        let resp = self.propose_txn(op.into()).await?;
+        let revision = resp.header.as_ref().map_or(0, |header| header.revision);
+        // a server unaware of the operation fails the txn without applying it
+        let result = ServerOpResult::from_txn_response(&resp).ok_or_else(|| {
+            XlineClientError::RpcError(String::from("the server doesn't support the operation"))
+        })?;
+        Ok((revision, result))
+    }
+
+    /// The error of a result of another operation
+    fn unexpected_result(result: &ServerOpResult) -> XlineClientError<Command> {
+        XlineClientError::InternalError(format!("unexpected result of the operation: {result:?}"))
+    }
+
     /// Compacts the key-value store up to a given revision.
     /// All keys with revisions less than the given revision will be compacted.
     /// The compaction process will remove all historical versions of these keys, except for the most recent one.
@@ -1,10 +1,12 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{collections::HashSet, fmt::Debug, sync::Arc};
 
 use futures::channel::mpsc::channel;
 use tonic::{transport::Channel, Streaming};
 use xlineapi::{
-    command::Command, LeaseGrantResponse, LeaseKeepAliveResponse, LeaseLeasesResponse,
-    LeaseRevokeResponse, LeaseTimeToLiveResponse, RequestWrapper,
+    command::Command,
+    server_op::{GrantLeasesOp, LeaseGrant, ServerOp, ServerOpResult},
+    LeaseGrantResponse, LeaseKeepAliveResponse, LeaseLeasesResponse, LeaseRevokeResponse,
+    LeaseTimeToLiveResponse, RequestWrapper, TxnResponse,
 };
 
 use crate::{
@@ -108,6 +110,111 @@ impl LeaseClient {
         Ok(cmd_res.into_inner().into())
     }
 
+    /// Creates a batch of leases, the lease id of each request will be generated
+    /// if it is not specified. The responses are returned in the same order as the
+    /// requests.
+    ///
+    /// The batch is granted by the server as a single command, of at most
+    /// `MAX_GRANTED_LEASES` leases, and every lease is checked as a single grant, so
+    /// either all leases are granted or none of them is.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the batch contains duplicate lease ids,
+    /// any of the leases can't be granted, the server doesn't support the batches, or
+    /// the inner CURP client encountered a propose failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{types::lease::LeaseGrantRequest, Client, ClientOptions};
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let mut client = Client::connect(curp_members, ClientOptions::default())
+    ///         .await?
+    ///         .lease_client();
+    ///
+    ///     let requests = vec![
+    ///         LeaseGrantRequest::new(60),
+    ///         LeaseGrantRequest::new(60).with_id(1),
+    ///     ];
+    ///     let resps = client.grant_batch(requests).await?;
+    ///     for resp in resps {
+    ///         println!("lease id: {}", resp.id);
+    ///     }
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn grant_batch(
+        &self,
+        mut requests: Vec<LeaseGrantRequest>,
+    ) -> Result<Vec<LeaseGrantResponse>> {
+        let mut ids = HashSet::with_capacity(requests.len());
+        for request in requests.iter().filter(|r| r.inner.id != 0) {
+            if !ids.insert(request.inner.id) {
+                return Err(XlineClientError::InvalidArgs(format!(
+                    "duplicate lease id {} in the batch",
+                    request.inner.id
+                )));
+            }
+        }
+        for request in &mut requests {
+            while request.inner.id == 0 {
+                let id = self.id_gen.next();
+                if ids.insert(id) {
+                    request.inner.id = id;
+                }
+            }
+        }
+        if requests.is_empty() {
+            return Ok(Vec::new());
+        }
+        let leases: Vec<_> = requests
+            .iter()
+            .map(|request| LeaseGrant {
+                id: request.inner.id,
+                ttl: request.inner.ttl,
+            })
+            .collect();
+        let op = ServerOp::GrantLeases(GrantLeasesOp::new(leases.clone()));
+        let request = RequestWrapper::from(xlineapi::TxnRequest::from(op));
+        let cmd = Command::new(request.keys(), request);
+        let (cmd_res, _sync_res) = self
+            .curp_client
+            .propose(&cmd, self.token.as_ref(), true)
+            .await??;
+        let resp: TxnResponse = cmd_res.into_inner().into();
+        // a server unaware of the batches fails the txn without granting any lease
+        let Some(ServerOpResult::GrantLeases(result)) = ServerOpResult::from_txn_response(&resp)
+        else {
+            return Err(XlineClientError::RpcError(String::from(
+                "the server doesn't support the batch lease grants",
+            )));
+        };
+        if result.ids.len() != leases.len() {
+            return Err(XlineClientError::InternalError(format!(
+                "{} leases are granted for a batch of {}",
+                result.ids.len(),
+                leases.len()
+            )));
+        }
+        Ok(leases
+            .into_iter()
+            .map(|lease| LeaseGrantResponse {
+                header: resp.header.clone(),
+                id: lease.id,
+                ttl: lease.ttl,
+                error: String::new(),
+            })
+            .collect())
+    }
+
     /// Revokes a lease. All keys attached to the lease will expire and be deleted.
     ///
     /// # Errors
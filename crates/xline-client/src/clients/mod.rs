@@ -5,7 +5,10 @@ pub use kv::KvClient;
 pub use lease::LeaseClient;
 pub use lock::LockClient;
 pub use maintenance::MaintenanceClient;
+pub use namespace::{NamespaceKvClient, NamespaceWatchClient, NamespaceWatchStreaming};
+pub use read_snapshot::ReadSnapshot;
 pub use watch::WatchClient;
+pub use watch_mux::{MuxWatcher, WatchMultiplexer};
 
 /// Auth client.
 mod auth;
@@ -21,5 +24,11 @@ mod lease;
 mod lock;
 /// Maintenance client.
 mod maintenance;
+/// Namespace clients.
+mod namespace;
+/// Read snapshot sessions.
+mod read_snapshot;
 /// Watch client.
 mod watch;
+/// Watch multiplexer.
+mod watch_mux;
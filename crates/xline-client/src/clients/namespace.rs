@@ -0,0 +1,307 @@
+use xlineapi::{
+    command::KeyRange, CompactionResponse, DeleteRangeResponse, KeyValue, PutResponse,
+    RangeResponse, Request, Response, TxnResponse, WatchResponse,
+};
+
+use super::{KvClient, WatchClient};
+use crate::{
+    error::Result,
+    types::{
+        kv::{CompactionRequest, DeleteRangeRequest, PutRequest, RangeRequest, TxnRequest},
+        watch::{WatchRequest, WatchStreaming, Watcher},
+    },
+};
+
+/// Client for KV operations confined to a namespace.
+///
+/// Every key and range end of a request is prefixed by the namespace and the namespace
+/// is stripped from the keys of the responses, so the keys outside the namespace can
+/// neither be seen nor changed through it. A range end of `\0`, which means all keys
+/// greater than or equal to the key, stops at the end of the namespace. It is purely
+/// client side, the server stores the prefixed keys.
+#[derive(Clone, Debug)]
+pub struct NamespaceKvClient {
+    /// The inner kv client
+    inner: KvClient,
+    /// The namespace prefixing the keys
+    namespace: Vec<u8>,
+}
+
+impl NamespaceKvClient {
+    /// Creates a new `NamespaceKvClient`
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{
+    ///     clients::NamespaceKvClient,
+    ///     types::kv::{PutRequest, RangeRequest},
+    ///     Client, ClientOptions,
+    /// };
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default()).await?;
+    ///     let ns_client = NamespaceKvClient::new(client.kv_client(), "app/");
+    ///
+    ///     // stored as "app/key1" on the server
+    ///     ns_client.put(PutRequest::new("key1", "value1")).await?;
+    ///     let resp = ns_client.range(RangeRequest::new("key1")).await?;
+    ///     assert_eq!(resp.kvs[0].key, b"key1");
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn new(client: KvClient, namespace: impl Into<Vec<u8>>) -> Self {
+        Self {
+            inner: client,
+            namespace: namespace.into(),
+        }
+    }
+
+    /// Put a key-value into the namespace
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure
+    #[inline]
+    pub async fn put(&self, mut request: PutRequest) -> Result<PutResponse> {
+        request.inner.key = prefix_key(&self.namespace, &request.inner.key);
+        let mut resp = self.inner.put(request).await?;
+        if let Some(ref mut prev_kv) = resp.prev_kv {
+            strip_kv(&self.namespace, prev_kv);
+        }
+        Ok(resp)
+    }
+
+    /// Get a range of keys from the namespace
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure
+    #[inline]
+    pub async fn range(&self, mut request: RangeRequest) -> Result<RangeResponse> {
+        let inner = &mut request.inner;
+        prefix_range(&self.namespace, &mut inner.key, &mut inner.range_end);
+        let mut resp = self.inner.range(request).await?;
+        strip_range(&self.namespace, &mut resp);
+        Ok(resp)
+    }
+
+    /// Delete a range of keys from the namespace
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure
+    #[inline]
+    pub async fn delete(&self, mut request: DeleteRangeRequest) -> Result<DeleteRangeResponse> {
+        let inner = &mut request.inner;
+        prefix_range(&self.namespace, &mut inner.key, &mut inner.range_end);
+        let mut resp = self.inner.delete(request).await?;
+        strip_delete(&self.namespace, &mut resp);
+        Ok(resp)
+    }
+
+    /// Creates a transaction in the namespace, the keys of its compares and its
+    /// operations, including the nested transactions, are all in the namespace
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure
+    #[inline]
+    pub async fn txn(&self, mut request: TxnRequest) -> Result<TxnResponse> {
+        prefix_txn(&self.namespace, &mut request.inner);
+        let mut resp = self.inner.txn(request).await?;
+        strip_txn(&self.namespace, &mut resp);
+        Ok(resp)
+    }
+
+    /// Compacts the key-value store up to a given revision, the revisions are shared by
+    /// all namespaces so it compacts the keys outside the namespace as well
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure
+    #[inline]
+    pub async fn compact(&self, request: CompactionRequest) -> Result<CompactionResponse> {
+        self.inner.compact(request).await
+    }
+}
+
+/// Client for Watch operations confined to a namespace.
+///
+/// The watched range is prefixed by the namespace in the same way as `NamespaceKvClient`
+/// does, and the namespace is stripped from the keys of the events.
+#[derive(Clone, Debug)]
+pub struct NamespaceWatchClient {
+    /// The inner watch client
+    inner: WatchClient,
+    /// The namespace prefixing the keys
+    namespace: Vec<u8>,
+}
+
+impl NamespaceWatchClient {
+    /// Creates a new `NamespaceWatchClient`
+    #[inline]
+    #[must_use]
+    pub fn new(client: WatchClient, namespace: impl Into<Vec<u8>>) -> Self {
+        Self {
+            inner: client,
+            namespace: namespace.into(),
+        }
+    }
+
+    /// Watches for events happening or that have happened in the namespace
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the RPC client fails to send request
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the RPC server doesn't return a create watch response
+    #[inline]
+    pub async fn watch(
+        &mut self,
+        mut request: WatchRequest,
+    ) -> Result<(Watcher, NamespaceWatchStreaming)> {
+        let inner = &mut request.inner;
+        prefix_range(&self.namespace, &mut inner.key, &mut inner.range_end);
+        let (watcher, stream) = self.inner.watch(request).await?;
+        Ok((
+            watcher,
+            NamespaceWatchStreaming {
+                inner: stream,
+                namespace: self.namespace.clone(),
+            },
+        ))
+    }
+}
+
+/// Watch response stream of a namespace
+#[derive(Debug)]
+pub struct NamespaceWatchStreaming {
+    /// The inner watch response stream
+    inner: WatchStreaming,
+    /// The namespace prefixing the keys
+    namespace: Vec<u8>,
+}
+
+impl NamespaceWatchStreaming {
+    /// Get the next watch response, whose keys are stripped of the namespace
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the watch stream returns an error
+    #[inline]
+    pub async fn message(&mut self) -> Result<Option<WatchResponse>> {
+        let Some(mut resp) = self.inner.message().await? else {
+            return Ok(None);
+        };
+        for event in &mut resp.events {
+            for kv in event.kv.iter_mut().chain(event.prev_kv.iter_mut()) {
+                strip_kv(&self.namespace, kv);
+            }
+        }
+        Ok(Some(resp))
+    }
+}
+
+/// Prefix a key with the namespace
+fn prefix_key(namespace: &[u8], key: &[u8]) -> Vec<u8> {
+    [namespace, key].concat()
+}
+
+/// Prefix the range [key, `range_end`) with the namespace, a `range_end` of `\0` is
+/// replaced by the end of the namespace
+fn prefix_range(namespace: &[u8], key: &mut Vec<u8>, range_end: &mut Vec<u8>) {
+    *key = prefix_key(namespace, key);
+    if range_end.as_slice() == [0] {
+        *range_end = KeyRange::get_prefix(namespace);
+    } else if !range_end.is_empty() {
+        *range_end = prefix_key(namespace, range_end);
+    }
+}
+
+/// Prefix the keys of a txn with the namespace
+fn prefix_txn(namespace: &[u8], txn: &mut xlineapi::TxnRequest) {
+    for cmp in &mut txn.compare {
+        prefix_range(namespace, &mut cmp.key, &mut cmp.range_end);
+    }
+    for op in txn.success.iter_mut().chain(txn.failure.iter_mut()) {
+        match op.request {
+            Some(Request::RequestRange(ref mut req)) => {
+                prefix_range(namespace, &mut req.key, &mut req.range_end);
+            }
+            Some(Request::RequestPut(ref mut req)) => req.key = prefix_key(namespace, &req.key),
+            Some(Request::RequestDeleteRange(ref mut req)) => {
+                prefix_range(namespace, &mut req.key, &mut req.range_end);
+            }
+            Some(Request::RequestTxn(ref mut req)) => prefix_txn(namespace, req),
+            None => {}
+        }
+    }
+}
+
+/// Strip the namespace from the key of a `KeyValue`
+fn strip_kv(namespace: &[u8], kv: &mut KeyValue) {
+    if let Some(key) = kv.key.strip_prefix(namespace) {
+        kv.key = key.to_vec();
+    }
+}
+
+/// Strip the namespace from the keys of a `RangeResponse`
+fn strip_range(namespace: &[u8], resp: &mut RangeResponse) {
+    for kv in &mut resp.kvs {
+        strip_kv(namespace, kv);
+    }
+}
+
+/// Strip the namespace from the keys of a `DeleteRangeResponse`
+fn strip_delete(namespace: &[u8], resp: &mut DeleteRangeResponse) {
+    for kv in &mut resp.prev_kvs {
+        strip_kv(namespace, kv);
+    }
+}
+
+/// Strip the namespace from the keys of a `TxnResponse`
+fn strip_txn(namespace: &[u8], resp: &mut TxnResponse) {
+    for op in &mut resp.responses {
+        match op.response {
+            Some(Response::ResponseRange(ref mut res)) => strip_range(namespace, res),
+            Some(Response::ResponsePut(ref mut res)) => {
+                if let Some(ref mut prev_kv) = res.prev_kv {
+                    strip_kv(namespace, prev_kv);
+                }
+            }
+            Some(Response::ResponseDeleteRange(ref mut res)) => strip_delete(namespace, res),
+            Some(Response::ResponseTxn(ref mut res)) => strip_txn(namespace, res),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn range_should_be_scoped_within_the_namespace() {
+        let range = |key: &[u8], range_end: &[u8]| {
+            let (mut key, mut range_end) = (key.to_vec(), range_end.to_vec());
+            prefix_range(b"ns/", &mut key, &mut range_end);
+            (key, range_end)
+        };
+        assert_eq!(range(b"a", b""), (b"ns/a".to_vec(), vec![]));
+        // the prefix range of "a"
+        assert_eq!(range(b"a", b"b"), (b"ns/a".to_vec(), b"ns/b".to_vec()));
+        // the open range from "a" and the prefix range of the empty key
+        assert_eq!(range(b"a", &[0]), (b"ns/a".to_vec(), b"ns0".to_vec()));
+        assert_eq!(range(&[0], &[0]), (b"ns/\0".to_vec(), b"ns0".to_vec()));
+    }
+}
@@ -0,0 +1,128 @@
+use xlineapi::{RangeResponse, SAVEPOINT_PREFIX};
+
+use super::{KvClient, LeaseClient};
+use crate::{
+    error::Result,
+    types::{
+        kv::{PutRequest, RangeRequest},
+        lease::{LeaseGrantRequest, LeaseKeepAliveRequest, LeaseRevokeRequest},
+    },
+};
+
+/// The value of a savepoint key
+const SAVEPOINT_VALUE: &str = "savepoint";
+
+/// A read session whose reads all see the snapshot of the store at the revision it
+/// is opened, i.e. repeatable reads, however the store changes during the session.
+///
+/// The revision is pinned against compaction by a savepoint, which is a key under
+/// `SAVEPOINT_PREFIX` attached to a lease of the session, compactions beyond the
+/// oldest savepoint are rejected by the server. The savepoint is removed when the
+/// session is released, or when its lease expires if the session is abandoned, so
+/// a session longer than the ttl should be kept alive by `keep_alive`.
+#[derive(Clone, Debug)]
+pub struct ReadSnapshot {
+    /// The kv client
+    kv: KvClient,
+    /// The lease client
+    lease: LeaseClient,
+    /// The lease of the savepoint
+    lease_id: i64,
+    /// The revision of the snapshot
+    revision: i64,
+}
+
+impl ReadSnapshot {
+    /// Opens a read snapshot at the current revision, its savepoint is kept for `ttl`
+    /// seconds unless the session is kept alive
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{
+    ///     clients::ReadSnapshot, types::kv::RangeRequest, Client, ClientOptions,
+    /// };
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default()).await?;
+    ///     let snapshot = ReadSnapshot::open(client.kv_client(), client.lease_client(), 60).await?;
+    ///
+    ///     // both reads see the store at the same revision
+    ///     let users = snapshot.range(RangeRequest::new("users/").with_prefix()).await?;
+    ///     let orders = snapshot.range(RangeRequest::new("orders/").with_prefix()).await?;
+    ///
+    ///     snapshot.release().await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn open(kv: KvClient, lease: LeaseClient, ttl: i64) -> Result<Self> {
+        let lease_id = lease.grant(LeaseGrantRequest::new(ttl)).await?.id;
+        let key = [SAVEPOINT_PREFIX, format!("{lease_id:016x}").as_bytes()].concat();
+        let resp = kv
+            .put(PutRequest::new(key, SAVEPOINT_VALUE).with_lease(lease_id))
+            .await?;
+        let revision = resp.header.map_or(0, |header| header.revision);
+        Ok(Self {
+            kv,
+            lease,
+            lease_id,
+            revision,
+        })
+    }
+
+    /// The revision of the snapshot
+    #[inline]
+    #[must_use]
+    pub fn revision(&self) -> i64 {
+        self.revision
+    }
+
+    /// Gets a range of keys from the snapshot, the revision of the request is
+    /// replaced by the one of the snapshot
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner CURP client encountered a propose failure
+    #[inline]
+    pub async fn range(&self, request: RangeRequest) -> Result<RangeResponse> {
+        self.kv.range(request.with_revision(self.revision)).await
+    }
+
+    /// Keeps the savepoint of the snapshot alive for another ttl
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner RPC client encountered a propose failure
+    #[inline]
+    pub async fn keep_alive(&mut self) -> Result<()> {
+        let (_keeper, _stream) = self
+            .lease
+            .keep_alive(LeaseKeepAliveRequest::new(self.lease_id))
+            .await?;
+        Ok(())
+    }
+
+    /// Releases the snapshot, its revision may be compacted afterwards
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the inner RPC client encountered a propose failure
+    #[inline]
+    pub async fn release(mut self) -> Result<()> {
+        let _resp = self
+            .lease
+            .revoke(LeaseRevokeRequest::new(self.lease_id))
+            .await?;
+        Ok(())
+    }
+}
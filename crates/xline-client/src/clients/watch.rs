@@ -1,8 +1,10 @@
 use std::{fmt::Debug, sync::Arc};
 
-use futures::channel::mpsc::channel;
-use tonic::transport::Channel;
-use xlineapi::{self, RequestUnion};
+use futures::channel::mpsc::{channel, UnboundedReceiver};
+use tonic::{transport::Channel, Streaming};
+use xlineapi::{self, RequestUnion, WatchResponse};
+
+use super::WatchMultiplexer;
 
 use crate::{
     error::{Result, XlineClientError},
@@ -117,4 +119,24 @@ impl WatchClient {
             WatchStreaming::new(response_stream, request_sender),
         ))
     }
+
+    /// Creates a `WatchMultiplexer` that runs many logical watches over a single
+    /// watch stream of this client.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if it is not called within a tokio runtime
+    #[inline]
+    #[must_use]
+    pub fn multiplexer(&self) -> WatchMultiplexer {
+        WatchMultiplexer::new(self.clone())
+    }
+
+    /// Opens a watch stream with the given request stream
+    pub(crate) async fn open_stream(
+        &mut self,
+        requests: UnboundedReceiver<xlineapi::WatchRequest>,
+    ) -> Result<Streaming<WatchResponse>> {
+        Ok(self.inner.watch(requests).await?.into_inner())
+    }
 }
@@ -0,0 +1,336 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use clippy_utilities::OverflowArithmetic;
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use tokio::sync::{mpsc, oneshot};
+use xlineapi::{RequestUnion, WatchCancelRequest, WatchCreateRequest, WatchResponse};
+
+use super::WatchClient;
+use crate::{
+    error::{Result, XlineClientError},
+    types::watch::WatchRequest,
+};
+
+/// Interval to wait before re-establishing a broken watch stream
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Commands sent from the handles to the multiplexer task
+#[derive(Debug)]
+enum MuxCommand {
+    /// Create a logical watch
+    Create {
+        /// The create request with the assigned watch id
+        request: WatchCreateRequest,
+        /// Sender of the responses of the logical watch
+        responses: mpsc::UnboundedSender<WatchResponse>,
+        /// Sender of the creation result
+        created: oneshot::Sender<Result<()>>,
+    },
+    /// Cancel a logical watch
+    Cancel(i64),
+}
+
+/// A client side multiplexer that runs many logical watches over a single watch stream.
+///
+/// Responses are routed to the logical watches by watch id. When the underlying stream
+/// is broken, it is re-established transparently and every logical watch is resumed from
+/// the revision after the last one it has observed.
+#[derive(Clone, Debug)]
+pub struct WatchMultiplexer {
+    /// Sender of commands to the multiplexer task
+    cmd_tx: mpsc::UnboundedSender<MuxCommand>,
+    /// Generator of the watch ids, the ids are assigned by the client so that the
+    /// logical watches can be recreated with the same ids after reconnecting
+    next_id: Arc<AtomicI64>,
+}
+
+impl WatchMultiplexer {
+    /// Creates a new `WatchMultiplexer` and spawns its background task
+    pub(crate) fn new(client: WatchClient) -> Self {
+        let (cmd_tx, cmd_rx) = mpsc::unbounded_channel();
+        let task = MuxTask {
+            client,
+            cmd_rx,
+            watches: HashMap::new(),
+        };
+        let _ignore = tokio::spawn(task.run());
+        Self {
+            cmd_tx,
+            next_id: Arc::new(AtomicI64::new(1)),
+        }
+    }
+
+    /// Creates a logical watch, it returns after the server has confirmed the creation.
+    ///
+    /// The watch id of the request is overridden by the multiplexer.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the multiplexer has exited or the server
+    /// refuses to create the watch
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use xline_client::{
+    ///     types::{kv::PutRequest, watch::WatchRequest},
+    ///     Client, ClientOptions,
+    /// };
+    /// use anyhow::Result;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> Result<()> {
+    ///     let curp_members = ["10.0.0.1:2379", "10.0.0.2:2379", "10.0.0.3:2379"];
+    ///
+    ///     let client = Client::connect(curp_members, ClientOptions::default()).await?;
+    ///     let multiplexer = client.watch_client().multiplexer();
+    ///
+    ///     let mut watcher1 = multiplexer.watch(WatchRequest::new("key1")).await?;
+    ///     let mut watcher2 = multiplexer.watch(WatchRequest::new("key2")).await?;
+    ///     client.kv_client().put(PutRequest::new("key1", "value1")).await?;
+    ///
+    ///     let resp = watcher1.message().await.unwrap();
+    ///     println!("watch {} got {} events", resp.watch_id, resp.events.len());
+    ///
+    ///     watcher1.cancel()?;
+    ///     watcher2.cancel()?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    #[inline]
+    pub async fn watch(&self, request: WatchRequest) -> Result<MuxWatcher> {
+        let watch_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut request: WatchCreateRequest = request.into();
+        request.watch_id = watch_id;
+        let (responses_tx, responses_rx) = mpsc::unbounded_channel();
+        let (created_tx, created_rx) = oneshot::channel();
+        send_cmd(
+            &self.cmd_tx,
+            MuxCommand::Create {
+                request,
+                responses: responses_tx,
+                created: created_tx,
+            },
+        )?;
+        created_rx
+            .await
+            .map_err(|_e| XlineClientError::WatchError("watch multiplexer exited".to_owned()))??;
+        Ok(MuxWatcher {
+            watch_id,
+            responses: responses_rx,
+            cmd_tx: self.cmd_tx.clone(),
+        })
+    }
+}
+
+/// A logical watch of a `WatchMultiplexer`, it is canceled when dropped.
+#[derive(Debug)]
+pub struct MuxWatcher {
+    /// Id of the watch
+    watch_id: i64,
+    /// Receiver of the responses of this watch
+    responses: mpsc::UnboundedReceiver<WatchResponse>,
+    /// Sender of commands to the multiplexer task
+    cmd_tx: mpsc::UnboundedSender<MuxCommand>,
+}
+
+impl MuxWatcher {
+    /// The ID of the watch.
+    #[inline]
+    #[must_use]
+    pub const fn watch_id(&self) -> i64 {
+        self.watch_id
+    }
+
+    /// Receives the next response of this watch, returns `None` after the watch is canceled
+    /// and all its responses are received.
+    #[inline]
+    pub async fn message(&mut self) -> Option<WatchResponse> {
+        self.responses.recv().await
+    }
+
+    /// Cancels this watch.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the multiplexer has exited
+    #[inline]
+    pub fn cancel(&mut self) -> Result<()> {
+        send_cmd(&self.cmd_tx, MuxCommand::Cancel(self.watch_id))
+    }
+}
+
+impl Drop for MuxWatcher {
+    #[inline]
+    fn drop(&mut self) {
+        let _ignore = self.cmd_tx.send(MuxCommand::Cancel(self.watch_id));
+    }
+}
+
+/// Send a command to the multiplexer task
+fn send_cmd(cmd_tx: &mpsc::UnboundedSender<MuxCommand>, cmd: MuxCommand) -> Result<()> {
+    cmd_tx
+        .send(cmd)
+        .map_err(|_e| XlineClientError::WatchError("watch multiplexer exited".to_owned()))
+}
+
+/// State of a logical watch
+#[derive(Debug)]
+struct LogicalWatch {
+    /// The create request of the watch
+    request: WatchCreateRequest,
+    /// The revision to resume the watch from, 0 means the current revision
+    next_revision: i64,
+    /// Sender of the responses of the watch
+    responses: mpsc::UnboundedSender<WatchResponse>,
+    /// Sender of the creation result, `None` after the creation is confirmed
+    created: Option<oneshot::Sender<Result<()>>>,
+}
+
+impl LogicalWatch {
+    /// The create request used to (re)create the watch on a stream
+    fn create_request(&self) -> xlineapi::WatchRequest {
+        let mut request = self.request.clone();
+        if self.next_revision != 0 {
+            request.start_revision = self.next_revision;
+        }
+        xlineapi::WatchRequest {
+            request_union: Some(RequestUnion::CreateRequest(request)),
+        }
+    }
+}
+
+/// The background task of a `WatchMultiplexer`, it owns the underlying watch stream
+#[derive(Debug)]
+struct MuxTask {
+    /// The watch client used to open streams
+    client: WatchClient,
+    /// Receiver of commands from the handles
+    cmd_rx: mpsc::UnboundedReceiver<MuxCommand>,
+    /// Active logical watches
+    watches: HashMap<i64, LogicalWatch>,
+}
+
+impl MuxTask {
+    /// Run the task until all handles are dropped
+    async fn run(mut self) {
+        loop {
+            let (req_tx, req_rx) = unbounded();
+            for watch in self.watches.values() {
+                let _ignore = req_tx.unbounded_send(watch.create_request());
+            }
+            if let Ok(mut stream) = self.client.open_stream(req_rx).await {
+                loop {
+                    tokio::select! {
+                        cmd = self.cmd_rx.recv() => {
+                            let Some(cmd) = cmd else {
+                                return;
+                            };
+                            self.handle_command(cmd, &req_tx);
+                        }
+                        resp = stream.message() => {
+                            let Ok(Some(resp)) = resp else {
+                                break;
+                            };
+                            self.handle_response(resp, &req_tx);
+                        }
+                    }
+                }
+            } else if self.cmd_rx.is_closed() {
+                return;
+            }
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        }
+    }
+
+    /// Handle a command from the handles
+    fn handle_command(
+        &mut self,
+        cmd: MuxCommand,
+        req_tx: &UnboundedSender<xlineapi::WatchRequest>,
+    ) {
+        match cmd {
+            MuxCommand::Create {
+                request,
+                responses,
+                created,
+            } => {
+                let watch = LogicalWatch {
+                    next_revision: request.start_revision,
+                    request,
+                    responses,
+                    created: Some(created),
+                };
+                // a failed send means the stream is broken, the watch will be created
+                // after reconnecting
+                let _ignore = req_tx.unbounded_send(watch.create_request());
+                let _prev = self.watches.insert(watch.request.watch_id, watch);
+            }
+            MuxCommand::Cancel(watch_id) => {
+                if self.watches.remove(&watch_id).is_some() {
+                    let _ignore = req_tx.unbounded_send(xlineapi::WatchRequest {
+                        request_union: Some(RequestUnion::CancelRequest(WatchCancelRequest {
+                            watch_id,
+                        })),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Route a response to its logical watch
+    fn handle_response(
+        &mut self,
+        resp: WatchResponse,
+        req_tx: &UnboundedSender<xlineapi::WatchRequest>,
+    ) {
+        let watch_id = resp.watch_id;
+        let Some(watch) = self.watches.get_mut(&watch_id) else {
+            return;
+        };
+        if resp.created {
+            if let Some(created) = watch.created.take() {
+                if resp.canceled {
+                    let _ignore =
+                        created.send(Err(XlineClientError::WatchError(resp.cancel_reason)));
+                    let _prev = self.watches.remove(&watch_id);
+                    return;
+                }
+                let _ignore = created.send(Ok(()));
+            }
+            if watch.next_revision == 0 {
+                if let Some(ref header) = resp.header {
+                    watch.next_revision = header.revision.overflow_add(1);
+                }
+            }
+            // a resumed watch may be canceled on creation, e.g. it is compacted
+            if !resp.canceled {
+                return;
+            }
+        }
+        if let Some(kv) = resp.events.last().and_then(|event| event.kv.as_ref()) {
+            watch.next_revision = kv.mod_revision.overflow_add(1);
+        } else if let Some(ref header) = resp.header {
+            watch.next_revision = watch.next_revision.max(header.revision.overflow_add(1));
+        }
+        let canceled = resp.canceled;
+        if watch.responses.send(resp).is_err() || canceled {
+            let _prev = self.watches.remove(&watch_id);
+            if !canceled {
+                let _ignore = req_tx.unbounded_send(xlineapi::WatchRequest {
+                    request_union: Some(RequestUnion::CancelRequest(WatchCancelRequest {
+                        watch_id,
+                    })),
+                });
+            }
+        }
+    }
+}
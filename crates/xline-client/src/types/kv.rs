@@ -1,6 +1,13 @@
-use xlineapi::command::KeyRange;
+use clippy_utilities::NumericCast;
+use xlineapi::{
+    command::KeyRange,
+    server_op::{
+        AppendOp, ConditionalDeleteOp, IncrementOp, MovePrefixOp, ReserveRevisionsOp, ServerOp,
+        SwapOp,
+    },
+};
 pub use xlineapi::{
-    CompactionResponse, CompareResult, CompareTarget, DeleteRangeResponse, PutResponse,
+    CompactionResponse, CompareResult, CompareTarget, DeleteRangeResponse, KeyValue, PutResponse,
     RangeResponse, Response, ResponseOp, SortOrder, SortTarget, TargetUnion, TxnResponse,
 };
 
@@ -8,7 +15,7 @@ pub use xlineapi::{
 #[derive(Debug, PartialEq)]
 pub struct PutRequest {
     /// Inner request
-    inner: xlineapi::PutRequest,
+    pub(crate) inner: xlineapi::PutRequest,
 }
 
 impl PutRequest {
@@ -117,7 +124,7 @@ impl From<PutRequest> for xlineapi::PutRequest {
 #[derive(Debug, PartialEq)]
 pub struct RangeRequest {
     /// Inner request
-    inner: xlineapi::RangeRequest,
+    pub(crate) inner: xlineapi::RangeRequest,
 }
 
 impl RangeRequest {
@@ -371,7 +378,7 @@ impl From<RangeRequest> for xlineapi::RangeRequest {
 #[derive(Debug, PartialEq)]
 pub struct DeleteRangeRequest {
     /// Inner request
-    inner: xlineapi::DeleteRangeRequest,
+    pub(crate) inner: xlineapi::DeleteRangeRequest,
 }
 
 impl DeleteRangeRequest {
@@ -711,6 +718,556 @@ impl From<TxnRequest> for xlineapi::TxnRequest {
     }
 }
 
+/// A swap of a `CompareAndSwapRequest`
+#[derive(Debug, Clone, PartialEq)]
+struct KeySwap {
+    /// The key to swap
+    key: Vec<u8>,
+    /// The expected value of the key, `None` means the key should not exist
+    expected: Option<Vec<u8>>,
+    /// The new value of the key
+    value: Vec<u8>,
+}
+
+/// Multi-key compare-and-swap. All keys are swapped to their new values in a
+/// single transaction only if every key currently holds its expected value,
+/// otherwise none of them is changed.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CompareAndSwapRequest {
+    /// The swaps in the order they are added
+    swaps: Vec<KeySwap>,
+}
+
+impl CompareAndSwapRequest {
+    /// Creates a new empty `CompareAndSwapRequest`
+    #[inline]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { swaps: Vec::new() }
+    }
+
+    /// Swaps `key` to `value` if it currently holds `expected`
+    #[inline]
+    #[must_use]
+    pub fn with_swap(
+        mut self,
+        key: impl Into<Vec<u8>>,
+        expected: impl Into<Vec<u8>>,
+        value: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.swaps.push(KeySwap {
+            key: key.into(),
+            expected: Some(expected.into()),
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Creates `key` with `value` if it does not exist yet
+    #[inline]
+    #[must_use]
+    pub fn with_create(mut self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
+        self.swaps.push(KeySwap {
+            key: key.into(),
+            expected: None,
+            value: value.into(),
+        });
+        self
+    }
+
+    /// Get the keys to swap in the order they are added
+    #[inline]
+    #[must_use]
+    pub fn keys(&self) -> Vec<&[u8]> {
+        self.swaps.iter().map(|swap| swap.key.as_slice()).collect()
+    }
+}
+
+impl From<CompareAndSwapRequest> for TxnRequest {
+    #[inline]
+    fn from(req: CompareAndSwapRequest) -> Self {
+        let mut compares = Vec::with_capacity(req.swaps.len());
+        let mut puts = Vec::with_capacity(req.swaps.len());
+        let mut ranges = Vec::with_capacity(req.swaps.len());
+        for swap in req.swaps {
+            compares.push(match swap.expected {
+                Some(expected) => Compare::value(swap.key.clone(), CompareResult::Equal, expected),
+                None => Compare::version(swap.key.clone(), CompareResult::Equal, 0),
+            });
+            ranges.push(TxnOp::range(RangeRequest::new(swap.key.clone())));
+            puts.push(TxnOp::put(PutRequest::new(swap.key, swap.value)));
+        }
+        TxnRequest::new()
+            .when(compares)
+            .and_then(puts)
+            .or_else(ranges)
+    }
+}
+
+/// Response type of a multi-key compare-and-swap
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompareAndSwapResponse {
+    /// The revision of the store when the request was applied
+    pub revision: i64,
+    /// Whether all keys are swapped
+    pub succeeded: bool,
+    /// The current key-values when the swap failed, in the order of the swaps,
+    /// `None` means the key does not exist. It is empty if the swap succeeded.
+    pub current: Vec<(Vec<u8>, Option<KeyValue>)>,
+}
+
+/// Encoding of a counter value
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CounterEncoding {
+    /// Decimal string, e.g. `"42"`
+    #[default]
+    Decimal,
+    /// 8 bytes little-endian signed integer
+    LittleEndian,
+    /// 8 bytes big-endian signed integer
+    BigEndian,
+}
+
+impl From<CounterEncoding> for xlineapi::server_op::CounterEncoding {
+    #[inline]
+    fn from(encoding: CounterEncoding) -> Self {
+        match encoding {
+            CounterEncoding::Decimal => Self::Decimal,
+            CounterEncoding::LittleEndian => Self::LittleEndian,
+            CounterEncoding::BigEndian => Self::BigEndian,
+        }
+    }
+}
+
+/// Request type for atomically incrementing a counter
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncrementRequest {
+    /// The key of the counter
+    key: Vec<u8>,
+    /// The delta to add, it can be negative
+    delta: i64,
+    /// The encoding of the counter value
+    encoding: CounterEncoding,
+}
+
+impl IncrementRequest {
+    /// Creates a new `IncrementRequest` which adds `delta` to the decimal counter at `key`
+    #[inline]
+    #[must_use]
+    pub fn new(key: impl Into<Vec<u8>>, delta: i64) -> Self {
+        Self {
+            key: key.into(),
+            delta,
+            encoding: CounterEncoding::default(),
+        }
+    }
+
+    /// Set the encoding of the counter value
+    #[inline]
+    #[must_use]
+    pub fn with_encoding(mut self, encoding: CounterEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Get `key`
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Get `delta`
+    #[inline]
+    #[must_use]
+    pub fn delta(&self) -> i64 {
+        self.delta
+    }
+
+    /// Get `encoding`
+    #[inline]
+    #[must_use]
+    pub fn encoding(&self) -> CounterEncoding {
+        self.encoding
+    }
+}
+
+impl From<IncrementRequest> for ServerOp {
+    #[inline]
+    fn from(req: IncrementRequest) -> Self {
+        ServerOp::Increment(IncrementOp::new(req.key, req.delta, req.encoding.into()))
+    }
+}
+
+/// Response type of an increment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IncrementResponse {
+    /// The revision of the store when the increment was applied
+    pub revision: i64,
+    /// The value of the counter after the increment
+    pub value: i64,
+}
+
+/// The default maximum size of a value built by appends, 1.5 MiB which is the default
+/// request size limit of etcd. It's also the limit of the server, which a request can
+/// only lower.
+pub const DEFAULT_MAX_APPENDED_VALUE_SIZE: usize = 1_572_864;
+
+/// Request type for atomically appending bytes to the value of a key
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppendRequest {
+    /// The key to append to
+    key: Vec<u8>,
+    /// The bytes to append
+    bytes: Vec<u8>,
+    /// The maximum size of the value after the append
+    max_value_size: usize,
+}
+
+impl AppendRequest {
+    /// Creates a new `AppendRequest` which appends `bytes` to the value of `key`
+    #[inline]
+    #[must_use]
+    pub fn new(key: impl Into<Vec<u8>>, bytes: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: key.into(),
+            bytes: bytes.into(),
+            max_value_size: DEFAULT_MAX_APPENDED_VALUE_SIZE,
+        }
+    }
+
+    /// Set the maximum size of the value after the append, a size above the limit of
+    /// the server is capped by it
+    #[inline]
+    #[must_use]
+    pub fn with_max_value_size(mut self, max_value_size: usize) -> Self {
+        self.max_value_size = max_value_size;
+        self
+    }
+
+    /// Get `key`
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Get `bytes`
+    #[inline]
+    #[must_use]
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Get `max_value_size`
+    #[inline]
+    #[must_use]
+    pub fn max_value_size(&self) -> usize {
+        self.max_value_size
+    }
+}
+
+impl From<AppendRequest> for ServerOp {
+    #[inline]
+    fn from(req: AppendRequest) -> Self {
+        ServerOp::Append(AppendOp::new(
+            req.key,
+            req.bytes,
+            req.max_value_size.numeric_cast(),
+        ))
+    }
+}
+
+/// Response type of an append
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppendResponse {
+    /// The revision of the store when the append was applied
+    pub revision: i64,
+    /// The length of the value after the append
+    pub length: usize,
+}
+
+/// Request type for atomically swapping the values of two keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapRequest {
+    /// The first key
+    first: Vec<u8>,
+    /// The second key
+    second: Vec<u8>,
+    /// Whether the leases of the keys are swapped with the values
+    swap_leases: bool,
+    /// Whether a missing key is swapped as an empty value instead of failing the swap
+    create_missing: bool,
+}
+
+impl SwapRequest {
+    /// Creates a new `SwapRequest` which swaps the values of `first` and `second`,
+    /// each key keeps its own lease by default
+    #[inline]
+    #[must_use]
+    pub fn new(first: impl Into<Vec<u8>>, second: impl Into<Vec<u8>>) -> Self {
+        Self {
+            first: first.into(),
+            second: second.into(),
+            swap_leases: false,
+            create_missing: false,
+        }
+    }
+
+    /// Swap the leases of the keys with their values
+    #[inline]
+    #[must_use]
+    pub fn with_swap_leases(mut self, swap_leases: bool) -> Self {
+        self.swap_leases = swap_leases;
+        self
+    }
+
+    /// Swap a missing key as an empty value, i.e. the missing key is created with the
+    /// value of the other one, which is then emptied. Otherwise the swap fails if
+    /// either key is missing.
+    #[inline]
+    #[must_use]
+    pub fn with_create_missing(mut self, create_missing: bool) -> Self {
+        self.create_missing = create_missing;
+        self
+    }
+
+    /// Get `first`
+    #[inline]
+    #[must_use]
+    pub fn first(&self) -> &[u8] {
+        &self.first
+    }
+
+    /// Get `second`
+    #[inline]
+    #[must_use]
+    pub fn second(&self) -> &[u8] {
+        &self.second
+    }
+
+    /// Get `swap_leases`
+    #[inline]
+    #[must_use]
+    pub fn swap_leases(&self) -> bool {
+        self.swap_leases
+    }
+
+    /// Get `create_missing`
+    #[inline]
+    #[must_use]
+    pub fn create_missing(&self) -> bool {
+        self.create_missing
+    }
+}
+
+impl From<SwapRequest> for ServerOp {
+    #[inline]
+    fn from(req: SwapRequest) -> Self {
+        ServerOp::Swap(SwapOp::new(
+            req.first,
+            req.second,
+            req.swap_leases,
+            req.create_missing,
+        ))
+    }
+}
+
+/// Response type of a swap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapResponse {
+    /// The revision of the store when the swap was applied, both keys are modified
+    /// at this revision
+    pub revision: i64,
+}
+
+/// Request type for moving all the keys under a prefix to another prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovePrefixRequest {
+    /// The source prefix
+    source: Vec<u8>,
+    /// The destination prefix
+    destination: Vec<u8>,
+}
+
+impl MovePrefixRequest {
+    /// Creates a new `MovePrefixRequest` which moves the keys under `source` to
+    /// `destination`, e.g. `source/a` is moved to `destination/a` when the prefixes are
+    /// `source/` and `destination/`
+    #[inline]
+    #[must_use]
+    pub fn new(source: impl Into<Vec<u8>>, destination: impl Into<Vec<u8>>) -> Self {
+        Self {
+            source: source.into(),
+            destination: destination.into(),
+        }
+    }
+
+    /// Get `source`
+    #[inline]
+    #[must_use]
+    pub fn source(&self) -> &[u8] {
+        &self.source
+    }
+
+    /// Get `destination`
+    #[inline]
+    #[must_use]
+    pub fn destination(&self) -> &[u8] {
+        &self.destination
+    }
+}
+
+impl From<MovePrefixRequest> for ServerOp {
+    #[inline]
+    fn from(req: MovePrefixRequest) -> Self {
+        ServerOp::MovePrefix(MovePrefixOp::new(req.source, req.destination))
+    }
+}
+
+/// Response type of a prefix move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovePrefixResponse {
+    /// The revision of the store when the move was applied, all the moved keys are
+    /// put and deleted at this revision
+    pub revision: i64,
+    /// The number of the moved keys
+    pub moved: u64,
+}
+
+/// Request type for deleting a range only if there are fewer keys in it than a threshold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConditionalDeleteRequest {
+    /// The first key of the range
+    key: Vec<u8>,
+    /// The end of the range
+    range_end: Vec<u8>,
+    /// The range is deleted only if there are fewer keys in it than the threshold
+    threshold: u64,
+}
+
+impl ConditionalDeleteRequest {
+    /// Creates a new `ConditionalDeleteRequest` which deletes `key` only if there are
+    /// fewer keys than `threshold`, the threshold must be positive
+    #[inline]
+    #[must_use]
+    pub fn new(key: impl Into<Vec<u8>>, threshold: u64) -> Self {
+        Self {
+            key: key.into(),
+            range_end: Vec::new(),
+            threshold,
+        }
+    }
+
+    /// If set, Xline will count and delete all keys with the matching prefix
+    #[inline]
+    #[must_use]
+    pub fn with_prefix(mut self) -> Self {
+        if self.key.is_empty() {
+            self.key = vec![0];
+            self.range_end = vec![0];
+        } else {
+            self.range_end = KeyRange::get_prefix(&self.key);
+        }
+        self
+    }
+
+    /// `range_end` is the key following the last key of the range \[key, `range_end`),
+    /// the same as the one of a `DeleteRangeRequest`
+    #[inline]
+    #[must_use]
+    pub fn with_range_end(mut self, range_end: impl Into<Vec<u8>>) -> Self {
+        self.range_end = range_end.into();
+        self
+    }
+
+    /// Get `key`
+    #[inline]
+    #[must_use]
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Get `range_end`
+    #[inline]
+    #[must_use]
+    pub fn range_end(&self) -> &[u8] {
+        &self.range_end
+    }
+
+    /// Get `threshold`
+    #[inline]
+    #[must_use]
+    pub fn threshold(&self) -> u64 {
+        self.threshold
+    }
+}
+
+impl From<ConditionalDeleteRequest> for ServerOp {
+    #[inline]
+    fn from(req: ConditionalDeleteRequest) -> Self {
+        ServerOp::ConditionalDelete(ConditionalDeleteOp::new(
+            req.key,
+            req.range_end,
+            req.threshold,
+        ))
+    }
+}
+
+/// Response type of a conditional delete
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConditionalDeleteResponse {
+    /// The revision of the store when the delete was applied, the keys are counted and
+    /// deleted at this revision
+    pub revision: i64,
+    /// The number of the keys in the range when they were counted
+    pub count: u64,
+    /// Whether the range is deleted, false if the count is not less than the threshold
+    pub deleted: bool,
+}
+
+/// Request type for reserving a block of revisions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReserveRevisionsRequest {
+    /// The number of revisions to reserve
+    count: u64,
+}
+
+impl ReserveRevisionsRequest {
+    /// Creates a new `ReserveRevisionsRequest` which reserves `count` revisions, the
+    /// count must be in `1..=MAX_RESERVED_REVISIONS`
+    #[inline]
+    #[must_use]
+    pub fn new(count: u64) -> Self {
+        Self { count }
+    }
+
+    /// Get `count`
+    #[inline]
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl From<ReserveRevisionsRequest> for ServerOp {
+    #[inline]
+    fn from(req: ReserveRevisionsRequest) -> Self {
+        ServerOp::ReserveRevisions(ReserveRevisionsOp::new(req.count))
+    }
+}
+
+/// Response type of a revision reservation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReserveRevisionsResponse {
+    /// The first reserved revision
+    pub first: i64,
+    /// The last reserved revision, which is the revision of the store after the
+    /// reservation
+    pub last: i64,
+}
+
 /// Compaction Request compacts the key-value store up to a given revision.
 /// All keys with revisions less than the given revision will be compacted.
 /// The compaction process will remove all historical versions of these keys, except for the most recent one.
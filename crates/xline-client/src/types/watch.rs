@@ -105,7 +105,7 @@ impl Watcher {
 #[derive(Clone, Debug, PartialEq)]
 pub struct WatchRequest {
     /// Inner watch create request
-    inner: xlineapi::WatchCreateRequest,
+    pub(crate) inner: xlineapi::WatchCreateRequest,
 }
 
 impl WatchRequest {
@@ -246,6 +246,8 @@ pub struct WatchStreaming {
     inner: tonic::Streaming<WatchResponse>,
     /// A sender of WatchResponse, used to keep response stream alive
     _sender: Sender<xlineapi::WatchRequest>,
+    /// The fragments of an oversized event received so far
+    fragment: Option<WatchResponse>,
 }
 
 impl WatchStreaming {
@@ -259,6 +261,35 @@ impl WatchStreaming {
         Self {
             inner,
             _sender: sender,
+            fragment: None,
+        }
+    }
+
+    /// Get the next watch response, the fragments of an event exceeding the message
+    /// size limit of the server are reassembled into one response. A response whose
+    /// event is truncated by the server, see `WatchResponse::is_truncated`, is
+    /// returned as it is, the value of its event is to be fetched by a range.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the watch stream returns an error
+    #[inline]
+    pub async fn message(&mut self) -> std::result::Result<Option<WatchResponse>, tonic::Status> {
+        loop {
+            let Some(resp) = self.inner.message().await? else {
+                return Ok(None);
+            };
+            let resp = match self.fragment.take() {
+                Some(mut fragment) => {
+                    fragment.merge_fragment(resp);
+                    fragment
+                }
+                None => resp,
+            };
+            if !resp.is_fragment() {
+                return Ok(Some(resp));
+            }
+            self.fragment = Some(resp);
         }
     }
 }
@@ -1,12 +1,19 @@
 //! The following tests are originally from `etcd-client`
 use test_macros::abort_on_panic;
 use xline_client::{
-    error::Result,
-    types::kv::{
-        CompactionRequest, Compare, CompareResult, DeleteRangeRequest, PutRequest, RangeRequest,
-        TxnOp, TxnRequest,
+    clients::{NamespaceKvClient, ReadSnapshot},
+    error::{Result, XlineClientError},
+    types::{
+        kv::{
+            AppendRequest, CompactionRequest, Compare, CompareAndSwapRequest, CompareResult,
+            CounterEncoding, DeleteRangeRequest, IncrementRequest, PutRequest, RangeRequest,
+            Response, SwapRequest, TxnOp, TxnRequest,
+        },
+        lease::LeaseGrantRequest,
+        watch::WatchRequest,
     },
 };
+use xlineapi::execute_error::ExecuteError;
 
 use super::common::get_cluster_client;
 
@@ -234,6 +241,167 @@ async fn txn_should_execute_as_expected() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn compare_and_swap_should_change_nothing_if_any_key_is_stale() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let client = client.kv_client();
+
+    for i in 0..5 {
+        client
+            .put(PutRequest::new(format!("cas{i}"), format!("v{i}")))
+            .await?;
+    }
+    // cas3 is changed by someone else
+    client.put(PutRequest::new("cas3", "stale")).await?;
+
+    let request = (0..5).fold(CompareAndSwapRequest::new(), |req, i| {
+        req.with_swap(format!("cas{i}"), format!("v{i}"), format!("new{i}"))
+    });
+    let resp = client.compare_and_swap(request.clone()).await?;
+    assert!(!resp.succeeded);
+    assert_eq!(resp.current.len(), 5);
+    for (i, (key, kv)) in resp.current.iter().enumerate() {
+        assert_eq!(key, format!("cas{i}").as_bytes());
+        let expected = if i == 3 {
+            "stale".to_owned()
+        } else {
+            format!("v{i}")
+        };
+        assert_eq!(kv.as_ref().unwrap().value, expected.as_bytes());
+    }
+    for i in 0..5 {
+        let resp = client.range(RangeRequest::new(format!("cas{i}"))).await?;
+        assert_ne!(resp.kvs[0].value, format!("new{i}").as_bytes());
+    }
+
+    // succeeds after the stale key is restored
+    client.put(PutRequest::new("cas3", "v3")).await?;
+    let resp = client.compare_and_swap(request).await?;
+    assert!(resp.succeeded);
+    assert!(resp.current.is_empty());
+    for i in 0..5 {
+        let resp = client.range(RangeRequest::new(format!("cas{i}"))).await?;
+        assert_eq!(resp.kvs[0].value, format!("new{i}").as_bytes());
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn compare_and_swap_should_reject_duplicate_keys() {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let client = client.kv_client();
+
+    let request = CompareAndSwapRequest::new()
+        .with_swap("dup", "a", "b")
+        .with_create("dup", "c");
+    assert!(client.compare_and_swap(request).await.is_err());
+    assert!(client
+        .compare_and_swap(CompareAndSwapRequest::new())
+        .await
+        .is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn concurrent_increments_should_not_lose_updates() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let client = client.kv_client();
+
+    let handles: Vec<_> = (1..=20)
+        .map(|delta| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                for _ in 0..5 {
+                    let request = IncrementRequest::new("counter", delta)
+                        .with_encoding(CounterEncoding::BigEndian);
+                    client.increment(request).await?;
+                }
+                Result::Ok(())
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap()?;
+    }
+
+    let resp = client.range(RangeRequest::new("counter")).await?;
+    let value: [u8; 8] = resp.kvs[0].value.as_slice().try_into().unwrap();
+    assert_eq!(i64::from_be_bytes(value), 5 * (1..=20).sum::<i64>());
+
+    // decimal counters are created at 0
+    let resp = client
+        .increment(IncrementRequest::new("decimal", -3))
+        .await?;
+    assert_eq!(resp.value, -3);
+    let resp = client.range(RangeRequest::new("decimal")).await?;
+    assert_eq!(resp.kvs[0].value, b"-3");
+
+    // a value which is not a counter is rejected
+    client.put(PutRequest::new("text", "abc")).await?;
+    assert!(client
+        .increment(IncrementRequest::new("text", 1))
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn concurrent_appends_should_not_be_lost() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let client = client.kv_client();
+
+    let handles: Vec<_> = (0..10)
+        .map(|appender| {
+            let client = client.clone();
+            tokio::spawn(async move {
+                for i in 0..5 {
+                    let entry = format!("{appender}-{i};");
+                    client.append(AppendRequest::new("log", entry)).await?;
+                }
+                Result::Ok(())
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.await.unwrap()?;
+    }
+
+    let resp = client.range(RangeRequest::new("log")).await?;
+    let value = String::from_utf8(resp.kvs[0].value.clone()).unwrap();
+    let mut entries: Vec<_> = value.split_terminator(';').collect();
+    assert_eq!(entries.len(), 50);
+    entries.sort_unstable();
+    entries.dedup();
+    assert_eq!(entries.len(), 50);
+    // the entries of an appender keep their order
+    for appender in 0..10 {
+        let prefix = format!("{appender}-");
+        let own: Vec<_> = value
+            .split_terminator(';')
+            .filter(|entry| entry.starts_with(&prefix))
+            .collect();
+        let expected: Vec<_> = (0..5).map(|i| format!("{appender}-{i}")).collect();
+        assert_eq!(own, expected);
+    }
+
+    // the value size limit is enforced
+    let resp = client
+        .append(AppendRequest::new("small", "abc").with_max_value_size(4))
+        .await?;
+    assert_eq!(resp.length, 3);
+    assert!(client
+        .append(AppendRequest::new("small", "de").with_max_value_size(4))
+        .await
+        .is_err());
+
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
 async fn compact_should_remove_previous_revision() -> Result<()> {
@@ -270,3 +438,193 @@ async fn compact_should_remove_previous_revision() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn namespace_should_confine_operations() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let raw = client.kv_client();
+    let ns = NamespaceKvClient::new(client.kv_client(), "ns/");
+
+    raw.put(PutRequest::new("outside", "0")).await?;
+    // a key just after the namespace
+    raw.put(PutRequest::new("ns0", "0")).await?;
+    ns.put(PutRequest::new("a", "1")).await?;
+    let resp = ns.put(PutRequest::new("a", "2").with_prev_kv(true)).await?;
+    assert_eq!(resp.prev_kv.unwrap().key, b"a");
+    ns.put(PutRequest::new("b", "3")).await?;
+
+    // the raw keys on the server include the prefix
+    let resp = raw.range(RangeRequest::new("ns/").with_prefix()).await?;
+    let keys: Vec<_> = resp.kvs.iter().map(|kv| kv.key.as_slice()).collect();
+    assert_eq!(keys, [b"ns/a", b"ns/b"]);
+
+    let resp = ns.range(RangeRequest::new("a")).await?;
+    assert_eq!(resp.kvs[0].key, b"a");
+    assert_eq!(resp.kvs[0].value, b"2");
+    // the open ranges stop at the end of the namespace
+    for request in [
+        RangeRequest::new("").with_prefix(),
+        RangeRequest::new("").with_from_key(),
+        RangeRequest::new("a").with_from_key(),
+    ] {
+        let resp = ns.range(request).await?;
+        let keys: Vec<_> = resp.kvs.iter().map(|kv| kv.key.as_slice()).collect();
+        assert_eq!(keys, [b"a", b"b"]);
+    }
+    assert!(ns.range(RangeRequest::new("outside")).await?.kvs.is_empty());
+
+    let txn = TxnRequest::new()
+        .when(&[Compare::value("a", CompareResult::Equal, "2")][..])
+        .and_then(&[TxnOp::range(RangeRequest::new("b"))][..]);
+    let resp = ns.txn(txn).await?;
+    assert!(resp.succeeded);
+    let Some(Response::ResponseRange(ref range)) = resp.responses[0].response else {
+        panic!("unexpected response");
+    };
+    assert_eq!(range.kvs[0].key, b"b");
+
+    let resp = ns
+        .delete(DeleteRangeRequest::new("").with_prefix().with_prev_kv(true))
+        .await?;
+    assert_eq!(resp.deleted, 2);
+    assert_eq!(resp.prev_kvs[0].key, b"a");
+    let resp = raw.range(RangeRequest::new("").with_prefix()).await?;
+    let keys: Vec<_> = resp.kvs.iter().map(|kv| kv.key.as_slice()).collect();
+    assert_eq!(keys, [b"ns0".as_slice(), b"outside"]);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn read_snapshot_should_see_a_fixed_revision() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let kv = client.kv_client();
+
+    kv.put(PutRequest::new("report/a", "1")).await?;
+    kv.put(PutRequest::new("report/b", "1")).await?;
+    let snapshot = ReadSnapshot::open(client.kv_client(), client.lease_client(), 60).await?;
+
+    kv.put(PutRequest::new("report/a", "2")).await?;
+    kv.put(PutRequest::new("report/c", "2")).await?;
+    kv.delete(DeleteRangeRequest::new("report/b")).await?;
+    for _ in 0..2 {
+        let resp = snapshot
+            .range(RangeRequest::new("report/").with_prefix())
+            .await?;
+        let kvs: Vec<_> = resp
+            .kvs
+            .iter()
+            .map(|kv| (&kv.key[..], &kv.value[..]))
+            .collect();
+        assert_eq!(
+            kvs,
+            [(&b"report/a"[..], &b"1"[..]), (&b"report/b"[..], &b"1"[..])]
+        );
+        assert!(resp
+            .kvs
+            .iter()
+            .all(|kv| kv.mod_revision <= snapshot.revision()));
+    }
+
+    // the snapshot is pinned against compaction until it is released
+    let current = kv
+        .range(RangeRequest::new("report/a"))
+        .await?
+        .header
+        .unwrap()
+        .revision;
+    let err = kv
+        .compact(CompactionRequest::new(current))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        XlineClientError::ExecuteError(ExecuteError::Rejected(_))
+    ));
+    snapshot.release().await?;
+    kv.compact(CompactionRequest::new(current)).await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn swap_should_exchange_values_at_a_single_revision() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let kv = client.kv_client();
+    let mut watch_client = client.watch_client();
+
+    kv.put(PutRequest::new("lb/active", "10.0.0.1")).await?;
+    kv.put(PutRequest::new("lb/standby", "10.0.0.2")).await?;
+    let (_active_watcher, mut active) = watch_client.watch(WatchRequest::new("lb/active")).await?;
+    let (_standby_watcher, mut standby) =
+        watch_client.watch(WatchRequest::new("lb/standby")).await?;
+    let (_pair_watcher, mut pair) = watch_client
+        .watch(WatchRequest::new("lb/").with_prefix())
+        .await?;
+
+    let revision = kv
+        .swap(SwapRequest::new("lb/active", "lb/standby"))
+        .await?
+        .revision;
+
+    for (stream, key, value) in [
+        (&mut active, "lb/active", "10.0.0.2"),
+        (&mut standby, "lb/standby", "10.0.0.1"),
+    ] {
+        let events = stream.message().await?.unwrap().events;
+        assert_eq!(events.len(), 1);
+        let kv = events[0].kv.as_ref().unwrap();
+        assert_eq!(kv.key, key.as_bytes());
+        assert_eq!(kv.value, value.as_bytes());
+        assert_eq!(kv.mod_revision, revision);
+    }
+    // a watcher of both keys receives both changes in one response
+    let events = pair.message().await?.unwrap().events;
+    assert_eq!(events.len(), 2);
+    assert!(events
+        .iter()
+        .all(|event| event.kv.as_ref().unwrap().mod_revision == revision));
+
+    // a missing key fails the swap unless it is created
+    assert!(matches!(
+        kv.swap(SwapRequest::new("lb/active", "lb/missing")).await,
+        Err(XlineClientError::ExecuteError(ExecuteError::KeyNotFound))
+    ));
+    kv.swap(SwapRequest::new("lb/active", "lb/missing").with_create_missing(true))
+        .await?;
+    let resp = kv.range(RangeRequest::new("lb/").with_prefix()).await?;
+    let values: Vec<_> = resp.kvs.iter().map(|kv| kv.value.as_slice()).collect();
+    assert_eq!(values, [&b""[..], &b"10.0.0.2"[..], &b"10.0.0.1"[..]]);
+
+    // the leases are kept by default, or swapped with the values
+    let lease = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    kv.put(PutRequest::new("lb/leased", "10.0.0.3").with_lease(lease))
+        .await?;
+    kv.swap(SwapRequest::new("lb/leased", "lb/standby")).await?;
+    let leased = kv.range(RangeRequest::new("lb/leased")).await?.kvs;
+    assert_eq!(
+        (leased[0].value.as_slice(), leased[0].lease),
+        (&b"10.0.0.1"[..], lease)
+    );
+    kv.swap(SwapRequest::new("lb/leased", "lb/standby").with_swap_leases(true))
+        .await?;
+    let standby = kv.range(RangeRequest::new("lb/standby")).await?.kvs;
+    assert_eq!(
+        (standby[0].value.as_slice(), standby[0].lease),
+        (&b"10.0.0.1"[..], lease)
+    );
+    let leased = kv.range(RangeRequest::new("lb/leased")).await?.kvs;
+    assert_eq!(
+        (leased[0].value.as_slice(), leased[0].lease),
+        (&b"10.0.0.3"[..], 0)
+    );
+
+    Ok(())
+}
@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use xline_client::{
     error::Result,
     types::lease::{
@@ -103,3 +105,90 @@ async fn leases_should_include_granted_in_normal_path() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn grant_batch_should_success_in_normal_path() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let mut client = client.lease_client();
+
+    // the first half with specified ids, the second half with auto ids
+    let requests = (0..1000)
+        .map(|i| {
+            if i < 500 {
+                LeaseGrantRequest::new(60).with_id(1000 + i)
+            } else {
+                LeaseGrantRequest::new(60)
+            }
+        })
+        .collect();
+    let resps = client.grant_batch(requests).await?;
+    assert_eq!(resps.len(), 1000);
+    for (i, resp) in resps.iter().take(500).enumerate() {
+        assert_eq!(resp.id, 1000 + i as i64);
+    }
+    let ids: HashSet<_> = resps.iter().map(|resp| resp.id).collect();
+    assert_eq!(ids.len(), 1000);
+
+    let leases: HashSet<_> = client
+        .leases()
+        .await?
+        .leases
+        .iter()
+        .map(|status| status.id)
+        .collect();
+    assert!(ids.is_subset(&leases));
+
+    for id in [resps[0].id, resps[999].id] {
+        let (mut keeper, mut stream) = client.keep_alive(LeaseKeepAliveRequest::new(id)).await?;
+        keeper.keep_alive()?;
+        let resp = stream.message().await?.unwrap();
+        assert_eq!(resp.id, id);
+        assert_eq!(resp.ttl, 60);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn grant_batch_should_reject_duplicate_ids() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let client = client.lease_client();
+
+    let res = client
+        .grant_batch(vec![
+            LeaseGrantRequest::new(60).with_id(300),
+            LeaseGrantRequest::new(60).with_id(300),
+        ])
+        .await;
+    assert!(res.is_err());
+    assert!(client.leases().await?.leases.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn grant_batch_should_grant_no_lease_if_any_is_rejected() -> Result<()> {
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let client = client.lease_client();
+
+    let _resp = client
+        .grant(LeaseGrantRequest::new(60).with_id(400))
+        .await?;
+    let res = client
+        .grant_batch(vec![
+            LeaseGrantRequest::new(60).with_id(401),
+            LeaseGrantRequest::new(60).with_id(400),
+        ])
+        .await;
+    assert!(res.is_err());
+    let leases: Vec<_> = client
+        .leases()
+        .await?
+        .leases
+        .iter()
+        .map(|status| status.id)
+        .collect();
+    assert_eq!(leases, vec![400]);
+
+    Ok(())
+}
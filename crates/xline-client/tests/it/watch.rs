@@ -1,4 +1,7 @@
 //! The following tests are originally from `etcd-client`
+use std::time::Duration;
+
+use futures::future::join_all;
 use xline_client::{
     error::Result,
     types::{
@@ -58,3 +61,47 @@ async fn watch_stream_should_work_after_watcher_dropped() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn multiplexed_watches_should_only_receive_their_events() -> Result<()> {
+    const WATCH_NUM: usize = 300;
+    let (_cluster, client) = get_cluster_client().await.unwrap();
+    let multiplexer = client.watch_client().multiplexer();
+    let kv_client = client.kv_client();
+
+    let mut watchers = Vec::with_capacity(WATCH_NUM);
+    for i in 0..WATCH_NUM {
+        watchers.push(
+            multiplexer
+                .watch(WatchRequest::new(format!("mux{i}")))
+                .await?,
+        );
+    }
+    for i in 0..WATCH_NUM {
+        kv_client
+            .put(PutRequest::new(format!("mux{i}"), i.to_string()))
+            .await?;
+    }
+
+    for (i, watcher) in watchers.iter_mut().enumerate() {
+        let resp = watcher.message().await.unwrap();
+        assert_eq!(resp.watch_id, watcher.watch_id());
+        assert_eq!(resp.events.len(), 1);
+        let kv = resp.events[0].kv.as_ref().unwrap();
+        assert_eq!(kv.key, format!("mux{i}").into_bytes());
+        assert_eq!(kv.value, i.to_string().into_bytes());
+    }
+
+    let extra = join_all(
+        watchers
+            .iter_mut()
+            .map(|watcher| tokio::time::timeout(Duration::from_millis(500), watcher.message())),
+    )
+    .await;
+    assert!(
+        extra.iter().all(|res| res.is_err()),
+        "no watch should receive events of others"
+    );
+
+    Ok(())
+}
@@ -11,9 +11,10 @@ use tokio::{
 use tonic::transport::ClientTlsConfig;
 use utils::config::{
     default_quota, AuthConfig, ClusterConfig, CompactConfig, EngineConfig, InitialClusterState,
-    LogConfig, MetricsConfig, StorageConfig, TlsConfig, TraceConfig, XlineServerConfig,
+    KvConfig, LogConfig, MaintenancePolicy, MetricsConfig, StorageConfig, TlsConfig, TraceConfig,
+    WatchConfig, XlineServerConfig,
 };
-use xline::server::XlineServer;
+use xline::server::{ArchiveSink, AuthBackend, CommandHook, ValueValidator, XlineServer};
 use xline_client::types::auth::{
     AuthRoleAddRequest, AuthRoleGrantPermissionRequest, AuthUserAddRequest,
     AuthUserGrantRoleRequest, Permission, PermissionType,
@@ -34,6 +35,14 @@ pub struct Cluster {
     servers: Vec<Arc<XlineServer>>,
     /// Client of cluster
     client: Option<Client>,
+    /// Command hooks of members
+    command_hooks: HashMap<usize, Arc<dyn CommandHook>>,
+    /// External auth backends of members and their cache ttls
+    auth_backends: HashMap<usize, (Arc<dyn AuthBackend>, Duration)>,
+    /// Value validators of all the members by key prefixes
+    value_validators: Vec<(Vec<u8>, Arc<dyn ValueValidator>)>,
+    /// The archive sink shared by all the members
+    archive_sink: Option<Arc<dyn ArchiveSink>>,
 }
 
 impl Cluster {
@@ -82,9 +91,37 @@ impl Cluster {
             configs,
             servers: Vec::new(),
             client: None,
+            command_hooks: HashMap::new(),
+            auth_backends: HashMap::new(),
+            value_validators: Vec::new(),
+            archive_sink: None,
         }
     }
 
+    /// Register a command hook on the member at `idx`, it must be called before the
+    /// cluster starts
+    pub fn set_command_hook(&mut self, idx: usize, hook: Arc<dyn CommandHook>) {
+        let _prev = self.command_hooks.insert(idx, hook);
+    }
+
+    /// Register an external auth backend on the member at `idx`, it must be called
+    /// before the cluster starts
+    pub fn set_auth_backend(&mut self, idx: usize, backend: Arc<dyn AuthBackend>, ttl: Duration) {
+        let _prev = self.auth_backends.insert(idx, (backend, ttl));
+    }
+
+    /// Register a validator of the values under `prefix` on every member, it must be
+    /// called before the cluster starts
+    pub fn add_value_validator(&mut self, prefix: &str, validator: Arc<dyn ValueValidator>) {
+        self.value_validators.push((prefix.into(), validator));
+    }
+
+    /// Register an archive sink shared by every member, it must be called before the
+    /// cluster starts
+    pub fn set_archive_sink(&mut self, sink: Arc<dyn ArchiveSink>) {
+        self.archive_sink = Some(sink);
+    }
+
     /// Start `Cluster`
     pub async fn start(&mut self) {
         let mut futs = Vec::new();
@@ -108,17 +145,30 @@ impl Cluster {
                 InitialClusterState::New,
             );
 
-            let server = Arc::new(
-                XlineServer::new(
-                    config.cluster().clone(),
-                    config.storage().clone(),
-                    *config.compact(),
-                    config.auth().clone(),
-                    config.tls().clone(),
-                )
-                .await
-                .unwrap(),
-            );
+            let mut server = XlineServer::new(
+                config.cluster().clone(),
+                config.storage().clone(),
+                *config.compact(),
+                config.auth().clone(),
+                config.tls().clone(),
+                *config.watch(),
+                config.kv().clone(),
+            )
+            .await
+            .unwrap();
+            if let Some(hook) = self.command_hooks.get(&i) {
+                server = server.with_command_hook(Arc::clone(hook));
+            }
+            if let Some(&(ref backend, ttl)) = self.auth_backends.get(&i) {
+                server = server.with_auth_backend(Arc::clone(backend), ttl);
+            }
+            for &(ref prefix, ref validator) in &self.value_validators {
+                server = server.with_value_validator(prefix.clone(), Arc::clone(validator));
+            }
+            if let Some(ref sink) = self.archive_sink {
+                server = server.with_archive_sink(Arc::clone(sink));
+            }
+            let server = Arc::new(server);
             self.servers.push(Arc::clone(&server));
 
             futs.push(async move {
@@ -135,6 +185,11 @@ impl Cluster {
         time::sleep(Duration::from_millis(300)).await;
     }
 
+    /// Stop the member at `idx`, it is no longer reachable by the others
+    pub async fn stop_node(&self, idx: usize) {
+        self.servers[idx].stop().await;
+    }
+
     pub async fn run_node(&mut self, xline_listener: TcpListener, curp_listener: TcpListener) {
         let config = XlineServerConfig::default();
         self.run_node_with_config(xline_listener, curp_listener, config)
@@ -182,6 +237,8 @@ impl Cluster {
             *config.compact(),
             config.auth().clone(),
             config.tls().clone(),
+            *config.watch(),
+            config.kv().clone(),
         )
         .await
         .unwrap();
@@ -244,14 +301,28 @@ impl Cluster {
         quota: u64,
     ) -> XlineServerConfig {
         let cluster = ClusterConfig::default();
-        let storage = StorageConfig::new(EngineConfig::RocksDB(path), quota);
+        let storage = StorageConfig::new(
+            EngineConfig::RocksDB(path),
+            quota,
+            MaintenancePolicy::default(),
+            Vec::new(),
+            false,
+            Vec::new(),
+            None,
+            None,
+            None,
+        );
         let log = LogConfig::default();
         let trace = TraceConfig::default();
         let auth = AuthConfig::default();
         let compact = CompactConfig::default();
         let tls = TlsConfig::default();
         let metrics = MetricsConfig::default();
-        XlineServerConfig::new(cluster, storage, log, trace, auth, compact, tls, metrics)
+        let watch = WatchConfig::default();
+        let kv = KvConfig::default();
+        XlineServerConfig::new(
+            cluster, storage, log, trace, auth, compact, tls, metrics, watch, kv,
+        )
     }
 
     pub fn default_rocks_config_with_path(path: PathBuf) -> XlineServerConfig {
@@ -290,6 +361,10 @@ impl Cluster {
             *old_cluster.client_config(),
             *old_cluster.server_timeout(),
             initial_cluster_state,
+            *old_cluster.message_size(),
+            old_cluster.tags().clone(),
+            old_cluster.read_listen_urls().clone(),
+            old_cluster.write_listen_urls().clone(),
         );
         XlineServerConfig::new(
             new_cluster,
@@ -300,6 +375,8 @@ impl Cluster {
             *base_config.compact(),
             base_config.tls().clone(),
             base_config.metrics().clone(),
+            *base_config.watch(),
+            base_config.kv().clone(),
         )
     }
 }
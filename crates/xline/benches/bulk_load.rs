@@ -0,0 +1,95 @@
+#![cfg(bench)]
+#![feature(test)]
+
+extern crate test;
+
+use std::{iter, time::Duration};
+
+use test::Bencher;
+use tokio::runtime::Runtime;
+use utils::config::{
+    default_leaderless_read_timeout, default_range_stream_batch_size, AuthConfig, ClusterConfig,
+    CompactConfig, KeyCharset, KeyValueEncoding, KvConfig, LeaderlessReads, LogConfig,
+    MetricsConfig, RangeResultOverflow, SnapshotInstallReads, StorageConfig, TlsConfig,
+    TraceConfig, WatchConfig, XlineServerConfig,
+};
+use xline_test_utils::{
+    types::kv::{PutRequest, TxnOp, TxnRequest},
+    Cluster,
+};
+
+/// The number of puts of a txn
+const TXN_PUTS: usize = 1000;
+
+fn config(bulk_load_threshold: usize) -> XlineServerConfig {
+    XlineServerConfig::new(
+        ClusterConfig::default(),
+        StorageConfig::default(),
+        LogConfig::default(),
+        TraceConfig::default(),
+        AuthConfig::default(),
+        CompactConfig::default(),
+        TlsConfig::default(),
+        MetricsConfig::default(),
+        WatchConfig::default(),
+        KvConfig::new(
+            false,
+            false,
+            0,
+            0,
+            false,
+            Duration::ZERO,
+            SnapshotInstallReads::default(),
+            KeyValueEncoding::default(),
+            0,
+            LeaderlessReads::default(),
+            default_leaderless_read_timeout(),
+            Vec::new(),
+            Vec::new(),
+            Vec::new(),
+            false,
+            bulk_load_threshold,
+            false,
+            Duration::ZERO,
+            0,
+            RangeResultOverflow::default(),
+            KeyCharset::default(),
+            Vec::new(),
+            0,
+            Vec::new(),
+            default_range_stream_batch_size(),
+        ),
+    )
+}
+
+fn bench_txn_puts(bulk_load_threshold: usize, bench: &mut Bencher) {
+    let rt = Runtime::new().unwrap();
+    let mut cluster = rt.block_on(async {
+        let configs = iter::repeat_with(|| config(bulk_load_threshold))
+            .take(3)
+            .collect();
+        let mut cluster = Cluster::new_with_configs(configs).await;
+        cluster.start().await;
+        cluster
+    });
+    let client = rt.block_on(cluster.client()).kv_client();
+    let mut round = 0;
+    bench.iter(|| {
+        round += 1;
+        let puts: Vec<_> = (0..TXN_PUTS)
+            .map(|i| TxnOp::put(PutRequest::new(format!("key{round:06}{i:04}"), "value")))
+            .collect();
+        rt.block_on(client.txn(TxnRequest::new().and_then(puts)))
+            .unwrap();
+    });
+}
+
+#[bench]
+fn bench_txn_puts_per_key(bench: &mut Bencher) {
+    bench_txn_puts(0, bench);
+}
+
+#[bench]
+fn bench_txn_puts_bulk_load(bench: &mut Bencher) {
+    bench_txn_puts(1, bench);
+}
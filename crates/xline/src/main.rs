@@ -165,9 +165,11 @@ async fn main() -> Result<()> {
     let server = XlineServer::new(
         cluster_config.clone(),
         config.storage().clone(),
-        *config.compact(),
+        config.compact().clone(),
         config.auth().clone(),
         config.tls().clone(),
+        *config.watch(),
+        config.kv().clone(),
     )
     .await?;
     debug!("{:?}", server);
@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use clippy_utilities::NumericCast;
 use opentelemetry::{
     metrics::{Counter, MetricsError},
@@ -6,6 +8,8 @@ use opentelemetry::{
 use tracing::error;
 use utils::define_metrics;
 
+use crate::storage::prefix_stats::PrefixStats;
+
 define_metrics! {
     "xline",
     slow_read_indexes_total: Counter<u64> = meter()
@@ -19,12 +23,16 @@ define_metrics! {
     lease_expired_total: Counter<u64> = meter()
         .u64_counter("lease_expired")
         .with_description("The total number of expired leases.")
+        .init(),
+    throttled_proposals_total: Counter<u64> = meter()
+        .u64_counter("throttled_proposals")
+        .with_description("The total number of proposals throttled since too many are in flight.")
         .init()
 }
 
 impl Metrics {
     /// Register metrics
-    pub(super) fn register_callback() -> Result<(), MetricsError> {
+    pub(super) fn register_callback(prefix_stats: Arc<PrefixStats>) -> Result<(), MetricsError> {
         let meter = meter();
         let (fd_used, fd_limit, current_version, current_rust_version) = (
             meter
@@ -90,6 +98,28 @@ impl Metrics {
             },
         )?;
 
+        let (prefix_keys, prefix_value_bytes) = (
+            meter
+                .u64_observable_gauge("prefix_keys")
+                .with_description("The number of keys under a tracked prefix.")
+                .init(),
+            meter
+                .u64_observable_gauge("prefix_value_bytes")
+                .with_description("The total bytes of the values under a tracked prefix.")
+                .init(),
+        );
+
+        _ = meter.register_callback(
+            &[prefix_keys.as_any(), prefix_value_bytes.as_any()],
+            move |observer| {
+                for (prefix, total) in prefix_stats.totals() {
+                    let labels = [KeyValue::new("prefix", prefix)];
+                    observer.observe_u64(&prefix_keys, total.keys, &labels);
+                    observer.observe_u64(&prefix_value_bytes, total.value_bytes, &labels);
+                }
+            },
+        )?;
+
         Ok(())
     }
 }
@@ -1,6 +1,9 @@
 use xlineapi::execute_error::ExecuteError;
 
-use crate::rpc::{CompactionRequest, RangeRequest, Request, TxnRequest};
+use crate::{
+    rpc::{CompactionRequest, RangeRequest, Request, TxnRequest},
+    storage::index::CompactProtection,
+};
 
 /// A union of requests that need revision check
 pub(crate) enum RevisionRequest<'a> {
@@ -31,12 +34,27 @@ impl<'a> From<&'a TxnRequest> for RevisionRequest<'a> {
 }
 
 /// Revision check
-pub(crate) trait RevisionCheck {
+pub(crate) trait RevisionCheck: Sized {
     /// check if the request is valid given the compacted and current revision
     fn check_revision(
         self,
         compacted_revision: i64,
         current_revision: i64,
+    ) -> Result<(), ExecuteError> {
+        self.check_revision_with_protection(
+            compacted_revision,
+            current_revision,
+            &CompactProtection::default(),
+        )
+    }
+
+    /// check if the request is valid given the compacted and current revision, the
+    /// ranges of protected keys are readable below the compacted revision
+    fn check_revision_with_protection(
+        self,
+        compacted_revision: i64,
+        current_revision: i64,
+        protection: &CompactProtection,
     ) -> Result<(), ExecuteError>;
 }
 
@@ -44,10 +62,11 @@ impl<'a, T> RevisionCheck for &'a T
 where
     &'a T: Into<RevisionRequest<'a>>,
 {
-    fn check_revision(
+    fn check_revision_with_protection(
         self,
         compacted_revision: i64,
         current_revision: i64,
+        protection: &CompactProtection,
     ) -> Result<(), ExecuteError> {
         debug_assert!(
             compacted_revision <= current_revision,
@@ -71,12 +90,14 @@ where
                 if r.revision > current_revision {
                     Err(ExecuteError::RevisionTooLarge(r.revision, current_revision))
                 } else {
-                    (r.revision >= compacted_revision || r.revision <= 0)
-                        .then_some(())
-                        .ok_or(ExecuteError::RevisionCompacted(
-                            r.revision,
-                            compacted_revision,
-                        ))
+                    (r.revision >= compacted_revision
+                        || r.revision <= 0
+                        || protection.covers(&r.key, &r.range_end))
+                    .then_some(())
+                    .ok_or(ExecuteError::RevisionCompacted(
+                        r.revision,
+                        compacted_revision,
+                    ))
                 }
             }
             RevisionRequest::Txn(r) => {
@@ -84,10 +105,18 @@ where
                     if let Some(ref req) = op.request {
                         match *req {
                             Request::RequestRange(ref req) => {
-                                req.check_revision(compacted_revision, current_revision)?;
+                                req.check_revision_with_protection(
+                                    compacted_revision,
+                                    current_revision,
+                                    protection,
+                                )?;
                             }
                             Request::RequestTxn(ref req) => {
-                                req.check_revision(compacted_revision, current_revision)?;
+                                req.check_revision_with_protection(
+                                    compacted_revision,
+                                    current_revision,
+                                    protection,
+                                )?;
                             }
                             Request::RequestPut(_) | Request::RequestDeleteRange(_) => (),
                         }
@@ -20,6 +20,13 @@ impl RevisionNumberGenerator {
         self.0.fetch_add(1, Ordering::Relaxed).wrapping_add(1)
     }
 
+    /// Take the next `count` revision numbers and get the last one
+    pub(crate) fn next_n(&self, count: i64) -> i64 {
+        self.0
+            .fetch_add(count, Ordering::Relaxed)
+            .wrapping_add(count)
+    }
+
     /// Set the revision number
     pub(crate) fn set(&self, rev: i64) {
         self.0.store(rev, Ordering::Relaxed);
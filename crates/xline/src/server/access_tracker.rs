@@ -0,0 +1,128 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dashmap::DashMap;
+use tonic::metadata::{Ascii, MetadataValue};
+
+use crate::rpc::KeyValue;
+
+/// Metadata key of a range request asking for the last read times of the keys in its
+/// response. Its response carries them in the same metadata, as the unix timestamps
+/// in milliseconds separated by commas in the order of the keys, 0 if a key has not
+/// been read since the node started. Such a request requires the admin permission
+/// and doesn't count as a read of the keys.
+pub(crate) const LAST_ACCESS_KEY: &str = "last-access";
+
+/// The last read times of the keys served by the range requests of this node.
+///
+/// It is best effort: the times are only kept in memory, so they are lost when the
+/// node restarts, and they are not replicated, so every node only knows the reads it
+/// has served. The times of the deleted keys are kept until they are read again.
+#[derive(Debug)]
+pub(crate) struct AccessTracker {
+    /// Whether the reads are tracked
+    enabled: bool,
+    /// The last read times of the keys
+    last_read: DashMap<Vec<u8>, u64>,
+}
+
+impl AccessTracker {
+    /// New `AccessTracker`
+    pub(crate) fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            last_read: DashMap::new(),
+        }
+    }
+
+    /// Record a read of the keys of the kvs
+    pub(crate) fn record(&self, kvs: &[KeyValue]) {
+        if !self.enabled || kvs.is_empty() {
+            return;
+        }
+        let now = now_millis();
+        for kv in kvs {
+            if let Some(mut time) = self.last_read.get_mut(&kv.key) {
+                *time = now;
+            } else {
+                let _prev = self.last_read.insert(kv.key.clone(), now);
+            }
+        }
+    }
+
+    /// Get the last read times of the keys of the kvs as the metadata value
+    pub(crate) fn last_read(
+        &self,
+        kvs: &[KeyValue],
+    ) -> Result<MetadataValue<Ascii>, tonic::Status> {
+        if !self.enabled {
+            return Err(tonic::Status::failed_precondition(
+                "the last access times of keys are not tracked",
+            ));
+        }
+        let times: Vec<_> = kvs
+            .iter()
+            .map(|kv| {
+                self.last_read
+                    .get(&kv.key)
+                    .map_or(0, |time| *time)
+                    .to_string()
+            })
+            .collect();
+        Ok(times
+            .join(",")
+            .parse()
+            .unwrap_or_else(|_e| unreachable!("the times are ascii")))
+    }
+}
+
+/// Get the current unix timestamp in milliseconds
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kv(key: &str) -> KeyValue {
+        KeyValue {
+            key: key.into(),
+            ..Default::default()
+        }
+    }
+
+    fn times(tracker: &AccessTracker, keys: &[&str]) -> Vec<u64> {
+        let kvs: Vec<_> = keys.iter().map(|key| kv(key)).collect();
+        tracker
+            .last_read(&kvs)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split(',')
+            .map(|time| time.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn last_read_times_should_only_be_updated_by_reads() {
+        let tracker = AccessTracker::new(true);
+        tracker.record(&[kv("a"), kv("b")]);
+        let first = times(&tracker, &["a", "b", "c"]);
+        assert!(first[0] > 0);
+        assert_eq!(first[0], first[1]);
+        assert_eq!(first[2], 0);
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        tracker.record(&[kv("a")]);
+        let second = times(&tracker, &["a", "b", "c"]);
+        assert!(second[0] > first[0]);
+        assert_eq!(second[1], first[1]);
+        assert_eq!(second[2], 0);
+
+        let disabled = AccessTracker::new(false);
+        disabled.record(&[kv("a")]);
+        assert!(disabled.last_read(&[kv("a")]).is_err());
+    }
+}
@@ -0,0 +1,481 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use clippy_utilities::NumericCast;
+use tonic::{
+    body::BoxBody,
+    codec::ProstCodec,
+    codegen::{http, Body, BoxFuture, Service, StdError},
+    server::{Grpc, NamedService, UnaryService},
+};
+use xlineapi::admin::{
+    AttachedKeysRequest, AttachedKeysResponse, ClearDedupCacheRequest, ClearDedupCacheResponse,
+    DedupCacheRequest, DedupCacheResponse, ExportLeasesRequest, ExportLeasesResponse,
+    ImportLeasesRequest, ImportLeasesResponse, KeyBucket, KeyHistogramRequest,
+    KeyHistogramResponse, LeaseKeys, PasswordHashRoundsCount, PasswordHashRoundsRequest,
+    PasswordHashRoundsResponse, ResetUserStatsRequest, ResetUserStatsResponse,
+    SweepExpiredLeasesRequest, SweepExpiredLeasesResponse, UserRequestStats, UserStatsRequest,
+    UserStatsResponse, WatchEntry, WatchRegistryRequest, WatchRegistryResponse, ADMIN_SERVICE_NAME,
+    ATTACHED_KEYS_PATH, CLEAR_DEDUP_CACHE_PATH, DEDUP_CACHE_PATH, EXPORT_LEASES_PATH,
+    IMPORT_LEASES_PATH, KEY_HISTOGRAM_PATH, PASSWORD_HASH_ROUNDS_PATH, RESET_USER_STATS_PATH,
+    SWEEP_EXPIRED_LEASES_PATH, USER_STATS_PATH, WATCH_REGISTRY_PATH,
+};
+
+use super::{
+    lease_server::LeaseServer, maintenance::MaintenanceServer, user_stats::RequestCounters,
+};
+use crate::storage::storage_api::StorageApi;
+
+/// The max number of leases of a page of the attached keys
+const MAX_ATTACHED_KEYS_PAGE: usize = 1000;
+
+/// The max number of buckets of a key histogram
+const MAX_HISTOGRAM_BUCKETS: usize = 256;
+
+/// The max number of watches of a page of the watch registry
+const MAX_WATCH_REGISTRY_PAGE: usize = 1000;
+
+/// The max number of users of a page of the user statistics
+const MAX_USER_STATS_PAGE: usize = 1000;
+
+/// A unary method of the admin service served by an async handler
+struct Unary<F>(F);
+
+impl<Req, Res, F, Fut> UnaryService<Req> for Unary<F>
+where
+    F: FnMut(tonic::Request<Req>) -> Fut,
+    Fut: Future<Output = Result<Res, tonic::Status>> + Send + 'static,
+    Res: Send + 'static,
+{
+    type Response = Res;
+    type Future = BoxFuture<tonic::Response<Res>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<Req>) -> Self::Future {
+        let handled = (self.0)(request);
+        Box::pin(async move { handled.await.map(tonic::Response::new) })
+    }
+}
+
+/// An opaque token of the next page of the attached keys, it pins the listing to the
+/// revision of its first page
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct AttachedKeysToken {
+    /// The revision which the listing is pinned to
+    revision: i64,
+    /// The lease id to start the next page from
+    next_lease_id: i64,
+}
+
+impl AttachedKeysToken {
+    /// Encode the token
+    fn encode(self) -> String {
+        format!("{}.{}", self.revision, self.next_lease_id)
+    }
+
+    /// Decode a token
+    fn decode(token: &str) -> Option<Self> {
+        let (revision, next_lease_id) = token.split_once('.')?;
+        Some(Self {
+            revision: revision.parse().ok().filter(|rev| *rev > 0)?,
+            next_lease_id: next_lease_id.parse().ok()?,
+        })
+    }
+}
+
+/// The request statistics of a user in a response
+fn user_request_stats(user: String, counters: RequestCounters) -> UserRequestStats {
+    UserRequestStats {
+        user,
+        requests: counters.requests,
+        bytes_read: counters.bytes_read,
+        bytes_written: counters.bytes_written,
+        errors: counters.errors,
+    }
+}
+
+/// A grpc service of the admin rpcs, served at `xlinepb.Admin` with the messages of
+/// `xlineapi::admin`. Every rpc is only allowed to the root user when auth is enabled.
+pub(crate) struct AdminServer<S>
+where
+    S: StorageApi,
+{
+    /// The maintenance server serving the admin reads of the stores
+    maintenance_server: Arc<MaintenanceServer<S>>,
+    /// The lease server serving the admin rpcs of the leases
+    lease_server: Arc<LeaseServer<S>>,
+    /// The max size of a decoded request
+    max_decoding_message_size: Option<usize>,
+    /// The max size of an encoded response
+    max_encoding_message_size: Option<usize>,
+}
+
+impl<S> Clone for AdminServer<S>
+where
+    S: StorageApi,
+{
+    fn clone(&self) -> Self {
+        Self {
+            maintenance_server: Arc::clone(&self.maintenance_server),
+            lease_server: Arc::clone(&self.lease_server),
+            max_decoding_message_size: self.max_decoding_message_size,
+            max_encoding_message_size: self.max_encoding_message_size,
+        }
+    }
+}
+
+impl<S> AdminServer<S>
+where
+    S: StorageApi,
+{
+    /// New `AdminServer`
+    pub(crate) fn new(
+        maintenance_server: Arc<MaintenanceServer<S>>,
+        lease_server: Arc<LeaseServer<S>>,
+    ) -> Self {
+        Self {
+            maintenance_server,
+            lease_server,
+            max_decoding_message_size: None,
+            max_encoding_message_size: None,
+        }
+    }
+
+    /// Limit the max size of a decoded request
+    #[must_use]
+    pub(crate) fn max_decoding_message_size(mut self, limit: usize) -> Self {
+        self.max_decoding_message_size = Some(limit);
+        self
+    }
+
+    /// Limit the max size of an encoded response
+    #[must_use]
+    pub(crate) fn max_encoding_message_size(mut self, limit: usize) -> Self {
+        self.max_encoding_message_size = Some(limit);
+        self
+    }
+
+    /// The grpc server of a method with the configured message size limits
+    fn grpc<T, U>(&self) -> Grpc<ProstCodec<T, U>>
+    where
+        T: prost::Message + Send + 'static,
+        U: prost::Message + Default + Send + 'static,
+    {
+        Grpc::new(ProstCodec::default()).apply_max_message_size_config(
+            self.max_decoding_message_size,
+            self.max_encoding_message_size,
+        )
+    }
+
+    /// List a page of the keys attached to any lease, the first page is read from the
+    /// current revision and the following ones from the revision pinned by the token
+    async fn attached_keys(
+        self,
+        request: tonic::Request<AttachedKeysRequest>,
+    ) -> Result<AttachedKeysResponse, tonic::Status> {
+        let req = request.get_ref();
+        let token = if req.page_token.is_empty() {
+            None
+        } else {
+            let token = AttachedKeysToken::decode(&req.page_token)
+                .ok_or_else(|| tonic::Status::invalid_argument("invalid page token"))?;
+            Some(token)
+        };
+        let limit = match req.limit.numeric_cast::<usize>() {
+            0 => MAX_ATTACHED_KEYS_PAGE,
+            limit => limit.min(MAX_ATTACHED_KEYS_PAGE),
+        };
+        let page = self.maintenance_server.attached_keys(
+            &request,
+            token.map_or(0, |token| token.revision),
+            token.map_or(i64::MIN, |token| token.next_lease_id),
+            limit,
+        )?;
+        let next_page_token = page
+            .next_lease_id
+            .map(|next_lease_id| {
+                AttachedKeysToken {
+                    revision: page.revision,
+                    next_lease_id,
+                }
+                .encode()
+            })
+            .unwrap_or_default();
+        Ok(AttachedKeysResponse {
+            revision: page.revision,
+            leases: page
+                .leases
+                .into_iter()
+                .map(|(id, keys)| LeaseKeys { id, keys })
+                .collect(),
+            next_page_token,
+        })
+    }
+
+    /// Inspect the dedup cache of this node
+    async fn dedup_cache(
+        self,
+        request: tonic::Request<DedupCacheRequest>,
+    ) -> Result<DedupCacheResponse, tonic::Status> {
+        let len = self.maintenance_server.dedup_cache_len(&request)?;
+        Ok(DedupCacheResponse {
+            len: len.numeric_cast(),
+        })
+    }
+
+    /// Clear the dedup cache of this node
+    async fn clear_dedup_cache(
+        self,
+        request: tonic::Request<ClearDedupCacheRequest>,
+    ) -> Result<ClearDedupCacheResponse, tonic::Status> {
+        let removed = self.maintenance_server.clear_dedup_cache(&request)?;
+        Ok(ClearDedupCacheResponse {
+            removed: removed.numeric_cast(),
+        })
+    }
+
+    /// Estimate the key histogram of a range from the index of this node
+    async fn key_histogram(
+        self,
+        request: tonic::Request<KeyHistogramRequest>,
+    ) -> Result<KeyHistogramResponse, tonic::Status> {
+        let req = request.get_ref();
+        let buckets = req
+            .buckets
+            .numeric_cast::<usize>()
+            .min(MAX_HISTOGRAM_BUCKETS);
+        let histogram =
+            self.maintenance_server
+                .key_histogram(&request, &req.key, &req.range_end, buckets)?;
+        Ok(KeyHistogramResponse {
+            buckets: histogram
+                .buckets
+                .into_iter()
+                .map(|bucket| KeyBucket {
+                    start: bucket.start,
+                    end: bucket.end,
+                    count: bucket.count,
+                })
+                .collect(),
+            next_key: histogram.next_key.unwrap_or_default(),
+        })
+    }
+
+    /// Count the users by the rounds of their password hashes
+    async fn password_hash_rounds(
+        self,
+        request: tonic::Request<PasswordHashRoundsRequest>,
+    ) -> Result<PasswordHashRoundsResponse, tonic::Status> {
+        let report = self.maintenance_server.password_hash_rounds(&request)?;
+        Ok(PasswordHashRoundsResponse {
+            counts: report
+                .into_iter()
+                .map(|(rounds, users)| PasswordHashRoundsCount {
+                    rounds,
+                    users: users.numeric_cast(),
+                })
+                .collect(),
+        })
+    }
+
+    /// List a page of the active watches of this node
+    async fn watch_registry(
+        self,
+        request: tonic::Request<WatchRegistryRequest>,
+    ) -> Result<WatchRegistryResponse, tonic::Status> {
+        let req = request.get_ref();
+        let limit = match req.limit.numeric_cast::<usize>() {
+            0 => MAX_WATCH_REGISTRY_PAGE,
+            limit => limit.min(MAX_WATCH_REGISTRY_PAGE),
+        };
+        let page = self
+            .maintenance_server
+            .watch_registry(&request, req.start_watch_id, limit)?;
+        Ok(WatchRegistryResponse {
+            watches: page
+                .watches
+                .into_iter()
+                .map(|info| WatchEntry {
+                    watch_id: info.watch_id,
+                    key: info.key,
+                    range_end: info.range_end,
+                    start_revision: info.start_revision,
+                    delivered_revision: info.delivered_revision,
+                    filters: info.filters,
+                    owner: info.owner,
+                    victim: info.victim,
+                    compacted: info.compacted,
+                })
+                .collect(),
+            next_watch_id: page.next_watch_id.unwrap_or_default(),
+        })
+    }
+
+    /// List a page of the request statistics of the users of this node
+    async fn user_stats(
+        self,
+        request: tonic::Request<UserStatsRequest>,
+    ) -> Result<UserStatsResponse, tonic::Status> {
+        let req = request.get_ref();
+        let limit = match req.limit.numeric_cast::<usize>() {
+            0 => MAX_USER_STATS_PAGE,
+            limit => limit.min(MAX_USER_STATS_PAGE),
+        };
+        let (users, next_user) =
+            self.maintenance_server
+                .user_stats(&request, &req.start_user, limit)?;
+        Ok(UserStatsResponse {
+            users: users
+                .into_iter()
+                .map(|(user, counters)| user_request_stats(user, counters))
+                .collect(),
+            next_user: next_user.unwrap_or_default(),
+        })
+    }
+
+    /// Reset the request statistics of a user of this node, or of all users
+    async fn reset_user_stats(
+        self,
+        request: tonic::Request<ResetUserStatsRequest>,
+    ) -> Result<ResetUserStatsResponse, tonic::Status> {
+        let req = request.get_ref();
+        let user = (!req.all).then_some(req.user.as_str());
+        let (counters, reset) = self.maintenance_server.reset_user_stats(&request, user)?;
+        Ok(ResetUserStatsResponse {
+            stats: counters.map(|counters| user_request_stats(req.user.clone(), counters)),
+            reset: reset.numeric_cast(),
+        })
+    }
+
+    /// Revoke all the expired leases, it's only served by the leader
+    async fn sweep_expired_leases(
+        self,
+        request: tonic::Request<SweepExpiredLeasesRequest>,
+    ) -> Result<SweepExpiredLeasesResponse, tonic::Status> {
+        let revoked = self.lease_server.sweep_expired_leases(&request).await?;
+        Ok(SweepExpiredLeasesResponse {
+            revoked: revoked.numeric_cast(),
+        })
+    }
+
+    /// Export the full lease state, it's only served by the leader
+    async fn export_leases(
+        self,
+        request: tonic::Request<ExportLeasesRequest>,
+    ) -> Result<ExportLeasesResponse, tonic::Status> {
+        let (revision, leases) = self.lease_server.export_leases(&request)?;
+        Ok(ExportLeasesResponse { revision, leases })
+    }
+
+    /// Import a lease state exported from another cluster
+    async fn import_leases(
+        self,
+        request: tonic::Request<ImportLeasesRequest>,
+    ) -> Result<ImportLeasesResponse, tonic::Status> {
+        let leases = request.get_ref().leases.clone();
+        let (revision, result) = self.lease_server.import_leases(&request, leases).await?;
+        Ok(ImportLeasesResponse {
+            revision,
+            imported: result.imported,
+            revoked: result.revoked,
+        })
+    }
+}
+
+impl<S, B> Service<http::Request<B>> for AdminServer<S>
+where
+    S: StorageApi,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    #[inline]
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let server = self.clone();
+        Box::pin(async move {
+            let response = match req.uri().path() {
+                ATTACHED_KEYS_PATH => {
+                    let handler = Unary(|request| server.clone().attached_keys(request));
+                    server.grpc().unary(handler, req).await
+                }
+                DEDUP_CACHE_PATH => {
+                    let handler = Unary(|request| server.clone().dedup_cache(request));
+                    server.grpc().unary(handler, req).await
+                }
+                CLEAR_DEDUP_CACHE_PATH => {
+                    let handler = Unary(|request| server.clone().clear_dedup_cache(request));
+                    server.grpc().unary(handler, req).await
+                }
+                KEY_HISTOGRAM_PATH => {
+                    let handler = Unary(|request| server.clone().key_histogram(request));
+                    server.grpc().unary(handler, req).await
+                }
+                PASSWORD_HASH_ROUNDS_PATH => {
+                    let handler = Unary(|request| server.clone().password_hash_rounds(request));
+                    server.grpc().unary(handler, req).await
+                }
+                WATCH_REGISTRY_PATH => {
+                    let handler = Unary(|request| server.clone().watch_registry(request));
+                    server.grpc().unary(handler, req).await
+                }
+                USER_STATS_PATH => {
+                    let handler = Unary(|request| server.clone().user_stats(request));
+                    server.grpc().unary(handler, req).await
+                }
+                RESET_USER_STATS_PATH => {
+                    let handler = Unary(|request| server.clone().reset_user_stats(request));
+                    server.grpc().unary(handler, req).await
+                }
+                SWEEP_EXPIRED_LEASES_PATH => {
+                    let handler = Unary(|request| server.clone().sweep_expired_leases(request));
+                    server.grpc().unary(handler, req).await
+                }
+                EXPORT_LEASES_PATH => {
+                    let handler = Unary(|request| server.clone().export_leases(request));
+                    server.grpc().unary(handler, req).await
+                }
+                IMPORT_LEASES_PATH => {
+                    let handler = Unary(|request| server.clone().import_leases(request));
+                    server.grpc().unary(handler, req).await
+                }
+                path => tonic::Status::unimplemented(format!("{path} is unknown")).to_http(),
+            };
+            Ok(response)
+        })
+    }
+}
+
+impl<S> NamedService for AdminServer<S>
+where
+    S: StorageApi,
+{
+    const NAME: &'static str = ADMIN_SERVICE_NAME;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn attached_keys_token_should_be_encoded_and_decoded() {
+        let token = AttachedKeysToken {
+            revision: 42,
+            next_lease_id: -7,
+        };
+        assert_eq!(AttachedKeysToken::decode(&token.encode()), Some(token));
+        for invalid in ["", "42", "42.", "0.1", "-1.1", "42.x", "x.1"] {
+            assert_eq!(AttachedKeysToken::decode(invalid), None, "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use curp::{server::RawCurp, LogIndex};
+use tokio::time::Instant;
+use tracing::{error, info};
+use utils::task_manager::Listener;
+use xlineapi::command::{Command, CurpClient};
+
+use super::barriers::IndexBarrier;
+use crate::{state::State, storage::storage_api::StorageApi};
+
+/// The minimum interval between two checks of the apply progress
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Source of the apply progress of a node
+pub(crate) trait ApplyProgress: Send + Sync {
+    /// The index of the last entry applied to the state machine
+    fn applied_index(&self) -> LogIndex;
+    /// The index of the last committed entry
+    fn commit_index(&self) -> LogIndex;
+}
+
+/// Apply progress of a curp node, the applied index is the last index triggered
+/// by the command executor rather than the last index handed over to it, so a
+/// wedged executor is observable
+pub(crate) struct CurpApplyProgress<S>
+where
+    S: StorageApi,
+{
+    /// Raw curp
+    raw_curp: Arc<RawCurp<Command, State<S, Arc<CurpClient>>>>,
+    /// Barrier for applied index
+    index_barrier: Arc<IndexBarrier>,
+}
+
+impl<S> CurpApplyProgress<S>
+where
+    S: StorageApi,
+{
+    /// New `CurpApplyProgress`
+    pub(crate) fn new(
+        raw_curp: Arc<RawCurp<Command, State<S, Arc<CurpClient>>>>,
+        index_barrier: Arc<IndexBarrier>,
+    ) -> Self {
+        Self {
+            raw_curp,
+            index_barrier,
+        }
+    }
+}
+
+impl<S> ApplyProgress for CurpApplyProgress<S>
+where
+    S: StorageApi,
+{
+    fn applied_index(&self) -> LogIndex {
+        self.index_barrier.last_index()
+    }
+
+    fn commit_index(&self) -> LogIndex {
+        self.raw_curp.commit_index()
+    }
+}
+
+/// A transition of the apply health
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ApplyHealth {
+    /// The apply has not advanced for longer than the threshold while there are
+    /// committed entries to apply
+    Stalled,
+    /// The apply advances again after a stall
+    Recovered,
+}
+
+/// Detects the stalls of the apply progress
+#[derive(Debug)]
+pub(crate) struct ApplyWatchdog {
+    /// How long the apply may stay behind the commit without advancing
+    threshold: Duration,
+    /// The applied index observed last time
+    last_applied: LogIndex,
+    /// When the apply was last seen advancing or caught up
+    last_progress: Instant,
+    /// Whether a stall has been reported
+    stalled: bool,
+}
+
+impl ApplyWatchdog {
+    /// New `ApplyWatchdog`
+    pub(crate) fn new(threshold: Duration, now: Instant) -> Self {
+        Self {
+            threshold,
+            last_applied: 0,
+            last_progress: now,
+            stalled: false,
+        }
+    }
+
+    /// Observe the apply progress at `now`, return the transition of the apply
+    /// health if there is one
+    pub(crate) fn observe(
+        &mut self,
+        applied: LogIndex,
+        committed: LogIndex,
+        now: Instant,
+    ) -> Option<ApplyHealth> {
+        if applied != self.last_applied || applied >= committed {
+            self.last_applied = applied;
+            self.last_progress = now;
+            if self.stalled {
+                self.stalled = false;
+                info!(applied, committed, "apply has recovered from a stall");
+                return Some(ApplyHealth::Recovered);
+            }
+            return None;
+        }
+        let elapsed = now.saturating_duration_since(self.last_progress);
+        if self.stalled || elapsed < self.threshold {
+            return None;
+        }
+        self.stalled = true;
+        // tokio task dumps need an unstable runtime, so the diagnostic is limited
+        // to the progress of the apply
+        error!(
+            applied,
+            committed,
+            pending = committed.saturating_sub(applied),
+            ?elapsed,
+            threshold = ?self.threshold,
+            "apply has stalled, committed entries are not applied to the state machine"
+        );
+        Some(ApplyHealth::Stalled)
+    }
+}
+
+/// The serving status reported for an apply health transition
+pub(crate) fn serving_status(health: ApplyHealth) -> tonic_health::ServingStatus {
+    match health {
+        ApplyHealth::Stalled => tonic_health::ServingStatus::NotServing,
+        ApplyHealth::Recovered => tonic_health::ServingStatus::Serving,
+    }
+}
+
+/// Run the apply watchdog until shutdown, `on_transition` is called on every
+/// transition of the apply health, e.g. to report the node as not serving while
+/// the apply stalls so that load balancers route away from it
+#[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
+pub(crate) async fn run_apply_watchdog<F, Fut>(
+    progress: Arc<dyn ApplyProgress>,
+    threshold: Duration,
+    mut on_transition: F,
+    shutdown_listener: Listener,
+) where
+    F: FnMut(ApplyHealth) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut watchdog = ApplyWatchdog::new(threshold, Instant::now());
+    let check_interval = threshold
+        .checked_div(4)
+        .unwrap_or_default()
+        .max(MIN_CHECK_INTERVAL);
+    let mut interval = tokio::time::interval(check_interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            () = shutdown_listener.wait() => return,
+        }
+        let applied = progress.applied_index();
+        let committed = progress.commit_index();
+        if let Some(health) = watchdog.observe(applied, committed, Instant::now()) {
+            on_transition(health).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use parking_lot::Mutex;
+    use utils::task_manager::{tasks::TaskName, TaskManager};
+
+    use super::*;
+
+    /// A progress whose apply can be stalled, like a storage that blocks writes
+    #[derive(Debug, Default)]
+    struct StallingProgress {
+        applied: AtomicU64,
+        committed: AtomicU64,
+    }
+
+    impl ApplyProgress for StallingProgress {
+        fn applied_index(&self) -> LogIndex {
+            self.applied.load(Ordering::Relaxed)
+        }
+
+        fn commit_index(&self) -> LogIndex {
+            self.committed.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn watchdog_should_only_fire_on_stalls_with_pending_entries() {
+        let threshold = Duration::from_secs(1);
+        let start = Instant::now();
+        let mut watchdog = ApplyWatchdog::new(threshold, start);
+        // idle, nothing to apply
+        assert_eq!(watchdog.observe(5, 5, start + threshold * 3), None);
+        // behind but within the threshold
+        assert_eq!(watchdog.observe(5, 8, start + threshold * 3), None);
+        assert_eq!(
+            watchdog.observe(5, 8, start + threshold * 4),
+            Some(ApplyHealth::Stalled)
+        );
+        assert_eq!(watchdog.observe(5, 9, start + threshold * 5), None);
+        assert_eq!(
+            watchdog.observe(6, 9, start + threshold * 5),
+            Some(ApplyHealth::Recovered)
+        );
+    }
+
+    #[tokio::test]
+    async fn watchdog_should_flip_health_status_on_stalls() {
+        let progress = Arc::new(StallingProgress::default());
+        progress.applied.store(3, Ordering::Relaxed);
+        progress.committed.store(10, Ordering::Relaxed);
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let task_manager = TaskManager::new();
+        let progress_c: Arc<dyn ApplyProgress> = Arc::clone(&progress) as _;
+        let transitions_c = Arc::clone(&transitions);
+        task_manager.spawn(TaskName::ApplyWatchdog, |n| {
+            run_apply_watchdog(
+                progress_c,
+                Duration::from_millis(100),
+                move |health| {
+                    transitions_c.lock().push(health);
+                    async {}
+                },
+                n,
+            )
+        });
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(*transitions.lock(), vec![ApplyHealth::Stalled]);
+
+        progress.applied.store(10, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            *transitions.lock(),
+            vec![ApplyHealth::Stalled, ApplyHealth::Recovered]
+        );
+        task_manager.shutdown(true).await;
+
+        assert_eq!(
+            serving_status(ApplyHealth::Stalled),
+            tonic_health::ServingStatus::NotServing
+        );
+        assert_eq!(
+            serving_status(ApplyHealth::Recovered),
+            tonic_health::ServingStatus::Serving
+        );
+    }
+}
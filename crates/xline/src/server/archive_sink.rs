@@ -0,0 +1,45 @@
+use std::fmt::Debug;
+
+use xlineapi::command::KeyRange;
+
+use crate::rpc::KeyValue;
+
+/// Metadata key of a range at a compacted revision which asks to be served from the
+/// archive instead of failing with `RevisionCompacted`
+pub(crate) const ARCHIVE_READ_KEY: &str = "archive-read";
+
+/// A sink of the historical versions removed by the compaction, registered by
+/// `XlineServer::with_archive_sink` before the server starts.
+///
+/// Without a sink the compaction discards the versions it removes. With a sink, every
+/// chunk of them is archived before it is removed from the backend, and the archiving
+/// is retried until it succeeds, so no version is lost. The archiving is done by every
+/// node compacting its own backend, so a sink shared by the nodes receives the same
+/// versions from each of them and should treat them as idempotent by their keys and mod
+/// revisions.
+///
+/// A range at a compacted revision carrying the `archive-read` metadata is then served
+/// from the archived versions along with the versions retained by the backend instead of
+/// failing, which is slower than a normal range. Such a range only supports its key
+/// range, limit, `count_only` and `keys_only`.
+#[async_trait::async_trait]
+pub trait ArchiveSink: Send + Sync + Debug {
+    /// Archive the versions removed by a compaction. A deletion is archived as a
+    /// tombstone carrying only the key and the mod revision of the deletion.
+    ///
+    /// # Errors
+    ///
+    /// Return the reason if the versions can't be archived, they are archived again
+    /// later
+    async fn archive(&self, kvs: &[KeyValue]) -> Result<(), String>;
+
+    /// Get the archived versions of the keys in the range whose mod revisions are not
+    /// greater than the revision. Returning more versions is allowed, the visible
+    /// version of each key is picked by the server.
+    ///
+    /// # Errors
+    ///
+    /// Return the reason if the archive can't be read, the range fails with
+    /// `Unavailable`
+    async fn versions(&self, key_range: &KeyRange, revision: i64) -> Result<Vec<KeyValue>, String>;
+}
@@ -0,0 +1,153 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use xlineapi::execute_error::ExecuteError;
+
+/// The maximum time an external auth backend may take to verify a credential
+const AUTH_BACKEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A verifier of the credentials of the authenticating users, registered by
+/// `XlineServer::with_auth_backend` before the server starts.
+///
+/// By default the credentials are verified by the local auth store when the
+/// authentication is applied, as etcd does. A registered backend replaces it: the
+/// credentials are verified out of band by the node serving the authentication, and
+/// the token of a verified user is issued by the node at its auth revision. Only the
+/// verification is delegated, the users, their roles and permissions are still kept
+/// by Xline, so a user must be added to Xline before it can authenticate, usually
+/// without a password.
+#[async_trait::async_trait]
+pub trait AuthBackend: Send + Sync + Debug {
+    /// Verify the password of a user
+    ///
+    /// # Errors
+    ///
+    /// Return the reason if the credential is rejected or can't be verified, the
+    /// authentication fails with `ExecuteError::AuthFailed`
+    async fn authenticate(&self, username: &str, password: &str) -> Result<(), String>;
+}
+
+/// An external auth backend whose successful verifications are cached
+#[derive(Debug)]
+pub(crate) struct CachedAuthBackend {
+    /// The external backend
+    backend: Arc<dyn AuthBackend>,
+    /// How long a successful verification is cached
+    ttl: Duration,
+    /// The digests of the verified passwords and the times they expire, by users
+    verified: Mutex<HashMap<String, (Vec<u8>, Instant)>>,
+}
+
+impl CachedAuthBackend {
+    /// New `CachedAuthBackend`, a zero `ttl` disables the cache
+    pub(crate) fn new(backend: Arc<dyn AuthBackend>, ttl: Duration) -> Self {
+        Self {
+            backend,
+            ttl,
+            verified: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Verify the password of a user by the cache or the backend
+    pub(crate) async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<(), ExecuteError> {
+        let digest = Sha256::digest(password.as_bytes()).to_vec();
+        let now = Instant::now();
+        {
+            let mut verified = self.verified.lock();
+            verified.retain(|_, &mut (_, expire)| expire > now);
+            if verified
+                .get(username)
+                .map_or(false, |&(ref cached, _)| cached == &digest)
+            {
+                return Ok(());
+            }
+        }
+        match tokio::time::timeout(
+            AUTH_BACKEND_TIMEOUT,
+            self.backend.authenticate(username, password),
+        )
+        .await
+        {
+            Ok(Ok(())) => {
+                if let Some(expire) = now.checked_add(self.ttl).filter(|_| !self.ttl.is_zero()) {
+                    let _prev = self
+                        .verified
+                        .lock()
+                        .insert(username.to_owned(), (digest, expire));
+                }
+                Ok(())
+            }
+            Ok(Err(reason)) => {
+                warn!("auth backend rejects user {username}: {reason}");
+                Err(ExecuteError::AuthFailed)
+            }
+            Err(_elapsed) => {
+                warn!("auth backend timed out verifying user {username}");
+                Err(ExecuteError::AuthFailed)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct MockBackend {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl AuthBackend for MockBackend {
+        async fn authenticate(&self, username: &str, password: &str) -> Result<(), String> {
+            let _prev = self.calls.fetch_add(1, Ordering::Relaxed);
+            if (username, password) == ("u", "right") {
+                Ok(())
+            } else {
+                Err("invalid credential".to_owned())
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn verifications_should_be_delegated_and_cached() {
+        let mock = Arc::new(MockBackend::default());
+        let backend = CachedAuthBackend::new(
+            Arc::clone(&mock) as Arc<dyn AuthBackend>,
+            Duration::from_secs(60),
+        );
+        backend.authenticate("u", "right").await.unwrap();
+        backend.authenticate("u", "right").await.unwrap();
+        assert_eq!(mock.calls.load(Ordering::Relaxed), 1);
+
+        for (username, password) in [("u", "wrong"), ("v", "right")] {
+            assert!(matches!(
+                backend.authenticate(username, password).await,
+                Err(ExecuteError::AuthFailed)
+            ));
+        }
+        // the rejections are never cached
+        assert!(backend.authenticate("u", "wrong").await.is_err());
+        assert_eq!(mock.calls.load(Ordering::Relaxed), 4);
+
+        let uncached =
+            CachedAuthBackend::new(Arc::clone(&mock) as Arc<dyn AuthBackend>, Duration::ZERO);
+        uncached.authenticate("u", "right").await.unwrap();
+        uncached.authenticate("u", "right").await.unwrap();
+        assert_eq!(mock.calls.load(Ordering::Relaxed), 6);
+    }
+}
@@ -1,13 +1,15 @@
 use std::sync::Arc;
 
 use tonic::metadata::MetadataMap;
-use tracing::debug;
-use utils::hash_password;
+use tracing::{debug, warn};
+use utils::hash_password_with_rounds;
 use xlineapi::{
     command::{Command, CommandResponse, CurpClient, SyncResponse},
     request_validation::RequestValidator,
+    server_op::{RehashPasswordOp, ServerOp},
 };
 
+use super::auth_backend::CachedAuthBackend;
 use crate::{
     rpc::{
         Auth, AuthDisableRequest, AuthDisableResponse, AuthEnableRequest, AuthEnableResponse,
@@ -20,7 +22,7 @@ use crate::{
         AuthUserGetRequest, AuthUserGetResponse, AuthUserGrantRoleRequest,
         AuthUserGrantRoleResponse, AuthUserListRequest, AuthUserListResponse,
         AuthUserRevokeRoleRequest, AuthUserRevokeRoleResponse, AuthenticateRequest,
-        AuthenticateResponse, RequestWrapper, ResponseWrapper,
+        AuthenticateResponse, RequestWrapper, ResponseWrapper, TxnRequest,
     },
     storage::{storage_api::StorageApi, AuthStore},
 };
@@ -34,6 +36,13 @@ where
     client: Arc<CurpClient>,
     /// Auth Store
     auth_store: Arc<AuthStore<S>>,
+    /// The pbkdf2 rounds of the password hashes made by the server
+    password_hash_rounds: u32,
+    /// Whether the stored password hashes at other rounds are re-hashed on login
+    rehash_passwords_on_login: bool,
+    /// The external verifier of the credentials, `None` if they are verified by the
+    /// local auth store
+    auth_backend: Option<Arc<CachedAuthBackend>>,
 }
 
 /// Get token from metadata
@@ -49,8 +58,76 @@ where
     S: StorageApi,
 {
     /// New `AuthServer`
-    pub(crate) fn new(client: Arc<CurpClient>, auth_store: Arc<AuthStore<S>>) -> Self {
-        Self { client, auth_store }
+    pub(crate) fn new(
+        client: Arc<CurpClient>,
+        auth_store: Arc<AuthStore<S>>,
+        password_hash_rounds: u32,
+        rehash_passwords_on_login: bool,
+        auth_backend: Option<Arc<CachedAuthBackend>>,
+    ) -> Self {
+        Self {
+            client,
+            auth_store,
+            password_hash_rounds,
+            rehash_passwords_on_login,
+            auth_backend,
+        }
+    }
+
+    /// Hash a password at the configured rounds
+    fn hash_password(&self, password: &str) -> Result<String, tonic::Status> {
+        hash_password_with_rounds(password.as_bytes(), self.password_hash_rounds)
+            .map_err(|err| tonic::Status::internal(format!("Failed to hash password: {err}")))
+    }
+
+    /// Re-hash the stored password of an authenticating user at the configured rounds
+    /// if it is hashed at other rounds. It is proposed by the root user as a server op
+    /// which keeps the auth revision, so that no issued token is outdated by it.
+    ///
+    /// Return whether the password is verified by the re-hash, then the token is issued
+    /// without verifying the password again.
+    async fn rehash_password(&self, req: &AuthenticateRequest) -> bool {
+        let Some(previous_hash) = self.auth_store.password_hash(&req.name) else {
+            return false;
+        };
+        let Some(rounds) = utils::password_hash_rounds(&previous_hash) else {
+            return false;
+        };
+        if rounds == self.password_hash_rounds
+            || self
+                .auth_store
+                .check_password(&req.name, &req.password)
+                .is_err()
+        {
+            return false;
+        }
+        if let Err(err) = self.propose_rehash(req, previous_hash).await {
+            warn!("failed to re-hash the password of user {}: {err}", req.name);
+            return true;
+        }
+        debug!(
+            "password of user {} is re-hashed from {rounds} to {} rounds",
+            req.name, self.password_hash_rounds
+        );
+        true
+    }
+
+    /// Propose the re-hash of a verified password
+    async fn propose_rehash(
+        &self,
+        req: &AuthenticateRequest,
+        previous_hash: String,
+    ) -> Result<(), tonic::Status> {
+        let op = RehashPasswordOp::new(
+            req.name.clone(),
+            previous_hash,
+            self.hash_password(&req.password)?,
+        );
+        let request = RequestWrapper::from(TxnRequest::from(ServerOp::RehashPassword(op)));
+        let auth_info = self.auth_store.root_auth_info();
+        let cmd = Command::new_with_auth_info(request.keys(), request, Some(auth_info));
+        let _res = self.client.propose(&cmd, None, false).await??;
+        Ok(())
     }
 
     /// Propose request and get result with fast/slow path
@@ -123,6 +200,16 @@ where
         request: tonic::Request<AuthenticateRequest>,
     ) -> Result<tonic::Response<AuthenticateResponse>, tonic::Status> {
         debug!("Receive AuthenticateRequest {:?}", request);
+        if let Some(ref auth_backend) = self.auth_backend {
+            let req = request.get_ref();
+            auth_backend.authenticate(&req.name, &req.password).await?;
+            let res = self.auth_store.issue_token(&req.name)?;
+            return Ok(tonic::Response::new(res));
+        }
+        if self.rehash_passwords_on_login && self.rehash_password(request.get_ref()).await {
+            let res = self.auth_store.issue_token(&request.get_ref().name)?;
+            return Ok(tonic::Response::new(res));
+        }
         self.handle_req(request, false).await
     }
 
@@ -133,9 +220,7 @@ where
         let user_add_req = request.get_mut();
         debug!("Receive AuthUserAddRequest {}", user_add_req);
         user_add_req.validation()?;
-        let hashed_password = hash_password(user_add_req.password.as_bytes())
-            .map_err(|err| tonic::Status::internal(format!("Failed to hash password: {err}")))?;
-        user_add_req.hashed_password = hashed_password;
+        user_add_req.hashed_password = self.hash_password(&user_add_req.password)?;
         user_add_req.password = String::new();
         self.handle_req(request, false).await
     }
@@ -172,9 +257,8 @@ where
     ) -> Result<tonic::Response<AuthUserChangePasswordResponse>, tonic::Status> {
         debug!("Receive AuthUserChangePasswordRequest {:?}", request);
         let user_change_password_req = request.get_mut();
-        let hashed_password = hash_password(user_change_password_req.password.as_bytes())
-            .map_err(|err| tonic::Status::internal(format!("Failed to hash password: {err}")))?;
-        user_change_password_req.hashed_password = hashed_password;
+        user_change_password_req.hashed_password =
+            self.hash_password(&user_change_password_req.password)?;
         user_change_password_req.password = String::new();
         self.handle_req(request, false).await
     }
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use curp::{
-    cmd::PbCodec,
+    cmd::{Command as CurpCommand, PbCodec},
     rpc::{
         FetchClusterRequest, FetchClusterResponse, FetchReadStateRequest, FetchReadStateResponse,
         LeaseKeepAliveMsg, MoveLeaderRequest, MoveLeaderResponse, ProposeConfChangeRequest,
@@ -10,7 +10,7 @@ use curp::{
     },
 };
 use tracing::debug;
-use xlineapi::command::Command;
+use xlineapi::{command::Command, execute_error::ExecuteError};
 
 use super::xline_server::CurpServer;
 use crate::storage::{storage_api::StorageApi, AuthStore};
@@ -24,6 +24,9 @@ where
     curp_server: CurpServer<S>,
     /// Auth store
     auth_store: Arc<AuthStore<S>>,
+    /// The min number of healthy voters for the leader to accept a write, 0 means
+    /// the writes are accepted regardless of the healthy voters
+    min_healthy_voters: usize,
 }
 
 impl<S> AuthWrapper<S>
@@ -31,14 +34,42 @@ where
     S: StorageApi,
 {
     /// Create a new auth wrapper
-    pub(crate) fn new(curp_server: CurpServer<S>, auth_store: Arc<AuthStore<S>>) -> Self {
+    pub(crate) fn new(
+        curp_server: CurpServer<S>,
+        auth_store: Arc<AuthStore<S>>,
+        min_healthy_voters: usize,
+    ) -> Self {
         Self {
             curp_server,
             auth_store,
+            min_healthy_voters,
         }
     }
 }
 
+/// Check if there are enough healthy voters for the leader to accept a write, this is
+/// the only place the healthy voters are checked
+///
+/// The healthy voters are only known by the leader, `healthy_voters` is `None` on the
+/// other nodes, which accept the write and leave the check to the leader. The leader
+/// rejects the write before it is appended to the log, the rejection is surfaced to
+/// the clients as a `FailedPrecondition` status.
+fn check_healthy_voters(
+    min_healthy_voters: usize,
+    healthy_voters: Option<usize>,
+) -> Result<(), ExecuteError> {
+    let Some(healthy_voters) = healthy_voters else {
+        return Ok(());
+    };
+    if healthy_voters < min_healthy_voters {
+        return Err(ExecuteError::Rejected(format!(
+            "only {healthy_voters} voters are healthy, at least {min_healthy_voters} are \
+             required to accept writes"
+        )));
+    }
+    Ok(())
+}
+
 #[tonic::async_trait]
 impl<S> Protocol for AuthWrapper<S>
 where
@@ -52,11 +83,40 @@ where
             "AuthWrapper received propose request: {}",
             request.get_ref().propose_id()
         );
+        if self.min_healthy_voters > 0 {
+            let command: Command = request
+                .get_ref()
+                .cmd()
+                .map_err(|e| tonic::Status::internal(e.to_string()))?;
+            let healthy_voters = self.curp_server.raw_curp().healthy_voters();
+            let checked = if command.is_read_only() {
+                Ok(())
+            } else {
+                check_healthy_voters(self.min_healthy_voters, healthy_voters)
+            };
+            if let Err(err) = checked {
+                // returned as the execute result so that the curp client surfaces it
+                // instead of retrying the proposal
+                return Ok(tonic::Response::new(
+                    ProposeResponse::new_result::<Command>(&Err(err)),
+                ));
+            }
+        }
         if let Some(auth_info) = self.auth_store.try_get_auth_info_from_request(&request)? {
             let mut command: Command = request
                 .get_ref()
                 .cmd()
                 .map_err(|e| tonic::Status::internal(e.to_string()))?;
+            if self.auth_store.has_role_quotas() {
+                self.auth_store
+                    .check_permission(command.request(), Some(&auth_info))?;
+                self.auth_store
+                    .check_write_rate(command.request(), Some(&auth_info))?;
+                let ranges = self
+                    .auth_store
+                    .role_quota_ranges(command.request(), Some(&auth_info))?;
+                command.extend_keys(ranges);
+            }
             command.set_auth_info(auth_info);
             request.get_mut().command = command.encode();
         };
@@ -119,3 +179,20 @@ where
         self.curp_server.lease_keep_alive(request).await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn writes_should_require_min_healthy_voters() {
+        assert!(check_healthy_voters(3, Some(3)).is_ok());
+        // the check is left to the leader
+        assert!(check_healthy_voters(3, None).is_ok());
+        let err = check_healthy_voters(3, Some(2)).unwrap_err();
+        assert_eq!(
+            tonic::Status::from(err).code(),
+            tonic::Code::FailedPrecondition
+        );
+    }
+}
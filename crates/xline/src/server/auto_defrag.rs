@@ -0,0 +1,364 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use curp::{
+    members::{ClusterInfo, ServerId},
+    server::RawCurp,
+};
+#[cfg(not(madsim))]
+use tonic::transport::ClientTlsConfig;
+use tonic::transport::{Channel, Endpoint};
+use tracing::{debug, info, warn};
+#[cfg(madsim)]
+use utils::ClientTlsConfig;
+use utils::{build_endpoint, config::AutoDefragConfig, task_manager::Listener};
+use xlineapi::command::{Command, CurpClient};
+
+use crate::{
+    rpc::{DefragmentRequest, MaintenanceClient, StatusRequest},
+    state::State,
+    storage::{storage_api::StorageApi, AuthStore},
+};
+
+/// Minutes per day
+const MINUTES_PER_DAY: u64 = 24 * 60;
+
+/// The cluster seen by the automatic defragmentation
+#[async_trait::async_trait]
+pub(crate) trait DefragCluster: Send + Sync {
+    /// The id of the current node
+    fn self_id(&self) -> ServerId;
+    /// The id of the leader known by the current node
+    fn leader(&self) -> Option<ServerId>;
+    /// The ids of all the members
+    fn members(&self) -> Vec<ServerId>;
+    /// Whether a member is a voter, the leadership can only be transferred to a voter
+    fn is_voter(&self, id: ServerId) -> bool;
+    /// The size of the storage file of a member and the size of its live data
+    async fn db_sizes(&self, id: ServerId) -> Result<(u64, u64), tonic::Status>;
+    /// Defragment a member, returns after the defragmentation is done
+    async fn defragment(&self, id: ServerId) -> Result<(), tonic::Status>;
+    /// Transfer the leadership to a member
+    async fn move_leader(&self, id: ServerId) -> Result<(), tonic::Status>;
+}
+
+/// The cluster of a curp node, the members are reached by their maintenance services
+pub(crate) struct CurpDefragCluster<S>
+where
+    S: StorageApi,
+{
+    /// Cluster information
+    cluster_info: Arc<ClusterInfo>,
+    /// Raw curp
+    raw_curp: Arc<RawCurp<Command, State<S, Arc<CurpClient>>>>,
+    /// Consensus client
+    client: Arc<CurpClient>,
+    /// Client tls config
+    client_tls_config: Option<ClientTlsConfig>,
+    /// Auth store, the defragmentations are requested on behalf of the root user
+    auth_store: Arc<AuthStore<S>>,
+}
+
+impl<S> CurpDefragCluster<S>
+where
+    S: StorageApi,
+{
+    /// New `CurpDefragCluster`
+    pub(crate) fn new(
+        cluster_info: Arc<ClusterInfo>,
+        raw_curp: Arc<RawCurp<Command, State<S, Arc<CurpClient>>>>,
+        client: Arc<CurpClient>,
+        client_tls_config: Option<ClientTlsConfig>,
+        auth_store: Arc<AuthStore<S>>,
+    ) -> Self {
+        Self {
+            cluster_info,
+            raw_curp,
+            client,
+            client_tls_config,
+            auth_store,
+        }
+    }
+
+    /// Get a maintenance client of a member
+    fn maintenance_client(
+        &self,
+        id: ServerId,
+    ) -> Result<MaintenanceClient<Channel>, tonic::Status> {
+        let client_urls = self
+            .cluster_info
+            .client_urls(id)
+            .filter(|urls| !urls.is_empty())
+            .ok_or_else(|| {
+                tonic::Status::unavailable(format!("the address of member {id} is unknown"))
+            })?;
+        let endpoints = client_urls
+            .iter()
+            .map(|addr| {
+                build_endpoint(addr, self.client_tls_config.as_ref())
+                    .map_err(|e| tonic::Status::internal(e.to_string()))
+            })
+            .collect::<Result<Vec<Endpoint>, _>>()?;
+        Ok(MaintenanceClient::new(Channel::balance_list(
+            endpoints.into_iter(),
+        )))
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> DefragCluster for CurpDefragCluster<S>
+where
+    S: StorageApi,
+{
+    fn self_id(&self) -> ServerId {
+        self.cluster_info.self_id()
+    }
+
+    fn leader(&self) -> Option<ServerId> {
+        self.raw_curp.leader().0
+    }
+
+    fn members(&self) -> Vec<ServerId> {
+        self.cluster_info.all_ids()
+    }
+
+    fn is_voter(&self, id: ServerId) -> bool {
+        self.cluster_info
+            .get(&id)
+            .map_or(false, |member| !member.is_learner())
+    }
+
+    async fn db_sizes(&self, id: ServerId) -> Result<(u64, u64), tonic::Status> {
+        let status = self
+            .maintenance_client(id)?
+            .status(StatusRequest::default())
+            .await?
+            .into_inner();
+        Ok((
+            u64::try_from(status.db_size).unwrap_or_default(),
+            u64::try_from(status.db_size_in_use).unwrap_or_default(),
+        ))
+    }
+
+    async fn defragment(&self, id: ServerId) -> Result<(), tonic::Status> {
+        let mut request = tonic::Request::new(DefragmentRequest::default());
+        if let Ok(token) = self.auth_store.root_token() {
+            let _ignore = request.metadata_mut().insert(
+                "token",
+                token
+                    .parse()
+                    .unwrap_or_else(|e| panic!("metadata value parse error: {e}")),
+            );
+        }
+        let _resp = self.maintenance_client(id)?.defragment(request).await?;
+        Ok(())
+    }
+
+    async fn move_leader(&self, id: ServerId) -> Result<(), tonic::Status> {
+        self.client.move_leader(id).await?;
+        Ok(())
+    }
+}
+
+/// The percentage of the free space in a storage file
+fn free_space_percent(db_size: u64, db_size_in_use: u64) -> u64 {
+    db_size
+        .saturating_sub(db_size_in_use)
+        .saturating_mul(100)
+        .checked_div(db_size)
+        .unwrap_or_default()
+}
+
+/// Run a round of the automatic defragmentation, which only the leader does.
+///
+/// The members whose free space exceeds the threshold are defragmented one by one,
+/// each defragmentation returns after it is done, so there is never more than one at
+/// a time. The leader is skipped, if it needs a defragmentation it transfers the
+/// leadership to a voter instead, and the new leader defragments it in its round.
+pub(crate) async fn defrag_round(
+    cluster: &dyn DefragCluster,
+    free_space_threshold: u64,
+) -> Result<(), tonic::Status> {
+    let self_id = cluster.self_id();
+    let mut members = cluster.members();
+    members.sort_unstable();
+    let mut leader_needs_defrag = false;
+    for id in members.iter().copied() {
+        // stop once the leadership is lost, the new leader takes over
+        if cluster.leader() != Some(self_id) {
+            return Ok(());
+        }
+        let (db_size, db_size_in_use) = cluster.db_sizes(id).await?;
+        let free = free_space_percent(db_size, db_size_in_use);
+        if free <= free_space_threshold {
+            continue;
+        }
+        if id == self_id {
+            leader_needs_defrag = true;
+            continue;
+        }
+        info!(id, free, "defragmenting member");
+        cluster.defragment(id).await?;
+    }
+    if !leader_needs_defrag || cluster.leader() != Some(self_id) {
+        return Ok(());
+    }
+    let Some(target) = members
+        .into_iter()
+        .find(|&id| id != self_id && cluster.is_voter(id))
+    else {
+        debug!("the leader needs a defragmentation but there is no voter to lead instead");
+        return Ok(());
+    };
+    info!(
+        target,
+        "transferring the leadership to defragment the current leader"
+    );
+    cluster.move_leader(target).await
+}
+
+/// The minute of the day in UTC
+fn utc_minute_of_day() -> u32 {
+    let minutes = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / 60);
+    u32::try_from(minutes % MINUTES_PER_DAY).unwrap_or_default()
+}
+
+/// Run the automatic defragmentation until shutdown, a round runs on every check
+/// within the maintenance window
+#[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
+pub(crate) async fn run_auto_defrag(
+    cluster: Arc<dyn DefragCluster>,
+    config: AutoDefragConfig,
+    shutdown_listener: Listener,
+) {
+    let mut interval = tokio::time::interval(*config.check_interval());
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            () = shutdown_listener.wait() => return,
+        }
+        if !config.window().contains(utc_minute_of_day()) {
+            continue;
+        }
+        if let Err(e) = defrag_round(cluster.as_ref(), *config.free_space_percent()).await {
+            warn!("automatic defragmentation failed: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, time::Duration};
+
+    use parking_lot::Mutex;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Op {
+        Defrag(ServerId),
+        MoveLeader(ServerId),
+    }
+
+    /// A cluster whose members report a free space percentage, seen by `self_id`
+    #[derive(Debug, Default)]
+    struct MockCluster {
+        self_id: Mutex<ServerId>,
+        leader: Mutex<ServerId>,
+        free: Mutex<HashMap<ServerId, u64>>,
+        learners: Vec<ServerId>,
+        ops: Mutex<Vec<Op>>,
+        running: Mutex<usize>,
+    }
+
+    #[async_trait::async_trait]
+    impl DefragCluster for MockCluster {
+        fn self_id(&self) -> ServerId {
+            *self.self_id.lock()
+        }
+
+        fn leader(&self) -> Option<ServerId> {
+            Some(*self.leader.lock())
+        }
+
+        fn members(&self) -> Vec<ServerId> {
+            self.free.lock().keys().copied().collect()
+        }
+
+        fn is_voter(&self, id: ServerId) -> bool {
+            !self.learners.contains(&id)
+        }
+
+        async fn db_sizes(&self, id: ServerId) -> Result<(u64, u64), tonic::Status> {
+            Ok((100, 100 - self.free.lock()[&id]))
+        }
+
+        async fn defragment(&self, id: ServerId) -> Result<(), tonic::Status> {
+            assert_ne!(
+                id,
+                *self.leader.lock(),
+                "the leader should never be defragmented"
+            );
+            {
+                let mut running = self.running.lock();
+                *running += 1;
+                assert_eq!(*running, 1, "only one member is defragmented at a time");
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            *self.running.lock() -= 1;
+            let _prev = self.free.lock().insert(id, 0);
+            self.ops.lock().push(Op::Defrag(id));
+            Ok(())
+        }
+
+        async fn move_leader(&self, id: ServerId) -> Result<(), tonic::Status> {
+            *self.leader.lock() = id;
+            self.ops.lock().push(Op::MoveLeader(id));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn members_should_be_defragmented_one_at_a_time_except_the_leader() {
+        let cluster = MockCluster {
+            free: Mutex::new(HashMap::from([(1, 60), (2, 10), (3, 70), (4, 80)])),
+            learners: vec![4],
+            ..MockCluster::default()
+        };
+        *cluster.self_id.lock() = 1;
+        *cluster.leader.lock() = 1;
+
+        // a follower never defragments
+        *cluster.self_id.lock() = 2;
+        defrag_round(&cluster, 50).await.unwrap();
+        assert!(cluster.ops.lock().is_empty());
+
+        // the leader defragments the others and transfers the leadership to a voter
+        *cluster.self_id.lock() = 1;
+        defrag_round(&cluster, 50).await.unwrap();
+        assert_eq!(
+            *cluster.ops.lock(),
+            [Op::Defrag(3), Op::Defrag(4), Op::MoveLeader(2)]
+        );
+
+        // the new leader defragments the previous one
+        *cluster.self_id.lock() = 2;
+        defrag_round(&cluster, 50).await.unwrap();
+        assert_eq!(cluster.ops.lock().last(), Some(&Op::Defrag(1)));
+
+        // nothing is left to defragment
+        defrag_round(&cluster, 50).await.unwrap();
+        assert_eq!(cluster.ops.lock().len(), 4);
+    }
+
+    #[test]
+    fn free_space_percent_should_handle_empty_files() {
+        assert_eq!(free_space_percent(0, 0), 0);
+        assert_eq!(free_space_percent(200, 50), 75);
+        assert_eq!(free_space_percent(100, 120), 0);
+    }
+}
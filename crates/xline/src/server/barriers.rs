@@ -35,6 +35,11 @@ impl IndexBarrier {
         listener.await;
     }
 
+    /// Get the last triggered index, i.e. the last index applied to the state machine
+    pub(crate) fn last_index(&self) -> LogIndex {
+        self.inner.lock().last_trigger_index
+    }
+
     /// Trigger all barriers whose index is less than or equal to the given index.
     pub(crate) fn trigger(&self, index: LogIndex) {
         let mut inner_l = self.inner.lock();
@@ -1,35 +1,334 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use curp::{
-    members::ClusterInfo,
+    members::{ClusterInfo, MemberUpdate, ServerId},
     rpc::{
         ConfChange,
         ConfChangeType::{Add, AddLearner, Promote, Remove, Update},
     },
+    server::RawCurp,
 };
+use futures::{future::join_all, Stream, StreamExt};
 use itertools::Itertools;
-use tonic::{Request, Response, Status};
-use utils::timestamp;
+use serde::Serialize;
+use tokio::time::{sleep, timeout};
+#[cfg(not(madsim))]
+use tonic::transport::ClientTlsConfig;
+use tonic::{
+    metadata::{Binary, MetadataValue},
+    transport::{Channel, Endpoint},
+    Request, Response, Status,
+};
+use tracing::warn;
+#[cfg(madsim)]
+use utils::ClientTlsConfig;
+use utils::{build_endpoint, timestamp};
 use xlineapi::{
-    command::CurpClient, Cluster, Member, MemberAddRequest, MemberAddResponse, MemberListRequest,
-    MemberListResponse, MemberPromoteRequest, MemberPromoteResponse, MemberRemoveRequest,
-    MemberRemoveResponse, MemberUpdateRequest, MemberUpdateResponse,
+    command::{Command, CurpClient},
+    member_watch::{MemberTags, MemberUpdateType, MemberWatchResponse},
+    Cluster, ClusterClient, HashKvRequest, HashKvResponse, MaintenanceClient, Member,
+    MemberAddRequest, MemberAddResponse, MemberListRequest, MemberListResponse,
+    MemberPromoteRequest, MemberPromoteResponse, MemberRemoveRequest, MemberRemoveResponse,
+    MemberUpdateRequest, MemberUpdateResponse, StatusRequest,
+};
+
+use super::member_tags::{self, MEMBER_TAGS_KEY};
+use crate::{
+    header_gen::HeaderGenerator,
+    state::State,
+    storage::{storage_api::StorageApi, AuthStore, KvStore},
 };
 
-use crate::header_gen::HeaderGenerator;
+/// Metadata key which asks a `MemberPromote` to verify that the keyspace hash of the
+/// learner matches the one of the leader at the same revision before promoting it
+pub(crate) const VERIFY_PROMOTION_KEY: &str = "verify-promotion";
+
+/// The maximum time a verified promotion waits for the learner to apply the revision
+/// of the leader
+const PROMOTION_CATCH_UP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The interval to check whether the learner has applied the revision of the leader
+const PROMOTION_CATCH_UP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Metadata key which asks a `MemberList` for the progress of the members, it requires
+/// the admin permission. The response carries it by `MEMBER_PROGRESS_KEY`.
+pub(crate) const MEMBER_PROGRESS_REQUEST_KEY: &str = "member-progress";
+
+/// Metadata key of a member list response carrying the progress of its members, as a
+/// json object from the member ids to their progress
+pub(crate) const MEMBER_PROGRESS_KEY: &str = "member-progress-bin";
+
+/// Metadata key which marks a member list forwarded to the leader for the progress of
+/// the members, such a request is never forwarded again
+const FORWARDED_PROGRESS_KEY: &str = "forwarded-member-progress";
+
+/// The maximum time to wait for a member to report its progress
+const MEMBER_PROGRESS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The progress of a member, a coordinator can pick a revision that every member has
+/// applied by the minimum of their applied revisions
+#[derive(Debug, Default, Serialize)]
+struct MemberProgress {
+    /// The index of the last log entry replicated to the member in the view of the
+    /// leader, `None` if the leader is unknown or doesn't know the member
+    match_index: Option<u64>,
+    /// The index of the last log entry applied by the member, reported by itself,
+    /// `None` if it doesn't report in time
+    applied_index: Option<u64>,
+    /// The latest revision applied by the member, reported by itself, `None` if it
+    /// doesn't report in time
+    applied_revision: Option<i64>,
+}
 
 /// Cluster Server
-pub(crate) struct ClusterServer {
+pub(crate) struct ClusterServer<S>
+where
+    S: StorageApi,
+{
     /// Consensus client
     client: Arc<CurpClient>,
+    /// Kv storage, which keeps the tags of the members
+    kv_storage: Arc<KvStore<S>>,
+    /// Auth storage
+    auth_storage: Arc<AuthStore<S>>,
+    /// Raw curp, which knows the match indices of the members when it leads
+    raw_curp: Arc<RawCurp<Command, State<S, Arc<CurpClient>>>>,
     /// Header generator
     header_gen: Arc<HeaderGenerator>,
+    /// Cluster information
+    cluster_info: Arc<ClusterInfo>,
+    /// Client tls config
+    client_tls_config: Option<ClientTlsConfig>,
 }
 
-impl ClusterServer {
+impl<S> ClusterServer<S>
+where
+    S: StorageApi,
+{
     /// New `ClusterServer`
-    pub(crate) fn new(client: Arc<CurpClient>, header_gen: Arc<HeaderGenerator>) -> Self {
-        Self { client, header_gen }
+    pub(crate) fn new(
+        client: Arc<CurpClient>,
+        kv_storage: Arc<KvStore<S>>,
+        auth_storage: Arc<AuthStore<S>>,
+        raw_curp: Arc<RawCurp<Command, State<S, Arc<CurpClient>>>>,
+        header_gen: Arc<HeaderGenerator>,
+        cluster_info: Arc<ClusterInfo>,
+        client_tls_config: Option<ClientTlsConfig>,
+    ) -> Self {
+        Self {
+            client,
+            kv_storage,
+            auth_storage,
+            raw_curp,
+            header_gen,
+            cluster_info,
+            client_tls_config,
+        }
+    }
+
+    /// Connect to the client services of a member
+    fn member_channel(&self, id: u64) -> Result<Channel, Status> {
+        let client_urls = self
+            .cluster_info
+            .client_urls(id)
+            .filter(|urls| !urls.is_empty())
+            .ok_or_else(|| {
+                Status::unavailable(format!(
+                    "the address of member {id} is unknown, please retry later"
+                ))
+            })?;
+        let endpoints = client_urls
+            .iter()
+            .map(|addr| {
+                build_endpoint(addr, self.client_tls_config.as_ref())
+                    .map_err(|e| Status::internal(e.to_string()))
+            })
+            .collect::<Result<Vec<Endpoint>, _>>()?;
+        Ok(Channel::balance_list(endpoints.into_iter()))
+    }
+
+    /// Connect to the maintenance service of a member
+    fn maintenance_client(&self, id: u64) -> Result<MaintenanceClient<Channel>, Status> {
+        self.member_channel(id).map(MaintenanceClient::new)
+    }
+
+    /// Get a cluster client of the leader if a member list asking for the progress
+    /// should be forwarded to it, which knows the match indices of the members.
+    /// Return `None` if it should be served by the current node.
+    fn progress_forward_client(
+        &self,
+        request: &Request<MemberListRequest>,
+    ) -> Result<Option<ClusterClient<Channel>>, Status> {
+        if request.metadata().contains_key(FORWARDED_PROGRESS_KEY) {
+            return Ok(None);
+        }
+        let (leader_id, _term, is_leader) = self.raw_curp.leader();
+        match leader_id {
+            Some(leader_id) if !is_leader => self
+                .member_channel(leader_id)
+                .map(|channel| Some(ClusterClient::new(channel))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Ask a member to report its last applied index and its latest revision
+    async fn reported_progress(&self, id: ServerId) -> Result<(u64, i64), Status> {
+        let mut client = self.maintenance_client(id)?;
+        let status = timeout(
+            MEMBER_PROGRESS_TIMEOUT,
+            client.status(StatusRequest::default()),
+        )
+        .await
+        .map_err(|_elapsed| Status::deadline_exceeded("the member doesn't report in time"))??
+        .into_inner();
+        let revision = status.header.map_or(0, |header| header.revision);
+        Ok((status.raft_applied_index, revision))
+    }
+
+    /// Get the progress of the members, they report concurrently
+    async fn member_progress(&self, ids: &[ServerId]) -> HashMap<ServerId, MemberProgress> {
+        join_all(ids.iter().map(|&id| async move {
+            let mut progress = MemberProgress {
+                match_index: self.raw_curp.match_index(id),
+                ..MemberProgress::default()
+            };
+            match self.reported_progress(id).await {
+                Ok((applied_index, applied_revision)) => {
+                    progress.applied_index = Some(applied_index);
+                    progress.applied_revision = Some(applied_revision);
+                }
+                Err(e) => warn!("member {id} doesn't report its progress: {e}"),
+            }
+            (id, progress)
+        }))
+        .await
+        .into_iter()
+        .collect()
+    }
+
+    /// Encode the progress of the members as the metadata value of `MEMBER_PROGRESS_KEY`
+    fn progress_metadata(progress: &HashMap<ServerId, MemberProgress>) -> MetadataValue<Binary> {
+        let json = serde_json::to_vec(progress)
+            .unwrap_or_else(|e| unreachable!("the progress is always serializable: {e}"));
+        MetadataValue::from_bytes(&json)
+    }
+
+    /// Verify that the keyspace hash of a learner matches the one of the leader at the
+    /// current revision of the leader, waiting for the learner to apply the revision
+    async fn verify_learner(&self, learner_id: u64) -> Result<(), Status> {
+        let leader_id = self.client.fetch_leader_id(true).await?;
+        let mut leader_client = self.maintenance_client(leader_id)?;
+        // pin the revision, the current revision of the leader moves on with the writes
+        let revision = leader_client
+            .hash_kv(HashKvRequest { revision: 0 })
+            .await?
+            .into_inner()
+            .header
+            .map_or(0, |header| header.revision);
+        let leader = leader_client
+            .hash_kv(HashKvRequest { revision })
+            .await?
+            .into_inner();
+        let mut learner_client = self.maintenance_client(learner_id)?;
+        let learner = timeout(PROMOTION_CATCH_UP_TIMEOUT, async {
+            loop {
+                match learner_client.hash_kv(HashKvRequest { revision }).await {
+                    // the revision is a future revision of the learner
+                    Err(status) if status.code() == tonic::Code::OutOfRange => {
+                        sleep(PROMOTION_CATCH_UP_INTERVAL).await;
+                    }
+                    res => return res,
+                }
+            }
+        })
+        .await
+        .map_err(|_elapsed| {
+            Status::deadline_exceeded("the learner doesn't catch up with the leader in time")
+        })??
+        .into_inner();
+        Self::check_learner_hash(&leader, &learner)
+    }
+
+    /// Check the keyspace hash of a learner against the one of the leader, both of the
+    /// same revision
+    fn check_learner_hash(leader: &HashKvResponse, learner: &HashKvResponse) -> Result<(), Status> {
+        if leader.compact_revision != learner.compact_revision {
+            return Err(Status::failed_precondition(format!(
+                "the learner is compacted to revision {}, but the leader is compacted to {}, \
+                 please retry after the compaction",
+                learner.compact_revision, leader.compact_revision
+            )));
+        }
+        if leader.hash != learner.hash {
+            return Err(Status::data_loss(
+                "etcdserver: corrupt cluster, the keyspace of the learner doesn't match the \
+                 leader",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Watch the members known by this server, a snapshot of all the members is
+    /// yielded first, then the updates of every change of them. Every update comes
+    /// with the tags of its members at that time, a change of the tags alone doesn't
+    /// yield an update.
+    #[cfg_attr(madsim, allow(dead_code))] // The member watch service is not simulated
+    pub(crate) fn watch_members(&self) -> impl Stream<Item = MemberWatchResponse> {
+        let kv_storage = Arc::clone(&self.kv_storage);
+        let header_gen = Arc::clone(&self.header_gen);
+        Arc::clone(&self.cluster_info)
+            .watch_members()
+            .map(move |update| {
+                let mut tags = kv_storage.member_tags().unwrap_or_else(|e| {
+                    warn!("failed to read the member tags: {e}");
+                    HashMap::new()
+                });
+                #[allow(clippy::wildcard_enum_match_arm)] // `MemberUpdate` is non-exhaustive
+                let (update_type, members) = match update {
+                    MemberUpdate::Snapshot(members) => (
+                        MemberUpdateType::Snapshot,
+                        members.into_iter().map(Self::pb_member).collect(),
+                    ),
+                    MemberUpdate::Add(member) => {
+                        (MemberUpdateType::Add, vec![Self::pb_member(member)])
+                    }
+                    MemberUpdate::Remove(id) => (
+                        MemberUpdateType::Remove,
+                        vec![Member {
+                            id,
+                            ..Member::default()
+                        }],
+                    ),
+                    MemberUpdate::Update(member) => {
+                        (MemberUpdateType::Update, vec![Self::pb_member(member)])
+                    }
+                    update => unreachable!("unknown member update: {update:?}"),
+                };
+                let tags = members
+                    .iter()
+                    .filter_map(|member| {
+                        let tags = tags.remove(&member.id)?;
+                        Some((member.id, MemberTags { tags }))
+                    })
+                    .collect();
+                MemberWatchResponse {
+                    header: Some(header_gen.gen_header()),
+                    r#type: update_type.into(),
+                    members,
+                    tags,
+                }
+            })
+    }
+
+    /// Convert a member of curp to the one of the cluster service
+    fn pb_member(member: curp::rpc::Member) -> Member {
+        Member {
+            id: member.id,
+            name: member.name,
+            peer_ur_ls: member.peer_urls,
+            client_ur_ls: member.client_urls,
+            is_learner: member.is_learner,
+        }
     }
 
     /// Send propose conf change request
@@ -51,7 +350,10 @@ impl ClusterServer {
 }
 
 #[tonic::async_trait]
-impl Cluster for ClusterServer {
+impl<S> Cluster for ClusterServer<S>
+where
+    S: StorageApi,
+{
     async fn member_add(
         &self,
         request: Request<MemberAddRequest>,
@@ -92,6 +394,10 @@ impl Cluster for ClusterServer {
                 address: vec![],
             }])
             .await?;
+        let request = member_tags::remove_request(req.id);
+        if let Err(e) = member_tags::propose(&self.client, &self.auth_storage, request).await {
+            warn!("failed to remove the tags of member {}: {e:?}", req.id);
+        }
         let resp = MemberRemoveResponse {
             header: Some(self.header_gen.gen_header()),
             members,
@@ -122,9 +428,33 @@ impl Cluster for ClusterServer {
         &self,
         request: Request<MemberListRequest>,
     ) -> Result<Response<MemberListResponse>, Status> {
+        let with_progress = request.metadata().contains_key(MEMBER_PROGRESS_REQUEST_KEY);
+        if with_progress {
+            self.auth_storage.check_admin_request(&request)?;
+            if let Some(mut leader_client) = self.progress_forward_client(&request)? {
+                let (mut metadata, _extensions, message) = request.into_parts();
+                let _prev =
+                    metadata.insert(FORWARDED_PROGRESS_KEY, MetadataValue::from_static("true"));
+                return leader_client
+                    .member_list(Request::from_parts(
+                        metadata,
+                        tonic::Extensions::default(),
+                        message,
+                    ))
+                    .await;
+            }
+        }
         let req = request.into_inner();
         let header = self.header_gen.gen_header();
         let members = self.client.fetch_cluster(req.linearizable).await?.members;
+        let mut tags = self.kv_storage.member_tags()?;
+        tags.retain(|id, _| members.iter().any(|member| member.id == *id));
+        let progress = if with_progress {
+            let ids: Vec<_> = members.iter().map(|member| member.id).collect();
+            Some(self.member_progress(&ids).await)
+        } else {
+            None
+        };
         let resp = MemberListResponse {
             header: Some(header),
             members: members
@@ -138,13 +468,25 @@ impl Cluster for ClusterServer {
                 })
                 .collect(),
         };
-        Ok(Response::new(resp))
+        let mut response = Response::new(resp);
+        let _prev = response
+            .metadata_mut()
+            .insert_bin(MEMBER_TAGS_KEY, member_tags::to_metadata(&tags));
+        if let Some(progress) = progress {
+            let _prev = response
+                .metadata_mut()
+                .insert_bin(MEMBER_PROGRESS_KEY, Self::progress_metadata(&progress));
+        }
+        Ok(response)
     }
 
     async fn member_promote(
         &self,
         request: Request<MemberPromoteRequest>,
     ) -> Result<Response<MemberPromoteResponse>, Status> {
+        if request.metadata().contains_key(VERIFY_PROMOTION_KEY) {
+            self.verify_learner(request.get_ref().id).await?;
+        }
         let req = request.into_inner();
         let members = self
             .propose_conf_change(vec![ConfChange {
@@ -160,3 +502,27 @@ impl Cluster for ClusterServer {
         Ok(Response::new(resp))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::db::DB;
+
+    fn hash_kv(hash: u32, compact_revision: i64) -> HashKvResponse {
+        HashKvResponse {
+            header: None,
+            hash,
+            compact_revision,
+        }
+    }
+
+    #[test]
+    fn learner_with_a_divergent_keyspace_should_not_be_promoted() {
+        let leader = hash_kv(42, 3);
+        assert!(ClusterServer::<DB>::check_learner_hash(&leader, &hash_kv(42, 3)).is_ok());
+        let err = ClusterServer::<DB>::check_learner_hash(&leader, &hash_kv(7, 3)).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::DataLoss);
+        let err = ClusterServer::<DB>::check_learner_hash(&leader, &hash_kv(42, 5)).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+    }
+}
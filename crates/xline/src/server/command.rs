@@ -1,6 +1,6 @@
 use std::{fmt::Debug, sync::Arc};
 
-use clippy_utilities::OverflowArithmetic;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
 use curp::{
     cmd::{Command as CurpCommand, CommandExecutor as CurpCommandExecutor},
     members::ServerId,
@@ -11,18 +11,25 @@ use engine::Snapshot;
 use event_listener::Event;
 use parking_lot::RwLock;
 use tracing::warn;
-use utils::table_names::META_TABLE;
+use utils::{config::MaintenanceOp, table_names::META_TABLE};
 use xlineapi::{
     command::{Command, CurpClient},
     execute_error::ExecuteError,
+    server_op::ServerOp,
     AlarmAction, AlarmRequest, AlarmType,
 };
 
-use super::barriers::{IdBarrier, IndexBarrier};
+use super::{
+    barriers::{IdBarrier, IndexBarrier},
+    command_hook::CommandHook,
+};
 use crate::{
     revision_number::RevisionNumberGenerator,
     rpc::{RequestBackend, RequestWrapper},
-    storage::{db::WriteOp, storage_api::StorageApi, AlarmStore, AuthStore, KvStore, LeaseStore},
+    storage::{
+        db::WriteOp, maintenance_scheduler::MaintenanceScheduler, storage_api::StorageApi,
+        AlarmStore, AuthStore, KvStore, LeaseStore,
+    },
 };
 
 /// Key of applied index
@@ -87,6 +94,10 @@ where
     quota_checker: Arc<dyn QuotaChecker>,
     /// Alarmer
     alarmer: RwLock<Option<Alarmer>>,
+    /// Scheduler of the heavy storage maintenance operations
+    maintenance_scheduler: Arc<MaintenanceScheduler>,
+    /// Hooks around the apply of commands
+    hooks: Vec<Arc<dyn CommandHook>>,
 }
 
 /// Quota checker
@@ -223,6 +234,13 @@ impl Alarmer {
         let _ig = self.client.propose(&cmd, None, true).await?;
         Ok(())
     }
+
+    /// Raise an alarm of the current node, the failure to propose it is logged
+    pub(crate) async fn activate(&self, alarm: AlarmType) {
+        if let Err(e) = self.alarm(AlarmAction::Activate, alarm).await {
+            warn!("{} propose alarm failed: {:?}", self.id, e);
+        }
+    }
 }
 
 impl<S> CommandExecutor<S>
@@ -243,6 +261,8 @@ where
         auth_rev: Arc<RevisionNumberGenerator>,
         compact_events: Arc<DashMap<u64, Arc<Event>>>,
         quota: u64,
+        maintenance_scheduler: Arc<MaintenanceScheduler>,
+        hooks: Vec<Arc<dyn CommandHook>>,
     ) -> Self {
         let alarmer = RwLock::new(None);
         let quota_checker = Arc::new(CommandQuotaChecker::new(quota, Arc::clone(&persistent)));
@@ -259,6 +279,8 @@ where
             compact_events,
             quota_checker,
             alarmer,
+            maintenance_scheduler,
+            hooks,
         }
     }
 
@@ -267,6 +289,19 @@ where
         *self.alarmer.write() = Some(alarmer);
     }
 
+    /// Get the number of revisions reserved by a command, `None` if it isn't a
+    /// reservation
+    fn reserved_revisions(wrapper: &RequestWrapper) -> Result<Option<i64>, ExecuteError> {
+        let RequestWrapper::TxnRequest(ref req) = *wrapper else {
+            return Ok(None);
+        };
+        let Some(ServerOp::ReserveRevisions(op)) = ServerOp::from_txn(req)? else {
+            return Ok(None);
+        };
+        op.check_count()?;
+        Ok(Some(op.count.numeric_cast()))
+    }
+
     /// Check if the alarm is activated
     fn check_alarm(&self, cmd: &Command) -> Result<(), ExecuteError> {
         #[allow(clippy::wildcard_enum_match_arm)]
@@ -305,6 +340,13 @@ where
         let wrapper = cmd.request();
         let auth_info = cmd.auth_info();
         self.auth_storage.check_permission(wrapper, auth_info)?;
+        // the writes counted against the same role quota conflict by their keys, so a
+        // write is prepared after the previous ones are applied
+        self.auth_storage
+            .check_role_usage(wrapper, auth_info, self.kv_storage.as_ref())?;
+        for hook in &self.hooks {
+            hook.pre_apply(wrapper).map_err(ExecuteError::Rejected)?;
+        }
         let revision = match wrapper.backend() {
             RequestBackend::Auth => {
                 if wrapper.skip_auth_revision() {
@@ -316,6 +358,8 @@ where
             RequestBackend::Kv | RequestBackend::Lease => {
                 if wrapper.skip_general_revision() {
                     -1
+                } else if let Some(count) = Self::reserved_revisions(wrapper)? {
+                    self.general_rev.next_n(count)
                 } else {
                     self.general_rev.next()
                 }
@@ -373,6 +417,9 @@ where
             self.kv_storage.insert_index(key_revisions);
         }
         self.lease_storage.mark_lease_synced(wrapper);
+        for hook in &self.hooks {
+            hook.post_apply(wrapper, revision);
+        }
         if !quota_enough {
             if let Some(alarmer) = self.alarmer.read().clone() {
                 let _ig = tokio::spawn(async move {
@@ -392,18 +439,23 @@ where
         &self,
         snapshot: Option<(Snapshot, LogIndex)>,
     ) -> Result<(), <Command as CurpCommand>::Error> {
-        let s = if let Some((snapshot, index)) = snapshot {
-            _ = self
-                .persistent
-                .flush_ops(vec![WriteOp::PutAppliedIndex(index)])?;
-            Some(snapshot)
-        } else {
-            None
+        let Some((snapshot, index)) = snapshot else {
+            return self.persistent.reset(None).await;
         };
-        self.persistent.reset(s).await
+        // the in-flight serializable reads are finished before the state is overwritten,
+        // and the new ones are held off until the snapshot is fully installed
+        let _gate = self.kv_storage.begin_snapshot_install().await;
+        _ = self
+            .persistent
+            .flush_ops(vec![WriteOp::PutAppliedIndex(index)])?;
+        self.persistent.reset(Some(snapshot)).await
     }
 
     async fn snapshot(&self) -> Result<Snapshot, <Command as CurpCommand>::Error> {
+        let _permit = self
+            .maintenance_scheduler
+            .acquire(MaintenanceOp::Snapshot)
+            .await;
         let path = format!("/tmp/snapshot-{}", uuid::Uuid::new_v4());
         self.persistent.get_snapshot(path)
     }
@@ -0,0 +1,32 @@
+use std::fmt::Debug;
+
+use xlineapi::RequestWrapper;
+
+/// A hook invoked around the apply of every command, registered by
+/// `XlineServer::with_command_hook` before the server starts.
+///
+/// The commands are applied by every node independently, so a hook must keep them
+/// deterministic across the nodes:
+/// - `pre_apply` may reject a command, but its decision must only depend on the
+///   request, never on the time, the node or any state outside the request, otherwise
+///   the nodes diverge. A rejected command is not applied and fails with
+///   `ExecuteError::Rejected`.
+/// - `post_apply` only observes the applied commands, it cannot change their results.
+///
+/// The hooks run on the apply path, so they should be cheap and never block.
+pub trait CommandHook: Send + Sync + Debug {
+    /// Invoked before a command is applied, returns the reason to reject it
+    ///
+    /// # Errors
+    ///
+    /// Return the reason if the command is rejected
+    #[inline]
+    fn pre_apply(&self, _request: &RequestWrapper) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Invoked after a command is applied and its changes are persisted, the revision
+    /// is the one of its changes, or -1 if it doesn't change the revision
+    #[inline]
+    fn post_apply(&self, _request: &RequestWrapper, _revision: i64) {}
+}
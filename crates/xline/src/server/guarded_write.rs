@@ -0,0 +1,230 @@
+use tonic::metadata::MetadataMap;
+
+use crate::rpc::{
+    Compare, CompareResult, CompareTarget, PutRequest, PutResponse, Request, RequestOp, Response,
+    TargetUnion, TxnRequest, TxnResponse,
+};
+
+/// Metadata key of the mod revision that a put expects its key to be at, 0 expects
+/// the key to be absent
+pub(crate) const EXPECTED_MOD_REVISION_KEY: &str = "expected-mod-revision";
+
+/// The key prefixes whose keys can only be written by compare-and-set.
+///
+/// A put to a guarded key must carry the expected mod revision of the key in the
+/// `expected-mod-revision` metadata, the server proposes it as a txn comparing the
+/// mod revision, so the put fails with a conflict instead of blindly overwriting a
+/// concurrent write. Since it is a compare, such a put also requires the read
+/// permission of the key. A put to a guarded key in a txn is only allowed in the
+/// success branch of a txn comparing the mod revision of the key.
+#[derive(Debug, Default)]
+pub(crate) struct GuardedPrefixes {
+    /// The guarded prefixes
+    prefixes: Vec<Vec<u8>>,
+}
+
+impl GuardedPrefixes {
+    /// New `GuardedPrefixes`
+    pub(crate) fn new(prefixes: &[String]) -> Self {
+        Self {
+            prefixes: prefixes.iter().map(|p| p.as_bytes().to_vec()).collect(),
+        }
+    }
+
+    /// Check whether a key is guarded
+    fn is_guarded(&self, key: &[u8]) -> bool {
+        self.prefixes.iter().any(|prefix| key.starts_with(prefix))
+    }
+
+    /// Get the compare-and-set txn of a put, `None` if its key is not guarded
+    pub(crate) fn compare_and_put(
+        &self,
+        req: &PutRequest,
+        metadata: &MetadataMap,
+    ) -> Result<Option<TxnRequest>, tonic::Status> {
+        if !self.is_guarded(&req.key) {
+            return Ok(None);
+        }
+        let Some(value) = metadata.get(EXPECTED_MOD_REVISION_KEY) else {
+            return Err(tonic::Status::failed_precondition(
+                "a put to a guarded key requires an expected mod revision",
+            ));
+        };
+        let expected = value
+            .to_str()
+            .ok()
+            .and_then(|s| s.parse::<i64>().ok())
+            .filter(|rev| *rev >= 0)
+            .ok_or_else(|| tonic::Status::invalid_argument("invalid expected mod revision"))?;
+        Ok(Some(TxnRequest {
+            compare: vec![Compare {
+                result: CompareResult::Equal.into(),
+                target: CompareTarget::Mod.into(),
+                key: req.key.clone(),
+                range_end: vec![],
+                target_union: Some(TargetUnion::ModRevision(expected)),
+            }],
+            success: vec![RequestOp {
+                request: Some(Request::RequestPut(req.clone())),
+            }],
+            failure: vec![],
+        }))
+    }
+
+    /// Get the response of a put from the response of its compare-and-set txn
+    pub(crate) fn put_response(res: TxnResponse) -> Result<PutResponse, tonic::Status> {
+        if !res.succeeded {
+            return Err(tonic::Status::aborted(
+                "the mod revision of the key doesn't match the expected one",
+            ));
+        }
+        let Some(Response::ResponsePut(mut put_res)) =
+            res.responses.into_iter().next().and_then(|op| op.response)
+        else {
+            unreachable!("Receive wrong response for PutRequest");
+        };
+        put_res.header = res.header;
+        Ok(put_res)
+    }
+
+    /// Check that every put to a guarded key in a txn is guarded by a compare of
+    /// its mod revision
+    pub(crate) fn check_txn(&self, txn: &TxnRequest) -> Result<(), tonic::Status> {
+        if self.prefixes.is_empty() {
+            return Ok(());
+        }
+        self.check_ops(txn, &[])
+    }
+
+    /// Check the ops of a txn, `compared` are the keys whose mod revisions are
+    /// compared by the enclosing txns whose success branches contain it
+    fn check_ops(&self, txn: &TxnRequest, compared: &[&[u8]]) -> Result<(), tonic::Status> {
+        let success_compared: Vec<&[u8]> = compared
+            .iter()
+            .copied()
+            .chain(
+                txn.compare
+                    .iter()
+                    .filter(|cmp| Self::is_mod_revision_guard(cmp))
+                    .map(|cmp| cmp.key.as_slice()),
+            )
+            .collect();
+        let branches = [
+            (&txn.success, success_compared.as_slice()),
+            (&txn.failure, compared),
+        ];
+        for (ops, guarded_keys) in branches {
+            for request in ops.iter().filter_map(|op| op.request.as_ref()) {
+                match *request {
+                    Request::RequestPut(ref put) => {
+                        if self.is_guarded(&put.key) && !guarded_keys.contains(&put.key.as_slice())
+                        {
+                            return Err(tonic::Status::failed_precondition(
+                                "a put to a guarded key in a txn requires a compare of its \
+                                 mod revision",
+                            ));
+                        }
+                    }
+                    Request::RequestTxn(ref nested) => self.check_ops(nested, guarded_keys)?,
+                    Request::RequestRange(_) | Request::RequestDeleteRange(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a compare checks the mod revision of a single key
+    fn is_mod_revision_guard(cmp: &Compare) -> bool {
+        cmp.range_end.is_empty()
+            && cmp.result() == CompareResult::Equal
+            && cmp.target() == CompareTarget::Mod
+            && matches!(cmp.target_union, Some(TargetUnion::ModRevision(_)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn put(key: &str) -> PutRequest {
+        PutRequest {
+            key: key.into(),
+            value: b"v".to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn op(request: Request) -> RequestOp {
+        RequestOp {
+            request: Some(request),
+        }
+    }
+
+    #[test]
+    fn guarded_put_should_require_an_expected_mod_revision() {
+        let guarded = GuardedPrefixes::new(&["config/".to_owned()]);
+        let mut metadata = MetadataMap::new();
+        assert!(guarded
+            .compare_and_put(&put("other"), &metadata)
+            .unwrap()
+            .is_none());
+        let err = guarded
+            .compare_and_put(&put("config/a"), &metadata)
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+
+        let _ignore = metadata.insert(EXPECTED_MOD_REVISION_KEY, "-1".parse().unwrap());
+        let err = guarded
+            .compare_and_put(&put("config/a"), &metadata)
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        let _ignore = metadata.insert(EXPECTED_MOD_REVISION_KEY, "5".parse().unwrap());
+        let txn = guarded
+            .compare_and_put(&put("config/a"), &metadata)
+            .unwrap()
+            .unwrap();
+        assert!(GuardedPrefixes::is_mod_revision_guard(&txn.compare[0]));
+        assert_eq!(
+            txn.compare[0].target_union,
+            Some(TargetUnion::ModRevision(5))
+        );
+        assert!(guarded.check_txn(&txn).is_ok());
+    }
+
+    #[test]
+    fn guarded_put_in_txn_should_require_a_compare_of_its_mod_revision() {
+        let guarded = GuardedPrefixes::new(&["config/".to_owned()]);
+        let compare = Compare {
+            result: CompareResult::Equal.into(),
+            target: CompareTarget::Mod.into(),
+            key: b"config/a".to_vec(),
+            range_end: vec![],
+            target_union: Some(TargetUnion::ModRevision(3)),
+        };
+        let blind = TxnRequest {
+            compare: vec![],
+            success: vec![op(Request::RequestPut(put("config/a")))],
+            failure: vec![],
+        };
+        assert!(guarded.check_txn(&blind).is_err());
+        let nested = TxnRequest {
+            compare: vec![compare.clone()],
+            success: vec![op(Request::RequestTxn(blind.clone()))],
+            failure: vec![],
+        };
+        assert!(guarded.check_txn(&nested).is_ok());
+        let in_failure = TxnRequest {
+            compare: vec![compare],
+            success: vec![],
+            failure: vec![op(Request::RequestPut(put("config/a")))],
+        };
+        assert!(guarded.check_txn(&in_failure).is_err());
+        let unguarded = TxnRequest {
+            compare: vec![],
+            success: vec![op(Request::RequestPut(put("other")))],
+            failure: vec![],
+        };
+        assert!(guarded.check_txn(&unguarded).is_ok());
+    }
+}
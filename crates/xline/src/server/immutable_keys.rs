@@ -0,0 +1,319 @@
+use tonic::metadata::MetadataMap;
+use xlineapi::command::KeyRange;
+
+use crate::rpc::{
+    Compare, CompareResult, CompareTarget, DeleteRangeRequest, DeleteRangeResponse, PutRequest,
+    RangeRequest, Request, RequestOp, Response, TargetUnion, TxnRequest, TxnResponse,
+};
+
+/// Metadata key of a write which unlocks the immutable keys it writes, only an admin
+/// can unlock them
+pub(crate) const UNLOCK_IMMUTABLE_KEY: &str = "unlock-immutable";
+
+/// The key prefixes whose keys are write-once.
+///
+/// A key under them can be created once, and then it can't be overwritten or deleted.
+/// A put to such a key is proposed as a txn comparing the version of the key with 0,
+/// and a delete with the keys under them is proposed as a txn comparing the versions
+/// of those keys with 0, so the writes of the existing keys fail atomically. A write
+/// of an admin carrying the `unlock-immutable` metadata is not guarded, which corrects
+/// the keys and leaves them immutable again. An immutable key can't be attached to a
+/// lease, which would delete it on expiry. A put to an immutable key in a txn is only
+/// allowed in the success branch of a txn comparing the version of the key with 0, and
+/// a txn can't delete the immutable keys.
+#[derive(Debug, Default)]
+pub(crate) struct ImmutablePrefixes {
+    /// The immutable prefixes
+    prefixes: Vec<Vec<u8>>,
+}
+
+impl ImmutablePrefixes {
+    /// New `ImmutablePrefixes`
+    pub(crate) fn new(prefixes: &[String]) -> Self {
+        Self {
+            prefixes: prefixes.iter().map(|p| p.as_bytes().to_vec()).collect(),
+        }
+    }
+
+    /// Whether a write asks to unlock the immutable keys
+    pub(crate) fn asks_unlock(metadata: &MetadataMap) -> bool {
+        metadata.contains_key(UNLOCK_IMMUTABLE_KEY)
+    }
+
+    /// Check whether a key is immutable
+    pub(crate) fn is_immutable(&self, key: &[u8]) -> bool {
+        self.prefixes.iter().any(|prefix| key.starts_with(prefix))
+    }
+
+    /// The status of a write rejected for modifying the immutable keys
+    fn rejection() -> tonic::Status {
+        tonic::Status::failed_precondition(
+            "the keys are immutable, only an admin can unlock them by the unlock-immutable \
+             metadata",
+        )
+    }
+
+    /// Guard a put against overwriting an immutable key, `txn` is the txn of the put
+    /// built by the other guards if any. Returns the txn to propose instead of the put,
+    /// `None` if the put is proposed as it is.
+    pub(crate) fn guard_put(
+        &self,
+        req: &PutRequest,
+        txn: Option<TxnRequest>,
+    ) -> Result<Option<TxnRequest>, tonic::Status> {
+        if !self.is_immutable(&req.key) {
+            return Ok(txn);
+        }
+        if req.lease != 0 {
+            return Err(tonic::Status::failed_precondition(
+                "an immutable key can't be attached to a lease",
+            ));
+        }
+        let mut txn = txn.unwrap_or_else(|| TxnRequest {
+            compare: vec![],
+            success: vec![RequestOp {
+                request: Some(Request::RequestPut(req.clone())),
+            }],
+            failure: vec![],
+        });
+        txn.compare.push(Self::absent(req.key.clone(), vec![]));
+        // read the key on failure to tell whether it failed by the immutability
+        let read_back = txn.failure.iter().any(|op| {
+            matches!(op.request, Some(Request::RequestRange(ref range)) if range.key == req.key)
+        });
+        if !read_back {
+            txn.failure.push(RequestOp {
+                request: Some(Request::RequestRange(RangeRequest {
+                    key: req.key.clone(),
+                    keys_only: true,
+                    ..RangeRequest::default()
+                })),
+            });
+        }
+        Ok(Some(txn))
+    }
+
+    /// Check that a guarded put has not failed because its key already exists
+    pub(crate) fn check_put_response(res: &TxnResponse) -> Result<(), tonic::Status> {
+        if res.succeeded {
+            return Ok(());
+        }
+        let exists = res.responses.iter().any(|op| {
+            matches!(op.response, Some(Response::ResponseRange(ref range)) if !range.kvs.is_empty())
+        });
+        if exists {
+            return Err(Self::rejection());
+        }
+        Ok(())
+    }
+
+    /// Get the txn of a delete which fails if it deletes an immutable key, `None` if the
+    /// range has no immutable keys
+    pub(crate) fn guard_delete(&self, req: &DeleteRangeRequest) -> Option<TxnRequest> {
+        let compare = self.compares(&req.key, &req.range_end);
+        (!compare.is_empty()).then(|| TxnRequest {
+            compare,
+            success: vec![RequestOp {
+                request: Some(Request::RequestDeleteRange(req.clone())),
+            }],
+            failure: vec![],
+        })
+    }
+
+    /// Get the response of a delete from the response of its guarded txn
+    pub(crate) fn delete_response(res: TxnResponse) -> Result<DeleteRangeResponse, tonic::Status> {
+        if !res.succeeded {
+            return Err(Self::rejection());
+        }
+        let Some(Response::ResponseDeleteRange(mut delete_res)) =
+            res.responses.into_iter().next().and_then(|op| op.response)
+        else {
+            unreachable!("Receive wrong response for DeleteRangeRequest");
+        };
+        delete_res.header = res.header;
+        Ok(delete_res)
+    }
+
+    /// Reject a delete that is not a plain one, the prefix moves and the conditional
+    /// deletes, if it may delete the immutable keys
+    pub(crate) fn check_delete(&self, req: &DeleteRangeRequest) -> Result<(), tonic::Status> {
+        if self.compares(&req.key, &req.range_end).is_empty() {
+            return Ok(());
+        }
+        Err(Self::rejection())
+    }
+
+    /// Check that a txn only creates the immutable keys
+    pub(crate) fn check_txn(&self, txn: &TxnRequest) -> Result<(), tonic::Status> {
+        if self.prefixes.is_empty() {
+            return Ok(());
+        }
+        self.check_ops(txn, &[])
+    }
+
+    /// Check the ops of a txn, `absent` are the keys compared to be absent by the
+    /// enclosing txns whose success branches contain it
+    fn check_ops(&self, txn: &TxnRequest, absent: &[&[u8]]) -> Result<(), tonic::Status> {
+        let success_absent: Vec<&[u8]> = absent
+            .iter()
+            .copied()
+            .chain(
+                txn.compare
+                    .iter()
+                    .filter(|cmp| Self::is_absence_check(cmp))
+                    .map(|cmp| cmp.key.as_slice()),
+            )
+            .collect();
+        let branches = [
+            (&txn.success, success_absent.as_slice()),
+            (&txn.failure, absent),
+        ];
+        for (ops, absent_keys) in branches {
+            for request in ops.iter().filter_map(|op| op.request.as_ref()) {
+                match *request {
+                    Request::RequestPut(ref put) => {
+                        if !self.is_immutable(&put.key) {
+                            continue;
+                        }
+                        if put.lease != 0 || !absent_keys.contains(&put.key.as_slice()) {
+                            return Err(tonic::Status::failed_precondition(
+                                "a put to an immutable key in a txn requires a compare of its \
+                                 version with 0 and no lease",
+                            ));
+                        }
+                    }
+                    Request::RequestDeleteRange(ref delete) => self.check_delete(delete)?,
+                    Request::RequestTxn(ref nested) => self.check_ops(nested, absent_keys)?,
+                    Request::RequestRange(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a compare checks a single key is absent
+    fn is_absence_check(cmp: &Compare) -> bool {
+        cmp.range_end.is_empty()
+            && cmp.result() == CompareResult::Equal
+            && cmp.target() == CompareTarget::Version
+            && cmp.target_union == Some(TargetUnion::Version(0))
+    }
+
+    /// The compares checking the immutable keys in a range are absent
+    fn compares(&self, key: &[u8], range_end: &[u8]) -> Vec<Compare> {
+        if range_end.is_empty() {
+            return if self.is_immutable(key) {
+                vec![Self::absent(key.to_vec(), vec![])]
+            } else {
+                vec![]
+            };
+        }
+        self.prefixes
+            .iter()
+            .filter_map(|prefix| {
+                let (start, end) =
+                    intersect((key, range_end), (prefix, &KeyRange::get_prefix(prefix)))?;
+                Some(Self::absent(start, end))
+            })
+            .collect()
+    }
+
+    /// A compare checking the keys in a range are absent
+    fn absent(key: Vec<u8>, range_end: Vec<u8>) -> Compare {
+        Compare {
+            result: CompareResult::Equal.into(),
+            target: CompareTarget::Version.into(),
+            key,
+            range_end,
+            target_union: Some(TargetUnion::Version(0)),
+        }
+    }
+}
+
+/// Intersect two ranges, whose ends are exclusive and `[0]` means unbounded, `None`
+/// if they don't overlap
+fn intersect(a: (&[u8], &[u8]), b: (&[u8], &[u8])) -> Option<(Vec<u8>, Vec<u8>)> {
+    let start = a.0.max(b.0);
+    let end = match (a.1, b.1) {
+        ([0], end) | (end, [0]) => end,
+        (a_end, b_end) => a_end.min(b_end),
+    };
+    (end == [0] || start < end).then(|| (start.to_vec(), end.to_vec()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn put(key: &str) -> PutRequest {
+        PutRequest {
+            key: key.into(),
+            value: b"v".to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn delete(key: &str, range_end: &str) -> DeleteRangeRequest {
+        DeleteRangeRequest {
+            key: key.into(),
+            range_end: range_end.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn writes_of_immutable_keys_should_be_guarded() {
+        let immutable = ImmutablePrefixes::new(&["audit/".to_owned()]);
+        assert!(immutable.guard_put(&put("other"), None).unwrap().is_none());
+        let txn = immutable.guard_put(&put("audit/1"), None).unwrap().unwrap();
+        assert!(ImmutablePrefixes::is_absence_check(&txn.compare[0]));
+        assert_eq!(txn.failure.len(), 1);
+        let attach = PutRequest {
+            lease: 1,
+            ..put("audit/1")
+        };
+        assert!(immutable.guard_put(&attach, None).is_err());
+
+        assert!(immutable.guard_delete(&delete("other", "")).is_none());
+        assert!(immutable.guard_delete(&delete("a", "audit/")).is_none());
+        let txn = immutable.guard_delete(&delete("a", "b")).unwrap();
+        assert_eq!(txn.compare[0].key, b"audit/");
+        assert_eq!(txn.compare[0].range_end, b"audit0");
+        let txn = immutable.guard_delete(&delete("audit/5", "\0")).unwrap();
+        assert_eq!(txn.compare[0].key, b"audit/5");
+        assert_eq!(txn.compare[0].range_end, b"audit0");
+        assert!(immutable.check_delete(&delete("audit/1", "")).is_err());
+    }
+
+    #[test]
+    fn txns_should_only_create_immutable_keys() {
+        let immutable = ImmutablePrefixes::new(&["audit/".to_owned()]);
+        let blind = TxnRequest {
+            compare: vec![],
+            success: vec![RequestOp {
+                request: Some(Request::RequestPut(put("audit/1"))),
+            }],
+            failure: vec![],
+        };
+        assert!(immutable.check_txn(&blind).is_err());
+        let create = TxnRequest {
+            compare: vec![ImmutablePrefixes::absent(b"audit/1".to_vec(), vec![])],
+            ..blind.clone()
+        };
+        assert!(immutable.check_txn(&create).is_ok());
+        let create_on_failure = TxnRequest {
+            compare: create.compare.clone(),
+            success: vec![],
+            failure: blind.success,
+        };
+        assert!(immutable.check_txn(&create_on_failure).is_err());
+        let delete = TxnRequest {
+            compare: vec![],
+            success: vec![RequestOp {
+                request: Some(Request::RequestDeleteRange(delete("audit/", "audit0"))),
+            }],
+            failure: vec![],
+        };
+        assert!(immutable.check_txn(&delete).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use tracing::debug;
+use utils::config::KeyCharset;
+use xlineapi::request_validation::ValidationError;
+
+use crate::rpc::{PutRequest, Request, TxnRequest};
+
+/// The key prefixes whose keys must consist of the characters of a key charset.
+///
+/// The puts are checked before they are proposed, a put of a key containing a byte
+/// disallowed by the charset fails with `ValidationError::DisallowedKeyCharacter`.
+/// The whole key is checked, including its prefix. Only the keys being written are
+/// checked, so the existing keys can still be read and deleted.
+#[derive(Debug, Default)]
+pub(crate) struct KeyCharsetPrefixes {
+    /// The characters the keys must consist of
+    charset: KeyCharset,
+    /// The prefixes whose keys are checked
+    prefixes: Vec<Vec<u8>>,
+}
+
+impl KeyCharsetPrefixes {
+    /// New `KeyCharsetPrefixes`
+    pub(crate) fn new(charset: KeyCharset, prefixes: &[String]) -> Self {
+        Self {
+            charset,
+            prefixes: prefixes.iter().map(|p| p.as_bytes().to_vec()).collect(),
+        }
+    }
+
+    /// Whether nothing is checked
+    fn is_disabled(&self) -> bool {
+        self.charset == KeyCharset::Any || self.prefixes.is_empty()
+    }
+
+    /// Check the key of a put
+    pub(crate) fn check_put(&self, put: &PutRequest) -> Result<(), ValidationError> {
+        if self.is_disabled() || !self.prefixes.iter().any(|p| put.key.starts_with(p)) {
+            return Ok(());
+        }
+        if let Some(pos) = put.key.iter().position(|&b| !self.charset.allows(b)) {
+            debug!(
+                "key {} has a disallowed byte at {pos}",
+                String::from_utf8_lossy(&put.key)
+            );
+            return Err(ValidationError::DisallowedKeyCharacter);
+        }
+        Ok(())
+    }
+
+    /// Check the keys of all the puts of a txn, including the nested ones
+    pub(crate) fn check_txn(&self, txn: &TxnRequest) -> Result<(), ValidationError> {
+        if self.is_disabled() {
+            return Ok(());
+        }
+        for request in txn
+            .success
+            .iter()
+            .chain(txn.failure.iter())
+            .filter_map(|op| op.request.as_ref())
+        {
+            match *request {
+                Request::RequestPut(ref put) => self.check_put(put)?,
+                Request::RequestTxn(ref txn) => self.check_txn(txn)?,
+                Request::RequestRange(_) | Request::RequestDeleteRange(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::RequestOp;
+
+    fn put(key: &[u8]) -> PutRequest {
+        PutRequest {
+            key: key.to_vec(),
+            value: b"value".to_vec(),
+            ..PutRequest::default()
+        }
+    }
+
+    #[test]
+    fn keys_under_the_prefixes_should_only_have_allowed_bytes() {
+        let prefixes = KeyCharsetPrefixes::new(KeyCharset::NoControl, &["names/".to_owned()]);
+        assert!(prefixes.check_put(&put(b"names/caf\xc3\xa9")).is_ok());
+        assert!(prefixes.check_put(&put(b"other/a\nb")).is_ok());
+        assert_eq!(
+            prefixes.check_put(&put(b"names/a\nb")),
+            Err(ValidationError::DisallowedKeyCharacter)
+        );
+
+        let printable = KeyCharsetPrefixes::new(KeyCharset::Printable, &["names/".to_owned()]);
+        assert!(printable.check_put(&put(b"names/a b~")).is_ok());
+        assert_eq!(
+            printable.check_put(&put(b"names/caf\xc3\xa9")),
+            Err(ValidationError::DisallowedKeyCharacter)
+        );
+
+        let any = KeyCharsetPrefixes::new(KeyCharset::Any, &["names/".to_owned()]);
+        assert!(any.check_put(&put(b"names/\x00")).is_ok());
+
+        let nested = TxnRequest {
+            compare: vec![],
+            success: vec![RequestOp {
+                request: Some(Request::RequestTxn(TxnRequest {
+                    compare: vec![],
+                    success: vec![],
+                    failure: vec![RequestOp {
+                        request: Some(Request::RequestPut(put(b"names/\x7f"))),
+                    }],
+                })),
+            }],
+            failure: vec![],
+        };
+        assert_eq!(
+            prefixes.check_txn(&nested),
+            Err(ValidationError::DisallowedKeyCharacter)
+        );
+    }
+}
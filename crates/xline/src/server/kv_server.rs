@@ -3,34 +3,98 @@ use std::{
         atomic::{AtomicU64, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use curp::rpc::ReadState;
+use curp::{
+    members::{ClusterInfo, ServerId},
+    server::RawCurp,
+};
 use dashmap::DashMap;
 use event_listener::Event;
-use futures::future::{join_all, Either};
-use tokio::time::timeout;
+use futures::future::Either;
+use parking_lot::Mutex;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::timeout,
+};
+#[cfg(not(madsim))]
+use tonic::transport::ClientTlsConfig;
+use tonic::{
+    metadata::{Ascii, MetadataValue},
+    transport::{Channel, Endpoint},
+};
 use tracing::{debug, instrument};
+#[cfg(madsim)]
+use utils::ClientTlsConfig;
+use utils::{
+    build_endpoint,
+    config::{
+        KeyCharset, KeyValueEncoding, LeaderlessReads, MessageSizeLimits, SnapshotInstallReads,
+    },
+};
 use xlineapi::{
-    command::{Command, CommandResponse, CurpClient, SyncResponse},
+    command::{Command, CommandResponse, CurpClient, KeyRange, SyncResponse},
     execute_error::ExecuteError,
-    request_validation::RequestValidator,
-    AuthInfo, ResponseWrapper,
+    request_validation::{EmptyValueValidator, EncodingValidator, RequestValidator},
+    AuthInfo, CommandKeys, ResponseWrapper,
 };
 
-use super::barriers::{IdBarrier, IndexBarrier};
+use super::{
+    access_tracker::{AccessTracker, LAST_ACCESS_KEY},
+    archive_sink::{ArchiveSink, ARCHIVE_READ_KEY},
+    guarded_write::GuardedPrefixes,
+    immutable_keys::ImmutablePrefixes,
+    key_charset::KeyCharsetPrefixes,
+    lease_guard::LeaseGuardedPrefixes,
+    propose_throttle::ProposeThrottle,
+    range_token::{RangeToken, RANGE_TOKEN_KEY},
+    read_index::ReadIndexWaiter,
+    request_log::log_sampled,
+    request_order::{OrderGuard, RequestSequencer},
+    user_stats::{kvs_size, put_size, TxnWrites, UserStats},
+    value_validator::ValueValidators,
+    write_coalescer::WriteCoalescer,
+};
 use crate::{
-    metrics,
     revision_check::RevisionCheck,
     rpc::{
         CompactionRequest, CompactionResponse, DeleteRangeRequest, DeleteRangeResponse, Kv,
-        PutRequest, PutResponse, RangeRequest, RangeResponse, RequestWrapper, Response, ResponseOp,
-        TxnRequest, TxnResponse,
+        KvClient, PutRequest, PutResponse, RangeRequest, RangeResponse, RequestWrapper, Response,
+        ResponseOp, SortOrder, TxnRequest, TxnResponse,
     },
+    state::State,
     storage::{storage_api::StorageApi, AuthStore, KvStore},
 };
 
+/// Metadata key which carries the client urls of the current leader when
+/// a write request is served by a follower
+pub(crate) const LEADER_ENDPOINT_KEY: &str = "leader-endpoint";
+
+/// Metadata key which carries the compacted revision of the node when a kv request is
+/// served, 0 if nothing is compacted yet. The revisions below it can't be read or
+/// watched from.
+pub(crate) const COMPACT_REVISION_KEY: &str = "compact-revision";
+
+/// Metadata key which marks a write request forwarded by a follower, such a
+/// request is never forwarded again
+const FORWARDED_WRITE_KEY: &str = "forwarded-write";
+
+/// Metadata key which marks a serializable read served while the node is
+/// installing a snapshot, the response may be outdated
+const STALE_READ_KEY: &str = "stale-read";
+
+/// Metadata key which asks a serializable read to be served after the node applies
+/// the commit index it learns from the leader, the read fails if the node doesn't
+/// catch up within the catch-up read timeout
+pub(crate) const CATCH_UP_READ_KEY: &str = "catch-up-read";
+
+/// Metadata key of a write asking for how long its command took from the proposal to
+/// the apply, the response carries it in microseconds. It is only honored if the
+/// server reports the apply latencies, such a write is proposed by the slow path so
+/// that it returns after the apply, a coalesced put doesn't report it.
+pub(crate) const APPLY_LATENCY_KEY: &str = "apply-latency";
+
 /// KV Server
 pub(crate) struct KvServer<S>
 where
@@ -40,12 +104,8 @@ where
     kv_storage: Arc<KvStore<S>>,
     /// Auth storage
     auth_storage: Arc<AuthStore<S>>,
-    /// Barrier for applied index
-    index_barrier: Arc<IndexBarrier>,
-    /// Barrier for propose id
-    id_barrier: Arc<IdBarrier>,
-    /// Range request retry timeout
-    range_retry_timeout: Duration,
+    /// Read index waiter
+    read_index_waiter: Arc<ReadIndexWaiter>,
     /// Compact timeout
     compact_timeout: Duration,
     /// Consensus client
@@ -54,6 +114,52 @@ where
     compact_events: Arc<DashMap<u64, Arc<Event>>>,
     /// Next compact_id
     next_compact_id: AtomicU64,
+    /// Cluster information
+    cluster_info: Arc<ClusterInfo>,
+    /// Raw curp
+    raw_curp: Arc<RawCurp<Command, State<S, Arc<CurpClient>>>>,
+    /// Whether puts with an empty value are rejected
+    reject_empty_value_put: bool,
+    /// Whether the writes received by a follower are forwarded to the leader
+    forward_writes_to_leader: bool,
+    /// Client tls config
+    client_tls_config: Option<ClientTlsConfig>,
+    /// The message size limits of the writes forwarded to the leader
+    client_message_size: MessageSizeLimits,
+    /// The channel to the current leader and its id
+    leader_channel: Mutex<Option<(u64, Channel)>>,
+    /// Coalescer of the puts hinted by clients
+    write_coalescer: Arc<WriteCoalescer>,
+    /// How serializable reads are handled while a snapshot is being installed
+    snapshot_install_reads: SnapshotInstallReads,
+    /// The encoding that the keys and values of requests must be valid in
+    key_value_encoding: KeyValueEncoding,
+    /// How linearizable reads are handled while there is no known leader
+    leaderless_reads: LeaderlessReads,
+    /// The maximum time a linearizable read waits for a leader to be elected
+    leaderless_read_timeout: Duration,
+    /// The key prefixes which can only be put by compare-and-set
+    guarded_prefixes: GuardedPrefixes,
+    /// The key prefixes whose lease-attached keys can't be silently detached
+    lease_guarded_prefixes: LeaseGuardedPrefixes,
+    /// The last read times of keys
+    access_tracker: AccessTracker,
+    /// Whether the writes can ask for their apply latencies
+    report_apply_latency: bool,
+    /// Orders the numbered requests of each connection
+    request_sequencer: RequestSequencer,
+    /// Validators of the values under key prefixes
+    value_validators: ValueValidators,
+    /// The key prefixes whose keys must consist of the characters of a charset
+    key_charset_prefixes: KeyCharsetPrefixes,
+    /// Throttle of the proposals in flight
+    propose_throttle: ProposeThrottle,
+    /// The key prefixes whose keys are write-once
+    immutable_prefixes: ImmutablePrefixes,
+    /// The sink archiving the versions removed by the compaction
+    archive_sink: Option<Arc<dyn ArchiveSink>>,
+    /// The request statistics of the users
+    user_stats: Arc<UserStats>,
 }
 
 impl<S> KvServer<S>
@@ -65,24 +171,261 @@ where
     pub(crate) fn new(
         kv_storage: Arc<KvStore<S>>,
         auth_storage: Arc<AuthStore<S>>,
-        index_barrier: Arc<IndexBarrier>,
-        id_barrier: Arc<IdBarrier>,
-        range_retry_timeout: Duration,
+        read_index_waiter: Arc<ReadIndexWaiter>,
         compact_timeout: Duration,
         client: Arc<CurpClient>,
         compact_events: Arc<DashMap<u64, Arc<Event>>>,
+        cluster_info: Arc<ClusterInfo>,
+        raw_curp: Arc<RawCurp<Command, State<S, Arc<CurpClient>>>>,
+        reject_empty_value_put: bool,
+        forward_writes_to_leader: bool,
+        client_tls_config: Option<ClientTlsConfig>,
+        client_message_size: MessageSizeLimits,
+        max_write_coalescing_window: Duration,
+        snapshot_install_reads: SnapshotInstallReads,
+        key_value_encoding: KeyValueEncoding,
+        leaderless_reads: LeaderlessReads,
+        leaderless_read_timeout: Duration,
+        guarded_prefixes: &[String],
+        lease_guarded_prefixes: &[String],
+        track_last_access: bool,
+        report_apply_latency: bool,
+        max_request_order_wait: Duration,
+        value_validators: ValueValidators,
+        key_charset: KeyCharset,
+        key_charset_prefixes: &[String],
+        max_inflight_proposals: usize,
+        immutable_prefixes: &[String],
+        archive_sink: Option<Arc<dyn ArchiveSink>>,
+        user_stats: Arc<UserStats>,
     ) -> Self {
+        let write_coalescer = Arc::new(WriteCoalescer::new(
+            Arc::clone(&client),
+            max_write_coalescing_window,
+        ));
         Self {
             kv_storage,
             auth_storage,
-            index_barrier,
-            id_barrier,
-            range_retry_timeout,
+            read_index_waiter,
             compact_timeout,
             client,
             compact_events,
             next_compact_id: AtomicU64::new(0),
+            cluster_info,
+            raw_curp,
+            reject_empty_value_put,
+            forward_writes_to_leader,
+            client_tls_config,
+            client_message_size,
+            leader_channel: Mutex::new(None),
+            write_coalescer,
+            snapshot_install_reads,
+            key_value_encoding,
+            leaderless_reads,
+            leaderless_read_timeout,
+            guarded_prefixes: GuardedPrefixes::new(guarded_prefixes),
+            lease_guarded_prefixes: LeaseGuardedPrefixes::new(lease_guarded_prefixes),
+            access_tracker: AccessTracker::new(track_last_access),
+            report_apply_latency,
+            request_sequencer: RequestSequencer::new(max_request_order_wait),
+            value_validators,
+            key_charset_prefixes: KeyCharsetPrefixes::new(key_charset, key_charset_prefixes),
+            propose_throttle: ProposeThrottle::new(max_inflight_proposals),
+            immutable_prefixes: ImmutablePrefixes::new(immutable_prefixes),
+            archive_sink,
+            user_stats,
+        }
+    }
+
+    /// Get the user counted by the user statistics of a request, `None` if it has no
+    /// authenticated user
+    fn request_user<T>(&self, request: &tonic::Request<T>) -> Option<String> {
+        self.auth_storage
+            .try_get_auth_info_from_request(request)
+            .ok()
+            .flatten()
+            .map(|auth_info| auth_info.username)
+    }
+
+    /// Whether a write unlocks the immutable keys, it fails if the write asks to unlock
+    /// them but is not of an admin
+    fn unlocks_immutable<T>(&self, request: &tonic::Request<T>) -> Result<bool, tonic::Status> {
+        if !ImmutablePrefixes::asks_unlock(request.metadata()) {
+            return Ok(false);
+        }
+        self.auth_storage.check_admin_request(request)?;
+        Ok(true)
+    }
+
+    /// Get a kv client of the leader if the write request should be forwarded to it,
+    /// return `None` if the write should be proposed by the current node
+    ///
+    /// A request which has already been forwarded is proposed by the receiver even
+    /// if the leader has changed in the meantime, so there is no forwarding loop.
+    fn forward_client<T>(
+        &self,
+        request: &tonic::Request<T>,
+    ) -> Result<Option<KvClient<Channel>>, tonic::Status> {
+        if !self.forward_writes_to_leader || request.metadata().contains_key(FORWARDED_WRITE_KEY) {
+            return Ok(None);
+        }
+        let (leader_id, _term, is_leader) = self.raw_curp.leader();
+        if is_leader {
+            return Ok(None);
+        }
+        let Some(leader_id) = leader_id else {
+            return Err(tonic::Status::unavailable(
+                "the leader is unknown, please retry later",
+            ));
+        };
+        let mut leader_channel = self.leader_channel.lock();
+        if let Some((id, ref channel)) = *leader_channel {
+            if id == leader_id {
+                return Ok(Some(self.leader_client(channel.clone())));
+            }
+        }
+        let client_urls = self
+            .cluster_info
+            .client_urls(leader_id)
+            .filter(|urls| !urls.is_empty())
+            .ok_or_else(|| {
+                tonic::Status::unavailable(
+                    "the address of the leader is unknown, please retry later",
+                )
+            })?;
+        let endpoints = client_urls
+            .iter()
+            .map(|addr| {
+                build_endpoint(addr, self.client_tls_config.as_ref())
+                    .map_err(|e| tonic::Status::internal(e.to_string()))
+            })
+            .collect::<Result<Vec<Endpoint>, _>>()?;
+        let channel = Channel::balance_list(endpoints.into_iter());
+        *leader_channel = Some((leader_id, channel.clone()));
+        Ok(Some(self.leader_client(channel)))
+    }
+
+    /// Get a kv client of a channel to the leader, the forwarded writes and their
+    /// responses are bounded by the message size limits of the client listener
+    fn leader_client(&self, channel: Channel) -> KvClient<Channel> {
+        with_message_size!(
+            KvClient::new(channel),
+            *self.client_message_size.max_send(),
+            *self.client_message_size.max_recv()
+        )
+    }
+
+    /// Check a write against the write rates of the roles of its user after its
+    /// permission, the check is skipped if no role has a quota. The key and byte
+    /// limits of the roles are checked when the write is applied.
+    fn check_write_rate<T>(
+        &self,
+        request: &T,
+        auth_info: Option<&AuthInfo>,
+    ) -> Result<(), tonic::Status>
+    where
+        T: Clone + Into<RequestWrapper>,
+    {
+        if !self.auth_storage.has_role_quotas() {
+            return Ok(());
+        }
+        let wrapper = request.clone().into();
+        self.auth_storage.check_permission(&wrapper, auth_info)?;
+        self.auth_storage.check_write_rate(&wrapper, auth_info)?;
+        Ok(())
+    }
+
+    /// Build the request forwarded to the leader, it carries the metadata of the
+    /// original request, e.g. the auth token
+    fn forwarded_request<T>(request: tonic::Request<T>) -> tonic::Request<T> {
+        let (mut metadata, _extensions, message) = request.into_parts();
+        let _prev = metadata.insert(FORWARDED_WRITE_KEY, MetadataValue::from_static("true"));
+        tonic::Request::from_parts(metadata, tonic::Extensions::default(), message)
+    }
+
+    /// Get the client urls of the current leader, return `None` if the current
+    /// node is the leader or the leader is unknown
+    fn leader_endpoint(&self) -> Option<MetadataValue<Ascii>> {
+        let (leader_id, _term, is_leader) = self.raw_curp.leader();
+        if is_leader {
+            return None;
+        }
+        let client_urls = self.cluster_info.client_urls(leader_id?)?;
+        if client_urls.is_empty() {
+            return None;
+        }
+        client_urls.join(",").parse().ok()
+    }
+
+    /// Attach the compacted revision to the metadata of a response, so that the client
+    /// always knows the lower bound of the revisions it can read or watch from
+    fn with_compact_revision<T>(&self, mut response: tonic::Response<T>) -> tonic::Response<T> {
+        let compacted = self.kv_storage.compacted_revision().max(0);
+        let _prev = response
+            .metadata_mut()
+            .insert(COMPACT_REVISION_KEY, MetadataValue::from(compacted));
+        response
+    }
+
+    /// Attach the leader endpoint to the metadata of a write result, so that the
+    /// client can retarget the leader without a separate `MemberList`
+    fn with_leader_endpoint<T>(
+        &self,
+        result: Result<tonic::Response<T>, tonic::Status>,
+    ) -> Result<tonic::Response<T>, tonic::Status> {
+        let Some(endpoint) = self.leader_endpoint() else {
+            return result;
+        };
+        match result {
+            Ok(mut response) => {
+                let _prev = response
+                    .metadata_mut()
+                    .insert(LEADER_ENDPOINT_KEY, endpoint);
+                Ok(response)
+            }
+            Err(mut status) => {
+                let _prev = status.metadata_mut().insert(LEADER_ENDPOINT_KEY, endpoint);
+                Err(status)
+            }
+        }
+    }
+
+    /// Wait until a request numbered by the `request-seq` metadata can be handled in
+    /// the order of its connection, a forwarded request has been ordered by the
+    /// follower forwarding it
+    async fn order_request<T: CommandKeys>(
+        &self,
+        request: &tonic::Request<T>,
+    ) -> Result<Option<OrderGuard>, tonic::Status> {
+        if request.metadata().contains_key(FORWARDED_WRITE_KEY) {
+            return Ok(None);
+        }
+        self.request_sequencer
+            .order(request.remote_addr(), request.metadata(), || {
+                request.get_ref().keys()
+            })
+            .await
+    }
+
+    /// Get the instant a write is proposed at if it asks for its apply latency
+    fn apply_latency_start<T>(&self, request: &tonic::Request<T>) -> Option<Instant> {
+        (self.report_apply_latency && request.metadata().contains_key(APPLY_LATENCY_KEY))
+            .then(Instant::now)
+    }
+
+    /// Attach the apply latency of a write proposed at `start` to the metadata of
+    /// its response
+    fn with_apply_latency<T>(
+        mut response: tonic::Response<T>,
+        start: Option<Instant>,
+    ) -> tonic::Response<T> {
+        if let Some(start) = start {
+            let micros = u64::try_from(start.elapsed().as_micros()).unwrap_or(u64::MAX);
+            let _prev = response
+                .metadata_mut()
+                .insert(APPLY_LATENCY_KEY, MetadataValue::from(micros));
         }
+        response
     }
 
     /// Parse `ResponseOp`
@@ -94,6 +437,15 @@ where
         }
     }
 
+    /// Parse `TxnResponse` from `Response`
+    fn parse_txn_response(res: Response) -> TxnResponse {
+        if let Response::ResponseTxn(response) = res {
+            response
+        } else {
+            unreachable!("Receive wrong response {res:?} for TxnRequest");
+        }
+    }
+
     /// serializable execute request in current node
     fn do_serializable(&self, command: &Command) -> Result<Response, tonic::Status> {
         self.auth_storage
@@ -112,8 +464,14 @@ where
     where
         T: Into<RequestWrapper>,
     {
+        let _inflight = self.propose_throttle.enter()?;
         let request = request.into();
-        let cmd = Command::new_with_auth_info(request.keys(), request, auth_info);
+        let mut keys = request.keys();
+        keys.extend(
+            self.auth_storage
+                .role_quota_ranges(&request, auth_info.as_ref())?,
+        );
+        let cmd = Command::new_with_auth_info(keys, request, auth_info);
         let res = self.client.propose(&cmd, None, use_fast_path).await??;
         Ok(res)
     }
@@ -159,157 +517,445 @@ where
             .ok_or(ExecuteError::RevisionCompacted(range_revision, compacted_revision).into())
     }
 
-    /// Wait current node's state machine apply the conflict commands
-    async fn wait_read_state(&self, cmd: &Command) -> Result<(), tonic::Status> {
-        loop {
-            let rd_state = self.client.fetch_read_state(cmd).await.map_err(|e| {
-                metrics::get().read_indexes_failed_total.add(1, &[]);
-                e
-            })?;
-            let wait_future = async move {
-                match rd_state {
-                    ReadState::Ids(id_set) => {
-                        debug!(?id_set, "Range wait for command ids");
-                        let fus = id_set
-                            .inflight_ids
-                            .into_iter()
-                            .map(|id| self.id_barrier.wait(id))
-                            .collect::<Vec<_>>();
-                        let _ignore = join_all(fus).await;
-                    }
-                    ReadState::CommitIndex(index) => {
-                        debug!(?index, "Range wait for commit index");
-                        self.index_barrier.wait(index).await;
+    /// Check whether a serializable read waits for a snapshot install in progress
+    /// instead of being rejected
+    fn waits_for_snapshot_install(mode: SnapshotInstallReads) -> bool {
+        match mode {
+            SnapshotInstallReads::Unavailable => false,
+            SnapshotInstallReads::WaitForInstall => true,
+            _ => unreachable!("xline only supports two snapshot install reads modes"),
+        }
+    }
+
+    /// Check whether a linearizable read can be served by a known leader, a leader is
+    /// waited for within the timeout if the leaderless reads wait for it
+    async fn wait_leader(
+        mode: LeaderlessReads,
+        wait_timeout: Duration,
+        mut leader_rx: broadcast::Receiver<Option<ServerId>>,
+        has_leader: impl Fn() -> bool,
+    ) -> Result<(), tonic::Status> {
+        // the receiver is subscribed before the check, so that no election is missed
+        if has_leader() {
+            return Ok(());
+        }
+        let no_leader =
+            || tonic::Status::unavailable("there is no leader to serve linearizable reads");
+        match mode {
+            LeaderlessReads::Unavailable => Err(no_leader()),
+            LeaderlessReads::Wait => {
+                let elected = async {
+                    loop {
+                        match leader_rx.recv().await {
+                            Ok(Some(_)) => return true,
+                            Ok(None) => {}
+                            Err(RecvError::Lagged(_)) => {
+                                if has_leader() {
+                                    return true;
+                                }
+                            }
+                            Err(RecvError::Closed) => return false,
+                        }
                     }
+                };
+                match timeout(wait_timeout, elected).await {
+                    Ok(true) => Ok(()),
+                    Ok(false) | Err(_) => Err(no_leader()),
                 }
-            };
-            if timeout(self.range_retry_timeout, wait_future).await.is_ok() {
-                break;
             }
-            metrics::get().slow_read_indexes_total.add(1, &[]);
+            _ => unreachable!("xline only supports two leaderless reads modes"),
         }
-        Ok(())
+    }
+
+    /// Wait until current node's state machine applies the commit index of the leader
+    async fn wait_caught_up(&self) -> Result<(), tonic::Status> {
+        Self::wait_leader(
+            self.leaderless_reads,
+            self.leaderless_read_timeout,
+            self.raw_curp.leader_rx(),
+            || self.raw_curp.leader().0.is_some(),
+        )
+        .await?;
+        self.read_index_waiter
+            .wait_caught_up(self.raw_curp.commit_index())
+            .await
+    }
+
+    /// Wait current node's state machine apply the conflict commands
+    async fn wait_read_state(&self, cmd: &Command) -> Result<(), tonic::Status> {
+        Self::wait_leader(
+            self.leaderless_reads,
+            self.leaderless_read_timeout,
+            self.raw_curp.leader_rx(),
+            || self.raw_curp.leader().0.is_some(),
+        )
+        .await?;
+        self.read_index_waiter.wait(cmd).await
     }
 }
 
-#[tonic::async_trait]
-impl<S> Kv for KvServer<S>
+impl<S> KvServer<S>
 where
     S: StorageApi,
 {
-    /// Range gets the keys in the range from the key-value store.
+    /// Serve a range at a compacted revision from the archive, `None` if the range is
+    /// not compacted and is served as usual
+    async fn range_archived(
+        &self,
+        range_req: &RangeRequest,
+        auth_info: Option<&AuthInfo>,
+    ) -> Result<Option<RangeResponse>, tonic::Status> {
+        let Some(ref sink) = self.archive_sink else {
+            return Err(tonic::Status::failed_precondition(
+                "the compacted versions are not archived",
+            ));
+        };
+        let compacted_revision = self.kv_storage.compacted_revision();
+        let compacted = range_req.revision > 0
+            && range_req.revision < compacted_revision
+            && !self
+                .kv_storage
+                .compact_protection()
+                .covers(&range_req.key, &range_req.range_end);
+        if !compacted {
+            return Ok(None);
+        }
+        if range_req.sort_order() != SortOrder::None
+            || range_req.max_mod_revision != 0
+            || range_req.min_mod_revision != 0
+            || range_req.max_create_revision != 0
+            || range_req.min_create_revision != 0
+        {
+            return Err(tonic::Status::invalid_argument(
+                "a range from the archive can't be sorted or filtered by the revisions",
+            ));
+        }
+        // the versions of an unfinished compaction may be neither archived nor retained
+        let compacting = || {
+            self.kv_storage.compaction_backlog() > 0
+                || self.kv_storage.compacted_revision() != compacted_revision
+        };
+        if compacting() {
+            return Err(Self::archiving_status());
+        }
+        self.auth_storage
+            .check_permission(&RequestWrapper::from(range_req.clone()), auth_info)?;
+        let key_range = KeyRange::new(range_req.key.as_slice(), range_req.range_end.as_slice());
+        let archived = sink
+            .versions(&key_range, range_req.revision)
+            .await
+            .map_err(|e| tonic::Status::unavailable(format!("failed to read the archive: {e}")))?;
+        let response = self
+            .kv_storage
+            .handle_archived_range_request(range_req, archived)?;
+        // a compaction during the read may have removed the versions read from neither
+        if compacting() {
+            return Err(Self::archiving_status());
+        }
+        Ok(Some(response))
+    }
+
+    /// The status of a range from the archive during a compaction
+    fn archiving_status() -> tonic::Status {
+        tonic::Status::unavailable("the compacted versions are being archived, retry later")
+    }
+
+    /// Handle a `RangeRequest`
     #[instrument(skip_all)]
-    async fn range(
+    async fn handle_range(
         &self,
         request: tonic::Request<RangeRequest>,
     ) -> Result<tonic::Response<RangeResponse>, tonic::Status> {
+        let _order = self.order_request(&request).await?;
         let range_req = request.get_ref();
         range_req.validation()?;
+        range_req.validate_encoding(self.key_value_encoding)?;
         debug!("Receive grpc request: {}", range_req);
-        range_req.check_revision(
+        let last_access = request.metadata().contains_key(LAST_ACCESS_KEY);
+        let catch_up = request.metadata().contains_key(CATCH_UP_READ_KEY);
+        let archive_read = request.metadata().contains_key(ARCHIVE_READ_KEY);
+        if last_access {
+            self.auth_storage.check_admin_request(&request)?;
+        }
+        let token = RangeToken::from_metadata(request.metadata())?;
+        let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
+        let mut range_req = request.into_inner();
+        if let Some(token) = token {
+            token.resume(&mut range_req)?;
+        }
+        if archive_read {
+            if let Some(response) = self.range_archived(&range_req, auth_info.as_ref()).await? {
+                return Ok(tonic::Response::new(response));
+            }
+        }
+        range_req.check_revision_with_protection(
             self.kv_storage.compacted_revision(),
             self.kv_storage.revision(),
+            self.kv_storage.compact_protection(),
         )?;
-        let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
+        let is_protected = self
+            .kv_storage
+            .compact_protection()
+            .covers(&range_req.key, &range_req.range_end);
         let range_required_revision = range_req.revision;
         let is_serializable = range_req.serializable;
-        let request = RequestWrapper::from(request.into_inner());
-        let cmd = Command::new_with_auth_info(request.keys(), request, auth_info);
-        if !is_serializable {
-            self.wait_read_state(&cmd).await?;
+        let new_cmd = |range_req: RangeRequest| {
+            let request = RequestWrapper::from(range_req);
+            Command::new_with_auth_info(request.keys(), request, auth_info.clone())
+        };
+        let mut cmd = new_cmd(range_req.clone());
+        if !is_serializable || catch_up {
+            if is_serializable {
+                self.wait_caught_up().await?;
+            } else {
+                self.wait_read_state(&cmd).await?;
+            }
             // Double check whether the range request is compacted or not since the compaction request
             // may be executed during the process of `wait_read_state` which results in the result of
             // previous `check_range_request` outdated.
-            Self::check_range_compacted(
-                range_required_revision,
-                self.kv_storage.compacted_revision(),
-            )?;
+            if !is_protected {
+                Self::check_range_compacted(
+                    range_required_revision,
+                    self.kv_storage.compacted_revision(),
+                )?;
+            }
+        }
+        // The first page of a paged range is pinned to the current revision, so that the
+        // following pages are served from the same snapshot
+        if range_req.limit > 0 && range_req.revision <= 0 {
+            range_req.revision = self.kv_storage.revision();
+            cmd = new_cmd(range_req.clone());
         }
 
+        // a linearizable read always waits for a snapshot install in progress
+        let read_gate = self
+            .kv_storage
+            .begin_serializable_read(
+                !is_serializable || Self::waits_for_snapshot_install(self.snapshot_install_reads),
+            )
+            .await
+            .ok_or_else(|| {
+                tonic::Status::unavailable(
+                    "serializable reads are not served while a snapshot is being installed",
+                )
+            })?;
+        let stale = is_serializable && read_gate.waited_for_install();
         let res = self.do_serializable(&cmd)?;
+        drop(read_gate);
         if let Response::ResponseRange(response) = res {
-            Ok(tonic::Response::new(response))
+            let next_token = RangeToken::next_page(&range_req, &response);
+            let last_read = if last_access {
+                Some(self.access_tracker.last_read(&response.kvs)?)
+            } else {
+                self.access_tracker.record(&response.kvs);
+                None
+            };
+            let mut response = tonic::Response::new(response);
+            if let Some(value) = last_read {
+                let _prev = response.metadata_mut().insert(LAST_ACCESS_KEY, value);
+            }
+            if let Some(token) = next_token {
+                let value = token
+                    .encode()
+                    .parse()
+                    .unwrap_or_else(|_e| unreachable!("the token is ascii"));
+                let _prev = response.metadata_mut().insert(RANGE_TOKEN_KEY, value);
+            }
+            if stale {
+                let _prev = response.metadata_mut().insert(
+                    STALE_READ_KEY,
+                    MetadataValue::from_static("snapshot-install"),
+                );
+            }
+            Ok(self.with_compact_revision(response))
         } else {
             unreachable!("Receive wrong response {res:?} for RangeRequest");
         }
     }
 
-    /// Put puts the given key into the key-value store.
-    /// A put request increments the revision of the key-value store
-    /// and generates one event in the event history.
+    /// Handle a `PutRequest`
     #[instrument(skip_all)]
-    async fn put(
+    async fn handle_put(
         &self,
         request: tonic::Request<PutRequest>,
     ) -> Result<tonic::Response<PutResponse>, tonic::Status> {
+        let _order = self.order_request(&request).await?;
         let put_req: &PutRequest = request.get_ref();
         put_req.validation()?;
+        put_req.validate_encoding(self.key_value_encoding)?;
+        if self.reject_empty_value_put {
+            put_req.validate_non_empty_value()?;
+        }
+        self.key_charset_prefixes.check_put(put_req)?;
+        self.value_validators.check_put(put_req)?;
         debug!("Receive grpc request: {}", put_req);
-        let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
-        let is_fast_path = true;
-        let (cmd_res, sync_res) = self
-            .propose(request.into_inner(), auth_info, is_fast_path)
-            .await?;
-        let mut res = Self::parse_response_op(cmd_res.into_inner().into());
-        if let Some(sync_res) = sync_res {
-            let revision = sync_res.revision();
-            debug!("Get revision {} for PutRequest", revision);
-            Self::update_header_revision(&mut res, revision);
-        }
-        if let Response::ResponsePut(response) = res {
-            Ok(tonic::Response::new(response))
+        let compare_and_put = self
+            .guarded_prefixes
+            .compare_and_put(put_req, request.metadata())?;
+        let compare_and_put =
+            self.lease_guarded_prefixes
+                .guard(put_req, request.metadata(), compare_and_put);
+        let immutable = !self.unlocks_immutable(&request)?
+            && self.immutable_prefixes.is_immutable(&put_req.key);
+        let compare_and_put = if immutable {
+            self.immutable_prefixes
+                .guard_put(put_req, compare_and_put)?
         } else {
-            unreachable!("Receive wrong response {res:?} for PutRequest");
+            compare_and_put
+        };
+        if let Some(mut leader_client) = self.forward_client(&request)? {
+            return leader_client.put(Self::forwarded_request(request)).await;
+        }
+        let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
+        self.check_write_rate(request.get_ref(), auth_info.as_ref())?;
+        let apply_start = self.apply_latency_start(&request);
+        if let Some(txn_req) = compare_and_put {
+            let result = self
+                .propose(txn_req, auth_info, apply_start.is_none())
+                .await
+                .and_then(|(cmd_res, sync_res)| {
+                    let mut res = Self::parse_response_op(cmd_res.into_inner().into());
+                    if let Some(sync_res) = sync_res {
+                        let revision = sync_res.revision();
+                        debug!("Get revision {} for guarded PutRequest", revision);
+                        Self::update_header_revision(&mut res, revision);
+                    }
+                    let res = Self::parse_txn_response(res);
+                    if immutable {
+                        ImmutablePrefixes::check_put_response(&res)?;
+                    }
+                    LeaseGuardedPrefixes::check_response(&res)?;
+                    GuardedPrefixes::put_response(res)
+                })
+                .map(|res| Self::with_apply_latency(tonic::Response::new(res), apply_start));
+            return self
+                .with_leader_endpoint(result)
+                .map(|res| self.with_compact_revision(res));
         }
+        if let Some(window) = self.write_coalescer.window(request.metadata())? {
+            let put_req = request.into_inner();
+            // check the permission before waiting so that an unauthorized put fails fast
+            self.auth_storage
+                .check_permission(&RequestWrapper::from(put_req.clone()), auth_info.as_ref())?;
+            let result = self
+                .write_coalescer
+                .put(put_req, auth_info, window)
+                .await
+                .map(tonic::Response::new);
+            return self
+                .with_leader_endpoint(result)
+                .map(|res| self.with_compact_revision(res));
+        }
+        let is_fast_path = apply_start.is_none();
+        let result = self
+            .propose(request.into_inner(), auth_info, is_fast_path)
+            .await
+            .map(|(cmd_res, sync_res)| {
+                let mut res = Self::parse_response_op(cmd_res.into_inner().into());
+                if let Some(sync_res) = sync_res {
+                    let revision = sync_res.revision();
+                    debug!("Get revision {} for PutRequest", revision);
+                    Self::update_header_revision(&mut res, revision);
+                }
+                if let Response::ResponsePut(response) = res {
+                    Self::with_apply_latency(tonic::Response::new(response), apply_start)
+                } else {
+                    unreachable!("Receive wrong response {res:?} for PutRequest");
+                }
+            });
+        self.with_leader_endpoint(result)
+            .map(|res| self.with_compact_revision(res))
     }
 
-    /// DeleteRange deletes the given range from the key-value store.
-    /// A delete request increments the revision of the key-value store
-    /// and generates a delete event in the event history for every deleted key.
+    /// Handle a `DeleteRangeRequest`
     #[instrument(skip_all)]
-    async fn delete_range(
+    async fn handle_delete_range(
         &self,
         request: tonic::Request<DeleteRangeRequest>,
     ) -> Result<tonic::Response<DeleteRangeResponse>, tonic::Status> {
+        let _order = self.order_request(&request).await?;
         let delete_range_req = request.get_ref();
         delete_range_req.validation()?;
+        delete_range_req.validate_encoding(self.key_value_encoding)?;
         debug!("Receive grpc request: {}", delete_range_req);
+        if let Some(mut leader_client) = self.forward_client(&request)? {
+            return leader_client
+                .delete_range(Self::forwarded_request(request))
+                .await;
+        }
         let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
-        let is_fast_path = true;
-        let (cmd_res, sync_res) = self
-            .propose(request.into_inner(), auth_info, is_fast_path)
-            .await?;
-        let mut res = Self::parse_response_op(cmd_res.into_inner().into());
-        if let Some(sync_res) = sync_res {
-            let revision = sync_res.revision();
-            debug!("Get revision {} for DeleteRangeRequest", revision);
-            Self::update_header_revision(&mut res, revision);
-        }
-        if let Response::ResponseDeleteRange(response) = res {
-            Ok(tonic::Response::new(response))
-        } else {
-            unreachable!("Receive wrong response {res:?} for DeleteRangeRequest");
+        let unlocked = self.unlocks_immutable(&request)?;
+        self.check_write_rate(request.get_ref(), auth_info.as_ref())?;
+        if let Some(txn_req) = (!unlocked)
+            .then(|| self.immutable_prefixes.guard_delete(request.get_ref()))
+            .flatten()
+        {
+            let result = self
+                .propose(txn_req, auth_info, false)
+                .await
+                .and_then(|(cmd_res, sync_res)| {
+                    let mut res = Self::parse_response_op(cmd_res.into_inner().into());
+                    if let Some(sync_res) = sync_res {
+                        let revision = sync_res.revision();
+                        debug!("Get revision {} for guarded DeleteRangeRequest", revision);
+                        Self::update_header_revision(&mut res, revision);
+                    }
+                    ImmutablePrefixes::delete_response(Self::parse_txn_response(res))
+                })
+                .map(tonic::Response::new);
+            return self
+                .with_leader_endpoint(result)
+                .map(|res| self.with_compact_revision(res));
         }
+        let apply_start = self.apply_latency_start(&request);
+        let is_fast_path = apply_start.is_none();
+        let result = self
+            .propose(request.into_inner(), auth_info, is_fast_path)
+            .await
+            .map(|(cmd_res, sync_res)| {
+                let mut res = Self::parse_response_op(cmd_res.into_inner().into());
+                if let Some(sync_res) = sync_res {
+                    let revision = sync_res.revision();
+                    debug!("Get revision {} for DeleteRangeRequest", revision);
+                    Self::update_header_revision(&mut res, revision);
+                }
+                if let Response::ResponseDeleteRange(response) = res {
+                    Self::with_apply_latency(tonic::Response::new(response), apply_start)
+                } else {
+                    unreachable!("Receive wrong response {res:?} for DeleteRangeRequest");
+                }
+            });
+        self.with_leader_endpoint(result)
+            .map(|res| self.with_compact_revision(res))
     }
 
-    /// Txn processes multiple requests in a single transaction.
-    /// A txn request increments the revision of the key-value store
-    /// and generates events with the same revision for every completed request.
-    /// It is not allowed to modify the same key several times within one txn.
+    /// Handle a `TxnRequest`
     #[instrument(skip_all)]
-    async fn txn(
+    async fn handle_txn(
         &self,
         request: tonic::Request<TxnRequest>,
     ) -> Result<tonic::Response<TxnResponse>, tonic::Status> {
+        let _order = self.order_request(&request).await?;
         let txn_req = request.get_ref();
         txn_req.validation()?;
+        txn_req.validate_encoding(self.key_value_encoding)?;
+        if self.reject_empty_value_put {
+            txn_req.validate_non_empty_value()?;
+        }
+        self.key_charset_prefixes.check_txn(txn_req)?;
+        self.value_validators.check_txn(txn_req)?;
+        self.guarded_prefixes.check_txn(txn_req)?;
+        if !self.unlocks_immutable(&request)? {
+            self.immutable_prefixes.check_txn(txn_req)?;
+        }
         debug!("Receive grpc request: {}", txn_req);
-        txn_req.check_revision(
+        txn_req.check_revision_with_protection(
             self.kv_storage.compacted_revision(),
             self.kv_storage.revision(),
+            self.kv_storage.compact_protection(),
         )?;
         let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
-        let res = if txn_req.is_read_only() {
+        if txn_req.is_read_only() {
             debug!("TxnRequest is read only");
             let is_serializable = txn_req.is_serializable();
             let request = RequestWrapper::from(request.into_inner());
@@ -317,32 +963,36 @@ where
             if !is_serializable {
                 self.wait_read_state(&cmd).await?;
             }
-            self.do_serializable(&cmd)?
-        } else {
-            let is_fast_path = true;
-            let (cmd_res, sync_res) = self
-                .propose(request.into_inner(), auth_info, is_fast_path)
-                .await?;
-            let mut res = Self::parse_response_op(cmd_res.into_inner().into());
-            if let Some(sync_res) = sync_res {
-                let revision = sync_res.revision();
-                debug!("Get revision {} for TxnRequest", revision);
-                Self::update_header_revision(&mut res, revision);
-            }
-            res
-        };
-        if let Response::ResponseTxn(response) = res {
-            Ok(tonic::Response::new(response))
-        } else {
-            unreachable!("Receive wrong response {res:?} for TxnRequest");
+            let res = self.do_serializable(&cmd)?;
+            let response = tonic::Response::new(Self::parse_txn_response(res));
+            return Ok(self.with_compact_revision(response));
+        }
+        if let Some(mut leader_client) = self.forward_client(&request)? {
+            return leader_client.txn(Self::forwarded_request(request)).await;
         }
+        self.check_write_rate(request.get_ref(), auth_info.as_ref())?;
+        let apply_start = self.apply_latency_start(&request);
+        let is_fast_path = apply_start.is_none();
+        let result = self
+            .propose(request.into_inner(), auth_info, is_fast_path)
+            .await
+            .map(|(cmd_res, sync_res)| {
+                let mut res = Self::parse_response_op(cmd_res.into_inner().into());
+                if let Some(sync_res) = sync_res {
+                    let revision = sync_res.revision();
+                    debug!("Get revision {} for TxnRequest", revision);
+                    Self::update_header_revision(&mut res, revision);
+                }
+                let response = tonic::Response::new(Self::parse_txn_response(res));
+                Self::with_apply_latency(response, apply_start)
+            });
+        self.with_leader_endpoint(result)
+            .map(|res| self.with_compact_revision(res))
     }
 
-    /// Compact compacts the event history in the etcd key-value store. The key-value
-    /// store should be periodically compacted or the event history will continue to grow
-    /// indefinitely.
+    /// Handle a `CompactionRequest`
     #[instrument(skip_all)]
-    async fn compact(
+    async fn handle_compact(
         &self,
         request: tonic::Request<CompactionRequest>,
     ) -> Result<tonic::Response<CompactionResponse>, tonic::Status> {
@@ -381,10 +1031,113 @@ where
     }
 }
 
+#[tonic::async_trait]
+impl<S> Kv for KvServer<S>
+where
+    S: StorageApi,
+{
+    /// Range gets the keys in the range from the key-value store.
+    async fn range(
+        &self,
+        request: tonic::Request<RangeRequest>,
+    ) -> Result<tonic::Response<RangeResponse>, tonic::Status> {
+        let user = self.request_user(&request);
+        let handled = log_sampled(
+            "Range",
+            request,
+            |req| req.to_string(),
+            |request| self.handle_range(request),
+        );
+        self.user_stats
+            .count(user, handled, |res| (kvs_size(&res.kvs), 0))
+            .await
+    }
+
+    /// Put puts the given key into the key-value store.
+    /// A put request increments the revision of the key-value store
+    /// and generates one event in the event history.
+    async fn put(
+        &self,
+        request: tonic::Request<PutRequest>,
+    ) -> Result<tonic::Response<PutResponse>, tonic::Status> {
+        let user = self.request_user(&request);
+        let written = put_size(request.get_ref());
+        let handled = log_sampled(
+            "Put",
+            request,
+            |req| req.to_string(),
+            |request| self.handle_put(request),
+        );
+        self.user_stats
+            .count(user, handled, |res| (kvs_size(&res.prev_kv), written))
+            .await
+    }
+
+    /// DeleteRange deletes the given range from the key-value store.
+    /// A delete request increments the revision of the key-value store
+    /// and generates a delete event in the event history for every deleted key.
+    async fn delete_range(
+        &self,
+        request: tonic::Request<DeleteRangeRequest>,
+    ) -> Result<tonic::Response<DeleteRangeResponse>, tonic::Status> {
+        let user = self.request_user(&request);
+        let handled = log_sampled(
+            "DeleteRange",
+            request,
+            |req| req.to_string(),
+            |request| self.handle_delete_range(request),
+        );
+        self.user_stats
+            .count(user, handled, |res| (kvs_size(&res.prev_kvs), 0))
+            .await
+    }
+
+    /// Txn processes multiple requests in a single transaction.
+    /// A txn request increments the revision of the key-value store
+    /// and generates events with the same revision for every completed request.
+    /// It is not allowed to modify the same key several times within one txn.
+    async fn txn(
+        &self,
+        request: tonic::Request<TxnRequest>,
+    ) -> Result<tonic::Response<TxnResponse>, tonic::Status> {
+        let user = self.request_user(&request);
+        let writes = TxnWrites::new(request.get_ref());
+        let handled = log_sampled(
+            "Txn",
+            request,
+            |req| req.to_string(),
+            |request| self.handle_txn(request),
+        );
+        self.user_stats
+            .count(user, handled, |res| writes.usage(res))
+            .await
+    }
+
+    /// Compact compacts the event history in the etcd key-value store. The key-value
+    /// store should be periodically compacted or the event history will continue to grow
+    /// indefinitely.
+    async fn compact(
+        &self,
+        request: tonic::Request<CompactionRequest>,
+    ) -> Result<tonic::Response<CompactionResponse>, tonic::Status> {
+        let user = self.request_user(&request);
+        let handled = log_sampled(
+            "Compact",
+            request,
+            |req| format!("{req:?}"),
+            |request| self.handle_compact(request),
+        );
+        self.user_stats.count(user, handled, |_res| (0, 0)).await
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::rpc::{Request, RequestOp};
+    use crate::{
+        rpc::{Request, RequestOp},
+        storage::db::DB,
+    };
 
     #[test]
     fn txn_check() {
@@ -507,6 +1260,50 @@ mod test {
         assert_eq!(expected_tonic_status.code(), tonic::Code::OutOfRange);
     }
 
+    #[test]
+    fn serializable_reads_should_follow_snapshot_install_mode() {
+        type Server = KvServer<DB>;
+        assert!(!Server::waits_for_snapshot_install(
+            SnapshotInstallReads::Unavailable
+        ));
+        assert!(Server::waits_for_snapshot_install(
+            SnapshotInstallReads::WaitForInstall
+        ));
+    }
+
+    #[tokio::test]
+    async fn leaderless_reads_should_fail_fast_or_wait_for_a_leader() {
+        type Server = KvServer<DB>;
+        let (leader_tx, _leader_rx) = broadcast::channel(1);
+        let elected = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let wait_leader = |mode, wait_timeout| {
+            let elected = Arc::clone(&elected);
+            Server::wait_leader(mode, wait_timeout, leader_tx.subscribe(), move || {
+                elected.load(Ordering::Relaxed)
+            })
+        };
+
+        let status = wait_leader(LeaderlessReads::Unavailable, Duration::from_secs(10))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        let status = wait_leader(LeaderlessReads::Wait, Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+
+        // a brief election is ridden through
+        let read = tokio::spawn(wait_leader(LeaderlessReads::Wait, Duration::from_secs(10)));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!read.is_finished());
+        elected.store(true, Ordering::Relaxed);
+        let _ignore = leader_tx.send(Some(1));
+        assert!(read.await.unwrap().is_ok());
+        assert!(wait_leader(LeaderlessReads::Unavailable, Duration::ZERO)
+            .await
+            .is_ok());
+    }
+
     #[tokio::test]
     async fn test_compact_invalid_revision() {
         let compact_request = CompactionRequest {
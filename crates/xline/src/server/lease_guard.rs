@@ -0,0 +1,143 @@
+use tonic::metadata::MetadataMap;
+
+use crate::rpc::{
+    Compare, CompareResult, CompareTarget, PutRequest, RangeRequest, Request, RequestOp, Response,
+    TargetUnion, TxnRequest, TxnResponse,
+};
+
+/// Metadata key of a put which explicitly detaches its key from its lease
+pub(crate) const DETACH_LEASE_KEY: &str = "detach-lease";
+
+/// The key prefixes whose lease-attached keys can't be silently detached.
+///
+/// A put without a lease detaches its key from the lease, which is easily done by
+/// mistake when the lease is meant to be kept. A put to a guarded key must keep the
+/// lease by `ignore_lease`, attach a lease, or detach it explicitly by the
+/// `detach-lease` metadata. Any other put is proposed as a txn comparing the lease of
+/// the key with 0, so the put fails instead of detaching the key. Since it is a
+/// compare, such a put also requires the read permission of the key. The puts in
+/// txns are not guarded, a txn can compare the lease of the key by itself.
+#[derive(Debug, Default)]
+pub(crate) struct LeaseGuardedPrefixes {
+    /// The guarded prefixes
+    prefixes: Vec<Vec<u8>>,
+}
+
+impl LeaseGuardedPrefixes {
+    /// New `LeaseGuardedPrefixes`
+    pub(crate) fn new(prefixes: &[String]) -> Self {
+        Self {
+            prefixes: prefixes.iter().map(|p| p.as_bytes().to_vec()).collect(),
+        }
+    }
+
+    /// Check whether a put may silently detach its key from a lease
+    fn may_detach(&self, req: &PutRequest, metadata: &MetadataMap) -> bool {
+        req.lease == 0
+            && !req.ignore_lease
+            && !metadata.contains_key(DETACH_LEASE_KEY)
+            && self
+                .prefixes
+                .iter()
+                .any(|prefix| req.key.starts_with(prefix))
+    }
+
+    /// Guard a put against silently detaching its key, `txn` is the compare-and-set
+    /// txn of the put if any. Returns the txn to propose instead of the put, `None`
+    /// if the put is proposed as it is.
+    pub(crate) fn guard(
+        &self,
+        req: &PutRequest,
+        metadata: &MetadataMap,
+        txn: Option<TxnRequest>,
+    ) -> Option<TxnRequest> {
+        if !self.may_detach(req, metadata) {
+            return txn;
+        }
+        let mut txn = txn.unwrap_or_else(|| TxnRequest {
+            compare: vec![],
+            success: vec![RequestOp {
+                request: Some(Request::RequestPut(req.clone())),
+            }],
+            failure: vec![],
+        });
+        txn.compare.push(Compare {
+            result: CompareResult::Equal.into(),
+            target: CompareTarget::Lease.into(),
+            key: req.key.clone(),
+            range_end: vec![],
+            target_union: Some(TargetUnion::Lease(0)),
+        });
+        // read the key on failure to tell whether it failed by the lease
+        txn.failure.push(RequestOp {
+            request: Some(Request::RequestRange(RangeRequest {
+                key: req.key.clone(),
+                keys_only: true,
+                ..RangeRequest::default()
+            })),
+        });
+        Some(txn)
+    }
+
+    /// Check that a guarded put has not failed because its key is attached to a lease
+    pub(crate) fn check_response(res: &TxnResponse) -> Result<(), tonic::Status> {
+        if res.succeeded {
+            return Ok(());
+        }
+        let attached = res
+            .responses
+            .iter()
+            .filter_map(|op| match op.response {
+                Some(Response::ResponseRange(ref range)) => Some(range),
+                _ => None,
+            })
+            .flat_map(|range| range.kvs.iter())
+            .any(|kv| kv.lease != 0);
+        if attached {
+            return Err(tonic::Status::failed_precondition(
+                "the key is attached to a lease, a put must keep it by ignore_lease or \
+                 detach it explicitly by the detach-lease metadata",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn put(key: &str) -> PutRequest {
+        PutRequest {
+            key: key.into(),
+            value: b"v".to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_puts_which_may_detach_a_guarded_key_should_be_guarded() {
+        let guarded = LeaseGuardedPrefixes::new(&["services/".to_owned()]);
+        let mut metadata = MetadataMap::new();
+        assert!(guarded.guard(&put("other"), &metadata, None).is_none());
+        let keep = PutRequest {
+            ignore_lease: true,
+            ..put("services/a")
+        };
+        assert!(guarded.guard(&keep, &metadata, None).is_none());
+        let attach = PutRequest {
+            lease: 1,
+            ..put("services/a")
+        };
+        assert!(guarded.guard(&attach, &metadata, None).is_none());
+
+        let txn = guarded.guard(&put("services/a"), &metadata, None).unwrap();
+        assert_eq!(txn.compare[0].target(), CompareTarget::Lease);
+        assert_eq!(txn.compare[0].target_union, Some(TargetUnion::Lease(0)));
+        assert_eq!(txn.success.len(), 1);
+        assert_eq!(txn.failure.len(), 1);
+
+        let _ignore = metadata.insert(DETACH_LEASE_KEY, "true".parse().unwrap());
+        assert!(guarded.guard(&put("services/a"), &metadata, None).is_none());
+    }
+}
@@ -1,10 +1,15 @@
-use std::{pin::Pin, sync::Arc, time::Duration};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use async_stream::{stream, try_stream};
-use clippy_utilities::NumericCast;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
 use curp::members::ClusterInfo;
 use futures::stream::Stream;
-use tokio::time;
+use tokio::{sync::mpsc, time};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 #[cfg(not(madsim))]
 use tonic::transport::ClientTlsConfig;
 use tonic::transport::Endpoint;
@@ -18,6 +23,8 @@ use utils::{
 use xlineapi::{
     command::{Command, CommandResponse, CurpClient, KeyRange, SyncResponse},
     execute_error::ExecuteError,
+    server_op::{ImportLeasesOp, ImportLeasesOpResult, LeaseImport, ServerOp, ServerOpResult},
+    AuthInfo,
 };
 
 use crate::{
@@ -27,6 +34,7 @@ use crate::{
         Lease, LeaseClient, LeaseGrantRequest, LeaseGrantResponse, LeaseKeepAliveRequest,
         LeaseKeepAliveResponse, LeaseLeasesRequest, LeaseLeasesResponse, LeaseRevokeRequest,
         LeaseRevokeResponse, LeaseTimeToLiveRequest, LeaseTimeToLiveResponse, RequestWrapper,
+        TxnRequest, TxnResponse,
     },
     storage::{storage_api::StorageApi, AuthStore, LeaseStore},
 };
@@ -34,6 +42,13 @@ use crate::{
 /// Default Lease Request Time
 const DEFAULT_LEASE_REQUEST_TIME: Duration = Duration::from_millis(500);
 
+/// The number of keepalive responses buffered for a consumer before a send waits
+const KEEP_ALIVE_CHANNEL_SIZE: usize = 128;
+
+/// The stream of keepalive responses
+type KeepAliveStream =
+    Pin<Box<dyn Stream<Item = Result<LeaseKeepAliveResponse, tonic::Status>> + Send>>;
+
 /// Lease Server
 pub(crate) struct LeaseServer<S>
 where
@@ -51,6 +66,8 @@ where
     cluster_info: Arc<ClusterInfo>,
     /// Client tls config
     client_tls_config: Option<ClientTlsConfig>,
+    /// How long a keepalive response may wait for its consumer, 0 means forever
+    keep_alive_send_timeout: Duration,
     /// Task manager
     task_manager: Arc<TaskManager>,
 }
@@ -60,6 +77,7 @@ where
     S: StorageApi,
 {
     /// New `LeaseServer`
+    #[allow(clippy::too_many_arguments)] // Consistent with other servers
     pub(crate) fn new(
         lease_storage: Arc<LeaseStore<S>>,
         auth_storage: Arc<AuthStore<S>>,
@@ -67,6 +85,7 @@ where
         id_gen: Arc<IdGenerator>,
         cluster_info: Arc<ClusterInfo>,
         client_tls_config: Option<ClientTlsConfig>,
+        keep_alive_send_timeout: Duration,
         task_manager: &Arc<TaskManager>,
     ) -> Arc<Self> {
         let lease_server = Arc::new(Self {
@@ -76,6 +95,7 @@ where
             id_gen,
             cluster_info,
             client_tls_config,
+            keep_alive_send_timeout,
             task_manager: Arc::clone(task_manager),
         });
         task_manager.spawn(TaskName::RevokeExpiredLeases, |n| {
@@ -100,18 +120,8 @@ where
                 for id in lease_server.lease_storage.find_expired_leases() {
                     let _handle = tokio::spawn({
                         let s = Arc::clone(&lease_server);
-                        let token_option = lease_server.auth_storage.root_token();
                         async move {
-                            let mut request = tonic::Request::new(LeaseRevokeRequest { id });
-                            if let Ok(token) = token_option {
-                                let _ignore = request.metadata_mut().insert(
-                                    "token",
-                                    token.parse().unwrap_or_else(|e| {
-                                        panic!("metadata value parse error: {e}")
-                                    }),
-                                );
-                            }
-                            if let Err(e) = s.lease_revoke(request).await {
+                            if let Err(e) = s.revoke_expired_lease(id).await {
                                 warn!("Failed to revoke expired leases: {}", e);
                             }
                         }
@@ -121,6 +131,58 @@ where
         }
     }
 
+    /// Revoke an expired lease on behalf of the root user
+    async fn revoke_expired_lease(&self, id: i64) -> Result<(), tonic::Status> {
+        let mut request = tonic::Request::new(LeaseRevokeRequest { id });
+        if let Ok(token) = self.auth_storage.root_token() {
+            let _ignore = request.metadata_mut().insert(
+                "token",
+                token
+                    .parse()
+                    .unwrap_or_else(|e| panic!("metadata value parse error: {e}")),
+            );
+        }
+        let _response = self.lease_revoke(request).await?;
+        Ok(())
+    }
+
+    /// Revoke all the leases which have expired right away instead of waiting for
+    /// the next tick of the background task, and return the number of revoked
+    /// leases. It only runs on the leader, and only the root user is allowed when
+    /// auth is enabled. A lease is taken from the expiry queue once, so the leases
+    /// which are already revoked or being revoked by the background task are not
+    /// counted and it is safe to call repeatedly.
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) async fn sweep_expired_leases<T>(
+        &self,
+        request: &tonic::Request<T>,
+    ) -> Result<usize, tonic::Status> {
+        self.sweep_expired_leases_at(request, Instant::now()).await
+    }
+
+    /// Revoke all the leases which have expired at `now`
+    async fn sweep_expired_leases_at<T>(
+        &self,
+        request: &tonic::Request<T>,
+        now: Instant,
+    ) -> Result<usize, tonic::Status> {
+        self.auth_storage.check_admin_request(request)?;
+        if !self.lease_storage.is_primary() {
+            return Err(tonic::Status::failed_precondition(
+                "lease expiry can only be swept on the leader",
+            ));
+        }
+        let mut revoked = 0;
+        for id in self.lease_storage.find_expired_leases_at(now) {
+            match self.revoke_expired_lease(id).await {
+                Ok(()) => revoked = revoked.overflow_add(1),
+                Err(e) => warn!("Failed to revoke expired lease {id}: {e}"),
+            }
+        }
+        debug!("{revoked} expired leases are revoked by the sweep");
+        Ok(revoked)
+    }
+
     /// Propose request and get result with fast/slow path
     async fn propose<T>(
         &self,
@@ -131,7 +193,17 @@ where
         T: Into<RequestWrapper>,
     {
         let auth_info = self.auth_storage.try_get_auth_info_from_request(&request)?;
-        let request = request.into_inner().into();
+        self.propose_with_auth_info(request.into_inner().into(), auth_info, use_fast_path)
+            .await
+    }
+
+    /// Propose request on behalf of the user of `auth_info`
+    async fn propose_with_auth_info(
+        &self,
+        request: RequestWrapper,
+        auth_info: Option<AuthInfo>,
+        use_fast_path: bool,
+    ) -> Result<(CommandResponse, Option<SyncResponse>), tonic::Status> {
         let keys = {
             if let RequestWrapper::LeaseRevokeRequest(ref req) = request {
                 self.lease_storage
@@ -140,7 +212,7 @@ where
                     .map(|k| KeyRange::new(k, ""))
                     .collect()
             } else {
-                vec![]
+                request.keys()
             }
         };
         let cmd = Command::new_with_auth_info(keys, request, auth_info);
@@ -148,6 +220,70 @@ where
         Ok(res)
     }
 
+    /// Export the full lease state, it only runs on the leader where the remaining
+    /// ttls of the leases are known, and only the root user is allowed when auth is
+    /// enabled. Return the revision of the keyspace the attached keys match with the
+    /// leases in ascending order of lease id.
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn export_leases<T>(
+        &self,
+        request: &tonic::Request<T>,
+    ) -> Result<(i64, Vec<LeaseImport>), tonic::Status> {
+        self.auth_storage.check_admin_request(request)?;
+        if !self.lease_storage.is_primary() {
+            return Err(tonic::Status::failed_precondition(
+                "the leases can only be exported on the leader",
+            ));
+        }
+        let (revision, leases) = self.lease_storage.export();
+        let leases = leases
+            .iter()
+            .map(|lease| {
+                let mut keys = lease.keys();
+                keys.sort_unstable();
+                LeaseImport {
+                    id: lease.id(),
+                    ttl: lease.ttl().as_secs().try_into().unwrap_or(i64::MAX),
+                    remaining_ttl: lease.remaining().as_secs().try_into().unwrap_or(i64::MAX),
+                    keys,
+                }
+            })
+            .collect();
+        Ok((revision, leases))
+    }
+
+    /// Import a lease state exported from another cluster by one command, so that
+    /// the leases are granted and their keys are reattached or deleted at one
+    /// revision, or nothing is changed. Only the root user is allowed when auth is
+    /// enabled. Return the revision of the import with its result.
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) async fn import_leases<T>(
+        &self,
+        request: &tonic::Request<T>,
+        leases: Vec<LeaseImport>,
+    ) -> Result<(i64, ImportLeasesOpResult), tonic::Status> {
+        self.auth_storage.check_admin_request(request)?;
+        let auth_info = self.auth_storage.try_get_auth_info_from_request(request)?;
+        let txn = TxnRequest::from(ServerOp::ImportLeases(ImportLeasesOp::new(leases)));
+        let (res, sync_res) = self
+            .propose_with_auth_info(txn.into(), auth_info, false)
+            .await?;
+        let res: TxnResponse = res.into_inner().into();
+        let Some(ServerOpResult::ImportLeases(result)) = ServerOpResult::from_txn_response(&res)
+        else {
+            return Err(tonic::Status::internal(
+                "the import of leases got an unexpected response",
+            ));
+        };
+        let revision = sync_res.map_or(0, |sync_res| sync_res.revision());
+        debug!(
+            "{} leases are imported and {} are revoked at revision {revision}",
+            result.imported.len(),
+            result.revoked.len()
+        );
+        Ok((revision, result))
+    }
+
     /// Handle keep alive at leader
     #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
     fn leader_keep_alive(
@@ -242,6 +378,35 @@ where
     }
 }
 
+/// Close a keepalive stream once a response waits for its consumer longer than the
+/// send timeout, a zero timeout leaves the stream as it is.
+///
+/// The responses are forwarded through a bounded buffer. A consumer that stops
+/// reading fills it up, after which the keepalive requests are no longer read, so
+/// its leases expire normally. The stream ends after the buffered responses.
+fn close_slow_consumer(mut stream: KeepAliveStream, send_timeout: Duration) -> KeepAliveStream {
+    if send_timeout.is_zero() {
+        return stream;
+    }
+    let (tx, rx) = mpsc::channel(KEEP_ALIVE_CHANNEL_SIZE);
+    let _handle = tokio::spawn(async move {
+        while let Some(res) = stream.next().await {
+            match time::timeout(send_timeout, tx.send(res)).await {
+                Ok(Ok(())) => {}
+                // the consumer has dropped the stream
+                Ok(Err(_)) => return,
+                Err(_) => {
+                    warn!(
+                        "close the keepalive stream of a consumer not reading for {send_timeout:?}"
+                    );
+                    return;
+                }
+            }
+        }
+    });
+    Box::pin(ReceiverStream::new(rx))
+}
+
 /// Build endpoints from addresses
 fn build_endpoints(
     addrs: &[String],
@@ -343,7 +508,10 @@ where
                     .await?;
             }
         };
-        Ok(tonic::Response::new(stream))
+        Ok(tonic::Response::new(close_slow_consumer(
+            stream,
+            self.keep_alive_send_timeout,
+        )))
     }
 
     /// LeaseTimeToLive retrieves lease information.
@@ -412,3 +580,259 @@ where
         Ok(tonic::Response::new(res))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use async_trait::async_trait;
+    use curp::{
+        members::ServerId,
+        rpc::{ConfChange, FetchClusterResponse, Member, ReadState},
+    };
+    use jsonwebtoken::{DecodingKey, EncodingKey};
+    use parking_lot::Mutex;
+    use test_macros::abort_on_panic;
+    use utils::config::EngineConfig;
+    use xlineapi::ResponseWrapper;
+
+    use super::*;
+    use crate::{
+        header_gen::HeaderGenerator,
+        rpc::{
+            AuthEnableRequest, AuthRoleAddRequest, AuthUserAddRequest, AuthUserGrantRoleRequest,
+        },
+        storage::{db::DB, index::Index, lease_store::LeaseCollection},
+    };
+
+    /// A client which records the ids of the proposed lease revokes
+    #[derive(Debug, Default)]
+    struct FakeClient {
+        /// The ids of the proposed lease revokes
+        revoked: Mutex<Vec<i64>>,
+    }
+
+    #[async_trait]
+    impl curp::client::ClientApi for FakeClient {
+        type Error = tonic::Status;
+
+        type Cmd = Command;
+
+        async fn propose(
+            &self,
+            cmd: &Command,
+            _token: Option<&String>,
+            _use_fast_path: bool,
+        ) -> Result<Result<(CommandResponse, Option<SyncResponse>), ExecuteError>, tonic::Status>
+        {
+            let RequestWrapper::LeaseRevokeRequest(ref req) = *cmd.request() else {
+                unreachable!("the sweep only proposes lease revokes")
+            };
+            self.revoked.lock().push(req.id);
+            let response = ResponseWrapper::LeaseRevokeResponse(LeaseRevokeResponse::default());
+            Ok(Ok((CommandResponse::new(response), None)))
+        }
+
+        async fn propose_conf_change(
+            &self,
+            _changes: Vec<ConfChange>,
+        ) -> Result<Vec<Member>, tonic::Status> {
+            unreachable!("the sweep never changes the membership")
+        }
+
+        async fn propose_shutdown(&self) -> Result<(), tonic::Status> {
+            unreachable!("the sweep never shuts down the cluster")
+        }
+
+        async fn propose_publish(
+            &self,
+            _node_id: ServerId,
+            _node_name: String,
+            _node_client_urls: Vec<String>,
+        ) -> Result<(), tonic::Status> {
+            unreachable!("the sweep never publishes")
+        }
+
+        async fn move_leader(&self, _node_id: ServerId) -> Result<(), tonic::Status> {
+            unreachable!("the sweep never moves the leader")
+        }
+
+        async fn fetch_read_state(&self, _cmd: &Command) -> Result<ReadState, tonic::Status> {
+            unreachable!("the sweep never reads")
+        }
+
+        async fn fetch_cluster(
+            &self,
+            _linearizable: bool,
+        ) -> Result<FetchClusterResponse, tonic::Status> {
+            unreachable!("the sweep never fetches the cluster")
+        }
+    }
+
+    fn test_key_pair() -> Option<(EncodingKey, DecodingKey)> {
+        let private_key = include_bytes!("../../../../fixtures/private.pem");
+        let public_key = include_bytes!("../../../../fixtures/public.pem");
+        let encoding_key = EncodingKey::from_rsa_pem(private_key).ok()?;
+        let decoding_key = DecodingKey::from_rsa_pem(public_key).ok()?;
+        Some((encoding_key, decoding_key))
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn expired_leases_should_be_swept_by_the_admin_on_the_leader() {
+        let db = DB::open(&EngineConfig::Memory).unwrap();
+        let lease_collection = Arc::new(LeaseCollection::new(0, Duration::ZERO));
+        let header_gen = Arc::new(HeaderGenerator::new(0, 0));
+        let (kv_update_tx, _kv_update_rx) = mpsc::channel(1);
+        let lease_storage = Arc::new(LeaseStore::new(
+            Arc::clone(&lease_collection),
+            Arc::clone(&header_gen),
+            Arc::clone(&db),
+            Arc::new(Index::new()),
+            kv_update_tx,
+            true,
+            0,
+            Arc::default(),
+        ));
+        let auth_storage = Arc::new(AuthStore::new(
+            lease_collection,
+            test_key_pair(),
+            header_gen,
+            Arc::clone(&db),
+            0,
+            16,
+            HashMap::new(),
+            0,
+            0,
+        ));
+        let client = Arc::new(FakeClient::default());
+        let task_manager = Arc::new(TaskManager::new());
+        let lease_server = LeaseServer::new(
+            Arc::clone(&lease_storage),
+            Arc::clone(&auth_storage),
+            Arc::clone(&client) as Arc<CurpClient>,
+            Arc::new(IdGenerator::new(1)),
+            Arc::new(ClusterInfo::new(1, 1, Vec::new())),
+            None,
+            Duration::ZERO,
+            &task_manager,
+        );
+        let mut revision = 1;
+        for (id, ttl) in [(1, 10), (2, 20), (3, 30), (4, 100)] {
+            let req = RequestWrapper::from(LeaseGrantRequest { ttl, id });
+            let _res = lease_storage.execute(&req).unwrap();
+            let (_sync_res, ops) = lease_storage.after_sync(&req, revision).await.unwrap();
+            let _key_revs = db.flush_ops(ops).unwrap();
+            revision = revision.overflow_add(1);
+        }
+
+        let now = Instant::now().checked_add(Duration::from_secs(50)).unwrap();
+        let request = tonic::Request::new(());
+        assert_eq!(
+            lease_server
+                .sweep_expired_leases_at(&request, now)
+                .await
+                .unwrap(),
+            3
+        );
+        let mut revoked = client.revoked.lock().clone();
+        revoked.sort_unstable();
+        assert_eq!(revoked, vec![1, 2, 3]);
+        // the swept leases are not revoked again
+        assert_eq!(
+            lease_server
+                .sweep_expired_leases_at(&request, now)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(client.revoked.lock().len(), 3);
+
+        // only the root user is allowed once auth is enabled
+        let requests = [
+            RequestWrapper::from(AuthRoleAddRequest {
+                name: "root".to_owned(),
+            }),
+            RequestWrapper::from(AuthUserAddRequest {
+                name: "root".to_owned(),
+                password: String::new(),
+                hashed_password: "123".to_owned(),
+                options: None,
+            }),
+            RequestWrapper::from(AuthUserGrantRoleRequest {
+                user: "root".to_owned(),
+                role: "root".to_owned(),
+            }),
+            RequestWrapper::from(AuthEnableRequest {}),
+        ];
+        for req in &requests {
+            let _res = auth_storage.execute(req).unwrap();
+            let (_sync_res, ops) = auth_storage.after_sync(req, revision).unwrap();
+            let _key_revs = db.flush_ops(ops).unwrap();
+            revision = revision.overflow_add(1);
+        }
+        let later = now.checked_add(Duration::from_secs(100)).unwrap();
+        let denied = lease_server
+            .sweep_expired_leases_at(&request, later)
+            .await
+            .unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+        let mut root_request = tonic::Request::new(());
+        let _prev = root_request
+            .metadata_mut()
+            .insert("token", auth_storage.root_token().unwrap().parse().unwrap());
+
+        assert_eq!(
+            lease_server
+                .sweep_expired_leases_at(&root_request, later)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(client.revoked.lock().last(), Some(&4));
+
+        // a follower refuses to sweep
+        lease_storage.demote();
+        let refused = lease_server
+            .sweep_expired_leases_at(&root_request, later)
+            .await
+            .unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(client.revoked.lock().len(), 4);
+
+        task_manager.shutdown(true).await;
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn keep_alive_stream_of_a_slow_consumer_should_be_closed() {
+        let (src_tx, src_rx) = mpsc::channel(KEEP_ALIVE_CHANNEL_SIZE);
+        let source: KeepAliveStream = Box::pin(ReceiverStream::new(src_rx));
+        let mut stream = close_slow_consumer(source, Duration::from_millis(100));
+        let response = |id| -> Result<_, tonic::Status> {
+            Ok(LeaseKeepAliveResponse {
+                id,
+                ..LeaseKeepAliveResponse::default()
+            })
+        };
+        // a reading consumer is not affected
+        for id in 0..10 {
+            src_tx.send(response(id)).await.unwrap();
+            assert_eq!(stream.next().await.unwrap().unwrap().id, id);
+            time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // the consumer stops reading, it fills up the buffer of the stream and then the
+        // one of the source
+        for id in 0..KEEP_ALIVE_CHANNEL_SIZE.overflow_mul(2).overflow_add(1) {
+            src_tx.send(response(id.numeric_cast())).await.unwrap();
+        }
+        time::sleep(Duration::from_millis(300)).await;
+        // the source is dropped
+        assert!(src_tx.is_closed());
+        // the buffered responses are still delivered before the stream ends
+        let buffered: Vec<_> = stream.collect().await;
+        assert_eq!(buffered.len(), KEEP_ALIVE_CHANNEL_SIZE);
+        assert!(buffered.iter().all(Result::is_ok));
+    }
+}
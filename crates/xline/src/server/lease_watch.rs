@@ -0,0 +1,348 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use clippy_utilities::OverflowArithmetic;
+use tokio::sync::mpsc;
+use tokio_stream::{Stream, StreamExt};
+use tracing::warn;
+use utils::task_manager::Listener;
+
+use crate::{
+    header_gen::HeaderGenerator,
+    rpc::{
+        Event, EventType, KeyValue, RequestUnion, WatchCreateRequest, WatchRequest, WatchResponse,
+    },
+    storage::lease_store::{LeaseEvent, LeaseEventHub, LeaseEventKind},
+};
+
+/// Metadata key of a watch stream watching the liveness events of the leases instead
+/// of the keys.
+///
+/// A lease is watched like a key named by its decimal id, an empty key watches all
+/// the leases. A grant is a put creating the key, a keep alive is a put updating it,
+/// and a revoke or an expiry is a delete. The value of a put is the remaining ttl in
+/// seconds, and the revisions of the events are the sequence numbers of the lease
+/// events of the node, from which a watch can be resumed by its start revision.
+pub(crate) const LEASE_EVENTS_KEY: &str = "lease-events";
+
+/// A watch of the lease events
+#[derive(Debug)]
+struct LeaseWatch {
+    /// The watched lease, `None` means all the leases
+    lease_id: Option<i64>,
+    /// The sequence number of the next event to deliver
+    next_seq: i64,
+}
+
+/// Convert a lease event to a watch event
+fn watch_event(event: &LeaseEvent) -> Event {
+    let kv = KeyValue {
+        key: event.id.to_string().into_bytes(),
+        mod_revision: event.seq,
+        ..KeyValue::default()
+    };
+    let (event_type, kv) = match event.kind {
+        LeaseEventKind::Grant | LeaseEventKind::KeepAlive => (
+            EventType::Put,
+            KeyValue {
+                create_revision: event.grant_seq,
+                version: event.version,
+                value: event.remaining_ttl.to_string().into_bytes(),
+                lease: event.id,
+                ..kv
+            },
+        ),
+        LeaseEventKind::Revoke => (EventType::Delete, kv),
+    };
+    let mut watch_event = Event {
+        kv: Some(kv),
+        ..Event::default()
+    };
+    watch_event.set_type(event_type);
+    watch_event
+}
+
+/// Handler of a watch stream of the lease events
+struct LeaseWatchHandle {
+    /// The lease events
+    hub: Arc<LeaseEventHub>,
+    /// Header generator
+    header_gen: Arc<HeaderGenerator>,
+    /// The sender of the responses
+    res_tx: mpsc::Sender<Result<WatchResponse, tonic::Status>>,
+    /// The active watches by their ids
+    watches: BTreeMap<i64, LeaseWatch>,
+    /// The id of the next automatically assigned watch
+    next_watch_id: i64,
+}
+
+impl LeaseWatchHandle {
+    /// Send a response, returns `false` if the stream is closed
+    async fn send(&self, response: Result<WatchResponse, tonic::Status>) -> bool {
+        self.res_tx.send(response).await.is_ok()
+    }
+
+    /// Handle a `WatchCreateRequest`
+    async fn handle_create(&mut self, req: WatchCreateRequest) -> bool {
+        let watch_id = if req.watch_id == 0 {
+            while self.watches.contains_key(&self.next_watch_id) {
+                self.next_watch_id = self.next_watch_id.overflow_add(1);
+            }
+            self.next_watch_id
+        } else if self.watches.contains_key(&req.watch_id) {
+            return self
+                .send(Err(tonic::Status::already_exists(format!(
+                    "Watch ID {} has already been used",
+                    req.watch_id
+                ))))
+                .await;
+        } else {
+            req.watch_id
+        };
+        let mut response = WatchResponse {
+            header: Some(self.header_gen.gen_header()),
+            watch_id,
+            created: true,
+            ..WatchResponse::default()
+        };
+        let lease_id = if req.key.is_empty() {
+            None
+        } else if let Some(id) = std::str::from_utf8(&req.key)
+            .ok()
+            .and_then(|key| key.parse().ok())
+        {
+            Some(id)
+        } else {
+            response.canceled = true;
+            response.cancel_reason = "the key of a lease watch must be a lease id".to_owned();
+            return self.send(Ok(response)).await;
+        };
+        let next_seq = if req.start_revision > 0 {
+            req.start_revision
+        } else {
+            self.hub.next_seq()
+        };
+        if let Err(oldest) = self.hub.events_from(next_seq, lease_id) {
+            response.canceled = true;
+            response.compact_revision = oldest;
+            return self.send(Ok(response)).await;
+        }
+        let _prev = self
+            .watches
+            .insert(watch_id, LeaseWatch { lease_id, next_seq });
+        self.send(Ok(response)).await
+    }
+
+    /// Handle a `WatchRequest`
+    async fn handle_request(&mut self, req: WatchRequest) -> bool {
+        match req.request_union {
+            Some(RequestUnion::CreateRequest(req)) => self.handle_create(req).await,
+            Some(RequestUnion::CancelRequest(req)) => {
+                let response = if self.watches.remove(&req.watch_id).is_some() {
+                    Ok(WatchResponse {
+                        header: Some(self.header_gen.gen_header()),
+                        watch_id: req.watch_id,
+                        canceled: true,
+                        ..WatchResponse::default()
+                    })
+                } else {
+                    Err(tonic::Status::not_found(format!(
+                        "Watch ID {} doesn't exist",
+                        req.watch_id
+                    )))
+                };
+                self.send(response).await
+            }
+            Some(RequestUnion::ProgressRequest(_)) => {
+                self.send(Ok(WatchResponse {
+                    header: Some(self.header_gen.gen_header()),
+                    watch_id: -1,
+                    ..WatchResponse::default()
+                }))
+                .await
+            }
+            None => true,
+        }
+    }
+
+    /// Deliver the new events to the watches, a watch falling behind the retained
+    /// events is canceled with the smallest retained sequence number
+    async fn deliver(&mut self) -> bool {
+        let mut responses = Vec::new();
+        let mut fallen_behind = Vec::new();
+        for (&watch_id, watch) in &mut self.watches {
+            match self.hub.events_from(watch.next_seq, watch.lease_id) {
+                Ok((events, next_seq)) => {
+                    watch.next_seq = next_seq;
+                    if !events.is_empty() {
+                        responses.push(WatchResponse {
+                            header: Some(self.header_gen.gen_header()),
+                            watch_id,
+                            events: events.iter().map(watch_event).collect(),
+                            ..WatchResponse::default()
+                        });
+                    }
+                }
+                Err(oldest) => {
+                    fallen_behind.push(watch_id);
+                    responses.push(WatchResponse {
+                        header: Some(self.header_gen.gen_header()),
+                        watch_id,
+                        canceled: true,
+                        compact_revision: oldest,
+                        ..WatchResponse::default()
+                    });
+                }
+            }
+        }
+        for watch_id in fallen_behind {
+            let _prev = self.watches.remove(&watch_id);
+        }
+        for response in responses {
+            if !self.send(Ok(response)).await {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// bg task of a watch stream of the lease events
+#[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
+pub(crate) async fn lease_watch_task<ST>(
+    hub: Arc<LeaseEventHub>,
+    header_gen: Arc<HeaderGenerator>,
+    res_tx: mpsc::Sender<Result<WatchResponse, tonic::Status>>,
+    mut req_rx: ST,
+    shutdown_listener: Listener,
+) where
+    ST: Stream<Item = Result<WatchRequest, tonic::Status>> + Unpin,
+{
+    let mut handle = LeaseWatchHandle {
+        hub,
+        header_gen,
+        res_tx,
+        watches: BTreeMap::new(),
+        next_watch_id: 1,
+    };
+    loop {
+        // listen before delivering, so that no event is missed in between
+        let recorded = handle.hub.listen();
+        if !handle.deliver().await {
+            break;
+        }
+        tokio::select! {
+            _ = shutdown_listener.wait() => break,
+            req = req_rx.next() => {
+                match req {
+                    Some(Ok(req)) => {
+                        if !handle.handle_request(req).await {
+                            break;
+                        }
+                    }
+                    Some(Err(e)) => {
+                        warn!("Receive WatchRequest error {:?}", e);
+                        break;
+                    }
+                    None => break,
+                }
+            }
+            _ = recorded => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use test_macros::abort_on_panic;
+    use tokio::time::timeout;
+    use utils::task_manager::{tasks::TaskName, TaskManager};
+
+    use super::*;
+    use crate::rpc::WatchCancelRequest;
+
+    fn create(key: &str, start_revision: i64) -> Result<WatchRequest, tonic::Status> {
+        Ok(WatchRequest {
+            request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                key: key.as_bytes().to_vec(),
+                start_revision,
+                ..WatchCreateRequest::default()
+            })),
+        })
+    }
+
+    async fn recv(
+        res_rx: &mut mpsc::Receiver<Result<WatchResponse, tonic::Status>>,
+    ) -> WatchResponse {
+        timeout(Duration::from_secs(1), res_rx.recv())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn lease_watches_should_deliver_and_resume_lease_events() {
+        let hub = Arc::new(LeaseEventHub::new());
+        let (req_tx, req_rx) = mpsc::channel(8);
+        let (res_tx, mut res_rx) = mpsc::channel(8);
+        let task_manager = TaskManager::new();
+        task_manager.spawn(TaskName::WatchTask, |n| {
+            lease_watch_task(
+                Arc::clone(&hub),
+                Arc::new(HeaderGenerator::new(0, 0)),
+                res_tx,
+                tokio_stream::wrappers::ReceiverStream::new(req_rx),
+                n,
+            )
+        });
+
+        hub.record(1, LeaseEventKind::Grant, 10);
+        req_tx.send(create("1", 1)).await.unwrap();
+        req_tx.send(create("", 0)).await.unwrap();
+        req_tx.send(create("foo", 0)).await.unwrap();
+        let resumed = recv(&mut res_rx).await;
+        assert!(resumed.created && !resumed.canceled);
+        let history = recv(&mut res_rx).await;
+        assert_eq!(history.watch_id, resumed.watch_id);
+        let kv = history.events[0].kv.clone().unwrap();
+        assert_eq!((kv.key, kv.value), (b"1".to_vec(), b"10".to_vec()));
+        assert_eq!(kv.create_revision, kv.mod_revision);
+        let all = recv(&mut res_rx).await;
+        assert!(all.created && !all.canceled);
+        assert!(
+            recv(&mut res_rx).await.canceled,
+            "an invalid lease id is rejected"
+        );
+
+        hub.record(2, LeaseEventKind::Grant, 20);
+        let granted = recv(&mut res_rx).await;
+        assert_eq!(granted.watch_id, all.watch_id);
+        hub.record(1, LeaseEventKind::KeepAlive, 10);
+        hub.record(1, LeaseEventKind::Revoke, 0);
+        let mut kinds = Vec::new();
+        while kinds.len() < 4 {
+            let res = recv(&mut res_rx).await;
+            kinds.extend(res.events.iter().map(|e| (res.watch_id, e.r#type)));
+        }
+        kinds.sort_unstable();
+        let (put, delete) = (i32::from(EventType::Put), i32::from(EventType::Delete));
+        let (one, all_id) = (resumed.watch_id, all.watch_id);
+        let mut expected = vec![(one, put), (one, delete), (all_id, put), (all_id, delete)];
+        expected.sort_unstable();
+        assert_eq!(kinds, expected);
+
+        req_tx
+            .send(Ok(WatchRequest {
+                request_union: Some(RequestUnion::CancelRequest(WatchCancelRequest {
+                    watch_id: all_id,
+                })),
+            }))
+            .await
+            .unwrap();
+        assert!(recv(&mut res_rx).await.canceled);
+        task_manager.shutdown(true).await;
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    convert::Infallible,
+    task::{Context, Poll},
+};
+
+use futures::future::{self, Either, Ready};
+use tonic::{
+    body::BoxBody,
+    codegen::{http, Service},
+    server::NamedService,
+};
+
+/// The methods served by a read-only listener, which don't modify the data. The
+/// methods the clients need to find the cluster and authenticate are served too.
+const READ_METHODS: [&str; 28] = [
+    "/etcdserverpb.KV/Range",
+    "/xlinepb.RangeStream/Range",
+    "/etcdserverpb.Watch/Watch",
+    "/etcdserverpb.Lease/LeaseTimeToLive",
+    "/etcdserverpb.Lease/LeaseLeases",
+    "/etcdserverpb.Cluster/MemberList",
+    "/xlinepb.MemberWatch/Watch",
+    "/etcdserverpb.Maintenance/Status",
+    "/etcdserverpb.Maintenance/Hash",
+    "/etcdserverpb.Maintenance/HashKV",
+    "/etcdserverpb.Maintenance/Snapshot",
+    "/xlinepb.Admin/AttachedKeys",
+    "/xlinepb.Admin/DedupCache",
+    "/xlinepb.Admin/KeyHistogram",
+    "/xlinepb.Admin/PasswordHashRounds",
+    "/xlinepb.Admin/WatchRegistry",
+    "/xlinepb.Admin/UserStats",
+    "/xlinepb.Admin/ExportLeases",
+    "/etcdserverpb.Auth/AuthStatus",
+    "/etcdserverpb.Auth/Authenticate",
+    "/etcdserverpb.Auth/UserGet",
+    "/etcdserverpb.Auth/UserList",
+    "/etcdserverpb.Auth/RoleGet",
+    "/etcdserverpb.Auth/RoleList",
+    "/commandpb.Protocol/FetchCluster",
+    "/commandpb.Protocol/FetchReadState",
+    "/grpc.health.v1.Health/Check",
+    "/grpc.health.v1.Health/Watch",
+];
+
+/// The reads of the data, which are rejected by a write-only listener
+const DATA_READ_METHODS: [&str; 6] = [
+    "/etcdserverpb.KV/Range",
+    "/xlinepb.RangeStream/Range",
+    "/xlinepb.Admin/AttachedKeys",
+    "/xlinepb.Admin/KeyHistogram",
+    "/xlinepb.Admin/ExportLeases",
+    "/etcdserverpb.Watch/Watch",
+];
+
+/// The role of a client listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ListenerRole {
+    /// The listener only serves the reads
+    Read,
+    /// The listener only serves the writes
+    Write,
+}
+
+impl ListenerRole {
+    /// Whether the listener of the role serves the method of the path. The methods are
+    /// told apart by their paths only, so a txn is a write even if it only reads.
+    fn allows(self, path: &str) -> bool {
+        match self {
+            ListenerRole::Read => READ_METHODS.contains(&path),
+            ListenerRole::Write => !DATA_READ_METHODS.contains(&path),
+        }
+    }
+
+    /// The status of a request rejected by the listener of the role
+    fn rejection(self, path: &str) -> tonic::Status {
+        let role = match self {
+            ListenerRole::Read => "read-only",
+            ListenerRole::Write => "write-only",
+        };
+        tonic::Status::failed_precondition(format!("{path} is not served by the {role} listener"))
+    }
+}
+
+/// A grpc service behind a listener of a role, which rejects the requests of the
+/// methods the role doesn't serve with `FailedPrecondition`
+#[derive(Debug, Clone)]
+pub(crate) struct ListenerGuard<S> {
+    /// The inner service
+    inner: S,
+    /// The role of the listener
+    role: ListenerRole,
+}
+
+impl<S> ListenerGuard<S> {
+    /// New `ListenerGuard`
+    pub(crate) fn new(inner: S, role: ListenerRole) -> Self {
+        Self { inner, role }
+    }
+}
+
+impl<S, B> Service<http::Request<B>> for ListenerGuard<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>, Error = Infallible>,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Either<Ready<Result<Self::Response, Self::Error>>, S::Future>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    #[inline]
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        let path = req.uri().path();
+        if self.role.allows(path) {
+            Either::Right(self.inner.call(req))
+        } else {
+            Either::Left(future::ok(self.role.rejection(path).to_http()))
+        }
+    }
+}
+
+impl<S: NamedService> NamedService for ListenerGuard<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn listeners_should_only_serve_the_methods_of_their_roles() {
+        for path in [
+            "/etcdserverpb.KV/Range",
+            "/xlinepb.RangeStream/Range",
+            "/etcdserverpb.Watch/Watch",
+        ] {
+            assert!(ListenerRole::Read.allows(path));
+            assert!(!ListenerRole::Write.allows(path));
+        }
+        for path in [
+            "/etcdserverpb.KV/Put",
+            "/etcdserverpb.KV/Txn",
+            "/etcdserverpb.Lease/LeaseGrant",
+            "/commandpb.Protocol/ProposeStream",
+        ] {
+            assert!(!ListenerRole::Read.allows(path));
+            assert!(ListenerRole::Write.allows(path));
+        }
+        assert!(ListenerRole::Read.allows("/commandpb.Protocol/FetchCluster"));
+        assert!(ListenerRole::Write.allows("/commandpb.Protocol/FetchCluster"));
+        assert_eq!(
+            ListenerRole::Read.rejection("/etcdserverpb.KV/Put").code(),
+            tonic::Code::FailedPrecondition
+        );
+    }
+}
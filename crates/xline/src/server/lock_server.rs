@@ -6,9 +6,9 @@ use clippy_utilities::OverflowArithmetic;
 use tonic::transport::ClientTlsConfig;
 use tonic::transport::{Channel, Endpoint};
 use tracing::debug;
-use utils::build_endpoint;
 #[cfg(madsim)]
 use utils::ClientTlsConfig;
+use utils::{build_endpoint, redaction::redact_key};
 use xlineapi::{
     command::{Command, CommandResponse, CurpClient, KeyRange, SyncResponse},
     execute_error::ExecuteError,
@@ -225,7 +225,11 @@ where
         &self,
         request: tonic::Request<LockRequest>,
     ) -> Result<tonic::Response<LockResponse>, tonic::Status> {
-        debug!("Receive LockRequest {:?}", request);
+        debug!(
+            "Receive LockRequest {{ name: {}, lease: {} }}",
+            redact_key(&request.get_ref().name),
+            request.get_ref().lease
+        );
         let auth_info = self.auth_store.try_get_auth_info_from_request(&request)?;
         let lock_req = request.into_inner();
         let lease_id = if lock_req.lease == 0 {
@@ -299,7 +303,10 @@ where
         &self,
         request: tonic::Request<UnlockRequest>,
     ) -> Result<tonic::Response<UnlockResponse>, tonic::Status> {
-        debug!("Receive UnlockRequest {:?}", request);
+        debug!(
+            "Receive UnlockRequest {{ key: {} }}",
+            redact_key(&request.get_ref().key)
+        );
         let auth_info = self.auth_store.try_get_auth_info_from_request(&request)?;
         let header = self.delete_key(&request.get_ref().key, auth_info).await?;
         Ok(tonic::Response::new(UnlockResponse { header }))
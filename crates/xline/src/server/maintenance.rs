@@ -1,4 +1,4 @@
-use std::{fmt::Debug, pin::Pin, sync::Arc};
+use std::{collections::BTreeMap, fmt::Debug, path::PathBuf, pin::Pin, sync::Arc};
 
 use async_stream::try_stream;
 use bytes::BytesMut;
@@ -7,13 +7,19 @@ use curp::{cmd::CommandExecutor as _, members::ClusterInfo, server::RawCurp};
 use engine::SnapshotApi;
 use futures::stream::Stream;
 use sha2::{Digest, Sha256};
+use tonic::metadata::MetadataValue;
 use tracing::{debug, error};
+use utils::config::MaintenanceOp;
 use xlineapi::{
     command::{Command, CommandResponse, CurpClient, SyncResponse},
     RequestWrapper,
 };
 
-use super::command::CommandExecutor;
+use super::{
+    command::CommandExecutor,
+    storage_health::{free_disk_space, StorageHealth},
+    user_stats::{RequestCounters, UserStats},
+};
 use crate::{
     header_gen::HeaderGenerator,
     rpc::{
@@ -23,9 +29,28 @@ use crate::{
         StatusResponse,
     },
     state::State,
-    storage::{storage_api::StorageApi, AlarmStore, AuthStore, KvStore},
+    storage::{
+        index::KeyHistogram,
+        kv_store::AttachedKeysPage,
+        kvwatcher::{KvWatcher, WatchId, WatchRegistryPage},
+        maintenance_scheduler::{MaintenancePermit, MaintenanceScheduler},
+        storage_api::StorageApi,
+        AlarmStore, AuthStore, KvStore, LeaseStore,
+    },
 };
 
+/// Metadata key which asks a `Status` for the latest changes of a change history of
+/// the node, its value is the name of the history, `auth` or `lease`, and it requires
+/// the admin permission. The response carries them by `CHANGE_HISTORY_KEY`.
+pub(crate) const CHANGE_HISTORY_REQUEST_KEY: &str = "change-history";
+
+/// Metadata key of a status response carrying the latest changes of a change
+/// history, as a json object of the compacted history revision and the changes
+pub(crate) const CHANGE_HISTORY_KEY: &str = "change-history-bin";
+
+/// The max number of the changes of a history carried by a status response
+const MAX_REPORTED_CHANGES: usize = 100;
+
 /// Minimum page size
 const MIN_PAGE_SIZE: u64 = 512;
 /// Snapshot chunk size
@@ -40,6 +65,8 @@ where
     kv_store: Arc<KvStore<S>>,
     /// Auth Storage
     auth_store: Arc<AuthStore<S>>,
+    /// Lease Storage
+    lease_store: Arc<LeaseStore<S>>,
     /// persistent storage
     persistent: Arc<S>, // TODO: `persistent` is not a good name, rename it in a better way
     /// Header generator
@@ -54,6 +81,14 @@ where
     ce: Arc<CommandExecutor<S>>,
     /// Alarm store
     alarm_store: Arc<AlarmStore<S>>,
+    /// Scheduler of the heavy storage maintenance operations
+    maintenance_scheduler: Arc<MaintenanceScheduler>,
+    /// The data directory of the backend, `None` if the backend is in memory
+    data_dir: Option<PathBuf>,
+    /// KV watcher
+    kv_watcher: Arc<KvWatcher<S>>,
+    /// The request statistics of the users
+    user_stats: Arc<UserStats>,
 }
 
 impl<S> MaintenanceServer<S>
@@ -65,6 +100,7 @@ where
     pub(crate) fn new(
         kv_store: Arc<KvStore<S>>,
         auth_store: Arc<AuthStore<S>>,
+        lease_store: Arc<LeaseStore<S>>,
         client: Arc<CurpClient>,
         persistent: Arc<S>,
         header_gen: Arc<HeaderGenerator>,
@@ -72,10 +108,15 @@ where
         raw_curp: Arc<RawCurp<Command, State<S, Arc<CurpClient>>>>,
         ce: Arc<CommandExecutor<S>>,
         alarm_store: Arc<AlarmStore<S>>,
+        maintenance_scheduler: Arc<MaintenanceScheduler>,
+        data_dir: Option<PathBuf>,
+        kv_watcher: Arc<KvWatcher<S>>,
+        user_stats: Arc<UserStats>,
     ) -> Self {
         Self {
             kv_store,
             auth_store,
+            lease_store,
             persistent,
             header_gen,
             client,
@@ -83,6 +124,10 @@ where
             raw_curp,
             ce,
             alarm_store,
+            maintenance_scheduler,
+            data_dir,
+            kv_watcher,
+            user_stats,
         }
     }
 
@@ -101,6 +146,120 @@ where
         let res = self.client.propose(&cmd, None, use_fast_path).await??;
         Ok(res)
     }
+
+    /// Get a page of the keys attached to the leases whose id is not less than
+    /// `start_lease_id` at `revision`, only the root user is allowed when auth is
+    /// enabled
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn attached_keys<T>(
+        &self,
+        request: &tonic::Request<T>,
+        revision: i64,
+        start_lease_id: i64,
+        limit: usize,
+    ) -> Result<AttachedKeysPage, tonic::Status> {
+        self.auth_store.check_admin_request(request)?;
+        Ok(self
+            .kv_store
+            .attached_keys(revision, start_lease_id, limit)?)
+    }
+
+    /// Get the number of propose ids in the dedup cache of this node, only the root
+    /// user is allowed when auth is enabled
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn dedup_cache_len<T>(
+        &self,
+        request: &tonic::Request<T>,
+    ) -> Result<usize, tonic::Status> {
+        self.auth_store.check_admin_request(request)?;
+        Ok(self.raw_curp.dedup_cache_len())
+    }
+
+    /// Clear the dedup cache of this node and return the number of removed propose
+    /// ids, only the root user is allowed when auth is enabled.
+    ///
+    /// WARNING: a retried command may be applied again after the cache is cleared. The
+    /// clear is node-local, the dedup caches of the other nodes are not cleared.
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn clear_dedup_cache<T>(
+        &self,
+        request: &tonic::Request<T>,
+    ) -> Result<usize, tonic::Status> {
+        self.auth_store.check_admin_request(request)?;
+        Ok(self.raw_curp.clear_dedup_cache())
+    }
+
+    /// Estimate how the live keys of a range are distributed, the range is split into
+    /// at most `buckets` buckets holding roughly the same number of keys. It is used
+    /// to choose split points, only the root user is allowed when auth is enabled.
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn key_histogram<T>(
+        &self,
+        request: &tonic::Request<T>,
+        key: &[u8],
+        range_end: &[u8],
+        buckets: usize,
+    ) -> Result<KeyHistogram, tonic::Status> {
+        self.auth_store.check_admin_request(request)?;
+        Ok(self.kv_store.key_histogram(key, range_end, buckets))
+    }
+
+    /// Count the users by the pbkdf2 rounds of their stored password hashes, only the
+    /// root user is allowed when auth is enabled
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn password_hash_rounds<T>(
+        &self,
+        request: &tonic::Request<T>,
+    ) -> Result<BTreeMap<u32, usize>, tonic::Status> {
+        self.auth_store.check_admin_request(request)?;
+        Ok(self.auth_store.password_hash_rounds_report()?)
+    }
+
+    /// List a page of at most `limit` active watches of this node from
+    /// `start_watch_id`, only the root user is allowed when auth is enabled
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn watch_registry<T>(
+        &self,
+        request: &tonic::Request<T>,
+        start_watch_id: WatchId,
+        limit: usize,
+    ) -> Result<WatchRegistryPage, tonic::Status> {
+        self.auth_store.check_admin_request(request)?;
+        Ok(self.kv_watcher.registry(start_watch_id, limit))
+    }
+
+    /// Get the request statistics of at most `limit` users of this node from
+    /// `start_user`, only the root user is allowed when auth is enabled
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn user_stats<T>(
+        &self,
+        request: &tonic::Request<T>,
+        start_user: &str,
+        limit: usize,
+    ) -> Result<(Vec<(String, RequestCounters)>, Option<String>), tonic::Status> {
+        self.auth_store.check_admin_request(request)?;
+        Ok(self.user_stats.page(start_user, limit))
+    }
+
+    /// Reset the request statistics of a user of this node, or of all users if `user`
+    /// is `None`, only the root user is allowed when auth is enabled. It returns the
+    /// statistics of the user before the reset, or the number of the reset users.
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn reset_user_stats<T>(
+        &self,
+        request: &tonic::Request<T>,
+        user: Option<&str>,
+    ) -> Result<(Option<RequestCounters>, usize), tonic::Status> {
+        self.auth_store.check_admin_request(request)?;
+        Ok(match user {
+            Some(user) => {
+                let counters = self.user_stats.reset(user);
+                let reset = usize::from(counters.is_some());
+                (counters, reset)
+            }
+            None => (None, self.user_stats.reset_all()),
+        })
+    }
 }
 
 #[tonic::async_trait]
@@ -127,8 +286,12 @@ where
 
     async fn status(
         &self,
-        _request: tonic::Request<StatusRequest>,
+        request: tonic::Request<StatusRequest>,
     ) -> Result<tonic::Response<StatusResponse>, tonic::Status> {
+        let history = request.metadata().get(CHANGE_HISTORY_REQUEST_KEY).cloned();
+        if history.is_some() {
+            self.auth_store.check_admin_request(&request)?;
+        }
         let is_learner = self.cluster_info.self_member().is_learner;
         let (leader, term, _) = self.raw_curp.leader();
         let commit_index = self.raw_curp.commit_index();
@@ -136,6 +299,10 @@ where
             error!("get file size failed, {e}");
             tonic::Status::internal("get file size failed")
         })?;
+        let size_in_use = self.persistent.live_data_size().map_err(|e| {
+            error!("get live data size failed, {e}");
+            tonic::Status::internal("get live data size failed")
+        })?;
         let last_applied = self.ce.last_applied().map_err(|e| {
             error!("get last applied failed, {e}");
             tonic::Status::internal("get last applied failed")
@@ -144,9 +311,25 @@ where
         if leader.is_none() {
             errors.push("etcdserver: no leader".to_owned());
         }
-        for a in self.alarm_store.get_all_alarms() {
+        if self.raw_curp.leader_flapping() {
+            errors.push("etcdserver: leader flapping".to_owned());
+        }
+        let alarms = self.alarm_store.get_all_alarms();
+        for a in &alarms {
             errors.push(a.to_string());
         }
+        let self_id = self.cluster_info.self_id();
+        let own_alarms: Vec<_> = alarms
+            .into_iter()
+            .filter(|a| a.member_id == self_id)
+            .collect();
+        let health = StorageHealth::new(
+            self.persistent.last_synced_at(),
+            self.kv_store.compaction_backlog(),
+            self.data_dir.as_deref().and_then(free_disk_space),
+            self.persistent.write_failure(),
+            &own_alarms,
+        );
         let response = StatusResponse {
             header: Some(self.header_gen.gen_header()),
             version: env!("CARGO_PKG_VERSION").to_owned(),
@@ -156,19 +339,45 @@ where
             raft_term: term,
             raft_applied_index: last_applied,
             errors,
-            db_size_in_use: size.numeric_cast(),
+            // the live data of an engine without the estimation is the whole file
+            db_size_in_use: if size_in_use == 0 {
+                size
+            } else {
+                size_in_use.min(size)
+            }
+            .numeric_cast(),
             is_learner,
         };
-        Ok(tonic::Response::new(response))
+        let mut response = tonic::Response::new(response);
+        health.insert_into(response.metadata_mut())?;
+        if let Some(name) = history {
+            let report = match name.to_str() {
+                Ok("auth") => self.auth_store.history(MAX_REPORTED_CHANGES),
+                Ok("lease") => self.lease_store.history(MAX_REPORTED_CHANGES),
+                _ => return Err(tonic::Status::invalid_argument("unknown change history")),
+            };
+            let json = serde_json::to_vec(&report)
+                .unwrap_or_else(|e| unreachable!("the history is always serializable: {e}"));
+            let _prev = response
+                .metadata_mut()
+                .insert_bin(CHANGE_HISTORY_KEY, MetadataValue::from_bytes(&json));
+        }
+        Ok(response)
     }
 
     async fn defragment(
         &self,
-        _request: tonic::Request<DefragmentRequest>,
+        request: tonic::Request<DefragmentRequest>,
     ) -> Result<tonic::Response<DefragmentResponse>, tonic::Status> {
-        Err(tonic::Status::unimplemented(
-            "defragment is unimplemented".to_owned(),
-        ))
+        self.auth_store.check_admin_request(&request)?;
+        let _permit = self
+            .maintenance_scheduler
+            .acquire(MaintenanceOp::Defrag)
+            .await;
+        self.persistent.defragment()?;
+        Ok(tonic::Response::new(DefragmentResponse {
+            header: Some(self.header_gen.gen_header()),
+        }))
     }
 
     async fn hash(
@@ -202,7 +411,11 @@ where
         &self,
         _request: tonic::Request<SnapshotRequest>,
     ) -> Result<tonic::Response<Self::SnapshotStream>, tonic::Status> {
-        let stream = snapshot_stream(self.header_gen.as_ref(), self.persistent.as_ref())?;
+        let permit = self
+            .maintenance_scheduler
+            .acquire(MaintenanceOp::Snapshot)
+            .await;
+        let stream = snapshot_stream(self.header_gen.as_ref(), self.persistent.as_ref(), permit)?;
 
         Ok(tonic::Response::new(Box::pin(stream)))
     }
@@ -228,10 +441,11 @@ where
     }
 }
 
-/// Generate snapshot stream
+/// Generate snapshot stream, the maintenance `permit` is held until the stream ends
 fn snapshot_stream<S: StorageApi>(
     header_gen: &HeaderGenerator,
     persistent: &S,
+    permit: MaintenancePermit,
 ) -> Result<impl Stream<Item = Result<SnapshotResponse, tonic::Status>>, tonic::Status> {
     let tmp_path = format!("/tmp/snapshot-{}", uuid::Uuid::new_v4());
     let mut snapshot = persistent.get_snapshot(tmp_path).map_err(|e| {
@@ -242,6 +456,7 @@ fn snapshot_stream<S: StorageApi>(
     let header = header_gen.gen_header();
 
     let stream = try_stream! {
+        let _permit = permit;
         if let Err(e) = snapshot.rewind() {
             error!("snapshot rewind failed, {e}");
             return;
@@ -284,11 +499,11 @@ fn snapshot_stream<S: StorageApi>(
 
 #[cfg(test)]
 mod test {
-    use std::{error::Error, path::PathBuf};
+    use std::{error::Error, path::PathBuf, time::Duration};
 
     use test_macros::abort_on_panic;
     use tokio_stream::StreamExt;
-    use utils::config::EngineConfig;
+    use utils::config::{EngineConfig, MaintenancePolicy};
 
     use super::*;
     use crate::storage::db::DB;
@@ -302,7 +517,9 @@ mod test {
 
         let persistent = DB::open(&EngineConfig::RocksDB(db_path.clone()))?;
         let header_gen = HeaderGenerator::new(0, 0);
-        let snap1_stream = snapshot_stream(&header_gen, persistent.as_ref())?;
+        let scheduler = Arc::new(MaintenanceScheduler::new(MaintenancePolicy::Serial, &[]));
+        let permit = scheduler.acquire(MaintenanceOp::Snapshot).await;
+        let snap1_stream = snapshot_stream(&header_gen, persistent.as_ref(), permit)?;
         tokio::pin!(snap1_stream);
         let mut recv_data = Vec::new();
         while let Some(data) = snap1_stream.next().await {
@@ -312,6 +529,13 @@ mod test {
             recv_data.len() % MIN_PAGE_SIZE.numeric_cast::<usize>(),
             Sha256::output_size()
         );
+        // the permit is released once the stream ends
+        let next = tokio::time::timeout(
+            Duration::from_secs(1),
+            scheduler.acquire(MaintenanceOp::Compaction),
+        )
+        .await;
+        assert!(next.is_ok());
 
         let mut snap2 = persistent.get_snapshot(snapshot_path).unwrap();
         let size = snap2.size().numeric_cast();
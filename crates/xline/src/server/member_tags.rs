@@ -0,0 +1,145 @@
+use std::{collections::HashMap, time::Duration};
+
+use clippy_utilities::OverflowArithmetic;
+use curp::members::ServerId;
+use tokio::time::sleep;
+use tonic::{
+    metadata::{Binary, MetadataValue},
+    Status,
+};
+use xlineapi::{
+    command::{Command, CurpClient},
+    execute_error::ExecuteError,
+    server_op::{MemberTagsOp, ServerOp},
+};
+
+use crate::{
+    rpc::{RequestWrapper, TxnRequest},
+    storage::{db::WriteOp, storage_api::StorageApi, AuthStore},
+};
+
+/// Metadata key of a member list response carrying the tags of its members, as a
+/// json object from the member ids to their tags. A member without tags is absent.
+pub(crate) const MEMBER_TAGS_KEY: &str = "member-tags-bin";
+
+/// The prefix of the keys keeping the tags of the members in the meta table, the key
+/// of a member is followed by its id in big-endian and its value is the tags in json
+const MEMBER_TAGS_PREFIX: &[u8] = b"member_tags/";
+
+/// The attempts to propose a request of the tags. The auth store of a member which
+/// has just joined may lag behind the one of the leader, and the root auth info it
+/// attaches is rejected as an old revision until it catches up.
+const PROPOSE_ATTEMPTS: usize = 10;
+
+/// The interval between two attempts to propose a request of the tags
+const PROPOSE_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The tags of a member
+pub(crate) type Tags = HashMap<String, String>;
+
+/// Get the key of the tags of a member in the meta table
+pub(crate) fn tags_key(id: ServerId) -> Vec<u8> {
+    [MEMBER_TAGS_PREFIX, id.to_be_bytes().as_slice()].concat()
+}
+
+/// Get the request publishing the tags of a member. It's an admin-only server op
+/// replicated by the log, which keeps the tags out of the keyspace and takes no
+/// revision, a member without tags removes the ones it published before.
+pub(crate) fn publish_request(id: ServerId, tags: &Tags) -> RequestWrapper {
+    TxnRequest::from(ServerOp::MemberTags(MemberTagsOp::new(id, tags.clone()))).into()
+}
+
+/// Get the request removing the tags of a removed member
+pub(crate) fn remove_request(id: ServerId) -> RequestWrapper {
+    publish_request(id, &Tags::new())
+}
+
+/// Get the write op persisting the tags set by an op
+pub(crate) fn write_op<'a>(op: &MemberTagsOp) -> WriteOp<'a> {
+    if op.tags.is_empty() {
+        return WriteOp::DeleteMemberTags(op.member_id);
+    }
+    let json = serde_json::to_vec(&op.tags)
+        .unwrap_or_else(|e| unreachable!("the tags are always serializable: {e}"));
+    WriteOp::PutMemberTags(op.member_id, json)
+}
+
+/// Parse the tags of the members from the records of the meta table, the other
+/// records and the malformed ones are skipped
+pub(crate) fn parse(records: Vec<(Vec<u8>, Vec<u8>)>) -> HashMap<ServerId, Tags> {
+    records
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let id = key
+                .strip_prefix(MEMBER_TAGS_PREFIX)
+                .and_then(|id| id.try_into().ok())
+                .map(ServerId::from_be_bytes)?;
+            let tags = serde_json::from_slice(&value).ok()?;
+            Some((id, tags))
+        })
+        .collect()
+}
+
+/// Propose a request of the tags as the root user
+pub(crate) async fn propose<S: StorageApi>(
+    client: &CurpClient,
+    auth_store: &AuthStore<S>,
+    request: RequestWrapper,
+) -> Result<(), Status> {
+    let mut attempts = 1;
+    loop {
+        let auth_info = Some(auth_store.root_auth_info());
+        let cmd = Command::new_with_auth_info(request.keys(), request.clone(), auth_info);
+        match client.propose(&cmd, None, true).await? {
+            Ok(_res) => return Ok(()),
+            Err(ExecuteError::TokenOldRevision(..)) if attempts < PROPOSE_ATTEMPTS => {
+                attempts = attempts.overflow_add(1);
+                sleep(PROPOSE_RETRY_INTERVAL).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Encode the tags of the members as the metadata value of `MEMBER_TAGS_KEY`
+pub(crate) fn to_metadata(tags: &HashMap<ServerId, Tags>) -> MetadataValue<Binary> {
+    let json = serde_json::to_vec(tags)
+        .unwrap_or_else(|e| unreachable!("the tags are always serializable: {e}"));
+    MetadataValue::from_bytes(&json)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn published_tags_should_be_parsed_back() {
+        let tags = Tags::from([
+            ("region".to_owned(), "us-east-1".to_owned()),
+            ("zone".to_owned(), "a".to_owned()),
+        ]);
+        let RequestWrapper::TxnRequest(txn) = publish_request(42, &tags) else {
+            panic!("tags should be published by a txn");
+        };
+        let Ok(Some(ServerOp::MemberTags(op))) = ServerOp::from_txn(&txn) else {
+            panic!("tags should be published by a server op");
+        };
+        let WriteOp::PutMemberTags(id, json) = write_op(&op) else {
+            panic!("tags should be put");
+        };
+        let records = vec![
+            (tags_key(id), json),
+            ([MEMBER_TAGS_PREFIX, b"not-an-id"].concat(), b"{}".to_vec()),
+            (b"finished_compact_revision".to_vec(), vec![0; 8]),
+        ];
+        assert_eq!(parse(records), HashMap::from([(42, tags)]));
+
+        let RequestWrapper::TxnRequest(txn) = remove_request(42) else {
+            panic!("tags should be removed by a txn");
+        };
+        let Ok(Some(ServerOp::MemberTags(op))) = ServerOp::from_txn(&txn) else {
+            panic!("tags should be removed by a server op");
+        };
+        assert!(matches!(write_op(&op), WriteOp::DeleteMemberTags(42)));
+    }
+}
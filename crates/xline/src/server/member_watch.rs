@@ -0,0 +1,135 @@
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{future, Stream, StreamExt};
+use tonic::{
+    body::BoxBody,
+    codec::ProstCodec,
+    codegen::{http, Body, BoxFuture, Service, StdError},
+    server::{Grpc, NamedService, ServerStreamingService},
+};
+use xlineapi::member_watch::{
+    MemberWatchRequest, MemberWatchResponse, MEMBER_WATCH_PATH, MEMBER_WATCH_SERVICE_NAME,
+};
+
+use super::cluster_server::ClusterServer;
+use crate::storage::storage_api::StorageApi;
+
+/// The stream of the updates of a member watch
+type MemberUpdates = Pin<Box<dyn Stream<Item = Result<MemberWatchResponse, tonic::Status>> + Send>>;
+
+/// A server-streaming grpc service of the member watch, served at
+/// `/xlinepb.MemberWatch/Watch` with the messages of `xlineapi::member_watch`.
+///
+/// A watch yields a snapshot of the members known by this node first, then an update
+/// of every addition, removal, promotion and url update of a member applied by it, so
+/// the updates of a follower may lag behind the ones of the leader.
+pub(crate) struct MemberWatchServer<S>
+where
+    S: StorageApi,
+{
+    /// The cluster server serving the watches
+    cluster_server: Arc<ClusterServer<S>>,
+    /// The max size of a decoded request
+    max_decoding_message_size: Option<usize>,
+    /// The max size of an encoded response
+    max_encoding_message_size: Option<usize>,
+}
+
+impl<S> Clone for MemberWatchServer<S>
+where
+    S: StorageApi,
+{
+    fn clone(&self) -> Self {
+        Self {
+            cluster_server: Arc::clone(&self.cluster_server),
+            max_decoding_message_size: self.max_decoding_message_size,
+            max_encoding_message_size: self.max_encoding_message_size,
+        }
+    }
+}
+
+impl<S> MemberWatchServer<S>
+where
+    S: StorageApi,
+{
+    /// New `MemberWatchServer`
+    pub(crate) fn new(cluster_server: Arc<ClusterServer<S>>) -> Self {
+        Self {
+            cluster_server,
+            max_decoding_message_size: None,
+            max_encoding_message_size: None,
+        }
+    }
+
+    /// Limit the max size of a decoded request
+    #[must_use]
+    pub(crate) fn max_decoding_message_size(mut self, limit: usize) -> Self {
+        self.max_decoding_message_size = Some(limit);
+        self
+    }
+
+    /// Limit the max size of an encoded response
+    #[must_use]
+    pub(crate) fn max_encoding_message_size(mut self, limit: usize) -> Self {
+        self.max_encoding_message_size = Some(limit);
+        self
+    }
+}
+
+impl<S> ServerStreamingService<MemberWatchRequest> for MemberWatchServer<S>
+where
+    S: StorageApi,
+{
+    type Response = MemberWatchResponse;
+    type ResponseStream = MemberUpdates;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+
+    fn call(&mut self, _request: tonic::Request<MemberWatchRequest>) -> Self::Future {
+        let updates: MemberUpdates = Box::pin(self.cluster_server.watch_members().map(Ok));
+        Box::pin(future::ok(tonic::Response::new(updates)))
+    }
+}
+
+impl<S, B> Service<http::Request<B>> for MemberWatchServer<S>
+where
+    S: StorageApi,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    #[inline]
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        if req.uri().path() != MEMBER_WATCH_PATH {
+            let status = tonic::Status::unimplemented(format!("{} is unknown", req.uri().path()));
+            return Box::pin(future::ok(status.to_http()));
+        }
+        let server = self.clone();
+        Box::pin(async move {
+            let mut grpc = Grpc::new(ProstCodec::default()).apply_max_message_size_config(
+                server.max_decoding_message_size,
+                server.max_encoding_message_size,
+            );
+            Ok(grpc.server_streaming(server, req).await)
+        })
+    }
+}
+
+impl<S> NamedService for MemberWatchServer<S>
+where
+    S: StorageApi,
+{
+    const NAME: &'static str = MEMBER_WATCH_SERVICE_NAME;
+}
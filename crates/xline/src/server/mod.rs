@@ -1,25 +1,108 @@
+/// Apply the max send and receive message sizes to a grpc service or client
+#[cfg(not(madsim))]
+macro_rules! with_message_size {
+    ($service:expr, $max_send:expr, $max_recv:expr) => {
+        $service
+            .max_encoding_message_size($max_send)
+            .max_decoding_message_size($max_recv)
+    };
+}
+
+/// The simulated grpc services and clients don't limit the message size
+#[cfg(madsim)]
+macro_rules! with_message_size {
+    ($service:expr, $max_send:expr, $max_recv:expr) => {{
+        let _ignore = ($max_send, $max_recv);
+        $service
+    }};
+}
+
+/// Last read times of keys
+mod access_tracker;
+/// Admin rpcs without an etcd counterpart
+#[cfg(not(madsim))]
+mod admin_server;
+/// Watchdog of the apply progress
+mod apply_watchdog;
+/// Pluggable sinks of the compacted versions
+mod archive_sink;
+/// Pluggable verifiers of the credentials
+mod auth_backend;
 /// Xline auth server
 mod auth_server;
 /// Auth Wrapper
 mod auth_wrapper;
+/// Automatic defragmentation
+mod auto_defrag;
 /// Barriers for range requests
 mod barriers;
 /// Cluster server
 mod cluster_server;
 /// Command to be executed
 pub(crate) mod command;
+/// Hooks around the apply of commands
+mod command_hook;
+/// Compare-and-set of guarded keys
+mod guarded_write;
+/// Write-once keys under key prefixes
+mod immutable_keys;
+/// Character policies of the keys under key prefixes
+mod key_charset;
 /// Xline kv server
 mod kv_server;
+/// Guard of lease-attached keys against silent detaching
+mod lease_guard;
 /// Xline lease server
 mod lease_server;
+/// Watches of the lease events
+mod lease_watch;
+/// Read-only and write-only client listeners
+#[cfg(not(madsim))]
+mod listener_role;
 /// Xline lock server
 mod lock_server;
 /// Xline maintenance client
 mod maintenance;
+/// Tags of the members
+pub(crate) mod member_tags;
+/// Server-streaming of the member updates
+#[cfg(not(madsim))]
+mod member_watch;
+/// Throttle of the proposals in flight
+mod propose_throttle;
+/// Server-streaming of large ranges
+#[cfg(not(madsim))]
+mod range_stream;
+/// Continuation tokens of paged ranges
+mod range_token;
+/// Read index waiter
+mod read_index;
+/// Sampled logging of the requests
+mod request_log;
+/// Ordering of the requests of a connection
+mod request_order;
+/// Storage health of the members
+mod storage_health;
+/// Restricted tls termination of the listeners
+#[cfg(not(madsim))]
+mod tls;
+/// Request statistics of the users
+mod user_stats;
+/// Validators of the values under key prefixes
+mod value_validator;
+/// Splitting of oversized watch responses
+mod watch_fragment;
+/// Projection of watched values
+mod watch_projection;
 /// Xline watch server
 mod watch_server;
+/// Coalescing of hinted writes
+mod write_coalescer;
 /// Xline server
 mod xline_server;
 
-pub use self::xline_server::XlineServer;
+pub use self::{
+    archive_sink::ArchiveSink, auth_backend::AuthBackend, command_hook::CommandHook,
+    value_validator::ValueValidator, xline_server::XlineServer,
+};
 pub(crate) use self::{auth_server::get_token, maintenance::MAINTENANCE_SNAPSHOT_CHUNK_SIZE};
@@ -0,0 +1,148 @@
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use tonic::metadata::MetadataValue;
+
+use crate::metrics;
+
+/// Metadata key of the time in milliseconds after which a throttled request should be
+/// retried, carried by the `ResourceExhausted` status of the request
+pub(crate) const RETRY_AFTER_MS_KEY: &str = "retry-after-ms";
+
+/// The minimum retry-after hint of a throttled request
+const MIN_RETRY_AFTER: Duration = Duration::from_millis(1);
+
+/// Throttle of the proposals of a node.
+///
+/// The proposals in flight, which are proposed but have not returned yet, are counted.
+/// Once there are as many as the limit, a new proposal is rejected with
+/// `ResourceExhausted` at once instead of queuing behind the others, which would grow
+/// the latency of all of them. The rejection carries a retry-after hint, which is the
+/// moving average of the latencies of the recent proposals, about when the pipeline
+/// has room again.
+#[derive(Debug)]
+pub(crate) struct ProposeThrottle {
+    /// The maximum number of proposals in flight, 0 means unlimited
+    max_inflight: usize,
+    /// The number of proposals in flight
+    inflight: AtomicUsize,
+    /// The moving average of the latencies of the proposals in microseconds
+    avg_latency_us: AtomicU64,
+}
+
+/// A proposal in flight, it leaves the pipeline when dropped
+#[derive(Debug)]
+pub(crate) struct InflightProposal<'a> {
+    /// The throttle counting the proposal, `None` if the throttle is disabled
+    throttle: Option<&'a ProposeThrottle>,
+    /// When the proposal starts
+    start: Instant,
+}
+
+impl ProposeThrottle {
+    /// New `ProposeThrottle`
+    pub(crate) fn new(max_inflight: usize) -> Self {
+        Self {
+            max_inflight,
+            inflight: AtomicUsize::new(0),
+            avg_latency_us: AtomicU64::new(0),
+        }
+    }
+
+    /// Enter a new proposal into the pipeline, it fails with a `ResourceExhausted`
+    /// status carrying the retry-after hint if the pipeline is full
+    pub(crate) fn enter(&self) -> Result<InflightProposal<'_>, tonic::Status> {
+        if self.max_inflight == 0 {
+            return Ok(InflightProposal {
+                throttle: None,
+                start: Instant::now(),
+            });
+        }
+        let entered = self
+            .inflight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |inflight| {
+                (inflight < self.max_inflight).then(|| inflight.saturating_add(1))
+            })
+            .is_ok();
+        if !entered {
+            metrics::get().throttled_proposals_total.add(1, &[]);
+            return Err(self.throttled());
+        }
+        Ok(InflightProposal {
+            throttle: Some(self),
+            start: Instant::now(),
+        })
+    }
+
+    /// The time after which a throttled proposal should be retried
+    fn retry_after(&self) -> Duration {
+        Duration::from_micros(self.avg_latency_us.load(Ordering::Relaxed)).max(MIN_RETRY_AFTER)
+    }
+
+    /// The status of a throttled proposal
+    fn throttled(&self) -> tonic::Status {
+        let retry_after_ms = u64::try_from(self.retry_after().as_millis()).unwrap_or(u64::MAX);
+        let mut status = tonic::Status::resource_exhausted("etcdserver: too many requests");
+        let _prev = status
+            .metadata_mut()
+            .insert(RETRY_AFTER_MS_KEY, MetadataValue::from(retry_after_ms));
+        status
+    }
+
+    /// Record the latency of a proposal leaving the pipeline
+    fn leave(&self, latency: Duration) {
+        let sample = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let _prev = self
+            .avg_latency_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |avg| {
+                if avg == 0 {
+                    return Some(sample);
+                }
+                // weight the new sample by 1/8
+                avg.saturating_mul(7).saturating_add(sample).checked_div(8)
+            });
+        let _prev = self.inflight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Drop for InflightProposal<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(throttle) = self.throttle {
+            throttle.leave(self.start.elapsed());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn proposals_beyond_the_limit_should_be_throttled_with_a_hint() {
+        let throttle = ProposeThrottle::new(2);
+        let first = throttle.enter().unwrap();
+        let second = throttle.enter().unwrap();
+        let status = throttle.enter().unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(
+            status.metadata().get(RETRY_AFTER_MS_KEY).unwrap(),
+            &MetadataValue::from(1_u64)
+        );
+
+        std::thread::sleep(Duration::from_millis(20));
+        drop(first);
+        assert!(throttle.retry_after() >= Duration::from_millis(20));
+        let third = throttle.enter().unwrap();
+        assert!(throttle.enter().is_err());
+        drop(second);
+        drop(third);
+        assert_eq!(throttle.inflight.load(Ordering::Relaxed), 0);
+
+        let unlimited = ProposeThrottle::new(0);
+        let proposals: Vec<_> = (0..100).map(|_| unlimited.enter().unwrap()).collect();
+        assert_eq!(proposals.len(), 100);
+    }
+}
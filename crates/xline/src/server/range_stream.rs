@@ -0,0 +1,229 @@
+use std::{
+    convert::Infallible,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use async_stream::try_stream;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
+use futures::{future, Stream};
+use tonic::{
+    body::BoxBody,
+    codec::ProstCodec,
+    codegen::{http, Body, BoxFuture, Service, StdError},
+    server::{Grpc, NamedService, ServerStreamingService},
+};
+
+use super::{
+    kv_server::KvServer,
+    range_token::{RangeToken, RANGE_TOKEN_KEY},
+    request_order::REQUEST_SEQ_KEY,
+};
+use crate::{
+    rpc::{Kv, RangeRequest, RangeResponse},
+    storage::storage_api::StorageApi,
+};
+
+/// The grpc service name of the streamed range
+const SERVICE_NAME: &str = "xlinepb.RangeStream";
+
+/// The grpc path of the streamed range
+const RANGE_STREAM_PATH: &str = "/xlinepb.RangeStream/Range";
+
+/// The stream of the batches of a streamed range
+type RangeBatches = Pin<Box<dyn Stream<Item = Result<RangeResponse, tonic::Status>> + Send>>;
+
+/// A server-streaming grpc service of the ranges too large for a single response,
+/// served at `/xlinepb.RangeStream/Range` with the messages of the kv range.
+///
+/// A streamed range is served as the pages of a paged range, each response is a batch
+/// of up to the configured batch size of keys, which is produced only when the client
+/// has consumed the previous ones. The first batch is pinned to the revision at which
+/// it is served, so the following batches are served from the same snapshot however
+/// the keys are modified in the meantime, and a batch whose revision is compacted ends
+/// the stream with the compaction error. The limit, `keys_only`, `count_only` and the
+/// revision filters of the range are honored, the count of each batch is the count of
+/// the keys from its first key, and `more` of the last batch tells whether the limit
+/// left keys out. Only the ranges ordered by key ascending can be streamed. Every batch
+/// is served, logged and counted in the user statistics as a range.
+pub(crate) struct RangeStreamServer<S>
+where
+    S: StorageApi,
+{
+    /// The kv server serving the batches
+    kv_server: Arc<KvServer<S>>,
+    /// The max number of keys in a batch
+    batch_size: usize,
+    /// The max size of a decoded request
+    max_decoding_message_size: Option<usize>,
+    /// The max size of an encoded response
+    max_encoding_message_size: Option<usize>,
+}
+
+impl<S> Clone for RangeStreamServer<S>
+where
+    S: StorageApi,
+{
+    fn clone(&self) -> Self {
+        Self {
+            kv_server: Arc::clone(&self.kv_server),
+            batch_size: self.batch_size,
+            max_decoding_message_size: self.max_decoding_message_size,
+            max_encoding_message_size: self.max_encoding_message_size,
+        }
+    }
+}
+
+impl<S> RangeStreamServer<S>
+where
+    S: StorageApi,
+{
+    /// New `RangeStreamServer`
+    pub(crate) fn new(kv_server: Arc<KvServer<S>>, batch_size: usize) -> Self {
+        Self {
+            kv_server,
+            batch_size,
+            max_decoding_message_size: None,
+            max_encoding_message_size: None,
+        }
+    }
+
+    /// Limit the max size of a decoded request
+    #[must_use]
+    pub(crate) fn max_decoding_message_size(mut self, limit: usize) -> Self {
+        self.max_decoding_message_size = Some(limit);
+        self
+    }
+
+    /// Limit the max size of an encoded response
+    #[must_use]
+    pub(crate) fn max_encoding_message_size(mut self, limit: usize) -> Self {
+        self.max_encoding_message_size = Some(limit);
+        self
+    }
+
+    /// The limit of the next batch of a range with `limit` which has streamed
+    /// `streamed` keys, 0 if the limit is reached
+    fn batch_limit(batch_size: i64, limit: i64, streamed: i64) -> i64 {
+        if limit <= 0 {
+            return batch_size;
+        }
+        batch_size.min(limit.overflow_sub(streamed).max(0))
+    }
+
+    /// Serve the first batch of a streamed range, and then the stream of it and the
+    /// following batches
+    async fn range(
+        self,
+        request: tonic::Request<RangeRequest>,
+    ) -> Result<RangeBatches, tonic::Status> {
+        if !RangeToken::is_key_ordered(request.get_ref()) {
+            return Err(tonic::Status::invalid_argument(
+                "a streamed range requires the range to be ordered by key ascending",
+            ));
+        }
+        let batch_size: i64 = self.batch_size.numeric_cast();
+        let (mut metadata, extensions, mut range_req) = request.into_parts();
+        let limit = range_req.limit;
+        range_req.limit = Self::batch_limit(batch_size, limit, 0);
+        let first = tonic::Request::from_parts(metadata.clone(), extensions, range_req.clone());
+        let mut response = self.kv_server.range(first).await?;
+        // the following batches are served from the revision pinned by the first one,
+        // which has been applied by this node, and are ordered by the first one
+        range_req.serializable = true;
+        let _ignore = metadata.remove(REQUEST_SEQ_KEY);
+        let kv_server = self.kv_server;
+        let stream = try_stream! {
+            let mut streamed = 0_i64;
+            loop {
+                let token = response.metadata().get(RANGE_TOKEN_KEY).cloned();
+                let batch = response.into_inner();
+                streamed = streamed.overflow_add(batch.kvs.len().numeric_cast());
+                let next_limit = Self::batch_limit(batch_size, limit, streamed);
+                yield batch;
+                let Some(token) = token else {
+                    break;
+                };
+                if next_limit == 0 {
+                    break;
+                }
+                let mut next = tonic::Request::new(RangeRequest {
+                    limit: next_limit,
+                    ..range_req.clone()
+                });
+                *next.metadata_mut() = metadata.clone();
+                let _prev = next.metadata_mut().insert(RANGE_TOKEN_KEY, token);
+                response = kv_server.range(next).await?;
+            }
+        };
+        Ok(Box::pin(stream))
+    }
+}
+
+impl<S> ServerStreamingService<RangeRequest> for RangeStreamServer<S>
+where
+    S: StorageApi,
+{
+    type Response = RangeResponse;
+    type ResponseStream = RangeBatches;
+    type Future = BoxFuture<tonic::Response<Self::ResponseStream>, tonic::Status>;
+
+    fn call(&mut self, request: tonic::Request<RangeRequest>) -> Self::Future {
+        let server = self.clone();
+        Box::pin(async move { server.range(request).await.map(tonic::Response::new) })
+    }
+}
+
+impl<S, B> Service<http::Request<B>> for RangeStreamServer<S>
+where
+    S: StorageApi,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    #[inline]
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        if req.uri().path() != RANGE_STREAM_PATH {
+            let status = tonic::Status::unimplemented(format!("{} is unknown", req.uri().path()));
+            return Box::pin(future::ok(status.to_http()));
+        }
+        let server = self.clone();
+        Box::pin(async move {
+            let mut grpc = Grpc::new(ProstCodec::default()).apply_max_message_size_config(
+                server.max_decoding_message_size,
+                server.max_encoding_message_size,
+            );
+            Ok(grpc.server_streaming(server, req).await)
+        })
+    }
+}
+
+impl<S> NamedService for RangeStreamServer<S>
+where
+    S: StorageApi,
+{
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::db::DB;
+
+    #[test]
+    fn batches_should_be_bounded_by_the_limit() {
+        assert_eq!(RangeStreamServer::<DB>::batch_limit(10, 0, 30), 10);
+        assert_eq!(RangeStreamServer::<DB>::batch_limit(10, 25, 10), 10);
+        assert_eq!(RangeStreamServer::<DB>::batch_limit(10, 25, 20), 5);
+        assert_eq!(RangeStreamServer::<DB>::batch_limit(10, 25, 25), 0);
+    }
+}
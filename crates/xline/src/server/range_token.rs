@@ -0,0 +1,185 @@
+use std::fmt::Write;
+
+use tonic::metadata::MetadataMap;
+
+use crate::rpc::{RangeRequest, RangeResponse, SortOrder, SortTarget};
+
+/// Metadata key of the continuation token of a paged range, it is returned with a
+/// range response which has more keys and passed back to get the next page
+pub(crate) const RANGE_TOKEN_KEY: &str = "range-continuation-token";
+
+/// An opaque continuation token of a paged range.
+///
+/// The first page of a range with a limit is pinned to the revision at which it is
+/// served, the token carries that revision and the last returned key, so that the
+/// following pages are served from the same snapshot however the keys are modified
+/// in the meantime. A page whose revision is compacted fails with the compaction
+/// error. Only the ranges ordered by key ascending can be paged by tokens.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct RangeToken {
+    /// The revision which the range is pinned to
+    revision: i64,
+    /// The last key returned by the previous page
+    last_key: Vec<u8>,
+}
+
+impl RangeToken {
+    /// Get the token from the metadata of a range request, `None` means the range
+    /// starts from its key
+    pub(crate) fn from_metadata(metadata: &MetadataMap) -> Result<Option<Self>, tonic::Status> {
+        let Some(value) = metadata.get(RANGE_TOKEN_KEY) else {
+            return Ok(None);
+        };
+        value
+            .to_str()
+            .ok()
+            .and_then(Self::decode)
+            .map(Some)
+            .ok_or_else(|| tonic::Status::invalid_argument("invalid range continuation token"))
+    }
+
+    /// Build the token of the next page of a response, `None` if there is no next page
+    /// or the range can't be paged by tokens
+    pub(crate) fn next_page(req: &RangeRequest, res: &RangeResponse) -> Option<Self> {
+        if !res.more || req.revision <= 0 || !Self::is_key_ordered(req) {
+            return None;
+        }
+        res.kvs.last().map(|kv| Self {
+            revision: req.revision,
+            last_key: kv.key.clone(),
+        })
+    }
+
+    /// Continue the range after the last key of the token at its revision
+    pub(crate) fn resume(self, req: &mut RangeRequest) -> Result<(), tonic::Status> {
+        if !Self::is_key_ordered(req) {
+            return Err(tonic::Status::invalid_argument(
+                "range continuation token requires the range to be ordered by key ascending",
+            ));
+        }
+        if req.revision > 0 && req.revision != self.revision {
+            return Err(tonic::Status::invalid_argument(
+                "range continuation token is pinned to another revision",
+            ));
+        }
+        let to_end = req.range_end.as_slice() == [0];
+        if self.last_key < req.key
+            || req.range_end.is_empty()
+            || (!to_end && self.last_key >= req.range_end)
+        {
+            return Err(tonic::Status::invalid_argument(
+                "range continuation token is out of the range",
+            ));
+        }
+        let mut next_key = self.last_key;
+        next_key.push(0);
+        req.key = next_key;
+        req.revision = self.revision;
+        Ok(())
+    }
+
+    /// Whether the range is returned in the ascending order of keys
+    pub(crate) fn is_key_ordered(req: &RangeRequest) -> bool {
+        match req.sort_order() {
+            SortOrder::None => true,
+            SortOrder::Ascend => req.sort_target() == SortTarget::Key,
+            SortOrder::Descend => false,
+        }
+    }
+
+    /// Encode the token
+    pub(crate) fn encode(&self) -> String {
+        let mut token = format!("{}.", self.revision);
+        for byte in &self.last_key {
+            let _ignore = write!(token, "{byte:02x}");
+        }
+        token
+    }
+
+    /// Decode a token
+    fn decode(token: &str) -> Option<Self> {
+        let (revision, key) = token.split_once('.')?;
+        let revision = revision.parse::<i64>().ok().filter(|rev| *rev > 0)?;
+        let pairs = key.as_bytes().chunks_exact(2);
+        if key.is_empty() || !pairs.remainder().is_empty() {
+            return None;
+        }
+        let last_key = pairs
+            .map(|pair| {
+                std::str::from_utf8(pair)
+                    .ok()
+                    .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            })
+            .collect::<Option<_>>()?;
+        Some(Self { revision, last_key })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::KeyValue;
+
+    #[test]
+    fn token_should_be_encoded_and_decoded() {
+        let token = RangeToken {
+            revision: 42,
+            last_key: vec![b'k', 0, 0xff],
+        };
+        let encoded = token.encode();
+        assert_eq!(RangeToken::decode(&encoded), Some(token));
+        for invalid in ["", "42", "42.", "0.6b", "-1.6b", "42.6", "42.zz", "x.6b"] {
+            assert_eq!(RangeToken::decode(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn token_should_resume_after_the_last_key_at_the_pinned_revision() {
+        let req = RangeRequest {
+            key: b"a".to_vec(),
+            range_end: b"z".to_vec(),
+            limit: 2,
+            revision: 5,
+            ..Default::default()
+        };
+        let res = RangeResponse {
+            kvs: vec![
+                KeyValue {
+                    key: b"b".to_vec(),
+                    ..Default::default()
+                },
+                KeyValue {
+                    key: b"c".to_vec(),
+                    ..Default::default()
+                },
+            ],
+            more: true,
+            ..Default::default()
+        };
+        let token = RangeToken::next_page(&req, &res).unwrap();
+        let mut next = RangeRequest {
+            revision: 0,
+            ..req.clone()
+        };
+        token.clone().resume(&mut next).unwrap();
+        assert_eq!(next.key, b"c\0".to_vec());
+        assert_eq!(next.revision, 5);
+
+        let last_page = RangeResponse {
+            more: false,
+            ..res.clone()
+        };
+        assert!(RangeToken::next_page(&req, &last_page).is_none());
+        let mut other_range = RangeRequest {
+            key: b"d".to_vec(),
+            ..req.clone()
+        };
+        assert!(token.clone().resume(&mut other_range).is_err());
+        let mut descend = RangeRequest {
+            sort_order: SortOrder::Descend.into(),
+            ..req
+        };
+        assert!(RangeToken::next_page(&descend, &res).is_none());
+        assert!(token.resume(&mut descend).is_err());
+    }
+}
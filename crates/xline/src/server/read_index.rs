@@ -0,0 +1,344 @@
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use curp::rpc::ReadState;
+use futures::future::join_all;
+use parking_lot::Mutex;
+use tokio::{sync::oneshot, time::timeout};
+use tracing::debug;
+use xlineapi::{
+    command::{Command, CurpClient, KeyRange},
+    RequestWrapper,
+};
+
+use super::barriers::{IdBarrier, IndexBarrier};
+use crate::metrics;
+
+/// Linearizable reads waiting to share a read index
+#[derive(Debug)]
+struct PendingReads {
+    /// The keys of all the reads
+    keys: Vec<KeyRange>,
+    /// The request of the first read, the read state only depends on the keys of all
+    /// the reads and the backend of the request
+    request: RequestWrapper,
+    /// The senders of the results of the reads
+    senders: Vec<oneshot::Sender<Result<(), tonic::Status>>>,
+}
+
+/// Waiter that fetches the read state from the cluster and waits until the
+/// current node has applied it
+pub(crate) struct ReadIndexWaiter {
+    /// Consensus client
+    client: Arc<CurpClient>,
+    /// Barrier for applied index
+    index_barrier: Arc<IndexBarrier>,
+    /// Barrier for propose id
+    id_barrier: Arc<IdBarrier>,
+    /// Read state retry timeout
+    retry_timeout: Duration,
+    /// The window in which the reads are coalesced, 0 means the coalescing is disabled
+    batch_window: Duration,
+    /// The reads waiting for the window of the first one to expire
+    pending: Mutex<Option<PendingReads>>,
+    /// The number of the read states fetched from the cluster
+    round_trips: AtomicU64,
+    /// The maximum time a catch-up read waits for the commit index to be applied
+    catch_up_timeout: Duration,
+}
+
+impl Debug for ReadIndexWaiter {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReadIndexWaiter")
+            .field("index_barrier", &self.index_barrier)
+            .field("id_barrier", &self.id_barrier)
+            .field("retry_timeout", &self.retry_timeout)
+            .field("batch_window", &self.batch_window)
+            .field("catch_up_timeout", &self.catch_up_timeout)
+            .finish()
+    }
+}
+
+impl ReadIndexWaiter {
+    /// New `ReadIndexWaiter`
+    pub(crate) fn new(
+        client: Arc<CurpClient>,
+        index_barrier: Arc<IndexBarrier>,
+        id_barrier: Arc<IdBarrier>,
+        retry_timeout: Duration,
+        batch_window: Duration,
+        catch_up_timeout: Duration,
+    ) -> Self {
+        Self {
+            client,
+            index_barrier,
+            id_barrier,
+            retry_timeout,
+            batch_window,
+            pending: Mutex::new(None),
+            round_trips: AtomicU64::new(0),
+            catch_up_timeout,
+        }
+    }
+
+    /// Fetch the read state of the command and wait until it is applied.
+    ///
+    /// If the batch window is set, the reads arriving in the window of the first one
+    /// are coalesced into a single read state covering all their keys. The read state
+    /// is only fetched after the window expires and no read joins the batch after
+    /// that, so every read still waits for a read state fetched after it arrives.
+    pub(crate) async fn wait(self: &Arc<Self>, cmd: &Command) -> Result<(), tonic::Status> {
+        if self.batch_window.is_zero() {
+            return self.wait_read_state(cmd).await;
+        }
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock();
+            if let Some(ref mut reads) = *pending {
+                reads.keys.extend_from_slice(cmd.keys());
+                reads.senders.push(tx);
+            } else {
+                *pending = Some(PendingReads {
+                    keys: cmd.keys().to_vec(),
+                    request: cmd.request().clone(),
+                    senders: vec![tx],
+                });
+                let waiter = Arc::clone(self);
+                let _handle = tokio::spawn(async move {
+                    tokio::time::sleep(waiter.batch_window).await;
+                    let reads = waiter.pending.lock().take();
+                    if let Some(reads) = reads {
+                        waiter.wait_batch(reads).await;
+                    }
+                });
+            }
+        }
+        rx.await
+            .unwrap_or_else(|_e| Err(tonic::Status::internal("coalesced read is dropped")))
+    }
+
+    /// Wait for the read state of a batch and send the result to all its reads
+    async fn wait_batch(&self, reads: PendingReads) {
+        debug!(
+            "fetch the read state of {} coalesced reads",
+            reads.senders.len()
+        );
+        let cmd = Command::new(reads.keys, reads.request);
+        let result = self.wait_read_state(&cmd).await;
+        for sender in reads.senders {
+            let _ignore = sender.send(result.clone());
+        }
+    }
+
+    /// Fetch the read state of the command from the cluster and wait until it is applied
+    async fn wait_read_state(&self, cmd: &Command) -> Result<(), tonic::Status> {
+        loop {
+            let _prev = self.round_trips.fetch_add(1, Ordering::Relaxed);
+            let rd_state = self.client.fetch_read_state(cmd).await.map_err(|e| {
+                metrics::get().read_indexes_failed_total.add(1, &[]);
+                e
+            })?;
+            let wait_future = async move {
+                match rd_state {
+                    ReadState::Ids(id_set) => {
+                        debug!(?id_set, "Range wait for command ids");
+                        let fus = id_set
+                            .inflight_ids
+                            .into_iter()
+                            .map(|id| self.id_barrier.wait(id))
+                            .collect::<Vec<_>>();
+                        let _ignore = join_all(fus).await;
+                    }
+                    ReadState::CommitIndex(index) => {
+                        debug!(?index, "Range wait for commit index");
+                        self.index_barrier.wait(index).await;
+                    }
+                }
+            };
+            if timeout(self.retry_timeout, wait_future).await.is_ok() {
+                break;
+            }
+            metrics::get().slow_read_indexes_total.add(1, &[]);
+        }
+        Ok(())
+    }
+
+    /// Wait until the current node applies a commit index learned from the leader, it
+    /// fails if the index isn't applied within the catch-up timeout.
+    ///
+    /// Unlike a linearizable read, no read state is fetched from the cluster. The commit
+    /// index is carried by the heartbeats of the leader, so it may be behind the latest
+    /// one by about a heartbeat.
+    pub(crate) async fn wait_caught_up(&self, commit_index: u64) -> Result<(), tonic::Status> {
+        timeout(self.catch_up_timeout, self.index_barrier.wait(commit_index))
+            .await
+            .map_err(|_e| {
+                tonic::Status::deadline_exceeded(format!(
+                    "the node doesn't apply the commit index {commit_index} in {:?}",
+                    self.catch_up_timeout
+                ))
+            })
+    }
+
+    /// Get the number of the read states fetched from the cluster
+    #[cfg(test)]
+    fn round_trips(&self) -> u64 {
+        self.round_trips.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use async_trait::async_trait;
+    use curp::{
+        members::ServerId,
+        rpc::{ConfChange, FetchClusterResponse, Member},
+    };
+    use xlineapi::{
+        command::{CommandResponse, SyncResponse},
+        execute_error::ExecuteError,
+    };
+
+    use super::*;
+    use crate::rpc::RangeRequest;
+
+    /// A client whose read states are the commit index when they are fetched
+    #[derive(Debug, Default)]
+    struct FakeClient {
+        /// The commit index
+        commit_index: AtomicU64,
+    }
+
+    #[async_trait]
+    impl curp::client::ClientApi for FakeClient {
+        type Error = tonic::Status;
+
+        type Cmd = Command;
+
+        async fn propose(
+            &self,
+            _cmd: &Command,
+            _token: Option<&String>,
+            _use_fast_path: bool,
+        ) -> Result<Result<(CommandResponse, Option<SyncResponse>), ExecuteError>, tonic::Status>
+        {
+            unreachable!("the waiter never proposes")
+        }
+
+        async fn propose_conf_change(
+            &self,
+            _changes: Vec<ConfChange>,
+        ) -> Result<Vec<Member>, tonic::Status> {
+            unreachable!("the waiter never changes the membership")
+        }
+
+        async fn propose_shutdown(&self) -> Result<(), tonic::Status> {
+            unreachable!("the waiter never shuts down the cluster")
+        }
+
+        async fn propose_publish(
+            &self,
+            _node_id: ServerId,
+            _node_name: String,
+            _node_client_urls: Vec<String>,
+        ) -> Result<(), tonic::Status> {
+            unreachable!("the waiter never publishes")
+        }
+
+        async fn move_leader(&self, _node_id: ServerId) -> Result<(), tonic::Status> {
+            unreachable!("the waiter never moves the leader")
+        }
+
+        async fn fetch_read_state(&self, _cmd: &Command) -> Result<ReadState, tonic::Status> {
+            Ok(ReadState::CommitIndex(
+                self.commit_index.load(Ordering::Relaxed),
+            ))
+        }
+
+        async fn fetch_cluster(
+            &self,
+            _linearizable: bool,
+        ) -> Result<FetchClusterResponse, tonic::Status> {
+            unreachable!("the waiter never fetches the cluster")
+        }
+    }
+
+    fn range(key: &str) -> Command {
+        let request = RequestWrapper::from(RangeRequest {
+            key: key.into(),
+            ..RangeRequest::default()
+        });
+        Command::new(request.keys(), request)
+    }
+
+    async fn concurrent_reads(batch_window: Duration) -> u64 {
+        let client = Arc::new(FakeClient::default());
+        let index_barrier = Arc::new(IndexBarrier::new());
+        let waiter = Arc::new(ReadIndexWaiter::new(
+            Arc::clone(&client) as Arc<CurpClient>,
+            Arc::clone(&index_barrier),
+            Arc::new(IdBarrier::new()),
+            Duration::from_secs(10),
+            batch_window,
+            Duration::from_secs(1),
+        ));
+        client.commit_index.store(5, Ordering::Relaxed);
+        let reads: Vec<_> = (0..100)
+            .map(|i| {
+                let waiter = Arc::clone(&waiter);
+                tokio::spawn(async move { waiter.wait(&range(&format!("key{i}"))).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(
+            reads.iter().all(|read| !read.is_finished()),
+            "no read should finish before the read index is applied"
+        );
+        index_barrier.trigger(5);
+        for read in reads {
+            read.await.unwrap().unwrap();
+        }
+        waiter.round_trips()
+    }
+
+    #[tokio::test]
+    async fn catch_up_reads_should_wait_for_the_commit_index_within_the_timeout() {
+        let index_barrier = Arc::new(IndexBarrier::new());
+        let waiter = Arc::new(ReadIndexWaiter::new(
+            Arc::new(FakeClient::default()) as Arc<CurpClient>,
+            Arc::clone(&index_barrier),
+            Arc::new(IdBarrier::new()),
+            Duration::from_secs(10),
+            Duration::ZERO,
+            Duration::from_millis(500),
+        ));
+        index_barrier.trigger(3);
+        waiter.wait_caught_up(3).await.unwrap();
+
+        let read = {
+            let waiter = Arc::clone(&waiter);
+            tokio::spawn(async move { waiter.wait_caught_up(5).await })
+        };
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!read.is_finished(), "the read should wait for the catch-up");
+        index_barrier.trigger(5);
+        read.await.unwrap().unwrap();
+
+        let status = waiter.wait_caught_up(6).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+    }
+
+    #[tokio::test]
+    async fn concurrent_reads_should_share_read_indexes() {
+        assert_eq!(concurrent_reads(Duration::ZERO).await, 100);
+        let round_trips = concurrent_reads(Duration::from_millis(50)).await;
+        assert!(round_trips < 10, "{round_trips}");
+    }
+}
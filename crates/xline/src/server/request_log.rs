@@ -0,0 +1,41 @@
+use std::{future::Future, time::Instant};
+
+use tracing::{info, warn};
+use utils::request_log::RequestLogSampler;
+
+/// Handle a request and log it if it's chosen by the installed `RequestLogSampler`.
+///
+/// The request is sampled before it is handled, and its summary is only taken when
+/// the sampler may log anything, so the sampling costs nothing if it's disabled.
+pub(crate) async fn log_sampled<Req, Res, F>(
+    method: &'static str,
+    request: tonic::Request<Req>,
+    summary: impl FnOnce(&Req) -> String,
+    handle: impl FnOnce(tonic::Request<Req>) -> F,
+) -> Result<tonic::Response<Res>, tonic::Status>
+where
+    F: Future<Output = Result<tonic::Response<Res>, tonic::Status>>,
+{
+    let sampler = RequestLogSampler::current();
+    if !sampler.is_enabled() {
+        return handle(request).await;
+    }
+    let sampled = sampler.sample();
+    let remote_addr = request.remote_addr();
+    let summary = summary(request.get_ref());
+    let start = Instant::now();
+    let result = handle(request).await;
+    let elapsed = start.elapsed();
+    if sampler.should_log(sampled, result.is_err(), elapsed) {
+        let peer = remote_addr.map_or_else(|| "unknown".to_owned(), |addr| addr.to_string());
+        match result {
+            Ok(_) => info!("{method} from {peer} took {elapsed:?}: {summary}"),
+            Err(ref status) => warn!(
+                "{method} from {peer} failed after {elapsed:?} with {:?}, {}: {summary}",
+                status.code(),
+                status.message()
+            ),
+        }
+    }
+    result
+}
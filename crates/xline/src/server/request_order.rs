@@ -0,0 +1,298 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clippy_utilities::OverflowArithmetic;
+use event_listener::Event;
+use parking_lot::Mutex;
+use tonic::metadata::MetadataMap;
+use tracing::warn;
+use xlineapi::command::KeyRange;
+
+/// Metadata key of the sequence number of a request on its connection, the
+/// requests of a connection are numbered from 1 in the order they are sent
+pub(crate) const REQUEST_SEQ_KEY: &str = "request-seq";
+
+/// The time after which the order of an idle connection is forgotten
+const IDLE_CONNECTION_TTL: Duration = Duration::from_secs(300);
+
+/// The order of the numbered requests of a connection
+#[derive(Debug)]
+struct ConnectionOrder {
+    /// Every sequence number below it has arrived or has been given up
+    next: u64,
+    /// The arrived sequence numbers above `next`
+    arrived_ahead: BTreeSet<u64>,
+    /// The keys of the requests which have arrived but not returned
+    in_flight: BTreeMap<u64, Vec<KeyRange>>,
+    /// Notified whenever a request arrives or returns
+    changed: Event,
+    /// The last time a request arrived or returned
+    last_active: Instant,
+}
+
+impl ConnectionOrder {
+    /// New `ConnectionOrder`
+    fn new() -> Self {
+        Self {
+            next: 1,
+            arrived_ahead: BTreeSet::new(),
+            in_flight: BTreeMap::new(),
+            changed: Event::new(),
+            last_active: Instant::now(),
+        }
+    }
+
+    /// Move `next` over the arrived sequence numbers
+    fn advance(&mut self) {
+        while self.arrived_ahead.remove(&self.next) {
+            self.next = self.next.overflow_add(1);
+        }
+    }
+
+    /// Give up waiting for the sequence numbers up to `seq`, they are rejected if
+    /// they arrive later
+    fn give_up_to(&mut self, seq: u64) {
+        let next = seq.overflow_add(1);
+        self.arrived_ahead = self.arrived_ahead.split_off(&next);
+        self.next = self.next.max(next);
+        self.advance();
+    }
+
+    /// Check whether an earlier request touching any of the keys has not returned
+    fn earlier_overlapping(&self, seq: u64, keys: &[KeyRange]) -> bool {
+        self.in_flight
+            .range(..seq)
+            .flat_map(|(_, earlier)| earlier.iter())
+            .any(|earlier| keys.iter().any(|key| key.is_conflicted(earlier)))
+    }
+}
+
+/// Orders the requests of a connection by the sequence numbers given by the client.
+///
+/// The requests sent on one connection may be handled concurrently and proposed in a
+/// different order, e.g. by the stream multiplexing, the batching of proposals or the
+/// parallel apply of the commands. A numbered request waits until every request of
+/// its connection with a smaller number has arrived, then until the ones among them
+/// touching the same keys have returned. So it is proposed after them, and since it
+/// conflicts with them it is also applied after them. A request waiting too long for
+/// a missing one stops waiting, and the missing one is rejected if it arrives later,
+/// so the requests never take effect out of order.
+#[derive(Debug)]
+pub(crate) struct RequestSequencer {
+    /// The maximum time a request waits for the earlier ones to arrive, 0 means the
+    /// ordering is disabled
+    max_wait: Duration,
+    /// The order of the connections, identified by their remote addresses
+    connections: Arc<Mutex<HashMap<SocketAddr, ConnectionOrder>>>,
+}
+
+impl RequestSequencer {
+    /// New `RequestSequencer`
+    pub(crate) fn new(max_wait: Duration) -> Self {
+        Self {
+            max_wait,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Get the sequence number of a request, `None` if it is not numbered or the
+    /// ordering is disabled
+    fn seq(&self, metadata: &MetadataMap) -> Result<Option<u64>, tonic::Status> {
+        if self.max_wait.is_zero() {
+            return Ok(None);
+        }
+        let Some(value) = metadata.get(REQUEST_SEQ_KEY) else {
+            return Ok(None);
+        };
+        value
+            .to_str()
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|&seq| seq > 0)
+            .map(Some)
+            .ok_or_else(|| tonic::Status::invalid_argument("invalid request sequence number"))
+    }
+
+    /// Wait until a request can be proposed in the order of its connection, `keys`
+    /// are the keys it touches. The returned guard must be held until the request
+    /// returns, `None` if the request is not ordered.
+    pub(crate) async fn order(
+        &self,
+        connection: Option<SocketAddr>,
+        metadata: &MetadataMap,
+        keys: impl FnOnce() -> Vec<KeyRange>,
+    ) -> Result<Option<OrderGuard>, tonic::Status> {
+        let Some(seq) = self.seq(metadata)? else {
+            return Ok(None);
+        };
+        let Some(connection) = connection else {
+            return Ok(None);
+        };
+        let keys = keys();
+        let start = Instant::now();
+        {
+            let mut connections = self.connections.lock();
+            if !connections.contains_key(&connection) {
+                connections.retain(|_, order| {
+                    !order.in_flight.is_empty() || order.last_active.elapsed() < IDLE_CONNECTION_TTL
+                });
+            }
+            let order = connections
+                .entry(connection)
+                .or_insert_with(ConnectionOrder::new);
+            order.last_active = start;
+            if seq < order.next || order.arrived_ahead.contains(&seq) {
+                return Err(tonic::Status::failed_precondition(format!(
+                    "request {seq} of the connection arrived twice or after a later request \
+                     stopped waiting for it"
+                )));
+            }
+            let _prev = order.in_flight.insert(seq, keys.clone());
+            let _new = order.arrived_ahead.insert(seq);
+            order.advance();
+            let _ignore = order.changed.notify(usize::MAX);
+        }
+        let guard = OrderGuard {
+            connections: Arc::clone(&self.connections),
+            connection,
+            seq,
+        };
+
+        // wait for the earlier requests to arrive
+        loop {
+            let remaining = self.max_wait.saturating_sub(start.elapsed());
+            let listener = {
+                let mut connections = self.connections.lock();
+                let Some(order) = connections.get_mut(&connection) else {
+                    return Ok(Some(guard));
+                };
+                if order.next > seq {
+                    break;
+                }
+                if remaining.is_zero() {
+                    warn!(
+                        "request {seq} of {connection} stops waiting for the earlier ones, \
+                         the missing ones will be rejected"
+                    );
+                    order.give_up_to(seq);
+                    let _ignore = order.changed.notify(usize::MAX);
+                    break;
+                }
+                order.changed.listen()
+            };
+            let _elapsed = tokio::time::timeout(remaining, listener).await;
+        }
+
+        // wait for the earlier requests touching the same keys to return
+        loop {
+            let listener = {
+                let connections = self.connections.lock();
+                match connections.get(&connection) {
+                    Some(order) if order.earlier_overlapping(seq, &keys) => order.changed.listen(),
+                    _ => break,
+                }
+            };
+            listener.await;
+        }
+        Ok(Some(guard))
+    }
+}
+
+/// Guard of an ordered request, the later requests touching its keys are released
+/// when it is dropped
+#[derive(Debug)]
+pub(crate) struct OrderGuard {
+    /// The order of the connections
+    connections: Arc<Mutex<HashMap<SocketAddr, ConnectionOrder>>>,
+    /// The connection of the request
+    connection: SocketAddr,
+    /// The sequence number of the request
+    seq: u64,
+}
+
+impl Drop for OrderGuard {
+    fn drop(&mut self) {
+        if let Some(order) = self.connections.lock().get_mut(&self.connection) {
+            let _prev = order.in_flight.remove(&self.seq);
+            order.last_active = Instant::now();
+            let _ignore = order.changed.notify(usize::MAX);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_macros::abort_on_panic;
+
+    use super::*;
+
+    fn numbered(seq: u64) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        let _ignore = metadata.insert(REQUEST_SEQ_KEY, seq.to_string().parse().unwrap());
+        metadata
+    }
+
+    fn key(k: &str) -> impl FnOnce() -> Vec<KeyRange> + '_ {
+        move || vec![KeyRange::new_one_key(k)]
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn later_requests_should_wait_for_earlier_overlapping_ones() {
+        let sequencer = Arc::new(RequestSequencer::new(Duration::from_secs(5)));
+        let conn = Some("127.0.0.1:1234".parse().unwrap());
+
+        // the second request arrives first and waits for the first one
+        let second = tokio::spawn({
+            let sequencer = Arc::clone(&sequencer);
+            async move {
+                sequencer
+                    .order(conn, &numbered(2), key("a"))
+                    .await
+                    .map(|_g| ())
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!second.is_finished());
+        let first = sequencer.order(conn, &numbered(1), key("a")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!second.is_finished(), "the first request has not returned");
+        drop(first);
+        second.await.unwrap().unwrap();
+
+        // a request touching other keys only waits for the arrival
+        let _third = sequencer.order(conn, &numbered(3), key("a")).await.unwrap();
+        let _fourth = sequencer.order(conn, &numbered(4), key("b")).await.unwrap();
+
+        // a duplicated request is rejected, another connection is not ordered with it
+        assert!(sequencer.order(conn, &numbered(4), key("b")).await.is_err());
+        let other = Some("127.0.0.1:5678".parse().unwrap());
+        let _other = sequencer
+            .order(other, &numbered(1), key("a"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn a_missing_request_should_be_given_up_and_rejected_if_it_arrives() {
+        let sequencer = RequestSequencer::new(Duration::from_millis(100));
+        let conn = Some("127.0.0.1:1234".parse().unwrap());
+        let start = Instant::now();
+        let _second = sequencer.order(conn, &numbered(2), key("a")).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(sequencer.order(conn, &numbered(1), key("a")).await.is_err());
+        assert!(sequencer.order(conn, &numbered(3), key("b")).await.is_ok());
+
+        let disabled = RequestSequencer::new(Duration::ZERO);
+        assert!(disabled
+            .order(conn, &numbered(5), key("a"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
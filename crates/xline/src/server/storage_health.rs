@@ -0,0 +1,147 @@
+use std::{
+    ffi::CString,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clippy_utilities::NumericCast;
+use serde::{Deserialize, Serialize};
+use tonic::metadata::{MetadataMap, MetadataValue};
+use xlineapi::{AlarmMember, AlarmType};
+
+/// Metadata key of the storage health of the member as json, carried by the
+/// response of a `Status`
+pub(crate) const STORAGE_HEALTH_KEY: &str = "storage-health-bin";
+
+/// The health of the storage of a member
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StorageHealth {
+    /// The unix time in milliseconds the last write was synced to the disk, `None` if
+    /// no write has been synced since the member starts
+    pub(crate) last_fsync_unix_ms: Option<u64>,
+    /// The number of revisions compacted but not yet removed from the backend
+    pub(crate) compaction_backlog: i64,
+    /// The free space in bytes of the data volume, `None` if the backend is in memory
+    pub(crate) free_disk_space: Option<u64>,
+    /// Whether the backend only serves reads, since the member raises a NOSPACE alarm
+    pub(crate) read_only: bool,
+    /// Whether the backend is degraded
+    pub(crate) degraded: bool,
+    /// The reasons the backend is degraded or read only
+    pub(crate) reasons: Vec<String>,
+}
+
+impl StorageHealth {
+    /// New `StorageHealth` from the states of the backend and the alarms raised by the
+    /// member itself
+    pub(crate) fn new(
+        last_synced_at: Option<SystemTime>,
+        compaction_backlog: i64,
+        free_disk_space: Option<u64>,
+        write_failure: Option<String>,
+        alarms: &[AlarmMember],
+    ) -> Self {
+        let mut health = Self {
+            last_fsync_unix_ms: last_synced_at.and_then(|time| {
+                time.duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|elapsed| u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX))
+            }),
+            compaction_backlog,
+            free_disk_space,
+            ..Self::default()
+        };
+        if let Some(failure) = write_failure {
+            health.degraded = true;
+            health.reasons.push(failure);
+        }
+        for alarm in alarms {
+            match AlarmType::try_from(alarm.alarm) {
+                Ok(AlarmType::Nospace) => {
+                    health.read_only = true;
+                    health.reasons.push(alarm.to_string());
+                }
+                Ok(AlarmType::Corrupt) => {
+                    health.degraded = true;
+                    health.reasons.push(alarm.to_string());
+                }
+                Ok(AlarmType::None) | Err(_) => {}
+            }
+        }
+        health
+    }
+
+    /// Get the storage health carried by the metadata, `None` if it is absent
+    #[cfg(test)]
+    pub(crate) fn from_metadata(metadata: &MetadataMap) -> Option<Self> {
+        let bytes = metadata.get_bin(STORAGE_HEALTH_KEY)?.to_bytes().ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    /// Carry the storage health by the metadata
+    pub(crate) fn insert_into(&self, metadata: &mut MetadataMap) -> Result<(), tonic::Status> {
+        let bytes = serde_json::to_vec(self).map_err(|e| {
+            tonic::Status::internal(format!("failed to serialize the storage health: {e}"))
+        })?;
+        let _prev = metadata.insert_bin(STORAGE_HEALTH_KEY, MetadataValue::from_bytes(&bytes));
+        Ok(())
+    }
+}
+
+/// Get the free space in bytes of the volume of a path, `None` if it can't be read
+#[allow(unsafe_code)] // statvfs is only provided by the libc
+pub(crate) fn free_disk_space(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: the path is a valid c string and the buffer outlives the call
+    let stat = unsafe {
+        let mut stat: nix::libc::statvfs = std::mem::zeroed();
+        (nix::libc::statvfs(path.as_ptr(), &mut stat) == 0).then_some(stat)
+    }?;
+    let available: u64 = stat.f_bavail.numeric_cast();
+    available.checked_mul(stat.f_frsize.numeric_cast())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn degraded_backends_should_be_reported() {
+        let synced_at = UNIX_EPOCH + Duration::from_millis(1234);
+        let healthy = StorageHealth::new(Some(synced_at), 3, Some(4096), None, &[]);
+        assert_eq!(healthy.last_fsync_unix_ms, Some(1234));
+        assert_eq!(healthy.compaction_backlog, 3);
+        assert_eq!(healthy.free_disk_space, Some(4096));
+        assert!(!healthy.degraded && !healthy.read_only && healthy.reasons.is_empty());
+
+        let alarm = |alarm: AlarmType| AlarmMember {
+            member_id: 1,
+            alarm: alarm.into(),
+        };
+        let failed = StorageHealth::new(
+            None,
+            0,
+            None,
+            Some("Failed to flush ops".to_owned()),
+            &[alarm(AlarmType::Nospace)],
+        );
+        assert!(failed.degraded && failed.read_only);
+        assert_eq!(failed.reasons.len(), 2);
+        let corrupted = StorageHealth::new(None, 0, None, None, &[alarm(AlarmType::Corrupt)]);
+        assert!(corrupted.degraded && !corrupted.read_only);
+
+        let mut metadata = MetadataMap::new();
+        assert_eq!(StorageHealth::from_metadata(&metadata), None);
+        corrupted.insert_into(&mut metadata).unwrap();
+        assert_eq!(StorageHealth::from_metadata(&metadata), Some(corrupted));
+    }
+
+    #[test]
+    fn free_disk_space_should_be_read_from_the_volume() {
+        assert!(free_disk_space(&std::env::temp_dir()).is_some());
+        assert!(free_disk_space(Path::new("/nonexistent/xline")).is_none());
+    }
+}
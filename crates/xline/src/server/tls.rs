@@ -0,0 +1,162 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Result};
+use futures::{future, Stream, StreamExt};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncWrite},
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::{ring, CryptoProvider},
+        server::WebPkiClientVerifier,
+        ProtocolVersion, RootCertStore, ServerConfig, SupportedCipherSuite, ALL_VERSIONS,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+use tracing::debug;
+use utils::config::{TlsConfig, TlsVersion};
+
+/// The maximum time a tls handshake of an incoming connection may take
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The maximum number of concurrent tls handshakes of a listener
+const MAX_CONCURRENT_HANDSHAKES: usize = 128;
+
+/// Get the IANA name of a cipher suite
+fn suite_name(suite: SupportedCipherSuite) -> String {
+    format!("{:?}", suite.suite())
+}
+
+/// Select the cipher suites by their names, empty names select all the supported
+/// ones. Only strong AEAD suites are supported, so a weak suite is rejected as an
+/// unknown one.
+fn select_cipher_suites(names: &[String]) -> Result<Vec<SupportedCipherSuite>> {
+    let supported = ring::default_provider().cipher_suites;
+    if names.is_empty() {
+        return Ok(supported);
+    }
+    names
+        .iter()
+        .map(|name| {
+            supported
+                .iter()
+                .copied()
+                .find(|&suite| suite_name(suite).eq_ignore_ascii_case(name))
+                .ok_or_else(|| {
+                    let supported_names: Vec<_> =
+                        supported.iter().copied().map(suite_name).collect();
+                    anyhow!(
+                        "unknown or weak cipher suite {name}, the supported ones are {}",
+                        supported_names.join(", ")
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Build the tls config of the client and peer listeners whose handshakes are
+/// restricted by the minimum tls version and the cipher suites, it fails if the
+/// restrictions leave no usable handshake
+pub(crate) async fn restricted_config(tls_config: &TlsConfig) -> Result<Arc<ServerConfig>> {
+    let (Some(cert_path), Some(key_path)) = (
+        tls_config.peer_cert_path().as_ref(),
+        tls_config.peer_key_path().as_ref(),
+    ) else {
+        return Err(anyhow!(
+            "min_version and cipher_suites require peer_cert_path and peer_key_path"
+        ));
+    };
+    let provider = Arc::new(CryptoProvider {
+        cipher_suites: select_cipher_suites(tls_config.cipher_suites())?,
+        ..ring::default_provider()
+    });
+    let min_version = *tls_config.min_version();
+    let versions: Vec<_> = ALL_VERSIONS
+        .iter()
+        .copied()
+        .filter(|version| {
+            min_version < TlsVersion::Tls13 || version.version == ProtocolVersion::TLSv1_3
+        })
+        .collect();
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(&versions)
+        .map_err(|e| anyhow!("no cipher suite is usable by the tls versions, {e}"))?;
+
+    let certs = rustls_pemfile::certs(&mut fs::read(cert_path).await?.as_slice())
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut fs::read(key_path).await?.as_slice())?
+        .ok_or_else(|| anyhow!("no private key is found in {}", key_path.display()))?;
+    let builder = match tls_config.peer_ca_cert_path().as_ref() {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in rustls_pemfile::certs(&mut fs::read(ca_path).await?.as_slice()) {
+                roots.add(cert?)?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(certs, key)?;
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// Terminate the tls of the incoming connections by the acceptor, the connections
+/// failing the handshake are dropped without affecting the others
+pub(crate) fn tls_incoming<I, IO, IE>(
+    incoming: I,
+    acceptor: TlsAcceptor,
+) -> impl Stream<Item = Result<TlsStream<IO>, IE>>
+where
+    I: Stream<Item = Result<IO, IE>>,
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    incoming
+        .map(move |conn| {
+            let acceptor = acceptor.clone();
+            async move {
+                let io = match conn {
+                    Ok(io) => io,
+                    Err(e) => return Some(Err(e)),
+                };
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(io)).await {
+                    Ok(Ok(stream)) => Some(Ok(stream)),
+                    Ok(Err(e)) => {
+                        debug!("tls handshake failed: {e}");
+                        None
+                    }
+                    Err(_elapsed) => {
+                        debug!("tls handshake timed out");
+                        None
+                    }
+                }
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_HANDSHAKES)
+        .filter_map(future::ready)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unknown_or_weak_cipher_suites_should_be_rejected() {
+        assert!(!select_cipher_suites(&[]).unwrap().is_empty());
+        let suites = select_cipher_suites(&["tls13_aes_256_gcm_sha384".to_owned()]).unwrap();
+        assert_eq!(suites.len(), 1);
+        assert_eq!(suite_name(suites[0]), "TLS13_AES_256_GCM_SHA384");
+        for weak in [
+            "TLS_RSA_WITH_AES_128_CBC_SHA",
+            "TLS_RSA_WITH_RC4_128_MD5",
+            "foo",
+        ] {
+            let err = select_cipher_suites(&[weak.to_owned()]).unwrap_err();
+            assert!(err.to_string().contains("unknown or weak"), "{weak}");
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::future::Future;
+
+use clippy_utilities::{NumericCast, OverflowArithmetic};
+use dashmap::DashMap;
+
+use crate::rpc::{KeyValue, PutRequest, Request, RequestOp, Response, TxnRequest, TxnResponse};
+
+/// The request counters of a user
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RequestCounters {
+    /// The number of the requests
+    pub(crate) requests: u64,
+    /// The bytes of the keys and values returned to the requests
+    pub(crate) bytes_read: u64,
+    /// The bytes of the keys and values put by the requests
+    pub(crate) bytes_written: u64,
+    /// The number of the failed requests
+    pub(crate) errors: u64,
+}
+
+/// The request statistics of the users of the kv requests served by this node.
+///
+/// The statistics are node-local: they are kept in memory, so they are lost when the
+/// node restarts, and they are not replicated, so every node only counts the requests
+/// it has served. A client spreading its requests over the nodes needs the statistics
+/// of all of them. The requests without an authenticated user, including all requests
+/// if the auth is disabled, are counted under the empty user name.
+#[derive(Debug, Default)]
+pub(crate) struct UserStats {
+    /// The counters of the users
+    counters: DashMap<String, RequestCounters>,
+}
+
+impl UserStats {
+    /// Handle a request of the user and count it, `usage` gives the bytes read and the
+    /// bytes written by a successful request from its response
+    pub(crate) async fn count<Res, F>(
+        &self,
+        user: Option<String>,
+        handled: F,
+        usage: impl FnOnce(&Res) -> (u64, u64),
+    ) -> Result<tonic::Response<Res>, tonic::Status>
+    where
+        F: Future<Output = Result<tonic::Response<Res>, tonic::Status>>,
+    {
+        let result = handled.await;
+        let (bytes_read, bytes_written) = result
+            .as_ref()
+            .map_or((0, 0), |response| usage(response.get_ref()));
+        let mut counters = self.counters.entry(user.unwrap_or_default()).or_default();
+        counters.requests = counters.requests.overflow_add(1);
+        counters.bytes_read = counters.bytes_read.overflow_add(bytes_read);
+        counters.bytes_written = counters.bytes_written.overflow_add(bytes_written);
+        if result.is_err() {
+            counters.errors = counters.errors.overflow_add(1);
+        }
+        result
+    }
+
+    /// Get the counters of at most `limit` users from `start_user` in ascending order
+    /// of user name, and the user to start the next page from if there are more
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn page(
+        &self,
+        start_user: &str,
+        limit: usize,
+    ) -> (Vec<(String, RequestCounters)>, Option<String>) {
+        let mut users: Vec<_> = self
+            .counters
+            .iter()
+            .filter(|entry| entry.key().as_str() >= start_user)
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect();
+        users.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        let next_user = users.get(limit).map(|(user, _)| user.clone());
+        users.truncate(limit);
+        (users, next_user)
+    }
+
+    /// Reset the counters of a user, and return them so that none is lost between a
+    /// read and the reset
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn reset(&self, user: &str) -> Option<RequestCounters> {
+        self.counters.remove(user).map(|(_, counters)| counters)
+    }
+
+    /// Reset the counters of all users, and return the number of the reset users
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn reset_all(&self) -> usize {
+        let len = self.counters.len();
+        self.counters.clear();
+        len
+    }
+}
+
+/// The bytes of the keys and values of the kvs
+pub(crate) fn kvs_size<'a>(kvs: impl IntoIterator<Item = &'a KeyValue>) -> u64 {
+    kvs.into_iter()
+        .map(|kv| kv.key.len().overflow_add(kv.value.len()))
+        .fold(0_u64, |size, kv_size| {
+            size.overflow_add(kv_size.numeric_cast())
+        })
+}
+
+/// The bytes of the key and value of a put
+pub(crate) fn put_size(req: &PutRequest) -> u64 {
+    req.key.len().overflow_add(req.value.len()).numeric_cast()
+}
+
+/// The bytes put by the branches of a txn, which are known before the txn is handled
+/// and resolved by its response
+#[derive(Debug, Default)]
+pub(crate) struct TxnWrites {
+    /// The bytes put by the success branch and its nested txns
+    success: (u64, Vec<TxnWrites>),
+    /// The bytes put by the failure branch and its nested txns
+    failure: (u64, Vec<TxnWrites>),
+}
+
+impl TxnWrites {
+    /// New `TxnWrites` of a txn
+    pub(crate) fn new(txn: &TxnRequest) -> Self {
+        Self {
+            success: Self::branch(&txn.success),
+            failure: Self::branch(&txn.failure),
+        }
+    }
+
+    /// The bytes put by the ops of a branch and its nested txns
+    fn branch(ops: &[RequestOp]) -> (u64, Vec<TxnWrites>) {
+        let mut size = 0_u64;
+        let mut nested = Vec::new();
+        for request in ops.iter().filter_map(|op| op.request.as_ref()) {
+            match *request {
+                Request::RequestPut(ref put) => size = size.overflow_add(put_size(put)),
+                Request::RequestTxn(ref txn) => nested.push(Self::new(txn)),
+                Request::RequestRange(_) | Request::RequestDeleteRange(_) => {}
+            }
+        }
+        (size, nested)
+    }
+
+    /// The bytes read and written by the txn of the response
+    pub(crate) fn usage(&self, res: &TxnResponse) -> (u64, u64) {
+        let &(written, ref nested) = if res.succeeded {
+            &self.success
+        } else {
+            &self.failure
+        };
+        let (mut read, mut written) = (0_u64, written);
+        let mut nested = nested.iter();
+        for response in res.responses.iter().filter_map(|op| op.response.as_ref()) {
+            match *response {
+                Response::ResponseRange(ref range) => {
+                    read = read.overflow_add(kvs_size(&range.kvs));
+                }
+                Response::ResponsePut(ref put) => {
+                    read = read.overflow_add(kvs_size(&put.prev_kv));
+                }
+                Response::ResponseDeleteRange(ref delete) => {
+                    read = read.overflow_add(kvs_size(&delete.prev_kvs));
+                }
+                Response::ResponseTxn(ref txn) => {
+                    if let Some(writes) = nested.next() {
+                        let (nested_read, nested_written) = writes.usage(txn);
+                        read = read.overflow_add(nested_read);
+                        written = written.overflow_add(nested_written);
+                    }
+                }
+            }
+        }
+        (read, written)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::{PutResponse, RangeRequest, RangeResponse, ResponseOp};
+
+    fn put(key: &str, value: &str) -> RequestOp {
+        RequestOp {
+            request: Some(Request::RequestPut(PutRequest {
+                key: key.into(),
+                value: value.into(),
+                ..Default::default()
+            })),
+        }
+    }
+
+    #[test]
+    fn stats_should_be_paged_and_reset_by_user() {
+        let stats = UserStats::default();
+        for (user, requests) in [("b", 2), ("", 1), ("c", 3), ("a", 4)] {
+            let _prev = stats.counters.insert(
+                user.to_owned(),
+                RequestCounters {
+                    requests,
+                    ..Default::default()
+                },
+            );
+        }
+        let users = |page: &[(String, RequestCounters)]| -> Vec<_> {
+            page.iter()
+                .map(|(user, counters)| (user.clone(), counters.requests))
+                .collect()
+        };
+        let (first, next) = stats.page("", 3);
+        assert_eq!(
+            users(&first),
+            [(String::new(), 1), ("a".to_owned(), 4), ("b".to_owned(), 2)]
+        );
+        assert_eq!(next.as_deref(), Some("c"));
+        let (second, next) = stats.page("c", 3);
+        assert_eq!(users(&second), [("c".to_owned(), 3)]);
+        assert_eq!(next, None);
+
+        assert_eq!(stats.reset("a").map(|counters| counters.requests), Some(4));
+        assert_eq!(stats.reset("a"), None);
+        assert_eq!(stats.reset_all(), 3);
+        assert!(stats.page("", 3).0.is_empty());
+    }
+
+    #[test]
+    fn txn_usage_should_follow_the_taken_branches() {
+        let txn = TxnRequest {
+            compare: vec![],
+            success: vec![
+                put("a", "bb"),
+                RequestOp {
+                    request: Some(Request::RequestTxn(TxnRequest {
+                        compare: vec![],
+                        success: vec![put("c", "dddd")],
+                        failure: vec![
+                            put("e", "ffffffff"),
+                            RequestOp {
+                                request: Some(Request::RequestRange(RangeRequest {
+                                    key: b"e".to_vec(),
+                                    ..Default::default()
+                                })),
+                            },
+                        ],
+                    })),
+                },
+            ],
+            failure: vec![put("g", "hhhhhhhhhhhhhhhh")],
+        };
+        let writes = TxnWrites::new(&txn);
+        let res = TxnResponse {
+            succeeded: true,
+            responses: vec![
+                ResponseOp {
+                    response: Some(Response::ResponsePut(PutResponse::default())),
+                },
+                ResponseOp {
+                    response: Some(Response::ResponseTxn(TxnResponse {
+                        succeeded: false,
+                        responses: vec![
+                            ResponseOp {
+                                response: Some(Response::ResponsePut(PutResponse::default())),
+                            },
+                            ResponseOp {
+                                response: Some(Response::ResponseRange(RangeResponse {
+                                    kvs: vec![KeyValue {
+                                        key: b"e".to_vec(),
+                                        value: b"ffffffff".to_vec(),
+                                        ..Default::default()
+                                    }],
+                                    ..Default::default()
+                                })),
+                            },
+                        ],
+                        ..Default::default()
+                    })),
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(writes.usage(&res), (9, 3 + 9));
+        let failed = TxnResponse {
+            succeeded: false,
+            ..Default::default()
+        };
+        assert_eq!(writes.usage(&failed), (0, 17));
+    }
+}
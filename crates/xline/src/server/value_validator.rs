@@ -0,0 +1,216 @@
+use std::{fmt::Debug, sync::Arc};
+
+use tracing::debug;
+use xlineapi::{request_validation::ValidationError, server_op::ServerOp};
+
+use crate::rpc::{PutRequest, Request, TxnRequest};
+
+/// A validator of the values written under a key prefix, registered by
+/// `XlineServer::with_value_validator` before the server starts.
+///
+/// The validators run in the validation of the puts, before they are proposed, and a
+/// put whose value is rejected fails with `ValidationError::NonConformingValue`. A
+/// put may be validated by any node serving it, so a validator must be registered on
+/// every node and be deterministic: its decision must only depend on the key and the
+/// value, never on the time, the node or the state of the keyspace. A put with
+/// `ignore_value` keeps the existing value, so it is never validated. The values
+/// written by a server op are only known when it is applied, so a server op writing
+/// values under a validated prefix fails with `ValidationError::UnvalidatedServerOp`.
+pub trait ValueValidator: Send + Sync + Debug {
+    /// Validate the value of a put to a key under the prefix of the validator
+    ///
+    /// # Errors
+    ///
+    /// Return the reason if the value doesn't conform
+    fn validate(&self, key: &[u8], value: &[u8]) -> Result<(), String>;
+}
+
+/// The value validators by key prefixes
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ValueValidators {
+    /// The prefixes and their validators in the order they are registered
+    validators: Vec<(Vec<u8>, Arc<dyn ValueValidator>)>,
+}
+
+impl ValueValidators {
+    /// Register a validator of the values under `prefix`
+    pub(crate) fn register(&mut self, prefix: Vec<u8>, validator: Arc<dyn ValueValidator>) {
+        self.validators.push((prefix, validator));
+    }
+
+    /// Validate the value of a put by every validator of a prefix of its key
+    pub(crate) fn check_put(&self, put: &PutRequest) -> Result<(), ValidationError> {
+        if put.ignore_value {
+            return Ok(());
+        }
+        for &(ref prefix, ref validator) in &self.validators {
+            if !put.key.starts_with(prefix) {
+                continue;
+            }
+            if let Err(reason) = validator.validate(&put.key, &put.value) {
+                debug!(
+                    "value validator of prefix {} rejects the put: {reason}",
+                    String::from_utf8_lossy(prefix)
+                );
+                return Err(ValidationError::NonConformingValue);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether a key is under the prefix of a validator
+    fn validates_key(&self, key: &[u8]) -> bool {
+        self.validators
+            .iter()
+            .any(|&(ref prefix, _)| key.starts_with(prefix))
+    }
+
+    /// Check whether a key under a prefix may be under the prefix of a validator
+    fn validates_prefix(&self, prefix: &[u8]) -> bool {
+        self.validators.iter().any(|&(ref validated, _)| {
+            prefix.starts_with(validated) || validated.starts_with(prefix)
+        })
+    }
+
+    /// Reject a server op which writes values under a validated prefix. The footprint
+    /// of the op has no values, so it's checked by the keys the op writes.
+    fn check_server_op(&self, op: &ServerOp) -> Result<(), ValidationError> {
+        let writes_validated = match *op {
+            ServerOp::Increment(ref inc) => self.validates_key(&inc.key),
+            ServerOp::Append(ref app) => self.validates_key(&app.key),
+            ServerOp::Swap(ref swap) => {
+                self.validates_key(&swap.first) || self.validates_key(&swap.second)
+            }
+            ServerOp::MovePrefix(ref mv) => self.validates_prefix(&mv.destination),
+            // the imported leases are attached to the existing keys
+            ServerOp::ReserveRevisions(_)
+            | ServerOp::GrantLeases(_)
+            | ServerOp::ImportLeases(_)
+            | ServerOp::ConditionalDelete(_)
+            | ServerOp::MemberTags(_)
+            | ServerOp::RehashPassword(_) => false,
+        };
+        if writes_validated {
+            return Err(ValidationError::UnvalidatedServerOp);
+        }
+        Ok(())
+    }
+
+    /// Validate the values of all the puts of a txn, including the nested ones
+    pub(crate) fn check_txn(&self, txn: &TxnRequest) -> Result<(), ValidationError> {
+        if self.validators.is_empty() {
+            return Ok(());
+        }
+        // a txn whose op can't be decoded is rejected when it is executed
+        if let Ok(Some(op)) = ServerOp::from_txn(txn) {
+            return self.check_server_op(&op);
+        }
+        for request in txn
+            .success
+            .iter()
+            .chain(txn.failure.iter())
+            .filter_map(|op| op.request.as_ref())
+        {
+            match *request {
+                Request::RequestPut(ref put) => self.check_put(put)?,
+                Request::RequestTxn(ref txn) => self.check_txn(txn)?,
+                Request::RequestRange(_) | Request::RequestDeleteRange(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use xlineapi::server_op::{AppendOp, MovePrefixOp, SwapOp};
+
+    use super::*;
+    use crate::rpc::RequestOp;
+
+    #[derive(Debug)]
+    struct MaxLen(usize);
+
+    impl ValueValidator for MaxLen {
+        fn validate(&self, _key: &[u8], value: &[u8]) -> Result<(), String> {
+            if value.len() > self.0 {
+                return Err(format!("value is longer than {}", self.0));
+            }
+            Ok(())
+        }
+    }
+
+    fn put(key: &str, value: &str) -> PutRequest {
+        PutRequest {
+            key: key.into(),
+            value: value.into(),
+            ..PutRequest::default()
+        }
+    }
+
+    #[test]
+    fn values_should_be_checked_by_the_validators_of_their_prefixes() {
+        let mut validators = ValueValidators::default();
+        validators.register(b"short/".to_vec(), Arc::new(MaxLen(3)));
+        validators.register(b"short/shorter/".to_vec(), Arc::new(MaxLen(1)));
+
+        assert!(validators.check_put(&put("short/a", "abc")).is_ok());
+        assert!(validators.check_put(&put("other", "abcd")).is_ok());
+        assert!(validators.check_put(&put("short/shorter/a", "a")).is_ok());
+        for rejected in [put("short/a", "abcd"), put("short/shorter/a", "ab")] {
+            assert_eq!(
+                validators.check_put(&rejected),
+                Err(ValidationError::NonConformingValue)
+            );
+        }
+        let ignore_value = PutRequest {
+            ignore_value: true,
+            ..put("short/a", "")
+        };
+        assert!(validators.check_put(&ignore_value).is_ok());
+
+        let nested = TxnRequest {
+            compare: vec![],
+            success: vec![],
+            failure: vec![RequestOp {
+                request: Some(Request::RequestTxn(TxnRequest {
+                    compare: vec![],
+                    success: vec![RequestOp {
+                        request: Some(Request::RequestPut(put("short/a", "abcd"))),
+                    }],
+                    failure: vec![],
+                })),
+            }],
+        };
+        assert_eq!(
+            validators.check_txn(&nested),
+            Err(ValidationError::NonConformingValue)
+        );
+    }
+
+    #[test]
+    fn server_ops_should_not_write_the_values_under_validated_prefixes() {
+        let mut validators = ValueValidators::default();
+        validators.register(b"short/".to_vec(), Arc::new(MaxLen(3)));
+        let append = |key: &str| ServerOp::Append(AppendOp::new(key.into(), b"a".to_vec(), 0));
+        let swap =
+            |first: &str| ServerOp::Swap(SwapOp::new(first.into(), b"other".to_vec(), false, true));
+        let move_to = |destination: &str| {
+            ServerOp::MovePrefix(MovePrefixOp::new(b"from/".to_vec(), destination.into()))
+        };
+        for op in [
+            append("short/a"),
+            swap("short/a"),
+            move_to("short/a/"),
+            move_to("sh"),
+        ] {
+            assert_eq!(
+                validators.check_txn(&TxnRequest::from(op)),
+                Err(ValidationError::UnvalidatedServerOp)
+            );
+        }
+        for op in [append("other/a"), swap("other/a"), move_to("to/")] {
+            assert!(validators.check_txn(&TxnRequest::from(op)).is_ok());
+        }
+    }
+}
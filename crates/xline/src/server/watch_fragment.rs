@@ -0,0 +1,214 @@
+use prost::Message;
+use utils::config::OversizedWatchEvent;
+use xlineapi::{WATCH_FRAGMENT_MARKER, WATCH_TRUNCATED_MARKER};
+
+use crate::rpc::{Event, WatchResponse};
+
+/// The max length of the field tag of an event in a watch response
+const EVENT_TAG_LEN: usize = 2;
+
+/// Splits the watch responses exceeding the max send message size, which would
+/// otherwise break the watch stream.
+///
+/// The events of a response are spread over several responses within the limit. An
+/// event which alone exceeds the limit is delivered in the configured way, and its
+/// previous kv is delivered without the value:
+///
+/// - `Fragment` splits the value across consecutive responses, every one but the last
+///   is marked by `WATCH_FRAGMENT_MARKER` and the client concatenates the values
+/// - `Truncate` delivers the event without the value in a response marked by
+///   `WATCH_TRUNCATED_MARKER`, the client fetches the value by a range at the
+///   revision of the event
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ResponseSplitter {
+    /// The max encoded length of a response
+    limit: usize,
+    /// How an event exceeding the limit is delivered
+    oversized_event: OversizedWatchEvent,
+}
+
+impl Default for ResponseSplitter {
+    fn default() -> Self {
+        Self::new(usize::MAX, OversizedWatchEvent::default())
+    }
+}
+
+impl ResponseSplitter {
+    /// New `ResponseSplitter`
+    pub(crate) fn new(limit: usize, oversized_event: OversizedWatchEvent) -> Self {
+        Self {
+            limit,
+            oversized_event,
+        }
+    }
+
+    /// Split a response into the responses within the limit
+    pub(crate) fn split(&self, mut response: WatchResponse) -> Vec<WatchResponse> {
+        if response.encoded_len() <= self.limit {
+            return vec![response];
+        }
+        let events = std::mem::take(&mut response.events);
+        let base_len = response.encoded_len();
+        let mut responses = Vec::new();
+        let mut batch = Vec::new();
+        let mut batch_len = base_len;
+        for event in events {
+            let len = event_len(&event);
+            if !batch.is_empty() && batch_len.saturating_add(len) > self.limit {
+                responses.push(WatchResponse {
+                    events: std::mem::take(&mut batch),
+                    ..response.clone()
+                });
+                batch_len = base_len;
+            }
+            if base_len.saturating_add(len) > self.limit {
+                responses.extend(self.split_event(&response, event));
+                continue;
+            }
+            batch_len = batch_len.saturating_add(len);
+            batch.push(event);
+        }
+        if !batch.is_empty() {
+            responses.push(WatchResponse {
+                events: batch,
+                ..response
+            });
+        }
+        responses
+    }
+
+    /// Deliver an event which alone exceeds the limit
+    fn split_event(&self, base: &WatchResponse, mut event: Event) -> Vec<WatchResponse> {
+        if let Some(ref mut prev_kv) = event.prev_kv {
+            prev_kv.value.clear();
+        }
+        let value = event
+            .kv
+            .as_mut()
+            .map(|kv| std::mem::take(&mut kv.value))
+            .unwrap_or_default();
+        let single = |event: Event, marker: &str| WatchResponse {
+            events: vec![event],
+            cancel_reason: marker.to_owned(),
+            ..base.clone()
+        };
+        match self.oversized_event {
+            OversizedWatchEvent::Truncate => vec![single(event, WATCH_TRUNCATED_MARKER)],
+            OversizedWatchEvent::Fragment => {
+                // the lengths of the value and the event grow by at most the length of
+                // their length delimiters
+                let overhead = single(event.clone(), WATCH_FRAGMENT_MARKER)
+                    .encoded_len()
+                    .saturating_add(prost::length_delimiter_len(self.limit).saturating_mul(2));
+                let chunk_size = self.limit.saturating_sub(overhead).max(1);
+                let mut responses: Vec<_> = value
+                    .chunks(chunk_size)
+                    .map(|chunk| {
+                        let mut fragment = event.clone();
+                        if let Some(ref mut kv) = fragment.kv {
+                            kv.value = chunk.to_vec();
+                        }
+                        single(fragment, WATCH_FRAGMENT_MARKER)
+                    })
+                    .collect();
+                match responses.last_mut() {
+                    Some(last) => last.cancel_reason.clear(),
+                    None => responses.push(single(event, "")),
+                }
+                responses
+            }
+            _ => unreachable!("xline only supports two oversized watch event modes"),
+        }
+    }
+}
+
+/// Get the encoded length of an event in a watch response
+fn event_len(event: &Event) -> usize {
+    let len = event.encoded_len();
+    EVENT_TAG_LEN
+        .saturating_add(prost::length_delimiter_len(len))
+        .saturating_add(len)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::{KeyValue, ResponseHeader};
+
+    fn event(key: &str, value: Vec<u8>) -> Event {
+        Event {
+            kv: Some(KeyValue {
+                key: key.into(),
+                value,
+                mod_revision: 2,
+                ..KeyValue::default()
+            }),
+            prev_kv: Some(KeyValue {
+                key: key.into(),
+                value: vec![b'p'; 16],
+                ..KeyValue::default()
+            }),
+            ..Event::default()
+        }
+    }
+
+    fn response(events: Vec<Event>) -> WatchResponse {
+        WatchResponse {
+            header: Some(ResponseHeader {
+                revision: 2,
+                ..ResponseHeader::default()
+            }),
+            watch_id: 1,
+            events,
+            ..WatchResponse::default()
+        }
+    }
+
+    #[test]
+    fn events_should_be_spread_within_the_limit() {
+        let splitter = ResponseSplitter::new(256, OversizedWatchEvent::Fragment);
+        let events: Vec<_> = (0..8).map(|i| event(&i.to_string(), vec![0; 64])).collect();
+        let responses = splitter.split(response(events.clone()));
+        assert!(responses.len() > 1);
+        assert!(responses.iter().all(|r| r.encoded_len() <= 256));
+        assert!(responses.iter().all(|r| r.cancel_reason.is_empty()));
+        let delivered: Vec<_> = responses.into_iter().flat_map(|r| r.events).collect();
+        assert_eq!(delivered, events);
+    }
+
+    #[test]
+    fn oversized_event_should_be_fragmented() {
+        let splitter = ResponseSplitter::new(256, OversizedWatchEvent::Fragment);
+        let value: Vec<u8> = (0..=u8::MAX).cycle().take(1024).collect();
+        let mut responses = splitter
+            .split(response(vec![event("a", value.clone())]))
+            .into_iter();
+        let mut merged = responses.next().unwrap();
+        assert!(merged.is_fragment());
+        for next in responses {
+            assert!(next.encoded_len() <= 256);
+            assert!(merged.is_fragment());
+            merged.merge_fragment(next);
+        }
+        assert!(!merged.is_fragment());
+        let kv = merged.events[0].kv.as_ref().unwrap();
+        assert_eq!(kv.value, value);
+        assert!(merged.events[0].prev_kv.as_ref().unwrap().value.is_empty());
+    }
+
+    #[test]
+    fn oversized_event_should_be_truncated() {
+        let splitter = ResponseSplitter::new(256, OversizedWatchEvent::Truncate);
+        let responses = splitter.split(response(vec![
+            event("a", vec![0; 8]),
+            event("b", vec![0; 1024]),
+        ]));
+        assert_eq!(responses.len(), 2);
+        assert!(!responses[0].is_truncated());
+        assert!(responses[1].is_truncated());
+        let kv = responses[1].events[0].kv.as_ref().unwrap();
+        assert_eq!(kv.key, b"b");
+        assert_eq!(kv.mod_revision, 2);
+        assert!(kv.value.is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use tonic::metadata::MetadataMap;
+
+use crate::{rpc::Event, storage::kvwatcher::WatchId};
+
+/// Metadata key of the projections applied to the values delivered by the watches of
+/// a stream
+pub(crate) const WATCH_PROJECTION_KEY: &str = "watch-projection";
+
+/// The maximum depth of a JSON field path
+const MAX_JSON_PATH_DEPTH: usize = 8;
+
+/// The maximum number of watches of a stream with a projection
+const MAX_WATCH_PROJECTIONS: usize = 64;
+
+/// A bounded transformation applied to the values of the events delivered by a watch,
+/// it is specified as:
+///
+/// - `prefix:<n>` keeps the first `n` bytes of a value
+/// - `json:<path>` keeps the JSON encoding of the field selected by a dot separated
+///   path of at most `MAX_JSON_PATH_DEPTH` names, a value which is not a JSON object
+///   or doesn't have the field is delivered as empty
+///
+/// The projections of a stream are selected by its `watch-projection` metadata, a
+/// comma separated list of `<watch_id>=<projection>`. A projection applies only to the
+/// watch created with its watch id, so a watch with an auto-generated id or without a
+/// projection listed delivers the full values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum WatchProjection {
+    /// Keep a prefix of the value
+    Prefix(usize),
+    /// Keep a field of a JSON value
+    JsonField(Vec<String>),
+}
+
+impl WatchProjection {
+    /// Get the projections of the watches by watch id from the metadata of a watch
+    /// stream
+    pub(crate) fn from_metadata(
+        metadata: &MetadataMap,
+    ) -> Result<HashMap<WatchId, Self>, tonic::Status> {
+        let Some(value) = metadata.get(WATCH_PROJECTION_KEY) else {
+            return Ok(HashMap::new());
+        };
+        let invalid =
+            |e: String| tonic::Status::invalid_argument(format!("invalid watch projection: {e}"));
+        let specs = value.to_str().map_err(|e| invalid(e.to_string()))?;
+        Self::parse_all(specs).map_err(invalid)
+    }
+
+    /// Parse the projections of the watches from a list of `<watch_id>=<projection>`
+    fn parse_all(specs: &str) -> Result<HashMap<WatchId, Self>, String> {
+        let mut projections = HashMap::new();
+        for entry in specs.split(',') {
+            let Some((watch_id, spec)) = entry.split_once('=') else {
+                return Err(format!("{entry} is not <watch_id>=<projection>"));
+            };
+            let watch_id: WatchId = watch_id
+                .parse()
+                .ok()
+                .filter(|id| *id > 0)
+                .ok_or_else(|| format!("invalid watch id {watch_id}"))?;
+            if projections.insert(watch_id, Self::parse(spec)?).is_some() {
+                return Err(format!("watch {watch_id} has more than one projection"));
+            }
+        }
+        if projections.len() > MAX_WATCH_PROJECTIONS {
+            return Err(format!(
+                "more than {MAX_WATCH_PROJECTIONS} watches have a projection"
+            ));
+        }
+        Ok(projections)
+    }
+
+    /// Parse a projection from its specification
+    fn parse(spec: &str) -> Result<Self, String> {
+        match spec.split_once(':') {
+            Some(("prefix", len)) => len
+                .parse()
+                .map(Self::Prefix)
+                .map_err(|e| format!("invalid prefix length {len}: {e}")),
+            Some(("json", path)) => {
+                let names: Vec<String> = path.split('.').map(str::to_owned).collect();
+                if names.iter().any(String::is_empty) {
+                    return Err(format!("invalid json path {path}"));
+                }
+                if names.len() > MAX_JSON_PATH_DEPTH {
+                    return Err(format!(
+                        "json path {path} is deeper than {MAX_JSON_PATH_DEPTH}"
+                    ));
+                }
+                Ok(Self::JsonField(names))
+            }
+            _ => Err(format!("unknown projection {spec}")),
+        }
+    }
+
+    /// Apply the projection to the values of an event
+    pub(crate) fn apply(&self, event: &mut Event) {
+        for kv in event.kv.iter_mut().chain(event.prev_kv.iter_mut()) {
+            kv.value = self.project(&kv.value);
+        }
+    }
+
+    /// Project a value
+    fn project(&self, value: &[u8]) -> Vec<u8> {
+        match *self {
+            Self::Prefix(len) => value.get(..len).unwrap_or(value).to_vec(),
+            Self::JsonField(ref names) => {
+                let Ok(mut field) = serde_json::from_slice::<serde_json::Value>(value) else {
+                    return Vec::new();
+                };
+                for name in names {
+                    match field.get_mut(name.as_str()) {
+                        Some(child) => field = child.take(),
+                        None => return Vec::new(),
+                    }
+                }
+                serde_json::to_vec(&field).unwrap_or_default()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn projection_should_be_parsed() {
+        assert_eq!(
+            WatchProjection::parse("prefix:4"),
+            Ok(WatchProjection::Prefix(4))
+        );
+        assert_eq!(
+            WatchProjection::parse("json:a.b"),
+            Ok(WatchProjection::JsonField(vec![
+                "a".to_owned(),
+                "b".to_owned()
+            ]))
+        );
+        assert!(WatchProjection::parse("prefix:-1").is_err());
+        assert!(WatchProjection::parse("json:a..b").is_err());
+        assert!(WatchProjection::parse("json:a.b.c.d.e.f.g.h.i").is_err());
+        assert!(WatchProjection::parse("script:a").is_err());
+    }
+
+    #[test]
+    fn projections_should_be_keyed_by_watch_id() {
+        assert_eq!(
+            WatchProjection::parse_all("1=prefix:4,3=json:a"),
+            Ok(HashMap::from([
+                (1, WatchProjection::Prefix(4)),
+                (3, WatchProjection::JsonField(vec!["a".to_owned()]))
+            ]))
+        );
+        assert!(WatchProjection::parse_all("prefix:4").is_err());
+        assert!(WatchProjection::parse_all("0=prefix:4").is_err());
+        assert!(WatchProjection::parse_all("1=prefix:4,1=prefix:2").is_err());
+        let too_many: Vec<String> = (1..=65).map(|id| format!("{id}=prefix:1")).collect();
+        assert!(WatchProjection::parse_all(&too_many.join(",")).is_err());
+    }
+
+    #[test]
+    fn values_should_be_projected() {
+        let prefix = WatchProjection::Prefix(3);
+        assert_eq!(prefix.project(b"abcdef"), b"abc");
+        assert_eq!(prefix.project(b"ab"), b"ab");
+
+        let json = WatchProjection::JsonField(vec!["spec".to_owned(), "replicas".to_owned()]);
+        assert_eq!(
+            json.project(br#"{"spec":{"replicas":3,"image":"xline"},"status":{}}"#),
+            b"3"
+        );
+        assert!(json.project(br#"{"spec":{}}"#).is_empty());
+        assert!(json.project(b"not json").is_empty());
+    }
+}
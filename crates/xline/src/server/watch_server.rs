@@ -7,18 +7,29 @@ use std::{
 use event_listener::Event;
 use tokio::sync::mpsc;
 use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tonic::metadata::MetadataMap;
 use tracing::{debug, warn};
-use utils::task_manager::{tasks::TaskName, Listener, TaskManager};
-use xlineapi::command::KeyRange;
+use utils::{
+    config::WatchHistoryReplay,
+    task_manager::{tasks::TaskName, Listener, TaskManager},
+};
+use xlineapi::command::{Command, KeyRange};
 
+use super::{
+    lease_watch::{lease_watch_task, LEASE_EVENTS_KEY},
+    read_index::ReadIndexWaiter,
+    watch_fragment::ResponseSplitter,
+    watch_projection::WatchProjection,
+};
 use crate::{
     header_gen::HeaderGenerator,
     rpc::{
-        RequestUnion, ResponseHeader, Watch, WatchCancelRequest, WatchCreateRequest,
-        WatchProgressRequest, WatchRequest, WatchResponse,
+        RangeRequest, RequestUnion, RequestWrapper, ResponseHeader, Watch, WatchCancelRequest,
+        WatchCreateRequest, WatchProgressRequest, WatchRequest, WatchResponse,
     },
     storage::{
         kvwatcher::{KvWatcher, KvWatcherOps, WatchEvent, WatchId, WatchIdGenerator},
+        lease_store::LeaseEventHub,
         storage_api::StorageApi,
     },
 };
@@ -26,6 +37,18 @@ use crate::{
 /// Default channel size
 pub(crate) const CHANNEL_SIZE: usize = 1024;
 
+/// Metadata key of a watch stream annotating the recreations of keys. An event which
+/// creates a key deleted before carries the tombstone of the deletion as its
+/// `prev_kv`, i.e. a kv with only the key and the `mod_revision` of the deletion, and
+/// a version of 0. The creation of a key whose deletion is compacted is not annotated.
+pub(crate) const WATCH_RECREATION_KEY: &str = "watch-recreation";
+
+/// Metadata key of a watch stream setting the buffer depth of the watches it creates,
+/// i.e. how many undelivered events a watch can hold before it falls behind and
+/// its events are resent later. The depth is clamped to the bounds of the server,
+/// a stream without it leaves its watches bounded only by the stream.
+pub(crate) const WATCH_BUFFER_DEPTH_KEY: &str = "watch-buffer-depth";
+
 /// Watch Server
 #[derive(Debug)]
 pub(crate) struct WatchServer<S>
@@ -40,6 +63,17 @@ where
     header_gen: Arc<HeaderGenerator>,
     /// Watch progress notify interval
     watch_progress_notify_interval: Duration,
+    /// Read index waiter, `None` means watches created from the current
+    /// revision are not linearizable
+    read_index_waiter: Option<Arc<ReadIndexWaiter>>,
+    /// How a watch created from a historical revision is handled
+    history_replay: WatchHistoryReplay,
+    /// Splitter of the responses exceeding the max send message size
+    splitter: ResponseSplitter,
+    /// The min and max buffer depths a watch can request
+    buffer_depth_bounds: (usize, usize),
+    /// The liveness events of the leases
+    lease_events: Arc<LeaseEventHub>,
     /// Task manager
     task_manager: Arc<TaskManager>,
 }
@@ -49,10 +83,16 @@ where
     S: StorageApi,
 {
     /// New `WatchServer`
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         watcher: Arc<KvWatcher<S>>,
         header_gen: Arc<HeaderGenerator>,
         watch_progress_notify_interval: Duration,
+        read_index_waiter: Option<Arc<ReadIndexWaiter>>,
+        history_replay: WatchHistoryReplay,
+        splitter: ResponseSplitter,
+        buffer_depth_bounds: (usize, usize),
+        lease_events: Arc<LeaseEventHub>,
         task_manager: Arc<TaskManager>,
     ) -> Self {
         Self {
@@ -60,12 +100,34 @@ where
             next_id_gen: Arc::new(WatchIdGenerator::new(1)), // watch_id starts from 1, 0 means auto-generating
             header_gen,
             watch_progress_notify_interval,
+            read_index_waiter,
+            history_replay,
+            splitter,
+            buffer_depth_bounds,
+            lease_events,
             task_manager,
         }
     }
 
+    /// Get the buffer depth of the watches of a stream from its metadata
+    fn buffer_depth(&self, metadata: &MetadataMap) -> Result<Option<usize>, tonic::Status> {
+        let Some(value) = metadata.get(WATCH_BUFFER_DEPTH_KEY) else {
+            return Ok(None);
+        };
+        let depth: usize = value
+            .to_str()
+            .ok()
+            .and_then(|depth| depth.parse().ok())
+            .ok_or_else(|| {
+                tonic::Status::invalid_argument(format!("invalid watch buffer depth {value:?}"))
+            })?;
+        let (min, max) = self.buffer_depth_bounds;
+        Ok(Some(depth.clamp(min, max.max(min))))
+    }
+
     /// bg task for handle watch connection
     #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
+    #[allow(clippy::too_many_arguments)]
     async fn task<ST, W>(
         next_id_gen: Arc<WatchIdGenerator>,
         kv_watcher: Arc<W>,
@@ -73,6 +135,13 @@ where
         mut req_rx: ST,
         header_gen: Arc<HeaderGenerator>,
         watch_progress_notify_interval: Duration,
+        read_index_waiter: Option<Arc<ReadIndexWaiter>>,
+        history_replay: WatchHistoryReplay,
+        projections: HashMap<WatchId, WatchProjection>,
+        annotate_recreation: bool,
+        buffer_depth: Option<usize>,
+        splitter: ResponseSplitter,
+        owner: String,
         shutdown_listener: Listener,
     ) where
         ST: Stream<Item = Result<WatchRequest, tonic::Status>> + Unpin,
@@ -87,6 +156,13 @@ where
             Arc::clone(&stop_notify),
             next_id_gen,
             header_gen,
+            read_index_waiter,
+            history_replay,
+            projections,
+            annotate_recreation,
+            buffer_depth,
+            splitter,
+            owner,
         );
         let mut ticker = tokio::time::interval(watch_progress_notify_interval);
         let stop_listener = stop_notify.listen();
@@ -158,6 +234,22 @@ where
     ///
     /// `false` means the next tick should be skipped
     progress: HashMap<WatchId, bool>,
+    /// Read index waiter for linearizable watch create
+    read_index_waiter: Option<Arc<ReadIndexWaiter>>,
+    /// How a watch created from a historical revision is handled
+    history_replay: WatchHistoryReplay,
+    /// Projections declared by the stream for the watches created with explicit ids
+    declared_projections: HashMap<WatchId, WatchProjection>,
+    /// Projections applied to the values delivered by the active watches
+    projections: HashMap<WatchId, WatchProjection>,
+    /// Whether the creations of the keys deleted before are annotated
+    annotate_recreation: bool,
+    /// Buffer depth of the watches, `None` means unbounded
+    buffer_depth: Option<usize>,
+    /// Splitter of the responses exceeding the max send message size
+    splitter: ResponseSplitter,
+    /// The remote address of the connection
+    owner: String,
 }
 
 impl<W> WatchHandle<W>
@@ -165,6 +257,7 @@ where
     W: KvWatcherOps,
 {
     /// New `WatchHandle`
+    #[allow(clippy::too_many_arguments)]
     fn new(
         kv_watcher: Arc<W>,
         response_tx: mpsc::Sender<Result<WatchResponse, tonic::Status>>,
@@ -172,6 +265,13 @@ where
         stop_notify: Arc<Event>,
         next_id_gen: Arc<WatchIdGenerator>,
         header_gen: Arc<HeaderGenerator>,
+        read_index_waiter: Option<Arc<ReadIndexWaiter>>,
+        history_replay: WatchHistoryReplay,
+        declared_projections: HashMap<WatchId, WatchProjection>,
+        annotate_recreation: bool,
+        buffer_depth: Option<usize>,
+        splitter: ResponseSplitter,
+        owner: String,
     ) -> Self {
         Self {
             kv_watcher,
@@ -183,9 +283,63 @@ where
             header_gen,
             prev_kv: HashSet::new(),
             progress: HashMap::new(),
+            read_index_waiter,
+            history_replay,
+            declared_projections,
+            projections: HashMap::new(),
+            annotate_recreation,
+            buffer_depth,
+            splitter,
+            owner,
         }
     }
 
+    /// Apply the history replay mode to a watch create request, return the cancel
+    /// reason if the watch is rejected
+    fn check_history_replay(&self, req: &mut WatchCreateRequest) -> Option<String> {
+        let current_revision = self.header_gen.general_revision();
+        if req.start_revision == 0 || req.start_revision > current_revision {
+            return None;
+        }
+        match self.history_replay {
+            WatchHistoryReplay::Reject => Some(format!(
+                "watch history replay is disabled, start revision {} is historical",
+                req.start_revision
+            )),
+            WatchHistoryReplay::Current => {
+                debug!(
+                    start_revision = req.start_revision,
+                    "watch history replay is disabled, start the watch from the current revision"
+                );
+                req.start_revision = 0;
+                None
+            }
+            WatchHistoryReplay::Allow => None,
+            _ => unreachable!("xline only supports three watch history replay modes"),
+        }
+    }
+
+    /// Wait for a read index before creating a watch from the current revision,
+    /// so that the start revision of the watch is linearizable
+    async fn wait_linearizable_create(
+        &self,
+        req: &WatchCreateRequest,
+    ) -> Result<(), tonic::Status> {
+        let Some(ref waiter) = self.read_index_waiter else {
+            return Ok(());
+        };
+        if req.start_revision != 0 {
+            return Ok(());
+        }
+        let request = RequestWrapper::from(RangeRequest {
+            key: req.key.clone(),
+            range_end: req.range_end.clone(),
+            ..RangeRequest::default()
+        });
+        let cmd = Command::new(request.keys(), request);
+        waiter.wait(&cmd).await
+    }
+
     /// Validate the given `watch_id`, return None if the given id is not available, will generate a new one if the given one equals 0
     fn validate_watch_id(&mut self, watch_id: WatchId) -> Option<WatchId> {
         // 0 means auto-generate
@@ -204,7 +358,8 @@ where
     }
 
     /// Handle `WatchCreateRequest`
-    async fn handle_watch_create(&mut self, req: WatchCreateRequest) {
+    async fn handle_watch_create(&mut self, mut req: WatchCreateRequest) {
+        let explicit_id = req.watch_id != 0;
         let Some(watch_id) = self.validate_watch_id(req.watch_id) else {
             let result = Err(tonic::Status::already_exists(format!(
                 "Watch ID {} has already been used",
@@ -215,6 +370,26 @@ where
             }
             return;
         };
+        if let Some(cancel_reason) = self.check_history_replay(&mut req) {
+            let response = WatchResponse {
+                header: Some(self.header_gen.gen_header()),
+                watch_id,
+                created: true,
+                canceled: true,
+                cancel_reason,
+                ..WatchResponse::default()
+            };
+            if self.response_tx.send(Ok(response)).await.is_err() {
+                let _ignore = self.stop_notify.notify(1);
+            }
+            return;
+        }
+        if let Err(e) = self.wait_linearizable_create(&req).await {
+            if self.response_tx.send(Err(e)).await.is_err() {
+                let _ignore = self.stop_notify.notify(1);
+            }
+            return;
+        }
 
         let key_range = KeyRange::new(req.key, req.range_end);
         self.kv_watcher.watch(
@@ -222,8 +397,10 @@ where
             key_range,
             req.start_revision,
             req.filters,
+            self.owner.clone(),
             Arc::clone(&self.stop_notify),
             self.event_tx.clone(),
+            self.buffer_depth,
         );
         if req.prev_kv {
             assert!(
@@ -237,6 +414,13 @@ where
                 "WatchId {watch_id} already exists in progress",
             );
         }
+        if let Some(projection) = self
+            .declared_projections
+            .get(&watch_id)
+            .filter(|_| explicit_id)
+        {
+            let _prev = self.projections.insert(watch_id, projection.clone());
+        }
         assert!(
             self.active_watch_ids.insert(watch_id),
             "WatchId {watch_id} already exists in active_watch_ids",
@@ -259,6 +443,7 @@ where
         let result = if self.active_watch_ids.remove(&watch_id) {
             self.kv_watcher.cancel(watch_id);
             let _prev = self.active_watch_ids.remove(&watch_id);
+            let _prev = self.projections.remove(&watch_id);
             let response = WatchResponse {
                 header: Some(self.header_gen.gen_header()),
                 watch_id,
@@ -325,11 +510,26 @@ where
                     }
                 }
             }
+            if self.annotate_recreation {
+                for ev in events.iter_mut().filter(|ev| ev.is_create()) {
+                    let kv = ev
+                        .kv
+                        .as_ref()
+                        .unwrap_or_else(|| panic!("event.kv can't be None"));
+                    ev.prev_kv = self.kv_watcher.get_prev_deletion(kv);
+                }
+            }
+            if let Some(projection) = self.projections.get(&watch_id) {
+                events.iter_mut().for_each(|ev| projection.apply(ev));
+            }
             response.events = events;
         };
 
-        if self.response_tx.send(Ok(response)).await.is_err() {
-            let _ignore = self.stop_notify.notify(1);
+        for response in self.splitter.split(response) {
+            if self.response_tx.send(Ok(response)).await.is_err() {
+                let _ignore = self.stop_notify.notify(1);
+                break;
+            }
         }
         if let Some(progress) = self.progress.get_mut(&watch_id) {
             *progress = false;
@@ -404,6 +604,26 @@ where
         request: tonic::Request<tonic::Streaming<WatchRequest>>,
     ) -> Result<tonic::Response<Self::WatchStream>, tonic::Status> {
         debug!("Receive Watch Connection {:?}", request);
+        if request.metadata().contains_key(LEASE_EVENTS_KEY) {
+            let req_stream = request.into_inner();
+            let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
+            self.task_manager.spawn(TaskName::WatchTask, |n| {
+                lease_watch_task(
+                    Arc::clone(&self.lease_events),
+                    Arc::clone(&self.header_gen),
+                    tx,
+                    req_stream,
+                    n,
+                )
+            });
+            return Ok(tonic::Response::new(ReceiverStream::new(rx)));
+        }
+        let projections = WatchProjection::from_metadata(request.metadata())?;
+        let annotate_recreation = request.metadata().contains_key(WATCH_RECREATION_KEY);
+        let buffer_depth = self.buffer_depth(request.metadata())?;
+        let owner = request
+            .remote_addr()
+            .map_or_else(|| "unknown".to_owned(), |addr| addr.to_string());
         let req_stream = request.into_inner();
         let (tx, rx) = mpsc::channel(CHANNEL_SIZE);
         self.task_manager.spawn(TaskName::WatchTask, |n| {
@@ -414,6 +634,13 @@ where
                 req_stream,
                 Arc::clone(&self.header_gen),
                 self.watch_progress_notify_interval,
+                self.read_index_waiter.clone(),
+                self.history_replay,
+                projections,
+                annotate_recreation,
+                buffer_depth,
+                self.splitter,
+                owner,
                 n,
             )
         });
@@ -442,8 +669,13 @@ mod test {
     use crate::{
         rpc::{PutRequest, WatchProgressRequest},
         storage::{
-            compact::COMPACT_CHANNEL_SIZE, db::DB, index::Index, kv_store::KvStoreInner,
-            kvwatcher::MockKvWatcherOps, lease_store::LeaseCollection, KvStore,
+            compact::COMPACT_CHANNEL_SIZE,
+            db::DB,
+            index::Index,
+            kv_store::{KvStoreInner, KvStoreOptions},
+            kvwatcher::MockKvWatcherOps,
+            lease_store::LeaseCollection,
+            KvStore,
         },
     };
 
@@ -497,6 +729,13 @@ mod test {
             req_stream,
             header_gen,
             default_watch_progress_notify_interval(),
+            None,
+            WatchHistoryReplay::Allow,
+            HashMap::new(),
+            false,
+            None,
+            ResponseSplitter::default(),
+            "test".to_owned(),
             n,
         ));
         req_tx
@@ -526,7 +765,7 @@ mod test {
         let collection = Arc::new(Mutex::new(HashMap::new()));
         let collection_c = Arc::clone(&collection);
         let _ = mock_watcher.expect_watch().times(2).returning({
-            move |x, _, _, _, _, _| {
+            move |x, _, _, _, _, _, _, _| {
                 let mut c = collection_c.lock();
                 let e = c.entry(x).or_insert(0);
                 *e += 1;
@@ -552,6 +791,13 @@ mod test {
                 req_stream1,
                 Arc::clone(&header_gen),
                 default_watch_progress_notify_interval(),
+                None,
+                WatchHistoryReplay::Allow,
+                HashMap::new(),
+                false,
+                None,
+                ResponseSplitter::default(),
+                "test".to_owned(),
                 n,
             )
         });
@@ -568,6 +814,13 @@ mod test {
                 req_stream2,
                 header_gen,
                 default_watch_progress_notify_interval(),
+                None,
+                WatchHistoryReplay::Allow,
+                HashMap::new(),
+                false,
+                None,
+                ResponseSplitter::default(),
+                "test".to_owned(),
                 n,
             )
         });
@@ -597,7 +850,7 @@ mod test {
         let index = Arc::new(Index::new());
         let db = DB::open(&EngineConfig::Memory).unwrap();
         let header_gen = Arc::new(HeaderGenerator::new(0, 0));
-        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let lease_collection = Arc::new(LeaseCollection::new(0, Duration::ZERO));
         let next_id_gen = Arc::new(WatchIdGenerator::new(1));
         let (kv_update_tx, kv_update_rx) = mpsc::channel(CHANNEL_SIZE);
         let kv_store_inner = Arc::new(KvStoreInner::new(index, Arc::clone(&db)));
@@ -607,6 +860,7 @@ mod test {
             kv_update_tx,
             compact_tx,
             lease_collection,
+            KvStoreOptions::default(),
         ));
         let kv_watcher = KvWatcher::new_arc(
             kv_store_inner,
@@ -639,6 +893,13 @@ mod test {
                 req_stream,
                 Arc::clone(&header_gen),
                 default_watch_progress_notify_interval(),
+                None,
+                WatchHistoryReplay::Allow,
+                HashMap::new(),
+                false,
+                None,
+                ResponseSplitter::default(),
+                "test".to_owned(),
                 n,
             )
         });
@@ -686,6 +947,13 @@ mod test {
                 req_stream,
                 header_gen,
                 Duration::from_millis(100),
+                None,
+                WatchHistoryReplay::Allow,
+                HashMap::new(),
+                false,
+                None,
+                ResponseSplitter::default(),
+                "test".to_owned(),
                 n,
             )
         });
@@ -749,6 +1017,13 @@ mod test {
             req_stream,
             header_gen,
             Duration::from_millis(100),
+            None,
+            WatchHistoryReplay::Allow,
+            HashMap::new(),
+            false,
+            None,
+            ResponseSplitter::default(),
+            "test".to_owned(),
             n,
         ));
 
@@ -783,7 +1058,7 @@ mod test {
         let index = Arc::new(Index::new());
         let db = DB::open(&EngineConfig::Memory).unwrap();
         let header_gen = Arc::new(HeaderGenerator::new(0, 0));
-        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let lease_collection = Arc::new(LeaseCollection::new(0, Duration::ZERO));
         let next_id_gen = Arc::new(WatchIdGenerator::new(1));
         let (kv_update_tx, kv_update_rx) = mpsc::channel(CHANNEL_SIZE);
         let kv_store_inner = Arc::new(KvStoreInner::new(index, Arc::clone(&db)));
@@ -793,6 +1068,7 @@ mod test {
             kv_update_tx,
             compact_tx,
             lease_collection,
+            KvStoreOptions::default(),
         ));
         let kv_watcher = KvWatcher::new_arc(
             kv_store_inner,
@@ -826,6 +1102,13 @@ mod test {
                 req_stream,
                 Arc::clone(&header_gen),
                 default_watch_progress_notify_interval(),
+                None,
+                WatchHistoryReplay::Allow,
+                HashMap::new(),
+                false,
+                None,
+                ResponseSplitter::default(),
+                "test".to_owned(),
                 n,
             )
         });
@@ -0,0 +1,233 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::future::join_all;
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+use tonic::metadata::MetadataMap;
+use tracing::debug;
+use utils::parse_duration;
+use xlineapi::{
+    command::{Command, CommandResponse, CurpClient, SyncResponse},
+    execute_error::ExecuteError,
+    AuthInfo,
+};
+
+use crate::rpc::{
+    PutRequest, PutResponse, Request, RequestOp, RequestWrapper, Response, ResponseOp, TxnRequest,
+};
+
+/// Metadata key which hints the window in which a put may wait to be coalesced
+/// with other puts, a put without it is proposed immediately
+pub(crate) const WRITE_COALESCING_WINDOW_KEY: &str = "write-coalescing-window";
+
+/// The maximum number of puts coalesced into one proposal
+const MAX_COALESCED_PUTS: usize = 128;
+
+/// Result of a coalesced put
+type PutResult = Result<PutResponse, tonic::Status>;
+
+/// Puts waiting to be proposed together
+#[derive(Debug)]
+struct PendingBatch {
+    /// Id of the batch
+    id: u64,
+    /// Auth info shared by all puts of the batch
+    auth_info: Option<AuthInfo>,
+    /// Keys of the puts, a txn can't modify the same key twice
+    keys: HashSet<Vec<u8>>,
+    /// The puts and the senders of their results
+    puts: Vec<(PutRequest, oneshot::Sender<PutResult>)>,
+}
+
+/// Coalesces the puts of latency-insensitive clients, e.g. bulk loaders, into
+/// a single txn proposal to improve the throughput.
+///
+/// A client opts in by hinting a window with the `write-coalescing-window`
+/// metadata, the window is capped by the server. A batch is proposed when the
+/// window of its first put expires or it is full, so a put waits no longer than
+/// its own window. Only the puts of the same user are coalesced.
+#[derive(Debug)]
+pub(crate) struct WriteCoalescer {
+    /// Consensus client
+    client: Arc<CurpClient>,
+    /// The maximum window, 0 means the coalescing is disabled
+    max_window: Duration,
+    /// Batches waiting for their windows to expire
+    pending: Mutex<Vec<PendingBatch>>,
+    /// Generator of batch ids
+    next_batch_id: AtomicU64,
+}
+
+impl WriteCoalescer {
+    /// New `WriteCoalescer`
+    pub(crate) fn new(client: Arc<CurpClient>, max_window: Duration) -> Self {
+        Self {
+            client,
+            max_window,
+            pending: Mutex::new(Vec::new()),
+            next_batch_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the coalescing window of a put from its metadata, `None` means the put
+    /// should be proposed immediately
+    pub(crate) fn window(&self, metadata: &MetadataMap) -> Result<Option<Duration>, tonic::Status> {
+        if self.max_window.is_zero() {
+            return Ok(None);
+        }
+        let Some(value) = metadata.get(WRITE_COALESCING_WINDOW_KEY) else {
+            return Ok(None);
+        };
+        let window = value
+            .to_str()
+            .ok()
+            .and_then(|s| parse_duration(s).ok())
+            .ok_or_else(|| tonic::Status::invalid_argument("invalid write coalescing window"))?;
+        Ok((!window.is_zero()).then(|| window.min(self.max_window)))
+    }
+
+    /// Propose a put together with the other puts arriving in its window
+    pub(crate) async fn put(
+        self: &Arc<Self>,
+        request: PutRequest,
+        auth_info: Option<AuthInfo>,
+        window: Duration,
+    ) -> PutResult {
+        let (tx, rx) = oneshot::channel();
+        let full_batch = {
+            let mut pending = self.pending.lock();
+            let position = pending.iter().position(|batch| {
+                batch.auth_info == auth_info && !batch.keys.contains(&request.key)
+            });
+            if let Some(position) = position {
+                let batch = pending
+                    .get_mut(position)
+                    .unwrap_or_else(|| unreachable!("the position is found above"));
+                let _ignore = batch.keys.insert(request.key.clone());
+                batch.puts.push((request, tx));
+                (batch.puts.len() >= MAX_COALESCED_PUTS).then(|| pending.swap_remove(position))
+            } else {
+                let id = self.next_batch_id.fetch_add(1, Ordering::Relaxed);
+                pending.push(PendingBatch {
+                    id,
+                    auth_info,
+                    keys: HashSet::from([request.key.clone()]),
+                    puts: vec![(request, tx)],
+                });
+                let coalescer = Arc::clone(self);
+                let _handle = tokio::spawn(async move {
+                    tokio::time::sleep(window).await;
+                    let batch = {
+                        let mut pending = coalescer.pending.lock();
+                        pending
+                            .iter()
+                            .position(|batch| batch.id == id)
+                            .map(|position| pending.swap_remove(position))
+                    };
+                    if let Some(batch) = batch {
+                        coalescer.propose(batch).await;
+                    }
+                });
+                None
+            }
+        };
+        if let Some(batch) = full_batch {
+            let coalescer = Arc::clone(self);
+            let _handle = tokio::spawn(async move { coalescer.propose(batch).await });
+        }
+        rx.await
+            .unwrap_or_else(|_e| Err(tonic::Status::internal("coalesced put is dropped")))
+    }
+
+    /// Propose a batch as a txn and send the results to the puts. A bad put, e.g.
+    /// one whose lease is missing or which exceeds the quota, fails the whole txn, so
+    /// the puts of a failed txn are proposed one by one to fail only the bad ones.
+    async fn propose(&self, batch: PendingBatch) {
+        let (requests, senders): (Vec<_>, Vec<_>) = batch.puts.into_iter().unzip();
+        debug!("propose {} coalesced puts", requests.len());
+        let txn = TxnRequest {
+            compare: vec![],
+            success: requests
+                .iter()
+                .map(|req| RequestOp {
+                    request: Some(Request::RequestPut(req.clone())),
+                })
+                .collect(),
+            failure: vec![],
+        };
+        match self.propose_request(txn, batch.auth_info.clone()).await {
+            Ok(Ok((cmd_res, sync_res))) => {
+                let revision = sync_res.map(|res| res.revision());
+                let Some(Response::ResponseTxn(txn_res)) =
+                    ResponseOp::from(cmd_res.into_inner()).response
+                else {
+                    unreachable!("Receive wrong response for TxnRequest");
+                };
+                for (sender, op) in senders.into_iter().zip(txn_res.responses) {
+                    let Some(Response::ResponsePut(put_res)) = op.response else {
+                        unreachable!("Receive wrong response for PutRequest");
+                    };
+                    let _ignore = sender.send(Ok(Self::with_revision(put_res, revision)));
+                }
+            }
+            Ok(Err(err)) if requests.len() > 1 => {
+                debug!("coalesced puts are rejected: {err:?}, propose them one by one");
+                let puts = requests.into_iter().zip(senders).map(|(req, sender)| {
+                    let auth_info = batch.auth_info.clone();
+                    async move {
+                        let _ignore = sender.send(self.propose_put(req, auth_info).await);
+                    }
+                });
+                let _results = join_all(puts).await;
+            }
+            Ok(Err(err)) => Self::fail(senders, &err.into()),
+            Err(status) => Self::fail(senders, &status),
+        }
+    }
+
+    /// Send the same error to all the puts
+    fn fail(senders: Vec<oneshot::Sender<PutResult>>, status: &tonic::Status) {
+        for sender in senders {
+            let _ignore = sender.send(Err(status.clone()));
+        }
+    }
+
+    /// Propose a single put
+    async fn propose_put(&self, request: PutRequest, auth_info: Option<AuthInfo>) -> PutResult {
+        let (cmd_res, sync_res) = self.propose_request(request, auth_info).await??;
+        let Some(Response::ResponsePut(put_res)) = ResponseOp::from(cmd_res.into_inner()).response
+        else {
+            unreachable!("Receive wrong response for PutRequest");
+        };
+        Ok(Self::with_revision(
+            put_res,
+            sync_res.map(|res| res.revision()),
+        ))
+    }
+
+    /// Set the revision of the header of a put response if it's known
+    fn with_revision(mut response: PutResponse, revision: Option<i64>) -> PutResponse {
+        if let (Some(header), Some(revision)) = (response.header.as_mut(), revision) {
+            header.revision = revision;
+        }
+        response
+    }
+
+    /// Propose a request through the fast path
+    async fn propose_request<T: Into<RequestWrapper>>(
+        &self,
+        request: T,
+        auth_info: Option<AuthInfo>,
+    ) -> Result<Result<(CommandResponse, Option<SyncResponse>), ExecuteError>, tonic::Status> {
+        let request = request.into();
+        let cmd = Command::new_with_auth_info(request.keys(), request, auth_info);
+        Ok(self.client.propose(&cmd, None, true).await?)
+    }
+}
@@ -6,7 +6,7 @@ use curp::{
     client::ClientBuilder as CurpClientBuilder,
     members::{get_cluster_info_from_remote, ClusterInfo},
     rpc::{InnerProtocolServer, ProtocolServer},
-    server::{Rpc, StorageApi as _, DB as CurpDB},
+    server::{RawCurp, Rpc, StorageApi as _, DB as CurpDB},
 };
 use dashmap::DashMap;
 use engine::{MemorySnapshotAllocator, RocksSnapshotAllocator, SnapshotAllocator};
@@ -17,6 +17,8 @@ use jsonwebtoken::{DecodingKey, EncodingKey};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::{fs, sync::mpsc::channel};
 #[cfg(not(madsim))]
+use tokio_rustls::{rustls::ServerConfig as RestrictedTlsConfig, TlsAcceptor};
+#[cfg(not(madsim))]
 use tonic::transport::{
     server::Connected, Certificate, ClientTlsConfig, Identity, ServerTlsConfig,
 };
@@ -24,25 +26,46 @@ use tonic::transport::{server::Router, Server};
 use tracing::{info, warn};
 use utils::{
     config::{
-        AuthConfig, ClusterConfig, CompactConfig, EngineConfig, InitialClusterState, StorageConfig,
-        TlsConfig,
+        AuthConfig, ClusterConfig, CompactConfig, EngineConfig, InitialClusterState, KvConfig,
+        StorageConfig, TlsConfig, WatchConfig, MAX_LEASE_GRACE_PERIOD,
     },
     task_manager::{tasks::TaskName, TaskManager},
 };
 #[cfg(madsim)]
 use utils::{ClientTlsConfig, ServerTlsConfig};
-use xlineapi::command::{Command, CurpClient};
+use xlineapi::{
+    command::{Command, CurpClient},
+    AlarmType,
+};
 
+#[cfg(not(madsim))]
+use super::{
+    admin_server::AdminServer,
+    listener_role::{ListenerGuard, ListenerRole},
+    member_watch::MemberWatchServer,
+    range_stream::RangeStreamServer,
+    tls,
+};
 use super::{
+    apply_watchdog::{run_apply_watchdog, serving_status, ApplyProgress, CurpApplyProgress},
+    archive_sink::ArchiveSink,
+    auth_backend::{AuthBackend, CachedAuthBackend},
     auth_server::AuthServer,
     auth_wrapper::AuthWrapper,
+    auto_defrag::{run_auto_defrag, CurpDefragCluster, DefragCluster},
     barriers::{IdBarrier, IndexBarrier},
     cluster_server::ClusterServer,
     command::{Alarmer, CommandExecutor},
+    command_hook::CommandHook,
     kv_server::KvServer,
     lease_server::LeaseServer,
     lock_server::LockServer,
     maintenance::MaintenanceServer,
+    member_tags,
+    read_index::ReadIndexWaiter,
+    user_stats::UserStats,
+    value_validator::{ValueValidator, ValueValidators},
+    watch_fragment::ResponseSplitter,
     watch_server::{WatchServer, CHANNEL_SIZE},
 };
 use crate::{
@@ -57,12 +80,18 @@ use crate::{
     },
     state::State,
     storage::{
-        compact::{auto_compactor, compact_bg_task, COMPACT_CHANNEL_SIZE},
+        compact::{
+            auto_compactor, compact_bg_task, ActiveWatches, WatchFloor, COMPACT_CHANNEL_SIZE,
+        },
         db::DB,
-        index::Index,
-        kv_store::KvStoreInner,
+        encryption::RecordCipher,
+        index::{CompactProtection, Index},
+        index_check::run_index_check,
+        kv_store::{KvStoreInner, KvStoreOptions},
         kvwatcher::KvWatcher,
         lease_store::LeaseCollection,
+        maintenance_scheduler::MaintenanceScheduler,
+        prefix_stats::PrefixStats,
         storage_api::StorageApi,
         AlarmStore, AuthStore, KvStore, LeaseStore,
     },
@@ -84,15 +113,33 @@ pub struct XlineServer {
     compact_config: CompactConfig,
     /// Auth config
     auth_config: AuthConfig,
+    /// Watch config
+    watch_config: WatchConfig,
+    /// KV config
+    kv_config: KvConfig,
     /// Client tls config
     client_tls_config: Option<ClientTlsConfig>,
     /// Server tls config
     #[cfg_attr(madsim, allow(unused))]
     server_tls_config: Option<ServerTlsConfig>,
+    /// Config terminating the tls of the listeners by rustls, used instead of the
+    /// server tls config when the tls handshakes are restricted
+    #[cfg(not(madsim))]
+    restricted_tls_config: Option<Arc<RestrictedTlsConfig>>,
     /// Task Manager
     task_manager: Arc<TaskManager>,
     /// Curp storage
     curp_storage: Arc<CurpDB<Command>>,
+    /// Scheduler of the heavy storage maintenance operations
+    maintenance_scheduler: Arc<MaintenanceScheduler>,
+    /// Hooks around the apply of commands
+    command_hooks: Vec<Arc<dyn CommandHook>>,
+    /// The external verifier of the credentials
+    auth_backend: Option<Arc<CachedAuthBackend>>,
+    /// Validators of the values under key prefixes
+    value_validators: ValueValidators,
+    /// The sink archiving the versions removed by the compaction
+    archive_sink: Option<Arc<dyn ArchiveSink>>,
 }
 
 impl XlineServer {
@@ -106,9 +153,17 @@ impl XlineServer {
         compact_config: CompactConfig,
         auth_config: AuthConfig,
         #[cfg_attr(madsim, allow(unused_variables))] tls_config: TlsConfig,
+        watch_config: WatchConfig,
+        kv_config: KvConfig,
     ) -> Result<Self> {
         #[cfg(not(madsim))]
         let (client_tls_config, server_tls_config) = Self::read_tls_config(&tls_config).await?;
+        #[cfg(not(madsim))]
+        let (server_tls_config, restricted_tls_config) = if tls_config.restricts_handshakes() {
+            (None, Some(tls::restricted_config(&tls_config).await?))
+        } else {
+            (server_tls_config, None)
+        };
         #[cfg(madsim)]
         let (client_tls_config, server_tls_config) = (None, None);
         let curp_storage = Arc::new(CurpDB::open(&cluster_config.curp_config().engine_cfg)?);
@@ -120,19 +175,75 @@ impl XlineServer {
             )
             .await?,
         );
+        let maintenance_scheduler = Arc::new(MaintenanceScheduler::new(
+            storage_config.maintenance_policy,
+            &storage_config.maintenance_priority,
+        ));
         Ok(Self {
             cluster_info,
             cluster_config,
             storage_config,
             compact_config,
             auth_config,
+            watch_config,
+            kv_config,
             client_tls_config,
             server_tls_config,
+            #[cfg(not(madsim))]
+            restricted_tls_config,
             task_manager: Arc::new(TaskManager::new()),
             curp_storage,
+            maintenance_scheduler,
+            command_hooks: Vec::new(),
+            auth_backend: None,
+            value_validators: ValueValidators::default(),
+            archive_sink: None,
         })
     }
 
+    /// Register a hook around the apply of commands, the hooks run in the order they
+    /// are registered and must be registered before the server starts
+    #[inline]
+    #[must_use]
+    pub fn with_command_hook(mut self, hook: Arc<dyn CommandHook>) -> Self {
+        self.command_hooks.push(hook);
+        self
+    }
+
+    /// Delegate the verification of the credentials to an external backend, whose
+    /// successful verifications are cached for `cache_ttl`. It must be registered
+    /// before the server starts.
+    #[inline]
+    #[must_use]
+    pub fn with_auth_backend(mut self, backend: Arc<dyn AuthBackend>, cache_ttl: Duration) -> Self {
+        self.auth_backend = Some(Arc::new(CachedAuthBackend::new(backend, cache_ttl)));
+        self
+    }
+
+    /// Register a validator of the values written under `prefix`, a value must
+    /// conform to the validators of all the prefixes of its key. It must be registered
+    /// on every node before the server starts.
+    #[inline]
+    #[must_use]
+    pub fn with_value_validator(
+        mut self,
+        prefix: impl Into<Vec<u8>>,
+        validator: Arc<dyn ValueValidator>,
+    ) -> Self {
+        self.value_validators.register(prefix.into(), validator);
+        self
+    }
+
+    /// Archive the versions removed by the compaction to `sink`, so that the ranges at
+    /// the compacted revisions asking for the archive can still be served. It must be
+    /// registered before the server starts.
+    #[inline]
+    #[must_use]
+    pub fn with_archive_sink(mut self, sink: Arc<dyn ArchiveSink>) -> Self {
+        self.archive_sink = Some(sink);
+        self
+    }
+
     /// Init cluster info from cluster config
     async fn init_cluster_info(
         cluster_config: &ClusterConfig,
@@ -169,6 +280,7 @@ impl XlineServer {
                     cluster_config.name(),
                     *cluster_config.client_config().wait_synced_timeout(),
                     tls_config,
+                    cluster_config.message_size().peer_limits(),
                 )
                 .await
                 .ok_or_else(|| anyhow!("Failed to get cluster info from remote"))?;
@@ -187,13 +299,20 @@ impl XlineServer {
     fn construct_lease_collection(
         heartbeat_interval: Duration,
         candidate_timeout_ticks: u8,
+        grace_period: Duration,
     ) -> Arc<LeaseCollection> {
         let min_ttl = 3 * heartbeat_interval * candidate_timeout_ticks.numeric_cast() / 2;
         // Safe ceiling
         let min_ttl_secs = min_ttl
             .as_secs()
             .overflow_add(u64::from(min_ttl.subsec_nanos() > 0));
-        Arc::new(LeaseCollection::new(min_ttl_secs.numeric_cast()))
+        if grace_period > MAX_LEASE_GRACE_PERIOD {
+            warn!("lease grace period {grace_period:?} is capped at {MAX_LEASE_GRACE_PERIOD:?}");
+        }
+        Arc::new(LeaseCollection::new(
+            min_ttl_secs.numeric_cast(),
+            grace_period.min(MAX_LEASE_GRACE_PERIOD),
+        ))
     }
 
     /// Construct underlying storages, including `KvStore`, `LeaseStore`, `AuthStore`
@@ -213,11 +332,16 @@ impl XlineServer {
         Arc<KvWatcher<S>>,
     )> {
         let (compact_task_tx, compact_task_rx) = channel(COMPACT_CHANNEL_SIZE);
-        let index = Arc::new(Index::new());
+        let index = Arc::new(Index::new_with_protection(CompactProtection::new(
+            self.compact_config.protected_prefixes(),
+            *self.compact_config.protected_retention(),
+        )));
         let (kv_update_tx, kv_update_rx) = channel(CHANNEL_SIZE);
-        let kv_store_inner = Arc::new(KvStoreInner::new(
+        let prefix_stats = Arc::new(PrefixStats::new(self.kv_config.tracked_prefixes()));
+        let kv_store_inner = Arc::new(KvStoreInner::new_with_prefix_stats(
             Arc::clone(&index),
             Arc::clone(&persistent),
+            Arc::clone(&prefix_stats),
         ));
         let kv_storage = Arc::new(KvStore::new(
             Arc::clone(&kv_store_inner),
@@ -225,6 +349,13 @@ impl XlineServer {
             kv_update_tx.clone(),
             compact_task_tx,
             Arc::clone(&lease_collection),
+            KvStoreOptions {
+                noop_identical_put: *self.kv_config.noop_identical_put(),
+                range_memory_budget: *self.kv_config.range_memory_budget(),
+                bulk_load_threshold: *self.kv_config.bulk_load_threshold(),
+                max_range_result_count: *self.kv_config.max_range_result_count(),
+                range_result_overflow: *self.kv_config.range_result_overflow(),
+            },
         ));
         self.task_manager.spawn(TaskName::CompactBg, |n| {
             compact_bg_task(
@@ -232,6 +363,8 @@ impl XlineServer {
                 Arc::clone(&index),
                 *self.compact_config.compact_batch_size(),
                 *self.compact_config.compact_sleep_interval(),
+                Arc::clone(&self.maintenance_scheduler),
+                self.archive_sink.clone(),
                 compact_task_rx,
                 n,
             )
@@ -243,12 +376,19 @@ impl XlineServer {
             index,
             kv_update_tx,
             *self.cluster_config.is_leader(),
+            *self.compact_config.lease_history_retention(),
+            prefix_stats,
         ));
         let auth_storage = Arc::new(AuthStore::new(
             lease_collection,
             key_pair,
             Arc::clone(&header_gen),
             Arc::clone(&persistent),
+            *self.compact_config.auth_history_retention(),
+            *self.auth_config.token_cache_size(),
+            self.auth_config.role_quotas().clone(),
+            *self.auth_config.max_roles_per_user(),
+            *self.auth_config.max_permissions_per_role(),
         ));
         let alarm_storage = Arc::new(AlarmStore::new(header_gen, persistent));
 
@@ -283,17 +423,24 @@ impl XlineServer {
         )
     }
 
-    /// Init xline and curp router
+    /// Init xline and curp router, and the routers of the read-only and write-only
+    /// listeners with their urls
     ///
     /// # Errors
     ///
     /// Will return `Err` when `init_servers` return an error
-    #[inline]
-    pub async fn init_router<S: StorageApi>(
+    #[allow(clippy::type_complexity)] // it is easy to read
+    async fn init_router<S: StorageApi>(
         &self,
         persistent: Arc<S>,
         key_pair: Option<(EncodingKey, DecodingKey)>,
-    ) -> Result<(Router, Router, Arc<CurpClient>)> {
+    ) -> Result<(
+        Router,
+        Router,
+        Vec<(Vec<String>, Router)>,
+        Arc<CurpClient>,
+        Arc<AuthStore<S>>,
+    )> {
         let (
             kv_server,
             lock_server,
@@ -305,34 +452,142 @@ impl XlineServer {
             curp_server,
             auth_wrapper,
             curp_client,
+            apply_progress,
+            auth_storage,
         ) = self.init_servers(persistent, key_pair).await?;
         let mut builder = Server::builder();
         #[cfg(not(madsim))]
         if let Some(ref cfg) = self.server_tls_config {
             builder = builder.tls_config(cfg.clone())?;
         }
+        let (client_limits, peer_limits) = (
+            self.cluster_config.message_size().client_limits(),
+            self.cluster_config.message_size().peer_limits(),
+        );
+        let (client_send, client_recv) = (*client_limits.max_send(), *client_limits.max_recv());
+        let (peer_send, peer_recv) = (*peer_limits.max_send(), *peer_limits.max_recv());
+        let lock_service =
+            with_message_size!(RpcLockServer::new(lock_server), client_send, client_recv);
+        let kv_server = Arc::new(kv_server);
+        #[cfg(not(madsim))]
+        let range_stream_service = (*self.kv_config.range_stream_batch_size() > 0).then(|| {
+            with_message_size!(
+                RangeStreamServer::new(
+                    Arc::clone(&kv_server),
+                    *self.kv_config.range_stream_batch_size()
+                ),
+                client_send,
+                client_recv
+            )
+        });
+        let kv_service =
+            with_message_size!(RpcKvServer::from_arc(kv_server), client_send, client_recv);
+        #[cfg(not(madsim))]
+        let admin_service = with_message_size!(
+            AdminServer::new(Arc::clone(&maintenance_server), Arc::clone(&lease_server)),
+            client_send,
+            client_recv
+        );
+        let lease_service = with_message_size!(
+            RpcLeaseServer::from_arc(lease_server),
+            client_send,
+            client_recv
+        );
+        let auth_service =
+            with_message_size!(RpcAuthServer::new(auth_server), client_send, client_recv);
+        let watch_service =
+            with_message_size!(RpcWatchServer::new(watch_server), client_send, client_recv);
+        let maintenance_service = with_message_size!(
+            RpcMaintenanceServer::from_arc(maintenance_server),
+            client_send,
+            client_recv
+        );
+        let cluster_server = Arc::new(cluster_server);
+        #[cfg(not(madsim))]
+        let member_watch_service = with_message_size!(
+            MemberWatchServer::new(Arc::clone(&cluster_server)),
+            client_send,
+            client_recv
+        );
+        let cluster_service = with_message_size!(
+            RpcClusterServer::from_arc(cluster_server),
+            client_send,
+            client_recv
+        );
+        let protocol_service =
+            with_message_size!(ProtocolServer::new(auth_wrapper), client_send, client_recv);
+        let (mut reporter, health_server) = tonic_health::server::health_reporter();
+        #[cfg(not(madsim))]
+        let role_routers = [
+            (ListenerRole::Read, self.cluster_config.read_listen_urls()),
+            (ListenerRole::Write, self.cluster_config.write_listen_urls()),
+        ]
+        .into_iter()
+        .filter(|&(_, urls)| !urls.is_empty())
+        .map(|(role, urls)| {
+            let router = builder
+                .clone()
+                .add_service(ListenerGuard::new(lock_service.clone(), role))
+                .add_service(ListenerGuard::new(kv_service.clone(), role))
+                .add_service(ListenerGuard::new(lease_service.clone(), role))
+                .add_service(ListenerGuard::new(auth_service.clone(), role))
+                .add_service(ListenerGuard::new(watch_service.clone(), role))
+                .add_service(ListenerGuard::new(maintenance_service.clone(), role))
+                .add_service(ListenerGuard::new(cluster_service.clone(), role))
+                .add_service(ListenerGuard::new(protocol_service.clone(), role))
+                .add_service(ListenerGuard::new(admin_service.clone(), role))
+                .add_service(ListenerGuard::new(member_watch_service.clone(), role))
+                .add_optional_service(
+                    range_stream_service
+                        .clone()
+                        .map(|service| ListenerGuard::new(service, role)),
+                )
+                .add_service(health_server.clone());
+            (urls.clone(), router)
+        })
+        .collect();
+        #[cfg(madsim)]
+        let role_routers = Vec::new();
         let xline_router = builder
             .clone()
-            .add_service(RpcLockServer::new(lock_server))
-            .add_service(RpcKvServer::new(kv_server))
-            .add_service(RpcLeaseServer::from_arc(lease_server))
-            .add_service(RpcAuthServer::new(auth_server))
-            .add_service(RpcWatchServer::new(watch_server))
-            .add_service(RpcMaintenanceServer::new(maintenance_server))
-            .add_service(RpcClusterServer::new(cluster_server))
-            .add_service(ProtocolServer::new(auth_wrapper));
+            .add_service(lock_service)
+            .add_service(kv_service)
+            .add_service(lease_service)
+            .add_service(auth_service)
+            .add_service(watch_service)
+            .add_service(maintenance_service)
+            .add_service(cluster_service)
+            .add_service(protocol_service);
         let curp_router = builder
-            .add_service(ProtocolServer::new(curp_server.clone()))
-            .add_service(InnerProtocolServer::new(curp_server));
+            .add_service(with_message_size!(
+                ProtocolServer::new(curp_server.clone()),
+                peer_send,
+                peer_recv
+            ))
+            .add_service(with_message_size!(
+                InnerProtocolServer::new(curp_server),
+                peer_send,
+                peer_recv
+            ));
+        reporter
+            .set_service_status("", tonic_health::ServingStatus::Serving)
+            .await;
+        self.spawn_apply_watchdog(apply_progress, reporter);
         #[cfg(not(madsim))]
-        let xline_router = {
-            let (mut reporter, health_server) = tonic_health::server::health_reporter();
-            reporter
-                .set_service_status("", tonic_health::ServingStatus::Serving)
-                .await;
-            xline_router.add_service(health_server)
-        };
-        Ok((xline_router, curp_router, curp_client))
+        let xline_router = xline_router
+            .add_optional_service(range_stream_service)
+            .add_service(admin_service)
+            .add_service(member_watch_service)
+            .add_service(health_server);
+        #[cfg(madsim)]
+        drop(health_server);
+        Ok((
+            xline_router,
+            curp_router,
+            role_routers,
+            curp_client,
+            auth_storage,
+        ))
     }
 
     /// Start `XlineServer`
@@ -351,9 +606,19 @@ impl XlineServer {
             .task_manager
             .get_shutdown_listener(TaskName::TonicServer);
         let n2 = n1.clone();
-        let persistent = DB::open(&self.storage_config.engine)?;
+        let persistent = DB::open_with_options(
+            &self.storage_config.engine,
+            *self.kv_config.dedup_value_threshold(),
+            self.storage_config.sync_writes,
+            &self.storage_config.non_durable_prefixes,
+            self.storage_config
+                .encryption
+                .as_ref()
+                .map(RecordCipher::from_config)
+                .transpose()?,
+        )?;
         let key_pair = Self::read_key_pair(&self.auth_config).await?;
-        let (xline_router, curp_router, curp_client) =
+        let (xline_router, curp_router, _role_routers, curp_client, auth_storage) =
             self.init_router(persistent, key_pair).await?;
         let handle = tokio::spawn(async move {
             tokio::select! {
@@ -362,7 +627,7 @@ impl XlineServer {
             }
             Ok(())
         });
-        if let Err(e) = self.publish(curp_client).await {
+        if let Err(e) = self.publish(&curp_client, &auth_storage).await {
             warn!("publish name to cluster failed: {:?}", e);
         };
         Ok(handle)
@@ -378,10 +643,53 @@ impl XlineServer {
         IO::ConnectInfo: Clone + Send + Sync + 'static,
         IE: Into<Box<dyn std::error::Error + Send + Sync>> + Send,
     {
-        let persistent = DB::open(&self.storage_config.engine)?;
+        let persistent = DB::open_with_options(
+            &self.storage_config.engine,
+            *self.kv_config.dedup_value_threshold(),
+            self.storage_config.sync_writes,
+            &self.storage_config.non_durable_prefixes,
+            self.storage_config
+                .encryption
+                .as_ref()
+                .map(RecordCipher::from_config)
+                .transpose()?,
+        )?;
         let key_pair = Self::read_key_pair(&self.auth_config).await?;
-        let (xline_router, curp_router, curp_client) =
+        let (xline_router, curp_router, role_routers, curp_client, auth_storage) =
             self.init_router(persistent, key_pair).await?;
+        self.serve_role_routers(role_routers)?;
+        if let Some(ref config) = self.restricted_tls_config {
+            let acceptor = TlsAcceptor::from(Arc::clone(config));
+            self.serve_routers(
+                xline_router,
+                curp_router,
+                tls::tls_incoming(xline_incoming, acceptor.clone()),
+                tls::tls_incoming(curp_incoming, acceptor),
+            );
+        } else {
+            self.serve_routers(xline_router, curp_router, xline_incoming, curp_incoming);
+        }
+        if let Err(e) = self.publish(&curp_client, &auth_storage).await {
+            warn!("publish name to cluster failed: {e:?}");
+        };
+        Ok(())
+    }
+
+    /// Serve the routers on the incoming connections until shutdown
+    #[cfg(not(madsim))]
+    fn serve_routers<I1, I2, IO, IE>(
+        &self,
+        xline_router: Router,
+        curp_router: Router,
+        xline_incoming: I1,
+        curp_incoming: I2,
+    ) where
+        I1: Stream<Item = Result<IO, IE>> + Send + 'static,
+        I2: Stream<Item = Result<IO, IE>> + Send + 'static,
+        IO: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
+        IO::ConnectInfo: Clone + Send + Sync + 'static,
+        IE: Into<Box<dyn std::error::Error + Send + Sync>> + Send,
+    {
         self.task_manager
             .spawn(TaskName::TonicServer, |n1| async move {
                 let n2 = n1.clone();
@@ -390,12 +698,42 @@ impl XlineServer {
                     _ = curp_router.serve_with_incoming_shutdown(curp_incoming, n2.wait()) => {},
                 }
             });
-        if let Err(e) = self.publish(curp_client).await {
-            warn!("publish name to cluster failed: {e:?}");
-        };
+    }
+
+    /// Serve the routers of the read-only and write-only listeners on their urls until
+    /// shutdown
+    #[cfg(not(madsim))]
+    fn serve_role_routers(&self, role_routers: Vec<(Vec<String>, Router)>) -> Result<()> {
+        for (urls, router) in role_routers {
+            let incoming = bind_addrs(&urls)?;
+            info!("start xline role listener on {:?}", urls);
+            if let Some(ref config) = self.restricted_tls_config {
+                let acceptor = TlsAcceptor::from(Arc::clone(config));
+                self.serve_router(router, tls::tls_incoming(incoming, acceptor));
+            } else {
+                self.serve_router(router, incoming);
+            }
+        }
         Ok(())
     }
 
+    /// Serve a router on the incoming connections until shutdown
+    #[cfg(not(madsim))]
+    fn serve_router<I, IO, IE>(&self, router: Router, incoming: I)
+    where
+        I: Stream<Item = Result<IO, IE>> + Send + 'static,
+        IO: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
+        IO::ConnectInfo: Clone + Send + Sync + 'static,
+        IE: Into<Box<dyn std::error::Error + Send + Sync>> + Send,
+    {
+        self.task_manager
+            .spawn(TaskName::TonicServer, |n| async move {
+                let _ignore = router
+                    .serve_with_incoming_shutdown(incoming, n.wait())
+                    .await;
+            });
+    }
+
     /// Start `XlineServer`
     ///
     /// # Errors
@@ -445,16 +783,19 @@ impl XlineServer {
         Arc<LeaseServer<S>>,
         AuthServer<S>,
         WatchServer<S>,
-        MaintenanceServer<S>,
-        ClusterServer,
+        Arc<MaintenanceServer<S>>,
+        ClusterServer<S>,
         CurpServer<S>,
         AuthWrapper<S>,
         Arc<CurpClient>,
+        Arc<dyn ApplyProgress>,
+        Arc<AuthStore<S>>,
     )> {
         let (header_gen, id_gen) = Self::construct_generator(&self.cluster_info);
         let lease_collection = Self::construct_lease_collection(
             self.cluster_config.curp_config().heartbeat_interval,
             self.cluster_config.curp_config().candidate_timeout_ticks,
+            *self.cluster_config.server_timeout().lease_grace_period(),
         );
 
         let (kv_storage, lease_storage, auth_storage, alarm_storage, watcher) = self
@@ -481,6 +822,8 @@ impl XlineServer {
             header_gen.auth_revision_arc(),
             Arc::clone(&compact_events),
             self.storage_config.quota,
+            Arc::clone(&self.maintenance_scheduler),
+            self.command_hooks.clone(),
         ));
         let snapshot_allocator: Box<dyn SnapshotAllocator> = match self.storage_config.engine {
             EngineConfig::Memory => Box::<MemorySnapshotAllocator>::default(),
@@ -496,6 +839,12 @@ impl XlineServer {
                         *self.cluster_config.is_leader(),
                         header_gen.general_revision_arc(),
                         auto_config_cfg,
+                        self.compact_config.watch_safety_floor().then(|| {
+                            WatchFloor::new(
+                                Arc::clone(&watcher) as Arc<dyn ActiveWatches>,
+                                *self.compact_config.watch_safety_margin(),
+                            )
+                        }),
                         Arc::clone(&self.task_manager),
                     )
                     .await,
@@ -520,6 +869,7 @@ impl XlineServer {
             Arc::clone(&self.curp_storage),
             Arc::clone(&self.task_manager),
             self.client_tls_config.clone(),
+            self.cluster_config.message_size().peer_limits(),
             XlineSpeculativePools::default().into_inner(),
             XlineUncommittedPools::default().into_inner(),
         )
@@ -528,6 +878,7 @@ impl XlineServer {
         let client = Arc::new(
             CurpClientBuilder::new(*self.cluster_config.client_config(), false)
                 .tls_config(self.client_tls_config.clone())
+                .message_size(self.cluster_config.message_size().peer_limits())
                 .cluster_version(self.cluster_info.cluster_version())
                 .all_members(self.cluster_info.all_members_peer_urls())
                 .bypass(self.cluster_info.self_id(), curp_server.clone())
@@ -538,25 +889,63 @@ impl XlineServer {
         if let Some(compactor) = auto_compactor_c {
             compactor.set_compactable(Arc::clone(&client)).await;
         }
-        ce.set_alarmer(Alarmer::new(
-            self.cluster_info.self_id(),
-            Arc::clone(&client),
-        ));
+        let alarmer = Alarmer::new(self.cluster_info.self_id(), Arc::clone(&client));
+        ce.set_alarmer(alarmer.clone());
         let raw_curp = curp_server.raw_curp();
+        self.spawn_auto_defrag(
+            Arc::clone(&raw_curp),
+            Arc::clone(&client),
+            Arc::clone(&auth_storage),
+        );
+        self.spawn_index_check(Arc::clone(&kv_storage), alarmer);
 
-        Metrics::register_callback()?;
+        Metrics::register_callback(kv_storage.prefix_stats())?;
 
         let server_timeout = self.cluster_config.server_timeout();
+        let apply_progress: Arc<dyn ApplyProgress> = Arc::new(CurpApplyProgress::new(
+            Arc::clone(&raw_curp),
+            Arc::clone(&index_barrier),
+        ));
+        let read_index_waiter = Arc::new(ReadIndexWaiter::new(
+            Arc::clone(&client),
+            index_barrier,
+            id_barrier,
+            *server_timeout.range_retry_timeout(),
+            *server_timeout.read_index_batch_window(),
+            *server_timeout.catch_up_read_timeout(),
+        ));
+        let user_stats = Arc::new(UserStats::default());
         Ok((
             KvServer::new(
                 Arc::clone(&kv_storage),
                 Arc::clone(&auth_storage),
-                index_barrier,
-                id_barrier,
-                *server_timeout.range_retry_timeout(),
+                Arc::clone(&read_index_waiter),
                 *server_timeout.compact_timeout(),
                 Arc::clone(&client),
                 compact_events,
+                Arc::clone(&self.cluster_info),
+                Arc::clone(&raw_curp),
+                *self.kv_config.reject_empty_value_put(),
+                *self.kv_config.forward_writes_to_leader(),
+                self.client_tls_config.clone(),
+                self.cluster_config.message_size().client_limits(),
+                *self.kv_config.max_write_coalescing_window(),
+                *self.kv_config.snapshot_install_reads(),
+                *self.kv_config.key_value_encoding(),
+                *self.kv_config.leaderless_reads(),
+                *self.kv_config.leaderless_read_timeout(),
+                self.kv_config.guarded_prefixes(),
+                self.kv_config.lease_guarded_prefixes(),
+                *self.kv_config.track_last_access(),
+                *self.kv_config.report_apply_latency(),
+                *self.kv_config.max_request_order_wait(),
+                self.value_validators.clone(),
+                *self.kv_config.key_charset(),
+                self.kv_config.key_charset_prefixes(),
+                *self.kv_config.max_inflight_proposals(),
+                self.kv_config.immutable_prefixes(),
+                self.archive_sink.clone(),
+                Arc::clone(&user_stats),
             ),
             LockServer::new(
                 Arc::clone(&client),
@@ -566,48 +955,162 @@ impl XlineServer {
                 self.client_tls_config.as_ref(),
             ),
             LeaseServer::new(
-                lease_storage,
+                Arc::clone(&lease_storage),
                 Arc::clone(&auth_storage),
                 Arc::clone(&client),
                 id_gen,
                 Arc::clone(&self.cluster_info),
                 self.client_tls_config.clone(),
+                *server_timeout.lease_keep_alive_send_timeout(),
                 &self.task_manager,
             ),
-            AuthServer::new(Arc::clone(&client), Arc::clone(&auth_storage)),
+            AuthServer::new(
+                Arc::clone(&client),
+                Arc::clone(&auth_storage),
+                *self.auth_config.password_hash_rounds(),
+                *self.auth_config.rehash_passwords_on_login(),
+                self.auth_backend.clone(),
+            ),
             WatchServer::new(
-                watcher,
+                Arc::clone(&watcher),
                 Arc::clone(&header_gen),
                 *server_timeout.watch_progress_notify_interval(),
+                self.watch_config
+                    .linearizable_watch_create()
+                    .then_some(read_index_waiter),
+                *self.watch_config.history_replay(),
+                ResponseSplitter::new(
+                    *self
+                        .cluster_config
+                        .message_size()
+                        .client_limits()
+                        .max_send(),
+                    *self.watch_config.oversized_event(),
+                ),
+                (
+                    *self.watch_config.min_buffer_depth(),
+                    *self.watch_config.max_buffer_depth(),
+                ),
+                lease_storage.lease_events(),
                 Arc::clone(&self.task_manager),
             ),
-            MaintenanceServer::new(
-                kv_storage,
+            Arc::new(MaintenanceServer::new(
+                Arc::clone(&kv_storage),
                 Arc::clone(&auth_storage),
+                Arc::clone(&lease_storage),
                 Arc::clone(&client),
                 persistent,
                 Arc::clone(&header_gen),
                 Arc::clone(&self.cluster_info),
-                raw_curp,
+                Arc::clone(&raw_curp),
                 ce,
                 alarm_storage,
+                Arc::clone(&self.maintenance_scheduler),
+                match self.storage_config.engine {
+                    EngineConfig::RocksDB(ref path) => Some(path.clone()),
+                    _ => None,
+                },
+                watcher,
+                user_stats,
+            )),
+            ClusterServer::new(
+                Arc::clone(&client),
+                kv_storage,
+                Arc::clone(&auth_storage),
+                raw_curp,
+                header_gen,
+                Arc::clone(&self.cluster_info),
+                self.client_tls_config.clone(),
             ),
-            ClusterServer::new(Arc::clone(&client), header_gen),
             curp_server.clone(),
-            AuthWrapper::new(curp_server, auth_storage),
+            AuthWrapper::new(
+                curp_server,
+                Arc::clone(&auth_storage),
+                *self.kv_config.min_healthy_voters(),
+            ),
             client,
+            apply_progress,
+            auth_storage,
         ))
     }
 
-    /// Publish the name of current node to cluster
-    async fn publish(&self, curp_client: Arc<CurpClient>) -> Result<(), tonic::Status> {
+    /// Spawn the automatic defragmentation if it is configured
+    fn spawn_auto_defrag<S: StorageApi>(
+        &self,
+        raw_curp: Arc<RawCurp<Command, State<S, Arc<CurpClient>>>>,
+        client: Arc<CurpClient>,
+        auth_store: Arc<AuthStore<S>>,
+    ) {
+        let Some(config) = self.storage_config.auto_defrag else {
+            return;
+        };
+        let cluster: Arc<dyn DefragCluster> = Arc::new(CurpDefragCluster::new(
+            Arc::clone(&self.cluster_info),
+            raw_curp,
+            client,
+            self.client_tls_config.clone(),
+            auth_store,
+        ));
+        self.task_manager.spawn(TaskName::AutoDefrag, |n| {
+            run_auto_defrag(cluster, config, n)
+        });
+    }
+
+    /// Spawn the periodic check of the index against the backend if it is configured,
+    /// a mismatch raises a CORRUPT alarm of the current node
+    fn spawn_index_check<S: StorageApi>(&self, kv_storage: Arc<KvStore<S>>, alarmer: Alarmer) {
+        let Some(config) = self.storage_config.index_check else {
+            return;
+        };
+        let on_mismatch = move |_keys: Vec<Vec<u8>>| {
+            let alarmer = alarmer.clone();
+            async move { alarmer.activate(AlarmType::Corrupt).await }
+        };
+        self.task_manager.spawn(TaskName::IndexCheck, |n| {
+            run_index_check(kv_storage, config, on_mismatch, n)
+        });
+    }
+
+    /// Spawn the apply watchdog which reports the node as not serving while the
+    /// apply stalls, it is disabled by a zero threshold
+    fn spawn_apply_watchdog(
+        &self,
+        progress: Arc<dyn ApplyProgress>,
+        reporter: tonic_health::server::HealthReporter,
+    ) {
+        let threshold = *self.cluster_config.server_timeout().apply_stall_threshold();
+        if threshold.is_zero() {
+            return;
+        }
+        let on_transition = move |health| {
+            let mut reporter = reporter.clone();
+            async move {
+                reporter
+                    .set_service_status("", serving_status(health))
+                    .await;
+            }
+        };
+        self.task_manager.spawn(TaskName::ApplyWatchdog, |n| {
+            run_apply_watchdog(progress, threshold, on_transition, n)
+        });
+    }
+
+    /// Publish the name and the tags of current node to cluster
+    async fn publish<S: StorageApi>(
+        &self,
+        curp_client: &CurpClient,
+        auth_store: &AuthStore<S>,
+    ) -> Result<(), tonic::Status> {
         curp_client
             .propose_publish(
                 self.cluster_info.self_id(),
                 self.cluster_info.self_name(),
                 self.cluster_info.self_client_urls(),
             )
-            .await
+            .await?;
+        let request =
+            member_tags::publish_request(self.cluster_info.self_id(), self.cluster_config.tags());
+        member_tags::propose(curp_client, auth_store, request).await
     }
 
     /// Stop `XlineServer`
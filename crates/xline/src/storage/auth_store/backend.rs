@@ -47,6 +47,11 @@ where
         Self { db }
     }
 
+    /// Get the db of the backend
+    pub(crate) fn db(&self) -> &DB {
+        &self.db
+    }
+
     /// get user by username
     pub(crate) fn get_user(&self, username: &str) -> Result<User, ExecuteError> {
         match self.db.get_value(USER_TABLE, username)? {
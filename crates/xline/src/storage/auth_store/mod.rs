@@ -2,8 +2,11 @@
 mod backend;
 /// Structs for permission
 mod perms;
+/// Quotas of roles
+mod quota;
 /// Storage for auth
 mod store;
 
 pub(crate) use backend::{AUTH_ENABLE_KEY, AUTH_REVISION_KEY};
+pub(crate) use quota::KeyUsageSource;
 pub(crate) use store::AuthStore;
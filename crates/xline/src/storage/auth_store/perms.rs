@@ -1,4 +1,7 @@
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+};
 
 use jsonwebtoken::{
     errors::Error as JwtError, Algorithm, DecodingKey, EncodingKey, Header, Validation,
@@ -158,3 +161,179 @@ impl PermissionCache {
         }
     }
 }
+
+/// A validated token in the `TokenCache`
+#[derive(Debug, Clone)]
+struct CachedToken {
+    /// Auth info carried by the token
+    auth_info: AuthInfo,
+    /// Expiration of the token
+    exp: u64,
+    /// Tick of the last access
+    tick: u64,
+}
+
+/// A bounded LRU cache of validated tokens, so that the signature of a token is
+/// not verified on every request.
+///
+/// The whole cache is dropped when the auth revision changes, and an expired
+/// token is never served from it even if it is cached.
+#[derive(Debug)]
+pub(super) struct TokenCache {
+    /// The maximum number of tokens, 0 disables the cache
+    capacity: usize,
+    /// The auth revision at which the tokens are cached
+    revision: i64,
+    /// Cached tokens
+    tokens: HashMap<String, CachedToken>,
+    /// Tokens ordered by their last access
+    lru: BTreeMap<u64, String>,
+    /// Tick of the next access
+    next_tick: u64,
+    /// Number of lookups served from the cache
+    hits: u64,
+}
+
+impl TokenCache {
+    /// New `TokenCache`
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            revision: 0,
+            tokens: HashMap::new(),
+            lru: BTreeMap::new(),
+            next_tick: 0,
+            hits: 0,
+        }
+    }
+
+    /// Get the auth info of a cached token which is valid at `now` and the
+    /// current auth `revision`
+    pub(super) fn get(&mut self, token: &str, now: u64, revision: i64) -> Option<AuthInfo> {
+        self.sync_revision(revision);
+        let tick = self.tick();
+        let cached = self.tokens.get_mut(token)?;
+        if cached.exp <= now {
+            let _ignore = self.lru.remove(&cached.tick);
+            let _prev = self.tokens.remove(token);
+            return None;
+        }
+        let _ignore = self.lru.remove(&cached.tick);
+        cached.tick = tick;
+        let auth_info = cached.auth_info.clone();
+        let _prev = self.lru.insert(tick, token.to_owned());
+        self.hits = self.hits.wrapping_add(1);
+        Some(auth_info)
+    }
+
+    /// Cache a validated token, the least recently used one is evicted when
+    /// the cache is full
+    pub(super) fn insert(&mut self, token: &str, claims: &TokenClaims, now: u64, revision: i64) {
+        if self.capacity == 0 || claims.exp <= now {
+            return;
+        }
+        self.sync_revision(revision);
+        if let Some(cached) = self.tokens.remove(token) {
+            let _ignore = self.lru.remove(&cached.tick);
+        }
+        while self.tokens.len() >= self.capacity {
+            let Some((_, evicted)) = self.lru.pop_first() else {
+                break;
+            };
+            let _ignore = self.tokens.remove(&evicted);
+        }
+        let tick = self.tick();
+        let cached = CachedToken {
+            auth_info: AuthInfo {
+                username: claims.username.clone(),
+                auth_revision: claims.revision,
+            },
+            exp: claims.exp,
+            tick,
+        };
+        let _prev = self.tokens.insert(token.to_owned(), cached);
+        let _prev = self.lru.insert(tick, token.to_owned());
+    }
+
+    /// Remove the tokens of a user
+    pub(super) fn remove_user(&mut self, username: &str) {
+        let lru = &mut self.lru;
+        self.tokens.retain(|_, cached| {
+            let keep = cached.auth_info.username != username;
+            if !keep {
+                let _ignore = lru.remove(&cached.tick);
+            }
+            keep
+        });
+    }
+
+    /// Remove all tokens
+    pub(super) fn clear(&mut self) {
+        self.tokens.clear();
+        self.lru.clear();
+    }
+
+    /// Number of lookups served from the cache
+    #[cfg(test)]
+    pub(super) fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Drop the tokens cached at another auth revision
+    fn sync_revision(&mut self, revision: i64) {
+        if self.revision != revision {
+            self.clear();
+            self.revision = revision;
+        }
+    }
+
+    /// Get the tick of an access
+    fn tick(&mut self) -> u64 {
+        let tick = self.next_tick;
+        self.next_tick = self.next_tick.wrapping_add(1);
+        tick
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn claims(username: &str, exp: u64) -> TokenClaims {
+        TokenClaims {
+            username: username.to_owned(),
+            revision: 1,
+            exp,
+        }
+    }
+
+    #[test]
+    fn token_cache_should_not_serve_invalid_tokens() {
+        let mut cache = TokenCache::new(2);
+        cache.insert("t1", &claims("u1", 100), 10, 1);
+        assert_eq!(cache.get("t1", 10, 1).unwrap().username, "u1");
+        assert!(cache.get("t1", 100, 1).is_none());
+
+        cache.insert("t2", &claims("u2", 100), 10, 1);
+        assert!(cache.get("t2", 10, 2).is_none());
+
+        cache.insert("t3", &claims("u3", 5), 10, 2);
+        assert!(cache.get("t3", 10, 2).is_none());
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn token_cache_should_evict_the_least_recently_used() {
+        let mut cache = TokenCache::new(2);
+        cache.insert("t1", &claims("u1", 100), 10, 1);
+        cache.insert("t2", &claims("u2", 100), 10, 1);
+        assert!(cache.get("t1", 10, 1).is_some());
+        cache.insert("t3", &claims("u3", 100), 10, 1);
+        assert!(cache.get("t2", 10, 1).is_none());
+        assert!(cache.get("t1", 10, 1).is_some());
+        assert!(cache.get("t3", 10, 1).is_some());
+
+        cache.remove_user("u1");
+        assert!(cache.get("t1", 10, 1).is_none());
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use clippy_utilities::NumericCast;
+use utils::config::RoleQuota;
+use xlineapi::{command::KeyRange, execute_error::ExecuteError};
+
+use crate::rpc::{PutRequest, Request, RequestWrapper, Role, Type};
+
+/// The window in which the writes of a role are counted against its write rate
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Source of the live keys counted against the quotas of roles
+pub(crate) trait KeyUsageSource {
+    /// Get the live keys of a range and their sizes, the size of a key is the
+    /// bytes of the key and its value
+    fn key_sizes(&self, range: &KeyRange) -> Result<Vec<(Vec<u8>, u64)>, ExecuteError>;
+}
+
+/// Get the ranges that a role is permitted to write
+pub(super) fn write_ranges(role: &Role) -> Vec<KeyRange> {
+    role.key_permission
+        .iter()
+        .filter(|perm| {
+            matches!(
+                Type::try_from(perm.perm_type),
+                Ok(Type::Write | Type::Readwrite)
+            )
+        })
+        .map(|perm| KeyRange::new(perm.key.clone(), perm.range_end.clone()))
+        .collect()
+}
+
+/// Get the puts of a write request by their keys. The puts in both branches of
+/// a txn are collected, since which one is taken is unknown before it executes,
+/// and the largest put of a key is kept.
+pub(super) fn collect_puts(wrapper: &RequestWrapper) -> HashMap<&[u8], &PutRequest> {
+    let mut puts: HashMap<&[u8], &PutRequest> = HashMap::new();
+    let mut queue = VecDeque::new();
+    #[allow(clippy::wildcard_enum_match_arm)]
+    match *wrapper {
+        RequestWrapper::PutRequest(ref req) => {
+            let _prev = puts.insert(req.key.as_slice(), req);
+        }
+        RequestWrapper::TxnRequest(ref req) => queue.push_back(req),
+        _ => {}
+    }
+    while let Some(txn) = queue.pop_front() {
+        let requests = txn
+            .success
+            .iter()
+            .chain(txn.failure.iter())
+            .filter_map(|op| op.request.as_ref());
+        for request in requests {
+            match *request {
+                Request::RequestPut(ref req) => {
+                    let kept = puts.entry(req.key.as_slice()).or_insert(req);
+                    if req.value.len() > kept.value.len() {
+                        *kept = req;
+                    }
+                }
+                Request::RequestTxn(ref req) => queue.push_back(req),
+                Request::RequestRange(_) | Request::RequestDeleteRange(_) => {}
+            }
+        }
+    }
+    puts
+}
+
+/// Check whether the puts fit in the key and byte limits of a role whose
+/// write-permitted ranges are `ranges`.
+///
+/// The usage is derived from the applied kv state when the write is applied, and the
+/// writes of a role conflict with each other, so every node agrees on it. A write
+/// which doesn't grow the usage is always allowed, so that a role over its limits
+/// can still overwrite its keys with smaller values.
+pub(super) fn check_usage(
+    role: &str,
+    quota: &RoleQuota,
+    ranges: &[KeyRange],
+    puts: &HashMap<&[u8], &PutRequest>,
+    source: &dyn KeyUsageSource,
+) -> Result<(), ExecuteError> {
+    if *quota.max_keys() == 0 && *quota.max_bytes() == 0 {
+        return Ok(());
+    }
+    let puts: Vec<_> = puts
+        .iter()
+        .filter(|&(key, _)| ranges.iter().any(|range| range.contains_key(key)))
+        .collect();
+    if puts.is_empty() {
+        return Ok(());
+    }
+    let mut usage = HashMap::new();
+    for range in ranges {
+        usage.extend(source.key_sizes(range)?);
+    }
+    let used_bytes = usage
+        .values()
+        .fold(0_u64, |sum, size| sum.saturating_add(*size));
+    let (mut new_keys, mut bytes) = (0_u64, used_bytes);
+    for (key, put) in puts {
+        let old_size = usage.get(*key).copied();
+        let key_size: u64 = key.len().numeric_cast();
+        let new_size = if put.ignore_value {
+            old_size.unwrap_or(key_size)
+        } else {
+            key_size.saturating_add(put.value.len().numeric_cast())
+        };
+        if old_size.is_none() {
+            new_keys = new_keys.saturating_add(1);
+        }
+        bytes = bytes
+            .saturating_sub(old_size.unwrap_or(0))
+            .saturating_add(new_size);
+    }
+    let keys = usage.len().numeric_cast::<u64>().saturating_add(new_keys);
+    let exceeds_keys = *quota.max_keys() != 0 && new_keys > 0 && keys > *quota.max_keys();
+    let exceeds_bytes = *quota.max_bytes() != 0 && bytes > used_bytes && bytes > *quota.max_bytes();
+    if exceeds_keys || exceeds_bytes {
+        return Err(ExecuteError::RoleQuotaExceeded(role.to_owned()));
+    }
+    Ok(())
+}
+
+/// Limits the write rates of roles in fixed windows.
+///
+/// The rates are limited by every serving node separately, a client proposing to
+/// all nodes is counted by each of them once per write.
+#[derive(Debug, Default)]
+pub(super) struct WriteRateLimiter {
+    /// The start of the current window and the writes in it of every role
+    windows: HashMap<String, (Instant, u64)>,
+}
+
+impl WriteRateLimiter {
+    /// Take a write of every role at `now` given their rates, nothing is taken
+    /// if the rate of any role is exceeded
+    pub(super) fn acquire(
+        &mut self,
+        rates: &[(&str, u64)],
+        now: Instant,
+    ) -> Result<(), ExecuteError> {
+        for &(role, rate) in rates {
+            if let Some(&(start, writes)) = self.windows.get(role) {
+                if now.saturating_duration_since(start) < RATE_WINDOW && writes >= rate {
+                    return Err(ExecuteError::RoleQuotaExceeded(role.to_owned()));
+                }
+            }
+        }
+        for &(role, _) in rates {
+            let window = self.windows.entry(role.to_owned()).or_insert((now, 0));
+            if now.saturating_duration_since(window.0) >= RATE_WINDOW {
+                *window = (now, 0);
+            }
+            window.1 = window.1.saturating_add(1);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::{RequestOp, TxnRequest};
+
+    /// A usage source of a fixed set of keys
+    struct FixedUsage(Vec<(Vec<u8>, u64)>);
+
+    impl KeyUsageSource for FixedUsage {
+        fn key_sizes(&self, range: &KeyRange) -> Result<Vec<(Vec<u8>, u64)>, ExecuteError> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|&(key, _)| range.contains_key(key))
+                .cloned()
+                .collect())
+        }
+    }
+
+    fn put(key: &str, value: &str) -> PutRequest {
+        PutRequest {
+            key: key.into(),
+            value: value.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn usage_should_be_limited_within_the_ranges_of_the_role() {
+        let usage = FixedUsage(vec![(b"a/1".to_vec(), 4), (b"b/1".to_vec(), 4)]);
+        let ranges = vec![KeyRange::new("a/", "a0")];
+        let quota = RoleQuota::new(1, 0, 0);
+        let check = |req: PutRequest| {
+            let wrapper = RequestWrapper::from(req);
+            check_usage("r", &quota, &ranges, &collect_puts(&wrapper), &usage)
+        };
+        // overwriting an existing key doesn't grow the key count
+        assert!(check(put("a/1", "v")).is_ok());
+        assert!(matches!(
+            check(put("a/2", "v")),
+            Err(ExecuteError::RoleQuotaExceeded(ref role)) if role == "r"
+        ));
+        // out of the ranges of the role
+        assert!(check(put("b/2", "v")).is_ok());
+
+        let quota = RoleQuota::new(0, 8, 0);
+        let txn = RequestWrapper::from(TxnRequest {
+            success: vec![RequestOp {
+                request: Some(Request::RequestPut(put("a/1", "1234"))),
+            }],
+            ..Default::default()
+        });
+        assert!(check_usage("r", &quota, &ranges, &collect_puts(&txn), &usage).is_ok());
+        let txn = RequestWrapper::from(TxnRequest {
+            failure: vec![RequestOp {
+                request: Some(Request::RequestPut(put("a/1", "12345678"))),
+            }],
+            ..Default::default()
+        });
+        assert!(check_usage("r", &quota, &ranges, &collect_puts(&txn), &usage).is_err());
+    }
+
+    #[test]
+    fn write_rate_should_be_limited_in_windows() {
+        let mut limiter = WriteRateLimiter::default();
+        let start = Instant::now();
+        assert!(limiter.acquire(&[("a", 2), ("b", 1)], start).is_ok());
+        // b is exhausted, nothing is taken from a
+        assert!(limiter.acquire(&[("a", 2), ("b", 1)], start).is_err());
+        assert!(limiter.acquire(&[("a", 2)], start).is_ok());
+        assert!(limiter.acquire(&[("a", 2)], start).is_err());
+        assert!(limiter
+            .acquire(&[("a", 2), ("b", 1)], start + RATE_WINDOW)
+            .is_ok());
+    }
+}
@@ -1,31 +1,34 @@
 use std::{
     cmp::Ordering,
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, VecDeque},
     sync::{
         atomic::{AtomicBool, Ordering as AtomicOrdering},
         Arc,
     },
+    time::Instant,
 };
 
 use clippy_utilities::NumericCast;
 use itertools::Itertools;
 use jsonwebtoken::{DecodingKey, EncodingKey};
 use log::debug;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use pbkdf2::{
     password_hash::{PasswordHash, PasswordVerifier},
     Pbkdf2,
 };
-use utils::parking_lot_lock::RwLockMap;
+use utils::{config::RoleQuota, parking_lot_lock::RwLockMap, timestamp};
 use xlineapi::{
     command::{CommandResponse, KeyRange, SyncResponse},
     execute_error::ExecuteError,
+    server_op::{RehashPasswordOp, RehashPasswordOpResult, ServerOp, ServerOpResult},
     AuthInfo,
 };
 
 use super::{
     backend::{ROOT_ROLE, ROOT_USER},
-    perms::{JwtTokenManager, PermissionCache, TokenOperate, UserPermissions},
+    perms::{JwtTokenManager, PermissionCache, TokenCache, TokenOperate, UserPermissions},
+    quota::{self, KeyUsageSource, WriteRateLimiter},
 };
 use crate::{
     header_gen::HeaderGenerator,
@@ -42,17 +45,22 @@ use crate::{
         AuthUserGrantRoleResponse, AuthUserListRequest, AuthUserListResponse,
         AuthUserRevokeRoleRequest, AuthUserRevokeRoleResponse, AuthenticateRequest,
         AuthenticateResponse, DeleteRangeRequest, LeaseRevokeRequest, Permission, PutRequest,
-        RangeRequest, Request, RequestOp, RequestWrapper, Role, TxnRequest, Type, User,
+        RangeRequest, Request, RequestOp, RequestWrapper, Role, TxnRequest, TxnResponse, Type,
+        User,
     },
     server::get_token,
     storage::{
         auth_store::backend::AuthStoreBackend,
         db::WriteOp,
+        history::{ChangeHistory, HistoryReport},
         lease_store::{Lease, LeaseCollection},
         storage_api::StorageApi,
     },
 };
 
+/// The name of the auth change history
+const AUTH_HISTORY: &str = "auth_history";
+
 /// Auth store
 #[derive(Debug)]
 pub(crate) struct AuthStore<S>
@@ -73,6 +81,18 @@ where
     permission_cache: RwLock<PermissionCache>,
     /// The manager of token
     token_manager: Option<JwtTokenManager>,
+    /// Cache of validated tokens
+    token_cache: Mutex<TokenCache>,
+    /// Auth change history
+    history: ChangeHistory<RequestWrapper>,
+    /// The quotas of roles
+    role_quotas: HashMap<String, RoleQuota>,
+    /// The limiter of the write rates of roles
+    write_rate_limiter: Mutex<WriteRateLimiter>,
+    /// The maximum number of roles granted to a user, 0 means unlimited
+    max_roles_per_user: usize,
+    /// The maximum number of permissions granted to a role, 0 means unlimited
+    max_permissions_per_role: usize,
 }
 
 impl<S> AuthStore<S>
@@ -81,11 +101,17 @@ where
 {
     /// New `AuthStore`
     #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         lease_collection: Arc<LeaseCollection>,
         key_pair: Option<(EncodingKey, DecodingKey)>,
         header_gen: Arc<HeaderGenerator>,
         storage: Arc<S>,
+        history_retention: usize,
+        token_cache_size: usize,
+        role_quotas: HashMap<String, RoleQuota>,
+        max_roles_per_user: usize,
+        max_permissions_per_role: usize,
     ) -> Self {
         let backend = Arc::new(AuthStoreBackend::new(storage));
         Self {
@@ -98,7 +124,50 @@ where
             token_manager: key_pair.map(|(encoding_key, decoding_key)| {
                 JwtTokenManager::new(encoding_key, decoding_key)
             }),
+            token_cache: Mutex::new(TokenCache::new(token_cache_size)),
+            history: ChangeHistory::new(AUTH_HISTORY, history_retention),
+            role_quotas: role_quotas
+                .into_iter()
+                .filter(|(_, quota)| !quota.is_unlimited())
+                .collect(),
+            write_rate_limiter: Mutex::new(WriteRateLimiter::default()),
+            max_roles_per_user,
+            max_permissions_per_role,
+        }
+    }
+
+    /// Get the latest `limit` changes of the auth change history
+    pub(crate) fn history(&self, limit: usize) -> HistoryReport<RequestWrapper> {
+        self.history.report(limit)
+    }
+
+    /// Record an auth change into the history, passwords are never recorded, return
+    /// the write ops persisting the history
+    fn record_history(&self, request: &RequestWrapper) -> Vec<WriteOp<'static>> {
+        if !self.history.is_enabled()
+            || request.is_auth_read_request()
+            // a password re-hash keeps the credentials of the user
+            || matches!(
+                *request,
+                RequestWrapper::AuthenticateRequest(_) | RequestWrapper::TxnRequest(_)
+            )
+        {
+            return Vec::new();
         }
+        let mut change = request.clone();
+        #[allow(clippy::wildcard_enum_match_arm)]
+        match change {
+            RequestWrapper::AuthUserAddRequest(ref mut req) => {
+                req.password.clear();
+                req.hashed_password.clear();
+            }
+            RequestWrapper::AuthUserChangePasswordRequest(ref mut req) => {
+                req.password.clear();
+                req.hashed_password.clear();
+            }
+            _ => {}
+        }
+        self.history.record(change)
     }
 
     /// Get Lease by lease id
@@ -123,13 +192,21 @@ where
 
     /// verify token
     pub(crate) fn verify(&self, token: &str) -> Result<AuthInfo, ExecuteError> {
-        match self.token_manager {
-            Some(ref token_manager) => token_manager
-                .verify(token)
-                .map(Into::into)
-                .map_err(|_ignore| ExecuteError::InvalidAuthToken),
-            None => Err(ExecuteError::TokenManagerNotInit),
+        let Some(ref token_manager) = self.token_manager else {
+            return Err(ExecuteError::TokenManagerNotInit);
+        };
+        let now = timestamp();
+        let revision = self.revision();
+        if let Some(auth_info) = self.token_cache.lock().get(token, now, revision) {
+            return Ok(auth_info);
         }
+        let claims = token_manager
+            .verify(token)
+            .map_err(|_ignore| ExecuteError::InvalidAuthToken)?;
+        self.token_cache
+            .lock()
+            .insert(token, &claims, now, revision);
+        Ok(claims.into())
     }
 
     /// Try get auth info from tonic request
@@ -154,6 +231,21 @@ where
         Ok(None)
     }
 
+    /// Check if the user of the tonic request has admin permission
+    pub(crate) fn check_admin_request<T>(
+        &self,
+        request: &tonic::Request<T>,
+    ) -> Result<(), tonic::Status> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let Some(auth_info) = self.try_get_auth_info_from_request(request)? else {
+            return Err(ExecuteError::PermissionDenied.into());
+        };
+        self.check_admin_permission(&auth_info.username)?;
+        Ok(())
+    }
+
     /// create permission cache
     fn create_permission_cache(&self) -> Result<(), ExecuteError> {
         let mut permission_cache = PermissionCache::new();
@@ -251,6 +343,9 @@ where
             RequestWrapper::AuthenticateRequest(ref req) => {
                 self.handle_authenticate_request(req).map(Into::into)
             }
+            RequestWrapper::TxnRequest(ref req) => {
+                self.handle_rehash_password_op(req).map(Into::into)
+            }
             _ => {
                 unreachable!("Other request should not be sent to this store");
             }
@@ -309,13 +404,44 @@ where
             return Err(ExecuteError::AuthNotEnabled);
         }
         self.check_password(&req.name, &req.password)?;
-        let token = self.assign(&req.name)?;
+        self.issue_token(&req.name)
+    }
+
+    /// Issue a token to a user whose credential has been verified, a user not kept
+    /// by the store fails the authentication since it has no roles
+    pub(crate) fn issue_token(&self, username: &str) -> Result<AuthenticateResponse, ExecuteError> {
+        if !self.is_enabled() {
+            return Err(ExecuteError::AuthNotEnabled);
+        }
+        let _user = self
+            .backend
+            .get_user(username)
+            .map_err(|_ignore| ExecuteError::AuthFailed)?;
+        let token = self.assign(username)?;
         Ok(AuthenticateResponse {
             header: Some(self.header_gen.gen_auth_header()),
             token,
         })
     }
 
+    /// Get the password re-hash carried by a txn applied by the auth store
+    fn password_rehash(req: &TxnRequest) -> Result<RehashPasswordOp, ExecuteError> {
+        ServerOp::password_rehash(req).ok_or_else(|| {
+            ExecuteError::Rejected(
+                "only a password re-hash is applied by the auth store".to_owned(),
+            )
+        })
+    }
+
+    /// Handle a txn carrying a `RehashPasswordOp`
+    fn handle_rehash_password_op(&self, req: &TxnRequest) -> Result<TxnResponse, ExecuteError> {
+        debug!("handle_rehash_password_op");
+        let op = Self::password_rehash(req)?;
+        let _user = self.backend.get_user(&op.name)?;
+        Ok(ServerOpResult::RehashPassword(RehashPasswordOpResult {})
+            .into_txn_response(Some(self.header_gen.gen_auth_header())))
+    }
+
     /// Handle `AuthUserAddRequest`
     fn handle_user_add_request(
         &self,
@@ -398,15 +524,50 @@ where
         req: &AuthUserGrantRoleRequest,
     ) -> Result<AuthUserGrantRoleResponse, ExecuteError> {
         debug!("handle_user_grant_role_request");
-        let _user = self.backend.get_user(&req.user)?;
+        let user = self.backend.get_user(&req.user)?;
         if req.role != ROOT_ROLE {
             let _role = self.backend.get_role(&req.role)?;
         }
+        self.check_roles_limit(&user, &req.role)?;
         Ok(AuthUserGrantRoleResponse {
             header: Some(self.header_gen.gen_auth_header()),
         })
     }
 
+    /// Check that granting a role to a user doesn't exceed the max number of roles of a
+    /// user, granting a role the user already has is checked elsewhere
+    fn check_roles_limit(&self, user: &User, role: &str) -> Result<(), ExecuteError> {
+        let max = self.max_roles_per_user;
+        if max == 0 || user.roles.len() < max || user.roles.iter().any(|r| r == role) {
+            return Ok(());
+        }
+        Err(ExecuteError::TooManyRoles(
+            String::from_utf8_lossy(&user.name).into_owned(),
+            max,
+        ))
+    }
+
+    /// Check that granting a permission to a role doesn't exceed the max number of
+    /// permissions of a role, a permission of an already granted range only updates it
+    fn check_permissions_limit(
+        &self,
+        role: &Role,
+        permission: &Permission,
+    ) -> Result<(), ExecuteError> {
+        let max = self.max_permissions_per_role;
+        let granted = role
+            .key_permission
+            .iter()
+            .any(|p| p.key == permission.key && p.range_end == permission.range_end);
+        if max == 0 || role.key_permission.len() < max || granted {
+            return Ok(());
+        }
+        Err(ExecuteError::TooManyPermissions(
+            String::from_utf8_lossy(&role.name).into_owned(),
+            max,
+        ))
+    }
+
     /// Handle `AuthUserRevokeRoleRequest`
     fn handle_user_revoke_role_request(
         &self,
@@ -501,7 +662,10 @@ where
         req: &AuthRoleGrantPermissionRequest,
     ) -> Result<AuthRoleGrantPermissionResponse, ExecuteError> {
         debug!("handle_role_grant_permission_request");
-        let _role = self.backend.get_role(&req.name)?;
+        let role = self.backend.get_role(&req.name)?;
+        if let Some(ref permission) = req.perm {
+            self.check_permissions_limit(&role, permission)?;
+        }
         Ok(AuthRoleGrantPermissionResponse {
             header: Some(self.header_gen.gen_auth_header()),
         })
@@ -537,7 +701,7 @@ where
         revision: i64,
     ) -> Result<(SyncResponse, Vec<WriteOp<'a>>), ExecuteError> {
         #[allow(clippy::wildcard_enum_match_arm)]
-        let ops = match *request {
+        let mut ops = match *request {
             RequestWrapper::AuthEnableRequest(ref req) => {
                 debug!("Sync AuthEnableRequest {:?}", req);
                 self.sync_auth_enable_request(req)?
@@ -606,10 +770,15 @@ where
                 debug!("Sync AuthenticateRequest {:?}", req);
                 Vec::new()
             }
+            RequestWrapper::TxnRequest(ref req) => {
+                debug!("Sync RehashPasswordOp");
+                self.sync_rehash_password_op(req)?
+            }
             _ => {
                 unreachable!("Other request should not be sent to this store");
             }
         };
+        ops.extend(self.record_history(request));
         Ok((SyncResponse::new(revision), ops))
     }
 
@@ -637,6 +806,7 @@ where
             return Vec::new();
         }
         self.enabled.store(false, AtomicOrdering::Relaxed);
+        self.token_cache.lock().clear();
         ops.push(WriteOp::PutAuthRevision(revision));
         ops.push(WriteOp::PutAuthEnable(false));
         ops
@@ -671,6 +841,7 @@ where
                 };
             });
         });
+        self.token_cache.lock().remove_user(&req.name);
         ops.push(WriteOp::PutAuthRevision(revision));
         ops.push(WriteOp::DeleteUser(req.name.as_str()));
         ops
@@ -690,6 +861,22 @@ where
         Ok(ops)
     }
 
+    /// Sync a txn carrying a `RehashPasswordOp`, it keeps the auth revision so that the
+    /// issued tokens stay valid
+    fn sync_rehash_password_op<'a>(
+        &self,
+        req: &TxnRequest,
+    ) -> Result<Vec<WriteOp<'a>>, ExecuteError> {
+        let op = Self::password_rehash(req)?;
+        let mut user = self.backend.get_user(&op.name)?;
+        // the password has been changed since it was verified
+        if user.password != op.previous_hash.as_bytes() {
+            return Ok(Vec::new());
+        }
+        user.password = op.hash.into_bytes();
+        Ok(vec![WriteOp::PutUser(user)])
+    }
+
     /// Sync `AuthUserGrantRoleRequest` and return whether authstore is changed.
     fn sync_user_grant_role_request<'a>(
         &self,
@@ -702,6 +889,7 @@ where
         if (req.role != ROOT_ROLE) && role.is_err() {
             return Err(ExecuteError::RoleNotFound(req.role.clone()));
         }
+        self.check_roles_limit(&user, &req.role)?;
         let Err(idx) = user.roles.binary_search(&req.role) else {
             return Err(ExecuteError::UserAlreadyHasRole(
                 req.user.clone(),
@@ -807,6 +995,7 @@ where
         let mut ops = Vec::new();
         let mut role = self.backend.get_role(&req.name)?;
         let permission = req.perm.clone().ok_or(ExecuteError::PermissionNotGiven)?;
+        self.check_permissions_limit(&role, &permission)?;
 
         #[allow(clippy::indexing_slicing)] // this index is always valid
         match role
@@ -908,8 +1097,48 @@ where
         Ok(())
     }
 
+    /// Get the stored password hash of a user, `None` if the user doesn't exist
+    pub(crate) fn password_hash(&self, username: &str) -> Option<String> {
+        let user = self.backend.get_user(username).ok()?;
+        Some(String::from_utf8_lossy(&user.password).into_owned())
+    }
+
+    /// Get the pbkdf2 rounds of the stored password hash of a user, `None` if the user
+    /// doesn't exist or has no password
+    pub(crate) fn password_hash_rounds(&self, username: &str) -> Option<u32> {
+        utils::password_hash_rounds(&self.password_hash(username)?)
+    }
+
+    /// Count the users by the pbkdf2 rounds of their stored password hashes, the users
+    /// without a password are not counted
+    #[cfg_attr(madsim, allow(dead_code))] // The admin service is not simulated
+    pub(crate) fn password_hash_rounds_report(&self) -> Result<BTreeMap<u32, usize>, ExecuteError> {
+        let mut report = BTreeMap::new();
+        for user in self.backend.get_all_users()? {
+            if let Some(rounds) =
+                utils::password_hash_rounds(&String::from_utf8_lossy(&user.password))
+            {
+                let count = report.entry(rounds).or_insert(0_usize);
+                *count = count.saturating_add(1);
+            }
+        }
+        Ok(report)
+    }
+
+    /// The auth info of the root user at the current revision, used by the proposals
+    /// made by the server itself
+    pub(crate) fn root_auth_info(&self) -> AuthInfo {
+        AuthInfo {
+            username: ROOT_USER.to_owned(),
+            auth_revision: self.revision(),
+        }
+    }
+
     /// Check if the request need admin permission
     fn need_admin_permission(wrapper: &RequestWrapper) -> bool {
+        if let RequestWrapper::TxnRequest(ref req) = *wrapper {
+            return ServerOp::from_txn(req).is_ok_and(|op| op.is_some_and(|op| op.needs_admin()));
+        }
         matches!(
             *wrapper,
             RequestWrapper::AuthEnableRequest(_)
@@ -934,6 +1163,11 @@ where
         self.permission_cache.map_read(|cache| cache.clone())
     }
 
+    #[cfg(test)]
+    pub(super) fn token_cache_hits(&self) -> u64 {
+        self.token_cache.lock().hits()
+    }
+
     /// check if the request is permitted
     pub(crate) fn check_permission(
         &self,
@@ -1058,7 +1292,10 @@ where
                     self.check_delete_permission(username, del_range_req)?;
                 }
                 Some(Request::RequestTxn(ref txn_req)) => {
-                    for compare in &txn_req.compare {
+                    // the marker of a server op is not a key, the keys of the op are
+                    // checked by its footprint, which must match the op
+                    let _op = ServerOp::from_txn(txn_req)?;
+                    for compare in txn_req.compare.iter().filter(|c| !ServerOp::is_marker(c)) {
                         self.check_op_permission(
                             username,
                             &compare.key,
@@ -1142,6 +1379,120 @@ where
         Err(ExecuteError::PermissionDenied)
     }
 
+    /// Whether any role has a quota
+    pub(crate) fn has_role_quotas(&self) -> bool {
+        !self.role_quotas.is_empty()
+    }
+
+    /// Get the roles of the user of a write which have quotas, `None` if the write
+    /// isn't limited by any quota
+    fn quota_roles(
+        &self,
+        wrapper: &RequestWrapper,
+        auth_info: Option<&AuthInfo>,
+    ) -> Result<Option<Vec<(String, &RoleQuota)>>, ExecuteError> {
+        #[allow(clippy::wildcard_enum_match_arm)]
+        let is_write = match *wrapper {
+            RequestWrapper::PutRequest(_) | RequestWrapper::DeleteRangeRequest(_) => true,
+            RequestWrapper::TxnRequest(ref txn_req) => !txn_req.is_read_only(),
+            _ => false,
+        };
+        if !is_write || !self.has_role_quotas() || !self.is_enabled() {
+            return Ok(None);
+        }
+        let Some(auth_info) = auth_info else {
+            return Ok(None);
+        };
+        let user = self.backend.get_user(&auth_info.username)?;
+        if user.has_role(ROOT_ROLE) {
+            return Ok(None);
+        }
+        Ok(Some(
+            user.roles
+                .into_iter()
+                .filter_map(|role| {
+                    let quota = self.role_quotas.get(&role)?;
+                    Some((role, quota))
+                })
+                .collect(),
+        ))
+    }
+
+    /// Get the write-permitted ranges of the roles of the user of a write which limit
+    /// its keys or bytes. The ranges are added to the keys of the command of the write,
+    /// so that the writes counted against the same quota conflict with each other and
+    /// are applied in the same order on every node.
+    pub(crate) fn role_quota_ranges(
+        &self,
+        wrapper: &RequestWrapper,
+        auth_info: Option<&AuthInfo>,
+    ) -> Result<Vec<KeyRange>, ExecuteError> {
+        let Some(roles) = self.quota_roles(wrapper, auth_info)? else {
+            return Ok(Vec::new());
+        };
+        let mut ranges = Vec::new();
+        for (role_name, role_quota) in roles {
+            if *role_quota.max_keys() == 0 && *role_quota.max_bytes() == 0 {
+                continue;
+            }
+            if let Ok(role) = self.backend.get_role(&role_name) {
+                ranges.extend(quota::write_ranges(&role));
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// Check a write against the key and byte limits of the roles of its user, it is
+    /// checked after the permission of the write when the write is applied, so every
+    /// node derives the same usage from the applied kv state
+    pub(crate) fn check_role_usage(
+        &self,
+        wrapper: &RequestWrapper,
+        auth_info: Option<&AuthInfo>,
+        usage: &dyn KeyUsageSource,
+    ) -> Result<(), ExecuteError> {
+        let Some(roles) = self.quota_roles(wrapper, auth_info)? else {
+            return Ok(());
+        };
+        let puts = quota::collect_puts(wrapper);
+        for (role_name, role_quota) in roles {
+            let Ok(role) = self.backend.get_role(&role_name) else {
+                continue;
+            };
+            let ranges = quota::write_ranges(&role);
+            quota::check_usage(&role_name, role_quota, &ranges, &puts, usage)?;
+        }
+        Ok(())
+    }
+
+    /// Check a write against the write rates of the roles of its user, the rates are
+    /// limited by the node serving the write
+    pub(crate) fn check_write_rate(
+        &self,
+        wrapper: &RequestWrapper,
+        auth_info: Option<&AuthInfo>,
+    ) -> Result<(), ExecuteError> {
+        self.check_write_rate_at(wrapper, auth_info, Instant::now())
+    }
+
+    /// Check a write against the write rates of the roles of its user at `now`
+    fn check_write_rate_at(
+        &self,
+        wrapper: &RequestWrapper,
+        auth_info: Option<&AuthInfo>,
+        now: Instant,
+    ) -> Result<(), ExecuteError> {
+        let Some(roles) = self.quota_roles(wrapper, auth_info)? else {
+            return Ok(());
+        };
+        let rates: Vec<_> = roles
+            .iter()
+            .filter(|&&(_, quota)| *quota.max_write_rate() != 0)
+            .map(|&(ref role, quota)| (role.as_str(), *quota.max_write_rate()))
+            .collect();
+        self.write_rate_limiter.lock().acquire(&rates, now)
+    }
+
     /// Assign root token
     pub(crate) fn root_token(&self) -> Result<String, ExecuteError> {
         self.assign(ROOT_USER)
@@ -1156,6 +1507,7 @@ where
         let revision = self.backend.get_revision()?;
         self.revision.set(revision);
         self.create_permission_cache()?;
+        self.history.recover(self.backend.db())?;
         Ok(())
     }
 }
@@ -1170,10 +1522,11 @@ fn get_cn<T>(request: &tonic::Request<T>) -> Option<String> {
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashMap;
+    use std::{collections::HashMap, time::Duration};
 
     use merged_range::MergedRange;
     use utils::config::EngineConfig;
+    use xlineapi::server_op::{CounterEncoding, IncrementOp, ReserveRevisionsOp};
 
     use super::*;
     use crate::{
@@ -1199,6 +1552,26 @@ mod test {
         assert_eq!(auth_info.username, "xline");
     }
 
+    #[test]
+    fn token_cache_should_be_invalidated_on_user_deletion() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_auth_store(db);
+        let token = store.assign("u")?;
+        let _auth_info = store.verify(&token)?;
+        assert_eq!(store.token_cache_hits(), 0);
+        let auth_info = store.verify(&token)?;
+        assert_eq!(store.token_cache_hits(), 1);
+        assert_eq!(auth_info.username, "u");
+
+        let req = RequestWrapper::from(AuthUserDeleteRequest {
+            name: "u".to_owned(),
+        });
+        assert!(exe_and_sync(&store, &req, 6).is_ok());
+        let _auth_info = store.verify(&token)?;
+        assert_eq!(store.token_cache_hits(), 1);
+        Ok(())
+    }
+
     #[test]
     fn test_role_grant_permission() -> Result<(), ExecuteError> {
         let db = DB::open(&EngineConfig::Memory)?;
@@ -1344,6 +1717,175 @@ mod test {
         Ok(())
     }
 
+    /// A usage source of a fixed set of keys whose sizes are 1
+    struct FixedKeys(Vec<&'static str>);
+
+    impl KeyUsageSource for FixedKeys {
+        fn key_sizes(&self, range: &KeyRange) -> Result<Vec<(Vec<u8>, u64)>, ExecuteError> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|key| range.contains_key(key.as_bytes()))
+                .map(|key| (key.as_bytes().to_vec(), 1))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn role_at_its_key_quota_should_be_rejected() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store_with_quotas(
+            db,
+            HashMap::from([
+                ("limited".to_owned(), RoleQuota::new(1, 0, 0)),
+                ("throttled".to_owned(), RoleQuota::new(0, 0, 1)),
+            ]),
+        );
+        let rev = Arc::clone(&store.revision);
+        for (user, role) in [
+            ("root", "root"),
+            ("u1", "limited"),
+            ("u2", "free"),
+            ("u3", "throttled"),
+        ] {
+            let requests = [
+                RequestWrapper::from(AuthRoleAddRequest {
+                    name: role.to_owned(),
+                }),
+                RequestWrapper::from(AuthUserAddRequest {
+                    name: user.to_owned(),
+                    password: String::new(),
+                    hashed_password: "123".to_owned(),
+                    options: None,
+                }),
+                RequestWrapper::from(AuthUserGrantRoleRequest {
+                    user: user.to_owned(),
+                    role: role.to_owned(),
+                }),
+                RequestWrapper::from(AuthRoleGrantPermissionRequest {
+                    name: role.to_owned(),
+                    perm: Some(Permission {
+                        #[allow(clippy::as_conversions)] // This cast is always valid
+                        perm_type: Type::Readwrite as i32,
+                        key: b"a".to_vec(),
+                        range_end: b"b".to_vec(),
+                    }),
+                }),
+            ];
+            for req in &requests {
+                let _ignore = exe_and_sync(&store, req, rev.next())?;
+            }
+        }
+        let _ignore = exe_and_sync(&store, &RequestWrapper::from(AuthEnableRequest {}), -1)?;
+
+        let usage = FixedKeys(vec!["a1"]);
+        let auth_info = |user: &str| AuthInfo {
+            username: user.to_owned(),
+            auth_revision: store.revision(),
+        };
+        let put = |key: &str| {
+            RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: b"v".to_vec(),
+                ..Default::default()
+            })
+        };
+        let now = Instant::now();
+        let check = |req: &RequestWrapper, user: &str| {
+            let auth_info = auth_info(user);
+            store.check_role_usage(req, Some(&auth_info), &usage)?;
+            store.check_write_rate_at(req, Some(&auth_info), now)
+        };
+        assert!(check(&put("a1"), "u1").is_ok());
+        assert!(matches!(
+            check(&put("a2"), "u1"),
+            Err(ExecuteError::RoleQuotaExceeded(ref role)) if role == "limited"
+        ));
+        assert!(check(&put("a2"), "u2").is_ok());
+        assert!(check(&put("a2"), "root").is_ok());
+        assert!(check(&put("a2"), "u3").is_ok());
+        assert!(check(&put("a2"), "u3").is_err());
+
+        // only the writes counted against a key or byte limit conflict by the ranges
+        let ranges = |user: &str| store.role_quota_ranges(&put("a2"), Some(&auth_info(user)));
+        assert_eq!(ranges("u1")?, vec![KeyRange::new("a", "b")]);
+        for user in ["u2", "u3", "root"] {
+            assert!(ranges(user)?.is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn server_ops_should_be_checked_by_their_footprints() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_auth_store(db);
+        let rev = Arc::clone(&store.revision);
+        let requests = [
+            RequestWrapper::from(AuthRoleAddRequest {
+                name: "root".to_owned(),
+            }),
+            RequestWrapper::from(AuthUserAddRequest {
+                name: "root".to_owned(),
+                password: String::new(),
+                hashed_password: "123".to_owned(),
+                options: None,
+            }),
+            RequestWrapper::from(AuthUserGrantRoleRequest {
+                user: "root".to_owned(),
+                role: "root".to_owned(),
+            }),
+        ];
+        for req in &requests {
+            let _ignore = exe_and_sync(&store, req, rev.next())?;
+        }
+        let _ignore = exe_and_sync(&store, &RequestWrapper::from(AuthEnableRequest {}), -1)?;
+        let auth_info = |user: &str| AuthInfo {
+            username: user.to_owned(),
+            auth_revision: store.revision(),
+        };
+        let check = |op: ServerOp, user: &str| {
+            let req = RequestWrapper::from(TxnRequest::from(op));
+            store.check_permission(&req, Some(&auth_info(user)))
+        };
+        let increment = |key: &str| {
+            ServerOp::Increment(IncrementOp::new(key.into(), 1, CounterEncoding::Decimal))
+        };
+        let reserve = || ServerOp::ReserveRevisions(ReserveRevisionsOp::new(10));
+
+        // "u" can read and write "foo"
+        assert!(check(increment("foo"), "u").is_ok());
+        assert!(check(increment("bar"), "u").is_err());
+        assert!(check(reserve(), "u").is_err());
+        assert!(check(reserve(), "root").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn server_ops_with_forged_footprints_should_be_rejected() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_auth_store(db);
+        let _ignore = exe_and_sync(&store, &RequestWrapper::from(AuthEnableRequest {}), -1)?;
+        let auth_info = AuthInfo {
+            username: "u".to_owned(),
+            auth_revision: store.revision(),
+        };
+        // "u" can write "foo" but not "bar"
+        let mut forged = TxnRequest::from(ServerOp::Increment(IncrementOp::new(
+            b"bar".to_vec(),
+            1,
+            CounterEncoding::Decimal,
+        )));
+        forged.success = ServerOp::Increment(IncrementOp::new(
+            b"foo".to_vec(),
+            1,
+            CounterEncoding::Decimal,
+        ))
+        .footprint();
+        let res = store.check_permission(&RequestWrapper::from(forged), Some(&auth_info));
+        assert!(matches!(res, Err(ExecuteError::Rejected(_))), "{res:?}");
+        Ok(())
+    }
+
     fn init_auth_store(db: Arc<DB>) -> AuthStore<DB> {
         let store = init_empty_store(db);
         let rev = Arc::clone(&store.revision);
@@ -1390,10 +1932,27 @@ mod test {
     }
 
     fn init_empty_store(db: Arc<DB>) -> AuthStore<DB> {
+        init_empty_store_with_quotas(db, HashMap::new())
+    }
+
+    fn init_empty_store_with_quotas(
+        db: Arc<DB>,
+        role_quotas: HashMap<String, RoleQuota>,
+    ) -> AuthStore<DB> {
         let key_pair = test_key_pair();
         let header_gen = Arc::new(HeaderGenerator::new(0, 0));
-        let lease_collection = Arc::new(LeaseCollection::new(0));
-        AuthStore::new(lease_collection, key_pair, header_gen, db)
+        let lease_collection = Arc::new(LeaseCollection::new(0, Duration::ZERO));
+        AuthStore::new(
+            lease_collection,
+            key_pair,
+            header_gen,
+            db,
+            0,
+            16,
+            role_quotas,
+            0,
+            0,
+        )
     }
 
     fn exe_and_sync(
@@ -1407,6 +1966,76 @@ mod test {
         Ok((cmd_res, sync_res))
     }
 
+    #[test]
+    fn password_hash_rounds_should_be_reported_by_user() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let rev = Arc::clone(&store.revision);
+        for (name, rounds) in [("u1", 1000), ("u2", 1000), ("u3", 2000)] {
+            let req = RequestWrapper::from(AuthUserAddRequest {
+                name: name.to_owned(),
+                password: String::new(),
+                hashed_password: utils::hash_password_with_rounds(b"123", rounds).unwrap(),
+                options: None,
+            });
+            assert!(exe_and_sync(&store, &req, rev.next()).is_ok());
+        }
+        assert_eq!(store.password_hash_rounds("u3"), Some(2000));
+        assert_eq!(store.password_hash_rounds("u4"), None);
+        assert_eq!(
+            store.password_hash_rounds_report()?,
+            BTreeMap::from([(1000, 2), (2000, 1)])
+        );
+
+        let req = RequestWrapper::from(AuthUserChangePasswordRequest {
+            name: "u3".to_owned(),
+            hashed_password: utils::hash_password_with_rounds(b"123", 1000).unwrap(),
+            password: String::new(),
+        });
+        assert!(exe_and_sync(&store, &req, rev.next()).is_ok());
+        assert_eq!(
+            store.password_hash_rounds_report()?,
+            BTreeMap::from([(1000, 3)])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn password_rehash_should_keep_the_auth_revision() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let rev = Arc::clone(&store.revision);
+        let req = RequestWrapper::from(AuthUserAddRequest {
+            name: "u".to_owned(),
+            password: String::new(),
+            hashed_password: utils::hash_password_with_rounds(b"123", 1000).unwrap(),
+            options: None,
+        });
+        assert!(exe_and_sync(&store, &req, rev.next()).is_ok());
+        let revision = store.revision();
+        let rehash = |previous_hash: String, rounds: u32| {
+            RequestWrapper::from(TxnRequest::from(ServerOp::RehashPassword(
+                RehashPasswordOp::new(
+                    "u".to_owned(),
+                    previous_hash,
+                    utils::hash_password_with_rounds(b"123", rounds).unwrap(),
+                ),
+            )))
+        };
+
+        let previous_hash = store.password_hash("u").unwrap();
+        let req = rehash(previous_hash.clone(), 2000);
+        assert!(req.is_auth_request());
+        assert!(req.skip_auth_revision());
+        assert!(exe_and_sync(&store, &req, -1).is_ok());
+        assert_eq!(store.password_hash_rounds("u"), Some(2000));
+        assert_eq!(store.revision(), revision);
+        // a re-hash of a password which has been changed since is skipped
+        assert!(exe_and_sync(&store, &rehash(previous_hash, 3000), -1).is_ok());
+        assert_eq!(store.password_hash_rounds("u"), Some(2000));
+        Ok(())
+    }
+
     fn test_key_pair() -> Option<(EncodingKey, DecodingKey)> {
         let private_key = include_bytes!("../../../../../fixtures/private.pem");
         let public_key = include_bytes!("../../../../../fixtures/public.pem");
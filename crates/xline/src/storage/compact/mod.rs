@@ -1,23 +1,28 @@
-use std::{sync::Arc, time::Duration};
+use std::{fmt::Debug, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
+use clippy_utilities::OverflowArithmetic;
 use curp::client::ClientApi;
 use event_listener::Event;
 use periodic_compactor::PeriodicCompactor;
 use revision_compactor::RevisionCompactor;
 use tokio::{sync::mpsc::Receiver, time::sleep};
+use tracing::{info, warn};
 use utils::{
-    config::AutoCompactConfig,
+    config::{AutoCompactConfig, MaintenanceOp},
     task_manager::{tasks::TaskName, Listener, TaskManager},
 };
 use xlineapi::{command::Command, execute_error::ExecuteError, RequestWrapper};
 
 use super::{
     index::{Index, IndexOperate},
+    maintenance_scheduler::MaintenanceScheduler,
     storage_api::StorageApi,
     KvStore,
 };
-use crate::{revision_number::RevisionNumberGenerator, rpc::CompactionRequest};
+use crate::{
+    revision_number::RevisionNumberGenerator, rpc::CompactionRequest, server::ArchiveSink,
+};
 
 /// mod revision compactor;
 mod revision_compactor;
@@ -28,6 +33,9 @@ mod periodic_compactor;
 /// compact task channel size
 pub(crate) const COMPACT_CHANNEL_SIZE: usize = 32;
 
+/// Interval between the retries of a failed archiving of compacted versions
+const ARCHIVE_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
 /// Compactor trait definition
 #[async_trait]
 pub(crate) trait Compactor<C: Compactable>: Send + Sync {
@@ -70,19 +78,78 @@ impl Compactable
     }
 }
 
+/// The watches whose needed history must survive the auto-compaction
+pub(crate) trait ActiveWatches: Send + Sync + Debug {
+    /// Get the minimum start revision of the active historical watches, `None` if
+    /// there is no such watch
+    fn min_start_revision(&self) -> Option<i64>;
+}
+
+/// The floor holding the auto-compaction below the revisions still needed by the
+/// active historical watches, so that they are not canceled by the compaction.
+///
+/// The auto-compaction is run by the leader, so only the watches served by the
+/// leader hold it.
+#[derive(Debug, Clone)]
+pub(crate) struct WatchFloor {
+    /// The active watches
+    watches: Arc<dyn ActiveWatches>,
+    /// The number of revisions kept below the minimum start revision
+    margin: i64,
+}
+
+impl WatchFloor {
+    /// New `WatchFloor`
+    pub(crate) fn new(watches: Arc<dyn ActiveWatches>, margin: i64) -> Self {
+        Self { watches, margin }
+    }
+
+    /// Hold the revision of an auto-compaction below the floor
+    fn hold(&self, revision: i64) -> i64 {
+        self.watches
+            .min_start_revision()
+            .map_or(revision, |needed| {
+                revision.min(needed.overflow_sub(self.margin))
+            })
+    }
+}
+
+/// Hold the revision of an auto-compaction below the floor if there is one, the
+/// compaction is skipped if the held revision makes no progress
+fn hold_revision(
+    floor: Option<&WatchFloor>,
+    revision: i64,
+    last_revision: Option<i64>,
+) -> Option<i64> {
+    let Some(floor) = floor else {
+        return Some(revision);
+    };
+    let held = floor.hold(revision);
+    if held == revision {
+        return Some(revision);
+    }
+    if held <= 0 || Some(held) <= last_revision {
+        info!("auto compaction at revision {revision} is held by the active watches");
+        return None;
+    }
+    info!("auto compaction at revision {revision} is held at {held} by the active watches");
+    Some(held)
+}
+
 /// Boot up an auto-compactor background task.
 pub(crate) async fn auto_compactor<C: Compactable>(
     is_leader: bool,
     revision_getter: Arc<RevisionNumberGenerator>,
     auto_compact_cfg: AutoCompactConfig,
+    watch_floor: Option<WatchFloor>,
     task_manager: Arc<TaskManager>,
 ) -> Arc<dyn Compactor<C>> {
     let auto_compactor: Arc<dyn Compactor<C>> = match auto_compact_cfg {
         AutoCompactConfig::Periodic(period) => {
-            PeriodicCompactor::new_arc(is_leader, revision_getter, period)
+            PeriodicCompactor::new_arc(is_leader, revision_getter, period, watch_floor)
         }
         AutoCompactConfig::Revision(retention) => {
-            RevisionCompactor::new_arc(is_leader, revision_getter, retention)
+            RevisionCompactor::new_arc(is_leader, revision_getter, retention, watch_floor)
         }
         _ => {
             unreachable!("xline only supports two auto-compaction modes: periodic, revision")
@@ -95,6 +162,40 @@ pub(crate) async fn auto_compactor<C: Compactable>(
     compactor_handle
 }
 
+/// Archive the versions of a chunk of revisions before they are compacted, the
+/// archiving is retried until it succeeds. Returns `false` if the server shuts down
+/// before the chunk is archived, in which case the chunk must not be compacted.
+#[allow(clippy::ignored_unit_patterns)] // introduced bt tokio::select! macro
+async fn archive_chunk<DB>(
+    kv_store: &KvStore<DB>,
+    sink: &dyn ArchiveSink,
+    revision_chunk: &[Vec<u8>],
+    shutdown_listener: &Listener,
+) -> bool
+where
+    DB: StorageApi,
+{
+    let kvs = match kv_store.compacting_values(revision_chunk) {
+        Ok(kvs) => kvs,
+        Err(e) => {
+            panic!("failed to get the values of revision chunk {revision_chunk:?} due to {e}")
+        }
+    };
+    loop {
+        let Err(e) = sink.archive(&kvs).await else {
+            return true;
+        };
+        warn!(
+            "failed to archive {} compacted versions, retrying: {e}",
+            kvs.len()
+        );
+        tokio::select! {
+            _ = sleep(ARCHIVE_RETRY_INTERVAL) => {},
+            _ = shutdown_listener.wait() => return false,
+        }
+    }
+}
+
 /// background compact executor
 #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // introduced bt tokio::select! macro
 pub(crate) async fn compact_bg_task<DB>(
@@ -102,6 +203,8 @@ pub(crate) async fn compact_bg_task<DB>(
     index: Arc<Index>,
     batch_limit: usize,
     interval: Duration,
+    maintenance_scheduler: Arc<MaintenanceScheduler>,
+    archive_sink: Option<Arc<dyn ArchiveSink>>,
     mut compact_task_rx: Receiver<(i64, Option<Arc<Event>>)>,
     shutdown_listener: Listener,
 ) where
@@ -118,6 +221,9 @@ pub(crate) async fn compact_bg_task<DB>(
             _ = shutdown_listener.wait() => break,
         };
 
+        let permit = maintenance_scheduler
+            .acquire(MaintenanceOp::Compaction)
+            .await;
         let target_revisions = index
             .compact(revision)
             .into_iter()
@@ -125,6 +231,13 @@ pub(crate) async fn compact_bg_task<DB>(
             .collect::<Vec<Vec<_>>>();
         // Given that the Xline uses a lim-tree database with smaller write amplification as the storage backend ,  does using progressive compaction really good at improving performance?
         for revision_chunk in target_revisions.chunks(batch_limit) {
+            if let Some(ref sink) = archive_sink {
+                if !archive_chunk(&kv_store, sink.as_ref(), revision_chunk, &shutdown_listener)
+                    .await
+                {
+                    return;
+                }
+            }
             if let Err(e) = kv_store.compact(revision_chunk) {
                 panic!("failed to compact revision chunk {revision_chunk:?} due to {e}");
             }
@@ -133,6 +246,7 @@ pub(crate) async fn compact_bg_task<DB>(
         if let Err(e) = kv_store.compact_finished(revision) {
             panic!("failed to set finished compact revision {revision:?} due to {e}");
         }
+        drop(permit);
         if let Some(notifier) = listener {
             let _ignore = notifier.notify(usize::MAX);
         }
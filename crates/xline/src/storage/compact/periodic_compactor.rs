@@ -12,7 +12,7 @@ use tokio::sync::RwLock;
 use tracing::{info, warn};
 use utils::task_manager::Listener;
 
-use super::{Compactable, Compactor};
+use super::{hold_revision, Compactable, Compactor, WatchFloor};
 use crate::revision_number::RevisionNumberGenerator;
 
 /// `RevisionWindow` is a ring buffer used to store periodically sampled revision.
@@ -65,6 +65,8 @@ pub(crate) struct PeriodicCompactor<C: Compactable> {
     revision_getter: Arc<RevisionNumberGenerator>,
     /// compaction period
     period: Duration,
+    /// The floor of the compaction held by the active watches
+    watch_floor: Option<WatchFloor>,
 }
 
 impl<C: Compactable> PeriodicCompactor<C> {
@@ -73,12 +75,14 @@ impl<C: Compactable> PeriodicCompactor<C> {
         is_leader: bool,
         revision_getter: Arc<RevisionNumberGenerator>,
         period: Duration,
+        watch_floor: Option<WatchFloor>,
     ) -> Arc<Self> {
         Arc::new(Self {
             is_leader: AtomicBool::new(is_leader),
             compactable: RwLock::new(None),
             revision_getter,
             period,
+            watch_floor,
         })
     }
 
@@ -97,6 +101,7 @@ impl<C: Compactable> PeriodicCompactor<C> {
         }
         let revision =
             target_revision.unwrap_or_else(|| unreachable!("target revision shouldn't be None"));
+        let revision = hold_revision(self.watch_floor.as_ref(), revision, last_revision)?;
         let now = Instant::now();
         info!(
             "starting auto periodic compaction, revision = {}, period = {:?}",
@@ -237,7 +242,7 @@ mod test {
         compactable.expect_compact().times(3).returning(Ok);
         let revision_gen = Arc::new(RevisionNumberGenerator::new(1));
         let periodic_compactor =
-            PeriodicCompactor::new_arc(true, revision_gen, Duration::from_secs(10));
+            PeriodicCompactor::new_arc(true, revision_gen, Duration::from_secs(10), None);
         periodic_compactor.set_compactable(compactable).await;
         // auto_compactor works successfully
         assert_eq!(
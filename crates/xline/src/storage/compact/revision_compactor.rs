@@ -11,7 +11,7 @@ use tokio::sync::RwLock;
 use tracing::{info, warn};
 use utils::task_manager::Listener;
 
-use super::{Compactable, Compactor};
+use super::{hold_revision, Compactable, Compactor, WatchFloor};
 use crate::revision_number::RevisionNumberGenerator;
 
 /// check for the need of compaction every 5 minutes
@@ -28,6 +28,8 @@ pub(crate) struct RevisionCompactor<C: Compactable> {
     revision_getter: Arc<RevisionNumberGenerator>,
     /// revision retention
     retention: i64,
+    /// The floor of the compaction held by the active watches
+    watch_floor: Option<WatchFloor>,
 }
 
 impl<C: Compactable> RevisionCompactor<C> {
@@ -36,12 +38,14 @@ impl<C: Compactable> RevisionCompactor<C> {
         is_leader: bool,
         revision_getter: Arc<RevisionNumberGenerator>,
         retention: i64,
+        watch_floor: Option<WatchFloor>,
     ) -> Arc<Self> {
         Arc::new(Self {
             is_leader: AtomicBool::new(is_leader),
             compactable: RwLock::new(None),
             revision_getter,
             retention,
+            watch_floor,
         })
     }
 
@@ -55,6 +59,8 @@ impl<C: Compactable> RevisionCompactor<C> {
         if target_revision <= 0 || Some(target_revision) <= last_revision {
             return None;
         }
+        let target_revision =
+            hold_revision(self.watch_floor.as_ref(), target_revision, last_revision)?;
 
         let now = Instant::now();
         info!(
@@ -121,15 +127,27 @@ impl<C: Compactable> Compactor<C> for RevisionCompactor<C> {
 
 #[cfg(test)]
 mod test {
+    use parking_lot::Mutex;
+
     use super::*;
-    use crate::storage::compact::MockCompactable;
+    use crate::storage::compact::{ActiveWatches, MockCompactable};
+
+    #[derive(Debug, Default)]
+    struct FakeWatches(Mutex<Option<i64>>);
+
+    impl ActiveWatches for FakeWatches {
+        fn min_start_revision(&self) -> Option<i64> {
+            *self.0.lock()
+        }
+    }
 
     #[tokio::test]
     async fn revision_compactor_should_work_in_normal_path() {
         let mut compactable = MockCompactable::new();
         compactable.expect_compact().times(3).returning(Ok);
         let revision_gen = Arc::new(RevisionNumberGenerator::new(110));
-        let revision_compactor = RevisionCompactor::new_arc(true, Arc::clone(&revision_gen), 100);
+        let revision_compactor =
+            RevisionCompactor::new_arc(true, Arc::clone(&revision_gen), 100, None);
         revision_compactor.set_compactable(compactable).await;
         // auto_compactor works successfully
         assert_eq!(revision_compactor.do_compact(None).await, Some(10));
@@ -145,4 +163,25 @@ mod test {
         // auto compactor should skip those revisions which have been auto compacted.
         assert!(revision_compactor.do_compact(Some(13)).await.is_none());
     }
+
+    #[tokio::test]
+    async fn revision_compactor_should_be_held_below_active_watches() {
+        let mut compactable = MockCompactable::new();
+        compactable.expect_compact().times(2).returning(Ok);
+        let revision_gen = Arc::new(RevisionNumberGenerator::new(150));
+        let watches = Arc::new(FakeWatches::default());
+        // a historical watch needs the revisions from 30
+        *watches.0.lock() = Some(30);
+        let floor = WatchFloor::new(Arc::clone(&watches) as Arc<dyn ActiveWatches>, 5);
+        let revision_compactor =
+            RevisionCompactor::new_arc(true, Arc::clone(&revision_gen), 100, Some(floor));
+        revision_compactor.set_compactable(compactable).await;
+        assert_eq!(revision_compactor.do_compact(None).await, Some(25));
+        // no progress while the watch is active
+        revision_gen.next();
+        assert!(revision_compactor.do_compact(Some(25)).await.is_none());
+        // the compaction catches up once the watch is gone
+        *watches.0.lock() = None;
+        assert_eq!(revision_compactor.do_compact(Some(25)).await, Some(51));
+    }
 }
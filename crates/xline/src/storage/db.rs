@@ -1,24 +1,37 @@
-use std::{collections::HashMap, path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
 
+use clippy_utilities::{NumericCast, OverflowArithmetic};
 use engine::{Engine, EngineType, Snapshot, StorageEngine, WriteOperation};
+use parking_lot::Mutex;
 use prost::Message;
+use sha2::{Digest, Sha256};
 use utils::{
     config::EngineConfig,
     table_names::{
         ALARM_TABLE, AUTH_TABLE, KV_TABLE, LEASE_TABLE, META_TABLE, ROLE_TABLE, USER_TABLE,
-        XLINE_TABLES,
+        VALUE_TABLE, XLINE_TABLES,
     },
 };
 use xlineapi::{execute_error::ExecuteError, AlarmMember};
 
 use super::{
     auth_store::{AUTH_ENABLE_KEY, AUTH_REVISION_KEY},
+    encryption::{ensure_plaintext, is_encrypted, RecordCipher},
+    history::history_key,
     revision::KeyRevision,
     storage_api::StorageApi,
 };
 use crate::{
     rpc::{KeyValue, PbLease, Role, User},
-    server::command::APPLIED_INDEX_KEY,
+    server::{command::APPLIED_INDEX_KEY, member_tags::tags_key},
     storage::Revision,
 };
 
@@ -26,12 +39,54 @@ use crate::{
 pub(crate) const FINISHED_COMPACT_REVISION: &str = "finished_compact_revision";
 /// Key of scheduled compact revision
 pub(crate) const SCHEDULED_COMPACT_REVISION: &str = "scheduled_compact_revision";
+/// Key of the last reserved revision
+pub(crate) const RESERVED_REVISION: &str = "reserved_revision";
+/// Key of the marker of a storage whose records have been encrypted
+pub(crate) const ENCRYPTED_RECORDS: &str = "encrypted_records";
+
+/// Size of the reference count prefix of a deduplicated value
+const REF_COUNT_SIZE: usize = 8;
+
+/// Reference count changes of the deduplicated values in a batch, the value is
+/// provided if it is newly referenced
+type ValueRefDeltas = HashMap<Vec<u8>, (i64, Option<Vec<u8>>)>;
+
+/// Reference to a deduplicated value. It is appended to an encoded `KeyValue` whose
+/// value is taken out, the field is unknown to `KeyValue` and skipped when decoding it.
+#[derive(Clone, PartialEq, Eq, Message)]
+struct ValueRef {
+    /// Sha256 digest of the value
+    #[prost(bytes = "vec", tag = "15")]
+    digest: Vec<u8>,
+}
 
 /// Database to store revision to kv mapping
 #[derive(Debug)]
 pub struct DB {
     /// internal storage of `DB`
     engine: Arc<Engine>,
+    /// Values of at least this many bytes are stored once in the value table by their
+    /// digest, 0 means the deduplication is disabled
+    dedup_value_threshold: usize,
+    /// Lock to serialize the updates of the value reference counts
+    value_ref_lock: Mutex<()>,
+    /// Whether the batches are synced to the disk, except those writing only the
+    /// non-durable prefixes
+    sync_writes: bool,
+    /// The key prefixes whose writes are not synced to the disk
+    non_durable_prefixes: Vec<Vec<u8>>,
+    /// The time the last batch was synced to the disk
+    last_synced_at: Mutex<Option<SystemTime>>,
+    /// The error of the last write if it failed, it's cleared by a successful write
+    write_failure: Mutex<Option<String>>,
+    /// The cipher of the records of the kv table, `None` means they are not encrypted
+    cipher: Option<RecordCipher>,
+    /// Whether the records may be encrypted, that is the encryption was ever enabled
+    /// on the storage, the records are not checked against the encryption otherwise
+    may_be_encrypted: AtomicBool,
+    /// The number of flushed batches synced to the disk
+    #[cfg(test)]
+    synced_flushes: std::sync::atomic::AtomicUsize,
 }
 
 impl DB {
@@ -41,6 +96,46 @@ impl DB {
     /// Return `ExecuteError::DbError` when open db failed
     #[inline]
     pub fn open(config: &EngineConfig) -> Result<Arc<Self>, ExecuteError> {
+        Self::open_with_value_dedup(config, 0)
+    }
+
+    /// Create a new `DB` which stores values of at least `dedup_value_threshold` bytes
+    /// only once, the values are reference counted and shared among the revisions.
+    /// The deduplication is disabled if the threshold is 0.
+    ///
+    /// # Errors
+    /// Return `ExecuteError::DbError` when open db failed
+    #[inline]
+    pub fn open_with_value_dedup(
+        config: &EngineConfig,
+        dedup_value_threshold: u64,
+    ) -> Result<Arc<Self>, ExecuteError> {
+        Self::open_with_options(config, dedup_value_threshold, false, &[], None)
+    }
+
+    /// Create a new `DB` with the value deduplication threshold, whether the writes
+    /// are synced, the key prefixes whose writes are not durable and the cipher of the
+    /// records. The batches are not synced to the disk unless `sync_writes` is set, in
+    /// which case a batch which only writes keys under the non-durable prefixes is
+    /// still not synced, but the other batches are. The non-durable prefixes have no
+    /// effect if the writes are not synced.
+    ///
+    /// If the cipher is provided, the records of the kv table are encrypted when they
+    /// are written and decrypted when they are read, so everything above the backend,
+    /// the hashes included, sees the plaintext records. The deduplicated values of the
+    /// encrypted records are encrypted as well, and the snapshots are taken and
+    /// installed with the plaintext records, so the members may have different keys.
+    ///
+    /// # Errors
+    /// Return `ExecuteError::DbError` when open db failed
+    #[inline]
+    pub fn open_with_options(
+        config: &EngineConfig,
+        dedup_value_threshold: u64,
+        sync_writes: bool,
+        non_durable_prefixes: &[String],
+        cipher: Option<RecordCipher>,
+    ) -> Result<Arc<Self>, ExecuteError> {
         let engine_type = match *config {
             EngineConfig::Memory => EngineType::Memory,
             EngineConfig::RocksDB(ref path) => EngineType::Rocks(path.clone()),
@@ -48,9 +143,302 @@ impl DB {
         };
         let engine = Engine::new(engine_type, &XLINE_TABLES)
             .map_err(|e| ExecuteError::DbError(format!("Cannot open database: {e}")))?;
-        Ok(Arc::new(Self {
+        let may_be_encrypted = cipher.is_some() || has_encrypted_records(&engine)?;
+        let db = Self {
             engine: Arc::new(engine),
-        }))
+            dedup_value_threshold: dedup_value_threshold.numeric_cast(),
+            value_ref_lock: Mutex::new(()),
+            sync_writes,
+            non_durable_prefixes: non_durable_prefixes
+                .iter()
+                .map(|p| p.as_bytes().to_vec())
+                .collect(),
+            last_synced_at: Mutex::new(None),
+            write_failure: Mutex::new(None),
+            cipher,
+            may_be_encrypted: AtomicBool::new(may_be_encrypted),
+            #[cfg(test)]
+            synced_flushes: std::sync::atomic::AtomicUsize::new(0),
+        };
+        db.mark_encrypted_records()?;
+        Ok(Arc::new(db))
+    }
+
+    /// Mark the storage as one whose records have been encrypted if the cipher is
+    /// provided, the marker outlives the cipher so that the encrypted records are
+    /// still detected if the storage is opened without the keys. The marker is always
+    /// synced to the disk, whether the writes are synced or not.
+    fn mark_encrypted_records(&self) -> Result<(), ExecuteError> {
+        if self.cipher.is_none() || has_encrypted_records(self.engine.as_ref())? {
+            return Ok(());
+        }
+        let op =
+            WriteOperation::new_put(META_TABLE, ENCRYPTED_RECORDS.as_bytes().to_vec(), vec![1]);
+        self.write_batch(vec![op], true, "Failed to mark the encryption")
+    }
+
+    /// Write a batch to the engine and record the outcome for the health of the backend
+    fn write_batch(
+        &self,
+        wr_ops: Vec<WriteOperation<'_>>,
+        sync: bool,
+        context: &str,
+    ) -> Result<(), ExecuteError> {
+        if let Err(e) = self.engine.write_batch(wr_ops, sync) {
+            let err = format!("{context}, error: {e}");
+            *self.write_failure.lock() = Some(err.clone());
+            return Err(ExecuteError::DbError(err));
+        }
+        *self.write_failure.lock() = None;
+        if sync {
+            *self.last_synced_at.lock() = Some(SystemTime::now());
+        }
+        Ok(())
+    }
+
+    /// Check whether a batch must be synced to the disk, that is the writes are synced
+    /// and it writes something other than the keys under the non-durable prefixes and
+    /// the applied index
+    fn needs_sync(&self, ops: &[WriteOp]) -> bool {
+        self.sync_writes
+            && ops.iter().any(|op| match *op {
+                WriteOp::PutKeyValue(_, ref kv) => !self
+                    .non_durable_prefixes
+                    .iter()
+                    .any(|prefix| kv.key.starts_with(prefix)),
+                WriteOp::PutAppliedIndex(_) => false,
+                WriteOp::PutLease(_)
+                | WriteOp::PutFinishedCompactRevision(_)
+                | WriteOp::PutScheduledCompactRevision(_)
+                | WriteOp::PutReservedRevision(_)
+                | WriteOp::PutMemberTags(..)
+                | WriteOp::DeleteMemberTags(_)
+                | WriteOp::PutHistoryChange(..)
+                | WriteOp::DeleteHistoryChange(..)
+                | WriteOp::DeleteKeyValue(_)
+                | WriteOp::DeleteLease(_)
+                | WriteOp::PutAuthEnable(_)
+                | WriteOp::PutAuthRevision(_)
+                | WriteOp::PutUser(_)
+                | WriteOp::DeleteUser(_)
+                | WriteOp::PutRole(_)
+                | WriteOp::DeleteRole(_)
+                | WriteOp::PutAlarm(_)
+                | WriteOp::DeleteAlarm(_) => true,
+            })
+    }
+
+    /// Encode a key-value pair for the kv table, a value which should be deduplicated
+    /// is replaced by its reference and its digest is returned with it
+    fn encode_kv(
+        &self,
+        mut kv: KeyValue,
+    ) -> Result<(Vec<u8>, Option<(Vec<u8>, Vec<u8>)>), ExecuteError> {
+        if self.dedup_value_threshold == 0 || kv.value.len() < self.dedup_value_threshold {
+            let buf = kv.encode_to_vec();
+            return match self.cipher {
+                Some(ref cipher) => Ok((cipher.encrypt(&buf)?, None)),
+                None => Ok((buf, None)),
+            };
+        }
+        let value = std::mem::take(&mut kv.value);
+        let digest = match self.cipher {
+            Some(ref cipher) => cipher.digest(&value),
+            None => Sha256::digest(&value).to_vec(),
+        };
+        let value_ref = ValueRef { digest };
+        let mut buf = kv.encode_to_vec();
+        buf.extend(value_ref.encode_to_vec());
+        match self.cipher {
+            Some(ref cipher) => Ok((
+                cipher.encrypt(&buf)?,
+                Some((value_ref.digest, cipher.encrypt(&value)?)),
+            )),
+            None => Ok((buf, Some((value_ref.digest, value)))),
+        }
+    }
+
+    /// Get the digests of the values referenced by the deleted revisions
+    fn deleted_value_refs(&self, ops: &[WriteOp]) -> Result<Vec<Vec<u8>>, ExecuteError> {
+        let revs: Vec<_> = ops
+            .iter()
+            .filter_map(|op| {
+                if let WriteOp::DeleteKeyValue(rev) = *op {
+                    Some(rev)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if revs.is_empty() {
+            return Ok(vec![]);
+        }
+        let digests = self
+            .get_raw_values(KV_TABLE, &revs)?
+            .into_iter()
+            .flatten()
+            .map(|raw| self.decrypt(raw))
+            .filter_map(|record| {
+                record
+                    .map(|record| {
+                        ValueRef::decode(record.as_slice())
+                            .ok()
+                            .map(|value_ref| value_ref.digest)
+                            .filter(|digest| !digest.is_empty())
+                    })
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(digests)
+    }
+
+    /// Update the reference counts of the values, a value is removed when it is no
+    /// longer referenced
+    fn update_value_refs(
+        &self,
+        deltas: ValueRefDeltas,
+        wr_ops: &mut Vec<WriteOperation<'_>>,
+    ) -> Result<(), ExecuteError> {
+        for (digest, (delta, value)) in deltas {
+            let stored = self
+                .get_raw_values(VALUE_TABLE, &[&digest])?
+                .pop()
+                .flatten();
+            let (count, value) = match stored {
+                Some(stored) => {
+                    let (count, _) = split_ref_count(&stored)?;
+                    (count, stored)
+                }
+                None => match value {
+                    Some(value) => (0, [0u64.to_le_bytes().as_slice(), &value].concat()),
+                    // the value has been removed
+                    None => continue,
+                },
+            };
+            let count: i64 = count.numeric_cast();
+            let new_count = count.overflow_add(delta);
+            if new_count <= 0 {
+                wr_ops.push(WriteOperation::new_delete(VALUE_TABLE, digest));
+            } else {
+                let mut buf = Vec::with_capacity(value.len());
+                buf.extend_from_slice(&u64::numeric_cast(new_count).to_le_bytes());
+                buf.extend_from_slice(value.get(REF_COUNT_SIZE..).unwrap_or_default());
+                wr_ops.push(WriteOperation::new_put(VALUE_TABLE, digest, buf));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the raw values from the engine
+    fn get_raw_values<K>(
+        &self,
+        table: &'static str,
+        keys: &[K],
+    ) -> Result<Vec<Option<Vec<u8>>>, ExecuteError>
+    where
+        K: AsRef<[u8]> + std::fmt::Debug + Sized,
+    {
+        let values = self
+            .engine
+            .get_multi(table, keys)
+            .map_err(|e| ExecuteError::DbError(format!("Failed to get keys {keys:?}: {e}")))?
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        assert_eq!(values.len(), keys.len(), "Index doesn't match with DB");
+
+        Ok(values)
+    }
+
+    /// Resolve the deduplicated value of a raw value of the table
+    fn resolve(&self, table: &str, raw: Vec<u8>) -> Result<Vec<u8>, ExecuteError> {
+        if table != KV_TABLE {
+            return Ok(raw);
+        }
+        // the deduplicated value of a record is encrypted iff the record is
+        match self.cipher {
+            Some(ref cipher) if is_encrypted(&raw) => {
+                resolve_value_ref(self.engine.as_ref(), cipher.decrypt(raw)?, Some(cipher))
+            }
+            Some(_) | None => resolve_value_ref(self.engine.as_ref(), self.decrypt(raw)?, None),
+        }
+    }
+
+    /// Decrypt a raw value of the kv table
+    fn decrypt(&self, raw: Vec<u8>) -> Result<Vec<u8>, ExecuteError> {
+        match self.cipher {
+            Some(ref cipher) => cipher.decrypt(raw),
+            None if self.may_be_encrypted.load(Ordering::Relaxed) => ensure_plaintext(raw),
+            None => Ok(raw),
+        }
+    }
+
+    /// Get a snapshot of the plaintext records. The records are decrypted, with their
+    /// deduplicated values filled back, into a staging engine whose snapshot is taken,
+    /// so neither the ciphertexts nor the digests of the keys leave the member.
+    fn get_plaintext_snapshot(&self, snap_path: &Path) -> Result<Snapshot, ExecuteError> {
+        let staging_path = snap_path.with_extension("plaintext");
+        let engine_type = match *self.engine {
+            Engine::Memory(_) => EngineType::Memory,
+            _ => EngineType::Rocks(staging_path.clone()),
+        };
+        // a staging engine left by a failed snapshot is discarded
+        let _ignore = std::fs::remove_dir_all(&staging_path);
+        let staging = Engine::new(engine_type, &XLINE_TABLES).map_err(|e| {
+            ExecuteError::DbError(format!("Failed to open the snapshot staging: {e}"))
+        })?;
+        let mut wr_ops = Vec::new();
+        for table in XLINE_TABLES {
+            if table == VALUE_TABLE {
+                continue;
+            }
+            for (key, value) in self.get_all(table)? {
+                if table == META_TABLE && key == ENCRYPTED_RECORDS.as_bytes() {
+                    continue;
+                }
+                wr_ops.push(WriteOperation::new_put(table, key, value));
+            }
+        }
+        let snapshot = staging
+            .write_batch(wr_ops, false)
+            .and_then(|()| staging.get_snapshot(snap_path, &XLINE_TABLES))
+            .map_err(|e| ExecuteError::DbError(format!("Failed to get snapshot, error: {e}")));
+        drop(staging);
+        let _ignore = std::fs::remove_dir_all(&staging_path);
+        snapshot
+    }
+
+    /// Encrypt the plaintext records installed by a snapshot, their values are filled
+    /// back and deduplicated again with the digests of the keys
+    fn encrypt_installed_records(&self) -> Result<(), ExecuteError> {
+        let mut values: HashMap<Vec<u8>, (u64, Vec<u8>)> = HashMap::new();
+        let mut wr_ops = Vec::new();
+        let stale_values = self.engine.get_all(VALUE_TABLE).map_err(|e| {
+            ExecuteError::DbError(format!("Failed to get all keys from {VALUE_TABLE:?}: {e}"))
+        })?;
+        for (digest, _) in &stale_values {
+            wr_ops.push(WriteOperation::new_delete(VALUE_TABLE, digest));
+        }
+        for (key, record) in self.get_all(KV_TABLE)? {
+            let kv = KeyValue::decode(record.as_slice())
+                .map_err(|e| ExecuteError::DbError(format!("Failed to decode key value: {e}")))?;
+            let (encoded, value_ref) = self.encode_kv(kv)?;
+            if let Some((digest, value)) = value_ref {
+                let entry = values.entry(digest).or_insert((0, value));
+                entry.0 = entry.0.overflow_add(1);
+            }
+            wr_ops.push(WriteOperation::new_put(KV_TABLE, key, encoded));
+        }
+        for (digest, (count, value)) in values {
+            let stored = [count.to_le_bytes().as_slice(), &value].concat();
+            wr_ops.push(WriteOperation::new_put(VALUE_TABLE, digest, stored));
+        }
+        wr_ops.push(WriteOperation::new_put(
+            META_TABLE,
+            ENCRYPTED_RECORDS.as_bytes().to_vec(),
+            vec![1],
+        ));
+        self.write_batch(wr_ops, self.sync_writes, "Failed to encrypt the records")
     }
 
     /// Get del lease key buffer
@@ -67,6 +455,34 @@ impl DB {
             .collect::<HashMap<_, _>>()
     }
 
+    /// Get del history key buffer
+    #[inline]
+    fn get_del_history_key_buffer(ops: &[WriteOp]) -> HashMap<(&'static str, i64), Vec<u8>> {
+        ops.iter()
+            .filter_map(|op| {
+                if let WriteOp::DeleteHistoryChange(name, rev) = *op {
+                    Some(((name, rev), history_key(name, rev)))
+                } else {
+                    None
+                }
+            })
+            .collect::<HashMap<_, _>>()
+    }
+
+    /// Get del member tags key buffer
+    #[inline]
+    fn get_del_member_tags_key_buffer(ops: &[WriteOp]) -> HashMap<u64, Vec<u8>> {
+        ops.iter()
+            .filter_map(|op| {
+                if let WriteOp::DeleteMemberTags(id) = *op {
+                    Some((id, tags_key(id)))
+                } else {
+                    None
+                }
+            })
+            .collect::<HashMap<_, _>>()
+    }
+
     /// get del alarm buffer
     #[inline]
     fn get_del_alarm_buffer(ops: &[WriteOp]) -> Vec<u8> {
@@ -91,16 +507,10 @@ impl StorageApi for DB {
     where
         K: AsRef<[u8]> + std::fmt::Debug + Sized,
     {
-        let values = self
-            .engine
-            .get_multi(table, keys)
-            .map_err(|e| ExecuteError::DbError(format!("Failed to get keys {keys:?}: {e}")))?
+        self.get_raw_values(table, keys)?
             .into_iter()
-            .collect::<Vec<_>>();
-
-        assert_eq!(values.len(), keys.len(), "Index doesn't match with DB");
-
-        Ok(values)
+            .map(|value| value.map(|raw| self.resolve(table, raw)).transpose())
+            .collect()
     }
 
     fn get_value<K>(&self, table: &'static str, key: K) -> Result<Option<Vec<u8>>, ExecuteError>
@@ -109,16 +519,26 @@ impl StorageApi for DB {
     {
         self.engine
             .get(table, key.as_ref())
-            .map_err(|e| ExecuteError::DbError(format!("Failed to get key {key:?}: {e}")))
+            .map_err(|e| ExecuteError::DbError(format!("Failed to get key {key:?}: {e}")))?
+            .map(|raw| self.resolve(table, raw))
+            .transpose()
     }
 
     fn get_all(&self, table: &'static str) -> Result<Vec<(Vec<u8>, Vec<u8>)>, ExecuteError> {
-        self.engine.get_all(table).map_err(|e| {
-            ExecuteError::DbError(format!("Failed to get all keys from {table:?}: {e}"))
-        })
+        self.engine
+            .get_all(table)
+            .map_err(|e| {
+                ExecuteError::DbError(format!("Failed to get all keys from {table:?}: {e}"))
+            })?
+            .into_iter()
+            .map(|(key, raw)| Ok((key, self.resolve(table, raw)?)))
+            .collect()
     }
 
     fn get_snapshot(&self, snap_path: impl AsRef<Path>) -> Result<Snapshot, ExecuteError> {
+        if self.may_be_encrypted.load(Ordering::Relaxed) {
+            return self.get_plaintext_snapshot(snap_path.as_ref());
+        }
         self.engine
             .get_snapshot(snap_path, &XLINE_TABLES)
             .map_err(|e| ExecuteError::DbError(format!("Failed to get snapshot, error: {e}")))
@@ -129,7 +549,17 @@ impl StorageApi for DB {
             self.engine
                 .apply_snapshot(snap, &XLINE_TABLES)
                 .await
-                .map_err(|e| ExecuteError::DbError(format!("Failed to reset database, error: {e}")))
+                .map_err(|e| {
+                    ExecuteError::DbError(format!("Failed to reset database, error: {e}"))
+                })?;
+            // the records of a snapshot are plaintext
+            self.may_be_encrypted
+                .store(self.cipher.is_some(), Ordering::Relaxed);
+            if self.cipher.is_some() {
+                let _value_ref_guard = self.value_ref_lock.lock();
+                self.encrypt_installed_records()?;
+            }
+            Ok(())
         } else {
             let start = vec![];
             let end = vec![0xff];
@@ -139,9 +569,11 @@ impl StorageApi for DB {
                     WriteOperation::new_delete_range(table, start.as_slice(), end.as_slice())
                 })
                 .collect();
-            self.engine
-                .write_batch(ops, true)
-                .map_err(|e| ExecuteError::DbError(format!("Failed to reset database, error: {e}")))
+            // a reset is always synced to the disk, whether the writes are synced or not
+            self.write_batch(ops, true, "Failed to reset database")?;
+            self.may_be_encrypted
+                .store(self.cipher.is_some(), Ordering::Relaxed);
+            self.mark_encrypted_records()
         }
     }
 
@@ -150,6 +582,20 @@ impl StorageApi for DB {
         let mut revs = Vec::new();
         let del_lease_key_buffer = Self::get_del_lease_key_buffer(&ops);
         let del_alarm_buffer = Self::get_del_alarm_buffer(&ops);
+        let del_history_key_buffer = Self::get_del_history_key_buffer(&ops);
+        let del_member_tags_key_buffer = Self::get_del_member_tags_key_buffer(&ops);
+        let sync = self.needs_sync(&ops);
+        let mut value_ref_deltas = ValueRefDeltas::new();
+        // the reference counts are read and updated under the lock
+        let _value_ref_guard = (self.dedup_value_threshold != 0
+            || ops
+                .iter()
+                .any(|op| matches!(*op, WriteOp::DeleteKeyValue(_))))
+        .then(|| self.value_ref_lock.lock());
+        for digest in self.deleted_value_refs(&ops)? {
+            let entry = value_ref_deltas.entry(digest).or_insert((0, None));
+            entry.0 = entry.0.overflow_sub(1);
+        }
         for op in ops {
             let wop = match op {
                 WriteOp::PutKeyValue(rev, value) => {
@@ -163,7 +609,13 @@ impl StorageApi for DB {
                         ),
                     ));
                     let key = rev.encode_to_vec();
-                    WriteOperation::new_put(KV_TABLE, key, value.encode_to_vec())
+                    let (encoded, value_ref) = self.encode_kv(value)?;
+                    if let Some((digest, value)) = value_ref {
+                        let entry = value_ref_deltas.entry(digest).or_insert((0, None));
+                        entry.0 = entry.0.overflow_add(1);
+                        entry.1 = Some(value);
+                    }
+                    WriteOperation::new_put(KV_TABLE, key, encoded)
                 }
                 WriteOp::PutAppliedIndex(index) => WriteOperation::new_put(
                     META_TABLE,
@@ -185,6 +637,29 @@ impl StorageApi for DB {
                     SCHEDULED_COMPACT_REVISION.as_bytes().to_vec(),
                     rev.to_le_bytes().to_vec(),
                 ),
+                WriteOp::PutReservedRevision(rev) => WriteOperation::new_put(
+                    META_TABLE,
+                    RESERVED_REVISION.as_bytes().to_vec(),
+                    rev.to_le_bytes().to_vec(),
+                ),
+                WriteOp::PutMemberTags(id, tags) => {
+                    WriteOperation::new_put(META_TABLE, tags_key(id), tags)
+                }
+                WriteOp::DeleteMemberTags(id) => {
+                    let key = del_member_tags_key_buffer.get(&id).unwrap_or_else(|| {
+                        panic!("member({id}) is not in del_member_tags_key_buffer")
+                    });
+                    WriteOperation::new_delete(META_TABLE, key)
+                }
+                WriteOp::PutHistoryChange(name, rev, change) => {
+                    WriteOperation::new_put(META_TABLE, history_key(name, rev), change)
+                }
+                WriteOp::DeleteHistoryChange(name, rev) => {
+                    let key = del_history_key_buffer.get(&(name, rev)).unwrap_or_else(|| {
+                        panic!("{name} revision({rev}) is not in del_history_key_buffer")
+                    });
+                    WriteOperation::new_delete(META_TABLE, key)
+                }
                 WriteOp::DeleteKeyValue(rev) => WriteOperation::new_delete(KV_TABLE, rev),
                 WriteOp::DeleteLease(lease_id) => {
                     let key = del_lease_key_buffer.get(&lease_id).unwrap_or_else(|| {
@@ -226,9 +701,14 @@ impl StorageApi for DB {
             };
             wr_ops.push(wop);
         }
-        self.engine
-            .write_batch(wr_ops, false)
-            .map_err(|e| ExecuteError::DbError(format!("Failed to flush ops, error: {e}")))?;
+        self.update_value_refs(value_ref_deltas, &mut wr_ops)?;
+        self.write_batch(wr_ops, sync, "Failed to flush ops")?;
+        #[cfg(test)]
+        if sync {
+            let _prev = self
+                .synced_flushes
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
         Ok(revs)
     }
 
@@ -240,6 +720,14 @@ impl StorageApi for DB {
                 ExecuteError::DbError(format!("Failed to get all keys from {table:?}: {e}"))
             })?;
             for (k, v) in kv_pairs {
+                // the records are encrypted with random data keys by each member and
+                // their values may be deduplicated, so the plaintext records are hashed
+                if table == VALUE_TABLE
+                    || (table == META_TABLE && k == ENCRYPTED_RECORDS.as_bytes())
+                {
+                    continue;
+                }
+                let v = self.resolve(table, v)?;
                 hasher.update(&k);
                 hasher.update(&v);
             }
@@ -256,6 +744,77 @@ impl StorageApi for DB {
             .file_size()
             .map_err(|e| ExecuteError::DbError(format!("Failed to get file size, error: {e}")))
     }
+
+    fn live_data_size(&self) -> Result<u64, ExecuteError> {
+        self.engine
+            .live_data_size()
+            .map_err(|e| ExecuteError::DbError(format!("Failed to get live data size, error: {e}")))
+    }
+
+    fn defragment(&self) -> Result<(), ExecuteError> {
+        self.engine
+            .defragment()
+            .map_err(|e| ExecuteError::DbError(format!("Failed to defragment, error: {e}")))
+    }
+
+    fn last_synced_at(&self) -> Option<SystemTime> {
+        *self.last_synced_at.lock()
+    }
+
+    fn write_failure(&self) -> Option<String> {
+        self.write_failure.lock().clone()
+    }
+}
+
+/// Split a stored value of the value table into its reference count and value
+fn split_ref_count(stored: &[u8]) -> Result<(u64, &[u8]), ExecuteError> {
+    if stored.len() < REF_COUNT_SIZE {
+        return Err(ExecuteError::DbError(
+            "deduplicated value is corrupted".to_owned(),
+        ));
+    }
+    let (count, value) = stored.split_at(REF_COUNT_SIZE);
+    let count = count
+        .try_into()
+        .map(u64::from_le_bytes)
+        .map_err(|_e| ExecuteError::DbError("deduplicated value is corrupted".to_owned()))?;
+    Ok((count, value))
+}
+
+/// Resolve the value reference of a raw value of the kv table, the referenced value
+/// is filled back into the key-value pair. The cipher is the one of an encrypted
+/// record, whose referenced value is encrypted as well.
+pub(crate) fn resolve_value_ref<E: StorageEngine>(
+    engine: &E,
+    raw: Vec<u8>,
+    cipher: Option<&RecordCipher>,
+) -> Result<Vec<u8>, ExecuteError> {
+    let digest = ValueRef::decode(raw.as_slice())
+        .map(|value_ref| value_ref.digest)
+        .unwrap_or_default();
+    if digest.is_empty() {
+        return Ok(raw);
+    }
+    let mut kv = KeyValue::decode(raw.as_slice())
+        .map_err(|e| ExecuteError::DbError(format!("Failed to decode key value: {e}")))?;
+    let stored = engine
+        .get(VALUE_TABLE, &digest)
+        .map_err(|e| ExecuteError::DbError(format!("Failed to get value {digest:?}: {e}")))?
+        .ok_or_else(|| ExecuteError::DbError(format!("Value {digest:?} is missing")))?;
+    let value = split_ref_count(&stored)?.1.to_vec();
+    kv.value = match cipher {
+        Some(cipher) => cipher.decrypt(value)?,
+        None => value,
+    };
+    Ok(kv.encode_to_vec())
+}
+
+/// Check whether the records of a storage have ever been encrypted
+pub(crate) fn has_encrypted_records<E: StorageEngine>(engine: &E) -> Result<bool, ExecuteError> {
+    engine
+        .get(META_TABLE, ENCRYPTED_RECORDS)
+        .map(|marker| marker.is_some())
+        .map_err(|e| ExecuteError::DbError(format!("Failed to get the encryption marker: {e}")))
 }
 
 /// Buffered Write Operation
@@ -272,6 +831,16 @@ pub enum WriteOp<'a> {
     PutFinishedCompactRevision(i64),
     /// Put a scheduled compact revision into meta table
     PutScheduledCompactRevision(i64),
+    /// Put the last reserved revision into meta table
+    PutReservedRevision(i64),
+    /// Put the tags of a member in json into meta table
+    PutMemberTags(u64, Vec<u8>),
+    /// Delete the tags of a member from meta table
+    DeleteMemberTags(u64),
+    /// Put a change of the named history at a history revision into meta table
+    PutHistoryChange(&'static str, i64, Vec<u8>),
+    /// Delete a change of the named history at a history revision from meta table
+    DeleteHistoryChange(&'static str, i64),
     /// Delete a key-value pair from kv table
     DeleteKeyValue(&'a [u8]),
     /// Delete a lease from lease table
@@ -302,7 +871,7 @@ mod test {
     use test_macros::abort_on_panic;
 
     use super::*;
-    use crate::storage::Revision;
+    use crate::storage::{encryption::KEY_LEN, Revision};
     #[tokio::test]
     #[abort_on_panic]
     async fn test_reset() -> Result<(), ExecuteError> {
@@ -319,8 +888,10 @@ mod test {
         _ = db.flush_ops(ops)?;
         let res = db.get_value(KV_TABLE, &key)?;
         assert_eq!(res, Some(kv.encode_to_vec()));
+        assert!(db.last_synced_at().is_none());
 
         db.reset(None).await?;
+        assert!(db.last_synced_at().is_some());
 
         let res = db.get_values(KV_TABLE, &[&key])?;
         assert_eq!(res, vec![None]);
@@ -466,4 +1037,357 @@ mod test {
         assert_eq!(db.get_value(USER_TABLE, b"user").unwrap(), None);
         assert_eq!(db.get_value(ROLE_TABLE, b"role").unwrap(), None);
     }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn writes_to_non_durable_prefixes_should_not_be_synced() {
+        let db = DB::open_with_options(
+            &EngineConfig::Memory,
+            0,
+            true,
+            &["session/".to_owned()],
+            None,
+        )
+        .unwrap();
+        let synced = || db.synced_flushes.load(std::sync::atomic::Ordering::Relaxed);
+        let put = |key: &str, rev: i64| {
+            WriteOp::PutKeyValue(
+                Revision::new(rev, 0),
+                KeyValue {
+                    key: key.as_bytes().to_vec(),
+                    value: b"v".to_vec(),
+                    ..Default::default()
+                },
+            )
+        };
+
+        _ = db
+            .flush_ops(vec![put("session/a", 1), WriteOp::PutAppliedIndex(1)])
+            .unwrap();
+        assert_eq!(synced(), 0);
+        _ = db
+            .flush_ops(vec![put("session/b", 2), put("config/a", 2)])
+            .unwrap();
+        assert_eq!(synced(), 1);
+        _ = db.flush_ops(vec![put("config/b", 3)]).unwrap();
+        assert_eq!(synced(), 2);
+        _ = db
+            .flush_ops(vec![WriteOp::PutLease(PbLease::default())])
+            .unwrap();
+        assert_eq!(synced(), 3);
+
+        let durable = DB::open_with_options(&EngineConfig::Memory, 0, true, &[], None).unwrap();
+        _ = durable.flush_ops(vec![put("session/a", 1)]).unwrap();
+        assert_eq!(
+            durable
+                .synced_flushes
+                .load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+
+        // the writes are not synced by default, whatever they write
+        let unsynced = DB::open_with_options(
+            &EngineConfig::Memory,
+            0,
+            false,
+            &["session/".to_owned()],
+            None,
+        )
+        .unwrap();
+        _ = unsynced
+            .flush_ops(vec![
+                put("config/a", 1),
+                WriteOp::PutLease(PbLease::default()),
+            ])
+            .unwrap();
+        assert_eq!(
+            unsynced
+                .synced_flushes
+                .load(std::sync::atomic::Ordering::Relaxed),
+            0
+        );
+    }
+
+    /// Put the value under `count` keys, one revision each
+    fn put_values(db: &DB, value: &[u8], count: i64) {
+        let ops = (1..=count)
+            .map(|i| {
+                let kv = KeyValue {
+                    key: format!("key{i}").into_bytes(),
+                    value: value.to_vec(),
+                    create_revision: i,
+                    mod_revision: i,
+                    version: 1,
+                    lease: 0,
+                };
+                WriteOp::PutKeyValue(Revision::new(i, 0), kv)
+            })
+            .collect();
+        _ = db.flush_ops(ops).unwrap();
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn test_identical_values_should_be_stored_once() {
+        let dedup_dir = PathBuf::from("/tmp/test_value_dedup");
+        let plain_dir = PathBuf::from("/tmp/test_value_no_dedup");
+        let dedup_db =
+            DB::open_with_value_dedup(&EngineConfig::RocksDB(dedup_dir.clone()), 1024).unwrap();
+        let plain_db = DB::open(&EngineConfig::RocksDB(plain_dir.clone())).unwrap();
+        let value: Vec<u8> = (0..64 * 1024).map(|_| rand::random()).collect();
+        put_values(&dedup_db, &value, 50);
+        put_values(&plain_db, &value, 50);
+
+        let value_size = value.len() as u64;
+        assert!(dedup_db.file_size().unwrap() < 4 * value_size);
+        assert!(plain_db.file_size().unwrap() > 40 * value_size);
+        let stored = dedup_db.engine.get_all(VALUE_TABLE).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(
+            split_ref_count(&stored[0].1).unwrap(),
+            (50, value.as_slice())
+        );
+
+        // overwrite key1 with a small value, the old revision still references the value
+        let kv = KeyValue {
+            key: b"key1".to_vec(),
+            value: b"small".to_vec(),
+            create_revision: 1,
+            mod_revision: 51,
+            version: 2,
+            lease: 0,
+        };
+        _ = dedup_db
+            .flush_ops(vec![WriteOp::PutKeyValue(Revision::new(51, 0), kv.clone())])
+            .unwrap();
+        let res = dedup_db
+            .get_values(
+                KV_TABLE,
+                &[
+                    Revision::new(1, 0).encode_to_vec(),
+                    Revision::new(51, 0).encode_to_vec(),
+                ],
+            )
+            .unwrap();
+        let old = KeyValue::decode(res[0].as_ref().unwrap().as_slice()).unwrap();
+        assert_eq!(old.value, value);
+        assert_eq!(res[1], Some(kv.encode_to_vec()));
+
+        // compacting the old revision decrements the reference count
+        let old_rev = Revision::new(1, 0).encode_to_vec();
+        _ = dedup_db
+            .flush_ops(vec![WriteOp::DeleteKeyValue(&old_rev)])
+            .unwrap();
+        let stored = dedup_db.engine.get_all(VALUE_TABLE).unwrap();
+        assert_eq!(split_ref_count(&stored[0].1).unwrap().0, 49);
+        let kvs = dedup_db.get_all(KV_TABLE).unwrap();
+        assert_eq!(kvs.len(), 50);
+        assert!(kvs
+            .iter()
+            .filter(|&&(ref rev, _)| Revision::decode(rev).revision() != 51)
+            .all(|&(_, ref raw)| KeyValue::decode(raw.as_slice()).unwrap().value == value));
+
+        // the value is removed after all revisions referencing it are compacted
+        let revs: Vec<_> = (2..=50)
+            .map(|i| Revision::new(i, 0).encode_to_vec())
+            .collect();
+        let ops = revs
+            .iter()
+            .map(|rev| WriteOp::DeleteKeyValue(rev.as_slice()))
+            .collect();
+        _ = dedup_db.flush_ops(ops).unwrap();
+        assert!(dedup_db.engine.get_all(VALUE_TABLE).unwrap().is_empty());
+
+        drop(dedup_db);
+        drop(plain_db);
+        std::fs::remove_dir_all(dedup_dir).unwrap();
+        std::fs::remove_dir_all(plain_dir).unwrap();
+    }
+
+    /// Put the value of the key `key` at the revision
+    fn put_value(db: &DB, rev: i64, value: &[u8]) {
+        let kv = KeyValue {
+            key: b"key".to_vec(),
+            value: value.to_vec(),
+            create_revision: 1,
+            mod_revision: rev,
+            version: rev,
+            lease: 0,
+        };
+        _ = db
+            .flush_ops(vec![WriteOp::PutKeyValue(Revision::new(rev, 0), kv)])
+            .unwrap();
+    }
+
+    /// Get the value at the revision
+    fn value_at(db: &DB, rev: i64) -> Result<Vec<u8>, ExecuteError> {
+        let raw = db
+            .get_value(KV_TABLE, Revision::new(rev, 0).encode_to_vec())?
+            .unwrap();
+        Ok(KeyValue::decode(raw.as_slice()).unwrap().value)
+    }
+
+    /// Open the db at the path with the value deduplication threshold and the cipher
+    /// of the keys
+    fn open_encrypted(
+        path: &Path,
+        dedup_value_threshold: u64,
+        active_key: &str,
+        keys: &[(&str, u8)],
+    ) -> Arc<DB> {
+        let keys = keys
+            .iter()
+            .map(|&(id, byte)| (id.to_owned(), [byte; KEY_LEN]))
+            .collect();
+        let cipher = RecordCipher::new(active_key.to_owned(), keys).unwrap();
+        DB::open_with_options(
+            &EngineConfig::RocksDB(path.to_owned()),
+            dedup_value_threshold,
+            false,
+            &[],
+            Some(cipher),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn encrypted_records_should_be_read_as_plaintext_after_restarts() {
+        let dir = PathBuf::from("/tmp/test_encryption_at_rest");
+        let plain_db = DB::open(&EngineConfig::Memory).unwrap();
+        let db = DB::open(&EngineConfig::RocksDB(dir.clone())).unwrap();
+        put_value(&db, 1, b"plain value");
+        put_value(&plain_db, 1, b"plain value");
+        drop(db);
+
+        let db = open_encrypted(&dir, 0, "k1", &[("k1", 1)]);
+        put_value(&db, 2, b"secret value");
+        put_value(&plain_db, 2, b"secret value");
+        let raw = db
+            .engine
+            .get(KV_TABLE, Revision::new(2, 0).encode_to_vec())
+            .unwrap()
+            .unwrap();
+        assert!(!raw.windows(12).any(|w| w == b"secret value"));
+        drop(db);
+
+        // the old plaintext records live along with the encrypted ones
+        let db = open_encrypted(&dir, 0, "k1", &[("k1", 1)]);
+        assert_eq!(value_at(&db, 1).unwrap(), b"plain value");
+        assert_eq!(value_at(&db, 2).unwrap(), b"secret value");
+        assert_eq!(
+            db.get_all(KV_TABLE).unwrap(),
+            plain_db.get_all(KV_TABLE).unwrap()
+        );
+        assert_eq!(db.hash().unwrap(), plain_db.hash().unwrap());
+        drop(db);
+
+        let db = DB::open(&EngineConfig::RocksDB(dir.clone())).unwrap();
+        assert_eq!(value_at(&db, 1).unwrap(), b"plain value");
+        assert!(value_at(&db, 2).is_err());
+        drop(db);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn records_should_be_readable_after_the_key_is_rotated() {
+        let dir = PathBuf::from("/tmp/test_encryption_key_rotation");
+        let db = open_encrypted(&dir, 0, "k1", &[("k1", 1)]);
+        put_value(&db, 1, b"value1");
+        drop(db);
+
+        let db = open_encrypted(&dir, 0, "k2", &[("k1", 1), ("k2", 2)]);
+        put_value(&db, 2, b"value2");
+        assert_eq!(value_at(&db, 1).unwrap(), b"value1");
+        assert_eq!(value_at(&db, 2).unwrap(), b"value2");
+        drop(db);
+
+        // the records written after the rotation only need the new key
+        let db = open_encrypted(&dir, 0, "k2", &[("k2", 2)]);
+        assert!(value_at(&db, 1).is_err());
+        assert_eq!(value_at(&db, 2).unwrap(), b"value2");
+        drop(db);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn deduplicated_values_of_encrypted_records_should_be_encrypted() {
+        let dir = PathBuf::from("/tmp/test_encrypted_value_dedup");
+        let db = open_encrypted(&dir, 8, "k1", &[("k1", 1)]);
+        put_value(&db, 1, b"secret large value");
+        put_value(&db, 2, b"secret large value");
+        let stored = db.engine.get_all(VALUE_TABLE).unwrap();
+        assert_eq!(stored.len(), 1);
+        let (digest, value) = stored.first().unwrap();
+        assert!(!value.windows(18).any(|w| w == b"secret large value"));
+        assert_ne!(digest, &Sha256::digest(b"secret large value").to_vec());
+        assert_eq!(value_at(&db, 1).unwrap(), b"secret large value");
+        assert_eq!(value_at(&db, 2).unwrap(), b"secret large value");
+
+        let rev1 = Revision::new(1, 0).encode_to_vec();
+        let rev2 = Revision::new(2, 0).encode_to_vec();
+        _ = db.flush_ops(vec![WriteOp::DeleteKeyValue(&rev1)]).unwrap();
+        assert_eq!(db.engine.get_all(VALUE_TABLE).unwrap().len(), 1);
+        _ = db.flush_ops(vec![WriteOp::DeleteKeyValue(&rev2)]).unwrap();
+        assert!(db.engine.get_all(VALUE_TABLE).unwrap().is_empty());
+        drop(db);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    #[abort_on_panic]
+    async fn snapshots_of_encrypted_records_should_be_plaintext() -> Result<(), ExecuteError> {
+        let dir = PathBuf::from("/tmp/test_encrypted_snapshot");
+        let db = open_encrypted(&dir.join("origin"), 8, "k1", &[("k1", 1)]);
+        put_value(&db, 1, b"small");
+        put_value(&db, 2, b"secret large value");
+
+        // a member without the encryption reads the records of the snapshot
+        let snapshot = db.get_snapshot(dir.join("snapshot1"))?;
+        let plain_db = DB::open(&EngineConfig::RocksDB(dir.join("plain")))?;
+        plain_db.reset(Some(snapshot)).await?;
+        assert!(!has_encrypted_records(plain_db.engine.as_ref())?);
+        assert_eq!(value_at(&plain_db, 1)?, b"small");
+        assert_eq!(value_at(&plain_db, 2)?, b"secret large value");
+        assert_eq!(plain_db.hash()?, db.hash()?);
+
+        // a member with another key encrypts the records of the snapshot again
+        let snapshot = db.get_snapshot(dir.join("snapshot2"))?;
+        let other_db = open_encrypted(&dir.join("other"), 8, "k2", &[("k2", 2)]);
+        other_db.reset(Some(snapshot)).await?;
+        for (_, raw) in other_db.engine.get_all(KV_TABLE).unwrap() {
+            assert!(is_encrypted(&raw));
+        }
+        let stored = other_db.engine.get_all(VALUE_TABLE).unwrap();
+        assert_eq!(stored.len(), 1);
+        assert!(!stored
+            .iter()
+            .any(|(_, v)| v.windows(18).any(|w| w == b"secret large value")));
+        assert_eq!(value_at(&other_db, 1)?, b"small");
+        assert_eq!(value_at(&other_db, 2)?, b"secret large value");
+        assert_eq!(other_db.hash()?, db.hash()?);
+
+        drop(db);
+        drop(plain_db);
+        drop(other_db);
+        std::fs::remove_dir_all(dir).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn records_should_not_be_probed_if_never_encrypted() {
+        let db = DB::open(&EngineConfig::Memory).unwrap();
+        assert!(!db.may_be_encrypted.load(Ordering::Relaxed));
+        let cipher = RecordCipher::new(
+            "k1".to_owned(),
+            HashMap::from([("k1".to_owned(), [1; KEY_LEN])]),
+        )
+        .unwrap();
+        let encrypted = cipher.encrypt(b"record").unwrap();
+        assert_eq!(db.decrypt(encrypted.clone()).unwrap(), encrypted);
+        db.may_be_encrypted.store(true, Ordering::Relaxed);
+        assert!(db.decrypt(encrypted).is_err());
+    }
 }
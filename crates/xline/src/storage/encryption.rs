@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use prost::Message;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use utils::config::EncryptionConfig;
+use xlineapi::execute_error::ExecuteError;
+
+/// Size of the encryption keys and the data keys
+pub(crate) const KEY_LEN: usize = 32;
+
+/// Label of the digest key derived from the active key
+const DIGEST_KEY_LABEL: &[u8] = b"xline value digest";
+
+/// An encrypted record of the kv table. Its fields are unknown to `KeyValue` and
+/// `ValueRef`, so a stored record is encrypted if a ciphertext is decoded from it,
+/// which lets the encrypted records live along with the plaintext ones.
+#[derive(Clone, PartialEq, Eq, Message)]
+struct EncryptedRecord {
+    /// The id of the key the data key is encrypted with
+    #[prost(string, tag = "12")]
+    key_id: String,
+    /// The data key of the record encrypted by the key, prefixed by its nonce
+    #[prost(bytes = "vec", tag = "13")]
+    data_key: Vec<u8>,
+    /// The record encrypted by the data key, prefixed by its nonce
+    #[prost(bytes = "vec", tag = "14")]
+    ciphertext: Vec<u8>,
+}
+
+/// Cipher of the records of the kv table.
+///
+/// It's an envelope encryption, every record is encrypted by a random data key of
+/// its own with AES-256-GCM, and the data key is encrypted by the active key and
+/// stored with the record. The records encrypted by the keys no longer active can
+/// still be decrypted as long as the keys are kept.
+#[derive(Debug)]
+pub struct RecordCipher {
+    /// The id of the key the new records are encrypted with
+    active_key: String,
+    /// The keys by their ids
+    keys: HashMap<String, LessSafeKey>,
+    /// The key of the digests of the deduplicated values, derived from the active key
+    digest_key: hmac::Key,
+    /// The source of the data keys and nonces
+    rng: SystemRandom,
+}
+
+impl RecordCipher {
+    /// Create a new `RecordCipher`
+    ///
+    /// # Errors
+    /// Return `ExecuteError::DbError` if the active key is not one of the keys
+    #[inline]
+    pub fn new(
+        active_key: String,
+        keys: HashMap<String, [u8; KEY_LEN]>,
+    ) -> Result<Self, ExecuteError> {
+        let Some(active) = keys.get(&active_key) else {
+            return Err(ExecuteError::DbError(format!(
+                "the active encryption key {active_key} is not configured"
+            )));
+        };
+        let digest_key = hmac::Key::new(
+            hmac::HMAC_SHA256,
+            hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, active), DIGEST_KEY_LABEL).as_ref(),
+        );
+        let keys: HashMap<_, _> = keys
+            .into_iter()
+            .map(|(id, key)| Ok((id, new_key(&key)?)))
+            .collect::<Result<_, ExecuteError>>()?;
+        Ok(Self {
+            active_key,
+            keys,
+            digest_key,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Create a new `RecordCipher` with the keys read from the key files
+    ///
+    /// # Errors
+    /// Return `ExecuteError::DbError` if a key file can't be read or doesn't hold a key,
+    /// or the active key is not one of the keys
+    #[inline]
+    pub fn from_config(config: &EncryptionConfig) -> Result<Self, ExecuteError> {
+        let keys: HashMap<_, _> = config
+            .key_files()
+            .iter()
+            .map(|(id, path)| {
+                let bytes = std::fs::read(path).map_err(|e| {
+                    ExecuteError::DbError(format!("Failed to read the key file {path:?}: {e}"))
+                })?;
+                let key = <[u8; KEY_LEN]>::try_from(bytes.as_slice()).map_err(|_e| {
+                    ExecuteError::DbError(format!(
+                        "the key file {path:?} should hold {KEY_LEN} bytes, got {}",
+                        bytes.len()
+                    ))
+                })?;
+                Ok((id.clone(), key))
+            })
+            .collect::<Result<_, ExecuteError>>()?;
+        Self::new(config.active_key().clone(), keys)
+    }
+
+    /// Encrypt a record with a new data key, which is encrypted by the active key
+    pub(crate) fn encrypt(&self, record: &[u8]) -> Result<Vec<u8>, ExecuteError> {
+        let key = self
+            .keys
+            .get(&self.active_key)
+            .unwrap_or_else(|| unreachable!("the active key is checked on creation"));
+        let mut data_key = [0; KEY_LEN];
+        self.rng
+            .fill(&mut data_key)
+            .map_err(|_e| ExecuteError::DbError("failed to generate a data key".to_owned()))?;
+        let ciphertext = self.seal(&new_key(&data_key)?, Aad::empty(), record)?;
+        let data_key = self.seal(key, Aad::from(self.active_key.as_bytes()), &data_key)?;
+        Ok(EncryptedRecord {
+            key_id: self.active_key.clone(),
+            data_key,
+            ciphertext,
+        }
+        .encode_to_vec())
+    }
+
+    /// Get the digest of a deduplicated value, it's keyed so the stored digests don't
+    /// reveal the plaintext values
+    pub(crate) fn digest(&self, value: &[u8]) -> Vec<u8> {
+        hmac::sign(&self.digest_key, value).as_ref().to_vec()
+    }
+
+    /// Decrypt a stored record, a plaintext record is returned as it is
+    pub(crate) fn decrypt(&self, raw: Vec<u8>) -> Result<Vec<u8>, ExecuteError> {
+        let Some(record) = decode_encrypted(&raw) else {
+            return Ok(raw);
+        };
+        let key = self.keys.get(&record.key_id).ok_or_else(|| {
+            ExecuteError::DbError(format!(
+                "the encryption key {} of a record is not configured",
+                record.key_id
+            ))
+        })?;
+        let data_key = open(key, Aad::from(record.key_id.as_bytes()), &record.data_key)?;
+        let data_key = <[u8; KEY_LEN]>::try_from(data_key.as_slice())
+            .map_err(|_e| ExecuteError::DbError("the data key is corrupted".to_owned()))?;
+        open(&new_key(&data_key)?, Aad::empty(), &record.ciphertext)
+    }
+
+    /// Encrypt the plaintext with a random nonce, which prefixes the ciphertext
+    fn seal<A: AsRef<[u8]>>(
+        &self,
+        key: &LessSafeKey,
+        aad: Aad<A>,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, ExecuteError> {
+        let mut nonce = [0; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_e| ExecuteError::DbError("failed to generate a nonce".to_owned()))?;
+        let mut in_out = plaintext.to_vec();
+        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), aad, &mut in_out)
+            .map_err(|_e| ExecuteError::DbError("failed to encrypt a record".to_owned()))?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN.saturating_add(in_out.len()));
+        sealed.extend_from_slice(&nonce);
+        sealed.extend(in_out);
+        Ok(sealed)
+    }
+}
+
+/// Check a stored record of a backend without the encryption keys is a plaintext one
+pub(crate) fn ensure_plaintext(raw: Vec<u8>) -> Result<Vec<u8>, ExecuteError> {
+    if is_encrypted(&raw) {
+        return Err(ExecuteError::DbError(
+            "the record is encrypted but no encryption key is configured".to_owned(),
+        ));
+    }
+    Ok(raw)
+}
+
+/// Check whether a stored record is an encrypted one
+pub(crate) fn is_encrypted(raw: &[u8]) -> bool {
+    decode_encrypted(raw).is_some()
+}
+
+/// Decode the encrypted record from a stored record, `None` if it is a plaintext one
+fn decode_encrypted(raw: &[u8]) -> Option<EncryptedRecord> {
+    EncryptedRecord::decode(raw)
+        .ok()
+        .filter(|record| !record.ciphertext.is_empty())
+}
+
+/// Create an AES-256-GCM key
+fn new_key(key: &[u8; KEY_LEN]) -> Result<LessSafeKey, ExecuteError> {
+    UnboundKey::new(&AES_256_GCM, key)
+        .map(LessSafeKey::new)
+        .map_err(|_e| ExecuteError::DbError("invalid encryption key".to_owned()))
+}
+
+/// Decrypt a ciphertext prefixed by its nonce
+fn open<A: AsRef<[u8]>>(
+    key: &LessSafeKey,
+    aad: Aad<A>,
+    sealed: &[u8],
+) -> Result<Vec<u8>, ExecuteError> {
+    if sealed.len() < NONCE_LEN {
+        return Err(ExecuteError::DbError(
+            "the encrypted record is corrupted".to_owned(),
+        ));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce)
+        .map_err(|_e| ExecuteError::DbError("the encrypted record is corrupted".to_owned()))?;
+    let mut in_out = ciphertext.to_vec();
+    let plaintext = key
+        .open_in_place(nonce, aad, &mut in_out)
+        .map_err(|_e| ExecuteError::DbError("failed to decrypt a record".to_owned()))?;
+    Ok(plaintext.to_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::KeyValue;
+
+    #[test]
+    fn records_should_be_decrypted_by_the_key_they_are_encrypted_with() {
+        let k1 = HashMap::from([("k1".to_owned(), [1; KEY_LEN])]);
+        let cipher = RecordCipher::new("k1".to_owned(), k1.clone()).unwrap();
+        let record = cipher.encrypt(b"record").unwrap();
+        assert!(!record.windows(6).any(|w| w == b"record"));
+        assert_ne!(cipher.encrypt(b"record").unwrap(), record);
+        assert_eq!(cipher.decrypt(record.clone()).unwrap(), b"record");
+        let plaintext = KeyValue {
+            key: b"key".to_vec(),
+            value: b"value".to_vec(),
+            ..KeyValue::default()
+        }
+        .encode_to_vec();
+        assert_eq!(cipher.decrypt(plaintext.clone()).unwrap(), plaintext);
+        assert!(ensure_plaintext(record.clone()).is_err());
+
+        let mut rotated = k1;
+        let _prev = rotated.insert("k2".to_owned(), [2; KEY_LEN]);
+        let rotated = RecordCipher::new("k2".to_owned(), rotated).unwrap();
+        assert_eq!(rotated.decrypt(record.clone()).unwrap(), b"record");
+        let k2_only = HashMap::from([("k2".to_owned(), [2; KEY_LEN])]);
+        let k2_only = RecordCipher::new("k2".to_owned(), k2_only).unwrap();
+        assert!(k2_only.decrypt(record).is_err());
+
+        let k2_with_wrong_k1 = HashMap::from([
+            ("k1".to_owned(), [3; KEY_LEN]),
+            ("k2".to_owned(), [2; KEY_LEN]),
+        ]);
+        let wrong = RecordCipher::new("k2".to_owned(), k2_with_wrong_k1).unwrap();
+        assert!(wrong.decrypt(cipher.encrypt(b"record").unwrap()).is_err());
+        assert!(RecordCipher::new("k3".to_owned(), HashMap::new()).is_err());
+    }
+}
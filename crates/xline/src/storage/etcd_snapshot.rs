@@ -0,0 +1,565 @@
+use std::{fs::File, io::Write, path::Path};
+
+use anyhow::Result;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
+use engine::StorageEngine;
+use prost::Message;
+use sha2::{Digest, Sha256};
+use utils::table_names::{AUTH_TABLE, KV_TABLE, LEASE_TABLE, META_TABLE, ROLE_TABLE, USER_TABLE};
+
+use super::{
+    auth_store::{AUTH_ENABLE_KEY, AUTH_REVISION_KEY},
+    db::{
+        has_encrypted_records, resolve_value_ref, FINISHED_COMPACT_REVISION,
+        SCHEDULED_COMPACT_REVISION,
+    },
+    encryption::ensure_plaintext,
+    Revision,
+};
+use crate::{
+    rpc::{KeyValue, PbLease},
+    server::command::APPLIED_INDEX_KEY,
+};
+
+/// Page size of the exported bolt database
+const PAGE_SIZE: usize = 4096;
+/// Size of a bolt page header
+const PAGE_HEADER_SIZE: usize = 16;
+/// Size of a bolt leaf or branch page element
+const ELEMENT_SIZE: usize = 16;
+/// Flag of a bolt branch page
+const BRANCH_PAGE_FLAG: u16 = 0x01;
+/// Flag of a bolt leaf page
+const LEAF_PAGE_FLAG: u16 = 0x02;
+/// Flag of a bolt meta page
+const META_PAGE_FLAG: u16 = 0x04;
+/// Flag of a bolt freelist page
+const FREELIST_PAGE_FLAG: u16 = 0x10;
+/// Flag of a leaf element which is a nested bucket
+const BUCKET_LEAF_FLAG: u32 = 0x01;
+/// Magic number of a bolt database
+const MAGIC: u32 = 0xED0C_DAED;
+/// Data file format version of a bolt database
+const VERSION: u32 = 2;
+/// Page id of the freelist page, page 0 and 1 are the meta pages
+const FREELIST_PAGE_ID: u64 = 2;
+
+/// etcd bucket of the key-value pairs
+const ETCD_KEY_BUCKET: &[u8] = b"key";
+/// etcd bucket of the metadata
+const ETCD_META_BUCKET: &[u8] = b"meta";
+/// etcd bucket of the leases
+const ETCD_LEASE_BUCKET: &[u8] = b"lease";
+/// etcd bucket of the auth status
+const ETCD_AUTH_BUCKET: &[u8] = b"auth";
+/// etcd bucket of the users
+const ETCD_AUTH_USERS_BUCKET: &[u8] = b"authUsers";
+/// etcd bucket of the roles
+const ETCD_AUTH_ROLES_BUCKET: &[u8] = b"authRoles";
+
+/// A key-value pair of a bucket
+type Item = (Vec<u8>, Vec<u8>);
+
+/// Export the keyspace of an xline storage engine to an etcd v3 snapshot file, which
+/// can be restored into etcd by `etcdutl snapshot restore`.
+///
+/// The file is a bolt database in the bucket layout of etcd, followed by the sha256
+/// checksum of the database like the files saved by `etcdctl snapshot save`.
+///
+/// Unsupported edge cases:
+/// - Password hashes of xline are not compatible with etcd, users with a password
+///   have to reset it after the restore.
+/// - Alarms and cluster membership are not exported, the membership is regenerated by
+///   `etcdutl snapshot restore`.
+/// - Revisions of keys that have been compacted by xline are not exported.
+/// - Storages whose records are encrypted at rest can't be exported.
+///
+/// # Errors
+///
+/// Return error if failed to read the storage engine, decode the stored values or
+/// write the snapshot file
+#[inline]
+pub fn export_etcd_snapshot<E: StorageEngine, P: AsRef<Path>>(engine: &E, path: P) -> Result<()> {
+    let buckets = collect_buckets(engine)?;
+    let db = build_bolt_db(buckets);
+    let checksum = Sha256::digest(&db);
+    let mut file = File::create(path)?;
+    file.write_all(&db)?;
+    file.write_all(&checksum)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Collect the etcd buckets from the xline tables, the buckets are sorted by name
+fn collect_buckets<E: StorageEngine>(engine: &E) -> Result<Vec<(&'static [u8], Vec<Item>)>> {
+    let mut keys = Vec::new();
+    let may_be_encrypted = has_encrypted_records(engine)?;
+    for (rev_bytes, value) in engine.get_all(KV_TABLE)? {
+        let rev = Revision::decode(&rev_bytes);
+        let value = if may_be_encrypted {
+            ensure_plaintext(value)?
+        } else {
+            value
+        };
+        let value = resolve_value_ref(engine, value, None)?;
+        let kv = KeyValue::decode(value.as_slice())?;
+        // a deletion is stored as a key-value pair of version 0 by xline, while etcd
+        // marks the revision as a tombstone and only keeps the key
+        if kv.version == 0 {
+            let tombstone = KeyValue {
+                key: kv.key,
+                ..KeyValue::default()
+            };
+            keys.push((etcd_revision(rev, true), tombstone.encode_to_vec()));
+        } else {
+            keys.push((etcd_revision(rev, false), value));
+        }
+    }
+
+    let mut meta = Vec::new();
+    if let Some(index) = engine.get(META_TABLE, APPLIED_INDEX_KEY)? {
+        meta.push((
+            b"consistent_index".to_vec(),
+            u64::from_le_bytes(le_bytes(&index)?).to_be_bytes().to_vec(),
+        ));
+    }
+    for (xline_key, etcd_key) in [
+        (FINISHED_COMPACT_REVISION, "finishedCompactRev"),
+        (SCHEDULED_COMPACT_REVISION, "scheduledCompactRev"),
+    ] {
+        if let Some(rev) = engine.get(META_TABLE, xline_key)? {
+            let rev = Revision::new(i64::from_le_bytes(le_bytes(&rev)?), 0);
+            meta.push((etcd_key.as_bytes().to_vec(), etcd_revision(rev, false)));
+        }
+    }
+
+    let mut leases = Vec::new();
+    for (_, value) in engine.get_all(LEASE_TABLE)? {
+        let lease = PbLease::decode(value.as_slice())?;
+        leases.push((lease.id.to_be_bytes().to_vec(), value));
+    }
+
+    let mut auth = Vec::new();
+    if let Some(enabled) = engine.get(AUTH_TABLE, AUTH_ENABLE_KEY)? {
+        auth.push((b"authEnabled".to_vec(), enabled));
+    }
+    if let Some(revision) = engine.get(AUTH_TABLE, AUTH_REVISION_KEY)? {
+        let revision: u64 = i64::decode(revision.as_slice())?.numeric_cast();
+        auth.push((b"authRevision".to_vec(), revision.to_be_bytes().to_vec()));
+    }
+
+    let mut buckets = vec![
+        (ETCD_KEY_BUCKET, keys),
+        (ETCD_META_BUCKET, meta),
+        (ETCD_LEASE_BUCKET, leases),
+        (ETCD_AUTH_BUCKET, auth),
+        (ETCD_AUTH_USERS_BUCKET, engine.get_all(USER_TABLE)?),
+        (ETCD_AUTH_ROLES_BUCKET, engine.get_all(ROLE_TABLE)?),
+    ];
+    for &mut (_, ref mut items) in &mut buckets {
+        items.sort_unstable();
+    }
+    buckets.sort_unstable_by_key(|&(name, _)| name);
+    Ok(buckets)
+}
+
+/// Convert a little endian encoded `u64` or `i64` to an array
+fn le_bytes(bytes: &[u8]) -> Result<[u8; 8]> {
+    bytes
+        .try_into()
+        .map_err(|_e| anyhow::anyhow!("invalid length of an 8 bytes integer: {}", bytes.len()))
+}
+
+/// Encode a revision as an etcd revision key, the tombstone of a key is marked by a
+/// trailing `t`
+fn etcd_revision(rev: Revision, tombstone: bool) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(18);
+    buf.extend_from_slice(&rev.revision().to_be_bytes());
+    buf.push(b'_');
+    buf.extend_from_slice(&rev.sub_revision().to_be_bytes());
+    if tombstone {
+        buf.push(b't');
+    }
+    buf
+}
+
+/// Build a bolt database from the buckets, every bucket is written as a B+ tree of
+/// its own and the database has no free pages
+fn build_bolt_db(buckets: Vec<(&'static [u8], Vec<Item>)>) -> Vec<u8> {
+    let mut writer = PageWriter::new();
+    let mut bucket_items = Vec::with_capacity(buckets.len());
+    for (name, items) in buckets {
+        let root = writer.write_tree(items);
+        // a bucket header is the page id of its root and a sequence number
+        let mut header = Vec::with_capacity(16);
+        header.extend_from_slice(&root.to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes());
+        bucket_items.push((BUCKET_LEAF_FLAG, name.to_vec(), header));
+    }
+    let root = writer.write_leaf(&bucket_items);
+
+    let mut db = Vec::with_capacity(writer.pages.len().overflow_add(PAGE_SIZE.overflow_mul(3)));
+    for txid in 0..2 {
+        db.extend(meta_page(txid, root, writer.next_id));
+    }
+    db.extend(page(FREELIST_PAGE_ID, FREELIST_PAGE_FLAG, 0, &[]));
+    db.extend(writer.pages);
+    db
+}
+
+/// Build a meta page of a bolt database
+fn meta_page(txid: u64, root: u64, high_water_mark: u64) -> Vec<u8> {
+    let mut meta = Vec::with_capacity(64);
+    meta.extend_from_slice(&MAGIC.to_le_bytes());
+    meta.extend_from_slice(&VERSION.to_le_bytes());
+    meta.extend_from_slice(&u32::numeric_cast(PAGE_SIZE).to_le_bytes());
+    // flags
+    meta.extend_from_slice(&0u32.to_le_bytes());
+    // root bucket
+    meta.extend_from_slice(&root.to_le_bytes());
+    meta.extend_from_slice(&0u64.to_le_bytes());
+    meta.extend_from_slice(&FREELIST_PAGE_ID.to_le_bytes());
+    meta.extend_from_slice(&high_water_mark.to_le_bytes());
+    meta.extend_from_slice(&txid.to_le_bytes());
+    let checksum = fnv1a_64(&meta);
+    meta.extend_from_slice(&checksum.to_le_bytes());
+    page(txid, META_PAGE_FLAG, 0, &meta)
+}
+
+/// Build a page, the page is padded to a multiple of the page size and the extra pages
+/// are recorded as its overflow
+fn page(id: u64, flags: u16, count: usize, body: &[u8]) -> Vec<u8> {
+    let len = PAGE_HEADER_SIZE.overflow_add(body.len());
+    let page_num = len.div_ceil(PAGE_SIZE);
+    let mut page = Vec::with_capacity(page_num.overflow_mul(PAGE_SIZE));
+    page.extend_from_slice(&id.to_le_bytes());
+    page.extend_from_slice(&flags.to_le_bytes());
+    page.extend_from_slice(&u16::numeric_cast(count).to_le_bytes());
+    page.extend_from_slice(&u32::numeric_cast(page_num.overflow_sub(1)).to_le_bytes());
+    page.extend_from_slice(body);
+    page.resize(page_num.overflow_mul(PAGE_SIZE), 0);
+    page
+}
+
+/// FNV-1a 64-bit hash, which is the checksum of a bolt meta page
+fn fnv1a_64(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Writer of the data pages of a bolt database
+#[derive(Debug)]
+struct PageWriter {
+    /// Written pages, starting from the page after the freelist page
+    pages: Vec<u8>,
+    /// Id of the next page
+    next_id: u64,
+}
+
+impl PageWriter {
+    /// New `PageWriter`
+    fn new() -> Self {
+        Self {
+            pages: Vec::new(),
+            next_id: FREELIST_PAGE_ID.overflow_add(1),
+        }
+    }
+
+    /// Append a page and return its id
+    fn append(&mut self, flags: u16, count: usize, body: &[u8]) -> u64 {
+        let id = self.next_id;
+        let page = page(id, flags, count, body);
+        self.next_id = self
+            .next_id
+            .overflow_add(page.len().overflow_div(PAGE_SIZE).numeric_cast());
+        self.pages.extend(page);
+        id
+    }
+
+    /// Write the sorted items as a B+ tree and return the id of its root page
+    fn write_tree(&mut self, items: Vec<Item>) -> u64 {
+        let mut level: Vec<(Vec<u8>, u64)> = Vec::new();
+        let mut leaf = Vec::new();
+        let mut size = PAGE_HEADER_SIZE;
+        for (key, value) in items {
+            let item_size = ELEMENT_SIZE
+                .overflow_add(key.len())
+                .overflow_add(value.len());
+            if !leaf.is_empty() && size.overflow_add(item_size) > PAGE_SIZE {
+                level.push(self.flush_leaf(&mut leaf));
+                size = PAGE_HEADER_SIZE;
+            }
+            size = size.overflow_add(item_size);
+            leaf.push((0, key, value));
+        }
+        if !leaf.is_empty() || level.is_empty() {
+            level.push(self.flush_leaf(&mut leaf));
+        }
+
+        while level.len() > 1 {
+            let mut next_level = Vec::new();
+            let mut branch = Vec::new();
+            let mut size = PAGE_HEADER_SIZE;
+            for (key, id) in level {
+                let item_size = ELEMENT_SIZE.overflow_add(key.len());
+                if !branch.is_empty() && size.overflow_add(item_size) > PAGE_SIZE {
+                    next_level.push(self.flush_branch(&mut branch));
+                    size = PAGE_HEADER_SIZE;
+                }
+                size = size.overflow_add(item_size);
+                branch.push((key, id));
+            }
+            next_level.push(self.flush_branch(&mut branch));
+            level = next_level;
+        }
+        let (_, root) = level
+            .pop()
+            .unwrap_or_else(|| unreachable!("a tree has at least one page"));
+        root
+    }
+
+    /// Write the pending leaf elements as a page, return its first key and page id
+    fn flush_leaf(&mut self, leaf: &mut Vec<(u32, Vec<u8>, Vec<u8>)>) -> (Vec<u8>, u64) {
+        let elements = std::mem::take(leaf);
+        let first_key = elements
+            .first()
+            .map(|&(_, ref key, _)| key.clone())
+            .unwrap_or_default();
+        (first_key, self.write_leaf(&elements))
+    }
+
+    /// Write the pending branch elements as a page, return its first key and page id
+    fn flush_branch(&mut self, branch: &mut Vec<(Vec<u8>, u64)>) -> (Vec<u8>, u64) {
+        let elements = std::mem::take(branch);
+        let first_key = elements
+            .first()
+            .map(|&(ref key, _)| key.clone())
+            .unwrap_or_default();
+        let mut header = Vec::with_capacity(elements.len().overflow_mul(ELEMENT_SIZE));
+        let mut data = Vec::new();
+        for (i, &(ref key, id)) in elements.iter().enumerate() {
+            // the position of the key is relative to the element itself
+            let pos = elements
+                .len()
+                .overflow_sub(i)
+                .overflow_mul(ELEMENT_SIZE)
+                .overflow_add(data.len());
+            header.extend_from_slice(&u32::numeric_cast(pos).to_le_bytes());
+            header.extend_from_slice(&u32::numeric_cast(key.len()).to_le_bytes());
+            header.extend_from_slice(&id.to_le_bytes());
+            data.extend_from_slice(key);
+        }
+        header.extend(data);
+        (
+            first_key,
+            self.append(BRANCH_PAGE_FLAG, elements.len(), &header),
+        )
+    }
+
+    /// Write the leaf elements as a page and return its id
+    fn write_leaf(&mut self, elements: &[(u32, Vec<u8>, Vec<u8>)]) -> u64 {
+        let mut header = Vec::with_capacity(elements.len().overflow_mul(ELEMENT_SIZE));
+        let mut data = Vec::new();
+        for (i, &(flags, ref key, ref value)) in elements.iter().enumerate() {
+            // the position of the key is relative to the element itself
+            let pos = elements
+                .len()
+                .overflow_sub(i)
+                .overflow_mul(ELEMENT_SIZE)
+                .overflow_add(data.len());
+            header.extend_from_slice(&flags.to_le_bytes());
+            header.extend_from_slice(&u32::numeric_cast(pos).to_le_bytes());
+            header.extend_from_slice(&u32::numeric_cast(key.len()).to_le_bytes());
+            header.extend_from_slice(&u32::numeric_cast(value.len()).to_le_bytes());
+            data.extend_from_slice(key);
+            data.extend_from_slice(value);
+        }
+        header.extend(data);
+        self.append(LEAF_PAGE_FLAG, elements.len(), &header)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use engine::{Engine, EngineType, WriteOperation};
+    use utils::table_names::XLINE_TABLES;
+
+    use super::*;
+
+    /// Read a little endian integer at the offset
+    fn read_u64(data: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// Read a little endian integer at the offset
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Read a little endian integer at the offset
+    fn read_u16(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+    }
+
+    /// Collect the items of the tree rooted at the page, items of nested buckets are
+    /// returned with the bucket flag
+    fn read_tree(db: &[u8], id: u64) -> Vec<(u32, Vec<u8>, Vec<u8>)> {
+        let page = &db[id as usize * PAGE_SIZE..];
+        assert_eq!(read_u64(page, 0), id);
+        let flags = read_u16(page, 8);
+        let count = read_u16(page, 10) as usize;
+        let mut items = Vec::new();
+        for i in 0..count {
+            let elem = PAGE_HEADER_SIZE + i * ELEMENT_SIZE;
+            if flags == BRANCH_PAGE_FLAG {
+                items.extend(read_tree(db, read_u64(page, elem + 8)));
+            } else {
+                assert_eq!(flags, LEAF_PAGE_FLAG);
+                let pos = elem + read_u32(page, elem + 4) as usize;
+                let ksize = read_u32(page, elem + 8) as usize;
+                let vsize = read_u32(page, elem + 12) as usize;
+                items.push((
+                    read_u32(page, elem),
+                    page[pos..pos + ksize].to_vec(),
+                    page[pos + ksize..pos + ksize + vsize].to_vec(),
+                ));
+            }
+        }
+        items
+    }
+
+    /// Collect the items of the nested bucket in the root bucket
+    fn read_bucket(db: &[u8], root: u64, name: &[u8]) -> Vec<Item> {
+        let (flags, _, header) = read_tree(db, root)
+            .into_iter()
+            .find(|&(_, ref key, _)| key == name)
+            .unwrap();
+        assert_eq!(flags, BUCKET_LEAF_FLAG);
+        read_tree(db, read_u64(&header, 0))
+            .into_iter()
+            .map(|(_, key, value)| (key, value))
+            .collect()
+    }
+
+    #[test]
+    fn exported_snapshot_should_be_a_valid_bolt_db() {
+        let engine = Engine::new(EngineType::Memory, &XLINE_TABLES).unwrap();
+        let mut ops = Vec::new();
+        let mut kvs = Vec::new();
+        // enough key-value pairs to span multiple leaf pages
+        for i in 1..=500 {
+            let kv = KeyValue {
+                key: format!("key{i}").into_bytes(),
+                value: vec![b'v'; 100],
+                create_revision: i,
+                mod_revision: i,
+                version: 1,
+                lease: 0,
+            };
+            kvs.push(kv.clone());
+            ops.push(WriteOperation::new_put(
+                KV_TABLE,
+                Revision::new(i, 0).encode_to_vec(),
+                kv.encode_to_vec(),
+            ));
+        }
+        let deletion = KeyValue {
+            key: b"key1".to_vec(),
+            mod_revision: 501,
+            ..KeyValue::default()
+        };
+        ops.push(WriteOperation::new_put(
+            KV_TABLE,
+            Revision::new(501, 0).encode_to_vec(),
+            deletion.encode_to_vec(),
+        ));
+        let lease = PbLease {
+            id: 1,
+            ttl: 10,
+            remaining_ttl: 10,
+        };
+        ops.push(WriteOperation::new_put(
+            LEASE_TABLE,
+            lease.id.encode_to_vec(),
+            lease.encode_to_vec(),
+        ));
+        ops.push(WriteOperation::new_put(
+            META_TABLE,
+            APPLIED_INDEX_KEY.as_bytes().to_vec(),
+            7u64.to_le_bytes().to_vec(),
+        ));
+        engine.write_batch(ops, false).unwrap();
+
+        let path = std::env::temp_dir().join(format!("etcd-snapshot-{}", uuid::Uuid::new_v4()));
+        export_etcd_snapshot(&engine, &path).unwrap();
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // etcd tooling detects the checksum by the remainder of the file size
+        assert_eq!(file.len() % 512, 32);
+        let (db, checksum) = file.split_at(file.len() - 32);
+        assert_eq!(Sha256::digest(db).as_slice(), checksum);
+        assert_eq!(db.len() % PAGE_SIZE, 0);
+
+        let mut root = 0;
+        for id in 0..2 {
+            let page = &db[id * PAGE_SIZE..];
+            assert_eq!(read_u16(page, 8), META_PAGE_FLAG);
+            let meta = &page[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + 64];
+            assert_eq!(read_u32(meta, 0), MAGIC);
+            assert_eq!(read_u32(meta, 4), VERSION);
+            assert_eq!(read_u32(meta, 8) as usize, PAGE_SIZE);
+            assert_eq!(read_u64(meta, 56), fnv1a_64(&meta[..56]));
+            assert_eq!(read_u64(meta, 32), FREELIST_PAGE_ID);
+            assert_eq!(read_u64(meta, 40) as usize, db.len() / PAGE_SIZE);
+            root = read_u64(meta, 16);
+        }
+        assert_eq!(
+            read_u16(&db[FREELIST_PAGE_ID as usize * PAGE_SIZE..], 8),
+            FREELIST_PAGE_FLAG
+        );
+
+        let names: Vec<_> = read_tree(db, root)
+            .into_iter()
+            .map(|(_, name, _)| name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                b"auth".to_vec(),
+                b"authRoles".to_vec(),
+                b"authUsers".to_vec(),
+                b"key".to_vec(),
+                b"lease".to_vec(),
+                b"meta".to_vec(),
+            ]
+        );
+
+        let keys = read_bucket(db, root, ETCD_KEY_BUCKET);
+        assert_eq!(keys.len(), 501);
+        for (i, kv) in kvs.into_iter().enumerate() {
+            let rev = Revision::new(i as i64 + 1, 0);
+            assert_eq!(keys[i], (etcd_revision(rev, false), kv.encode_to_vec()));
+        }
+        let tombstone = KeyValue {
+            key: b"key1".to_vec(),
+            ..KeyValue::default()
+        };
+        assert_eq!(
+            keys[500],
+            (
+                etcd_revision(Revision::new(501, 0), true),
+                tombstone.encode_to_vec()
+            )
+        );
+        assert_eq!(
+            read_bucket(db, root, ETCD_LEASE_BUCKET),
+            vec![(1i64.to_be_bytes().to_vec(), lease.encode_to_vec())]
+        );
+        assert_eq!(
+            read_bucket(db, root, ETCD_META_BUCKET),
+            vec![(b"consistent_index".to_vec(), 7u64.to_be_bytes().to_vec())]
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::VecDeque;
+
+use clippy_utilities::OverflowArithmetic;
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Serialize};
+use utils::table_names::META_TABLE;
+use xlineapi::execute_error::ExecuteError;
+
+use super::{db::WriteOp, storage_api::StorageApi};
+
+/// Change history of a subsystem, it maintains its own revisions and is compacted
+/// independently of the KV history according to its own retention.
+///
+/// The changes are persisted in the meta table under the name of the history, a
+/// change is put when it is recorded and deleted when it is compacted, so the
+/// history is recovered after a restart.
+#[derive(Debug)]
+pub(crate) struct ChangeHistory<T> {
+    /// The name of the history, which is the key prefix of its changes
+    name: &'static str,
+    /// The number of history revisions to retain, 0 means the history is disabled
+    retention: usize,
+    /// Inner
+    inner: Mutex<ChangeHistoryInner<T>>,
+}
+
+/// Inner of `ChangeHistory`
+#[derive(Debug)]
+struct ChangeHistoryInner<T> {
+    /// The latest history revision
+    revision: i64,
+    /// The compacted history revision
+    compacted_revision: i64,
+    /// Changes in ascending order of history revision
+    changes: VecDeque<(i64, T)>,
+}
+
+/// The latest changes of a history
+#[derive(Debug, Serialize)]
+pub(crate) struct HistoryReport<T> {
+    /// The compacted history revision, the changes up to it are no longer retained
+    pub(crate) compacted_revision: i64,
+    /// The latest changes with their history revisions in ascending order
+    pub(crate) changes: Vec<(i64, T)>,
+}
+
+/// The key of a change of the history `name` in the meta table
+pub(crate) fn history_key(name: &str, revision: i64) -> Vec<u8> {
+    let mut key = history_prefix(name);
+    key.extend_from_slice(&revision.to_be_bytes());
+    key
+}
+
+/// The key prefix of the changes of the history `name` in the meta table
+fn history_prefix(name: &str) -> Vec<u8> {
+    let mut prefix = name.as_bytes().to_vec();
+    prefix.push(b'/');
+    prefix
+}
+
+impl<T> ChangeHistory<T>
+where
+    T: Clone + Serialize + DeserializeOwned,
+{
+    /// New `ChangeHistory`
+    pub(crate) fn new(name: &'static str, retention: usize) -> Self {
+        Self {
+            name,
+            retention,
+            inner: Mutex::new(ChangeHistoryInner {
+                revision: 0,
+                compacted_revision: 0,
+                changes: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Check whether the history is enabled
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.retention != 0
+    }
+
+    /// Record a change and compact the history which is out of retention, return the
+    /// write ops persisting them
+    pub(crate) fn record(&self, change: T) -> Vec<WriteOp<'static>> {
+        if !self.is_enabled() {
+            return Vec::new();
+        }
+        let encoded = serde_json::to_vec(&change)
+            .unwrap_or_else(|e| unreachable!("a change is always serializable: {e}"));
+        let mut inner = self.inner.lock();
+        inner.revision = inner.revision.overflow_add(1);
+        let revision = inner.revision;
+        inner.changes.push_back((revision, change));
+        let mut ops = vec![WriteOp::PutHistoryChange(self.name, revision, encoded)];
+        while inner.changes.len() > self.retention {
+            if let Some((rev, _)) = inner.changes.pop_front() {
+                inner.compacted_revision = rev;
+                ops.push(WriteOp::DeleteHistoryChange(self.name, rev));
+            }
+        }
+        ops
+    }
+
+    /// Recover the persisted changes from db
+    pub(crate) fn recover<S>(&self, db: &S) -> Result<(), ExecuteError>
+    where
+        S: StorageApi,
+    {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let prefix = history_prefix(self.name);
+        let mut changes = VecDeque::new();
+        for (key, value) in db.get_all(META_TABLE)? {
+            let Some(revision) = key.strip_prefix(prefix.as_slice()) else {
+                continue;
+            };
+            let revision = revision.try_into().map(i64::from_be_bytes).map_err(|e| {
+                ExecuteError::DbError(format!("cannot decode {} revision: {e:?}", self.name))
+            })?;
+            let change = serde_json::from_slice(&value).map_err(|e| {
+                ExecuteError::DbError(format!("cannot decode {} change: {e}", self.name))
+            })?;
+            changes.push_back((revision, change));
+        }
+        // the changes out of a lowered retention are compacted by the next record
+        let mut inner = self.inner.lock();
+        if let (Some(&(first, _)), Some(&(last, _))) = (changes.front(), changes.back()) {
+            inner.compacted_revision = first.overflow_sub(1);
+            inner.revision = last;
+        }
+        inner.changes = changes;
+        Ok(())
+    }
+
+    /// Get all retained changes with their history revisions
+    #[cfg(test)]
+    pub(crate) fn changes(&self) -> Vec<(i64, T)> {
+        self.inner.lock().changes.iter().cloned().collect()
+    }
+
+    /// Get the latest `limit` changes and the compacted history revision
+    pub(crate) fn report(&self, limit: usize) -> HistoryReport<T> {
+        let inner = self.inner.lock();
+        let skipped = inner.changes.len().saturating_sub(limit);
+        HistoryReport {
+            compacted_revision: inner.compacted_revision,
+            changes: inner.changes.iter().skip(skipped).cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::config::EngineConfig;
+
+    use super::*;
+    use crate::storage::db::DB;
+
+    #[test]
+    fn history_should_be_compacted_by_retention() {
+        let history = ChangeHistory::new("test", 2);
+        for i in 0..5 {
+            let _ops = history.record(i);
+        }
+        assert_eq!(history.changes(), vec![(4, 3), (5, 4)]);
+        let report = history.report(1);
+        assert_eq!(report.compacted_revision, 3);
+        assert_eq!(report.changes, vec![(5, 4)]);
+    }
+
+    #[test]
+    fn disabled_history_should_record_nothing() {
+        let history = ChangeHistory::new("test", 0);
+        assert!(history.record(1).is_empty());
+        assert!(history.changes().is_empty());
+    }
+
+    #[test]
+    fn history_should_be_recovered_from_db() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let history = ChangeHistory::new("test", 2);
+        for i in 0..5 {
+            let _key_revs = db.flush_ops(history.record(i))?;
+        }
+        let other = ChangeHistory::new("other", 2);
+        let _key_revs = db.flush_ops(other.record(10))?;
+
+        let recovered = ChangeHistory::new("test", 2);
+        recovered.recover(db.as_ref())?;
+        assert_eq!(recovered.changes(), vec![(4, 3), (5, 4)]);
+        assert_eq!(recovered.report(2).compacted_revision, 3);
+        let _key_revs = db.flush_ops(recovered.record(5))?;
+        assert_eq!(recovered.changes(), vec![(5, 4), (6, 5)]);
+        Ok(())
+    }
+}
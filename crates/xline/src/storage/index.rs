@@ -1,7 +1,7 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, ops::Bound};
 
-use clippy_utilities::OverflowArithmetic;
-use crossbeam_skiplist::SkipMap;
+use clippy_utilities::{NumericCast, OverflowArithmetic};
+use crossbeam_skiplist::{map::Entry, SkipMap};
 use itertools::Itertools;
 use parking_lot::RwLock;
 use utils::parking_lot_lock::RwLockMap;
@@ -10,21 +10,129 @@ use xlineapi::command::KeyRange;
 use super::revision::{KeyRevision, Revision};
 use crate::server::command::RangeType;
 
+/// The maximum number of keys inspected when estimating a key histogram
+const MAX_HISTOGRAM_SAMPLES: usize = 1024;
+
+/// The maximum number of index entries walked by the estimation of a key histogram
+const MAX_HISTOGRAM_ENTRIES: usize = 64 * MAX_HISTOGRAM_SAMPLES;
+
+/// The maximum number of index entries a bulk merge steps over before it searches
+/// the next key instead
+const MAX_MERGE_STEPS: usize = 8;
+
+/// An entry of the index
+type IndexEntry<'a> = Entry<'a, Vec<u8>, RwLock<Vec<KeyRevision>>>;
+
+/// A bucket of an estimated key histogram
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct KeyBucket {
+    /// The start key of the bucket, inclusive
+    pub(crate) start: Vec<u8>,
+    /// The end key of the bucket, exclusive, the last bucket ends at the end of
+    /// the requested range
+    pub(crate) end: Vec<u8>,
+    /// The estimated number of live keys in the bucket
+    pub(crate) count: u64,
+}
+
+/// An estimated key histogram of a range
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct KeyHistogram {
+    /// The buckets in ascending order of key
+    pub(crate) buckets: Vec<KeyBucket>,
+    /// The key to continue the estimation from, `None` if the buckets cover the range
+    pub(crate) next_key: Option<Vec<u8>>,
+}
+
+/// The key prefixes whose history is exempt from compaction.
+///
+/// The history of a protected key is kept below the compacted revision, so that it
+/// is readable at any historical revision, until the key is deleted and the deletion
+/// is older than the retention.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CompactProtection {
+    /// The protected key prefixes
+    prefixes: Vec<Vec<u8>>,
+    /// The number of revisions the history of a deleted protected key is retained
+    /// after its deletion, 0 means it is retained forever
+    retention: i64,
+}
+
+impl CompactProtection {
+    /// New `CompactProtection`
+    pub(crate) fn new(prefixes: &[String], retention: usize) -> Self {
+        Self {
+            prefixes: prefixes
+                .iter()
+                .map(|prefix| prefix.as_bytes().to_vec())
+                .collect(),
+            retention: retention.numeric_cast(),
+        }
+    }
+
+    /// Whether a key is protected
+    pub(crate) fn is_protected(&self, key: &[u8]) -> bool {
+        self.prefixes.iter().any(|prefix| key.starts_with(prefix))
+    }
+
+    /// Whether all the keys of a range are protected
+    pub(crate) fn covers(&self, key: &[u8], range_end: &[u8]) -> bool {
+        self.prefixes.iter().any(|prefix| {
+            if !key.starts_with(prefix) {
+                return false;
+            }
+            let prefix_end = KeyRange::get_prefix(prefix);
+            match range_end {
+                [] => true,
+                [0] => prefix_end == [0],
+                end => prefix_end == [0] || end <= prefix_end.as_slice(),
+            }
+        })
+    }
+
+    /// Get the number of the leading revisions of a protected key that can be
+    /// compacted at `at_rev`, which is the history up to its last deletion older
+    /// than the retention
+    fn compactable(&self, revisions: &[KeyRevision], at_rev: i64) -> usize {
+        if self.retention == 0 {
+            return 0;
+        }
+        let expired_rev = at_rev.overflow_sub(self.retention);
+        revisions
+            .iter()
+            .rposition(|rev| rev.mod_revision <= expired_rev && rev.is_deleted())
+            .map_or(0, |idx| idx.overflow_add(1))
+    }
+}
+
 /// Keys to revisions mapping
 #[derive(Debug)]
 pub(crate) struct Index {
     /// Inner struct of `Index`
     inner: SkipMap<Vec<u8>, RwLock<Vec<KeyRevision>>>,
+    /// The keys whose history is exempt from compaction
+    protection: CompactProtection,
 }
 
 impl Index {
     /// New `Index`
     pub(crate) fn new() -> Self {
+        Self::new_with_protection(CompactProtection::default())
+    }
+
+    /// New `Index` whose protected keys are exempt from compaction
+    pub(crate) fn new_with_protection(protection: CompactProtection) -> Self {
         Self {
             inner: SkipMap::new(),
+            protection,
         }
     }
 
+    /// Get the keys whose history is exempt from compaction
+    pub(crate) fn protection(&self) -> &CompactProtection {
+        &self.protection
+    }
+
     /// Filter out `KeyRevision` that is less than one revision and convert to `Revision`
     fn filter_revision(revs: &[KeyRevision], revision: i64) -> Vec<Revision> {
         revs.iter()
@@ -53,6 +161,55 @@ impl Index {
             .map(KeyRevision::as_revision)
     }
 
+    /// Get the next `KeyRevision` of a key whose last one is `last`
+    fn next_revision(last: Option<&KeyRevision>, revision: i64, sub_revision: i64) -> KeyRevision {
+        match last {
+            Some(rev) if !rev.is_deleted() => KeyRevision::new(
+                rev.create_revision,
+                rev.version.overflow_add(1),
+                revision,
+                sub_revision,
+            ),
+            _ => KeyRevision::new(revision, 1, revision, sub_revision),
+        }
+    }
+
+    /// Get the entries of the sorted keys, `None` for the keys absent from the index
+    ///
+    /// The entries are found by walking the index along with the keys, the index is
+    /// only searched again when the next key is far ahead, so a batch of adjacent keys
+    /// costs a single search instead of one per key.
+    fn merge_entries(&self, sorted_keys: &[&[u8]]) -> Vec<Option<IndexEntry<'_>>> {
+        let mut entries = Vec::with_capacity(sorted_keys.len());
+        let mut cursor = sorted_keys
+            .first()
+            .and_then(|key| self.inner.lower_bound(Bound::Included(*key)));
+        for key in sorted_keys {
+            let mut steps = 0;
+            loop {
+                let next = match cursor {
+                    Some(ref entry) if entry.key().as_slice() < *key => {
+                        if steps < MAX_MERGE_STEPS {
+                            entry.next()
+                        } else {
+                            self.inner.lower_bound(Bound::Included(*key))
+                        }
+                    }
+                    _ => break,
+                };
+                cursor = next;
+                steps = steps.overflow_add(1);
+            }
+            entries.push(
+                cursor
+                    .as_ref()
+                    .filter(|entry| entry.key().as_slice() == *key)
+                    .cloned(),
+            );
+        }
+        entries
+    }
+
     /// Insert `KeyRevision` of deleted and generate `Revision` pair of deleted
     fn gen_del_revision(
         revs: &mut Vec<KeyRevision>,
@@ -88,6 +245,131 @@ impl Index {
         });
         revs
     }
+
+    /// Get the revision of the deletion which ends the previous generation of a key,
+    /// whose current generation is created at `create_revision`. `None` if the key has
+    /// no previous generation or its deletion is compacted.
+    pub(crate) fn deletion_before(&self, key: &[u8], create_revision: i64) -> Option<i64> {
+        self.inner.get(key)?.value().map_read(|revs| {
+            let pivot = revs.partition_point(|rev| rev.mod_revision < create_revision);
+            revs.get(pivot.checked_sub(1)?)
+                .filter(|rev| rev.is_deleted())
+                .map(|rev| rev.mod_revision)
+        })
+    }
+
+    /// Sample at most `count` keys spread over the index with their latest revisions.
+    /// The keys are taken every `stride` keys from the `offset % stride`-th key, where
+    /// `stride` is the number of the keys divided by `count`, so a random offset picks
+    /// another sample each time.
+    pub(crate) fn sample(&self, count: usize, offset: usize) -> Vec<(Vec<u8>, KeyRevision)> {
+        if count == 0 {
+            return vec![];
+        }
+        let stride = self.inner.len().overflow_div(count).max(1);
+        self.inner
+            .iter()
+            .skip(offset.overflow_rem(stride))
+            .step_by(stride)
+            .take(count)
+            .filter_map(|entry| {
+                let last = entry.value().map_read(|revs| revs.last().copied())?;
+                Some((entry.key().clone(), last))
+            })
+            .collect()
+    }
+
+    /// Get the latest revision of a key, `None` if the key is not in the index
+    pub(crate) fn latest(&self, key: &[u8]) -> Option<KeyRevision> {
+        self.inner
+            .get(key)?
+            .value()
+            .map_read(|revs| revs.last().copied())
+    }
+
+    /// Estimate the distribution of the live keys in a range. The keys are split
+    /// into at most `buckets` buckets with roughly the same number of keys, so the
+    /// bucket boundaries can be used as split points.
+    ///
+    /// The estimation only walks the in-memory index, no value is read from the
+    /// storage, and the walk is bounded by `MAX_HISTOGRAM_ENTRIES` entries. If the
+    /// range holds more entries, the buckets stop at the first entry not walked, which
+    /// is returned as the key to continue the estimation of the rest of the range
+    /// from. About `MAX_HISTOGRAM_SAMPLES` evenly spaced keys of the walk are
+    /// inspected, and each of them stands for the `stride` keys following it, where
+    /// `stride` is the number of walked keys divided by the number of samples. So the
+    /// counts are exact if the walk holds no more than `MAX_HISTOGRAM_SAMPLES` keys
+    /// (live or deleted). Otherwise each count may be off by about `stride` keys at
+    /// each boundary, and the deleted keys are extrapolated from the inspected ones.
+    pub(crate) fn key_histogram(
+        &self,
+        key: &[u8],
+        range_end: &[u8],
+        buckets: usize,
+    ) -> KeyHistogram {
+        self.bounded_key_histogram(key, range_end, buckets, MAX_HISTOGRAM_ENTRIES)
+    }
+
+    /// Estimate the distribution of the live keys in a range, walking at most
+    /// `max_entries` entries
+    fn bounded_key_histogram(
+        &self,
+        key: &[u8],
+        range_end: &[u8],
+        buckets: usize,
+        max_entries: usize,
+    ) -> KeyHistogram {
+        if buckets == 0 {
+            return KeyHistogram::default();
+        }
+        let mut stride: usize = 1;
+        // sampled keys with their positions in the walk
+        let mut samples: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut next_key = None;
+        for (pos, entry) in self.inner.range(KeyRange::new(key, range_end)).enumerate() {
+            if pos >= max_entries {
+                next_key = Some(entry.key().clone());
+                break;
+            }
+            if pos.overflow_rem(stride) != 0 {
+                continue;
+            }
+            let is_live = entry
+                .value()
+                .map_read(|revs| revs.last().map_or(false, |rev| !rev.is_deleted()));
+            if is_live {
+                samples.push((pos, entry.key().clone()));
+            }
+            if pos >= MAX_HISTOGRAM_SAMPLES.overflow_mul(stride) {
+                stride = stride.overflow_mul(2);
+                samples.retain(|&(p, _)| p.overflow_rem(stride) == 0);
+            }
+        }
+        let per_bucket = samples.len().div_ceil(buckets).max(1);
+        let weight: u64 = stride.numeric_cast();
+        let mut histogram: Vec<KeyBucket> = samples
+            .chunks(per_bucket)
+            .map(|chunk| KeyBucket {
+                start: chunk.first().map(|s| s.1.clone()).unwrap_or_default(),
+                end: vec![],
+                count: weight.overflow_mul(chunk.len().numeric_cast()),
+            })
+            .collect();
+        let starts: Vec<_> = histogram.iter().skip(1).map(|b| b.start.clone()).collect();
+        for (bucket, end) in histogram.iter_mut().zip(starts) {
+            bucket.end = end;
+        }
+        if let Some(first) = histogram.first_mut() {
+            first.start = key.to_vec();
+        }
+        if let Some(last) = histogram.last_mut() {
+            last.end = next_key.clone().unwrap_or_else(|| range_end.to_vec());
+        }
+        KeyHistogram {
+            buckets: histogram,
+            next_key,
+        }
+    }
 }
 
 /// Operations of Index
@@ -111,9 +393,17 @@ pub(super) trait IndexOperate {
     /// Insert or update `KeyRevision`
     fn insert(&self, key_revisions: Vec<(Vec<u8>, KeyRevision)>);
 
+    /// Insert or update `KeyRevision` of a bulk load, the affected entries are
+    /// merged with the sorted key revisions at once instead of searched one by one
+    fn insert_bulk(&self, key_revisions: Vec<(Vec<u8>, KeyRevision)>);
+
     /// Register a new `KeyRevision` of the given key
     fn register_revision(&self, key: &[u8], revision: i64, sub_revision: i64) -> KeyRevision;
 
+    /// Register the new `KeyRevision`s of the puts of a bulk load, the sub revision
+    /// of a key is its position in `keys`
+    fn register_revisions(&self, keys: &[&[u8]], revision: i64) -> Vec<KeyRevision>;
+
     /// Restore `KeyRevision` of a key
     fn restore(
         &self,
@@ -126,7 +416,8 @@ pub(super) trait IndexOperate {
 
     /// Compact a `KeyRevision` by removing the versions with smaller or equal
     /// revision than the given atRev except the largest one (If the largest one is
-    /// a tombstone, it will not be kept).
+    /// a tombstone, it will not be kept). The protected keys only lose their history
+    /// up to a deletion older than the protected retention.
     fn compact(&self, at_rev: i64) -> Vec<KeyRevision>;
 }
 
@@ -268,29 +559,63 @@ impl IndexOperate for Index {
         }
     }
 
+    fn insert_bulk(&self, mut key_revisions: Vec<(Vec<u8>, KeyRevision)>) {
+        // a stable sort keeps the revisions of a key in order
+        key_revisions.sort_by(|a, b| a.0.cmp(&b.0));
+        let entries = {
+            let keys: Vec<&[u8]> = key_revisions
+                .iter()
+                .map(|(key, _)| key.as_slice())
+                .collect();
+            self.merge_entries(&keys)
+        };
+        let mut last: Option<IndexEntry<'_>> = None;
+        for ((key, revision), entry) in key_revisions.into_iter().zip(entries) {
+            // a key absent from the index may have been inserted by its previous revision
+            match entry.or_else(|| last.take().filter(|prev| *prev.key() == key)) {
+                Some(entry) => {
+                    entry.value().map_write(|mut revs| revs.push(revision));
+                    last = Some(entry);
+                }
+                None => last = Some(self.inner.insert(key, RwLock::new(vec![revision]))),
+            }
+        }
+    }
+
     fn register_revision(&self, key: &[u8], revision: i64, sub_revision: i64) -> KeyRevision {
         if let Some(entry) = self.inner.get(key) {
             entry.value().map_read(|revisions| {
-                if let Some(rev) = revisions.last() {
-                    if rev.is_deleted() {
-                        KeyRevision::new(revision, 1, revision, sub_revision)
-                    } else {
-                        KeyRevision::new(
-                            rev.create_revision,
-                            rev.version.overflow_add(1),
-                            revision,
-                            sub_revision,
-                        )
-                    }
-                } else {
+                let Some(rev) = revisions.last() else {
                     panic!("Get empty revision list for key {key:?}");
-                }
+                };
+                Self::next_revision(Some(rev), revision, sub_revision)
             })
         } else {
-            KeyRevision::new(revision, 1, revision, sub_revision)
+            Self::next_revision(None, revision, sub_revision)
         }
     }
 
+    fn register_revisions(&self, keys: &[&[u8]], revision: i64) -> Vec<KeyRevision> {
+        let mut sorted: Vec<(&[u8], i64)> = keys.iter().copied().zip(0..).collect();
+        sorted.sort_unstable();
+        let sorted_keys: Vec<&[u8]> = sorted.iter().map(|&(key, _)| key).collect();
+        let mut revisions: Vec<(i64, KeyRevision)> = sorted
+            .iter()
+            .zip(self.merge_entries(&sorted_keys))
+            .map(|(&(_, sub_revision), entry)| {
+                let rev = match entry {
+                    Some(entry) => entry
+                        .value()
+                        .map_read(|revs| Self::next_revision(revs.last(), revision, sub_revision)),
+                    None => Self::next_revision(None, revision, sub_revision),
+                };
+                (sub_revision, rev)
+            })
+            .collect();
+        revisions.sort_unstable_by_key(|&(sub_revision, _)| sub_revision);
+        revisions.into_iter().map(|(_, rev)| rev).collect()
+    }
+
     fn restore(
         &self,
         key: Vec<u8>,
@@ -318,7 +643,15 @@ impl IndexOperate for Index {
 
         self.inner.iter().for_each(|entry| {
             entry.value().map_write(|mut revisions| {
-                if let Some(revision) = revisions.first() {
+                if self.protection.is_protected(entry.key()) {
+                    let compactable = self.protection.compactable(&revisions, at_rev);
+                    if compactable > 0 {
+                        revs.extend(revisions.drain(..compactable));
+                        if revisions.is_empty() {
+                            del_keys.push(entry.key().clone());
+                        }
+                    }
+                } else if let Some(revision) = revisions.first() {
                     if revision.mod_revision < at_rev {
                         let pivot = revisions.partition_point(|rev| rev.mod_revision <= at_rev);
                         let compacted_last_idx = pivot.overflow_sub(1);
@@ -580,4 +913,142 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn protected_keys_should_keep_their_history_until_deleted_and_expired() {
+        let protection = CompactProtection::new(&["ke".to_owned()], 5);
+        assert!(protection.covers(b"key", b""));
+        assert!(protection.covers(b"ke", b"kf"));
+        assert!(!protection.covers(b"ke", b"kg"));
+        assert!(!protection.covers(b"foo", b""));
+        let index = Index::new_with_protection(protection);
+        for (key, revision) in [("key", 1), ("key", 2), ("foo", 3), ("foo", 4)] {
+            index.insert(vec![(
+                key.into(),
+                index.register_revision(key.as_bytes(), revision, 0),
+            )]);
+        }
+
+        let res = index.compact(5);
+        assert_eq!(res, vec![KeyRevision::new(3, 1, 3, 0)]);
+        match_values(
+            &index,
+            b"key",
+            &[KeyRevision::new(1, 1, 1, 0), KeyRevision::new(1, 2, 2, 0)],
+        );
+
+        index.delete(b"key", b"", 6, 0);
+        index.insert(vec![(
+            b"key".to_vec(),
+            index.register_revision(b"key", 7, 0),
+        )]);
+        // the deletion is within the retention
+        assert!(index.compact(10).is_empty());
+        let res = index.compact(11);
+        assert_eq!(
+            res,
+            vec![
+                KeyRevision::new(1, 1, 1, 0),
+                KeyRevision::new(1, 2, 2, 0),
+                KeyRevision::new(0, 0, 6, 0),
+            ]
+        );
+        match_values(&index, b"key", &[KeyRevision::new(7, 1, 7, 0)]);
+    }
+
+    #[test]
+    fn key_histogram_should_follow_skewed_keys() {
+        let index = Index::new();
+        let mut revision = 1;
+        for i in 0..9000 {
+            let key = format!("a{i:05}").into_bytes();
+            index.insert(vec![(
+                key.clone(),
+                index.register_revision(&key, revision, 0),
+            )]);
+            revision += 1;
+        }
+        for i in 0..1000 {
+            let key = format!("b{i:04}").into_bytes();
+            index.insert(vec![(
+                key.clone(),
+                index.register_revision(&key, revision, 0),
+            )]);
+            revision += 1;
+        }
+        let _revs = index.delete(b"b0500", b"b1000", revision, 0);
+
+        let histogram = index.key_histogram(b"\0", b"\0", 10);
+        assert_eq!(histogram.next_key, None);
+        let histogram = histogram.buckets;
+        assert!(histogram.len() <= 10);
+        let total: u64 = histogram.iter().map(|b| b.count).sum();
+        assert!((8550..=10450).contains(&total), "total: {total}");
+        assert!(
+            histogram
+                .iter()
+                .filter(|b| b.start >= b"b".to_vec())
+                .count()
+                <= 1
+        );
+        assert!(histogram.iter().filter(|b| b.end <= b"b".to_vec()).count() >= 8);
+        for pair in histogram.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+
+        // small ranges are counted exactly
+        let histogram = index.key_histogram(b"b", b"c", 4).buckets;
+        assert_eq!(histogram.iter().map(|b| b.count).sum::<u64>(), 500);
+        assert_eq!(
+            histogram.first().map(|b| b.start.clone()),
+            Some(b"b".to_vec())
+        );
+        assert_eq!(histogram.last().map(|b| b.end.clone()), Some(b"c".to_vec()));
+        assert!(index.key_histogram(b"b", b"c", 0).buckets.is_empty());
+
+        // the walk is bounded, and the rest of the range is continued from the next key
+        let first = index.bounded_key_histogram(b"a", b"c", 4, 6000);
+        assert_eq!(first.next_key, Some(b"a06000".to_vec()));
+        assert_eq!(
+            first.buckets.last().map(|b| b.end.clone()),
+            first.next_key.clone()
+        );
+        let first_total: u64 = first.buckets.iter().map(|b| b.count).sum();
+        assert!((5700..=6300).contains(&first_total), "total: {first_total}");
+        let rest = index.bounded_key_histogram(&first.next_key.unwrap(), b"c", 4, 6000);
+        assert_eq!(rest.next_key, None);
+        let rest_total: u64 = rest.buckets.iter().map(|b| b.count).sum();
+        assert!((3300..=3700).contains(&rest_total), "total: {rest_total}");
+    }
+
+    #[test]
+    fn bulk_registration_and_insertion_should_match_per_key() {
+        let per_key = init_and_test_insert();
+        let bulk = init_and_test_insert();
+        let _revs = per_key.delete(b"foo", &[], 10, 0);
+        let _revs = bulk.delete(b"foo", &[], 10, 0);
+        // both existing and new keys, unsorted, and far apart in the index
+        let mut keys: Vec<Vec<u8>> = vec![b"key".to_vec(), b"foo".to_vec(), b"bar".to_vec()];
+        keys.extend((0..100).rev().map(|i| format!("new{i:03}").into_bytes()));
+        keys.push(b"zzz".to_vec());
+        let key_refs: Vec<&[u8]> = keys.iter().map(Vec::as_slice).collect();
+
+        let revisions = bulk.register_revisions(&key_refs, 11);
+        let expected: Vec<_> = keys
+            .iter()
+            .zip(0..)
+            .map(|(key, sub_revision)| per_key.register_revision(key, 11, sub_revision))
+            .collect();
+        assert_eq!(revisions, expected);
+        assert_eq!(revisions[0], KeyRevision::new(1, 4, 11, 0));
+        assert_eq!(revisions[1], KeyRevision::new(11, 1, 11, 1));
+
+        per_key.insert(keys.iter().cloned().zip(expected).collect());
+        bulk.insert_bulk(keys.iter().cloned().zip(revisions).collect());
+        for key in &keys {
+            let expected = per_key.inner.get(key).unwrap().value().read().clone();
+            match_values(&bulk, key, &expected);
+        }
+        assert_eq!(bulk.inner.len(), per_key.inner.len());
+    }
 }
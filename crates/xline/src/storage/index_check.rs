@@ -0,0 +1,60 @@
+use std::{future::Future, sync::Arc};
+
+use itertools::Itertools;
+use ring::rand::{SecureRandom, SystemRandom};
+use tracing::{debug, error, warn};
+use utils::{config::IndexCheckConfig, redaction::redact_key, task_manager::Listener};
+
+use super::{storage_api::StorageApi, KvStore};
+
+/// Get a random offset of the sample, 0 if the system random source fails
+fn random_offset(rng: &SystemRandom) -> usize {
+    let mut bytes = [0; std::mem::size_of::<usize>()];
+    if rng.fill(&mut bytes).is_err() {
+        return 0;
+    }
+    usize::from_le_bytes(bytes)
+}
+
+/// Run the periodic check of the in-memory index against the backend until shutdown.
+/// `on_mismatch` is called with the mismatched keys of every sample which has any,
+/// e.g. to raise a CORRUPT alarm of the member.
+#[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
+pub(crate) async fn run_index_check<DB, F, Fut>(
+    kv_store: Arc<KvStore<DB>>,
+    config: IndexCheckConfig,
+    mut on_mismatch: F,
+    shutdown_listener: Listener,
+) where
+    DB: StorageApi,
+    F: FnMut(Vec<Vec<u8>>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let rng = SystemRandom::new();
+    let mut interval = tokio::time::interval(*config.interval());
+    // the first tick completes immediately, the first sample is checked an interval later
+    let _ignore = interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            () = shutdown_listener.wait() => return,
+        }
+        let mismatched =
+            match kv_store.mismatched_index_sample(*config.sample_size(), random_offset(&rng)) {
+                Ok(mismatched) => mismatched,
+                Err(e) => {
+                    warn!("failed to check the index against the backend: {e}");
+                    continue;
+                }
+            };
+        if mismatched.is_empty() {
+            debug!("the sampled keys of the index match the backend");
+            continue;
+        }
+        error!(
+            "the index mismatches the backend on the keys: {}",
+            mismatched.iter().map(|key| redact_key(key)).join(", ")
+        );
+        on_mismatch(mismatched).await;
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     cmp::Ordering,
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, VecDeque},
     sync::{
         atomic::{AtomicI64, Ordering::Relaxed},
         Arc,
@@ -9,18 +9,29 @@ use std::{
 
 use clippy_utilities::{NumericCast, OverflowArithmetic};
 use prost::Message;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use tracing::{debug, warn};
-use utils::table_names::{KV_TABLE, META_TABLE};
+use utils::{
+    config::RangeResultOverflow,
+    redaction::redact_key,
+    table_names::{KV_TABLE, META_TABLE},
+};
 use xlineapi::{
     command::{CommandResponse, KeyRange, SyncResponse},
     execute_error::ExecuteError,
+    server_op::{
+        ConditionalDeleteOp, ConditionalDeleteOpResult, MemberTagsOpResult, MovePrefixOp,
+        MovePrefixOpResult, ServerOp, ServerOpResult, SwapOpResult, MAX_MOVED_BYTES, SERVER_OP_KEY,
+    },
+    SAVEPOINT_PREFIX,
 };
 
 use super::{
-    db::SCHEDULED_COMPACT_REVISION,
-    index::{Index, IndexOperate},
+    auth_store::KeyUsageSource,
+    db::{RESERVED_REVISION, SCHEDULED_COMPACT_REVISION},
+    index::{CompactProtection, Index, IndexOperate, KeyHistogram},
     lease_store::LeaseCollection,
+    prefix_stats::PrefixStats,
     revision::{KeyRevision, Revision},
     storage_api::StorageApi,
 };
@@ -34,9 +45,51 @@ use crate::{
         PutResponse, RangeRequest, RangeResponse, Request, RequestWrapper, ResponseWrapper,
         SortOrder, SortTarget, TargetUnion, TxnRequest, TxnResponse,
     },
+    server::member_tags::{self, Tags},
     storage::db::{WriteOp, FINISHED_COMPACT_REVISION},
 };
 
+/// Number of revisions whose values are fetched at a time when building a range
+/// response under a memory budget
+const BUDGETED_FETCH_BATCH_SIZE: usize = 1024;
+
+/// Memory budget of the key-value pairs in a response
+#[derive(Debug)]
+struct ResponseBudget {
+    /// The budget in bytes, 0 means unlimited
+    limit: u64,
+    /// The bytes of the key-value pairs accumulated so far
+    used: u64,
+}
+
+impl ResponseBudget {
+    /// New `ResponseBudget`
+    fn new(limit: u64) -> Self {
+        Self { limit, used: 0 }
+    }
+
+    /// Check whether the budget is unlimited
+    fn is_unlimited(&self) -> bool {
+        self.limit == 0
+    }
+
+    /// Charge the key and value bytes of the key-value pairs to the budget
+    fn charge(&mut self, kvs: &[KeyValue]) -> Result<(), ExecuteError> {
+        if self.is_unlimited() {
+            return Ok(());
+        }
+        for kv in kvs {
+            self.used = self
+                .used
+                .overflow_add(kv.key.len().overflow_add(kv.value.len()).numeric_cast());
+        }
+        if self.used > self.limit {
+            return Err(ExecuteError::ResponseTooLarge(self.limit));
+        }
+        Ok(())
+    }
+}
+
 /// KV store
 #[derive(Debug)]
 pub(crate) struct KvStore<DB>
@@ -55,6 +108,56 @@ where
     compact_task_tx: mpsc::Sender<(i64, Option<Arc<event_listener::Event>>)>,
     /// Lease collection
     lease_collection: Arc<LeaseCollection>,
+    /// The options of the store
+    options: KvStoreOptions,
+    /// Gate between the serializable reads and the snapshot installs, an install holds
+    /// it exclusively so that no read observes a partially overwritten state
+    snapshot_install_gate: RwLock<()>,
+    /// The revision of the last compaction finished by the backend
+    finished_compacted_rev: AtomicI64,
+}
+
+/// The options of a `KvStore`
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct KvStoreOptions {
+    /// Whether a put whose value and lease equal the current ones is a no-op
+    pub(crate) noop_identical_put: bool,
+    /// The memory budget of a range or txn response, 0 means unlimited
+    pub(crate) range_memory_budget: u64,
+    /// The minimum number of puts of a txn applied as a bulk load, 0 disables it
+    pub(crate) bulk_load_threshold: usize,
+    /// The maximum number of keys a range returns after its filters, 0 means unlimited
+    pub(crate) max_range_result_count: usize,
+    /// How a range returning more keys than the max range result count is handled
+    pub(crate) range_result_overflow: RangeResultOverflow,
+}
+
+/// A page of the keys attached to leases at a revision
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct AttachedKeysPage {
+    /// The revision at which the page is built
+    pub(crate) revision: i64,
+    /// Lease ids with their attached keys in key order, in ascending order of lease id
+    pub(crate) leases: Vec<(i64, Vec<Vec<u8>>)>,
+    /// The lease id to start the next page from, `None` if this is the last page
+    pub(crate) next_lease_id: Option<i64>,
+}
+
+/// Holds off the snapshot installs of a `KvStore` until it is dropped
+#[derive(Debug)]
+pub(crate) struct SnapshotReadGuard<'a> {
+    /// The read side of the gate
+    _gate: RwLockReadGuard<'a, ()>,
+    /// Whether the read has waited for a snapshot install
+    waited_for_install: bool,
+}
+
+impl SnapshotReadGuard<'_> {
+    /// Check whether the read has waited for a snapshot install, the node may still be
+    /// catching up with the log after the snapshot
+    pub(crate) fn waited_for_install(&self) -> bool {
+        self.waited_for_install
+    }
 }
 
 /// KV store inner, shared by `KvStore` and `KvWatcher`
@@ -69,6 +172,8 @@ where
     db: Arc<DB>,
     /// Compacted Revision
     compacted_rev: AtomicI64,
+    /// Statistics of the tracked key prefixes
+    prefix_stats: Arc<PrefixStats>,
 }
 
 impl<DB> KvStoreInner<DB>
@@ -77,10 +182,20 @@ where
 {
     /// Create new `KvStoreInner`
     pub(crate) fn new(index: Arc<Index>, db: Arc<DB>) -> Self {
+        Self::new_with_prefix_stats(index, db, Arc::default())
+    }
+
+    /// Create new `KvStoreInner` which keeps the statistics of the tracked key prefixes
+    pub(crate) fn new_with_prefix_stats(
+        index: Arc<Index>,
+        db: Arc<DB>,
+        prefix_stats: Arc<PrefixStats>,
+    ) -> Self {
         Self {
             index,
             db,
             compacted_rev: AtomicI64::new(-1),
+            prefix_stats,
         }
     }
 
@@ -148,19 +263,49 @@ where
         Ok(events)
     }
 
-    /// Get previous `KeyValue` of a `KeyValue`
+    /// Get previous `KeyValue` of a `KeyValue`, `None` if there is no previous one or
+    /// it is compacted, which is the case once the revision of `kv` is compacted
     pub(crate) fn get_prev_kv(&self, kv: &KeyValue) -> Option<KeyValue> {
+        if kv.mod_revision <= self.compacted_revision()
+            && !self.index.protection().is_protected(&kv.key)
+        {
+            return None;
+        }
         self.get_range(&kv.key, &[], kv.mod_revision.overflow_sub(1))
             .ok()?
             .pop()
     }
 
+    /// Get the tombstone of the deletion right before a key is created by `kv`, which
+    /// only carries the key and the revision of the deletion. `None` if `kv` doesn't
+    /// create the key, the key is created for the first time, or the deletion is
+    /// compacted.
+    pub(crate) fn get_prev_deletion(&self, kv: &KeyValue) -> Option<KeyValue> {
+        if kv.create_revision != kv.mod_revision {
+            return None;
+        }
+        let mod_revision = self.index.deletion_before(&kv.key, kv.create_revision)?;
+        Some(KeyValue {
+            key: kv.key.clone(),
+            mod_revision,
+            ..KeyValue::default()
+        })
+    }
+
     /// Get compacted revision of  KV store
     pub(crate) fn compacted_revision(&self) -> i64 {
         self.compacted_rev.load(Relaxed)
     }
 
-    /// Get `KeyValue` of a range with limit and count only, return kvs and total count
+    /// Get the keys whose history is exempt from compaction
+    pub(crate) fn compact_protection(&self) -> &CompactProtection {
+        self.index.protection()
+    }
+
+    /// Get `KeyValue` of a range with limit and count only, return kvs and total count.
+    ///
+    /// The values are fetched in batches under a limited budget, so that the fetching
+    /// is aborted once the budget is exceeded
     fn get_range_with_opts(
         &self,
         key: &[u8],
@@ -168,6 +313,7 @@ where
         revision: i64,
         limit: usize,
         count_only: bool,
+        budget: &mut ResponseBudget,
     ) -> Result<(Vec<KeyValue>, usize), ExecuteError> {
         let mut revisions = self.index.get(key, range_end, revision);
         let total = revisions.len();
@@ -177,7 +323,16 @@ where
         if limit != 0 {
             revisions.truncate(limit);
         }
-        let kvs = self.get_values(&revisions)?;
+        if budget.is_unlimited() {
+            let kvs = self.get_values(&revisions)?;
+            return Ok((kvs, total));
+        }
+        let mut kvs = Vec::new();
+        for batch in revisions.chunks(BUDGETED_FETCH_BATCH_SIZE) {
+            let batch_kvs = self.get_values(batch)?;
+            budget.charge(&batch_kvs)?;
+            kvs.extend(batch_kvs);
+        }
         Ok((kvs, total))
     }
 }
@@ -210,9 +365,11 @@ where
         let mut key_to_lease: HashMap<Vec<u8>, i64> = HashMap::new();
         let kvs = self.inner.db.get_all(KV_TABLE)?;
 
+        // the reserved revisions have no keys, the writes after them must not reuse them
         let current_rev = kvs
             .last()
-            .map_or(1, |pair| Revision::decode(&pair.0).revision());
+            .map_or(1, |pair| Revision::decode(&pair.0).revision())
+            .max(self.get_meta_revision(RESERVED_REVISION)?.unwrap_or(0));
         self.revision.set(current_rev);
 
         for (key, value) in kvs {
@@ -220,6 +377,7 @@ where
             let kv = KeyValue::decode(value.as_slice())
                 .unwrap_or_else(|e| panic!("decode kv error: {e:?}"));
 
+            self.inner.prefix_stats.restore(&kv);
             if kv.lease == 0 {
                 let _ignore = key_to_lease.remove(&kv.key);
             } else {
@@ -238,14 +396,15 @@ where
         for (key, lease_id) in key_to_lease {
             self.attach(lease_id, key)?;
         }
-        if let Some(finished_rev) = self.get_compact_revision(FINISHED_COMPACT_REVISION)? {
+        if let Some(finished_rev) = self.get_meta_revision(FINISHED_COMPACT_REVISION)? {
             assert!(
                 finished_rev >= -1 && finished_rev <= current_rev,
                 "compacted revision corruption, which ({finished_rev}) must belong to the range [-1, {current_rev}]"
             );
             self.update_compacted_revision(finished_rev);
+            self.finished_compacted_rev.store(finished_rev, Relaxed);
         }
-        if let Some(scheduled_rev) = self.get_compact_revision(SCHEDULED_COMPACT_REVISION)? {
+        if let Some(scheduled_rev) = self.get_meta_revision(SCHEDULED_COMPACT_REVISION)? {
             if scheduled_rev > self.compacted_revision() {
                 let event = Arc::new(event_listener::Event::new());
                 let listener = event.listen();
@@ -262,14 +421,19 @@ where
         Ok(())
     }
 
-    /// Get compact revision from db
-    fn get_compact_revision(&self, revision_key: &str) -> Result<Option<i64>, ExecuteError> {
+    /// Get the tags of the members set by the member tags ops
+    pub(crate) fn member_tags(&self) -> Result<HashMap<u64, Tags>, ExecuteError> {
+        Ok(member_tags::parse(self.inner.db.get_all(META_TABLE)?))
+    }
+
+    /// Get a revision of the meta table from db
+    fn get_meta_revision(&self, revision_key: &str) -> Result<Option<i64>, ExecuteError> {
         let Some(revision_bytes) = self.inner.db.get_value(META_TABLE, revision_key)? else {
             return Ok(None);
         };
         let bytes = revision_bytes.try_into().map_err(|e| {
             ExecuteError::DbError(format!(
-                "cannot decode compacted revision from META_TABLE: {e:?}"
+                "cannot decode {revision_key} from META_TABLE: {e:?}"
             ))
         })?;
         Ok(Some(i64::from_le_bytes(bytes)))
@@ -287,6 +451,7 @@ where
         kv_update_tx: mpsc::Sender<(i64, Vec<Event>)>,
         compact_task_tx: mpsc::Sender<(i64, Option<Arc<event_listener::Event>>)>,
         lease_collection: Arc<LeaseCollection>,
+        options: KvStoreOptions,
     ) -> Self {
         Self {
             inner,
@@ -295,7 +460,39 @@ where
             kv_update_tx,
             compact_task_tx,
             lease_collection,
+            options,
+            snapshot_install_gate: RwLock::new(()),
+            finished_compacted_rev: AtomicI64::new(-1),
+        }
+    }
+
+    /// Wait for the in-flight serializable reads and hold off the new ones until the
+    /// returned guard is dropped, the snapshot is installed under the guard
+    pub(crate) async fn begin_snapshot_install(&self) -> RwLockWriteGuard<'_, ()> {
+        self.snapshot_install_gate.write().await
+    }
+
+    /// Hold off the snapshot installs until the returned guard is dropped, so that a
+    /// serializable read under it observes either the state before an install or the
+    /// one after it. Return `None` if an install is in progress and `wait` is false,
+    /// otherwise wait for the install to finish.
+    pub(crate) async fn begin_serializable_read(
+        &self,
+        wait: bool,
+    ) -> Option<SnapshotReadGuard<'_>> {
+        if let Ok(gate) = self.snapshot_install_gate.try_read() {
+            return Some(SnapshotReadGuard {
+                _gate: gate,
+                waited_for_install: false,
+            });
+        }
+        if !wait {
+            return None;
         }
+        Some(SnapshotReadGuard {
+            _gate: self.snapshot_install_gate.read().await,
+            waited_for_install: true,
+        })
     }
 
     /// Get revision of KV store
@@ -308,6 +505,16 @@ where
         self.inner.compacted_rev.load(Relaxed)
     }
 
+    /// Get the keys whose history is exempt from compaction
+    pub(crate) fn compact_protection(&self) -> &CompactProtection {
+        self.inner.compact_protection()
+    }
+
+    /// Get the statistics of the tracked key prefixes
+    pub(crate) fn prefix_stats(&self) -> Arc<PrefixStats> {
+        Arc::clone(&self.inner.prefix_stats)
+    }
+
     /// Update compacted revision of KV store
     pub(crate) fn update_compacted_revision(&self, revision: i64) {
         self.inner.compacted_rev.store(revision, Relaxed);
@@ -315,6 +522,7 @@ where
 
     /// Notify KV changes to KV watcher
     async fn notify_updates(&self, revision: i64, updates: Vec<Event>) {
+        self.inner.prefix_stats.apply(&updates);
         assert!(
             self.kv_update_tx.send((revision, updates)).await.is_ok(),
             "Failed to send updates to KV watcher"
@@ -440,7 +648,7 @@ where
                 } else {
                     0
                 };
-                Self::compare_i64(kv.mod_revision, les)
+                Self::compare_i64(kv.lease, les)
             }
         };
 
@@ -453,6 +661,12 @@ where
     }
 
     /// Check result of a `Compare`
+    ///
+    /// A compare is always evaluated against the current state of its keys, so it
+    /// never fails with a compaction error: the create and mod revisions of a live
+    /// key are those of its latest version, which is never compacted, even if the
+    /// compared revision itself is compacted. A key deleted before the compacted
+    /// revision is absent, as it is before the compaction.
     fn check_compare(&self, cmp: &Compare) -> bool {
         let kvs = self
             .inner
@@ -499,9 +713,67 @@ where
         let ops = vec![WriteOp::PutFinishedCompactRevision(revision)];
         _ = self.inner.db.flush_ops(ops)?;
         self.update_compacted_revision(revision);
+        self.finished_compacted_rev.store(revision, Relaxed);
         Ok(())
     }
 
+    /// Get the number of revisions compacted but not yet removed from the backend
+    pub(crate) fn compaction_backlog(&self) -> i64 {
+        self.compacted_revision()
+            .overflow_sub(self.finished_compacted_rev.load(Relaxed))
+            .max(0)
+    }
+
+    /// Get the versions of the encoded revisions to compact, which are archived before
+    /// they are removed from the backend
+    pub(crate) fn compacting_values(
+        &self,
+        revisions: &[Vec<u8>],
+    ) -> Result<Vec<KeyValue>, ExecuteError> {
+        self.inner
+            .db
+            .get_values(KV_TABLE, revisions)?
+            .into_iter()
+            .flatten()
+            .map(|v| KeyValue::decode(v.as_slice()))
+            .collect::<Result<_, _>>()
+            .map_err(|e| {
+                ExecuteError::DbError(format!("Failed to decode key-value from DB, error: {e}"))
+            })
+    }
+
+    /// Check a sample of the keys in the index against the backend, see `Index::sample`
+    /// for the sampling. Returns the sampled keys whose latest versions in the index
+    /// are not the ones stored at their revisions in the backend.
+    pub(crate) fn mismatched_index_sample(
+        &self,
+        sample_size: usize,
+        offset: usize,
+    ) -> Result<Vec<Vec<u8>>, ExecuteError> {
+        let sample = self.inner.index.sample(sample_size, offset);
+        let revisions: Vec<Vec<u8>> = sample
+            .iter()
+            .map(|&(_, ref rev)| rev.as_revision().encode_to_vec())
+            .collect();
+        let values = self.inner.db.get_values(KV_TABLE, &revisions)?;
+        let mut mismatched = Vec::new();
+        for ((key, rev), value) in sample.into_iter().zip(values) {
+            let matched = value
+                .and_then(|v| KeyValue::decode(v.as_slice()).ok())
+                .is_some_and(|kv| {
+                    kv.key == key
+                        && kv.mod_revision == rev.mod_revision
+                        && kv.create_revision == rev.create_revision
+                        && kv.version == rev.version
+                });
+            // a key updated or compacted after it is sampled is checked next time
+            if !matched && self.inner.index.latest(&key) == Some(rev) {
+                mismatched.push(key);
+            }
+        }
+        Ok(mismatched)
+    }
+
     /// Calculate hash of kv storage
     pub(crate) fn hash_kv(&self, mut rev: i64) -> Result<(u32, i64, i64), ExecuteError> {
         let (compact_rev, current_rev) = (self.compacted_revision(), self.revision());
@@ -534,6 +806,79 @@ where
         let hash = hasher.finalize();
         Ok((hash, compact_rev, rev))
     }
+
+    /// Estimate the distribution of the live keys in a range, see
+    /// `Index::key_histogram` for the accuracy of the estimation
+    pub(crate) fn key_histogram(
+        &self,
+        key: &[u8],
+        range_end: &[u8],
+        buckets: usize,
+    ) -> KeyHistogram {
+        self.inner.index.key_histogram(key, range_end, buckets)
+    }
+
+    /// Get the keys attached to the leases whose id is not less than `start_lease_id`
+    /// at `revision`, or at the current revision if it's not positive, grouped by lease
+    /// id. At most `limit` leases are returned, 0 means no limit.
+    ///
+    /// The keys are read from the keyspace at the revision instead of the lease
+    /// collection, which only knows the current attachments, so that all pages of a
+    /// listing pinned to a revision are consistent however the keys are attached and
+    /// detached in the meantime.
+    pub(crate) fn attached_keys(
+        &self,
+        revision: i64,
+        start_lease_id: i64,
+        limit: usize,
+    ) -> Result<AttachedKeysPage, ExecuteError> {
+        let (compact_rev, current_rev) = (self.compacted_revision(), self.revision());
+        if revision > 0 && revision < compact_rev {
+            return Err(ExecuteError::RevisionCompacted(revision, compact_rev));
+        }
+        if revision > current_rev {
+            return Err(ExecuteError::RevisionTooLarge(revision, current_rev));
+        }
+        let revision = if revision > 0 { revision } else { current_rev };
+        let revisions = self.inner.index.get(&[0], &[0], revision);
+        let mut leases: BTreeMap<i64, Vec<Vec<u8>>> = BTreeMap::new();
+        for batch in revisions.chunks(BUDGETED_FETCH_BATCH_SIZE) {
+            for kv in self.inner.get_values(batch)? {
+                if kv.lease != 0 && kv.lease >= start_lease_id {
+                    leases.entry(kv.lease).or_default().push(kv.key);
+                }
+            }
+        }
+        let mut leases = leases.into_iter();
+        let page: Vec<_> = if limit == 0 {
+            leases.by_ref().collect()
+        } else {
+            leases.by_ref().take(limit).collect()
+        };
+        Ok(AttachedKeysPage {
+            revision,
+            leases: page,
+            next_lease_id: leases.next().map(|(id, _)| id),
+        })
+    }
+}
+
+impl<DB> KeyUsageSource for KvStore<DB>
+where
+    DB: StorageApi,
+{
+    fn key_sizes(&self, range: &KeyRange) -> Result<Vec<(Vec<u8>, u64)>, ExecuteError> {
+        let kvs = self
+            .inner
+            .get_range(range.range_start(), range.range_end(), 0)?;
+        Ok(kvs
+            .into_iter()
+            .map(|kv| {
+                let size = kv.key.len().overflow_add(kv.value.len()).numeric_cast();
+                (kv.key, size)
+            })
+            .collect())
+    }
 }
 
 /// handle and sync kv requests
@@ -546,7 +891,7 @@ where
         &self,
         wrapper: &RequestWrapper,
     ) -> Result<ResponseWrapper, ExecuteError> {
-        debug!("Execute {:?}", wrapper);
+        debug!("Execute {}", wrapper);
         #[allow(clippy::wildcard_enum_match_arm)]
         let res = match *wrapper {
             RequestWrapper::RangeRequest(ref req) => self.handle_range_request(req).map(Into::into),
@@ -565,18 +910,40 @@ where
 
     /// Handle `RangeRequest`
     fn handle_range_request(&self, req: &RangeRequest) -> Result<RangeResponse, ExecuteError> {
-        req.check_revision(self.compacted_revision(), self.revision())?;
+        self.handle_range_request_with_budget(
+            req,
+            &mut ResponseBudget::new(self.options.range_memory_budget),
+        )
+    }
+
+    /// Handle `RangeRequest` whose response is charged to the budget
+    fn handle_range_request_with_budget(
+        &self,
+        req: &RangeRequest,
+        budget: &mut ResponseBudget,
+    ) -> Result<RangeResponse, ExecuteError> {
+        req.check_revision_with_protection(
+            self.compacted_revision(),
+            self.revision(),
+            self.compact_protection(),
+        )?;
 
+        // a limit above the max result count is capped to it, and an overflow past the
+        // cap is handled by the range result overflow
+        let max_count: i64 = self.options.max_range_result_count.numeric_cast();
+        let result_cap =
+            Some(max_count).filter(|&cap| cap > 0 && (req.limit == 0 || req.limit > cap));
+        let limit = result_cap.unwrap_or(req.limit);
         let storage_fetch_limit = if (req.sort_order() != SortOrder::None)
             || (req.max_mod_revision != 0)
             || (req.min_mod_revision != 0)
             || (req.max_create_revision != 0)
             || (req.min_create_revision != 0)
-            || (req.limit == 0)
+            || (limit == 0)
         {
             0 // get all from storage then sort and filter
         } else {
-            req.limit.overflow_add(1) // get one extra for "more" flag
+            limit.overflow_add(1) // get one extra for "more" flag
         };
         let (mut kvs, total) = self.inner.get_range_with_opts(
             &req.key,
@@ -584,6 +951,7 @@ where
             req.revision,
             storage_fetch_limit.numeric_cast(),
             req.count_only,
+            budget,
         )?;
         let mut response = RangeResponse {
             header: Some(self.header_gen.gen_header()),
@@ -603,7 +971,61 @@ where
         );
         Self::sort_kvs(&mut kvs, req.sort_order(), req.sort_target());
 
-        if (req.limit > 0) && (kvs.len() > req.limit.numeric_cast()) {
+        if (limit > 0) && (kvs.len() > limit.numeric_cast()) {
+            if result_cap.is_some()
+                && self.options.range_result_overflow == RangeResultOverflow::Reject
+            {
+                return Err(ExecuteError::TooManyResults(
+                    self.options.max_range_result_count,
+                ));
+            }
+            response.more = true;
+            kvs.truncate(limit.numeric_cast());
+        }
+        if req.keys_only {
+            kvs.iter_mut().for_each(|kv| kv.value.clear());
+        }
+        response.kvs = kvs;
+        Ok(response)
+    }
+
+    /// Handle a `RangeRequest` at a compacted revision, the versions removed by the
+    /// compaction are given by `archived`. The visible version of a key is the latest
+    /// one at the revision among the archived and the retained versions.
+    pub(crate) fn handle_archived_range_request(
+        &self,
+        req: &RangeRequest,
+        archived: Vec<KeyValue>,
+    ) -> Result<RangeResponse, ExecuteError> {
+        let key_range = KeyRange::new(req.key.as_slice(), req.range_end.as_slice());
+        let retained = self
+            .inner
+            .get_range(&req.key, &req.range_end, req.revision)?;
+        let mut visible: BTreeMap<Vec<u8>, KeyValue> = BTreeMap::new();
+        for kv in retained.into_iter().chain(archived) {
+            if kv.mod_revision > req.revision || !key_range.contains_key(&kv.key) {
+                continue;
+            }
+            match visible.get(&kv.key) {
+                Some(latest) if latest.mod_revision >= kv.mod_revision => {}
+                _ => {
+                    let _prev = visible.insert(kv.key.clone(), kv);
+                }
+            }
+        }
+        let mut kvs: Vec<KeyValue> = visible
+            .into_values()
+            .filter(|kv| kv.version != 0 || kv.create_revision != 0)
+            .collect();
+        let mut response = RangeResponse {
+            header: Some(self.header_gen.gen_header()),
+            count: kvs.len().numeric_cast(),
+            ..RangeResponse::default()
+        };
+        if req.count_only {
+            return Ok(response);
+        }
+        if req.limit > 0 && kvs.len() > req.limit.numeric_cast() {
             response.more = true;
             kvs.truncate(req.limit.numeric_cast());
         }
@@ -620,6 +1042,11 @@ where
             header: Some(self.header_gen.gen_header()),
             ..Default::default()
         };
+        if req.key == SERVER_OP_KEY {
+            return Err(ExecuteError::Rejected(
+                "the marker key of the server ops can't be written".to_owned(),
+            ));
+        }
         if req.lease != 0 && self.lease_collection.look_up(req.lease).is_none() {
             return Err(ExecuteError::LeaseNotFound(req.lease));
         };
@@ -654,7 +1081,27 @@ where
 
     /// Handle `TxnRequest`
     fn handle_txn_request(&self, req: &TxnRequest) -> Result<TxnResponse, ExecuteError> {
-        req.check_revision(self.compacted_revision(), self.revision())?;
+        if let Some(op) = ServerOp::from_txn(req)? {
+            let (_requests, result) = self.resolve_server_op(&op)?;
+            return Ok(result.into_txn_response(Some(self.header_gen.gen_header())));
+        }
+        self.handle_txn_request_with_budget(
+            req,
+            &mut ResponseBudget::new(self.options.range_memory_budget),
+        )
+    }
+
+    /// Handle `TxnRequest`, the responses of all its ranges are charged to the budget
+    fn handle_txn_request_with_budget(
+        &self,
+        req: &TxnRequest,
+        budget: &mut ResponseBudget,
+    ) -> Result<TxnResponse, ExecuteError> {
+        req.check_revision_with_protection(
+            self.compacted_revision(),
+            self.revision(),
+            self.compact_protection(),
+        )?;
 
         let success = req
             .compare
@@ -667,7 +1114,17 @@ where
         };
         let mut responses = Vec::with_capacity(requests.len());
         for request_op in requests {
-            let response = self.handle_kv_requests(&request_op.clone().into())?;
+            let wrapper: RequestWrapper = request_op.clone().into();
+            #[allow(clippy::wildcard_enum_match_arm)]
+            let response = match wrapper {
+                RequestWrapper::RangeRequest(ref req) => self
+                    .handle_range_request_with_budget(req, budget)
+                    .map(Into::into)?,
+                RequestWrapper::TxnRequest(ref req) => self
+                    .handle_txn_request_with_budget(req, budget)
+                    .map(Into::into)?,
+                _ => self.handle_kv_requests(&wrapper)?,
+            };
             responses.push(response.into());
         }
         Ok(TxnResponse {
@@ -683,6 +1140,13 @@ where
         req: &CompactionRequest,
     ) -> Result<CompactionResponse, ExecuteError> {
         req.check_revision(self.compacted_revision(), self.revision())?;
+        if let Some(savepoint) = self.oldest_savepoint()? {
+            if req.revision > savepoint {
+                return Err(ExecuteError::Rejected(format!(
+                    "revision {savepoint} is pinned by a savepoint"
+                )));
+            }
+        }
 
         let target_revision = req.revision;
         debug_assert!(
@@ -695,13 +1159,29 @@ where
         })
     }
 
+    /// Get the revision of the oldest savepoint, which is the oldest create revision
+    /// of the keys under `SAVEPOINT_PREFIX`
+    fn oldest_savepoint(&self) -> Result<Option<i64>, ExecuteError> {
+        let savepoints =
+            self.inner
+                .get_range(SAVEPOINT_PREFIX, &KeyRange::get_prefix(SAVEPOINT_PREFIX), 0)?;
+        Ok(savepoints.iter().map(|kv| kv.create_revision).min())
+    }
+
     /// Sync requests in kv store
     async fn sync_request(
         &self,
         wrapper: &RequestWrapper,
         revision: i64,
     ) -> Result<(i64, Vec<WriteOp>), ExecuteError> {
-        debug!("After Sync {:?} with revision {}", wrapper, revision);
+        debug!("After Sync {} with revision {}", wrapper, revision);
+        if let RequestWrapper::PutRequest(ref req) = *wrapper {
+            if let Some(mod_revision) = self.identical_put_revision(req)? {
+                debug!("Put to {} is identical, skip it", redact_key(&req.key));
+                self.notify_updates(revision, Vec::new()).await;
+                return Ok((mod_revision, Vec::new()));
+            }
+        }
         #[allow(clippy::wildcard_enum_match_arm)] // only kv requests can be sent to kv store
         let (ops, events) = match *wrapper {
             RequestWrapper::RangeRequest(_) => (Vec::new(), Vec::new()),
@@ -746,24 +1226,126 @@ where
         Ok((ops, Vec::new()))
     }
 
-    /// Sync `TxnRequest` and return if kvstore is changed
+    /// Sync `TxnRequest` and return if kvstore is changed.
+    ///
+    /// The txn is applied all-or-nothing, the store is only modified after the
+    /// branches of all nested txns are chosen and all puts are validated.
     fn sync_txn_request(
         &self,
         req: &TxnRequest,
         revision: i64,
     ) -> Result<(Vec<WriteOp>, Vec<Event>), ExecuteError> {
+        if let Some(ServerOp::ReserveRevisions(ref op)) = ServerOp::from_txn(req)? {
+            op.check_count()?;
+            // the block is taken when the op is prepared, its last revision is kept so
+            // that the revision is recovered above the block
+            return Ok((vec![WriteOp::PutReservedRevision(revision)], Vec::new()));
+        }
+        // the tags are kept out of the keyspace, so they fire no watch
+        if let Some(ServerOp::MemberTags(ref op)) = ServerOp::from_txn(req)? {
+            return Ok((vec![member_tags::write_op(op)], Vec::new()));
+        }
+        let requests = self.resolve_txn_request(req)?;
+        if let Some(puts) = self.bulk_load_puts(&requests) {
+            return self.sync_bulk_load(&puts, revision);
+        }
         let mut sub_revision = 0;
-        let mut origin_reqs = VecDeque::from([Request::RequestTxn(req.clone())]);
         let mut all_events = Vec::new();
         let mut all_ops = Vec::new();
-        while let Some(request) = origin_reqs.pop_front() {
+        for request in requests {
             let (mut ops, mut events) = match request {
-                Request::RequestRange(_) => (Vec::new(), Vec::new()),
                 Request::RequestPut(ref put_req) => {
                     self.sync_put_request(put_req, revision, sub_revision)?
                 }
+                Request::RequestDeleteRange(ref del_req) => {
+                    self.sync_delete_range_request(del_req, revision, sub_revision)
+                }
+                Request::RequestRange(_) | Request::RequestTxn(_) => continue,
+            };
+            sub_revision = sub_revision.overflow_add(events.len().numeric_cast());
+            all_events.append(&mut events);
+            all_ops.append(&mut ops);
+        }
+        Ok((all_ops, all_events))
+    }
+
+    /// Get the puts of a txn if it is applied as a bulk load, which only puts at least
+    /// `bulk_load_threshold` keys
+    fn bulk_load_puts<'a>(&self, requests: &'a [Request]) -> Option<Vec<&'a PutRequest>> {
+        if self.options.bulk_load_threshold == 0
+            || requests.len() < self.options.bulk_load_threshold
+        {
+            return None;
+        }
+        requests
+            .iter()
+            .map(|request| match *request {
+                Request::RequestPut(ref put_req) => Some(put_req),
+                Request::RequestRange(_)
+                | Request::RequestDeleteRange(_)
+                | Request::RequestTxn(_) => None,
+            })
+            .collect()
+    }
+
+    /// Sync the puts of a bulk load.
+    ///
+    /// It produces the same changes as syncing the puts one by one, but the revisions
+    /// of all the keys are registered by a single merge with the index, and so are
+    /// they inserted into the index by `insert_index` after they are flushed.
+    fn sync_bulk_load(
+        &self,
+        puts: &[&PutRequest],
+        revision: i64,
+    ) -> Result<(Vec<WriteOp>, Vec<Event>), ExecuteError> {
+        let keys: Vec<&[u8]> = puts.iter().map(|put_req| put_req.key.as_slice()).collect();
+        let revisions = self.inner.index.register_revisions(&keys, revision);
+        let mut all_events = Vec::with_capacity(puts.len());
+        let mut all_ops = Vec::with_capacity(puts.len());
+        for (put_req, new_rev) in puts.iter().zip(revisions) {
+            let (mut ops, mut events) = self.sync_put_with_revision(put_req, new_rev)?;
+            all_events.append(&mut events);
+            all_ops.append(&mut ops);
+        }
+        Ok((all_ops, all_events))
+    }
+
+    /// Choose the branches of a txn and its nested txns against the state before
+    /// the txn, and return the write requests to apply in order.
+    ///
+    /// The puts which keep the value or the lease of the key are resolved here, so
+    /// that applying the returned requests never fails halfway.
+    fn resolve_txn_request(&self, req: &TxnRequest) -> Result<Vec<Request>, ExecuteError> {
+        if let Some(op) = ServerOp::from_txn(req)? {
+            return self
+                .resolve_server_op(&op)
+                .map(|(requests, _result)| requests);
+        }
+        let mut origin_reqs = VecDeque::from([Request::RequestTxn(req.clone())]);
+        let mut requests = Vec::new();
+        while let Some(request) = origin_reqs.pop_front() {
+            match request {
+                Request::RequestRange(_) => {}
+                Request::RequestPut(mut put_req) => {
+                    if put_req.ignore_lease || put_req.ignore_value {
+                        let prev = self
+                            .inner
+                            .get_range(&put_req.key, &[], 0)?
+                            .pop()
+                            .ok_or(ExecuteError::KeyNotFound)?;
+                        if put_req.ignore_lease {
+                            put_req.lease = prev.lease;
+                            put_req.ignore_lease = false;
+                        }
+                        if put_req.ignore_value {
+                            put_req.value = prev.value;
+                            put_req.ignore_value = false;
+                        }
+                    }
+                    requests.push(Request::RequestPut(put_req));
+                }
                 Request::RequestDeleteRange(del_req) => {
-                    self.sync_delete_range_request(&del_req, revision, sub_revision)
+                    requests.push(Request::RequestDeleteRange(del_req));
                 }
                 Request::RequestTxn(txn_req) => {
                     let success = txn_req
@@ -776,14 +1358,222 @@ where
                         txn_req.failure.into_iter()
                     };
                     origin_reqs.extend(reqs_iter.filter_map(|req_op| req_op.request));
-                    continue;
                 }
-            };
-            sub_revision = sub_revision.overflow_add(events.len().numeric_cast());
-            all_events.append(&mut events);
-            all_ops.append(&mut ops);
+            }
         }
-        Ok((all_ops, all_events))
+        Ok(requests)
+    }
+
+    /// Resolve a server op against the current state into the write requests applying
+    /// it and its result. It's resolved the same way when the op is executed and when
+    /// it is synced, and the commands conflicting with it are serialized by the keys
+    /// of its footprint, so both see the same state.
+    fn resolve_server_op(
+        &self,
+        op: &ServerOp,
+    ) -> Result<(Vec<Request>, ServerOpResult), ExecuteError> {
+        match *op {
+            ServerOp::Increment(ref inc) => {
+                let encoding = inc.counter_encoding()?;
+                let prev = self.inner.get_range(&inc.key, &[], 0)?.pop();
+                let current = match prev {
+                    Some(ref kv) => encoding.decode(&kv.value).ok_or_else(|| {
+                        ExecuteError::Rejected(format!(
+                            "the value of the key is not a {encoding:?} counter"
+                        ))
+                    })?,
+                    None => 0,
+                };
+                let value = current
+                    .checked_add(inc.delta)
+                    .ok_or_else(|| ExecuteError::Rejected("the counter overflows".to_owned()))?;
+                // the counter keeps its lease
+                let put = PutRequest {
+                    key: inc.key.clone(),
+                    value: encoding.encode(value),
+                    lease: prev.map_or(0, |kv| kv.lease),
+                    ..Default::default()
+                };
+                Ok((
+                    vec![Request::RequestPut(put)],
+                    ServerOpResult::Increment(value),
+                ))
+            }
+            ServerOp::Append(ref app) => {
+                let (mut value, lease) = self
+                    .inner
+                    .get_range(&app.key, &[], 0)?
+                    .pop()
+                    .map_or((Vec::new(), 0), |kv| (kv.value, kv.lease));
+                let length: u64 = value.len().overflow_add(app.bytes.len()).numeric_cast();
+                let limit = app.value_size_limit();
+                if length > limit {
+                    return Err(ExecuteError::Rejected(format!(
+                        "the appended value of {length} bytes exceeds the limit of {limit} bytes"
+                    )));
+                }
+                value.extend_from_slice(&app.bytes);
+                let put = PutRequest {
+                    key: app.key.clone(),
+                    value,
+                    lease,
+                    ..Default::default()
+                };
+                Ok((
+                    vec![Request::RequestPut(put)],
+                    ServerOpResult::Append(length),
+                ))
+            }
+            ServerOp::Swap(ref swap) => {
+                if swap.first == swap.second {
+                    return Err(ExecuteError::Rejected(
+                        "the keys to swap are the same".to_owned(),
+                    ));
+                }
+                let first = self.inner.get_range(&swap.first, &[], 0)?.pop();
+                let second = self.inner.get_range(&swap.second, &[], 0)?.pop();
+                if (first.is_none() || second.is_none()) && !swap.create_missing {
+                    return Err(ExecuteError::KeyNotFound);
+                }
+                let swapped = |key: &[u8], own: Option<&KeyValue>, other: Option<&KeyValue>| {
+                    // a missing key has no lease to keep
+                    let lease = if swap.swap_leases { other } else { own };
+                    Request::RequestPut(PutRequest {
+                        key: key.to_vec(),
+                        value: other.map(|kv| kv.value.clone()).unwrap_or_default(),
+                        lease: lease.map_or(0, |kv| kv.lease),
+                        ..Default::default()
+                    })
+                };
+                let requests = vec![
+                    swapped(&swap.first, first.as_ref(), second.as_ref()),
+                    swapped(&swap.second, second.as_ref(), first.as_ref()),
+                ];
+                Ok((requests, ServerOpResult::Swap(SwapOpResult {})))
+            }
+            ServerOp::ReserveRevisions(ref reserve) => {
+                reserve.check_count()?;
+                Ok((Vec::new(), ServerOpResult::ReserveRevisions(reserve.count)))
+            }
+            ServerOp::GrantLeases(_) => Err(ExecuteError::Rejected(
+                "a batch of lease grants is applied by the lease store".to_owned(),
+            )),
+            ServerOp::ImportLeases(_) => Err(ExecuteError::Rejected(
+                "an import of leases is applied by the lease store".to_owned(),
+            )),
+            ServerOp::MovePrefix(ref mv) => self.resolve_prefix_move(mv),
+            ServerOp::ConditionalDelete(ref del) => self.resolve_conditional_delete(del),
+            ServerOp::MemberTags(_) => Ok((
+                Vec::new(),
+                ServerOpResult::MemberTags(MemberTagsOpResult {}),
+            )),
+            ServerOp::RehashPassword(_) => Err(ExecuteError::Rejected(
+                "a password re-hash is applied by the auth store".to_owned(),
+            )),
+        }
+    }
+
+    /// Resolve a prefix move into the puts of the keys under the destination and the
+    /// delete of the source prefix
+    fn resolve_prefix_move(
+        &self,
+        mv: &MovePrefixOp,
+    ) -> Result<(Vec<Request>, ServerOpResult), ExecuteError> {
+        mv.check_prefixes()?;
+        let destination_end = KeyRange::get_prefix(&mv.destination);
+        if !self
+            .inner
+            .index
+            .get(&mv.destination, &destination_end, 0)
+            .is_empty()
+        {
+            return Err(ExecuteError::Rejected(
+                "the destination prefix of the move is not empty".to_owned(),
+            ));
+        }
+        let source_end = KeyRange::get_prefix(&mv.source);
+        let kvs = self.inner.get_range(&mv.source, &source_end, 0)?;
+        let moved_bytes = kvs.iter().fold(0_u64, |bytes, kv| {
+            let moved_key_len = mv
+                .destination
+                .len()
+                .overflow_add(kv.key.len().saturating_sub(mv.source.len()));
+            bytes
+                .saturating_add(moved_key_len.numeric_cast())
+                .saturating_add(kv.value.len().numeric_cast())
+        });
+        if moved_bytes > MAX_MOVED_BYTES {
+            return Err(ExecuteError::Rejected(format!(
+                "the keys to move take {moved_bytes} bytes, more than {MAX_MOVED_BYTES}"
+            )));
+        }
+        let moved = kvs.len().numeric_cast();
+        let mut requests: Vec<_> = kvs
+            .into_iter()
+            .map(|kv| {
+                Request::RequestPut(PutRequest {
+                    key: mv.moved_key(&kv.key),
+                    value: kv.value,
+                    lease: kv.lease,
+                    ..Default::default()
+                })
+            })
+            .collect();
+        requests.push(Request::RequestDeleteRange(DeleteRangeRequest {
+            key: mv.source.clone(),
+            range_end: source_end,
+            ..Default::default()
+        }));
+        Ok((
+            requests,
+            ServerOpResult::MovePrefix(MovePrefixOpResult { moved }),
+        ))
+    }
+
+    /// Resolve a conditional delete into the delete of the range if there are fewer
+    /// keys in it than the threshold, or no request if it is skipped
+    fn resolve_conditional_delete(
+        &self,
+        del: &ConditionalDeleteOp,
+    ) -> Result<(Vec<Request>, ServerOpResult), ExecuteError> {
+        del.check_threshold()?;
+        let count: u64 = self
+            .inner
+            .index
+            .get(&del.key, &del.range_end, 0)
+            .len()
+            .numeric_cast();
+        let deleted = count < del.threshold;
+        let requests = if deleted {
+            vec![Request::RequestDeleteRange(DeleteRangeRequest {
+                key: del.key.clone(),
+                range_end: del.range_end.clone(),
+                ..Default::default()
+            })]
+        } else {
+            Vec::new()
+        };
+        Ok((
+            requests,
+            ServerOpResult::ConditionalDelete(ConditionalDeleteOpResult { count, deleted }),
+        ))
+    }
+
+    /// Get the mod revision of the key if the put neither changes its value nor its
+    /// lease and `noop_identical_put` is enabled.
+    ///
+    /// The revision allocated to such a put is left unused, so the put returns
+    /// the existing mod revision and does not bump the version.
+    fn identical_put_revision(&self, req: &PutRequest) -> Result<Option<i64>, ExecuteError> {
+        if !self.options.noop_identical_put {
+            return Ok(None);
+        }
+        let Some(prev) = self.inner.get_range(&req.key, &[], 0)?.pop() else {
+            return Ok(None);
+        };
+        let value_unchanged = req.ignore_value || prev.value == req.value;
+        let lease_unchanged = req.ignore_lease || prev.lease == req.lease;
+        Ok((value_unchanged && lease_unchanged).then_some(prev.mod_revision))
     }
 
     /// Sync `PutRequest` and return if kvstore is changed
@@ -793,11 +1583,20 @@ where
         revision: i64,
         sub_revision: i64,
     ) -> Result<(Vec<WriteOp>, Vec<Event>), ExecuteError> {
-        let mut ops = Vec::new();
         let new_rev = self
             .inner
             .index
             .register_revision(&req.key, revision, sub_revision);
+        self.sync_put_with_revision(req, new_rev)
+    }
+
+    /// Sync `PutRequest` whose new revision of the key is registered
+    fn sync_put_with_revision(
+        &self,
+        req: &PutRequest,
+        new_rev: KeyRevision,
+    ) -> Result<(Vec<WriteOp>, Vec<Event>), ExecuteError> {
+        let mut ops = Vec::new();
         let mut kv = KeyValue {
             key: req.key.clone(),
             value: req.value.clone(),
@@ -914,10 +1713,59 @@ where
         (ops, events)
     }
 
-    /// Insert the given pairs (key, `KeyRevision`) into the index
-    #[inline]
+    /// Reattach an existing key to a lease by a new revision of the key which keeps
+    /// its value, return the write operations and events
+    pub(crate) fn reattach_key<'a>(
+        inner: &KvStoreInner<DB>,
+        lease_collection: &LeaseCollection,
+        key: &[u8],
+        lease_id: i64,
+        revision: i64,
+        sub_revision: i64,
+    ) -> Result<(Vec<WriteOp<'a>>, Vec<Event>), ExecuteError> {
+        let prev = inner
+            .get_range(key, &[], 0)?
+            .pop()
+            .ok_or(ExecuteError::KeyNotFound)?;
+        let new_rev = inner.index.register_revision(key, revision, sub_revision);
+        let old_lease = lease_collection.get_lease(key);
+        if old_lease != 0 {
+            lease_collection
+                .detach(old_lease, key)
+                .unwrap_or_else(|e| warn!("Failed to detach lease from a key, error: {:?}", e));
+        }
+        lease_collection
+            .attach(lease_id, key.to_vec())
+            .unwrap_or_else(|e| panic!("unexpected error from lease Attach: {e}"));
+        let kv = KeyValue {
+            key: key.to_vec(),
+            value: prev.value,
+            create_revision: new_rev.create_revision,
+            mod_revision: new_rev.mod_revision,
+            version: new_rev.version,
+            lease: lease_id,
+        };
+        let ops = vec![WriteOp::PutKeyValue(new_rev.as_revision(), kv.clone())];
+        let event = Event {
+            #[allow(clippy::as_conversions)] // This cast is always valid
+            r#type: EventType::Put as i32,
+            kv: Some(kv),
+            prev_kv: None,
+        };
+        Ok((ops, vec![event]))
+    }
+
+    /// Insert the given pairs (key, `KeyRevision`) into the index, the pairs of a
+    /// bulk load are merged into the index at once
+    #[inline]
     pub(crate) fn insert_index(&self, key_revisions: Vec<(Vec<u8>, KeyRevision)>) {
-        self.inner.index.insert(key_revisions);
+        if self.options.bulk_load_threshold > 0
+            && key_revisions.len() >= self.options.bulk_load_threshold
+        {
+            self.inner.index.insert_bulk(key_revisions);
+        } else {
+            self.inner.index.insert(key_revisions);
+        }
     }
 }
 
@@ -928,18 +1776,26 @@ mod test {
     use test_macros::abort_on_panic;
     use tokio::{runtime::Handle, task::block_in_place};
     use utils::{
-        config::EngineConfig,
+        config::{EngineConfig, IndexCheckConfig, MaintenancePolicy},
+        redaction::RedactionPolicy,
         task_manager::{tasks::TaskName, TaskManager},
     };
+    use xlineapi::server_op::{
+        AppendOp, CounterEncoding, IncrementOp, SwapOp, MAX_APPENDED_VALUE_SIZE,
+    };
 
     use super::*;
     use crate::{
         revision_number::RevisionNumberGenerator,
-        rpc::{Request as UniRequest, RequestOp},
+        rpc::{AuthRoleAddRequest, AuthUserAddRequest, Request as UniRequest, RequestOp},
         storage::{
             compact::{compact_bg_task, COMPACT_CHANNEL_SIZE},
             db::DB,
+            index_check::run_index_check,
             kvwatcher::KvWatcher,
+            maintenance_scheduler::MaintenanceScheduler,
+            prefix_stats::PrefixTotal,
+            AuthStore,
         },
     };
 
@@ -1001,19 +1857,36 @@ mod test {
     }
 
     fn init_empty_store(db: Arc<DB>) -> StoreWrapper {
+        init_empty_store_with_opts(db, StoreOptions::default())
+    }
+
+    /// The options of a store for the tests
+    #[derive(Default)]
+    struct StoreOptions {
+        kv: KvStoreOptions,
+        protection: CompactProtection,
+        prefix_stats: PrefixStats,
+    }
+
+    fn init_empty_store_with_opts(db: Arc<DB>, opts: StoreOptions) -> StoreWrapper {
         let task_manager = Arc::new(TaskManager::new());
         let (compact_tx, compact_rx) = mpsc::channel(COMPACT_CHANNEL_SIZE);
         let (kv_update_tx, kv_update_rx) = mpsc::channel(CHANNEL_SIZE);
-        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let lease_collection = Arc::new(LeaseCollection::new(0, Duration::ZERO));
         let header_gen = Arc::new(HeaderGenerator::new(0, 0));
-        let index = Arc::new(Index::new());
-        let kv_store_inner = Arc::new(KvStoreInner::new(Arc::clone(&index), db));
+        let index = Arc::new(Index::new_with_protection(opts.protection));
+        let kv_store_inner = Arc::new(KvStoreInner::new_with_prefix_stats(
+            Arc::clone(&index),
+            db,
+            Arc::new(opts.prefix_stats),
+        ));
         let storage = Arc::new(KvStore::new(
             Arc::clone(&kv_store_inner),
             header_gen,
             kv_update_tx,
             compact_tx,
             lease_collection,
+            opts.kv,
         ));
         let _watcher = KvWatcher::new_arc(
             kv_store_inner,
@@ -1027,6 +1900,8 @@ mod test {
                 index,
                 1000,
                 Duration::from_millis(10),
+                Arc::new(MaintenanceScheduler::new(MaintenancePolicy::default(), &[])),
+                None,
                 compact_rx,
                 n,
             )
@@ -1055,6 +1930,81 @@ mod test {
             .collect::<Vec<Vec<_>>>()
     }
 
+    fn lease_put(key: &str, lease: i64) -> RequestWrapper {
+        RequestWrapper::from(PutRequest {
+            key: key.into(),
+            value: "v".into(),
+            lease,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn attached_keys_should_be_listed_at_the_pinned_revision() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        for id in 1..=4 {
+            let _lease = store.lease_collection.grant(id, 10, false);
+        }
+        let puts = [
+            ("a", 1),
+            ("b", 1),
+            ("c", 2),
+            ("d", 4),
+            ("e", 4),
+            ("f", 4),
+            ("g", 0),
+        ];
+        for (key, lease) in puts {
+            exe_as_and_flush(&store, &lease_put(key, lease), store.revision.next()).await?;
+        }
+        let keys = |keys: &[&str]| -> Vec<Vec<u8>> {
+            keys.iter().map(|key| key.as_bytes().to_vec()).collect()
+        };
+
+        let all = store.attached_keys(0, 0, 0)?;
+        assert_eq!(all.revision, store.revision());
+        assert_eq!(
+            all.leases,
+            vec![
+                (1, keys(&["a", "b"])),
+                (2, keys(&["c"])),
+                (4, keys(&["d", "e", "f"])),
+            ]
+        );
+        assert_eq!(all.next_lease_id, None);
+
+        let first = store.attached_keys(0, 0, 2)?;
+        assert_eq!(first.leases.len(), 2);
+        assert_eq!(first.next_lease_id, Some(4));
+        // the keys attached and detached after the first page are not seen by the
+        // following pages pinned to its revision
+        exe_as_and_flush(&store, &lease_put("a", 0), store.revision.next()).await?;
+        exe_as_and_flush(&store, &lease_put("h", 3), store.revision.next()).await?;
+        let second = store.attached_keys(first.revision, 4, 2)?;
+        assert_eq!(second.revision, first.revision);
+        assert_eq!(second.next_lease_id, None);
+        let paged: Vec<_> = first.leases.into_iter().chain(second.leases).collect();
+        assert_eq!(paged, all.leases);
+
+        let latest = store.attached_keys(0, 0, 0)?;
+        assert_eq!(
+            latest.leases,
+            vec![
+                (1, keys(&["b"])),
+                (2, keys(&["c"])),
+                (3, keys(&["h"])),
+                (4, keys(&["d", "e", "f"])),
+            ]
+        );
+        assert!(matches!(
+            store.attached_keys(store.revision().overflow_add(1), 0, 0),
+            Err(ExecuteError::RevisionTooLarge(_, _))
+        ));
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     #[abort_on_panic]
     async fn test_keys_only() -> Result<(), ExecuteError> {
@@ -1205,6 +2155,57 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn snapshot_install_should_be_gated_against_serializable_reads(
+    ) -> Result<(), ExecuteError> {
+        let source_db = DB::open(&EngineConfig::Memory)?;
+        let (_source_store, _rev) = init_store(Arc::clone(&source_db)).await?;
+        let installed = source_db.get_all(KV_TABLE)?;
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(Arc::clone(&db));
+
+        // an install waits for the in-flight reads
+        let read = store.begin_serializable_read(false).await.unwrap();
+        assert!(!read.waited_for_install());
+        let snapshot = source_db.get_snapshot("/tmp/snapshot_install_should_be_gated")?;
+        let install = async {
+            let _gate = store.begin_snapshot_install().await;
+            db.reset(Some(snapshot)).await
+        };
+        tokio::pin!(install);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), &mut install)
+                .await
+                .is_err()
+        );
+        assert!(db.get_all(KV_TABLE)?.is_empty());
+        drop(read);
+        install.await?;
+        assert_eq!(db.get_all(KV_TABLE)?, installed);
+
+        // the reads during an install are rejected or wait for it
+        let snapshot = source_db.get_snapshot("/tmp/snapshot_install_should_be_gated")?;
+        let (res, ()) = tokio::join!(
+            async {
+                let _gate = store.begin_snapshot_install().await;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                db.reset(Some(snapshot)).await
+            },
+            async {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                assert!(store.begin_serializable_read(false).await.is_none());
+                let read = store.begin_serializable_read(true).await.unwrap();
+                assert!(read.waited_for_install());
+            }
+        );
+        res?;
+        let read = store.begin_serializable_read(false).await.unwrap();
+        assert!(!read.waited_for_install());
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     #[abort_on_panic]
     async fn test_txn() -> Result<(), ExecuteError> {
@@ -1259,6 +2260,311 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn txn_should_be_applied_all_or_nothing() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, rev) = init_store(db).await?;
+        // the last put fails because the key does not exist, so the deletion of
+        // "a" must not be applied either
+        let txn_req = RequestWrapper::from(TxnRequest {
+            compare: vec![],
+            success: vec![
+                RequestOp {
+                    request: Some(Request::RequestDeleteRange(DeleteRangeRequest {
+                        key: "a".into(),
+                        ..Default::default()
+                    })),
+                },
+                RequestOp {
+                    request: Some(Request::RequestPut(PutRequest {
+                        key: "b".into(),
+                        value: "b1".into(),
+                        ..Default::default()
+                    })),
+                },
+                RequestOp {
+                    request: Some(Request::RequestPut(PutRequest {
+                        key: "missing".into(),
+                        ignore_value: true,
+                        ..Default::default()
+                    })),
+                },
+            ],
+            failure: vec![],
+        });
+        let result = exe_as_and_flush(&store, &txn_req, rev.next()).await;
+        assert!(matches!(result, Err(ExecuteError::KeyNotFound)));
+        for (key, value) in [("a", "a"), ("b", "b")] {
+            let response = store.handle_range_request(&RangeRequest {
+                key: key.into(),
+                ..Default::default()
+            })?;
+            assert_eq!(response.kvs.len(), 1);
+            assert_eq!(response.kvs[0].value, value.as_bytes());
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn increment_should_be_applied_by_a_single_txn() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, rev) = init_store(db).await?;
+        let increment = |key: &str, delta| {
+            let op = IncrementOp::new(key.into(), delta, CounterEncoding::BigEndian);
+            RequestWrapper::from(TxnRequest::from(ServerOp::Increment(op)))
+        };
+        for (delta, expected) in [(5, 5), (-7, -2)] {
+            let request = increment("counter", delta);
+            let response = store.execute(&request)?.into_inner();
+            let ResponseWrapper::TxnResponse(ref txn_res) = response else {
+                panic!("unexpected response {response:?}");
+            };
+            assert_eq!(
+                ServerOpResult::from_txn_response(txn_res),
+                Some(ServerOpResult::Increment(expected))
+            );
+            exe_as_and_flush(&store, &request, rev.next()).await?;
+            let kvs = store.handle_range_request(&RangeRequest {
+                key: "counter".into(),
+                ..Default::default()
+            })?;
+            assert_eq!(kvs.kvs[0].value, expected.to_be_bytes());
+        }
+        // "a" holds "a", which is not a counter
+        assert!(matches!(
+            store.execute(&increment("a", 1)),
+            Err(ExecuteError::Rejected(_))
+        ));
+        assert!(matches!(
+            exe_as_and_flush(&store, &increment("a", 1), rev.next()).await,
+            Err(ExecuteError::Rejected(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn append_should_enforce_the_size_limit_when_applied() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, rev) = init_store(db).await?;
+        let append = |bytes: &str| {
+            let op = AppendOp::new("log".into(), bytes.into(), 4);
+            RequestWrapper::from(TxnRequest::from(ServerOp::Append(op)))
+        };
+        exe_as_and_flush(&store, &append("abc"), rev.next()).await?;
+        // the limit is checked against the value when the append is applied
+        let request = append("de");
+        assert!(matches!(
+            exe_as_and_flush(&store, &request, rev.next()).await,
+            Err(ExecuteError::Rejected(_))
+        ));
+        let response = store.execute(&append("d"))?.into_inner();
+        let ResponseWrapper::TxnResponse(ref txn_res) = response else {
+            panic!("unexpected response {response:?}");
+        };
+        assert_eq!(
+            ServerOpResult::from_txn_response(txn_res),
+            Some(ServerOpResult::Append(4))
+        );
+        exe_as_and_flush(&store, &append("d"), rev.next()).await?;
+        let kvs = store.handle_range_request(&RangeRequest {
+            key: "log".into(),
+            ..Default::default()
+        })?;
+        assert_eq!(kvs.kvs[0].value, b"abcd");
+
+        // the limit of the client can't raise the one of the server
+        let size: usize = MAX_APPENDED_VALUE_SIZE.numeric_cast();
+        let large = |max_value_size| {
+            let op = AppendOp::new("large".into(), vec![0; size], max_value_size);
+            RequestWrapper::from(TxnRequest::from(ServerOp::Append(op)))
+        };
+        exe_as_and_flush(&store, &large(0), rev.next()).await?;
+        for max_value_size in [0, u64::MAX] {
+            assert!(matches!(
+                exe_as_and_flush(&store, &large(max_value_size), rev.next()).await,
+                Err(ExecuteError::Rejected(_))
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn swap_should_apply_both_keys_at_one_revision() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, rev) = init_store(db).await?;
+        let swap = |second: &str, create_missing| {
+            let op = SwapOp::new("a".into(), second.into(), false, create_missing);
+            RequestWrapper::from(TxnRequest::from(ServerOp::Swap(op)))
+        };
+        let revision = rev.next();
+        exe_as_and_flush(&store, &swap("b", false), revision).await?;
+        let range = |key: &str| {
+            store.handle_range_request(&RangeRequest {
+                key: key.into(),
+                ..Default::default()
+            })
+        };
+        for (key, value) in [("a", "b"), ("b", "a")] {
+            let kvs = range(key)?.kvs;
+            assert_eq!(kvs[0].value, value.as_bytes());
+            assert_eq!(kvs[0].mod_revision, revision);
+        }
+        assert!(matches!(
+            exe_as_and_flush(&store, &swap("missing", false), rev.next()).await,
+            Err(ExecuteError::KeyNotFound)
+        ));
+        exe_as_and_flush(&store, &swap("missing", true), rev.next()).await?;
+        assert_eq!(range("a")?.kvs[0].value, b"");
+        assert_eq!(range("missing")?.kvs[0].value, b"b");
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn prefix_move_should_apply_all_keys_at_one_revision() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, rev) = init_store(db).await?;
+        for key in ["cfg/a", "cfg/b/c", "cfgx"] {
+            let put = RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: key.into(),
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &put, rev.next()).await?;
+        }
+        let move_to = |destination: &str| {
+            let op = MovePrefixOp::new("cfg/".into(), destination.into());
+            RequestWrapper::from(TxnRequest::from(ServerOp::MovePrefix(op)))
+        };
+        let range = |prefix: &str| {
+            store.handle_range_request(&RangeRequest {
+                key: prefix.into(),
+                range_end: KeyRange::get_prefix(prefix.as_bytes()),
+                ..Default::default()
+            })
+        };
+        let response = store.execute(&move_to("config/"))?.into_inner();
+        let ResponseWrapper::TxnResponse(ref txn_res) = response else {
+            panic!("unexpected response {response:?}");
+        };
+        assert_eq!(
+            ServerOpResult::from_txn_response(txn_res),
+            Some(ServerOpResult::MovePrefix(MovePrefixOpResult { moved: 2 }))
+        );
+        let revision = rev.next();
+        exe_as_and_flush(&store, &move_to("config/"), revision).await?;
+        let moved: Vec<_> = range("config/")?
+            .kvs
+            .into_iter()
+            .map(|kv| (kv.key, kv.value, kv.mod_revision))
+            .collect();
+        assert_eq!(
+            moved,
+            [
+                (b"config/a".to_vec(), b"cfg/a".to_vec(), revision),
+                (b"config/b/c".to_vec(), b"cfg/b/c".to_vec(), revision),
+            ]
+        );
+        assert!(range("cfg/")?.kvs.is_empty());
+        assert_eq!(range("cfgx")?.kvs.len(), 1);
+
+        // the destination must be empty when the move is applied
+        let put = RequestWrapper::from(PutRequest {
+            key: "other/k".into(),
+            ..Default::default()
+        });
+        exe_as_and_flush(&store, &put, rev.next()).await?;
+        for destination in ["other/", "config/a/"] {
+            let op = MovePrefixOp::new("config/".into(), destination.into());
+            let request = RequestWrapper::from(TxnRequest::from(ServerOp::MovePrefix(op)));
+            assert!(matches!(
+                exe_as_and_flush(&store, &request, rev.next()).await,
+                Err(ExecuteError::Rejected(_))
+            ));
+        }
+        assert_eq!(range("config/")?.kvs.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn conditional_delete_should_apply_at_one_revision() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (store, rev) = init_store(db).await?;
+        for key in ["tmp/a", "tmp/b", "tmp/c", "tmq"] {
+            let put = RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: key.into(),
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &put, rev.next()).await?;
+        }
+        let delete_if_fewer_than = |threshold: u64| {
+            let op =
+                ConditionalDeleteOp::new("tmp/".into(), KeyRange::get_prefix(b"tmp/"), threshold);
+            RequestWrapper::from(TxnRequest::from(ServerOp::ConditionalDelete(op)))
+        };
+        let result = |request: &RequestWrapper| -> Result<_, ExecuteError> {
+            let response = store.execute(request)?.into_inner();
+            let ResponseWrapper::TxnResponse(ref txn_res) = response else {
+                panic!("unexpected response {response:?}");
+            };
+            Ok(ServerOpResult::from_txn_response(txn_res))
+        };
+        let count = |key: &str, range_end: &[u8]| -> Result<_, ExecuteError> {
+            let res = store.handle_range_request(&RangeRequest {
+                key: key.into(),
+                range_end: range_end.to_vec(),
+                count_only: true,
+                ..Default::default()
+            })?;
+            Ok(res.count)
+        };
+
+        let skipped = delete_if_fewer_than(3);
+        assert_eq!(
+            result(&skipped)?,
+            Some(ServerOpResult::ConditionalDelete(
+                ConditionalDeleteOpResult {
+                    count: 3,
+                    deleted: false
+                }
+            ))
+        );
+        exe_as_and_flush(&store, &skipped, rev.next()).await?;
+        assert_eq!(count("tmp/", &KeyRange::get_prefix(b"tmp/"))?, 3);
+
+        let performed = delete_if_fewer_than(4);
+        assert_eq!(
+            result(&performed)?,
+            Some(ServerOpResult::ConditionalDelete(
+                ConditionalDeleteOpResult {
+                    count: 3,
+                    deleted: true
+                }
+            ))
+        );
+        exe_as_and_flush(&store, &performed, rev.next()).await?;
+        assert_eq!(count("tmp/", &KeyRange::get_prefix(b"tmp/"))?, 0);
+        assert_eq!(count("tmq", &[])?, 1);
+
+        assert!(matches!(
+            result(&delete_if_fewer_than(0)),
+            Err(ExecuteError::Rejected(_))
+        ));
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     #[abort_on_panic]
     async fn test_kv_store_index_available() {
@@ -1291,42 +2597,433 @@ mod test {
     }
 
     #[tokio::test(flavor = "multi_thread")]
-    async fn test_compaction() -> Result<(), ExecuteError> {
+    #[abort_on_panic]
+    async fn test_identical_put_should_be_noop() -> Result<(), ExecuteError> {
         let db = DB::open(&EngineConfig::Memory)?;
-        let store = init_empty_store(db);
-        let revision = RevisionNumberGenerator::default();
-        // sample requests: (a, 1) (b, 2) (a, 3) (del a)
-        // their revisions:     2      3      4       5
-        let requests = vec![
-            RequestWrapper::from(PutRequest {
-                key: "a".into(),
-                value: "1".into(),
-                ..Default::default()
-            }),
-            RequestWrapper::from(PutRequest {
-                key: "b".into(),
-                value: "2".into(),
+        let store = init_empty_store_with_opts(
+            db,
+            StoreOptions {
+                kv: KvStoreOptions {
+                    noop_identical_put: true,
+                    ..Default::default()
+                },
                 ..Default::default()
-            }),
-            RequestWrapper::from(PutRequest {
-                key: "a".into(),
-                value: "3".into(),
+            },
+        );
+        let revision = RevisionNumberGenerator::default();
+        let put = RequestWrapper::from(PutRequest {
+            key: "a".into(),
+            value: "1".into(),
+            ..Default::default()
+        });
+        exe_as_and_flush(&store, &put, revision.next()).await?;
+        let (sync_res, ops) = store.after_sync(&put, revision.next()).await?;
+        assert!(ops.is_empty());
+        assert_eq!(sync_res.revision(), 2);
+        let kv = store.inner.get_range(b"a", b"", 0)?.pop().unwrap();
+        assert_eq!(kv.version, 1);
+        assert_eq!(kv.mod_revision, 2);
+
+        let changed = RequestWrapper::from(PutRequest {
+            key: "a".into(),
+            value: "2".into(),
+            ..Default::default()
+        });
+        exe_as_and_flush(&store, &changed, revision.next()).await?;
+        let kv = store.inner.get_range(b"a", b"", 0)?.pop().unwrap();
+        assert_eq!(kv.version, 2);
+        assert_eq!(kv.mod_revision, 4);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_range_over_memory_budget_should_be_aborted() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store_with_opts(
+            db,
+            StoreOptions {
+                kv: KvStoreOptions {
+                    range_memory_budget: 64 * 1024,
+                    ..Default::default()
+                },
                 ..Default::default()
-            }),
-            RequestWrapper::from(DeleteRangeRequest {
-                key: "a".into(),
+            },
+        );
+        let revision = RevisionNumberGenerator::default();
+        for i in 0..2000 {
+            let req = RequestWrapper::from(PutRequest {
+                key: format!("key{i:04}").into_bytes(),
+                value: vec![b'v'; 1024],
                 ..Default::default()
-            }),
-        ];
-
-        for req in requests {
-            exe_as_and_flush(&store, &req, revision.next())
-                .await
-                .unwrap();
+            });
+            exe_as_and_flush(&store, &req, revision.next()).await?;
         }
-
-        let target_revisions = index_compact(&store, 3);
-        store.compact(target_revisions.as_ref())?;
+        let unbounded = RangeRequest {
+            key: vec![0],
+            range_end: vec![0],
+            ..Default::default()
+        };
+        let res = store.handle_range_request(&unbounded);
+        assert!(matches!(res, Err(ExecuteError::ResponseTooLarge(65536))));
+
+        let limited = RangeRequest {
+            limit: 10,
+            ..unbounded.clone()
+        };
+        let response = store.handle_range_request(&limited)?;
+        assert_eq!(response.kvs.len(), 10);
+        assert!(response.more);
+
+        // ranges in a txn share the budget
+        let txn = TxnRequest {
+            success: (0..10)
+                .map(|_| RequestOp {
+                    request: Some(Request::RequestRange(RangeRequest {
+                        limit: 10,
+                        ..unbounded.clone()
+                    })),
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let res = store.handle_txn_request(&txn);
+        assert!(matches!(res, Err(ExecuteError::ResponseTooLarge(65536))));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn test_range_over_max_result_count_should_be_truncated_or_rejected(
+    ) -> Result<(), ExecuteError> {
+        for overflow in [RangeResultOverflow::Truncate, RangeResultOverflow::Reject] {
+            let db = DB::open(&EngineConfig::Memory)?;
+            let store = init_empty_store_with_opts(
+                db,
+                StoreOptions {
+                    kv: KvStoreOptions {
+                        max_range_result_count: 5,
+                        range_result_overflow: overflow,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            );
+            let revision = RevisionNumberGenerator::default();
+            for i in 0..10 {
+                let req = RequestWrapper::from(PutRequest {
+                    key: format!("key{i}").into_bytes(),
+                    value: b"v".to_vec(),
+                    ..Default::default()
+                });
+                exe_as_and_flush(&store, &req, revision.next()).await?;
+            }
+            let unbounded = RangeRequest {
+                key: vec![0],
+                range_end: vec![0],
+                ..Default::default()
+            };
+            for limit in [0, 10] {
+                let res = store.handle_range_request(&RangeRequest {
+                    limit,
+                    ..unbounded.clone()
+                });
+                if overflow == RangeResultOverflow::Reject {
+                    assert!(matches!(res, Err(ExecuteError::TooManyResults(5))));
+                } else {
+                    let response = res?;
+                    assert_eq!(response.kvs.len(), 5);
+                    assert!(response.more);
+                    assert_eq!(response.count, 10);
+                }
+            }
+
+            // a smaller limit and the results left by the filters are not affected
+            let limited = store.handle_range_request(&RangeRequest {
+                limit: 3,
+                ..unbounded.clone()
+            })?;
+            assert_eq!(limited.kvs.len(), 3);
+            let filtered = store.handle_range_request(&RangeRequest {
+                min_mod_revision: 7,
+                ..unbounded.clone()
+            })?;
+            assert_eq!(filtered.kvs.len(), 5);
+            assert!(!filtered.more);
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn bulk_load_should_match_per_key_writes() -> Result<(), ExecuteError> {
+        let bulk_txn = |round: usize| {
+            RequestWrapper::from(TxnRequest {
+                success: (0..10_000)
+                    .map(|i| RequestOp {
+                        // the keys of a round are interleaved with the earlier ones
+                        request: Some(Request::RequestPut(PutRequest {
+                            key: format!("key{:06}", i * 10 + round).into_bytes(),
+                            value: format!("value{round}").into_bytes(),
+                            ..Default::default()
+                        })),
+                    })
+                    .collect(),
+                ..Default::default()
+            })
+        };
+        // overwrites existing keys, including one put twice
+        let update = RequestWrapper::from(TxnRequest {
+            success: [0, 10, 0, 999_999]
+                .into_iter()
+                .map(|i| RequestOp {
+                    request: Some(Request::RequestPut(PutRequest {
+                        key: format!("key{i:06}").into_bytes(),
+                        value: b"updated".to_vec(),
+                        ..Default::default()
+                    })),
+                })
+                .collect(),
+            ..Default::default()
+        });
+        let mut ranges = Vec::new();
+        for bulk_load_threshold in [0, 1] {
+            let db = DB::open(&EngineConfig::Memory)?;
+            let store = init_empty_store_with_opts(
+                db,
+                StoreOptions {
+                    kv: KvStoreOptions {
+                        range_memory_budget: u64::MAX,
+                        bulk_load_threshold,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            );
+            let revision = RevisionNumberGenerator::default();
+            for round in 0..10 {
+                exe_as_and_flush(&store, &bulk_txn(round), revision.next()).await?;
+            }
+            exe_as_and_flush(&store, &update, revision.next()).await?;
+            let range = store.handle_range_request(&RangeRequest {
+                key: vec![0],
+                range_end: vec![0],
+                ..Default::default()
+            })?;
+            assert_eq!(range.kvs.len(), 100_001);
+            let history = store.handle_range_request(&RangeRequest {
+                key: b"key000000".to_vec(),
+                revision: 1,
+                ..Default::default()
+            })?;
+            assert_eq!(history.kvs[0].value, b"value0");
+            ranges.push(range.kvs);
+        }
+        assert_eq!(ranges[0], ranges[1]);
+        Ok(())
+    }
+
+    /// A log writer keeping the logs in memory
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<parking_lot::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn sensitive_keys_and_values_should_be_redacted_in_errors_and_logs(
+    ) -> Result<(), ExecuteError> {
+        let policy = RedactionPolicy::new(true, &["secret/".to_owned()]);
+        let _ignore = policy.clone().install();
+        assert_eq!(RedactionPolicy::current(), &policy);
+        let logs = LogBuffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .finish();
+        let _subscriber = tracing::subscriber::set_default(subscriber);
+
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store_with_opts(
+            db,
+            StoreOptions {
+                kv: KvStoreOptions {
+                    range_memory_budget: 64,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let revision = RevisionNumberGenerator::default();
+        let value = "hunter2".repeat(16);
+        let put = RequestWrapper::from(PutRequest {
+            key: b"secret/token".to_vec(),
+            value: value.clone().into_bytes(),
+            ..Default::default()
+        });
+        let _res = store.execute(&put)?;
+        exe_as_and_flush(&store, &put, revision.next()).await?;
+
+        let mut errors = vec![
+            store.execute(&RequestWrapper::from(PutRequest {
+                key: b"secret/token".to_vec(),
+                value: value.clone().into_bytes(),
+                lease: 42,
+                ..Default::default()
+            })),
+            store.execute(&RequestWrapper::from(RangeRequest {
+                key: b"secret/".to_vec(),
+                range_end: KeyRange::get_prefix(b"secret/"),
+                ..Default::default()
+            })),
+            store.execute(&RequestWrapper::from(RangeRequest {
+                key: b"secret/token".to_vec(),
+                revision: 100,
+                ..Default::default()
+            })),
+        ]
+        .into_iter()
+        .map(|res| res.unwrap_err())
+        .collect::<Vec<_>>();
+        let txn = RequestWrapper::from(TxnRequest {
+            compare: vec![Compare {
+                result: CompareResult::Equal as i32,
+                target: CompareTarget::Value as i32,
+                key: b"secret/token".to_vec(),
+                range_end: vec![],
+                target_union: Some(TargetUnion::Value(value.clone().into_bytes())),
+            }],
+            success: vec![RequestOp {
+                request: Some(Request::RequestPut(PutRequest {
+                    key: b"secret/missing".to_vec(),
+                    ignore_value: true,
+                    ..Default::default()
+                })),
+            }],
+            failure: vec![],
+        });
+        errors.push(
+            exe_as_and_flush(&store, &txn, revision.next())
+                .await
+                .unwrap_err(),
+        );
+        assert!(matches!(errors[0], ExecuteError::LeaseNotFound(42)));
+        assert!(matches!(errors[1], ExecuteError::ResponseTooLarge(64)));
+        assert!(matches!(errors[2], ExecuteError::RevisionTooLarge(100, _)));
+        assert!(matches!(errors[3], ExecuteError::KeyNotFound));
+
+        let logs = String::from_utf8(logs.0.lock().clone()).unwrap();
+        assert!(logs.contains("secret/"));
+        assert!(logs.contains("<redacted"));
+        let outputs = errors
+            .iter()
+            .map(ToString::to_string)
+            .chain(errors.iter().map(|e| format!("{e:?}")))
+            .chain([logs]);
+        for output in outputs {
+            assert!(!output.contains("hunter2"), "value leaked in {output}");
+            assert!(!output.contains("token"), "key leaked in {output}");
+            assert!(!output.contains("missing"), "key leaked in {output}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn compaction_beyond_savepoint_should_be_rejected() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        // the compaction is checked against the current revision of the store
+        let revision = Arc::clone(&store.revision);
+        let savepoint_key = [SAVEPOINT_PREFIX, b"1"].concat();
+        let requests = [
+            RequestWrapper::from(PutRequest {
+                key: "a".into(),
+                value: "1".into(),
+                ..Default::default()
+            }),
+            RequestWrapper::from(PutRequest {
+                key: savepoint_key.clone(),
+                value: "savepoint".into(),
+                ..Default::default()
+            }),
+            RequestWrapper::from(PutRequest {
+                key: "a".into(),
+                value: "2".into(),
+                ..Default::default()
+            }),
+        ];
+        for req in requests {
+            exe_as_and_flush(&store, &req, revision.next()).await?;
+        }
+        let compact = |revision| CompactionRequest {
+            revision,
+            physical: false,
+        };
+
+        let res = store.handle_compaction_request(&compact(4));
+        assert!(matches!(res, Err(ExecuteError::Rejected(_))));
+        // compacting up to the savepoint keeps its revision readable
+        let _res = store.handle_compaction_request(&compact(3))?;
+
+        let release = RequestWrapper::from(DeleteRangeRequest {
+            key: savepoint_key,
+            ..Default::default()
+        });
+        exe_as_and_flush(&store, &release, revision.next()).await?;
+        let _res = store.handle_compaction_request(&compact(4))?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_compaction() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let revision = RevisionNumberGenerator::default();
+        // sample requests: (a, 1) (b, 2) (a, 3) (del a)
+        // their revisions:     2      3      4       5
+        let requests = vec![
+            RequestWrapper::from(PutRequest {
+                key: "a".into(),
+                value: "1".into(),
+                ..Default::default()
+            }),
+            RequestWrapper::from(PutRequest {
+                key: "b".into(),
+                value: "2".into(),
+                ..Default::default()
+            }),
+            RequestWrapper::from(PutRequest {
+                key: "a".into(),
+                value: "3".into(),
+                ..Default::default()
+            }),
+            RequestWrapper::from(DeleteRangeRequest {
+                key: "a".into(),
+                ..Default::default()
+            }),
+        ];
+
+        for req in requests {
+            exe_as_and_flush(&store, &req, revision.next())
+                .await
+                .unwrap();
+        }
+
+        let target_revisions = index_compact(&store, 3);
+        store.compact(target_revisions.as_ref())?;
         assert_eq!(
             store.inner.get_range(b"a", b"", 2).unwrap().len(),
             1,
@@ -1378,6 +3075,411 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn prev_kv_should_be_omitted_once_compacted() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let revision = RevisionNumberGenerator::default();
+        for value in ["1", "2"] {
+            let put = RequestWrapper::from(PutRequest {
+                key: "a".into(),
+                value: value.into(),
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &put, revision.next()).await?;
+        }
+        let delete = RequestWrapper::from(DeleteRangeRequest {
+            key: "a".into(),
+            ..Default::default()
+        });
+        exe_as_and_flush(&store, &delete, revision.next()).await?;
+
+        let overwrite = store.inner.get_range(b"a", b"", 3)?.pop().unwrap();
+        let deletion = KeyValue {
+            key: "a".into(),
+            mod_revision: 4,
+            ..Default::default()
+        };
+        let prev_value = |kv: &KeyValue| store.inner.get_prev_kv(kv).map(|prev| prev.value);
+        assert_eq!(prev_value(&overwrite), Some(b"1".to_vec()));
+        assert_eq!(prev_value(&deletion), Some(b"2".to_vec()));
+
+        store.update_compacted_revision(3);
+        assert_eq!(prev_value(&overwrite), None);
+        assert_eq!(prev_value(&deletion), Some(b"2".to_vec()));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn archived_range_should_merge_the_archived_versions() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let revision = RevisionNumberGenerator::default();
+        for value in ["1", "2"] {
+            let put = RequestWrapper::from(PutRequest {
+                key: "a".into(),
+                value: value.into(),
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &put, revision.next()).await?;
+        }
+        // `z` is put at revision 2 and deleted at revision 3, all compacted
+        let archived = vec![
+            KeyValue {
+                key: "z".into(),
+                value: "1".into(),
+                create_revision: 2,
+                mod_revision: 2,
+                version: 1,
+                ..Default::default()
+            },
+            KeyValue {
+                key: "z".into(),
+                mod_revision: 3,
+                ..Default::default()
+            },
+        ];
+        let range = |revision: i64, limit: i64| RangeRequest {
+            key: vec![0],
+            range_end: vec![0],
+            revision,
+            limit,
+            ..Default::default()
+        };
+
+        let res = store.handle_archived_range_request(&range(2, 0), archived.clone())?;
+        let kvs: Vec<_> = res
+            .kvs
+            .iter()
+            .map(|kv| (&kv.key[..], &kv.value[..]))
+            .collect();
+        assert_eq!(kvs, [(&b"a"[..], &b"1"[..]), (b"z", b"1")]);
+        let res = store.handle_archived_range_request(&range(2, 1), archived.clone())?;
+        assert_eq!((res.kvs.len(), res.count, res.more), (1, 2, true));
+        let res = store.handle_archived_range_request(&range(3, 0), archived)?;
+        assert_eq!(res.kvs.len(), 1);
+        assert_eq!(res.kvs[0].value, b"2");
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn index_check_should_alarm_on_mismatched_keys() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let revision = RevisionNumberGenerator::default();
+        for key in ["a", "b", "c", "d"] {
+            let put = RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: "v".into(),
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &put, revision.next()).await?;
+        }
+        assert!(store.mismatched_index_sample(10, 0)?.is_empty());
+
+        // the backend loses the version of `c` the index points to
+        let mut stale = store.inner.get_range(b"c", b"", 0)?.pop().unwrap();
+        let rev = Revision::new(stale.mod_revision, 0);
+        stale.version = 0;
+        let _ignore = store
+            .inner
+            .db
+            .flush_ops(vec![WriteOp::PutKeyValue(rev, stale)])?;
+        assert_eq!(store.mismatched_index_sample(10, 0)?, vec![b"c".to_vec()]);
+
+        let alarms = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let alarms_c = Arc::clone(&alarms);
+        let task_manager = TaskManager::new();
+        let store_c = Arc::clone(&store);
+        task_manager.spawn(TaskName::IndexCheck, |n| {
+            run_index_check(
+                store_c,
+                IndexCheckConfig::new(Duration::from_millis(10), 10),
+                move |keys| {
+                    alarms_c.lock().push(keys);
+                    async {}
+                },
+                n,
+            )
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        task_manager.shutdown(true).await;
+        let alarms = alarms.lock();
+        assert!(!alarms.is_empty());
+        assert!(alarms.iter().all(|keys| keys == &[b"c".to_vec()]));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn compare_should_evaluate_against_current_state_after_compaction(
+    ) -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(db);
+        let revision = RevisionNumberGenerator::default();
+        // the revisions of "a": 2, 3, 4, and of "b": 5, deleted at 6
+        for key in ["a", "a", "a", "b"] {
+            let put = RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: b"v".to_vec(),
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &put, revision.next()).await?;
+        }
+        let delete = RequestWrapper::from(DeleteRangeRequest {
+            key: "b".into(),
+            ..Default::default()
+        });
+        exe_as_and_flush(&store, &delete, revision.next()).await?;
+        let target_revisions = index_compact(&store, 6);
+        store.compact(target_revisions.as_ref())?;
+        store.compact_finished(6)?;
+
+        let compare = |key: &str, result: CompareResult, target_union: TargetUnion| {
+            let target = if matches!(target_union, TargetUnion::CreateRevision(_)) {
+                CompareTarget::Create
+            } else {
+                CompareTarget::Mod
+            };
+            store.check_compare(&Compare {
+                result: result.into(),
+                target: target.into(),
+                key: key.into(),
+                range_end: vec![],
+                target_union: Some(target_union),
+            })
+        };
+        assert!(compare(
+            "a",
+            CompareResult::Equal,
+            TargetUnion::ModRevision(4)
+        ));
+        assert!(!compare(
+            "a",
+            CompareResult::Equal,
+            TargetUnion::ModRevision(3)
+        ));
+        assert!(compare(
+            "a",
+            CompareResult::Greater,
+            TargetUnion::ModRevision(2)
+        ));
+        assert!(!compare(
+            "a",
+            CompareResult::Less,
+            TargetUnion::ModRevision(4)
+        ));
+        // the create revision of "a" is compacted but still its current one
+        assert!(compare(
+            "a",
+            CompareResult::Equal,
+            TargetUnion::CreateRevision(2)
+        ));
+        assert!(compare(
+            "b",
+            CompareResult::Equal,
+            TargetUnion::ModRevision(0)
+        ));
+        assert!(compare(
+            "b",
+            CompareResult::Equal,
+            TargetUnion::CreateRevision(0)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn protected_keys_should_retain_history_after_compaction() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let protection = CompactProtection::new(&["legal/".to_owned()], 0);
+        let store = init_empty_store_with_opts(
+            db,
+            StoreOptions {
+                protection,
+                ..Default::default()
+            },
+        );
+        let revision = RevisionNumberGenerator::default();
+        // their revisions: 2, 3, 4, 5
+        for (key, value) in [("legal/a", "1"), ("a", "1"), ("legal/a", "2"), ("a", "2")] {
+            let put = RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: value.into(),
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &put, revision.next()).await?;
+        }
+        let target_revisions = index_compact(&store, 5);
+        store.compact(target_revisions.as_ref())?;
+        store.compact_finished(5)?;
+
+        let range_at = |key: &str, revision: i64| {
+            store.handle_range_request(&RangeRequest {
+                key: key.into(),
+                revision,
+                ..Default::default()
+            })
+        };
+        let res = range_at("legal/a", 2)?;
+        assert_eq!(res.kvs.len(), 1);
+        assert_eq!(res.kvs[0].value, b"1");
+        let prefix = store.handle_range_request(&RangeRequest {
+            key: "legal/".into(),
+            range_end: KeyRange::get_prefix(b"legal/"),
+            revision: 2,
+            ..Default::default()
+        })?;
+        assert_eq!(prefix.kvs.len(), 1);
+        assert!(matches!(
+            range_at("a", 3),
+            Err(ExecuteError::RevisionCompacted(3, 5))
+        ));
+        assert!(store.inner.get_range(b"a", b"", 3)?.is_empty());
+        assert_eq!(range_at("a", 5)?.kvs[0].value, b"2");
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn prefix_stats_should_track_keys_and_value_bytes() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let tracked = ["app/".to_owned(), "jobs/".to_owned()];
+        let store = init_empty_store_with_opts(
+            Arc::clone(&db),
+            StoreOptions {
+                prefix_stats: PrefixStats::new(&tracked),
+                ..Default::default()
+            },
+        );
+        let revision = RevisionNumberGenerator::default();
+        for (key, value) in [
+            ("app/a", "123"),
+            ("app/b", "12"),
+            ("jobs/x", "1234"),
+            ("other", "12345"),
+            ("app/a", "1"),
+        ] {
+            let put = RequestWrapper::from(PutRequest {
+                key: key.into(),
+                value: value.into(),
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &put, revision.next()).await?;
+        }
+        let total = |keys, value_bytes| PrefixTotal { keys, value_bytes };
+        assert_eq!(
+            store.prefix_stats().totals(),
+            vec![
+                ("app/".to_owned(), total(2, 3)),
+                ("jobs/".to_owned(), total(1, 4))
+            ]
+        );
+
+        let delete = RequestWrapper::from(DeleteRangeRequest {
+            key: "jobs/".into(),
+            range_end: KeyRange::get_prefix(b"jobs/"),
+            ..Default::default()
+        });
+        exe_as_and_flush(&store, &delete, revision.next()).await?;
+        let expected = vec![
+            ("app/".to_owned(), total(2, 3)),
+            ("jobs/".to_owned(), total(0, 0)),
+        ];
+        assert_eq!(store.prefix_stats().totals(), expected);
+
+        let new_store = init_empty_store_with_opts(
+            db,
+            StoreOptions {
+                prefix_stats: PrefixStats::new(&tracked),
+                ..Default::default()
+            },
+        );
+        new_store.recover().await?;
+        assert_eq!(new_store.prefix_stats().totals(), expected);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_auth_history_should_survive_kv_compaction() -> Result<(), ExecuteError> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let store = init_empty_store(Arc::clone(&db));
+        let auth_store = AuthStore::new(
+            Arc::new(LeaseCollection::new(0, Duration::ZERO)),
+            None,
+            Arc::new(HeaderGenerator::new(0, 0)),
+            Arc::clone(&db),
+            10,
+            0,
+            HashMap::new(),
+            0,
+            0,
+        );
+        let auth_requests = vec![
+            RequestWrapper::from(AuthRoleAddRequest {
+                name: "r".to_owned(),
+            }),
+            RequestWrapper::from(AuthUserAddRequest {
+                name: "u".to_owned(),
+                password: String::new(),
+                hashed_password: "123".to_owned(),
+                options: None,
+            }),
+        ];
+        for (rev, req) in (1..).zip(auth_requests.iter()) {
+            let (_sync_res, ops) = auth_store.after_sync(req, rev)?;
+            let _key_revs = db.flush_ops(ops)?;
+        }
+
+        let revision = RevisionNumberGenerator::default();
+        for val in ["1", "2", "3", "4"] {
+            let req = RequestWrapper::from(PutRequest {
+                key: "a".into(),
+                value: val.into(),
+                ..Default::default()
+            });
+            exe_as_and_flush(&store, &req, revision.next()).await?;
+        }
+        let target_revisions = index_compact(&store, revision.get());
+        store.compact(target_revisions.as_ref())?;
+
+        // the history is persisted, so it also survives a restart
+        let recovered = AuthStore::new(
+            Arc::new(LeaseCollection::new(0, Duration::ZERO)),
+            None,
+            Arc::new(HeaderGenerator::new(0, 0)),
+            Arc::clone(&db),
+            10,
+            0,
+            HashMap::new(),
+            0,
+            0,
+        );
+        recovered.recover()?;
+        for store in [&auth_store, &recovered] {
+            let history = store.history(usize::MAX);
+            assert_eq!(history.compacted_revision, 0);
+            assert_eq!(history.changes.len(), 2);
+            assert_eq!(history.changes.first().map(|entry| entry.0), Some(1));
+            #[allow(clippy::wildcard_enum_match_arm)]
+            match history.changes.get(1).map(|entry| &entry.1) {
+                Some(&RequestWrapper::AuthUserAddRequest(ref req)) => {
+                    assert_eq!(req.name, "u");
+                    assert!(
+                        req.hashed_password.is_empty(),
+                        "password should be redacted"
+                    );
+                }
+                _ => panic!("unexpected auth history"),
+            }
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn check_revision_will_return_correct_error_type() {
         let request = TxnRequest {
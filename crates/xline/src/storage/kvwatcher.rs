@@ -2,7 +2,7 @@ use std::{
     collections::{HashMap, HashSet},
     hash::Hash,
     sync::{
-        atomic::{AtomicI64, Ordering},
+        atomic::{AtomicI64, AtomicUsize, Ordering},
         Arc,
     },
     time::Duration,
@@ -22,7 +22,7 @@ use utils::{
 };
 use xlineapi::command::KeyRange;
 
-use super::{kv_store::KvStoreInner, storage_api::StorageApi};
+use super::{compact::ActiveWatches, kv_store::KvStoreInner, storage_api::StorageApi};
 use crate::rpc::{Event, KeyValue};
 
 /// Watch ID
@@ -55,12 +55,19 @@ struct Watcher {
     start_rev: i64,
     /// Event filters
     filters: Vec<i32>,
+    /// The connection owning the watch
+    owner: String,
+    /// The revision of the last events sent to the watch stream, 0 if nothing is sent
+    delivered_rev: i64,
     /// Stop notify
     stop_notify: Arc<event_listener::Event>,
     /// Sender of watch event
     event_tx: mpsc::Sender<WatchEvent>,
     /// Compacted flag
     compacted: bool,
+    /// Buffer of the undelivered events, `None` means the events are only bounded
+    /// by the event channel shared by the watchers of a stream
+    buffer: Option<WatchBuffer>,
     /// TODO: remove it when https://github.com/xline-kv/Xline/issues/491 has been closed
     /// Store the revision that has been notified
     notified_set: HashSet<i64>,
@@ -80,25 +87,67 @@ impl Hash for Watcher {
     }
 }
 
+/// The buffer of the undelivered events of a watcher
+#[derive(Debug)]
+struct WatchBuffer {
+    /// Max number of undelivered events
+    depth: usize,
+    /// Number of the events sent to the event channel but not yet delivered
+    pending: Arc<AtomicUsize>,
+}
+
+/// The events of a `WatchEvent` counted in the buffer of its watcher, they are
+/// released from the buffer when the `WatchEvent` is dropped after delivery
+#[derive(Debug)]
+struct BufferedEvents {
+    /// Number of undelivered events of the buffer
+    pending: Arc<AtomicUsize>,
+    /// Number of the events
+    count: usize,
+}
+
+impl BufferedEvents {
+    /// Count events in a buffer
+    fn new(pending: Arc<AtomicUsize>, count: usize) -> Self {
+        let _prev = pending.fetch_add(count, Ordering::AcqRel);
+        Self { pending, count }
+    }
+}
+
+impl Drop for BufferedEvents {
+    fn drop(&mut self) {
+        let _prev = self.pending.fetch_sub(self.count, Ordering::AcqRel);
+    }
+}
+
 impl Watcher {
     /// New `WatcherInner`
+    #[allow(clippy::too_many_arguments)]
     fn new(
         key_range: KeyRange,
         watch_id: WatchId,
         start_rev: i64,
         filters: Vec<i32>,
+        owner: String,
         stop_notify: Arc<event_listener::Event>,
         event_tx: mpsc::Sender<WatchEvent>,
         compacted: bool,
+        buffer_depth: Option<usize>,
     ) -> Self {
         Self {
             key_range,
             watch_id,
             start_rev,
             filters,
+            owner,
+            delivered_rev: 0,
             stop_notify,
             event_tx,
             compacted,
+            buffer: buffer_depth.map(|depth| WatchBuffer {
+                depth,
+                pending: Arc::new(AtomicUsize::new(0)),
+            }),
             notified_set: HashSet::new(),
         }
     }
@@ -131,13 +180,20 @@ impl Watcher {
         (revision, events): (i64, Vec<Event>),
     ) -> Result<(), TrySendError<WatchEvent>> {
         let watch_id = self.watch_id();
-        let events = self.filter_events(events);
+        let mut events = self.filter_events(events);
+        sort_events(&mut events);
         let events_len = events.len();
-        let watch_event = WatchEvent {
+        let event_revisions = events
+            .iter()
+            .filter_map(|event| event.kv.as_ref().map(|kv| kv.mod_revision))
+            .dedup()
+            .collect_vec();
+        let mut watch_event = WatchEvent {
             id: watch_id,
             events,
             revision,
             compacted: self.compacted,
+            buffered: None,
         };
         if !self.compacted
             && (revision < self.start_rev
@@ -146,10 +202,31 @@ impl Watcher {
         {
             return Ok(());
         };
+        if let Some(ref buffer) = self.buffer {
+            let pending = buffer.pending.load(Ordering::Acquire);
+            // A batch deeper than the buffer is still sent to an empty buffer, or it
+            // could never be delivered
+            if pending > 0 && pending.saturating_add(events_len) > buffer.depth {
+                warn!(
+                    watch_id,
+                    revision,
+                    pending,
+                    depth = buffer.depth,
+                    "watch buffer is full, will try to send later"
+                );
+                return Err(TrySendError::Full(watch_event));
+            }
+            watch_event.buffered =
+                Some(BufferedEvents::new(Arc::clone(&buffer.pending), events_len));
+        }
 
         match self.event_tx.try_send(watch_event) {
             Ok(()) => {
+                // A batch of initial events may contain several revisions, all of them
+                // are marked so that they will never be delivered again
                 let _ignore = self.notified_set.insert(revision);
+                self.notified_set.extend(event_revisions);
+                self.delivered_rev = self.delivered_rev.max(revision);
                 Ok(())
             }
             Err(TrySendError::Closed(_)) => {
@@ -168,6 +245,56 @@ impl Watcher {
     }
 }
 
+/// A watch in the registry of the active watches of a node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct WatchInfo {
+    /// The watch id
+    pub(crate) watch_id: WatchId,
+    /// The start key of the watched range
+    pub(crate) key: Vec<u8>,
+    /// The end key of the watched range
+    pub(crate) range_end: Vec<u8>,
+    /// The start revision, 0 means the watch starts from the revision it is created at
+    pub(crate) start_revision: i64,
+    /// The revision of the last events sent to the watch stream, 0 if nothing is sent
+    pub(crate) delivered_revision: i64,
+    /// The filter types of the events filtered out
+    pub(crate) filters: Vec<i32>,
+    /// The remote address of the connection owning the watch
+    pub(crate) owner: String,
+    /// Whether the watch is a victim, that is the watch stream doesn't keep up with the
+    /// events and they are resent later
+    pub(crate) victim: bool,
+    /// Whether the start revision is compacted
+    pub(crate) compacted: bool,
+}
+
+impl WatchInfo {
+    /// Take the info of a watcher
+    fn new(watcher: &Watcher, victim: bool) -> Self {
+        Self {
+            watch_id: watcher.watch_id,
+            key: watcher.key_range.range_start().to_vec(),
+            range_end: watcher.key_range.range_end().to_vec(),
+            start_revision: watcher.start_rev,
+            delivered_revision: watcher.delivered_rev,
+            filters: watcher.filters.clone(),
+            owner: watcher.owner.clone(),
+            victim,
+            compacted: watcher.compacted,
+        }
+    }
+}
+
+/// A page of the registry of the active watches of a node
+#[derive(Debug)]
+pub(crate) struct WatchRegistryPage {
+    /// The watches in ascending order of watch id
+    pub(crate) watches: Vec<WatchInfo>,
+    /// The watch id to start the next page from, `None` if this is the last page
+    pub(crate) next_watch_id: Option<WatchId>,
+}
+
 /// KV watcher
 #[derive(Debug)]
 pub(crate) struct KvWatcher<S>
@@ -239,6 +366,7 @@ impl WatcherMap {
             revision: updates.0,
             events: updates.1,
             compacted: false,
+            buffered: None,
         };
         assert!(
             self.victims
@@ -278,20 +406,41 @@ impl WatcherMap {
     }
 }
 
+impl<S> ActiveWatches for KvWatcher<S>
+where
+    S: StorageApi,
+{
+    fn min_start_revision(&self) -> Option<i64> {
+        let watcher_map = self.watcher_map.read();
+        // a victim reads the history again from its start revision once it's synced
+        watcher_map
+            .watchers
+            .values()
+            .chain(watcher_map.victims.keys())
+            .filter(|watcher| watcher.start_rev != 0 && !watcher.compacted)
+            .map(|watcher| watcher.start_rev)
+            .min()
+    }
+}
+
 /// Operations of KV watcher
 #[allow(clippy::arithmetic_side_effects, clippy::indexing_slicing)] // Introduced by mockall::automock
 #[cfg_attr(test, mockall::automock)]
 #[async_trait::async_trait]
 pub(crate) trait KvWatcherOps {
-    /// Create a watch to KV store
+    /// Create a watch to KV store, a watch with a `buffer_depth` holds at most
+    /// that many undelivered events before it is moved to the victims
+    #[allow(clippy::too_many_arguments)]
     fn watch(
         &self,
         id: WatchId,
         key_range: KeyRange,
         start_rev: i64,
         filters: Vec<i32>,
+        owner: String,
         stop_notify: Arc<event_listener::Event>,
         event_tx: mpsc::Sender<WatchEvent>,
+        buffer_depth: Option<usize>,
     );
 
     /// Cancel a watch from KV store
@@ -300,6 +449,9 @@ pub(crate) trait KvWatcherOps {
     /// Get Prev `KeyValue` of a `KeyValue`
     fn get_prev_kv(&self, kv: &KeyValue) -> Option<KeyValue>;
 
+    /// Get the tombstone of the deletion right before a key is created by a `KeyValue`
+    fn get_prev_deletion(&self, kv: &KeyValue) -> Option<KeyValue>;
+
     /// Get compacted revision from backend store
     fn compacted_revision(&self) -> i64;
 }
@@ -315,8 +467,10 @@ where
         key_range: KeyRange,
         start_rev: i64,
         filters: Vec<i32>,
+        owner: String,
         stop_notify: Arc<event_listener::Event>,
         event_tx: mpsc::Sender<WatchEvent>,
+        buffer_depth: Option<usize>,
     ) {
         let compacted = start_rev != 0 && start_rev < self.compacted_revision();
         let mut watcher = Watcher::new(
@@ -324,9 +478,11 @@ where
             id,
             start_rev,
             filters,
+            owner,
             stop_notify,
             event_tx,
             compacted,
+            buffer_depth,
         );
         let mut watcher_map_w = self.watcher_map.write();
         if compacted {
@@ -380,6 +536,10 @@ where
         self.kv_store_inner.get_prev_kv(kv)
     }
 
+    fn get_prev_deletion(&self, kv: &KeyValue) -> Option<KeyValue> {
+        self.kv_store_inner.get_prev_deletion(kv)
+    }
+
     fn compacted_revision(&self) -> i64 {
         self.kv_store_inner.compacted_revision()
     }
@@ -410,6 +570,27 @@ where
         kv_watcher
     }
 
+    /// Get a page of at most `limit` active watches from `start_watch_id`, the victims
+    /// included, in ascending order of watch id
+    pub(crate) fn registry(&self, start_watch_id: WatchId, limit: usize) -> WatchRegistryPage {
+        let watcher_map = self.watcher_map.read();
+        let mut watches: Vec<_> = watcher_map
+            .watchers
+            .values()
+            .map(|watcher| (watcher, false))
+            .chain(watcher_map.victims.keys().map(|watcher| (watcher, true)))
+            .filter(|&(watcher, _)| watcher.watch_id >= start_watch_id)
+            .map(|(watcher, victim)| WatchInfo::new(watcher, victim))
+            .collect();
+        watches.sort_unstable_by_key(|info| info.watch_id);
+        let next_watch_id = watches.get(limit).map(|info| info.watch_id);
+        watches.truncate(limit);
+        WatchRegistryPage {
+            watches,
+            next_watch_id,
+        }
+    }
+
     /// Background task to handle KV updates
     #[allow(clippy::arithmetic_side_effects, clippy::ignored_unit_patterns)] // Introduced by tokio::select!
     async fn kv_updates_task(
@@ -479,7 +660,7 @@ where
                                     .is_none(),
                                 "can't insert a watcher to new_victims twice"
                             );
-                            break;
+                            continue;
                         };
                     }
                     debug!(
@@ -549,6 +730,8 @@ pub(crate) struct WatchEvent {
     revision: i64,
     /// Compacted WatchEvent
     compacted: bool,
+    /// The events counted in the buffer of the watcher
+    buffered: Option<BufferedEvents>,
 }
 
 impl std::fmt::Debug for WatchEvent {
@@ -585,6 +768,20 @@ impl WatchEvent {
     }
 }
 
+/// Sort events in ascending order of revision, events of the same revision, i.e.
+/// those generated by a single txn, are ordered by key. The sort is stable so that
+/// events of the same key keep their sub revision order.
+fn sort_events(events: &mut [Event]) {
+    events.sort_by(|a, b| {
+        let (Some(a), Some(b)) = (a.kv.as_ref(), b.kv.as_ref()) else {
+            unreachable!("event.kv can't be None")
+        };
+        a.mod_revision
+            .cmp(&b.mod_revision)
+            .then_with(|| a.key.cmp(&b.key))
+    });
+}
+
 /// Get the last revision of a event slice
 fn get_last_revision(events: &[Event]) -> i64 {
     events
@@ -599,7 +796,10 @@ fn get_last_revision(events: &[Event]) -> i64 {
 #[cfg(test)]
 mod test {
 
-    use std::{collections::BTreeMap, time::Duration};
+    use std::{
+        collections::{BTreeMap, BTreeSet},
+        time::Duration,
+    };
 
     use clippy_utilities::{NumericCast, OverflowArithmetic};
     use test_macros::abort_on_panic;
@@ -610,10 +810,10 @@ mod test {
     use super::*;
     use crate::{
         header_gen::HeaderGenerator,
-        rpc::PutRequest,
+        rpc::{PutRequest, Request, RequestOp, TxnRequest},
         storage::{
-            compact::COMPACT_CHANNEL_SIZE, db::DB, index::Index, lease_store::LeaseCollection,
-            KvStore,
+            compact::COMPACT_CHANNEL_SIZE, db::DB, index::Index, kv_store::KvStoreOptions,
+            lease_store::LeaseCollection, KvStore,
         },
     };
 
@@ -624,7 +824,7 @@ mod test {
         let db = DB::open(&EngineConfig::Memory).unwrap();
         let header_gen = Arc::new(HeaderGenerator::new(0, 0));
         let index = Arc::new(Index::new());
-        let lease_collection = Arc::new(LeaseCollection::new(0));
+        let lease_collection = Arc::new(LeaseCollection::new(0, Duration::ZERO));
         let (kv_update_tx, kv_update_rx) = mpsc::channel(128);
         let kv_store_inner = Arc::new(KvStoreInner::new(index, Arc::clone(&db)));
         let store = Arc::new(KvStore::new(
@@ -633,6 +833,7 @@ mod test {
             kv_update_tx,
             compact_tx,
             lease_collection,
+            KvStoreOptions::default(),
         ));
         let sync_victims_interval = Duration::from_millis(10);
         let kv_watcher = KvWatcher::new_arc(
@@ -657,8 +858,10 @@ mod test {
             KeyRange::new_one_key("foo"),
             10,
             vec![],
+            "test".to_owned(),
             stop_notify,
             event_tx,
+            None,
         );
         sleep(Duration::from_micros(50)).await;
         let handle = tokio::spawn({
@@ -715,8 +918,10 @@ mod test {
             KeyRange::new_one_key("foo"),
             0,
             vec![],
+            "test".to_owned(),
             stop_notify,
             event_tx,
+            None,
         );
 
         let mut expect = 0;
@@ -760,8 +965,10 @@ mod test {
             KeyRange::new_one_key("foo"),
             0,
             vec![],
+            "test".to_owned(),
             stop_notify,
             event_tx,
+            None,
         );
         assert!(!kv_watcher.watcher_map.read().index.is_empty());
         assert!(!kv_watcher.watcher_map.read().watchers.is_empty());
@@ -772,6 +979,249 @@ mod test {
         task_manager.shutdown(true).await;
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn txn_events_should_be_grouped_in_key_order() {
+        let task_manager = Arc::new(TaskManager::new());
+        let (store, db, kv_watcher) = init_empty_store(&task_manager);
+        let (live_tx, mut live_rx) = mpsc::channel(128);
+        kv_watcher.watch(
+            1,
+            KeyRange::new("a", "z"),
+            0,
+            vec![],
+            "test".to_owned(),
+            Arc::new(event_listener::Event::new()),
+            live_tx,
+            None,
+        );
+
+        put(store.as_ref(), db.as_ref(), "x", vec![0], 2).await;
+        let req = RequestWrapper::from(TxnRequest {
+            compare: vec![],
+            success: ["d", "b", "c"]
+                .into_iter()
+                .map(|key| RequestOp {
+                    request: Some(Request::RequestPut(PutRequest {
+                        key: key.into(),
+                        value: vec![1],
+                        ..Default::default()
+                    })),
+                })
+                .collect(),
+            failure: vec![],
+        });
+        let (_sync_res, ops) = store.after_sync(&req, 3).await.unwrap();
+        let key_revisions = db.flush_ops(ops).unwrap();
+        store.insert_index(key_revisions);
+        put(store.as_ref(), db.as_ref(), "a", vec![2], 4).await;
+
+        let (history_tx, mut history_rx) = mpsc::channel(128);
+        kv_watcher.watch(
+            2,
+            KeyRange::new("a", "z"),
+            2,
+            vec![],
+            "test".to_owned(),
+            Arc::new(event_listener::Event::new()),
+            history_tx,
+            None,
+        );
+
+        let expected = vec![
+            (2, b"x".to_vec()),
+            (3, b"b".to_vec()),
+            (3, b"c".to_vec()),
+            (3, b"d".to_vec()),
+            (4, b"a".to_vec()),
+        ];
+        let mut live_events = vec![];
+        while live_events.len() < expected.len() {
+            let watch_event = timeout(Duration::from_secs(3), live_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            let revisions = watch_event
+                .events
+                .iter()
+                .map(|event| event.kv.as_ref().unwrap().mod_revision)
+                .collect::<HashSet<_>>();
+            assert_eq!(
+                revisions,
+                HashSet::from([watch_event.revision]),
+                "events of a live update should share the revision of the update"
+            );
+            live_events.extend(watch_event.events);
+        }
+        let history_event = timeout(Duration::from_secs(3), history_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        for events in [live_events, history_event.events] {
+            let got = events
+                .into_iter()
+                .map(|event| {
+                    let kv = event.kv.unwrap();
+                    (kv.mod_revision, kv.key)
+                })
+                .collect_vec();
+            assert_eq!(got, expected);
+        }
+        drop(store);
+        task_manager.shutdown(true).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn watchers_should_overflow_at_their_buffer_depths() {
+        let task_manager = Arc::new(TaskManager::new());
+        let (store, db, kv_watcher) = init_empty_store(&task_manager);
+        // the channel is large enough, the events are undelivered until received
+        let (event_tx, mut event_rx) = mpsc::channel(128);
+        for (id, depth) in [(1, 2), (2, 5)] {
+            kv_watcher.watch(
+                id,
+                KeyRange::new_one_key("foo"),
+                0,
+                vec![],
+                "test".to_owned(),
+                Arc::new(event_listener::Event::new()),
+                event_tx.clone(),
+                Some(depth),
+            );
+        }
+        // (whether the watcher is a victim, its undelivered events)
+        let buffer_state = |id: WatchId| {
+            let watcher_map = kv_watcher.watcher_map.read();
+            let (victim, watcher) = match watcher_map.watchers.get(&id) {
+                Some(watcher) => (false, watcher),
+                None => (
+                    true,
+                    watcher_map.victims.keys().find(|w| w.watch_id() == id)?,
+                ),
+            };
+            let pending = watcher.buffer.as_ref()?.pending.load(Ordering::Acquire);
+            Some((victim, pending))
+        };
+
+        for puts in 1..7_u8 {
+            let revision = puts.overflow_add(1).numeric_cast();
+            put(store.as_ref(), db.as_ref(), "foo", vec![puts], revision).await;
+            let puts = usize::from(puts);
+            let expected =
+                [(1, 2), (2, 5)].map(|(id, depth)| (id, Some((puts > depth, puts.min(depth)))));
+            timeout(Duration::from_secs(3), async {
+                while expected
+                    .iter()
+                    .any(|&(id, state)| buffer_state(id) != state)
+                {
+                    sleep(Duration::from_millis(1)).await;
+                }
+            })
+            .await
+            .unwrap_or_else(|_| panic!("unexpected buffer states after {puts} puts"));
+        }
+
+        // the victims are synced once their events are delivered
+        let mut delivered: HashMap<WatchId, BTreeSet<i64>> = HashMap::new();
+        while delivered.values().map(BTreeSet::len).sum::<usize>() < 12 {
+            let watch_event = timeout(Duration::from_secs(3), event_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            delivered.entry(watch_event.watch_id()).or_default().extend(
+                watch_event
+                    .events
+                    .iter()
+                    .map(|event| event.kv.as_ref().unwrap().mod_revision),
+            );
+        }
+        for id in [1, 2] {
+            assert_eq!(delivered[&id], (2..8).collect());
+        }
+        drop(store);
+        task_manager.shutdown(true).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn min_start_revision_should_track_the_historical_watches() {
+        let task_manager = Arc::new(TaskManager::new());
+        let (store, db, kv_watcher) = init_empty_store(&task_manager);
+        for i in 0..10_u8 {
+            put(
+                store.as_ref(),
+                db.as_ref(),
+                "foo",
+                vec![i],
+                i.overflow_add(2).numeric_cast(),
+            )
+            .await;
+        }
+        let (event_tx, _event_rx) = mpsc::channel(128);
+        let watch = |id, start_rev| {
+            kv_watcher.watch(
+                id,
+                KeyRange::new_one_key("foo"),
+                start_rev,
+                vec![],
+                "test".to_owned(),
+                Arc::new(event_listener::Event::new()),
+                event_tx.clone(),
+                None,
+            );
+        };
+        watch(1, 0);
+        assert_eq!(kv_watcher.min_start_revision(), None);
+        watch(2, 6);
+        watch(3, 4);
+        assert_eq!(kv_watcher.min_start_revision(), Some(4));
+
+        kv_watcher.cancel(3);
+        assert_eq!(kv_watcher.min_start_revision(), Some(6));
+        kv_watcher.cancel(2);
+        assert_eq!(kv_watcher.min_start_revision(), None);
+        drop(store);
+        task_manager.shutdown(true).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[abort_on_panic]
+    async fn registry_should_be_paged_by_watch_id() {
+        let task_manager = Arc::new(TaskManager::new());
+        let (store, _db, kv_watcher) = init_empty_store(&task_manager);
+        let (event_tx, _event_rx) = mpsc::channel(128);
+        for id in [3, 1, 2] {
+            kv_watcher.watch(
+                id,
+                KeyRange::new("a", "b"),
+                0,
+                vec![1],
+                "test".to_owned(),
+                Arc::new(event_listener::Event::new()),
+                event_tx.clone(),
+                None,
+            );
+        }
+        let ids = |page: &WatchRegistryPage| -> Vec<_> {
+            page.watches.iter().map(|info| info.watch_id).collect()
+        };
+        let first = kv_watcher.registry(0, 2);
+        assert_eq!(ids(&first), [1, 2]);
+        assert_eq!(first.next_watch_id, Some(3));
+        let second = kv_watcher.registry(3, 2);
+        assert_eq!(ids(&second), [3]);
+        assert_eq!(second.next_watch_id, None);
+        let info = &second.watches[0];
+        assert_eq!(
+            (info.key.as_slice(), info.range_end.as_slice()),
+            (&b"a"[..], &b"b"[..])
+        );
+        assert_eq!(info.filters, [1]);
+        drop(store);
+        task_manager.shutdown(true).await;
+    }
+
     async fn put(
         store: &KvStore<DB>,
         db: &DB,
@@ -31,6 +31,15 @@ impl Lease {
         }
     }
 
+    /// New `Lease` which expires in `remaining_ttl` seconds at its first refresh
+    /// instead of its ttl, 0 means its ttl
+    pub(crate) fn with_remaining_ttl(id: i64, ttl: u64, remaining_ttl: u64) -> Self {
+        Self {
+            remaining_ttl: Duration::from_secs(remaining_ttl),
+            ..Self::new(id, ttl)
+        }
+    }
+
     /// Return keys of lease
     pub(crate) fn keys(&self) -> Vec<Vec<u8>> {
         self.keys_set.iter().cloned().collect()
@@ -55,9 +64,10 @@ impl Lease {
         }
     }
 
-    /// Check if the lease is expired
-    pub(crate) fn expired(&self) -> bool {
-        self.remaining() <= Duration::from_secs(0)
+    /// Check if the lease has been expired for longer than the grace period
+    pub(crate) fn expired(&self, grace_period: Duration) -> bool {
+        self.expiry
+            .map_or(false, |exp| exp.add(grace_period) <= Instant::now())
     }
 
     /// Lease remaining ttl
@@ -69,9 +79,11 @@ impl Lease {
         }
     }
 
-    /// Refresh expiry and return new expiry
+    /// Refresh expiry and return new expiry, the remaining ttl only takes effect on
+    /// the first refresh and the later ones extend the lease by its ttl
     pub(crate) fn refresh(&mut self, extend: Duration) -> Instant {
         let new_expiry = Instant::now().add(extend).add(self.remaining_ttl());
+        self.remaining_ttl = Duration::ZERO;
         self.expiry = Some(new_expiry);
         new_expiry
     }
@@ -1,5 +1,6 @@
 use std::{
     collections::HashMap,
+    ops::Add,
     time::{Duration, Instant},
 };
 
@@ -19,6 +20,8 @@ pub(crate) struct LeaseCollection {
     inner: RwLock<LeaseCollectionInner>,
     /// Min lease ttl
     min_ttl: i64,
+    /// Grace period added to the expiry of a lease before it is revoked
+    grace_period: Duration,
 }
 
 #[derive(Debug)]
@@ -34,7 +37,7 @@ struct LeaseCollectionInner {
 
 impl LeaseCollection {
     /// New `LeaseCollection`
-    pub(crate) fn new(min_ttl: i64) -> Self {
+    pub(crate) fn new(min_ttl: i64, grace_period: Duration) -> Self {
         Self {
             inner: RwLock::new(LeaseCollectionInner {
                 lease_map: HashMap::new(),
@@ -42,15 +45,21 @@ impl LeaseCollection {
                 expired_queue: LeaseQueue::new(),
             }),
             min_ttl,
+            grace_period,
         }
     }
 
-    /// Find expired leases
+    /// Find leases which have been expired for longer than the grace period
     pub(crate) fn find_expired_leases(&self) -> Vec<i64> {
+        self.find_expired_leases_at(Instant::now())
+    }
+
+    /// Find leases which have been expired for longer than the grace period at `now`
+    pub(crate) fn find_expired_leases_at(&self, now: Instant) -> Vec<i64> {
         let mut expired_leases = vec![];
         let mut inner = self.inner.write();
         while let Some(expiry) = inner.expired_queue.peek() {
-            if *expiry <= Instant::now() {
+            if expiry.add(self.grace_period) <= now {
                 #[allow(clippy::unwrap_used)] // queue.peek() returns Some
                 let id = inner.expired_queue.pop().unwrap();
                 if inner.lease_map.contains_key(&id) {
@@ -70,7 +79,7 @@ impl LeaseCollection {
             let Some(lease) = inner.lease_map.get_mut(&lease_id) else {
                 return Err(ExecuteError::LeaseNotFound(lease_id));
             };
-            if lease.expired() {
+            if lease.expired(self.grace_period) {
                 return Err(ExecuteError::LeaseExpired(lease_id));
             }
             let expiry = lease.refresh(Duration::default());
@@ -126,6 +135,16 @@ impl LeaseCollection {
         leases
     }
 
+    /// Get all the leases in ascending order of lease id, `revision` is evaluated
+    /// inside the same critical section so that the attached keys of the leases
+    /// match the keyspace at the revision
+    pub(crate) fn export(&self, revision: impl FnOnce() -> i64) -> (i64, Vec<Lease>) {
+        let inner = self.inner.read();
+        let mut leases = inner.lease_map.values().cloned().collect_vec();
+        leases.sort_unstable_by_key(Lease::id);
+        (revision(), leases)
+    }
+
     /// Check if a lease exists
     pub(crate) fn contains_lease(&self, lease_id: i64) -> bool {
         self.inner.read().lease_map.contains_key(&lease_id)
@@ -133,7 +152,29 @@ impl LeaseCollection {
 
     /// Grant a lease
     pub(crate) fn grant(&self, lease_id: i64, ttl: i64, is_leader: bool) -> PbLease {
-        let mut lease = Lease::new(lease_id, ttl.max(self.min_ttl).numeric_cast());
+        self.grant_with_remaining_ttl(lease_id, ttl, 0, is_leader)
+    }
+
+    /// Grant a lease which expires in `remaining_ttl` seconds instead of its ttl, 0
+    /// means its ttl. A follower keeps the remaining ttl until it is promoted, so the
+    /// lease expires in it on a later leader too.
+    pub(crate) fn grant_with_remaining_ttl(
+        &self,
+        lease_id: i64,
+        ttl: i64,
+        remaining_ttl: i64,
+        is_leader: bool,
+    ) -> PbLease {
+        let mut lease = Lease::with_remaining_ttl(
+            lease_id,
+            ttl.max(self.min_ttl).numeric_cast(),
+            remaining_ttl.max(0).numeric_cast(),
+        );
+        let pb_lease = PbLease {
+            id: lease.id(),
+            ttl: lease.ttl().as_secs().numeric_cast(),
+            remaining_ttl: lease.remaining_ttl().as_secs().numeric_cast(),
+        };
         self.inner.map_write(|mut inner| {
             if is_leader {
                 let expiry = lease.refresh(Duration::ZERO);
@@ -141,13 +182,9 @@ impl LeaseCollection {
             } else {
                 lease.forever();
             }
-            let _ignore = inner.lease_map.insert(lease_id, lease.clone());
+            let _ignore = inner.lease_map.insert(lease_id, lease);
         });
-        PbLease {
-            id: lease.id(),
-            ttl: lease.ttl().as_secs().numeric_cast(),
-            remaining_ttl: lease.remaining_ttl().as_secs().numeric_cast(),
-        }
+        pb_lease
     }
 
     /// Revokes a lease
@@ -181,10 +218,55 @@ mod test {
     use super::*;
     #[test]
     fn test_grant_less_than_min_ttl() {
-        let c = LeaseCollection::new(3);
+        let c = LeaseCollection::new(3, Duration::ZERO);
         c.grant(1, 2, false);
         let l = c.look_up(1);
         assert!(l.is_some());
         assert_eq!(l.unwrap().ttl(), Duration::from_secs(3));
     }
+
+    #[test]
+    fn keepalive_within_grace_period_should_save_lease() {
+        let c = LeaseCollection::new(0, Duration::from_secs(1));
+        let _lease = c.grant(1, 1, true);
+        std::thread::sleep(Duration::from_millis(1500));
+        assert!(c.find_expired_leases().is_empty());
+        assert!(c.renew(1).is_ok());
+        assert!(c.find_expired_leases().is_empty());
+    }
+
+    #[test]
+    fn keepalive_after_grace_period_should_not_save_lease() {
+        let c = LeaseCollection::new(0, Duration::from_secs(1));
+        let _lease = c.grant(1, 1, true);
+        std::thread::sleep(Duration::from_millis(2200));
+        assert!(matches!(c.renew(1), Err(ExecuteError::LeaseExpired(1))));
+        assert_eq!(c.find_expired_leases(), vec![1]);
+    }
+
+    #[test]
+    fn lease_should_expire_in_the_imported_remaining_ttl() {
+        let c = LeaseCollection::new(0, Duration::ZERO);
+        let leader = c.grant_with_remaining_ttl(1, 60, 30, true);
+        let follower = c.grant_with_remaining_ttl(2, 60, 30, false);
+        assert_eq!(leader, follower);
+        assert_eq!((leader.ttl, leader.remaining_ttl), (60, 30));
+        let remaining = c.look_up(1).unwrap().remaining();
+        assert!(remaining <= Duration::from_secs(30) && remaining > Duration::from_secs(20));
+        assert_eq!(
+            c.look_up(2).unwrap().remaining(),
+            Duration::from_secs(u64::MAX)
+        );
+        // a keepalive extends the lease by its ttl
+        assert_eq!(c.renew(1).unwrap(), 60);
+        assert!(c.look_up(1).unwrap().remaining() > Duration::from_secs(50));
+        // the promoted follower expires the lease in the remaining ttl
+        c.promote(Duration::ZERO);
+        let remaining = c.look_up(2).unwrap().remaining();
+        assert!(remaining <= Duration::from_secs(30) && remaining > Duration::from_secs(20));
+
+        let (revision, leases) = c.export(|| 5);
+        assert_eq!(revision, 5);
+        assert_eq!(leases.iter().map(Lease::id).collect::<Vec<_>>(), [1, 2]);
+    }
 }
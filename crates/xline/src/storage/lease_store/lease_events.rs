@@ -0,0 +1,186 @@
+use std::collections::{HashMap, VecDeque};
+
+use clippy_utilities::OverflowArithmetic;
+use event_listener::{Event, EventListener};
+use parking_lot::Mutex;
+
+/// The number of lease events retained for the resumption of the subscribers
+const LEASE_EVENT_RETENTION: usize = 4096;
+
+/// The kind of a lease event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LeaseEventKind {
+    /// The lease is granted
+    Grant,
+    /// The lease is kept alive
+    KeepAlive,
+    /// The lease is revoked or has expired
+    Revoke,
+}
+
+/// A change of the liveness of a lease
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LeaseEvent {
+    /// The sequence number of the event, starting from 1
+    pub(crate) seq: i64,
+    /// The sequence number of the grant of the lease, 0 if the grant is not seen by
+    /// the current node, e.g. it is recovered from the storage
+    pub(crate) grant_seq: i64,
+    /// The number of the grant and keep alive events of the lease, 0 for a revoke
+    pub(crate) version: i64,
+    /// The lease id
+    pub(crate) id: i64,
+    /// The kind of the event
+    pub(crate) kind: LeaseEventKind,
+    /// The remaining ttl of the lease in seconds after the event, 0 for a revoke
+    pub(crate) remaining_ttl: i64,
+}
+
+/// The events retained by the `LeaseEventHub`
+#[derive(Debug)]
+struct LeaseEventHistory {
+    /// The sequence number of the next event
+    next_seq: i64,
+    /// The retained events in the order of their sequence numbers
+    events: VecDeque<LeaseEvent>,
+    /// The grant sequence numbers and versions of the live leases
+    live: HashMap<i64, (i64, i64)>,
+}
+
+/// The hub of the lease events of the current node.
+///
+/// The grants and revokes are recorded by every node when they are applied, while
+/// the keep alives are only seen by the leader, so a subscriber tracking keep alives
+/// subscribes on the leader. The sequence numbers are local to the node and restart
+/// with it, a subscriber may resume from any retained sequence number.
+#[derive(Debug)]
+pub(crate) struct LeaseEventHub {
+    /// The retained events
+    history: Mutex<LeaseEventHistory>,
+    /// Notified whenever an event is recorded
+    recorded: Event,
+}
+
+impl LeaseEventHub {
+    /// New `LeaseEventHub`
+    pub(crate) fn new() -> Self {
+        Self {
+            history: Mutex::new(LeaseEventHistory {
+                next_seq: 1,
+                events: VecDeque::new(),
+                live: HashMap::new(),
+            }),
+            recorded: Event::new(),
+        }
+    }
+
+    /// Record an event of a lease
+    pub(crate) fn record(&self, id: i64, kind: LeaseEventKind, remaining_ttl: i64) {
+        {
+            let mut history = self.history.lock();
+            let seq = history.next_seq;
+            history.next_seq = seq.overflow_add(1);
+            let (grant_seq, version) = match kind {
+                LeaseEventKind::Grant => {
+                    let _prev = history.live.insert(id, (seq, 1));
+                    (seq, 1)
+                }
+                LeaseEventKind::KeepAlive => {
+                    let entry = history.live.entry(id).or_insert((0, 0));
+                    entry.1 = entry.1.overflow_add(1);
+                    *entry
+                }
+                LeaseEventKind::Revoke => {
+                    let (grant_seq, _) = history.live.remove(&id).unwrap_or_default();
+                    (grant_seq, 0)
+                }
+            };
+            if history.events.len() >= LEASE_EVENT_RETENTION {
+                let _oldest = history.events.pop_front();
+            }
+            history.events.push_back(LeaseEvent {
+                seq,
+                grant_seq,
+                version,
+                id,
+                kind,
+                remaining_ttl,
+            });
+        }
+        let _ignore = self.recorded.notify(usize::MAX);
+    }
+
+    /// The sequence number of the next event
+    pub(crate) fn next_seq(&self) -> i64 {
+        self.history.lock().next_seq
+    }
+
+    /// Get the events from `start_seq` whose lease matches the filter with the
+    /// sequence number to continue from, returns the smallest retained sequence
+    /// number as the error if `start_seq` is no longer retained
+    pub(crate) fn events_from(
+        &self,
+        start_seq: i64,
+        lease_id: Option<i64>,
+    ) -> Result<(Vec<LeaseEvent>, i64), i64> {
+        let history = self.history.lock();
+        let oldest = history
+            .events
+            .front()
+            .map_or(history.next_seq, |event| event.seq);
+        if start_seq < oldest {
+            return Err(oldest);
+        }
+        let events = history
+            .events
+            .iter()
+            .filter(|event| event.seq >= start_seq && lease_id.map_or(true, |id| id == event.id))
+            .copied()
+            .collect();
+        Ok((events, history.next_seq))
+    }
+
+    /// Listen for the next recorded event
+    pub(crate) fn listen(&self) -> EventListener {
+        self.recorded.listen()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lease_events_should_be_sequenced_and_retained() {
+        let hub = LeaseEventHub::new();
+        hub.record(1, LeaseEventKind::Grant, 10);
+        hub.record(2, LeaseEventKind::Grant, 20);
+        hub.record(1, LeaseEventKind::KeepAlive, 10);
+        hub.record(1, LeaseEventKind::Revoke, 0);
+        assert_eq!(hub.next_seq(), 5);
+
+        let (events, next_seq) = hub.events_from(1, Some(1)).unwrap();
+        assert_eq!(next_seq, 5);
+        let summary: Vec<_> = events
+            .iter()
+            .map(|e| (e.seq, e.grant_seq, e.version, e.kind))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, 1, 1, LeaseEventKind::Grant),
+                (3, 1, 2, LeaseEventKind::KeepAlive),
+                (4, 1, 0, LeaseEventKind::Revoke),
+            ]
+        );
+        assert_eq!(hub.events_from(3, None).unwrap().0.len(), 2);
+        assert!(hub.events_from(5, None).unwrap().0.is_empty());
+
+        for _ in 0..LEASE_EVENT_RETENTION {
+            hub.record(2, LeaseEventKind::KeepAlive, 20);
+        }
+        assert_eq!(hub.events_from(1, None), Err(5));
+        let (events, _next_seq) = hub.events_from(5, None).unwrap();
+        assert_eq!(events.len(), LEASE_EVENT_RETENTION);
+    }
+}
@@ -2,6 +2,8 @@
 mod lease;
 /// Lease related structs collection
 mod lease_collection;
+/// Lease liveness events
+mod lease_events;
 /// Lease heap
 mod lease_queue;
 
@@ -11,7 +13,7 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use log::debug;
@@ -22,16 +24,31 @@ use utils::table_names::LEASE_TABLE;
 use xlineapi::{
     command::{CommandResponse, SyncResponse},
     execute_error::ExecuteError,
+    server_op::{
+        GrantLeasesOp, GrantLeasesOpResult, ImportLeasesOp, ImportLeasesOpResult, ServerOp,
+        ServerOpResult,
+    },
 };
 
-pub(crate) use self::{lease::Lease, lease_collection::LeaseCollection};
-use super::{db::WriteOp, index::Index, storage_api::StorageApi};
+pub(crate) use self::{
+    lease::Lease,
+    lease_collection::LeaseCollection,
+    lease_events::{LeaseEvent, LeaseEventHub, LeaseEventKind},
+};
+use super::{
+    db::WriteOp,
+    history::{ChangeHistory, HistoryReport},
+    index::{Index, IndexOperate},
+    kv_store::KvStoreInner,
+    prefix_stats::PrefixStats,
+    storage_api::StorageApi,
+};
 use crate::{
     header_gen::HeaderGenerator,
     rpc::{
         Event, LeaseGrantRequest, LeaseGrantResponse, LeaseLeasesRequest, LeaseLeasesResponse,
         LeaseRevokeRequest, LeaseRevokeResponse, LeaseStatus, PbLease, RequestWrapper,
-        ResponseHeader, ResponseWrapper,
+        ResponseHeader, ResponseWrapper, TxnRequest, TxnResponse,
     },
     storage::KvStore,
 };
@@ -39,6 +56,9 @@ use crate::{
 /// Max lease ttl
 const MAX_LEASE_TTL: i64 = 9_000_000_000;
 
+/// The name of the lease change history
+const LEASE_HISTORY: &str = "lease_history";
+
 /// Lease store
 #[derive(Debug)]
 pub(crate) struct LeaseStore<DB>
@@ -61,6 +81,13 @@ where
     unsynced_cache: Arc<RwLock<HashSet<i64>>>,
     /// notify sync event
     sync_event: event_listener::Event,
+    /// Lease change history
+    history: ChangeHistory<RequestWrapper>,
+    /// Statistics of the tracked key prefixes, updated by the deletions of the keys
+    /// of revoked leases
+    prefix_stats: Arc<PrefixStats>,
+    /// The liveness events of the leases
+    lease_events: Arc<LeaseEventHub>,
 }
 
 impl<DB> LeaseStore<DB>
@@ -68,6 +95,7 @@ where
     DB: StorageApi,
 {
     /// New `LeaseStore`
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         lease_collection: Arc<LeaseCollection>,
         header_gen: Arc<HeaderGenerator>,
@@ -75,6 +103,8 @@ where
         index: Arc<Index>,
         kv_update_tx: mpsc::Sender<(i64, Vec<Event>)>,
         is_leader: bool,
+        history_retention: usize,
+        prefix_stats: Arc<PrefixStats>,
     ) -> Self {
         Self {
             lease_collection,
@@ -85,9 +115,22 @@ where
             is_primary: AtomicBool::new(is_leader),
             unsynced_cache: Arc::new(RwLock::new(HashSet::new())),
             sync_event: event_listener::Event::new(),
+            history: ChangeHistory::new(LEASE_HISTORY, history_retention),
+            prefix_stats,
+            lease_events: Arc::new(LeaseEventHub::new()),
         }
     }
 
+    /// Get the liveness events of the leases
+    pub(crate) fn lease_events(&self) -> Arc<LeaseEventHub> {
+        Arc::clone(&self.lease_events)
+    }
+
+    /// Get the latest `limit` changes of the lease change history
+    pub(crate) fn history(&self, limit: usize) -> HistoryReport<RequestWrapper> {
+        self.history.report(limit)
+    }
+
     /// execute a lease request
     pub(crate) fn execute(
         &self,
@@ -123,6 +166,11 @@ where
         self.lease_collection.find_expired_leases()
     }
 
+    /// Find leases which have expired at `now`
+    pub(crate) fn find_expired_leases_at(&self, now: Instant) -> Vec<i64> {
+        self.lease_collection.find_expired_leases_at(now)
+    }
+
     /// Get keys attached to a lease
     pub(crate) fn get_keys(&self, lease_id: i64) -> Vec<Vec<u8>> {
         self.lease_collection
@@ -131,9 +179,19 @@ where
             .unwrap_or_default()
     }
 
+    /// Get all the leases with the revision of the keyspace their attached keys
+    /// match
+    pub(crate) fn export(&self) -> (i64, Vec<Lease>) {
+        self.lease_collection
+            .export(|| self.header_gen.general_revision())
+    }
+
     /// Keep alive a lease
     pub(crate) fn keep_alive(&self, lease_id: i64) -> Result<i64, ExecuteError> {
-        self.lease_collection.renew(lease_id)
+        let ttl = self.lease_collection.renew(lease_id)?;
+        self.lease_events
+            .record(lease_id, LeaseEventKind::KeepAlive, ttl);
+        Ok(ttl)
     }
 
     /// Generate `ResponseHeader`
@@ -159,7 +217,7 @@ where
         for lease in leases {
             let _ignore = self.lease_collection.grant(lease.id, lease.ttl, false);
         }
-        Ok(())
+        self.history.recover(self.db.as_ref())
     }
 
     /// Check whether the current lease storage is primary or not
@@ -169,16 +227,26 @@ where
 
     /// Make lease synced, remove it from `unsynced_cache`
     pub(crate) fn mark_lease_synced(&self, wrapper: &RequestWrapper) {
-        #[allow(clippy::wildcard_enum_match_arm)] // only the following two type are allowed
-        let lease_id = match *wrapper {
-            RequestWrapper::LeaseGrantRequest(ref req) => req.id,
-            RequestWrapper::LeaseRevokeRequest(ref req) => req.id,
+        #[allow(clippy::wildcard_enum_match_arm)] // only the following types are allowed
+        let lease_ids = match *wrapper {
+            RequestWrapper::LeaseGrantRequest(ref req) => vec![req.id],
+            RequestWrapper::LeaseRevokeRequest(ref req) => vec![req.id],
+            RequestWrapper::TxnRequest(ref req) => {
+                let Some(ids) = ServerOp::lease_ids(req) else {
+                    return;
+                };
+                ids
+            }
             _ => {
                 return;
             }
         };
 
-        _ = self.unsynced_cache.write().remove(&lease_id);
+        let mut unsynced_cache = self.unsynced_cache.write();
+        for lease_id in lease_ids {
+            _ = unsynced_cache.remove(&lease_id);
+        }
+        drop(unsynced_cache);
         let _ignore = self.sync_event.notify(usize::MAX);
     }
 
@@ -219,6 +287,10 @@ where
                 debug!("Receive LeaseLeasesRequest {:?}", req);
                 Ok(self.handle_lease_leases_request(req).into())
             }
+            RequestWrapper::TxnRequest(ref req) => {
+                debug!("Receive a lease server op {:?}", req);
+                self.handle_lease_server_op(req).map(Into::into)
+            }
             _ => unreachable!("Other request should not be sent to this store"),
         };
         res
@@ -229,15 +301,7 @@ where
         &self,
         req: &LeaseGrantRequest,
     ) -> Result<LeaseGrantResponse, ExecuteError> {
-        if req.id == 0 {
-            return Err(ExecuteError::LeaseNotFound(0));
-        }
-        if req.ttl > MAX_LEASE_TTL {
-            return Err(ExecuteError::LeaseTtlTooLarge(req.ttl));
-        }
-        if self.lease_collection.contains_lease(req.id) {
-            return Err(ExecuteError::LeaseAlreadyExists(req.id));
-        }
+        self.check_lease_grant(req.id, req.ttl)?;
 
         _ = self.unsynced_cache.write().insert(req.id);
 
@@ -249,6 +313,102 @@ where
         })
     }
 
+    /// Check whether a lease can be granted
+    fn check_lease_grant(&self, id: i64, ttl: i64) -> Result<(), ExecuteError> {
+        if id == 0 {
+            return Err(ExecuteError::LeaseNotFound(0));
+        }
+        if ttl > MAX_LEASE_TTL {
+            return Err(ExecuteError::LeaseTtlTooLarge(ttl));
+        }
+        if self.lease_collection.contains_lease(id) {
+            return Err(ExecuteError::LeaseAlreadyExists(id));
+        }
+        Ok(())
+    }
+
+    /// Get the lease server op carried by a txn
+    fn lease_server_op(req: &TxnRequest) -> Result<ServerOp, ExecuteError> {
+        let rejected =
+            || ExecuteError::Rejected("the txn doesn't carry a lease server op".to_owned());
+        let op = ServerOp::from_txn(req)?.ok_or_else(rejected)?;
+        match op {
+            ServerOp::GrantLeases(ref grant) => grant.check_count()?,
+            ServerOp::ImportLeases(ref import) => import.check_count()?,
+            ServerOp::Increment(_)
+            | ServerOp::Append(_)
+            | ServerOp::Swap(_)
+            | ServerOp::ReserveRevisions(_)
+            | ServerOp::MovePrefix(_)
+            | ServerOp::ConditionalDelete(_)
+            | ServerOp::MemberTags(_)
+            | ServerOp::RehashPassword(_) => return Err(rejected()),
+        }
+        Ok(op)
+    }
+
+    /// Handle a lease server op carried by a txn
+    fn handle_lease_server_op(&self, req: &TxnRequest) -> Result<TxnResponse, ExecuteError> {
+        #[allow(clippy::wildcard_enum_match_arm)] // the other ops are rejected
+        let result = match Self::lease_server_op(req)? {
+            ServerOp::GrantLeases(op) => self.handle_grant_leases_op(&op)?,
+            ServerOp::ImportLeases(op) => self.handle_import_leases_op(&op)?,
+            _ => unreachable!("the other ops are rejected"),
+        };
+        Ok(result.into_txn_response(Some(self.header_gen.gen_header())))
+    }
+
+    /// Handle a batch of lease grants, every lease is checked as a single grant and
+    /// the ids in the batch must be unique
+    fn handle_grant_leases_op(&self, op: &GrantLeasesOp) -> Result<ServerOpResult, ExecuteError> {
+        let mut ids = HashSet::with_capacity(op.leases.len());
+        for lease in &op.leases {
+            self.check_lease_grant(lease.id, lease.ttl)?;
+            if !ids.insert(lease.id) {
+                return Err(ExecuteError::LeaseAlreadyExists(lease.id));
+            }
+        }
+
+        self.unsynced_cache.write().extend(ids);
+
+        let ids = op.leases.iter().map(|lease| lease.id).collect();
+        Ok(ServerOpResult::GrantLeases(GrantLeasesOpResult { ids }))
+    }
+
+    /// Handle an import of leases, every lease is checked as a single grant, the ids
+    /// and the keys in the import must be unique, and every key must exist
+    fn handle_import_leases_op(&self, op: &ImportLeasesOp) -> Result<ServerOpResult, ExecuteError> {
+        let mut ids = HashSet::with_capacity(op.leases.len());
+        let mut keys = HashSet::new();
+        for lease in &op.leases {
+            self.check_lease_grant(lease.id, lease.ttl)?;
+            if !ids.insert(lease.id) {
+                return Err(ExecuteError::LeaseAlreadyExists(lease.id));
+            }
+            for key in &lease.keys {
+                if !keys.insert(key.as_slice()) {
+                    return Err(ExecuteError::Rejected(
+                        "a key can only be attached to one imported lease".to_owned(),
+                    ));
+                }
+                if self.index.get(key, &[], 0).is_empty() {
+                    return Err(ExecuteError::KeyNotFound);
+                }
+            }
+        }
+
+        self.unsynced_cache.write().extend(ids);
+
+        let (imported, revoked) = op
+            .leases
+            .iter()
+            .partition::<Vec<_>, _>(|lease| lease.remaining_ttl > 0);
+        Ok(ServerOpResult::ImportLeases(ImportLeasesOpResult {
+            imported: imported.iter().map(|lease| lease.id).collect(),
+            revoked: revoked.iter().map(|lease| lease.id).collect(),
+        }))
+    }
+
     /// Handle `LeaseRevokeRequest`
     fn handle_lease_revoke_request(
         &self,
@@ -289,16 +449,30 @@ where
         let ops = match *wrapper {
             RequestWrapper::LeaseGrantRequest(ref req) => {
                 debug!("Sync LeaseGrantRequest {:?}", req);
-                self.sync_lease_grant_request(req)
+                let mut ops = self.sync_lease_grant_request(req);
+                ops.extend(self.history.record(wrapper.clone()));
+                ops
             }
             RequestWrapper::LeaseRevokeRequest(ref req) => {
                 debug!("Sync LeaseRevokeRequest {:?}", req);
-                self.sync_lease_revoke_request(req, revision).await?
+                let mut ops = self.sync_lease_revoke_request(req, revision).await?;
+                ops.extend(self.history.record(wrapper.clone()));
+                ops
             }
             RequestWrapper::LeaseLeasesRequest(ref req) => {
                 debug!("Sync LeaseLeasesRequest {:?}", req);
                 vec![]
             }
+            RequestWrapper::TxnRequest(ref req) => {
+                debug!("Sync a lease server op {:?}", req);
+                #[allow(clippy::wildcard_enum_match_arm)] // the other ops are rejected
+                let ops = match Self::lease_server_op(req)? {
+                    ServerOp::GrantLeases(op) => self.sync_grant_leases_op(op),
+                    ServerOp::ImportLeases(op) => self.sync_import_leases_op(op, revision).await?,
+                    _ => unreachable!("the other ops are rejected"),
+                };
+                ops
+            }
             _ => unreachable!("Other request should not be sent to this store"),
         };
         Ok((revision, ops))
@@ -309,9 +483,92 @@ where
         let lease = self
             .lease_collection
             .grant(req.id, req.ttl, self.is_primary());
+        self.lease_events
+            .record(lease.id, LeaseEventKind::Grant, lease.ttl);
         vec![WriteOp::PutLease(lease)]
     }
 
+    /// Sync a batch of lease grants
+    fn sync_grant_leases_op(&self, op: GrantLeasesOp) -> Vec<WriteOp> {
+        let mut ops = Vec::with_capacity(op.leases.len());
+        for lease in op.leases {
+            let grant = LeaseGrantRequest {
+                id: lease.id,
+                ttl: lease.ttl,
+            };
+            ops.append(&mut self.sync_lease_grant_request(&grant));
+            ops.extend(self.history.record(grant.into()));
+        }
+        ops
+    }
+
+    /// Sync an import of leases, all the changes of the keys take the revision of the
+    /// import. The leases which are alive are granted with their remaining ttls, which
+    /// start at the apply on the leader, and their keys are reattached. The keys of
+    /// the expired leases are deleted.
+    async fn sync_import_leases_op(
+        &self,
+        op: ImportLeasesOp,
+        revision: i64,
+    ) -> Result<Vec<WriteOp>, ExecuteError> {
+        let kv = KvStoreInner::new(Arc::clone(&self.index), Arc::clone(&self.db));
+        let mut ops = Vec::new();
+        let mut updates = Vec::new();
+        let mut sub_revisions = 0..;
+        for lease in op.leases {
+            if lease.remaining_ttl <= 0 {
+                for (key, sub_revision) in lease.keys.iter().zip(&mut sub_revisions) {
+                    let (mut del_ops, mut del_events) = KvStore::<DB>::delete_keys(
+                        &self.index,
+                        &self.lease_collection,
+                        key,
+                        &[],
+                        revision,
+                        sub_revision,
+                    );
+                    ops.append(&mut del_ops);
+                    updates.append(&mut del_events);
+                }
+                continue;
+            }
+            let pb_lease = self.lease_collection.grant_with_remaining_ttl(
+                lease.id,
+                lease.ttl,
+                lease.remaining_ttl,
+                self.is_primary(),
+            );
+            self.lease_events
+                .record(lease.id, LeaseEventKind::Grant, pb_lease.remaining_ttl);
+            ops.push(WriteOp::PutLease(pb_lease));
+            let grant = LeaseGrantRequest {
+                id: lease.id,
+                ttl: lease.ttl,
+            };
+            ops.extend(self.history.record(grant.into()));
+            for (key, sub_revision) in lease.keys.iter().zip(&mut sub_revisions) {
+                let (mut put_ops, mut put_events) = KvStore::<DB>::reattach_key(
+                    &kv,
+                    &self.lease_collection,
+                    key,
+                    lease.id,
+                    revision,
+                    sub_revision,
+                )?;
+                ops.append(&mut put_ops);
+                updates.append(&mut put_events);
+            }
+        }
+
+        if !updates.is_empty() {
+            self.prefix_stats.apply(&updates);
+            assert!(
+                self.kv_update_tx.send((revision, updates)).await.is_ok(),
+                "Failed to send updates to KV watcher"
+            );
+        }
+        Ok(ops)
+    }
+
     /// Get all `PbLease`
     fn get_all(&self) -> Result<Vec<PbLease>, ExecuteError> {
         self.db
@@ -343,6 +600,7 @@ where
 
         if del_keys.is_empty() {
             let _ignore = self.lease_collection.revoke(req.id);
+            self.lease_events.record(req.id, LeaseEventKind::Revoke, 0);
             return Ok(Vec::new());
         }
 
@@ -360,6 +618,8 @@ where
         }
 
         let _ignore = self.lease_collection.revoke(req.id);
+        self.lease_events.record(req.id, LeaseEventKind::Revoke, 0);
+        self.prefix_stats.apply(&updates);
         assert!(
             self.kv_update_tx.send((revision, updates)).await.is_ok(),
             "Failed to send updates to KV watcher"
@@ -374,9 +634,13 @@ mod test {
 
     use test_macros::abort_on_panic;
     use utils::config::EngineConfig;
+    use xlineapi::{
+        server_op::{LeaseGrant, LeaseImport},
+        RequestBackend,
+    };
 
     use super::*;
-    use crate::storage::db::DB;
+    use crate::{rpc::KeyValue, storage::db::DB};
 
     #[tokio::test(flavor = "multi_thread")]
     #[abort_on_panic]
@@ -504,12 +768,183 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sweep_should_revoke_exactly_the_expired_leases() -> Result<(), Box<dyn Error>> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let lease_store = init_store(db);
+        let revision_gen = lease_store.header_gen.general_revision_arc();
+        for (id, ttl) in [(1, 10), (2, 20), (3, 30), (4, 100)] {
+            let req = RequestWrapper::from(LeaseGrantRequest { ttl, id });
+            let _ignore = exe_and_sync_req(&lease_store, &req, -1).await?;
+        }
+
+        let now = Instant::now();
+        assert!(lease_store.find_expired_leases_at(now).is_empty());
+        let mock_now = now + Duration::from_secs(50);
+        let mut expired = lease_store.find_expired_leases_at(mock_now);
+        expired.sort_unstable();
+        assert_eq!(expired, vec![1, 2, 3]);
+        for id in expired {
+            let req = RequestWrapper::from(LeaseRevokeRequest { id });
+            let _ignore = exe_and_sync_req(&lease_store, &req, revision_gen.next()).await?;
+        }
+        assert!(lease_store.find_expired_leases_at(mock_now).is_empty());
+        let remaining: Vec<_> = lease_store.leases().iter().map(Lease::id).collect();
+        assert_eq!(remaining, vec![4]);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lease_grants_should_be_applied_as_a_batch() -> Result<(), Box<dyn Error>> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let lease_store = init_store(db);
+        let batch = |leases: &[(i64, i64)]| {
+            let leases = leases
+                .iter()
+                .map(|&(id, ttl)| LeaseGrant { id, ttl })
+                .collect();
+            RequestWrapper::from(TxnRequest::from(ServerOp::GrantLeases(GrantLeasesOp::new(
+                leases,
+            ))))
+        };
+
+        let req = batch(&[(1, 10), (2, 20), (3, 30)]);
+        assert_eq!(req.backend(), RequestBackend::Lease);
+        let ResponseWrapper::TxnResponse(resp) = exe_and_sync_req(&lease_store, &req, -1).await?
+        else {
+            panic!("a batch of lease grants should get a txn response");
+        };
+        assert_eq!(
+            ServerOpResult::from_txn_response(&resp),
+            Some(ServerOpResult::GrantLeases(GrantLeasesOpResult {
+                ids: vec![1, 2, 3]
+            }))
+        );
+        for (id, ttl) in [(1, 10), (2, 20), (3, 30)] {
+            assert_eq!(
+                lease_store.look_up(id).map(|lease| lease.ttl()),
+                Some(Duration::from_secs(ttl))
+            );
+        }
+
+        // a batch is rejected as a whole by the checks of a single grant
+        for leases in [
+            &[(4, 10), (4, 10)][..],
+            &[(4, 10), (3, 10)],
+            &[(4, 10), (0, 10)],
+            &[],
+        ] {
+            assert!(exe_and_sync_req(&lease_store, &batch(leases), -1)
+                .await
+                .is_err());
+        }
+        assert!(lease_store.look_up(4).is_none());
+        assert_eq!(lease_store.leases().len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn lease_state_should_be_imported_at_one_revision() -> Result<(), Box<dyn Error>> {
+        let db = DB::open(&EngineConfig::Memory)?;
+        let (lease_store, mut kv_updates) = init_store_with_kv_updates(Arc::clone(&db));
+        for (key, revision) in [("foo", 1), ("bar", 2), ("baz", 3)] {
+            let rev = lease_store
+                .index
+                .register_revision(key.as_bytes(), revision, 0);
+            let kv = KeyValue {
+                key: key.into(),
+                value: "value".into(),
+                create_revision: rev.create_revision,
+                mod_revision: rev.mod_revision,
+                version: rev.version,
+                lease: 0,
+            };
+            let key_revisions = db.flush_ops(vec![WriteOp::PutKeyValue(rev.as_revision(), kv)])?;
+            lease_store.index.insert(key_revisions);
+        }
+        let import = |leases: Vec<(i64, i64, &[&str])>| {
+            let leases = leases
+                .into_iter()
+                .map(|(id, remaining_ttl, keys)| LeaseImport {
+                    id,
+                    ttl: 60,
+                    remaining_ttl,
+                    keys: keys.iter().map(|key| key.as_bytes().to_vec()).collect(),
+                })
+                .collect();
+            RequestWrapper::from(TxnRequest::from(ServerOp::ImportLeases(
+                ImportLeasesOp::new(leases),
+            )))
+        };
+
+        // an import is rejected as a whole by the checks of the leases and keys
+        for leases in [
+            vec![(1, 30, &["foo", "missing"][..])],
+            vec![(1, 30, &["foo"][..]), (2, 30, &["foo"][..])],
+            vec![(1, 30, &["foo"][..]), (1, 30, &["bar"][..])],
+            vec![],
+        ] {
+            assert!(lease_store.execute(&import(leases)).is_err());
+        }
+        assert!(lease_store.leases().is_empty());
+
+        let req = import(vec![(1, 30, &["bar", "foo"][..]), (2, 0, &["baz"][..])]);
+        assert_eq!(req.backend(), RequestBackend::Lease);
+        assert!(!req.skip_general_revision());
+        let ResponseWrapper::TxnResponse(resp) = lease_store.execute(&req)?.into_inner() else {
+            panic!("an import of leases should get a txn response");
+        };
+        assert_eq!(
+            ServerOpResult::from_txn_response(&resp),
+            Some(ServerOpResult::ImportLeases(ImportLeasesOpResult {
+                imported: vec![1],
+                revoked: vec![2],
+            }))
+        );
+        let (_ignore, ops) = lease_store.after_sync(&req, 4).await?;
+        let key_revisions = db.flush_ops(ops)?;
+        lease_store.index.insert(key_revisions);
+
+        let lease = lease_store.look_up(1).unwrap();
+        let mut keys = lease.keys();
+        keys.sort();
+        assert_eq!(keys, [b"bar".to_vec(), b"foo".to_vec()]);
+        assert!(lease.remaining() <= Duration::from_secs(30));
+        assert!(lease.remaining() > Duration::from_secs(20));
+        assert!(lease_store.look_up(2).is_none());
+        assert!(lease_store.index.get(b"baz", &[], 0).is_empty());
+        assert_eq!(lease_store.index.get(b"foo", &[], 0)[0].revision(), 4);
+        let (revision, events) = kv_updates.recv().await.unwrap();
+        assert_eq!(revision, 4);
+        assert_eq!(events.len(), 3);
+
+        Ok(())
+    }
+
     fn init_store(db: Arc<DB>) -> LeaseStore<DB> {
-        let lease_collection = Arc::new(LeaseCollection::new(0));
-        let (kv_update_tx, _) = mpsc::channel(1);
+        init_store_with_kv_updates(db).0
+    }
+
+    fn init_store_with_kv_updates(
+        db: Arc<DB>,
+    ) -> (LeaseStore<DB>, mpsc::Receiver<(i64, Vec<Event>)>) {
+        let lease_collection = Arc::new(LeaseCollection::new(0, Duration::ZERO));
+        let (kv_update_tx, kv_updates) = mpsc::channel(1);
         let header_gen = Arc::new(HeaderGenerator::new(0, 0));
         let index = Arc::new(Index::new());
-        LeaseStore::new(lease_collection, header_gen, db, index, kv_update_tx, true)
+        let store = LeaseStore::new(
+            lease_collection,
+            header_gen,
+            db,
+            index,
+            kv_update_tx,
+            true,
+            0,
+            Arc::default(),
+        );
+        (store, kv_updates)
     }
 
     async fn exe_and_sync_req(
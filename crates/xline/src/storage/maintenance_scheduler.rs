@@ -0,0 +1,227 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+use tracing::debug;
+use utils::config::{MaintenanceOp, MaintenancePolicy};
+
+/// An operation waiting for its turn
+#[derive(Debug)]
+struct Waiter {
+    /// The rank of the operation in the priority, the lower runs first
+    rank: usize,
+    /// The order in which the operation is queued
+    seq: u64,
+    /// The sender of the handed over permit
+    tx: oneshot::Sender<MaintenancePermit>,
+}
+
+/// The state of the scheduled operations
+#[derive(Debug, Default)]
+struct SchedulerState {
+    /// Whether an operation is running
+    running: bool,
+    /// The sequence number of the next queued operation
+    next_seq: u64,
+    /// The queued operations
+    waiting: Vec<Waiter>,
+}
+
+/// Schedules the heavy maintenance operations of the storage on a node, such as
+/// compactions, snapshots and defragmentations.
+///
+/// Under the serial policy at most one operation runs at a time and the others are
+/// queued, the queued operations run in the maintenance priority and then in the
+/// order they are queued. Under the concurrent policy the operations run as soon as
+/// they are triggered.
+#[derive(Debug)]
+pub(crate) struct MaintenanceScheduler {
+    /// Whether the operations run serially
+    serial: bool,
+    /// The operations in the order they run when queued
+    priority: Vec<MaintenanceOp>,
+    /// The state of the operations
+    state: Mutex<SchedulerState>,
+}
+
+/// Permits an operation to run until it is dropped
+#[derive(Debug)]
+#[must_use = "the operation may run only while its permit is held"]
+pub(crate) struct MaintenancePermit {
+    /// The scheduler to hand the permit over to the next operation, `None` if the
+    /// operations are not serialized
+    scheduler: Option<Arc<MaintenanceScheduler>>,
+}
+
+impl Drop for MaintenancePermit {
+    fn drop(&mut self) {
+        if let Some(scheduler) = self.scheduler.take() {
+            scheduler.release();
+        }
+    }
+}
+
+impl MaintenanceScheduler {
+    /// New `MaintenanceScheduler`
+    pub(crate) fn new(policy: MaintenancePolicy, priority: &[MaintenanceOp]) -> Self {
+        Self {
+            serial: policy == MaintenancePolicy::Serial,
+            priority: priority.to_vec(),
+            state: Mutex::new(SchedulerState::default()),
+        }
+    }
+
+    /// Wait for the turn of an operation, it runs while the returned permit is held
+    pub(crate) async fn acquire(self: &Arc<Self>, op: MaintenanceOp) -> MaintenancePermit {
+        if !self.serial {
+            return MaintenancePermit { scheduler: None };
+        }
+        let rx = {
+            let mut state = self.state.lock();
+            if !state.running {
+                state.running = true;
+                return MaintenancePermit {
+                    scheduler: Some(Arc::clone(self)),
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq = seq.wrapping_add(1);
+            let rank = self
+                .priority
+                .iter()
+                .position(|o| *o == op)
+                .unwrap_or(self.priority.len());
+            state.waiting.push(Waiter { rank, seq, tx });
+            rx
+        };
+        debug!("maintenance {op:?} is queued");
+        // a permit dropped with the receiver, e.g. the acquisition is cancelled, is
+        // handed over to the next operation
+        rx.await
+            .unwrap_or_else(|_| unreachable!("the permit is always handed over by the scheduler"))
+    }
+
+    /// Hand the permit over to the next queued operation, or mark that no operation
+    /// is running
+    fn release(self: Arc<Self>) {
+        loop {
+            let waiter = {
+                let mut state = self.state.lock();
+                let next = state
+                    .waiting
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|&(_, w)| (w.rank, w.seq))
+                    .map(|(i, _)| i);
+                let Some(next) = next else {
+                    state.running = false;
+                    return;
+                };
+                state.waiting.swap_remove(next)
+            };
+            let permit = MaintenancePermit {
+                scheduler: Some(Arc::clone(&self)),
+            };
+            let Err(mut permit) = waiter.tx.send(permit) else {
+                return;
+            };
+            // the operation stops waiting, try the next one
+            let _defused = permit.scheduler.take();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serial_compaction_and_defrag_should_not_overlap() {
+        let scheduler = Arc::new(MaintenanceScheduler::new(MaintenancePolicy::Serial, &[]));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = [MaintenanceOp::Compaction, MaintenanceOp::Defrag]
+            .into_iter()
+            .map(|op| {
+                let (scheduler, running, max_running) = (
+                    Arc::clone(&scheduler),
+                    Arc::clone(&running),
+                    Arc::clone(&max_running),
+                );
+                tokio::spawn(async move {
+                    let _permit = scheduler.acquire(op).await;
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    let _prev = max_running.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    let _prev = running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(max_running.load(Ordering::SeqCst), 1);
+
+        let concurrent = Arc::new(MaintenanceScheduler::new(
+            MaintenancePolicy::Concurrent,
+            &[],
+        ));
+        let _compaction = concurrent.acquire(MaintenanceOp::Compaction).await;
+        let _defrag = concurrent.acquire(MaintenanceOp::Defrag).await;
+    }
+
+    #[tokio::test]
+    async fn queued_operations_should_run_in_priority() {
+        let scheduler = Arc::new(MaintenanceScheduler::new(
+            MaintenancePolicy::Serial,
+            &[MaintenanceOp::Compaction, MaintenanceOp::Snapshot],
+        ));
+        let permit = scheduler.acquire(MaintenanceOp::Snapshot).await;
+        let (order_tx, mut order_rx) = mpsc::unbounded_channel();
+        let mut handles = Vec::new();
+        for op in [
+            MaintenanceOp::Defrag,
+            MaintenanceOp::Snapshot,
+            MaintenanceOp::Compaction,
+        ] {
+            let (scheduler, order_tx) = (Arc::clone(&scheduler), order_tx.clone());
+            handles.push(tokio::spawn(async move {
+                let _permit = scheduler.acquire(op).await;
+                order_tx.send(op).unwrap();
+            }));
+            // keep the order in which they are queued
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        // a cancelled acquisition doesn't hold up the others
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(10),
+            scheduler.acquire(MaintenanceOp::Compaction),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        drop(permit);
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        let mut order = Vec::new();
+        while let Ok(op) = order_rx.try_recv() {
+            order.push(op);
+        }
+        assert_eq!(
+            order,
+            vec![
+                MaintenanceOp::Compaction,
+                MaintenanceOp::Snapshot,
+                MaintenanceOp::Defrag
+            ]
+        );
+    }
+}
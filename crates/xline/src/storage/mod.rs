@@ -6,14 +6,26 @@ pub(crate) mod auth_store;
 pub(super) mod compact;
 /// Database module
 pub mod db;
+/// Encryption at rest module
+pub mod encryption;
+/// Export to etcd snapshot module
+pub mod etcd_snapshot;
+/// Change history module
+pub(crate) mod history;
 /// Index module
 pub(crate) mod index;
+/// Periodic check of the index against the backend
+pub(crate) mod index_check;
 /// Storage for KV
 pub(crate) mod kv_store;
 /// KV watcher module
 pub(crate) mod kvwatcher;
 /// Storage for lease
 pub(crate) mod lease_store;
+/// Scheduler of the heavy maintenance operations
+pub(crate) mod maintenance_scheduler;
+/// Key statistics of tracked prefixes
+pub(crate) mod prefix_stats;
 /// Revision module
 pub(crate) mod revision;
 /// Persistent storage abstraction
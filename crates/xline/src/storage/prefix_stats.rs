@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use clippy_utilities::NumericCast;
+use parking_lot::Mutex;
+
+use crate::rpc::{Event, EventType, KeyValue};
+
+/// The key count and value bytes of the live keys under a tracked prefix
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct PrefixTotal {
+    /// The number of live keys
+    pub(crate) keys: u64,
+    /// The total bytes of the values of the live keys
+    pub(crate) value_bytes: u64,
+}
+
+/// The statistics of the keys under a set of tracked prefixes.
+///
+/// They are updated incrementally by the changes of the kvs, the value size of every
+/// live key under the tracked prefixes is kept so that a deletion or an overwrite
+/// knows how many bytes it removes. A key under nested prefixes counts for each of
+/// them.
+#[derive(Debug, Default)]
+pub(crate) struct PrefixStats {
+    /// The tracked prefixes
+    prefixes: Vec<Vec<u8>>,
+    /// The statistics of the tracked prefixes
+    inner: Mutex<StatsInner>,
+}
+
+/// The statistics of the tracked prefixes under a lock
+#[derive(Debug, Default)]
+struct StatsInner {
+    /// The value sizes of the live keys under the tracked prefixes
+    value_sizes: HashMap<Vec<u8>, u64>,
+    /// The totals of the tracked prefixes, in the order of the prefixes
+    totals: Vec<PrefixTotal>,
+}
+
+impl PrefixStats {
+    /// New `PrefixStats` of the tracked prefixes
+    pub(crate) fn new(prefixes: &[String]) -> Self {
+        Self {
+            prefixes: prefixes.iter().map(|p| p.as_bytes().to_vec()).collect(),
+            inner: Mutex::new(StatsInner {
+                value_sizes: HashMap::new(),
+                totals: vec![PrefixTotal::default(); prefixes.len()],
+            }),
+        }
+    }
+
+    /// Apply the events of a revision
+    pub(crate) fn apply(&self, events: &[Event]) {
+        if self.prefixes.is_empty() {
+            return;
+        }
+        for event in events {
+            let Some(ref kv) = event.kv else {
+                continue;
+            };
+            let value_size =
+                (event.r#type() == EventType::Put).then(|| kv.value.len().numeric_cast());
+            self.update(&kv.key, value_size);
+        }
+    }
+
+    /// Restore a `KeyValue` read from the storage in the order of revisions, a
+    /// deletion is stored as a `KeyValue` of version 0
+    pub(crate) fn restore(&self, kv: &KeyValue) {
+        if self.prefixes.is_empty() {
+            return;
+        }
+        let value_size = (kv.version != 0).then(|| kv.value.len().numeric_cast());
+        self.update(&kv.key, value_size);
+    }
+
+    /// Get the totals of the tracked prefixes
+    pub(crate) fn totals(&self) -> Vec<(String, PrefixTotal)> {
+        let inner = self.inner.lock();
+        self.prefixes
+            .iter()
+            .map(|prefix| String::from_utf8_lossy(prefix).into_owned())
+            .zip(inner.totals.iter().copied())
+            .collect()
+    }
+
+    /// Update the value size of a key, `None` means the key is deleted
+    fn update(&self, key: &[u8], value_size: Option<u64>) {
+        if !self.prefixes.iter().any(|prefix| key.starts_with(prefix)) {
+            return;
+        }
+        let mut inner = self.inner.lock();
+        let old_size = match value_size {
+            Some(size) => inner.value_sizes.insert(key.to_vec(), size),
+            None => inner.value_sizes.remove(key),
+        };
+        if old_size.is_none() && value_size.is_none() {
+            return;
+        }
+        for (prefix, total) in self.prefixes.iter().zip(inner.totals.iter_mut()) {
+            if !key.starts_with(prefix) {
+                continue;
+            }
+            match (old_size, value_size) {
+                (None, Some(_)) => total.keys = total.keys.saturating_add(1),
+                (Some(_), None) => total.keys = total.keys.saturating_sub(1),
+                _ => {}
+            }
+            total.value_bytes = total
+                .value_bytes
+                .saturating_sub(old_size.unwrap_or(0))
+                .saturating_add(value_size.unwrap_or(0));
+        }
+    }
+}
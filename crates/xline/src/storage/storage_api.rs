@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::{path::Path, time::SystemTime};
 
 use engine::Snapshot;
 use xlineapi::execute_error::ExecuteError;
@@ -59,4 +59,22 @@ pub trait StorageApi: Send + Sync + 'static + std::fmt::Debug {
 
     /// Get the file size of the engine
     fn file_size(&self) -> Result<u64, ExecuteError>;
+
+    /// Get the estimated size of the live data of the engine
+    fn live_data_size(&self) -> Result<u64, ExecuteError>;
+
+    /// Defragment the storage to reclaim the space of the deleted data
+    ///
+    /// # Errors
+    ///
+    /// if error occurs in storage, return `Err(error)`
+    fn defragment(&self) -> Result<(), ExecuteError>;
+
+    /// Get the time the last write was synced to the disk, `None` if no write has
+    /// been synced since the storage is opened
+    fn last_synced_at(&self) -> Option<SystemTime>;
+
+    /// Get the error of the last write if it failed, the storage is degraded until a
+    /// write succeeds again
+    fn write_failure(&self) -> Option<String>;
 }
@@ -5,23 +5,43 @@ use clap::Parser;
 use tokio::fs;
 use utils::{
     config::{
-        default_batch_max_size, default_batch_timeout, default_candidate_timeout_ticks,
-        default_client_id_keep_alive_interval, default_client_wait_synced_timeout,
-        default_cmd_workers, default_compact_batch_size, default_compact_sleep_interval,
-        default_compact_timeout, default_follower_timeout_ticks, default_gc_interval,
-        default_heartbeat_interval, default_initial_retry_timeout, default_log_entries_cap,
-        default_log_level, default_max_retry_timeout, default_metrics_enable, default_metrics_path,
+        default_apply_stall_threshold, default_batch_max_size, default_batch_timeout,
+        default_bulk_load_threshold, default_candidate_timeout_ticks,
+        default_catch_up_read_timeout, default_client_id_keep_alive_interval,
+        default_client_wait_synced_timeout, default_cmd_workers, default_compact_batch_size,
+        default_compact_sleep_interval, default_compact_timeout, default_dedup_value_threshold,
+        default_defrag_check_interval, default_follower_timeout_ticks, default_gc_interval,
+        default_heartbeat_interval, default_history_retention, default_index_check_sample_size,
+        default_initial_retry_timeout, default_leader_flap_threshold, default_leader_flap_window,
+        default_leaderless_read_timeout, default_lease_grace_period,
+        default_lease_keep_alive_send_timeout, default_log_entries_cap, default_log_level,
+        default_max_inflight_proposals, default_max_range_result_count,
+        default_max_recv_message_size, default_max_request_order_wait, default_max_retry_timeout,
+        default_max_send_message_size, default_max_watch_buffer_depth,
+        default_max_write_coalescing_window, default_metrics_enable, default_metrics_path,
         default_metrics_port, default_metrics_push_endpoint, default_metrics_push_protocol,
-        default_propose_timeout, default_quota, default_range_retry_timeout, default_retry_count,
-        default_rotation, default_rpc_timeout, default_server_wait_synced_timeout,
-        default_sync_victims_interval, default_watch_progress_notify_interval, AuthConfig,
-        AutoCompactConfig, ClientConfig, ClusterConfig, CompactConfig, CurpConfigBuilder,
-        EngineConfig, InitialClusterState, LevelConfig, LogConfig, MetricsConfig,
-        MetricsPushProtocol, RotationConfig, ServerTimeout, StorageConfig, TlsConfig, TraceConfig,
-        XlineServerConfig,
+        default_min_healthy_voters, default_min_watch_buffer_depth, default_password_hash_rounds,
+        default_propose_timeout, default_protected_retention, default_quota,
+        default_range_memory_budget, default_range_retry_timeout, default_range_stream_batch_size,
+        default_read_index_batch_window, default_retry_count, default_rotation,
+        default_rpc_timeout, default_server_wait_synced_timeout, default_slow_request_threshold,
+        default_sync_victims_interval, default_token_cache_size,
+        default_watch_progress_notify_interval, default_watch_safety_margin, AuthConfig,
+        AutoCompactConfig, AutoDefragConfig, ClientConfig, ClusterConfig, CompactConfig,
+        CurpConfigBuilder, EncryptionConfig, EngineConfig, IndexCheckConfig, InitialClusterState,
+        KeyCharset, KeyValueEncoding, KvConfig, LeaderlessReads, LevelConfig, LogConfig,
+        MaintenanceOp, MaintenancePolicy, MaintenanceWindow, MessageSizeConfig, MetricsConfig,
+        MetricsPushProtocol, OversizedWatchEvent, RangeResultOverflow, RequestLogSampling,
+        RoleQuota, RotationConfig, ServerTimeout, SnapshotInstallReads, StorageConfig, TlsConfig,
+        TlsVersion, TraceConfig, WatchConfig, WatchHistoryReplay, XlineServerConfig,
     },
-    parse_batch_bytes, parse_duration, parse_log_file, parse_log_level, parse_members,
-    parse_metrics_push_protocol, parse_rotation, parse_state, ConfigFileError,
+    parse_batch_bytes, parse_duration, parse_encryption_key_files, parse_key_charset,
+    parse_key_value_encoding, parse_leaderless_reads, parse_log_file, parse_log_level,
+    parse_maintenance_op, parse_maintenance_policy, parse_maintenance_window, parse_member_tags,
+    parse_members, parse_metrics_push_protocol, parse_oversized_watch_event,
+    parse_range_result_overflow, parse_request_log_sampling, parse_role_quotas, parse_rotation,
+    parse_snapshot_install_reads, parse_state, parse_tls_version, parse_watch_history_replay,
+    ConfigFileError,
 };
 
 /// Xline server config path env name
@@ -49,9 +69,18 @@ pub struct ServerArgs {
     /// Node client advertise urls
     #[clap(long, num_args = 1.., value_delimiter = ',')]
     client_advertise_urls: Vec<String>,
+    /// Node client listen urls only serving the reads
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    read_listen_urls: Vec<String>,
+    /// Node client listen urls only serving the writes
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    write_listen_urls: Vec<String>,
     /// Cluster peers. eg: node1=192.168.x.x:8080,192.168.x.x:8081,node2=192.168.x.x:8083
     #[clap(long, value_parser = parse_members)]
     members: HashMap<String, Vec<String>>,
+    /// Tags of the node, which are returned by the member list. eg: region=us-east-1,zone=a
+    #[clap(long, value_parser = parse_member_tags)]
+    tags: Option<HashMap<String, String>>,
     /// If node is leader
     #[clap(long)]
     is_leader: bool,
@@ -61,6 +90,26 @@ pub struct ServerArgs {
     /// Public key used to verify the token
     #[clap(long)]
     auth_public_key: Option<PathBuf>,
+    /// Maximum number of validated tokens cached, 0 disables the cache
+    #[clap(long, default_value_t = default_token_cache_size())]
+    auth_token_cache_size: usize,
+    /// Quotas of roles, like "role1=max_keys:max_bytes:max_write_rate,role2=100:0:10",
+    /// a limit of 0 means unlimited
+    #[clap(long, value_parser = parse_role_quotas)]
+    auth_role_quotas: Option<HashMap<String, RoleQuota>>,
+    /// The pbkdf2 rounds of the password hashes made by the server
+    #[clap(long, default_value_t = default_password_hash_rounds())]
+    auth_password_hash_rounds: u32,
+    /// Re-hash the stored password of a user at the configured rounds on a successful
+    /// login, it invalidates the tokens issued before
+    #[clap(long)]
+    auth_rehash_passwords_on_login: bool,
+    /// Maximum number of roles granted to a user, 0 means unlimited
+    #[clap(long, default_value_t = 0)]
+    auth_max_roles_per_user: usize,
+    /// Maximum number of permissions granted to a role, 0 means unlimited
+    #[clap(long, default_value_t = 0)]
+    auth_max_permissions_per_role: usize,
     /// Open jaeger offline
     #[clap(long)]
     jaeger_offline: bool,
@@ -100,6 +149,21 @@ pub struct ServerArgs {
     /// Log verbosity level, eg: trace, debug, info, warn, error
     #[clap(long, value_parser = parse_log_level, default_value_t = default_log_level())]
     log_level: LevelConfig,
+    /// Redact the values of the kv store in the error messages, logs and traces
+    #[clap(long)]
+    log_redact_values: bool,
+    /// Redact the keys under these prefixes in the error messages, logs and traces
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    log_redacted_key_prefixes: Vec<String>,
+    /// Sampling of the request log, eg: off, 1/100, 10/s [default: off]
+    #[clap(long, value_parser = parse_request_log_sampling)]
+    log_request_sampling: Option<RequestLogSampling>,
+    /// Log the failed requests regardless of the sampling
+    #[clap(long)]
+    log_request_errors: bool,
+    /// Log the requests slower than it regardless of the sampling, 0 means disabled [default: 0s]
+    #[clap(long, value_parser = parse_duration)]
+    log_slow_request_threshold: Option<Duration>,
     /// Heartbeat interval between curp server nodes [default: 300ms]
     #[clap(long, value_parser = parse_duration)]
     heartbeat_interval: Option<Duration>,
@@ -130,6 +194,16 @@ pub struct ServerArgs {
     /// Number of log entries to keep in memory
     #[clap(long, default_value_t = default_log_entries_cap())]
     log_entries_cap: usize,
+    /// Elections allowed in the leader flap window before the leader is considered
+    /// flapping, 0 disables the detection [default: 0]
+    #[clap(long)]
+    leader_flap_threshold: Option<usize>,
+    /// The window in which the elections are counted [default: 60s]
+    #[clap(long, value_parser = parse_duration)]
+    leader_flap_window: Option<Duration>,
+    /// Back off the election timeouts while the leader is flapping
+    #[clap(long)]
+    leader_flap_backoff: bool,
     /// Curp client wait synced timeout [default: 2s]
     #[clap(long, value_parser = parse_duration)]
     client_wait_synced_timeout: Option<Duration>,
@@ -154,6 +228,18 @@ pub struct ServerArgs {
     /// Range request retry timeout [default: 2s]
     #[clap(long, value_parser = parse_duration)]
     range_retry_timeout: Option<Duration>,
+    /// Max size in bytes of a message sent by the client listener, 0 means unlimited
+    #[clap(long, default_value_t = default_max_send_message_size())]
+    client_max_send_message_size: u64,
+    /// Max size in bytes of a message received by the client listener, 0 means unlimited
+    #[clap(long, default_value_t = default_max_recv_message_size())]
+    client_max_recv_message_size: u64,
+    /// Max size in bytes of a message sent by the peer listener, 0 means unlimited
+    #[clap(long, default_value_t = default_max_send_message_size())]
+    peer_max_send_message_size: u64,
+    /// Max size in bytes of a message received by the peer listener, 0 means unlimited
+    #[clap(long, default_value_t = default_max_recv_message_size())]
+    peer_max_recv_message_size: u64,
     /// Compact timeout [default: 5s]
     #[clap(long, value_parser = parse_duration)]
     compact_timeout: Option<Duration>,
@@ -163,6 +249,121 @@ pub struct ServerArgs {
     /// How often should watch progress notify send a response [default: 600s]
     #[clap(long, value_parser = parse_duration)]
     watch_progress_notify_interval: Option<Duration>,
+    /// Grace period added to lease expiry before revocation, at most 5s [default: 0s]
+    #[clap(long, value_parser = parse_duration)]
+    lease_grace_period: Option<Duration>,
+    /// Report not serving if apply stalls for this long behind commit, 0 disables it [default: 0s]
+    #[clap(long, value_parser = parse_duration)]
+    apply_stall_threshold: Option<Duration>,
+    /// Close a lease keepalive stream not read for this long, 0 disables it [default: 0s]
+    #[clap(long, value_parser = parse_duration)]
+    lease_keep_alive_send_timeout: Option<Duration>,
+    /// Window in which concurrent linearizable reads share a read index, 0 disables it
+    /// [default: 0s]
+    #[clap(long, value_parser = parse_duration)]
+    read_index_batch_window: Option<Duration>,
+    /// Max wait of a catch-up read on the node to apply the commit index of the leader
+    /// [default: 1s]
+    #[clap(long, value_parser = parse_duration)]
+    catch_up_read_timeout: Option<Duration>,
+    /// Perform a read index before creating a watch from the current revision
+    #[clap(long)]
+    linearizable_watch_create: bool,
+    /// Handling of a watch from a historical revision: allow, reject or current [default: allow]
+    #[clap(long, value_parser = parse_watch_history_replay)]
+    watch_history_replay: Option<WatchHistoryReplay>,
+    /// Delivery of an oversized watch event: fragment or truncate [default: fragment]
+    #[clap(long, value_parser = parse_oversized_watch_event)]
+    watch_oversized_event: Option<OversizedWatchEvent>,
+    /// The lower bound of the buffer depth a watch can request
+    #[clap(long, default_value_t = default_min_watch_buffer_depth())]
+    watch_min_buffer_depth: usize,
+    /// The upper bound of the buffer depth a watch can request
+    #[clap(long, default_value_t = default_max_watch_buffer_depth())]
+    watch_max_buffer_depth: usize,
+    /// Make a put whose value and lease equal the current ones a no-op
+    #[clap(long)]
+    noop_identical_put: bool,
+    /// Reject puts with an empty value
+    #[clap(long)]
+    reject_empty_value_put: bool,
+    /// Values of at least this many bytes are stored once and shared, 0 disables it
+    #[clap(long, default_value_t = default_dedup_value_threshold())]
+    dedup_value_threshold: u64,
+    /// Memory budget in bytes of a range or txn response, 0 means unlimited
+    #[clap(long, default_value_t = default_range_memory_budget())]
+    range_memory_budget: u64,
+    /// Proxy the writes received by a follower to the leader
+    #[clap(long)]
+    forward_writes_to_leader: bool,
+    /// Max window in which the puts hinted by clients are coalesced, 0 disables it [default: 0s]
+    #[clap(long, value_parser = parse_duration)]
+    max_write_coalescing_window: Option<Duration>,
+    /// Serializable reads during a snapshot install: unavailable or wait-for-install
+    /// [default: unavailable]
+    #[clap(long, value_parser = parse_snapshot_install_reads)]
+    snapshot_install_reads: Option<SnapshotInstallReads>,
+    /// Encoding of keys and values: raw, utf8-keys or utf8 [default: raw]
+    #[clap(long, value_parser = parse_key_value_encoding)]
+    key_value_encoding: Option<KeyValueEncoding>,
+    /// Minimum healthy voters to accept writes, 0 means only the quorum is required
+    #[clap(long, default_value_t = default_min_healthy_voters())]
+    min_healthy_voters: usize,
+    /// Linearizable reads without a known leader: wait or unavailable [default: wait]
+    #[clap(long, value_parser = parse_leaderless_reads)]
+    leaderless_reads: Option<LeaderlessReads>,
+    /// Max time a linearizable read waits for a leader to be elected [default: 3s]
+    #[clap(long, value_parser = parse_duration)]
+    leaderless_read_timeout: Option<Duration>,
+    /// The key prefixes whose key count and value bytes are exported as metrics,
+    /// eg: app/,jobs/
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    tracked_prefixes: Vec<String>,
+    /// The key prefixes whose keys can only be put with an expected mod revision,
+    /// eg: config/
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    guarded_prefixes: Vec<String>,
+    /// The key prefixes whose lease-attached keys can't be put without keeping or
+    /// explicitly detaching the lease, eg: services/
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    lease_guarded_prefixes: Vec<String>,
+    /// Keep the last read time of each key in memory, best effort and not replicated
+    #[clap(long)]
+    track_last_access: bool,
+    /// Apply a txn putting at least this many keys as a bulk load, 0 disables it
+    #[clap(long, default_value_t = default_bulk_load_threshold())]
+    bulk_load_threshold: usize,
+    /// Let the writes ask for their latency from proposal to apply, for debugging
+    #[clap(long)]
+    report_apply_latency: bool,
+    /// Max time a numbered request waits for the earlier ones of its connection,
+    /// 0 disables the ordering [default: 0s]
+    #[clap(long, value_parser = parse_duration)]
+    max_request_order_wait: Option<Duration>,
+    /// Max number of keys a range returns after its filters, 0 means unlimited
+    #[clap(long, default_value_t = default_max_range_result_count())]
+    max_range_result_count: usize,
+    /// Ranges returning more than the max result count: truncate or reject [default: truncate]
+    #[clap(long, value_parser = parse_range_result_overflow)]
+    range_result_overflow: Option<RangeResultOverflow>,
+    /// Characters of the keys under the key charset prefixes: any, no-control or
+    /// printable [default: any]
+    #[clap(long, value_parser = parse_key_charset)]
+    key_charset: Option<KeyCharset>,
+    /// The key prefixes whose keys must consist of the characters of the key charset,
+    /// eg: names/
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    key_charset_prefixes: Vec<String>,
+    /// Max number of writes a node has in flight, a write beyond it is throttled with
+    /// a retry-after hint, 0 means unlimited
+    #[clap(long, default_value_t = default_max_inflight_proposals())]
+    max_inflight_proposals: usize,
+    /// The key prefixes whose keys are write-once, eg: audit/
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    immutable_prefixes: Vec<String>,
+    /// Max number of keys in a response of the streamed range, 0 disables it
+    #[clap(long, default_value_t = default_range_stream_batch_size())]
+    range_stream_batch_size: usize,
     /// Storage engine
     #[clap(long)]
     storage_engine: String,
@@ -189,12 +390,76 @@ pub struct ServerArgs {
     /// Auto revision compact retention
     #[clap(long)]
     auto_revision_retention: Option<i64>,
+    /// The number of auth history revisions to retain, 0 disables the auth history
+    #[clap(long, default_value_t = default_history_retention())]
+    auth_history_retention: usize,
+    /// The number of lease history revisions to retain, 0 disables the lease history
+    #[clap(long, default_value_t = default_history_retention())]
+    lease_history_retention: usize,
+    /// The key prefixes whose history is exempt from compaction, eg: legal/,audit/
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    protected_prefixes: Vec<String>,
+    /// The number of revisions the history of a deleted protected key is retained,
+    /// 0 retains it forever
+    #[clap(long, default_value_t = default_protected_retention())]
+    protected_retention: usize,
+    /// Hold the auto-compaction below the minimum start revision of the active
+    /// historical watches
+    #[clap(long)]
+    watch_safety_floor: bool,
+    /// The number of revisions the auto-compaction is held below the active
+    /// historical watches
+    #[clap(long, default_value_t = default_watch_safety_margin())]
+    watch_safety_margin: i64,
     /// Initial cluster state
     #[clap(long,value_parser = parse_state)]
     initial_cluster_state: Option<InitialClusterState>,
     /// Quota
     #[clap(long)]
     quota: Option<u64>,
+    /// How the heavy storage maintenance operations run: concurrent or serial
+    /// [default: concurrent]
+    #[clap(long, value_parser = parse_maintenance_policy)]
+    maintenance_policy: Option<MaintenancePolicy>,
+    /// The order of the queued maintenance operations under the serial policy,
+    /// eg: snapshot,compaction,defrag
+    #[clap(long, value_parser = parse_maintenance_op, num_args = 1.., value_delimiter = ',')]
+    maintenance_priority: Vec<MaintenanceOp>,
+    /// Sync the written batches to the disk before acknowledging them
+    #[clap(long)]
+    sync_writes: bool,
+    /// The key prefixes whose writes are acknowledged without syncing them to the disk
+    /// when the writes are synced, they may be lost from the storage of a node on a
+    /// crash, eg: session/,cache/
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    non_durable_prefixes: Vec<String>,
+    /// Defragment the members one at a time once the percentage of their free storage
+    /// space exceeds it, unset disables the automatic defragmentation
+    #[clap(long)]
+    auto_defrag_free_space_percent: Option<u64>,
+    /// The daily window in UTC the automatic defragmentations start within,
+    /// eg: 02:00-04:00 [default: the whole day]
+    #[clap(long, value_parser = parse_maintenance_window)]
+    auto_defrag_window: Option<MaintenanceWindow>,
+    /// How often the free storage space of the members is checked [default: 10m]
+    #[clap(long, value_parser = parse_duration)]
+    auto_defrag_check_interval: Option<Duration>,
+    /// The id of the key the key-value records are encrypted with at rest, unset
+    /// disables the encryption
+    #[clap(long)]
+    encryption_active_key: Option<String>,
+    /// The files of the encryption keys by their ids, the keys no longer active are
+    /// kept to decrypt the old records, eg: k1=/etc/xline/k1.key,k2=/etc/xline/k2.key
+    #[clap(long, value_parser = parse_encryption_key_files)]
+    encryption_key_files: Option<HashMap<String, PathBuf>>,
+    /// How often a sample of the keys in the in-memory index is checked against the
+    /// backend, unset disables the check
+    #[clap(long, value_parser = parse_duration)]
+    index_check_interval: Option<Duration>,
+    /// The maximum number of the keys checked against the backend each interval
+    /// [default: 100]
+    #[clap(long)]
+    index_check_sample_size: Option<usize>,
     /// Server ca certificate path, used to verify client certificate
     #[clap(long)]
     peer_ca_cert_path: Option<PathBuf>,
@@ -213,6 +478,13 @@ pub struct ServerArgs {
     /// Client private key path
     #[clap(long)]
     client_key_path: Option<PathBuf>,
+    /// Minimum tls version accepted by the listeners: 1.2 or 1.3 [default: 1.2]
+    #[clap(long, value_parser = parse_tls_version)]
+    tls_min_version: Option<TlsVersion>,
+    /// Cipher suites accepted by the listeners by their IANA names, the default ones
+    /// if empty, eg: TLS13_AES_256_GCM_SHA384,TLS13_AES_128_GCM_SHA256
+    #[clap(long, num_args = 1.., value_delimiter = ',')]
+    tls_cipher_suites: Vec<String>,
 }
 
 #[allow(clippy::too_many_lines)] // will be refactored in #604
@@ -233,7 +505,32 @@ impl From<ServerArgs> for XlineServerConfig {
             &_ => unreachable!("xline only supports memory and rocksdb engine"),
         };
 
-        let storage = StorageConfig::new(engine, args.quota.unwrap_or_else(default_quota));
+        let storage = StorageConfig::new(
+            engine,
+            args.quota.unwrap_or_else(default_quota),
+            args.maintenance_policy.unwrap_or_default(),
+            args.maintenance_priority,
+            args.sync_writes,
+            args.non_durable_prefixes,
+            args.auto_defrag_free_space_percent.map(|percent| {
+                AutoDefragConfig::new(
+                    percent,
+                    args.auto_defrag_window.unwrap_or_default(),
+                    args.auto_defrag_check_interval
+                        .unwrap_or_else(default_defrag_check_interval),
+                )
+            }),
+            args.encryption_active_key.map(|active_key| {
+                EncryptionConfig::new(active_key, args.encryption_key_files.unwrap_or_default())
+            }),
+            args.index_check_interval.map(|interval| {
+                IndexCheckConfig::new(
+                    interval,
+                    args.index_check_sample_size
+                        .unwrap_or_else(default_index_check_sample_size),
+                )
+            }),
+        );
         let Ok(curp_config) = CurpConfigBuilder::default()
             .heartbeat_interval(
                 args.heartbeat_interval
@@ -251,6 +548,15 @@ impl From<ServerArgs> for XlineServerConfig {
             .engine_cfg(curp_engine)
             .gc_interval(args.gc_interval.unwrap_or_else(default_gc_interval))
             .cmd_workers(args.cmd_workers)
+            .leader_flap_threshold(
+                args.leader_flap_threshold
+                    .unwrap_or_else(default_leader_flap_threshold),
+            )
+            .leader_flap_window(
+                args.leader_flap_window
+                    .unwrap_or_else(default_leader_flap_window),
+            )
+            .leader_flap_backoff(args.leader_flap_backoff)
             .build()
         else {
             panic!("failed to create curp config")
@@ -277,8 +583,24 @@ impl From<ServerArgs> for XlineServerConfig {
                 .unwrap_or_else(default_sync_victims_interval),
             args.watch_progress_notify_interval
                 .unwrap_or_else(default_watch_progress_notify_interval),
+            args.lease_grace_period
+                .unwrap_or_else(default_lease_grace_period),
+            args.apply_stall_threshold
+                .unwrap_or_else(default_apply_stall_threshold),
+            args.lease_keep_alive_send_timeout
+                .unwrap_or_else(default_lease_keep_alive_send_timeout),
+            args.read_index_batch_window
+                .unwrap_or_else(default_read_index_batch_window),
+            args.catch_up_read_timeout
+                .unwrap_or_else(default_catch_up_read_timeout),
         );
         let initial_cluster_state = args.initial_cluster_state.unwrap_or_default();
+        let message_size = MessageSizeConfig::new(
+            args.client_max_send_message_size,
+            args.client_max_recv_message_size,
+            args.peer_max_send_message_size,
+            args.peer_max_recv_message_size,
+        );
         let cluster = ClusterConfig::new(
             args.name,
             args.peer_listen_urls,
@@ -291,15 +613,38 @@ impl From<ServerArgs> for XlineServerConfig {
             client_config,
             server_timeout,
             initial_cluster_state,
+            message_size,
+            args.tags.unwrap_or_default(),
+            args.read_listen_urls,
+            args.write_listen_urls,
+        );
+        let log = LogConfig::new(
+            args.log_file,
+            args.log_rotate,
+            args.log_level,
+            args.log_redact_values,
+            args.log_redacted_key_prefixes,
+            args.log_request_sampling.unwrap_or_default(),
+            args.log_request_errors,
+            args.log_slow_request_threshold
+                .unwrap_or_else(default_slow_request_threshold),
         );
-        let log = LogConfig::new(args.log_file, args.log_rotate, args.log_level);
         let trace = TraceConfig::new(
             args.jaeger_online,
             args.jaeger_offline,
             args.jaeger_output_dir,
             args.jaeger_level,
         );
-        let auth = AuthConfig::new(args.auth_public_key, args.auth_private_key);
+        let auth = AuthConfig::new(
+            args.auth_public_key,
+            args.auth_private_key,
+            args.auth_token_cache_size,
+            args.auth_role_quotas.unwrap_or_default(),
+            args.auth_password_hash_rounds,
+            args.auth_rehash_passwords_on_login,
+            args.auth_max_roles_per_user,
+            args.auth_max_permissions_per_role,
+        );
         let auto_compactor_cfg = if let Some(mode) = args.auto_compact_mode {
             match mode.as_str() {
                 "periodic" => {
@@ -326,6 +671,12 @@ impl From<ServerArgs> for XlineServerConfig {
             args.compact_sleep_interval
                 .unwrap_or_else(default_compact_sleep_interval),
             auto_compactor_cfg,
+            args.auth_history_retention,
+            args.lease_history_retention,
+            args.protected_prefixes,
+            args.protected_retention,
+            args.watch_safety_floor,
+            args.watch_safety_margin,
         );
         let tls = TlsConfig::new(
             args.peer_ca_cert_path,
@@ -334,6 +685,8 @@ impl From<ServerArgs> for XlineServerConfig {
             args.client_ca_cert_path,
             args.client_cert_path,
             args.client_key_path,
+            args.tls_min_version.unwrap_or_default(),
+            args.tls_cipher_suites,
         );
         let metrics = MetricsConfig::new(
             args.metrics_enable,
@@ -343,7 +696,46 @@ impl From<ServerArgs> for XlineServerConfig {
             args.metrics_push_endpoint,
             args.metrics_push_protocol,
         );
-        XlineServerConfig::new(cluster, storage, log, trace, auth, compact, tls, metrics)
+        let watch = WatchConfig::new(
+            args.linearizable_watch_create,
+            args.watch_history_replay.unwrap_or_default(),
+            args.watch_oversized_event.unwrap_or_default(),
+            args.watch_min_buffer_depth,
+            args.watch_max_buffer_depth,
+        );
+        let kv = KvConfig::new(
+            args.noop_identical_put,
+            args.reject_empty_value_put,
+            args.dedup_value_threshold,
+            args.range_memory_budget,
+            args.forward_writes_to_leader,
+            args.max_write_coalescing_window
+                .unwrap_or_else(default_max_write_coalescing_window),
+            args.snapshot_install_reads.unwrap_or_default(),
+            args.key_value_encoding.unwrap_or_default(),
+            args.min_healthy_voters,
+            args.leaderless_reads.unwrap_or_default(),
+            args.leaderless_read_timeout
+                .unwrap_or_else(default_leaderless_read_timeout),
+            args.tracked_prefixes,
+            args.guarded_prefixes,
+            args.lease_guarded_prefixes,
+            args.track_last_access,
+            args.bulk_load_threshold,
+            args.report_apply_latency,
+            args.max_request_order_wait
+                .unwrap_or_else(default_max_request_order_wait),
+            args.max_range_result_count,
+            args.range_result_overflow.unwrap_or_default(),
+            args.key_charset.unwrap_or_default(),
+            args.key_charset_prefixes,
+            args.max_inflight_proposals,
+            args.immutable_prefixes,
+            args.range_stream_batch_size,
+        );
+        XlineServerConfig::new(
+            cluster, storage, log, trace, auth, compact, tls, metrics, watch, kv,
+        )
     }
 }
 
@@ -4,7 +4,11 @@ use opentelemetry_sdk::runtime::Tokio;
 use tracing::warn;
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{fmt::format, layer::SubscriberExt, util::SubscriberInitExt, Layer};
-use utils::config::{file_appender, LogConfig, RotationConfig, TraceConfig};
+use utils::{
+    config::{file_appender, LogConfig, RotationConfig, TraceConfig},
+    redaction::RedactionPolicy,
+    request_log::RequestLogSampler,
+};
 
 /// Return a Box trait from the config
 fn generate_writer(name: &str, log_config: &LogConfig) -> Box<dyn std::io::Write + Send> {
@@ -27,6 +31,17 @@ pub fn init_subscriber(
     log_config: &LogConfig,
     trace_config: &TraceConfig,
 ) -> Result<Option<WorkerGuard>> {
+    // installed before anything is logged, it also applies to the error messages
+    let policy = RedactionPolicy::new(
+        *log_config.redact_values(),
+        log_config.redacted_key_prefixes(),
+    );
+    if !policy.install() {
+        warn!("a redaction policy has already been installed, the log config is ignored");
+    }
+    if !RequestLogSampler::from_config(log_config).install() {
+        warn!("a request log sampler has already been installed, the log config is ignored");
+    }
     let jaeger_level = *trace_config.jaeger_level();
     let jaeger_online_layer = trace_config
         .jaeger_online()
@@ -1,10 +1,13 @@
-use std::{error::Error, iter, path::PathBuf};
+use std::{collections::HashMap, error::Error, iter, path::PathBuf, sync::Arc, time::Duration};
 
 use test_macros::abort_on_panic;
 use utils::config::{
-    AuthConfig, ClusterConfig, CompactConfig, LogConfig, MetricsConfig, StorageConfig, TlsConfig,
-    TraceConfig, XlineServerConfig,
+    default_compact_batch_size, default_compact_sleep_interval, default_password_hash_rounds,
+    default_protected_retention, default_token_cache_size, default_watch_safety_margin, AuthConfig,
+    ClusterConfig, CompactConfig, KvConfig, LogConfig, MetricsConfig, RoleQuota, StorageConfig,
+    TlsConfig, TraceConfig, WatchConfig, XlineServerConfig,
 };
+use xline::server::AuthBackend;
 use xline_test_utils::{
     enable_auth, set_user,
     types::{
@@ -13,6 +16,10 @@ use xline_test_utils::{
     },
     Client, ClientOptions, Cluster,
 };
+use xlineapi::admin::{
+    AdminClient, KeyHistogramRequest, PasswordHashRoundsCount, PasswordHashRoundsRequest,
+    ResetUserStatsRequest, UserRequestStats, UserStatsRequest,
+};
 
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
@@ -225,7 +232,229 @@ async fn test_auth_wrong_password() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_role_at_its_key_quota_should_be_rejected() -> Result<(), Box<dyn Error>> {
+    let quotas = HashMap::from([("r1".to_owned(), RoleQuota::new(1, 0, 0))]);
+    let mut cluster = Cluster::new_with_configs(configs_with_auth_and_quotas(3, quotas)).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    set_user(client, "u1", "123", "r1", b"foo", b"fop").await?;
+    set_user(client, "u2", "123", "r2", b"foo", b"fop").await?;
+    enable_auth(client).await?;
+
+    let u1_client = Client::connect(
+        vec![cluster.get_client_url(0)],
+        ClientOptions::default().with_user("u1", "123"),
+    )
+    .await?
+    .kv_client();
+    let u2_client = Client::connect(
+        vec![cluster.get_client_url(0)],
+        ClientOptions::default().with_user("u2", "123"),
+    )
+    .await?
+    .kv_client();
+
+    u1_client.put(PutRequest::new("foo1", "bar")).await?;
+    // overwriting the keys at the quota is allowed
+    u1_client.put(PutRequest::new("foo1", "baz")).await?;
+    let result = u1_client.put(PutRequest::new("foo2", "bar")).await;
+    assert!(
+        result.is_err(),
+        "the role at its key quota should be rejected: {result:?}"
+    );
+    u2_client.put(PutRequest::new("foo2", "bar")).await?;
+    u2_client.put(PutRequest::new("foo3", "bar")).await?;
+
+    // the quota is checked when the writes are applied, so every node agrees on them
+    for i in 0..3 {
+        let client = Client::connect(
+            vec![cluster.get_client_url(i)],
+            ClientOptions::default().with_user("u2", "123"),
+        )
+        .await?
+        .kv_client();
+        let mut keys = Vec::new();
+        for _ in 0..10 {
+            let res = client
+                .range(
+                    RangeRequest::new("foo")
+                        .with_range_end("fop")
+                        .with_serializable(true),
+                )
+                .await?;
+            keys = res.kvs.into_iter().map(|kv| kv.key).collect();
+            if keys.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(keys, [b"foo1".to_vec(), b"foo2".to_vec(), b"foo3".to_vec()]);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_password_hashed_at_other_rounds_should_be_rehashed_on_login(
+) -> Result<(), Box<dyn Error>> {
+    let configs = configs_with_auth_and_password_hash(3, HashMap::new(), 1000, true);
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    // the client hashes the passwords at the default rounds
+    set_user(client, "u", "123", "r", b"foo", &[]).await?;
+    enable_auth(client).await?;
+    let connect = |user: &'static str| {
+        Client::connect(
+            vec![cluster.get_client_url(0)],
+            ClientOptions::default().with_user(user, "123"),
+        )
+    };
+    let auth_revision = |client: Client| async move {
+        let status = client.auth_client().auth_status().await?;
+        Result::<_, Box<dyn Error>>::Ok(status.auth_revision)
+    };
+
+    let revision = auth_revision(connect("root").await?).await?;
+    let url = cluster.get_client_url(0);
+    let mut auth_client = xlineapi::AuthClient::connect(url.clone()).await?;
+    let mut tokens = HashMap::new();
+    for user in ["root", "u"] {
+        let res = auth_client
+            .authenticate(xlineapi::AuthenticateRequest {
+                name: user.to_owned(),
+                password: "123".to_owned(),
+            })
+            .await?;
+        let _prev = tokens.insert(user, res.into_inner().token);
+    }
+    let mut admin_client = AdminClient::connect(url).await?;
+    let err = admin_client
+        .password_hash_rounds(with_token(
+            &tokens["u"],
+            tonic::Request::new(PasswordHashRoundsRequest {}),
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    let user_client = connect("u").await?;
+    // the token issued after the re-hash is valid
+    user_client
+        .kv_client()
+        .put(PutRequest::new("foo", "bar"))
+        .await?;
+    // the re-hash keeps the auth revision, so the token of root issued before the re-hash
+    // of the password of u is still valid
+    assert_eq!(auth_revision(connect("root").await?).await?, revision);
+    let mut kv_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let _res = kv_client
+        .put(with_token(
+            &tokens["root"],
+            tonic::Request::new(xlineapi::PutRequest {
+                key: b"foo".to_vec(),
+                value: b"baz".to_vec(),
+                ..Default::default()
+            }),
+        ))
+        .await?;
+    // the password is re-hashed only once
+    let _user_client = connect("u").await?;
+    assert_eq!(auth_revision(connect("root").await?).await?, revision);
+    let res = admin_client
+        .password_hash_rounds(with_token(
+            &tokens["root"],
+            tonic::Request::new(PasswordHashRoundsRequest {}),
+        ))
+        .await?
+        .into_inner();
+    assert_eq!(
+        res.counts,
+        vec![PasswordHashRoundsCount {
+            rounds: 1000,
+            users: 2
+        }]
+    );
+
+    Ok(())
+}
+
+/// An external auth backend knowing other passwords than the ones kept by Xline
+#[derive(Debug)]
+struct MockAuthBackend;
+
+#[async_trait::async_trait]
+impl AuthBackend for MockAuthBackend {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<(), String> {
+        match (username, password) {
+            ("root" | "u" | "ghost", "external") => Ok(()),
+            _ => Err(format!("unknown credential of {username}")),
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_authentication_should_be_delegated_to_the_auth_backend() -> Result<(), Box<dyn Error>>
+{
+    let mut cluster = Cluster::new_with_configs(configs_with_auth(3)).await;
+    for i in 0..3 {
+        cluster.set_auth_backend(i, Arc::new(MockAuthBackend), Duration::from_secs(60));
+    }
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    set_user(client, "u", "123", "r", b"foo", &[]).await?;
+    enable_auth(client).await?;
+    let connect = |user: &'static str, password: &'static str| {
+        Client::connect(
+            vec![cluster.get_client_url(0)],
+            ClientOptions::default().with_user(user, password),
+        )
+    };
+
+    // the roles of a delegated user are still kept by Xline
+    let user_client = connect("u", "external").await?.kv_client();
+    user_client.put(PutRequest::new("foo", "bar")).await?;
+    assert!(user_client
+        .put(PutRequest::new("bar", "foo"))
+        .await
+        .is_err());
+    let _root_client = connect("root", "external").await?;
+
+    // the local passwords and the users unknown to Xline fail the authentication
+    for (user, password) in [("u", "123"), ("root", "123"), ("ghost", "external")] {
+        let err = connect(user, password).await.unwrap_err();
+        assert!(
+            err.to_string().contains("authentication failed"),
+            "{user}: {err}"
+        );
+    }
+
+    Ok(())
+}
+
 fn configs_with_auth(size: usize) -> Vec<XlineServerConfig> {
+    configs_with_auth_and_quotas(size, HashMap::new())
+}
+
+fn configs_with_auth_and_quotas(
+    size: usize,
+    role_quotas: HashMap<String, RoleQuota>,
+) -> Vec<XlineServerConfig> {
+    configs_with_auth_and_password_hash(size, role_quotas, default_password_hash_rounds(), false)
+}
+
+fn configs_with_auth_and_password_hash(
+    size: usize,
+    role_quotas: HashMap<String, RoleQuota>,
+    password_hash_rounds: u32,
+    rehash_passwords_on_login: bool,
+) -> Vec<XlineServerConfig> {
     iter::repeat_with(|| {
         (
             Some(PathBuf::from("../../fixtures/public.pem")),
@@ -238,12 +467,454 @@ fn configs_with_auth(size: usize) -> Vec<XlineServerConfig> {
             StorageConfig::default(),
             LogConfig::default(),
             TraceConfig::default(),
-            AuthConfig::new(auth_public_key, auth_private_key),
+            AuthConfig::new(
+                auth_public_key,
+                auth_private_key,
+                default_token_cache_size(),
+                role_quotas.clone(),
+                password_hash_rounds,
+                rehash_passwords_on_login,
+                0,
+                0,
+            ),
             CompactConfig::default(),
             TlsConfig::default(),
             MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::default(),
         )
     })
     .take(size)
     .collect()
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_grants_beyond_the_limits_should_be_rejected() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::new(
+                None,
+                None,
+                default_token_cache_size(),
+                HashMap::new(),
+                default_password_hash_rounds(),
+                false,
+                2,
+                3,
+            ),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::default(),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let mut client = xlineapi::AuthClient::connect(cluster.get_client_url(0)).await?;
+    let _ignore = client
+        .user_add(xlineapi::AuthUserAddRequest {
+            name: "u".to_owned(),
+            password: "123".to_owned(),
+            ..Default::default()
+        })
+        .await?;
+    for role in ["r1", "r2", "r3"] {
+        let _ignore = client
+            .role_add(xlineapi::AuthRoleAddRequest {
+                name: role.to_owned(),
+            })
+            .await?;
+    }
+    let grant_role = |role: &str| xlineapi::AuthUserGrantRoleRequest {
+        user: "u".to_owned(),
+        role: role.to_owned(),
+    };
+    let grant_permission = |key: &str| xlineapi::AuthRoleGrantPermissionRequest {
+        name: "r1".to_owned(),
+        perm: Some(xlineapi::Permission {
+            perm_type: xlineapi::Type::Read.into(),
+            key: key.into(),
+            range_end: vec![],
+        }),
+    };
+
+    for role in ["r1", "r2"] {
+        let _ignore = client.user_grant_role(grant_role(role)).await?;
+    }
+    let status = client.user_grant_role(grant_role("r3")).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    assert!(status.message().contains("max number of 2 roles"));
+
+    for key in ["a", "b", "c"] {
+        let _ignore = client.role_grant_permission(grant_permission(key)).await?;
+    }
+    let status = client
+        .role_grant_permission(grant_permission("d"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    assert!(status.message().contains("max number of 3 permissions"));
+    // a permission of a granted range is updated within the limit
+    let _ignore = client.role_grant_permission(grant_permission("a")).await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_user_stats_should_be_read_and_reset_by_admins() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new_with_configs(configs_with_auth(3)).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    set_user(client, "u1", "123", "r1", b"u1/", b"u10").await?;
+    set_user(client, "u2", "123", "r2", b"u2/", b"u20").await?;
+    enable_auth(client).await?;
+
+    // the statistics are kept by the node serving the requests
+    let url = cluster.get_client_url(0);
+    let mut auth_client = xlineapi::AuthClient::connect(url.clone()).await?;
+    let mut tokens = HashMap::new();
+    for user in ["root", "u1", "u2"] {
+        let res = auth_client
+            .authenticate(xlineapi::AuthenticateRequest {
+                name: user.to_owned(),
+                password: "123".to_owned(),
+            })
+            .await?;
+        let _prev = tokens.insert(user, res.into_inner().token);
+    }
+    let mut kv_client = xlineapi::KvClient::connect(url.clone()).await?;
+    let put = |key: &str, value: &str| {
+        tonic::Request::new(xlineapi::PutRequest {
+            key: key.into(),
+            value: value.into(),
+            ..Default::default()
+        })
+    };
+    let range = |key: &str| {
+        tonic::Request::new(xlineapi::RangeRequest {
+            key: key.into(),
+            ..Default::default()
+        })
+    };
+    let _ignore = kv_client
+        .put(with_token(&tokens["u1"], put("u1/a", "hello")))
+        .await?;
+    let _ignore = kv_client
+        .range(with_token(&tokens["u1"], range("u1/a")))
+        .await?;
+    assert!(kv_client
+        .range(with_token(&tokens["u1"], range("u2/a")))
+        .await
+        .is_err());
+    let _ignore = kv_client
+        .put(with_token(&tokens["u2"], put("u2/a", "v")))
+        .await?;
+    let _ignore = kv_client
+        .put(with_token(&tokens["u2"], put("u2/b", "vv")))
+        .await?;
+
+    let mut admin_client = AdminClient::connect(url).await?;
+    let read = |user: &str| {
+        with_token(
+            &tokens[user],
+            tonic::Request::new(UserStatsRequest {
+                limit: 1,
+                start_user: String::new(),
+            }),
+        )
+    };
+    let reset = |user: &str, reset: &str| {
+        with_token(
+            &tokens[user],
+            tonic::Request::new(ResetUserStatsRequest {
+                user: reset.to_owned(),
+                all: false,
+            }),
+        )
+    };
+    let stats = |user: &str, requests, bytes_read, bytes_written, errors| UserRequestStats {
+        user: user.to_owned(),
+        requests,
+        bytes_read,
+        bytes_written,
+        errors,
+    };
+
+    // only an admin can read or reset the statistics
+    let err = admin_client.user_stats(read("u1")).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    let err = admin_client
+        .reset_user_stats(reset("u1", "u1"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+
+    // the statistics are listed by pages of a single user
+    let mut users = Vec::new();
+    let mut request = read("root");
+    loop {
+        let page = admin_client.user_stats(request).await?.into_inner();
+        assert_eq!(page.users.len(), 1);
+        users.extend(page.users);
+        if page.next_user.is_empty() {
+            break;
+        }
+        request = read("root");
+        request.get_mut().start_user = page.next_user;
+    }
+    assert_eq!(users, [stats("u1", 3, 9, 9, 1), stats("u2", 2, 0, 11, 0)]);
+
+    // the reset returns the statistics before it, and only resets the user of it
+    let res = admin_client
+        .reset_user_stats(reset("root", "u1"))
+        .await?
+        .into_inner();
+    assert_eq!(res.stats, Some(stats("u1", 3, 9, 9, 1)));
+    assert_eq!(res.reset, 1);
+    let mut all = read("root");
+    all.get_mut().limit = 0;
+    let page = admin_client.user_stats(all).await?.into_inner();
+    assert_eq!(page.users, [stats("u2", 2, 0, 11, 0)]);
+
+    let _ignore = kv_client
+        .range(with_token(&tokens["u1"], range("u1/a")))
+        .await?;
+    let mut all = read("root");
+    all.get_mut().limit = 0;
+    let page = admin_client.user_stats(all).await?.into_inner();
+    assert_eq!(
+        page.users,
+        [stats("u1", 1, 9, 0, 0), stats("u2", 2, 0, 11, 0)]
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_change_history_should_be_read_by_admins() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::new(
+                Some(PathBuf::from("../../fixtures/public.pem")),
+                Some(PathBuf::from("../../fixtures/private.pem")),
+                default_token_cache_size(),
+                HashMap::new(),
+                default_password_hash_rounds(),
+                false,
+                0,
+                0,
+            ),
+            CompactConfig::new(
+                default_compact_batch_size(),
+                default_compact_sleep_interval(),
+                None,
+                100,
+                100,
+                Vec::new(),
+                default_protected_retention(),
+                false,
+                default_watch_safety_margin(),
+            ),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::default(),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    set_user(client, "u1", "123", "r1", b"u1/", b"u10").await?;
+    enable_auth(client).await?;
+
+    let url = cluster.get_client_url(0);
+    let mut auth_client = xlineapi::AuthClient::connect(url.clone()).await?;
+    let mut tokens = HashMap::new();
+    for user in ["root", "u1"] {
+        let res = auth_client
+            .authenticate(xlineapi::AuthenticateRequest {
+                name: user.to_owned(),
+                password: "123".to_owned(),
+            })
+            .await?;
+        let _prev = tokens.insert(user, res.into_inner().token);
+    }
+    let mut maintenance_client = xlineapi::MaintenanceClient::connect(url).await?;
+    let status = |user: &str, history: &'static str| {
+        let mut request = with_token(
+            &tokens[user],
+            tonic::Request::new(xlineapi::StatusRequest::default()),
+        );
+        let _prev = request.metadata_mut().insert(
+            "change-history",
+            tonic::metadata::MetadataValue::from_static(history),
+        );
+        request
+    };
+
+    assert!(maintenance_client
+        .status(status("u1", "auth"))
+        .await
+        .is_err());
+    assert!(maintenance_client
+        .status(status("root", "kv"))
+        .await
+        .is_err());
+    let res = maintenance_client.status(status("root", "auth")).await?;
+    let report: serde_json::Value = serde_json::from_slice(
+        &res.metadata()
+            .get_bin("change-history-bin")
+            .expect("the history should be carried by the status")
+            .to_bytes()?,
+    )?;
+    assert_eq!(report["compacted_revision"], 0);
+    let changes = report["changes"].as_array().unwrap();
+    let user_add = changes
+        .iter()
+        .find_map(|change| change[1].get("AuthUserAddRequest"))
+        .expect("the user add should be in the history");
+    assert_eq!(user_add["name"], "u1");
+    assert_eq!(user_add["hashed_password"], "");
+    assert!(changes.last().unwrap()[1]
+        .get("AuthEnableRequest")
+        .is_some());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_key_histogram_should_be_read_by_admins() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new_with_configs(configs_with_auth(3)).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    for i in 0..300 {
+        let _res = client
+            .kv_client()
+            .put(PutRequest::new(format!("a{i:03}"), "v"))
+            .await?;
+    }
+    for i in 0..100 {
+        let _res = client
+            .kv_client()
+            .put(PutRequest::new(format!("b{i:03}"), "v"))
+            .await?;
+    }
+    set_user(client, "u1", "123", "r1", b"a", b"c").await?;
+    enable_auth(client).await?;
+
+    let url = cluster.get_client_url(0);
+    let mut auth_client = xlineapi::AuthClient::connect(url.clone()).await?;
+    let mut tokens = HashMap::new();
+    for user in ["root", "u1"] {
+        let res = auth_client
+            .authenticate(xlineapi::AuthenticateRequest {
+                name: user.to_owned(),
+                password: "123".to_owned(),
+            })
+            .await?;
+        let _prev = tokens.insert(user, res.into_inner().token);
+    }
+    let mut admin_client = AdminClient::connect(url).await?;
+    let histogram = |user: &str| {
+        with_token(
+            &tokens[user],
+            tonic::Request::new(KeyHistogramRequest {
+                key: vec![0],
+                range_end: vec![0],
+                buckets: 4,
+            }),
+        )
+    };
+
+    let err = admin_client
+        .key_histogram(histogram("u1"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    let res = admin_client
+        .key_histogram(histogram("root"))
+        .await?
+        .into_inner();
+    assert!(res.next_key.is_empty());
+    assert!(res.buckets.len() <= 4);
+    // the histogram is exact for a small keyspace, and follows its skew
+    assert_eq!(res.buckets.iter().map(|b| b.count).sum::<u64>(), 400);
+    assert!(
+        res.buckets
+            .iter()
+            .filter(|b| b.start >= b"b".to_vec())
+            .count()
+            <= 1
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_defragment_should_be_done_by_admins() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new_with_configs(configs_with_auth(3)).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    set_user(client, "u1", "123", "r1", b"a", b"c").await?;
+    enable_auth(client).await?;
+
+    let url = cluster.get_client_url(0);
+    let mut auth_client = xlineapi::AuthClient::connect(url.clone()).await?;
+    let mut tokens = HashMap::new();
+    for user in ["root", "u1"] {
+        let res = auth_client
+            .authenticate(xlineapi::AuthenticateRequest {
+                name: user.to_owned(),
+                password: "123".to_owned(),
+            })
+            .await?;
+        let _prev = tokens.insert(user, res.into_inner().token);
+    }
+    let mut maintenance_client = xlineapi::MaintenanceClient::connect(url).await?;
+    let defragment = |user: &str| {
+        with_token(
+            &tokens[user],
+            tonic::Request::new(xlineapi::DefragmentRequest {}),
+        )
+    };
+
+    let err = maintenance_client
+        .defragment(tonic::Request::new(xlineapi::DefragmentRequest {}))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    let err = maintenance_client
+        .defragment(defragment("u1"))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::PermissionDenied);
+    let _res = maintenance_client.defragment(defragment("root")).await?;
+
+    Ok(())
+}
+
+/// Attach the token of a user to a request
+fn with_token<T>(token: &str, mut request: tonic::Request<T>) -> tonic::Request<T> {
+    let _prev = request
+        .metadata_mut()
+        .insert("token", token.parse().unwrap());
+    request
+}
@@ -1,15 +1,29 @@
-use std::{error::Error, time::Duration};
+use std::{collections::HashMap, error::Error, iter, path::PathBuf, time::Duration};
 
+use curp::rpc::{protocol_client::ProtocolClient, PbProposeId, ProposeRequest};
 use test_macros::abort_on_panic;
-use tokio::{net::TcpListener, time::sleep};
+use tokio::{
+    net::TcpListener,
+    time::{sleep, timeout},
+};
+use utils::config::{
+    default_leaderless_read_timeout, default_password_hash_rounds, default_range_stream_batch_size,
+    default_token_cache_size, AuthConfig, ClientConfig, ClusterConfig, CompactConfig, CurpConfig,
+    InitialClusterState, KeyCharset, KeyValueEncoding, KvConfig, LeaderlessReads, LogConfig,
+    MessageSizeConfig, MetricsConfig, RangeResultOverflow, ServerTimeout, SnapshotInstallReads,
+    StorageConfig, TlsConfig, TraceConfig, WatchConfig, XlineServerConfig,
+};
 use xline_client::{
     types::{
         cluster::{MemberAddRequest, MemberListRequest, MemberRemoveRequest, MemberUpdateRequest},
-        kv::PutRequest,
+        kv::{PutRequest, RangeRequest},
     },
     Client, ClientOptions,
 };
-use xline_test_utils::Cluster;
+use xline_test_utils::{enable_auth, Cluster};
+use xlineapi::member_watch::{
+    MemberUpdateType, MemberWatchClient, MemberWatchRequest, MemberWatchResponse,
+};
 
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
@@ -101,3 +115,570 @@ async fn xline_update_node() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn xline_message_size_limits_should_differ_between_client_and_peer(
+) -> Result<(), Box<dyn Error>> {
+    let message_size = MessageSizeConfig::new(0, 1024 * 1024, 0, 8 * 1024 * 1024);
+    let cluster_config = ClusterConfig::new(
+        "default".to_owned(),
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        HashMap::new(),
+        false,
+        CurpConfig::default(),
+        ClientConfig::default(),
+        ServerTimeout::default(),
+        InitialClusterState::New,
+        message_size,
+        HashMap::new(),
+        vec![],
+        vec![],
+    );
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            cluster_config.clone(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::default(),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let large_value = vec![0; 2 * 1024 * 1024];
+
+    let mut kv_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let err = kv_client
+        .put(xlineapi::PutRequest {
+            key: b"foo".to_vec(),
+            value: large_value.clone(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::OutOfRange);
+
+    let mut peer_client = ProtocolClient::connect(cluster.get_peer_url(0)).await?;
+    // the wrong cluster version makes the server reject the propose after the
+    // message is received, so that nothing is executed
+    let res = peer_client
+        .propose(ProposeRequest {
+            propose_id: Some(PbProposeId {
+                client_id: 1,
+                seq_num: 1,
+            }),
+            command: large_value,
+            cluster_version: u64::MAX,
+        })
+        .await;
+    if let Err(status) = res {
+        assert_ne!(status.code(), tonic::Code::OutOfRange);
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn xline_forwarded_writes_should_be_bounded_by_client_message_size(
+) -> Result<(), Box<dyn Error>> {
+    let message_size = MessageSizeConfig::new(1024 * 1024, 4 * 1024 * 1024, 0, 4 * 1024 * 1024);
+    let cluster_config = ClusterConfig::new(
+        "default".to_owned(),
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        HashMap::new(),
+        false,
+        CurpConfig::default(),
+        ClientConfig::default(),
+        ServerTimeout::default(),
+        InitialClusterState::New,
+        message_size,
+        HashMap::new(),
+        vec![],
+        vec![],
+    );
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            cluster_config.clone(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                true,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    // wait for the leader to be elected and known by the follower
+    let _res = cluster
+        .client()
+        .await
+        .kv_client()
+        .put(PutRequest::new("foo", "bar"))
+        .await?;
+    let put = xlineapi::PutRequest {
+        key: b"foo".to_vec(),
+        value: vec![0; 2 * 1024 * 1024],
+        ..Default::default()
+    };
+
+    // the follower receives the write but can't forward it to the leader
+    let mut follower_client = xlineapi::KvClient::connect(cluster.get_client_url(1)).await?;
+    let err = follower_client.put(put.clone()).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::OutOfRange);
+
+    let mut leader_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let _res = leader_client.put(put).await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn xline_verified_promotion_of_a_matching_learner_should_succeed(
+) -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = Client::connect(cluster.all_client_addrs(), ClientOptions::default()).await?;
+    let mut cluster_client = client.cluster_client();
+    let kv_client = client.kv_client();
+    for i in 0..10 {
+        _ = kv_client
+            .put(PutRequest::new(format!("key{i}"), "value"))
+            .await?;
+    }
+    let learner_peer_listener = TcpListener::bind("0.0.0.0:0").await?;
+    let learner_peer_urls = vec![format!("http://{}", learner_peer_listener.local_addr()?)];
+    let learner_client_listener = TcpListener::bind("0.0.0.0:0").await?;
+    let add_res = cluster_client
+        .member_add(MemberAddRequest::new(learner_peer_urls, true))
+        .await?;
+    let learner_id = add_res.member.unwrap().id;
+    cluster
+        .run_node(learner_client_listener, learner_peer_listener)
+        .await;
+    // wait for the learner to publish its client urls
+    sleep(Duration::from_secs(3)).await;
+
+    let mut raw_client = xlineapi::ClusterClient::connect(cluster.get_client_url(0)).await?;
+    let mut request = tonic::Request::new(xlineapi::MemberPromoteRequest { id: learner_id });
+    let _ignore = request
+        .metadata_mut()
+        .insert("verify-promotion", "true".parse().unwrap());
+    let res = raw_client.member_promote(request).await?.into_inner();
+    assert!(res
+        .members
+        .iter()
+        .any(|m| m.id == learner_id && !m.is_learner));
+
+    Ok(())
+}
+
+/// Build the config of a member tagged by its index
+fn tagged_config(i: usize, auth_config: AuthConfig) -> XlineServerConfig {
+    let cluster_config = ClusterConfig::new(
+        "default".to_owned(),
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        HashMap::new(),
+        false,
+        CurpConfig::default(),
+        ClientConfig::default(),
+        ServerTimeout::default(),
+        InitialClusterState::New,
+        MessageSizeConfig::default(),
+        HashMap::from([
+            ("region".to_owned(), "us-east-1".to_owned()),
+            ("zone".to_owned(), format!("zone{i}")),
+        ]),
+        vec![],
+        vec![],
+    );
+    XlineServerConfig::new(
+        cluster_config,
+        StorageConfig::default(),
+        LogConfig::default(),
+        TraceConfig::default(),
+        auth_config,
+        CompactConfig::default(),
+        TlsConfig::default(),
+        MetricsConfig::default(),
+        WatchConfig::default(),
+        KvConfig::default(),
+    )
+}
+
+/// List the members and their tags by a member until the tags of `count` members
+/// are published
+async fn list_tagged_members(
+    url: String,
+    count: usize,
+) -> Result<(Vec<xlineapi::Member>, HashMap<u64, HashMap<String, String>>), Box<dyn Error>> {
+    let mut client = xlineapi::ClusterClient::connect(url).await?;
+    // the tags are published after the members start
+    let mut attempts = 0;
+    loop {
+        let res = client
+            .member_list(xlineapi::MemberListRequest {
+                linearizable: false,
+            })
+            .await?;
+        let tags: HashMap<u64, HashMap<String, String>> = res
+            .metadata()
+            .get_bin("member-tags-bin")
+            .map(|value| serde_json::from_slice(&value.to_bytes().unwrap()).unwrap())
+            .unwrap_or_default();
+        if tags.len() == count || attempts == 50 {
+            return Ok((res.into_inner().members, tags));
+        }
+        attempts += 1;
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn xline_member_list_should_return_the_member_tags() -> Result<(), Box<dyn Error>> {
+    let configs = (0..3)
+        .map(|i| tagged_config(i, AuthConfig::default()))
+        .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+
+    for node in 0..3 {
+        let (members, tags) = list_tagged_members(cluster.get_client_url(node), 3).await?;
+        assert_eq!(tags.len(), 3);
+        for member in members {
+            let i = member.name.strip_prefix("server").unwrap();
+            let member_tags = &tags[&member.id];
+            assert_eq!(member_tags["region"], "us-east-1");
+            assert_eq!(member_tags["zone"], format!("zone{i}"));
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn xline_member_tags_should_be_published_with_auth_enabled() -> Result<(), Box<dyn Error>> {
+    let auth_config = || {
+        AuthConfig::new(
+            Some(PathBuf::from("../../fixtures/public.pem")),
+            Some(PathBuf::from("../../fixtures/private.pem")),
+            default_token_cache_size(),
+            HashMap::new(),
+            default_password_hash_rounds(),
+            false,
+            0,
+            0,
+        )
+    };
+    let configs = (0..3).map(|i| tagged_config(i, auth_config())).collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    enable_auth(cluster.client().await).await?;
+    let client = Client::connect(
+        cluster.all_client_addrs(),
+        ClientOptions::default().with_user("root", "123"),
+    )
+    .await?;
+
+    // the new member publishes its tags after the auth is enabled
+    let peer_listener = TcpListener::bind("0.0.0.0:0").await?;
+    let peer_urls = vec![format!("http://{}", peer_listener.local_addr()?)];
+    let client_listener = TcpListener::bind("0.0.0.0:0").await?;
+    let add_res = client
+        .cluster_client()
+        .member_add(MemberAddRequest::new(peer_urls, false))
+        .await?;
+    assert_eq!(add_res.members.len(), 4);
+    cluster
+        .run_node_with_config(
+            client_listener,
+            peer_listener,
+            tagged_config(3, auth_config()),
+        )
+        .await;
+
+    let (members, tags) = list_tagged_members(cluster.get_client_url(0), 4).await?;
+    assert_eq!(tags.len(), 4);
+    for member in members {
+        let i = member.name.strip_prefix("server").unwrap();
+        assert_eq!(tags[&member.id]["zone"], format!("zone{i}"));
+    }
+    // the tags are kept out of the keyspace and take no revision
+    let res = client
+        .kv_client()
+        .range(RangeRequest::new(vec![0]).with_from_key())
+        .await?;
+    assert!(res.kvs.is_empty());
+    assert_eq!(res.header.unwrap().revision, 1);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn xline_member_progress_should_reach_the_revisions_seen_by_all_members(
+) -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = Client::connect(cluster.all_client_addrs(), ClientOptions::default()).await?;
+    let mut revision = 0;
+    for i in 0..10 {
+        revision = client
+            .kv_client()
+            .put(PutRequest::new(format!("key{i}"), "value"))
+            .await?
+            .header
+            .unwrap()
+            .revision;
+    }
+
+    for node in 0..3 {
+        let mut raw_client = xlineapi::ClusterClient::connect(cluster.get_client_url(node)).await?;
+        let mut attempts = 0;
+        let progress = loop {
+            let mut request = tonic::Request::new(xlineapi::MemberListRequest {
+                linearizable: false,
+            });
+            let _ignore = request
+                .metadata_mut()
+                .insert("member-progress", "true".parse().unwrap());
+            let res = raw_client.member_list(request).await?;
+            let progress: HashMap<u64, serde_json::Value> = serde_json::from_slice(
+                &res.metadata()
+                    .get_bin("member-progress-bin")
+                    .unwrap()
+                    .to_bytes()
+                    .unwrap(),
+            )?;
+            let all_applied = progress.values().all(|p| {
+                p["applied_revision"]
+                    .as_i64()
+                    .is_some_and(|r| r >= revision)
+            });
+            if all_applied || attempts == 50 {
+                break progress;
+            }
+            attempts += 1;
+            sleep(Duration::from_millis(100)).await;
+        };
+        assert_eq!(progress.len(), 3);
+        // the members read from the leader's view, even if it is served by a follower
+        for member in progress.values() {
+            assert!(member["match_index"].as_u64().is_some());
+            assert!(member["applied_index"].as_u64().is_some());
+        }
+        let min_revision = progress
+            .values()
+            .filter_map(|p| p["applied_revision"].as_i64())
+            .min()
+            .unwrap();
+        assert!(min_revision >= revision);
+
+        // every member can serve the revision all of them have reached
+        for i in 0..3 {
+            let mut kv_client = xlineapi::KvClient::connect(cluster.get_client_url(i)).await?;
+            let res = kv_client
+                .range(xlineapi::RangeRequest {
+                    key: b"key9".to_vec(),
+                    revision,
+                    serializable: true,
+                    ..Default::default()
+                })
+                .await?
+                .into_inner();
+            assert_eq!(res.kvs.len(), 1);
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn xline_read_and_write_listeners_should_only_serve_their_requests(
+) -> Result<(), Box<dyn Error>> {
+    let mut role_addrs = Vec::new();
+    for _ in 0..2 {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        role_addrs.push(listener.local_addr()?.to_string());
+    }
+    let (read_addr, write_addr) = (role_addrs[0].clone(), role_addrs[1].clone());
+    let configs = (0..3)
+        .map(|i| {
+            let (read_listen_urls, write_listen_urls) = if i == 0 {
+                (vec![read_addr.clone()], vec![write_addr.clone()])
+            } else {
+                (vec![], vec![])
+            };
+            let cluster_config = ClusterConfig::new(
+                "default".to_owned(),
+                vec![],
+                vec![],
+                vec![],
+                vec![],
+                HashMap::new(),
+                false,
+                CurpConfig::default(),
+                ClientConfig::default(),
+                ServerTimeout::default(),
+                InitialClusterState::New,
+                MessageSizeConfig::default(),
+                HashMap::new(),
+                read_listen_urls,
+                write_listen_urls,
+            );
+            XlineServerConfig::new(
+                cluster_config,
+                StorageConfig::default(),
+                LogConfig::default(),
+                TraceConfig::default(),
+                AuthConfig::default(),
+                CompactConfig::default(),
+                TlsConfig::default(),
+                MetricsConfig::default(),
+                WatchConfig::default(),
+                KvConfig::default(),
+            )
+        })
+        .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let put = xlineapi::PutRequest {
+        key: b"key".to_vec(),
+        value: b"value".to_vec(),
+        ..Default::default()
+    };
+    let range = xlineapi::RangeRequest {
+        key: b"key".to_vec(),
+        ..Default::default()
+    };
+
+    let mut read_client = xlineapi::KvClient::connect(format!("http://{read_addr}")).await?;
+    let status = read_client.put(put.clone()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    let _res = read_client.range(range.clone()).await?;
+
+    let mut write_client = xlineapi::KvClient::connect(format!("http://{write_addr}")).await?;
+    let _res = write_client.put(put).await?;
+    let status = write_client.range(range.clone()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let res = client.range(range).await?.into_inner();
+    assert_eq!(res.kvs.len(), 1);
+
+    Ok(())
+}
+
+/// Get the next member update of the type, the members may publish their names and
+/// client urls in the meantime
+async fn next_member_update(
+    updates: &mut tonic::Streaming<MemberWatchResponse>,
+    expected: MemberUpdateType,
+) -> Result<MemberWatchResponse, tonic::Status> {
+    loop {
+        let update = updates.message().await?.unwrap();
+        if update.r#type() == expected {
+            return Ok(update);
+        }
+        assert_eq!(update.r#type(), MemberUpdateType::Update);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn xline_member_watch_should_yield_the_added_and_removed_members(
+) -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let mut cluster_client = cluster.client().await.cluster_client();
+    let mut watch_client = MemberWatchClient::connect(cluster.get_client_url(0)).await?;
+    let mut updates = watch_client
+        .watch(MemberWatchRequest {})
+        .await?
+        .into_inner();
+    let snapshot = updates.message().await?.unwrap();
+    assert_eq!(snapshot.r#type(), MemberUpdateType::Snapshot);
+    assert_eq!(snapshot.members.len(), 3);
+
+    let learner_peer_listener = TcpListener::bind("0.0.0.0:0").await?;
+    let learner_peer_urls = vec![format!("http://{}", learner_peer_listener.local_addr()?)];
+    let add_res = cluster_client
+        .member_add(MemberAddRequest::new(learner_peer_urls.clone(), true))
+        .await?;
+    let learner_id = add_res.member.unwrap().id;
+    let added = timeout(
+        Duration::from_secs(5),
+        next_member_update(&mut updates, MemberUpdateType::Add),
+    )
+    .await??;
+    assert_eq!(added.members.len(), 1);
+    assert_eq!(added.members[0].id, learner_id);
+    assert_eq!(added.members[0].peer_ur_ls, learner_peer_urls);
+    assert!(added.members[0].is_learner);
+
+    let _remove_res = cluster_client
+        .member_remove(MemberRemoveRequest::new(learner_id))
+        .await?;
+    let removed = timeout(
+        Duration::from_secs(5),
+        next_member_update(&mut updates, MemberUpdateType::Remove),
+    )
+    .await??;
+    assert_eq!(removed.members.len(), 1);
+    assert_eq!(removed.members[0].id, learner_id);
+
+    Ok(())
+}
@@ -1,13 +1,30 @@
-use std::{error::Error, time::Duration};
+use std::{collections::HashSet, error::Error, iter, sync::Arc, time::Duration};
 
 use test_macros::abort_on_panic;
+use utils::config::{
+    default_heartbeat_interval, default_leaderless_read_timeout, default_range_stream_batch_size,
+    AuthConfig, ClusterConfig, CompactConfig, KeyCharset, KeyValueEncoding, KvConfig,
+    LeaderlessReads, LogConfig, MetricsConfig, RangeResultOverflow, SnapshotInstallReads,
+    StorageConfig, TlsConfig, TraceConfig, WatchConfig, XlineServerConfig,
+};
+use xline::server::{ArchiveSink, CommandHook, ValueValidator};
+use xline_client::error::XlineClientError;
 use xline_test_utils::{
-    types::kv::{
-        Compare, CompareResult, DeleteRangeRequest, PutRequest, RangeRequest, Response, SortOrder,
-        SortTarget, TxnOp, TxnRequest,
+    types::{
+        kv::{
+            Compare, CompareResult, ConditionalDeleteRequest, DeleteRangeRequest,
+            MovePrefixRequest, PutRequest, RangeRequest, Response, SortOrder, SortTarget, TxnOp,
+            TxnRequest,
+        },
+        lease::LeaseGrantRequest,
     },
     Client, ClientOptions, Cluster,
 };
+use xlineapi::{
+    execute_error::ExecuteError,
+    server_op::{AppendOp, ServerOp, SwapOp},
+    RequestWrapper,
+};
 
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
@@ -330,3 +347,2359 @@ async fn test_txn() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_follower_write_should_return_leader_endpoint() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let leader_ep = cluster.get_client_url(0);
+    let follower_ep = cluster.get_client_url(1);
+    // wait for the leader to be elected and known by the follower
+    let _ = cluster
+        .client()
+        .await
+        .kv_client()
+        .put(PutRequest::new("foo", "bar"))
+        .await?;
+
+    let mut follower_client = xlineapi::KvClient::connect(follower_ep).await?;
+    let res = follower_client
+        .put(xlineapi::PutRequest {
+            key: b"foo".to_vec(),
+            value: b"baz".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+    let endpoint = res
+        .metadata()
+        .get("leader-endpoint")
+        .expect("leader endpoint should be present in the metadata")
+        .to_str()?;
+    assert!(endpoint.split(',').any(|ep| ep == leader_ep));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_follower_write_should_be_forwarded_to_leader() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                true,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let follower_ep = cluster.get_client_url(1);
+    // wait for the leader to be elected and known by the follower
+    let _ = cluster
+        .client()
+        .await
+        .kv_client()
+        .put(PutRequest::new("foo", "bar"))
+        .await?;
+
+    // the client only knows the follower
+    let mut follower_client = xlineapi::KvClient::connect(follower_ep).await?;
+    let res = follower_client
+        .put(xlineapi::PutRequest {
+            key: b"foo".to_vec(),
+            value: b"baz".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+    assert!(
+        res.metadata().get("leader-endpoint").is_none(),
+        "the redirect should be hidden from the client"
+    );
+    let _ignore = follower_client
+        .txn(xlineapi::TxnRequest {
+            compare: vec![],
+            success: vec![xlineapi::RequestOp {
+                request: Some(xlineapi::Request::RequestDeleteRange(
+                    xlineapi::DeleteRangeRequest {
+                        key: b"foo".to_vec(),
+                        ..Default::default()
+                    },
+                )),
+            }],
+            failure: vec![],
+        })
+        .await?;
+    let _ignore = follower_client
+        .put(xlineapi::PutRequest {
+            key: b"foo".to_vec(),
+            value: b"qux".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+
+    let res = follower_client
+        .range(xlineapi::RangeRequest {
+            key: b"foo".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(res.kvs.len(), 1);
+    assert_eq!(res.kvs[0].value, b"qux");
+    assert_eq!(
+        res.kvs[0].version, 1,
+        "the key should be recreated by the txn"
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_empty_value_put_should_be_rejected_in_strict_mode() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                true,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    // the validation is done by the etcd compatible kv service
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+
+    let empty_put = xlineapi::PutRequest {
+        key: b"foo".to_vec(),
+        ..Default::default()
+    };
+    let err = client.put(empty_put.clone()).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    let err = client
+        .txn(xlineapi::TxnRequest {
+            compare: vec![],
+            success: vec![xlineapi::RequestOp {
+                request: Some(xlineapi::Request::RequestPut(empty_put)),
+            }],
+            failure: vec![],
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+    let _ignore = client
+        .put(xlineapi::PutRequest {
+            key: b"foo".to_vec(),
+            value: b"bar".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+    let res = client
+        .range(xlineapi::RangeRequest {
+            key: b"foo".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(res.kvs.len(), 1);
+    assert_eq!(res.kvs[0].value, b"bar");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_empty_value_put_should_be_stored_by_default() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+
+    let _ignore = client
+        .put(xlineapi::PutRequest {
+            key: b"foo".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+    let res = client
+        .range(xlineapi::RangeRequest {
+            key: b"foo".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(res.kvs.len(), 1);
+    assert!(res.kvs[0].value.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_range_over_memory_budget_should_be_rejected() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                16 * 1024,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+
+    for i in 0..64 {
+        let _ignore = client
+            .put(xlineapi::PutRequest {
+                key: format!("key{i:02}").into_bytes(),
+                value: vec![0; 1024],
+                ..Default::default()
+            })
+            .await?;
+    }
+
+    let err = client
+        .range(xlineapi::RangeRequest {
+            key: b"key".to_vec(),
+            range_end: b"kez".to_vec(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+
+    let res = client
+        .range(xlineapi::RangeRequest {
+            key: b"key".to_vec(),
+            range_end: b"kez".to_vec(),
+            limit: 4,
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(res.kvs.len(), 4);
+    assert!(res.more);
+
+    Ok(())
+}
+
+/// Configs of a cluster which coalesces the hinted puts in a window of 100ms
+fn write_coalescing_configs() -> Vec<XlineServerConfig> {
+    iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::from_millis(100),
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_hinted_puts_should_be_coalesced() -> Result<(), Box<dyn Error>> {
+    let configs = write_coalescing_configs();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+
+    let put = |key: String, hinted: bool| {
+        let mut client = client.clone();
+        tokio::spawn(async move {
+            let mut request = tonic::Request::new(xlineapi::PutRequest {
+                key: key.into_bytes(),
+                value: b"value".to_vec(),
+                ..Default::default()
+            });
+            if hinted {
+                let _ignore = request
+                    .metadata_mut()
+                    .insert("write-coalescing-window", "50ms".parse().unwrap());
+            }
+            client.put(request).await.unwrap()
+        })
+    };
+    let bulk: Vec<_> = (0..20).map(|i| put(format!("bulk{i}"), true)).collect();
+    let interactive: Vec<_> = (0..20).map(|i| put(format!("inter{i}"), false)).collect();
+    for handle in bulk.into_iter().chain(interactive) {
+        let _ignore = handle.await?;
+    }
+
+    let revisions = |prefix: &'static str| {
+        let mut client = client.clone();
+        async move {
+            let mut revisions = HashSet::new();
+            for i in 0..20 {
+                let res = client
+                    .range(xlineapi::RangeRequest {
+                        key: format!("{prefix}{i}").into_bytes(),
+                        ..Default::default()
+                    })
+                    .await
+                    .unwrap()
+                    .into_inner();
+                assert_eq!(res.kvs.len(), 1);
+                let _ignore = revisions.insert(res.kvs[0].mod_revision);
+            }
+            revisions.len()
+        }
+    };
+    let interactive_revisions = revisions("inter").await;
+    assert_eq!(interactive_revisions, 20);
+    assert!(revisions("bulk").await < interactive_revisions);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_bad_coalesced_put_should_not_fail_the_others() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new_with_configs(write_coalescing_configs()).await;
+    cluster.start().await;
+    let client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+
+    let put = |key: String, lease: i64| {
+        let mut client = client.clone();
+        tokio::spawn(async move {
+            let mut request = tonic::Request::new(xlineapi::PutRequest {
+                key: key.into_bytes(),
+                value: b"value".to_vec(),
+                lease,
+                ..Default::default()
+            });
+            let _ignore = request
+                .metadata_mut()
+                .insert("write-coalescing-window", "50ms".parse().unwrap());
+            client.put(request).await
+        })
+    };
+    // the lease of the bad put doesn't exist
+    let bad = put("bad".to_owned(), 12345);
+    let good: Vec<_> = (0..10).map(|i| put(format!("good{i}"), 0)).collect();
+    assert!(bad.await?.is_err());
+    for handle in good {
+        let _ignore = handle.await??;
+    }
+
+    let res = client
+        .range(xlineapi::RangeRequest {
+            key: b"good".to_vec(),
+            range_end: b"goodz".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(res.kvs.len(), 10);
+    let res = client
+        .range(xlineapi::RangeRequest {
+            key: b"bad".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert!(res.kvs.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_non_utf8_key_should_be_rejected_in_utf8_mode() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::Utf8Keys,
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut utf8_cluster = Cluster::new_with_configs(configs).await;
+    utf8_cluster.start().await;
+    let mut utf8_client = xlineapi::KvClient::connect(utf8_cluster.get_client_url(0)).await?;
+    let mut raw_cluster = Cluster::new(3).await;
+    raw_cluster.start().await;
+    let mut raw_client = xlineapi::KvClient::connect(raw_cluster.get_client_url(0)).await?;
+
+    let put = || xlineapi::PutRequest {
+        key: vec![b'k', 0xff],
+        value: vec![0xfe],
+        ..Default::default()
+    };
+    let status = utf8_client.put(put()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    let _ignore = utf8_client
+        .put(xlineapi::PutRequest {
+            key: b"k".to_vec(),
+            value: vec![0xfe],
+            ..Default::default()
+        })
+        .await?;
+
+    let _ignore = raw_client.put(put()).await?;
+    let res = raw_client
+        .range(xlineapi::RangeRequest {
+            key: vec![b'k', 0xff],
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(res.kvs.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_keys_with_disallowed_characters_should_be_rejected_on_charset_prefixes(
+) -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::NoControl,
+                vec!["names/".to_owned()],
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let put = |key: &[u8]| xlineapi::PutRequest {
+        key: key.to_vec(),
+        value: b"v".to_vec(),
+        ..Default::default()
+    };
+
+    let status = client.put(put(b"names/a\x07b")).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert!(status.message().contains("key charset"), "{status:?}");
+    let status = client
+        .txn(xlineapi::TxnRequest {
+            success: vec![xlineapi::RequestOp {
+                request: Some(xlineapi::Request::RequestPut(put(b"names/\n"))),
+            }],
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+    let _ignore = client.put(put(b"names/ab")).await?;
+    let _ignore = client.put(put(b"other/a\x07b")).await?;
+    let res = client
+        .range(xlineapi::RangeRequest {
+            key: b"other/a\x07b".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(res.kvs.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_range_token_should_page_a_consistent_snapshot() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let keys: Vec<_> = (0..20).map(|i| format!("key{i:02}").into_bytes()).collect();
+    for key in &keys {
+        let _ignore = client
+            .put(xlineapi::PutRequest {
+                key: key.clone(),
+                value: b"v0".to_vec(),
+                ..Default::default()
+            })
+            .await?;
+    }
+
+    let range = |token: Option<String>| {
+        let mut request = tonic::Request::new(xlineapi::RangeRequest {
+            key: b"key".to_vec(),
+            range_end: b"kez".to_vec(),
+            limit: 6,
+            ..Default::default()
+        });
+        if let Some(token) = token {
+            let _ignore = request
+                .metadata_mut()
+                .insert("range-continuation-token", token.parse().unwrap());
+        }
+        request
+    };
+    let mut paged = Vec::new();
+    let mut first_token = None;
+    let mut token = None;
+    loop {
+        let response = client.range(range(token.take())).await?;
+        let next = response
+            .metadata()
+            .get("range-continuation-token")
+            .map(|value| value.to_str().unwrap().to_owned());
+        let res = response.into_inner();
+        paged.extend(res.kvs.into_iter().map(|kv| (kv.key, kv.value)));
+        let Some(next) = next else {
+            assert!(!res.more);
+            break;
+        };
+        let _ignore = first_token.get_or_insert_with(|| next.clone());
+        token = Some(next);
+        // concurrent writes which must not be observed by the following pages
+        let _ignore = client
+            .put(xlineapi::PutRequest {
+                key: format!("key{:02}a", paged.len()).into_bytes(),
+                value: b"v1".to_vec(),
+                ..Default::default()
+            })
+            .await?;
+        let _ignore = client
+            .put(xlineapi::PutRequest {
+                key: b"key18".to_vec(),
+                value: b"v1".to_vec(),
+                ..Default::default()
+            })
+            .await?;
+        let _ignore = client
+            .delete_range(xlineapi::DeleteRangeRequest {
+                key: b"key15".to_vec(),
+                ..Default::default()
+            })
+            .await?;
+    }
+    let paged_keys: Vec<_> = paged.iter().map(|(key, _)| key.clone()).collect();
+    assert_eq!(paged_keys, keys);
+    assert!(paged.iter().all(|(_, value)| value == b"v0"));
+
+    let revision = client
+        .range(xlineapi::RangeRequest {
+            key: b"key".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .header
+        .unwrap()
+        .revision;
+    let _ignore = client
+        .compact(xlineapi::CompactionRequest {
+            revision,
+            physical: true,
+        })
+        .await?;
+    let err = client.range(range(first_token)).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::OutOfRange);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_writes_should_be_rejected_below_min_healthy_voters() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                3,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let put = |value: &str| xlineapi::PutRequest {
+        key: b"foo".to_vec(),
+        value: value.as_bytes().to_vec(),
+        ..Default::default()
+    };
+    let _ignore = client.put(put("bar")).await?;
+
+    cluster.stop_node(2).await;
+    // wait for the stopped voter to be considered unhealthy after an election timeout
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    // the leader rejects the writes, whichever node receives them
+    for i in 0..2 {
+        let mut client = xlineapi::KvClient::connect(cluster.get_client_url(i)).await?;
+        let status = client.put(put("baz")).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    }
+    // a write proposed by the curp client is rejected by the leader as well
+    let kv_client = cluster.client().await.kv_client();
+    let err = kv_client
+        .put(PutRequest::new("foo", "qux"))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        XlineClientError::ExecuteError(ExecuteError::Rejected(_))
+    ));
+    let res = kv_client.range(RangeRequest::new("foo")).await?;
+    assert_eq!(res.kvs.len(), 1);
+    assert_eq!(res.kvs[0].value, b"bar");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_guarded_put_should_require_an_expected_mod_revision() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                vec!["config/".to_owned()],
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let put = |value: &str, expected: Option<i64>| {
+        let mut request = tonic::Request::new(xlineapi::PutRequest {
+            key: b"config/a".to_vec(),
+            value: value.as_bytes().to_vec(),
+            ..Default::default()
+        });
+        if let Some(expected) = expected {
+            let _ignore = request.metadata_mut().insert(
+                "expected-mod-revision",
+                expected.to_string().parse().unwrap(),
+            );
+        }
+        request
+    };
+
+    let status = client.put(put("v1", None)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    // 0 expects the key to be absent
+    let created = client.put(put("v1", Some(0))).await?.into_inner();
+    let revision = created.header.unwrap().revision;
+    let status = client.put(put("v2", Some(0))).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Aborted);
+    let _ignore = client.put(put("v2", Some(revision))).await?;
+    let res = client
+        .range(xlineapi::RangeRequest {
+            key: b"config/a".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(res.kvs[0].value, b"v2");
+
+    let blind_txn = xlineapi::TxnRequest {
+        compare: vec![],
+        success: vec![xlineapi::RequestOp {
+            request: Some(xlineapi::Request::RequestPut(xlineapi::PutRequest {
+                key: b"config/a".to_vec(),
+                value: b"v3".to_vec(),
+                ..Default::default()
+            })),
+        }],
+        failure: vec![],
+    };
+    let status = client.txn(blind_txn).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    let _ignore = client
+        .put(xlineapi::PutRequest {
+            key: b"other".to_vec(),
+            value: b"v".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_guarded_put_should_not_silently_detach_the_lease() -> Result<(), Box<dyn Error>>
+{
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                vec!["services/".to_owned()],
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let lease_id = cluster
+        .client()
+        .await
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let put = |value: &str| xlineapi::PutRequest {
+        key: b"services/a".to_vec(),
+        value: value.as_bytes().to_vec(),
+        ..Default::default()
+    };
+    let lease_of_key = |mut client: xlineapi::KvClient<_>| async move {
+        let res = client
+            .range(xlineapi::RangeRequest {
+                key: b"services/a".to_vec(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        (res.kvs[0].lease, res.kvs[0].value.clone())
+    };
+
+    // a key without a lease can be put as usual
+    let _ignore = client.put(put("v1")).await?;
+    let _ignore = client.put(put("v1")).await?;
+    let _ignore = client
+        .put(xlineapi::PutRequest {
+            lease: lease_id,
+            ..put("v2")
+        })
+        .await?;
+
+    let status = client.put(put("v3")).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    assert_eq!(
+        lease_of_key(client.clone()).await,
+        (lease_id, b"v2".to_vec())
+    );
+
+    let _ignore = client
+        .put(xlineapi::PutRequest {
+            ignore_lease: true,
+            ..put("v3")
+        })
+        .await?;
+    assert_eq!(
+        lease_of_key(client.clone()).await,
+        (lease_id, b"v3".to_vec())
+    );
+
+    let mut detach = tonic::Request::new(put("v4"));
+    let _ignore = detach
+        .metadata_mut()
+        .insert("detach-lease", "true".parse().unwrap());
+    let _ignore = client.put(detach).await?;
+    assert_eq!(lease_of_key(client.clone()).await, (0, b"v4".to_vec()));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_response_metadata_should_track_the_compacted_revision() -> Result<(), Box<dyn Error>>
+{
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let compact_revision = |metadata: &tonic::metadata::MetadataMap| -> i64 {
+        metadata
+            .get("compact-revision")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    let put = || xlineapi::PutRequest {
+        key: b"foo".to_vec(),
+        value: b"bar".to_vec(),
+        ..Default::default()
+    };
+    let range = || xlineapi::RangeRequest {
+        key: b"foo".to_vec(),
+        ..Default::default()
+    };
+
+    let res = client.put(put()).await?;
+    assert_eq!(compact_revision(res.metadata()), 0);
+    let revision = client
+        .put(put())
+        .await?
+        .into_inner()
+        .header
+        .unwrap()
+        .revision;
+    let _ignore = client
+        .compact(xlineapi::CompactionRequest {
+            revision,
+            physical: true,
+        })
+        .await?;
+
+    let res = client.range(range()).await?;
+    assert_eq!(compact_revision(res.metadata()), revision);
+    let res = client.put(put()).await?;
+    assert_eq!(compact_revision(res.metadata()), revision);
+    let res = client
+        .txn(xlineapi::TxnRequest {
+            compare: vec![],
+            success: vec![xlineapi::RequestOp {
+                request: Some(xlineapi::Request::RequestRange(range())),
+            }],
+            failure: vec![],
+        })
+        .await?;
+    assert_eq!(compact_revision(res.metadata()), revision);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_last_access_times_should_be_updated_by_reads() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                true,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    for key in ["a", "b", "c"] {
+        let _resp = client.kv_client().put(PutRequest::new(key, "v")).await?;
+    }
+    let mut kv_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let read = |key: &str| xlineapi::RangeRequest {
+        key: key.into(),
+        ..Default::default()
+    };
+    let admin_client = kv_client.clone();
+    let last_access = || {
+        let mut request = tonic::Request::new(xlineapi::RangeRequest {
+            key: b"a".to_vec(),
+            range_end: b"d".to_vec(),
+            ..Default::default()
+        });
+        let _ignore = request
+            .metadata_mut()
+            .insert("last-access", "true".parse().unwrap());
+        let mut admin_client = admin_client.clone();
+        async move {
+            let res = admin_client.range(request).await?;
+            let times: Vec<u64> = res
+                .metadata()
+                .get("last-access")
+                .unwrap()
+                .to_str()?
+                .split(',')
+                .map(|time| time.parse().unwrap())
+                .collect();
+            Result::<_, Box<dyn Error>>::Ok(times)
+        }
+    };
+
+    let _res = kv_client.range(read("a")).await?;
+    let _res = kv_client.range(read("b")).await?;
+    let first = last_access().await?;
+    assert!(first[0] > 0 && first[1] > 0);
+    // the unread key stays stale
+    assert_eq!(first[2], 0);
+
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let _res = kv_client.range(read("a")).await?;
+    let second = last_access().await?;
+    assert!(second[0] > first[0]);
+    // reading the times doesn't count as a read
+    assert_eq!(second[1], first[1]);
+    assert_eq!(second[2], 0);
+
+    Ok(())
+}
+
+/// Hook recording the revisions of the applied mutations and rejecting the puts of
+/// the keys under `readonly/`
+#[derive(Debug, Default)]
+struct RecordingHook {
+    /// The revisions of the applied mutations
+    revisions: parking_lot::Mutex<Vec<i64>>,
+}
+
+impl CommandHook for RecordingHook {
+    fn pre_apply(&self, request: &RequestWrapper) -> Result<(), String> {
+        match *request {
+            RequestWrapper::PutRequest(ref req) if req.key.starts_with(b"readonly/") => {
+                Err("the key is read only".to_owned())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn post_apply(&self, request: &RequestWrapper, revision: i64) {
+        if matches!(
+            *request,
+            RequestWrapper::PutRequest(_)
+                | RequestWrapper::DeleteRangeRequest(_)
+                | RequestWrapper::TxnRequest(_)
+        ) {
+            self.revisions.lock().push(revision);
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_command_hooks_should_observe_every_committed_mutation_once(
+) -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    let hooks: Vec<_> = iter::repeat_with(|| Arc::new(RecordingHook::default()))
+        .take(3)
+        .collect();
+    for (i, hook) in hooks.iter().enumerate() {
+        cluster.set_command_hook(i, Arc::clone(hook) as Arc<dyn CommandHook>);
+    }
+    cluster.start().await;
+    let client = cluster.client().await.kv_client();
+
+    let _res = client.put(PutRequest::new("a", "1")).await?;
+    let _res = client.put(PutRequest::new("b", "2")).await?;
+    let _res = client.delete(DeleteRangeRequest::new("a")).await?;
+    let _res = client
+        .txn(TxnRequest::new().and_then(&[TxnOp::put(PutRequest::new("c", "3"))][..]))
+        .await?;
+    let err = client
+        .put(PutRequest::new("readonly/d", "4"))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        XlineClientError::ExecuteError(ExecuteError::Rejected(_))
+    ));
+    let res = client.range(RangeRequest::new("readonly/d")).await?;
+    assert!(res.kvs.is_empty());
+
+    // wait for the followers to apply the mutations
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    for hook in hooks {
+        assert_eq!(*hook.revisions.lock(), vec![2, 3, 4, 5]);
+    }
+
+    Ok(())
+}
+
+/// Hook delaying the apply of the puts of the keys under `slow/`
+#[derive(Debug)]
+struct SlowApplyHook(Duration);
+
+impl CommandHook for SlowApplyHook {
+    fn post_apply(&self, request: &RequestWrapper, _revision: i64) {
+        if matches!(*request, RequestWrapper::PutRequest(ref req) if req.key.starts_with(b"slow/"))
+        {
+            std::thread::sleep(self.0);
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_apply_latency_should_be_reported_when_requested() -> Result<(), Box<dyn Error>> {
+    let delay = Duration::from_millis(300);
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                true,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    for i in 0..3 {
+        cluster.set_command_hook(i, Arc::new(SlowApplyHook(delay)));
+    }
+    cluster.start().await;
+    let kv_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let put = |key: &str, report: bool| {
+        let mut request = tonic::Request::new(xlineapi::PutRequest {
+            key: key.into(),
+            value: b"v".to_vec(),
+            ..Default::default()
+        });
+        if report {
+            let _ignore = request
+                .metadata_mut()
+                .insert("apply-latency", "true".parse().unwrap());
+        }
+        let mut kv_client = kv_client.clone();
+        async move {
+            let start = std::time::Instant::now();
+            let res = kv_client.put(request).await?;
+            let observed = start.elapsed();
+            let reported = res
+                .metadata()
+                .get("apply-latency")
+                .map(|latency| latency.to_str().unwrap().parse().unwrap())
+                .map(Duration::from_micros);
+            Result::<_, Box<dyn Error>>::Ok((reported, observed))
+        }
+    };
+
+    let (reported, _observed) = put("fast", false).await?;
+    assert!(
+        reported.is_none(),
+        "the latency is only reported on request"
+    );
+    let (reported, observed) = put("fast", true).await?;
+    let reported = reported.unwrap();
+    assert!(reported > Duration::ZERO && reported <= observed);
+
+    let (reported, observed) = put("slow/a", true).await?;
+    let reported = reported.unwrap();
+    assert!(reported >= delay, "the apply delay should be included");
+    assert!(reported <= observed);
+    // the difference is only the network and the handling outside the consensus
+    assert!(
+        observed - reported < delay,
+        "reported {reported:?} should be close to observed {observed:?}"
+    );
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_numbered_requests_should_be_applied_in_the_order_sent() -> Result<(), Box<dyn Error>>
+{
+    const COUNT: u64 = 8;
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            // the default cluster config executes the commands by several workers
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::from_secs(5),
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    // all the requests are sent on the connection of this client
+    let kv_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+
+    // request n + 1 increments the counter from n - 1 to n, so it only succeeds if
+    // the earlier ones have been applied before it
+    let increment = |n: u64| {
+        let value = |n: u64| n.to_string().into_bytes();
+        let mut request = tonic::Request::new(xlineapi::TxnRequest {
+            compare: vec![xlineapi::Compare {
+                result: xlineapi::CompareResult::Equal.into(),
+                target: xlineapi::CompareTarget::Value.into(),
+                key: b"counter".to_vec(),
+                range_end: vec![],
+                target_union: Some(xlineapi::TargetUnion::Value(value(n - 1))),
+            }],
+            success: vec![xlineapi::RequestOp {
+                request: Some(xlineapi::Request::RequestPut(xlineapi::PutRequest {
+                    key: b"counter".to_vec(),
+                    value: value(n),
+                    ..Default::default()
+                })),
+            }],
+            failure: vec![],
+        });
+        let _ignore = request
+            .metadata_mut()
+            .insert("request-seq", (n + 1).to_string().parse().unwrap());
+        request
+    };
+    let mut init = tonic::Request::new(xlineapi::PutRequest {
+        key: b"counter".to_vec(),
+        value: b"0".to_vec(),
+        ..Default::default()
+    });
+    let _ignore = init
+        .metadata_mut()
+        .insert("request-seq", "1".parse().unwrap());
+
+    // the dependent requests reach the server in the reverse order, the last one first
+    let handles: Vec<_> = (1..=COUNT)
+        .map(|n| {
+            let mut kv_client = kv_client.clone();
+            let request = increment(n);
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(20 * (COUNT - n))).await;
+                kv_client.txn(request).await
+            })
+        })
+        .collect();
+    tokio::time::sleep(Duration::from_millis(20 * COUNT)).await;
+    let _ignore = kv_client.clone().put(init).await?;
+    for handle in handles {
+        let res = handle.await??.into_inner();
+        assert!(res.succeeded, "every increment should see the previous one");
+    }
+
+    let res = kv_client
+        .clone()
+        .range(xlineapi::RangeRequest {
+            key: b"counter".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(res.kvs[0].value, COUNT.to_string().into_bytes());
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_range_over_max_result_count_should_be_truncated_or_rejected(
+) -> Result<(), Box<dyn Error>> {
+    for overflow in [RangeResultOverflow::Truncate, RangeResultOverflow::Reject] {
+        let configs = iter::repeat_with(|| {
+            XlineServerConfig::new(
+                ClusterConfig::default(),
+                StorageConfig::default(),
+                LogConfig::default(),
+                TraceConfig::default(),
+                AuthConfig::default(),
+                CompactConfig::default(),
+                TlsConfig::default(),
+                MetricsConfig::default(),
+                WatchConfig::default(),
+                KvConfig::new(
+                    false,
+                    false,
+                    0,
+                    0,
+                    false,
+                    Duration::ZERO,
+                    SnapshotInstallReads::default(),
+                    KeyValueEncoding::default(),
+                    0,
+                    LeaderlessReads::default(),
+                    default_leaderless_read_timeout(),
+                    Vec::new(),
+                    Vec::new(),
+                    Vec::new(),
+                    false,
+                    0,
+                    false,
+                    Duration::ZERO,
+                    3,
+                    overflow,
+                    KeyCharset::default(),
+                    Vec::new(),
+                    0,
+                    Vec::new(),
+                    default_range_stream_batch_size(),
+                ),
+            )
+        })
+        .take(3)
+        .collect();
+        let mut cluster = Cluster::new_with_configs(configs).await;
+        cluster.start().await;
+        let client = cluster.client().await.kv_client();
+        for key in ["a", "b", "c", "d", "e"] {
+            let _resp = client.put(PutRequest::new(key, "v")).await?;
+        }
+
+        let res = client
+            .range(RangeRequest::new("a").with_range_end("z"))
+            .await;
+        if overflow == RangeResultOverflow::Reject {
+            let err = res.unwrap_err();
+            assert!(err.to_string().contains("add a limit"), "{err}");
+        } else {
+            let res = res?;
+            assert_eq!(res.kvs.len(), 3);
+            assert!(res.more);
+            assert_eq!(res.count, 5);
+        }
+
+        // ranges within the max result count are served in both modes
+        let res = client
+            .range(RangeRequest::new("a").with_range_end("z").with_limit(2))
+            .await?;
+        assert_eq!(res.kvs.len(), 2);
+        let res = client
+            .range(
+                RangeRequest::new("a")
+                    .with_range_end("z")
+                    .with_min_mod_revision(4),
+            )
+            .await?;
+        assert_eq!(res.kvs.len(), 3);
+    }
+    Ok(())
+}
+
+/// A validator of the json values against a schema of the required fields and the
+/// types of the fields
+#[derive(Debug)]
+struct JsonSchemaValidator(serde_json::Value);
+
+impl JsonSchemaValidator {
+    /// Check whether the value is of the json type
+    fn is_of_type(value: &serde_json::Value, ty: &str) -> bool {
+        match ty {
+            "object" => value.is_object(),
+            "string" => value.is_string(),
+            "number" => value.is_number(),
+            "boolean" => value.is_boolean(),
+            _ => false,
+        }
+    }
+}
+
+impl ValueValidator for JsonSchemaValidator {
+    fn validate(&self, _key: &[u8], value: &[u8]) -> Result<(), String> {
+        let value: serde_json::Value = serde_json::from_slice(value).map_err(|e| e.to_string())?;
+        if !Self::is_of_type(&value, self.0["type"].as_str().unwrap_or_default()) {
+            return Err("unexpected type".to_owned());
+        }
+        for field in self.0["required"].as_array().into_iter().flatten() {
+            let field = field.as_str().unwrap_or_default();
+            if value.get(field).is_none() {
+                return Err(format!("missing field {field}"));
+            }
+        }
+        let properties = self.0["properties"].as_object().into_iter().flatten();
+        for (field, schema) in properties {
+            if let Some(value) = value.get(field) {
+                if !Self::is_of_type(value, schema["type"].as_str().unwrap_or_default()) {
+                    return Err(format!("unexpected type of field {field}"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_values_should_be_validated_by_the_validators_of_their_prefixes(
+) -> Result<(), Box<dyn Error>> {
+    let schema = serde_json::json!({
+        "type": "object",
+        "required": ["name", "port"],
+        "properties": {
+            "name": { "type": "string" },
+            "port": { "type": "number" },
+            "tls": { "type": "boolean" },
+        },
+    });
+    let mut cluster = Cluster::new(3).await;
+    cluster.add_value_validator("services/", Arc::new(JsonSchemaValidator(schema)));
+    cluster.start().await;
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let put = |key: &str, value: &str| xlineapi::PutRequest {
+        key: key.into(),
+        value: value.into(),
+        ..Default::default()
+    };
+
+    let conforming = [
+        put("services/a", r#"{"name": "a", "port": 2379}"#),
+        put("services/b", r#"{"name": "b", "port": 2379, "tls": true}"#),
+        put("other/c", "not json"),
+    ];
+    for req in conforming {
+        let _ignore = client.put(req).await?;
+    }
+    let malformed = [
+        put("services/d", "not json"),
+        put("services/d", r#"["d", 2379]"#),
+        put("services/d", r#"{"name": "d"}"#),
+        put("services/d", r#"{"name": "d", "port": "2379"}"#),
+    ];
+    for req in malformed {
+        let err = client.put(req.clone()).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        let err = client
+            .txn(xlineapi::TxnRequest {
+                compare: vec![],
+                success: vec![xlineapi::RequestOp {
+                    request: Some(xlineapi::Request::RequestPut(req)),
+                }],
+                failure: vec![],
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+    // the values written by the server ops are unknown until they are applied
+    let append = |key: &str| ServerOp::Append(AppendOp::new(key.into(), b"garbage".to_vec(), 0));
+    let swap = ServerOp::Swap(SwapOp::new(
+        b"services/a".to_vec(),
+        b"other/c".to_vec(),
+        false,
+        false,
+    ));
+    for op in [append("services/a"), swap] {
+        let err = client
+            .txn(xlineapi::TxnRequest::from(op))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+    let _ignore = client
+        .txn(xlineapi::TxnRequest::from(append("other/c")))
+        .await?;
+    let res = client
+        .range(xlineapi::RangeRequest {
+            key: b"services/".to_vec(),
+            range_end: b"services0".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner();
+    assert_eq!(res.kvs.len(), 2);
+    assert_eq!(res.kvs[0].value, br#"{"name": "a", "port": 2379}"#);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_prefix_move_should_move_a_subtree_atomically() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let lease_id = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    let kv_client = client.kv_client();
+    let _ignore = kv_client
+        .put(PutRequest::new("cfg/a", "1").with_lease(lease_id))
+        .await?;
+    let _ignore = kv_client.put(PutRequest::new("cfg/b/c", "2")).await?;
+    let _ignore = kv_client.put(PutRequest::new("cfgx", "3")).await?;
+
+    let res = kv_client
+        .move_prefix(MovePrefixRequest::new("cfg/", "config/"))
+        .await?;
+    assert_eq!(res.moved, 2);
+    let revision = res.revision;
+
+    let moved = kv_client
+        .range(RangeRequest::new("config/").with_prefix())
+        .await?;
+    let moved: Vec<_> = moved
+        .kvs
+        .iter()
+        .map(|kv| (kv.key.clone(), kv.value.clone(), kv.lease, kv.mod_revision))
+        .collect();
+    assert_eq!(
+        moved,
+        [
+            (b"config/a".to_vec(), b"1".to_vec(), lease_id, revision),
+            (b"config/b/c".to_vec(), b"2".to_vec(), 0, revision),
+        ]
+    );
+    let old = kv_client
+        .range(RangeRequest::new("cfg/").with_prefix())
+        .await?;
+    assert!(old.kvs.is_empty());
+    let before = kv_client
+        .range(
+            RangeRequest::new("cfg/")
+                .with_prefix()
+                .with_revision(revision - 1),
+        )
+        .await?;
+    assert_eq!(before.kvs.len(), 2);
+    let untouched = kv_client.range(RangeRequest::new("cfgx")).await?;
+    assert_eq!(untouched.kvs.len(), 1);
+    let ttl = xlineapi::LeaseClient::connect(cluster.get_client_url(0))
+        .await?
+        .lease_time_to_live(xlineapi::LeaseTimeToLiveRequest {
+            id: lease_id,
+            keys: true,
+        })
+        .await?
+        .into_inner();
+    assert_eq!(ttl.keys, [b"config/a".to_vec()]);
+
+    // the destination must be empty and the prefixes must not overlap
+    let _ignore = kv_client.put(PutRequest::new("cfg/d", "4")).await?;
+    for (source, destination) in [("cfg/", "config/"), ("cfg/", "cfg/e/")] {
+        let err = kv_client
+            .move_prefix(MovePrefixRequest::new(source, destination))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            XlineClientError::ExecuteError(ExecuteError::Rejected(_))
+        ));
+    }
+    let res = kv_client
+        .range(RangeRequest::new("cfg/").with_prefix())
+        .await?;
+    assert_eq!(res.kvs.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_catch_up_read_should_serve_fresh_data_on_a_follower() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let kv_client = cluster.client().await.kv_client();
+    let mut follower_client = xlineapi::KvClient::connect(cluster.get_client_url(1)).await?;
+    let catch_up_read = || {
+        let mut request = tonic::Request::new(xlineapi::RangeRequest {
+            key: b"foo".to_vec(),
+            serializable: true,
+            ..Default::default()
+        });
+        let _prev = request.metadata_mut().insert(
+            "catch-up-read",
+            tonic::metadata::MetadataValue::from_static("true"),
+        );
+        request
+    };
+
+    for i in 0..3 {
+        let value = format!("bar{i}");
+        let revision = kv_client
+            .put(PutRequest::new("foo", value.as_str()))
+            .await?
+            .header
+            .expect("response should have a header")
+            .revision;
+        // the commit index reaches the follower by the next heartbeat of the leader
+        tokio::time::sleep(default_heartbeat_interval() * 2).await;
+        let res = follower_client.range(catch_up_read()).await?.into_inner();
+        assert!(res.header.expect("response should have a header").revision >= revision);
+        assert_eq!(res.kvs.len(), 1);
+        assert_eq!(res.kvs[0].value, value.as_bytes());
+    }
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_saturated_proposals_should_be_throttled_with_a_retry_after_hint(
+) -> Result<(), Box<dyn Error>> {
+    const WRITES: usize = 200;
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                4,
+                Vec::new(),
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let kv_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+
+    let writes: Vec<_> = (0..WRITES)
+        .map(|i| {
+            let mut kv_client = kv_client.clone();
+            tokio::spawn(async move {
+                let start = std::time::Instant::now();
+                let res = kv_client
+                    .put(xlineapi::PutRequest {
+                        key: format!("key{i}").into_bytes(),
+                        value: b"value".to_vec(),
+                        ..Default::default()
+                    })
+                    .await;
+                (res, start.elapsed())
+            })
+        })
+        .collect();
+    let mut throttled = 0;
+    for write in writes {
+        let (res, latency) = write.await?;
+        // the writes beyond the limit are rejected at once instead of queuing
+        assert!(latency < Duration::from_secs(5), "{latency:?}");
+        let Err(status) = res else {
+            continue;
+        };
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        let retry_after_ms: u64 = status
+            .metadata()
+            .get("retry-after-ms")
+            .expect("a throttled write should have a retry-after hint")
+            .to_str()?
+            .parse()?;
+        assert!(retry_after_ms >= 1);
+        throttled += 1;
+    }
+    assert!(throttled > 0, "no write is throttled");
+    assert!(throttled < WRITES, "all writes are throttled");
+
+    // the writes succeed again once the pipeline is drained
+    let mut kv_client = kv_client;
+    let _res = kv_client
+        .put(xlineapi::PutRequest {
+            key: b"foo".to_vec(),
+            value: b"bar".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_conditional_delete_should_only_delete_ranges_under_the_threshold(
+) -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let kv_client = client.kv_client();
+    for i in 0..5 {
+        let _ignore = kv_client
+            .put(PutRequest::new(format!("jobs/{i}"), "done"))
+            .await?;
+    }
+
+    let delete_if_fewer_than =
+        |threshold: u64| ConditionalDeleteRequest::new("jobs/", threshold).with_prefix();
+
+    let skipped = kv_client
+        .conditional_delete(delete_if_fewer_than(5))
+        .await?;
+    assert_eq!((skipped.count, skipped.deleted), (5, false));
+    let kept = kv_client
+        .range(RangeRequest::new("jobs/").with_prefix())
+        .await?;
+    assert_eq!(kept.kvs.len(), 5);
+
+    let deleted = kv_client
+        .conditional_delete(delete_if_fewer_than(6))
+        .await?;
+    assert_eq!((deleted.count, deleted.deleted), (5, true));
+    let left = kv_client
+        .range(RangeRequest::new("jobs/").with_prefix())
+        .await?;
+    assert!(left.kvs.is_empty());
+    let before = kv_client
+        .range(
+            RangeRequest::new("jobs/")
+                .with_prefix()
+                .with_revision(deleted.revision - 1),
+        )
+        .await?;
+    assert_eq!(before.kvs.len(), 5);
+
+    let invalid = kv_client
+        .conditional_delete(delete_if_fewer_than(0))
+        .await
+        .unwrap_err();
+    assert!(matches!(invalid, XlineClientError::InvalidArgs(_)));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_immutable_key_should_be_unlocked_for_corrections() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                vec!["audit/".to_owned()],
+                default_range_stream_batch_size(),
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let mut raw_client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+    let put = |value: &str, unlock: bool| {
+        let mut request = tonic::Request::new(xlineapi::PutRequest {
+            key: b"audit/1".to_vec(),
+            value: value.as_bytes().to_vec(),
+            ..Default::default()
+        });
+        if unlock {
+            let _prev = request
+                .metadata_mut()
+                .insert("unlock-immutable", "true".parse().unwrap());
+        }
+        request
+    };
+    let delete = || {
+        tonic::Request::new(xlineapi::DeleteRangeRequest {
+            key: b"audit/".to_vec(),
+            range_end: b"audit0".to_vec(),
+            ..Default::default()
+        })
+    };
+    let value_of_the_key = || async {
+        let res = xlineapi::KvClient::connect(cluster.get_client_url(0))
+            .await
+            .unwrap()
+            .range(xlineapi::RangeRequest {
+                key: b"audit/1".to_vec(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        res.kvs[0].value.clone()
+    };
+
+    let _res = raw_client.put(put("created", false)).await?;
+    let status = raw_client.put(put("overwritten", false)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    let status = raw_client.delete_range(delete()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    assert_eq!(value_of_the_key().await, b"created");
+
+    let _res = raw_client.put(put("corrected", true)).await?;
+    assert_eq!(value_of_the_key().await, b"corrected");
+    let status = raw_client.put(put("overwritten", false)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+    assert_eq!(value_of_the_key().await, b"corrected");
+
+    Ok(())
+}
+
+/// Archive keeping the compacted versions in memory
+#[derive(Debug, Default)]
+struct MemoryArchive {
+    /// The archived versions
+    kvs: parking_lot::Mutex<Vec<xlineapi::KeyValue>>,
+    /// The number of the reads of the archive
+    reads: std::sync::atomic::AtomicUsize,
+}
+
+#[async_trait::async_trait]
+impl ArchiveSink for MemoryArchive {
+    async fn archive(&self, kvs: &[xlineapi::KeyValue]) -> Result<(), String> {
+        self.kvs.lock().extend_from_slice(kvs);
+        Ok(())
+    }
+
+    async fn versions(
+        &self,
+        key_range: &xlineapi::command::KeyRange,
+        revision: i64,
+    ) -> Result<Vec<xlineapi::KeyValue>, String> {
+        let _prev = self
+            .reads
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Ok(self
+            .kvs
+            .lock()
+            .iter()
+            .filter(|kv| key_range.contains_key(&kv.key) && kv.mod_revision <= revision)
+            .cloned()
+            .collect())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_compacted_revisions_should_be_read_from_the_archive() -> Result<(), Box<dyn Error>> {
+    let archive = Arc::new(MemoryArchive::default());
+    let mut cluster = Cluster::new(3).await;
+    cluster.set_archive_sink(Arc::clone(&archive) as _);
+    cluster.start().await;
+    let mut client = xlineapi::KvClient::connect(cluster.get_client_url(0)).await?;
+
+    let mut revisions = Vec::new();
+    for value in ["v1", "v2", "v3"] {
+        let res = client
+            .put(xlineapi::PutRequest {
+                key: b"archived".to_vec(),
+                value: value.into(),
+                ..Default::default()
+            })
+            .await?;
+        revisions.push(res.into_inner().header.unwrap().revision);
+    }
+    let _ignore = client
+        .compact(xlineapi::CompactionRequest {
+            revision: revisions[2],
+            physical: true,
+        })
+        .await?;
+    let range = |revision: i64, archive_read: bool| {
+        let mut request = tonic::Request::new(xlineapi::RangeRequest {
+            key: b"archived".to_vec(),
+            revision,
+            ..Default::default()
+        });
+        if archive_read {
+            let _ignore = request.metadata_mut().insert(
+                "archive-read",
+                tonic::metadata::MetadataValue::from_static(""),
+            );
+        }
+        request
+    };
+
+    assert!(client.range(range(revisions[0], false)).await.is_err());
+    let res = client.range(range(revisions[0], true)).await?.into_inner();
+    assert_eq!(res.kvs.len(), 1);
+    assert_eq!(res.kvs[0].value, b"v1");
+    assert_eq!(archive.reads.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+    // the revisions not compacted are served as usual
+    let res = client.range(range(revisions[2], true)).await?.into_inner();
+    assert_eq!(res.kvs[0].value, b"v3");
+    assert_eq!(archive.reads.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+    Ok(())
+}
+
+/// Start a streamed range on a member
+async fn range_stream(
+    url: String,
+    request: xlineapi::RangeRequest,
+) -> Result<tonic::Streaming<xlineapi::RangeResponse>, Box<dyn Error>> {
+    let channel = tonic::transport::Channel::from_shared(url)?
+        .connect()
+        .await?;
+    let mut grpc = tonic::client::Grpc::new(channel);
+    grpc.ready().await?;
+    let path = tonic::codegen::http::uri::PathAndQuery::from_static("/xlinepb.RangeStream/Range");
+    let response = grpc
+        .server_streaming(
+            tonic::Request::new(request),
+            path,
+            tonic::codec::ProstCodec::default(),
+        )
+        .await?;
+    Ok(response.into_inner())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_large_range_should_be_streamed_at_a_pinned_revision() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::default(),
+            KvConfig::new(
+                false,
+                false,
+                0,
+                0,
+                false,
+                Duration::ZERO,
+                SnapshotInstallReads::default(),
+                KeyValueEncoding::default(),
+                0,
+                LeaderlessReads::default(),
+                default_leaderless_read_timeout(),
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
+                0,
+                false,
+                Duration::ZERO,
+                0,
+                RangeResultOverflow::default(),
+                KeyCharset::default(),
+                Vec::new(),
+                0,
+                Vec::new(),
+                10,
+            ),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let url = cluster.get_client_url(0);
+    let mut client = xlineapi::KvClient::connect(url.clone()).await?;
+    // the values are large enough that the stream is held back by the flow control
+    let value = vec![b'v'; 8192];
+    for i in 0..250 {
+        let _ignore = client
+            .put(xlineapi::PutRequest {
+                key: format!("stream/{i:03}").into_bytes(),
+                value: value.clone(),
+                ..Default::default()
+            })
+            .await?;
+    }
+    let range = |limit: i64| xlineapi::RangeRequest {
+        key: b"stream/".to_vec(),
+        range_end: b"stream0".to_vec(),
+        limit,
+        ..Default::default()
+    };
+
+    let mut stream = range_stream(url.clone(), range(0)).await?;
+    let first = stream.message().await?.unwrap();
+    assert_eq!(first.kvs.len(), 10);
+    assert_eq!(first.count, 250);
+    // the writes after the first batch are not seen by the stream
+    let _ignore = client
+        .put(xlineapi::PutRequest {
+            key: b"stream/251".to_vec(),
+            value: b"new".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+    let _ignore = client
+        .delete_range(xlineapi::DeleteRangeRequest {
+            key: b"stream/200".to_vec(),
+            ..Default::default()
+        })
+        .await?;
+    let mut keys = first.kvs.into_iter().map(|kv| kv.key).collect::<Vec<_>>();
+    while let Some(batch) = stream.message().await? {
+        assert!(batch.kvs.len() <= 10);
+        assert!(batch.kvs.iter().all(|kv| kv.value == value));
+        keys.extend(batch.kvs.into_iter().map(|kv| kv.key));
+    }
+    let expected = (0..250)
+        .map(|i| format!("stream/{i:03}").into_bytes())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+
+    // the limit is honored across the batches
+    let mut stream = range_stream(url.clone(), range(25)).await?;
+    let mut batches = Vec::new();
+    while let Some(batch) = stream.message().await? {
+        batches.push(batch);
+    }
+    let sizes = batches
+        .iter()
+        .map(|batch| batch.kvs.len())
+        .collect::<Vec<_>>();
+    assert_eq!(sizes, [10, 10, 5]);
+    assert!(batches.iter().all(|batch| batch.more));
+    assert_eq!(batches[2].kvs[4].key, b"stream/024");
+
+    // a range not ordered by key ascending can't be streamed
+    let descend = xlineapi::RangeRequest {
+        sort_order: xlineapi::SortOrder::Descend.into(),
+        ..range(0)
+    };
+    let Err(err) = range_stream(url.clone(), descend).await else {
+        panic!("a descending range should be rejected");
+    };
+    let status = err.downcast_ref::<tonic::Status>().unwrap();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+    // the compaction of the pinned revision ends the stream with the compaction error
+    let mut stream = range_stream(url, range(0)).await?;
+    let first = stream.message().await?.unwrap();
+    assert_eq!(first.kvs.len(), 10);
+    let revision = client
+        .put(xlineapi::PutRequest {
+            key: b"stream/000".to_vec(),
+            value: b"new".to_vec(),
+            ..Default::default()
+        })
+        .await?
+        .into_inner()
+        .header
+        .unwrap()
+        .revision;
+    let _ignore = client
+        .compact(xlineapi::CompactionRequest {
+            revision,
+            physical: true,
+        })
+        .await?;
+    let mut received = first.kvs.len();
+    let status = loop {
+        match stream.message().await {
+            Ok(Some(batch)) => received += batch.kvs.len(),
+            Ok(None) => panic!("the stream should end with the compaction error"),
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(status.code(), tonic::Code::OutOfRange);
+    assert!(received < 249);
+
+    Ok(())
+}
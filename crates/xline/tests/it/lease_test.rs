@@ -1,14 +1,23 @@
 use std::{error::Error, time::Duration};
 
+use futures::channel::mpsc::channel;
 use test_macros::abort_on_panic;
 use tracing::info;
 use xline_test_utils::{
     types::{
         kv::{PutRequest, RangeRequest},
-        lease::{LeaseGrantRequest, LeaseKeepAliveRequest},
+        lease::{LeaseGrantRequest, LeaseKeepAliveRequest, LeaseRevokeRequest},
     },
     Client, ClientOptions, Cluster,
 };
+use xlineapi::{
+    admin::{
+        AdminClient, AttachedKeysRequest, ExportLeasesRequest, ImportLeasesRequest, LeaseKeys,
+    },
+    server_op::LeaseImport,
+    EventType, LeaseClient, LeaseTimeToLiveRequest, RequestUnion, WatchClient, WatchCreateRequest,
+    WatchRequest,
+};
 
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
@@ -89,3 +98,288 @@ async fn test_lease_keep_alive() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_events_should_be_watched() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut watch_client = WatchClient::connect(cluster.get_client_url(0)).await?;
+
+    let (mut req_tx, req_rx) = channel(1);
+    req_tx.try_send(WatchRequest {
+        request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest::default())),
+    })?;
+    let mut request = tonic::Request::new(req_rx);
+    let _prev = request
+        .metadata_mut()
+        .insert("lease-events", "true".parse()?);
+    let mut stream = watch_client.watch(request).await?.into_inner();
+    assert!(stream.message().await?.unwrap().created);
+
+    let lease_id = client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    // the keeper is created by a keep alive
+    let _keeper = client
+        .lease_client()
+        .keep_alive(LeaseKeepAliveRequest::new(lease_id))
+        .await?;
+    let _resp = client
+        .lease_client()
+        .revoke(LeaseRevokeRequest::new(lease_id))
+        .await?;
+
+    let mut events = Vec::new();
+    while events.len() < 3 {
+        let res = stream.message().await?.unwrap();
+        events.extend(res.events);
+    }
+    let key = lease_id.to_string().into_bytes();
+    let granted = events[0].kv.clone().unwrap();
+    assert_eq!(events[0].r#type, i32::from(EventType::Put));
+    assert_eq!(granted.key, key);
+    assert_eq!(granted.value, b"60");
+    assert_eq!(granted.create_revision, granted.mod_revision);
+    let kept_alive = events[1].kv.clone().unwrap();
+    assert_eq!(events[1].r#type, i32::from(EventType::Put));
+    assert_eq!(kept_alive.create_revision, granted.create_revision);
+    assert_eq!(kept_alive.version, 2);
+    assert_eq!(kept_alive.value, b"60");
+    let revoked = events[2].kv.clone().unwrap();
+    assert_eq!(events[2].r#type, i32::from(EventType::Delete));
+    assert_eq!(revoked.key, key);
+
+    // a watch of the lease resumes from the grant
+    let (mut req_tx, req_rx) = channel(1);
+    req_tx.try_send(WatchRequest {
+        request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+            key: key.clone(),
+            start_revision: granted.mod_revision,
+            ..Default::default()
+        })),
+    })?;
+    let mut request = tonic::Request::new(req_rx);
+    let _prev = request
+        .metadata_mut()
+        .insert("lease-events", "true".parse()?);
+    let mut stream = watch_client.watch(request).await?.into_inner();
+    assert!(stream.message().await?.unwrap().created);
+    let mut resumed = Vec::new();
+    while resumed.len() < 3 {
+        let res = stream.message().await?.unwrap();
+        resumed.extend(res.events);
+    }
+    assert_eq!(resumed, events);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_lease_state_should_be_exported_and_imported() -> Result<(), Box<dyn Error>> {
+    let mut source = Cluster::new(3).await;
+    source.start().await;
+    let (source_leader, source_follower) = (source.get_client_url(0), source.get_client_url(1));
+    let source_client = source.client().await;
+    let mut target = Cluster::new(3).await;
+    target.start().await;
+    let (target_leader, target_follower) = (target.get_client_url(0), target.get_client_url(1));
+    let target_client = target.client().await;
+
+    let live = source_client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    let expired = source_client
+        .lease_client()
+        .grant(LeaseGrantRequest::new(60))
+        .await?
+        .id;
+    for (key, lease) in [("foo", live), ("bar", live), ("baz", expired)] {
+        source_client
+            .kv_client()
+            .put(PutRequest::new(key, "value").with_lease(lease))
+            .await?;
+        // the keyspace is restored from a kv snapshot before the leases are imported
+        target_client
+            .kv_client()
+            .put(PutRequest::new(key, "value"))
+            .await?;
+    }
+
+    let mut exported = AdminClient::connect(source_leader)
+        .await?
+        .export_leases(ExportLeasesRequest {})
+        .await?
+        .into_inner();
+    assert!(exported.revision > 0);
+    let ids: Vec<_> = exported.leases.iter().map(|lease| lease.id).collect();
+    assert_eq!(ids, [live.min(expired), live.max(expired)]);
+    for lease in &mut exported.leases {
+        assert_eq!(lease.ttl, 60);
+        assert!(lease.remaining_ttl > 50 && lease.remaining_ttl <= 60);
+        // pretend that the second lease has expired at the export
+        if lease.id == expired {
+            lease.remaining_ttl = 0;
+        }
+    }
+    // a follower refuses to export since the remaining ttls are only known by the leader
+    let refused = AdminClient::connect(source_follower)
+        .await?
+        .export_leases(ExportLeasesRequest {})
+        .await
+        .unwrap_err();
+    assert_eq!(refused.code(), tonic::Code::FailedPrecondition);
+
+    let mut target_admin_client = AdminClient::connect(target_follower).await?;
+    let imported = target_admin_client
+        .import_leases(ImportLeasesRequest {
+            leases: exported.leases,
+        })
+        .await?
+        .into_inner();
+    assert_eq!(imported.imported, [live]);
+    assert_eq!(imported.revoked, [expired]);
+
+    let mut target_lease_client = LeaseClient::connect(target_leader).await?;
+    let ttl = target_lease_client
+        .lease_time_to_live(LeaseTimeToLiveRequest {
+            id: live,
+            keys: true,
+        })
+        .await?
+        .into_inner();
+    assert_eq!(ttl.granted_ttl, 60);
+    assert!(ttl.ttl > 50 && ttl.ttl <= 60, "remaining ttl {}", ttl.ttl);
+    let mut keys = ttl.keys;
+    keys.sort();
+    assert_eq!(keys, [b"bar".to_vec(), b"foo".to_vec()]);
+    // the keys are reattached at the revision of the import and keep their values
+    let res = target_client
+        .kv_client()
+        .range(RangeRequest::new("foo"))
+        .await?;
+    assert_eq!(res.kvs[0].lease, live);
+    assert_eq!(res.kvs[0].value, b"value");
+    assert_eq!(res.kvs[0].mod_revision, imported.revision);
+    // the expired lease is revoked with its keys
+    assert!(target_lease_client
+        .lease_time_to_live(LeaseTimeToLiveRequest {
+            id: expired,
+            keys: false,
+        })
+        .await
+        .is_err());
+    let res = target_client
+        .kv_client()
+        .range(RangeRequest::new("baz"))
+        .await?;
+    assert!(res.kvs.is_empty());
+
+    // an import attaching a missing key changes nothing
+    let missing = LeaseImport {
+        id: 42,
+        ttl: 60,
+        remaining_ttl: 60,
+        keys: vec![b"missing".to_vec()],
+    };
+    let other = LeaseImport {
+        id: 43,
+        ttl: 60,
+        remaining_ttl: 60,
+        keys: vec![b"foo".to_vec()],
+    };
+    assert!(target_admin_client
+        .import_leases(ImportLeasesRequest {
+            leases: vec![other, missing],
+        })
+        .await
+        .is_err());
+    for id in [42, 43] {
+        assert!(target_lease_client
+            .lease_time_to_live(LeaseTimeToLiveRequest { id, keys: false })
+            .await
+            .is_err());
+    }
+    let res = target_client
+        .kv_client()
+        .range(RangeRequest::new("foo"))
+        .await?;
+    assert_eq!(res.kvs[0].lease, live);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_attached_keys_should_be_listed_by_pages() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+
+    let mut expected = Vec::new();
+    for keys in [vec!["a", "b"], vec!["c"], vec!["d", "e", "f"]] {
+        let id = client
+            .lease_client()
+            .grant(LeaseGrantRequest::new(60))
+            .await?
+            .id;
+        for key in &keys {
+            let _res = client
+                .kv_client()
+                .put(PutRequest::new(*key, "v").with_lease(id))
+                .await?;
+        }
+        expected.push(LeaseKeys {
+            id,
+            keys: keys.iter().map(|key| key.as_bytes().to_vec()).collect(),
+        });
+    }
+    let _res = client.kv_client().put(PutRequest::new("g", "v")).await?;
+    expected.sort_by_key(|lease| lease.id);
+
+    let mut admin_client = AdminClient::connect(cluster.get_client_url(0)).await?;
+    // the listing is served by the node, which may apply the puts a bit later
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let res = admin_client
+                .attached_keys(AttachedKeysRequest::default())
+                .await?
+                .into_inner();
+            if res.leases == expected {
+                return Ok::<_, tonic::Status>(());
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await??;
+
+    let first = admin_client
+        .attached_keys(AttachedKeysRequest {
+            limit: 2,
+            page_token: String::new(),
+        })
+        .await?
+        .into_inner();
+    assert_eq!(first.leases.len(), 2);
+    assert!(!first.next_page_token.is_empty());
+    let _res = client.kv_client().put(PutRequest::new("c", "v")).await?;
+    let second = admin_client
+        .attached_keys(AttachedKeysRequest {
+            limit: 2,
+            page_token: first.next_page_token,
+        })
+        .await?
+        .into_inner();
+    assert_eq!(second.revision, first.revision);
+    assert!(second.next_page_token.is_empty());
+    let paged: Vec<_> = first.leases.into_iter().chain(second.leases).collect();
+    assert_eq!(paged, expected);
+
+    Ok(())
+}
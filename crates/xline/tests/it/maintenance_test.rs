@@ -9,7 +9,11 @@ use xline_test_utils::{
     types::kv::{PutRequest, RangeRequest},
     Client, ClientOptions, Cluster,
 };
-use xlineapi::{execute_error::ExecuteError, AlarmAction, AlarmRequest, AlarmType};
+use xlineapi::{
+    admin::{AdminClient, ClearDedupCacheRequest, DedupCacheRequest},
+    execute_error::ExecuteError,
+    AlarmAction, AlarmRequest, AlarmType,
+};
 
 #[tokio::test(flavor = "multi_thread")]
 #[abort_on_panic]
@@ -121,3 +125,114 @@ async fn test_status() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// Get the index of the leader by the statuses of the members
+async fn leader_index(cluster: &Cluster) -> Result<usize, Box<dyn std::error::Error>> {
+    for idx in 0..3 {
+        let res = xlineapi::MaintenanceClient::connect(cluster.get_client_url(idx))
+            .await?
+            .status(xlineapi::StatusRequest::default())
+            .await?
+            .into_inner();
+        if res
+            .header
+            .is_some_and(|header| header.member_id == res.leader)
+        {
+            return Ok(idx);
+        }
+    }
+    Err("no member is the leader".into())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_dedup_cache_should_be_cleared() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await.kv_client();
+    for i in 0..3 {
+        let _res = client
+            .put(PutRequest::new(format!("key{i}"), "value"))
+            .await?;
+    }
+
+    let leader = leader_index(&cluster).await?;
+    let mut admin_client = AdminClient::connect(cluster.get_client_url(leader)).await?;
+    let len = admin_client
+        .dedup_cache(DedupCacheRequest {})
+        .await?
+        .into_inner()
+        .len;
+    assert!(len >= 3);
+    let removed = admin_client
+        .clear_dedup_cache(ClearDedupCacheRequest {})
+        .await?
+        .into_inner()
+        .removed;
+    assert!(removed >= len);
+    let res = admin_client.dedup_cache(DedupCacheRequest {}).await?;
+    assert_eq!(res.into_inner().len, 0);
+
+    Ok(())
+}
+
+/// Get the status of a member by the raw client together with its storage health
+async fn storage_health(
+    cluster: &Cluster,
+    idx: usize,
+) -> Result<(xlineapi::StatusResponse, serde_json::Value), Box<dyn std::error::Error>> {
+    let mut client = xlineapi::MaintenanceClient::connect(cluster.get_client_url(idx)).await?;
+    let res = client.status(xlineapi::StatusRequest::default()).await?;
+    let health = res
+        .metadata()
+        .get_bin("storage-health-bin")
+        .expect("storage health should be carried by the status")
+        .to_bytes()?;
+    Ok((res.into_inner(), serde_json::from_slice(&health)?))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_status_should_report_storage_health() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cluster = Cluster::new_rocks(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut maintenance_client = client.maintenance_client();
+    let _ignore = client
+        .kv_client()
+        .put(PutRequest::new("foo", "bar"))
+        .await?;
+
+    let (status, health) = storage_health(&cluster, 0).await?;
+    assert!(health["last_fsync_unix_ms"]
+        .as_u64()
+        .is_some_and(|ms| ms > 0));
+    assert_eq!(health["compaction_backlog"], 0);
+    assert!(health["free_disk_space"]
+        .as_u64()
+        .is_some_and(|bytes| bytes > 0));
+    assert_eq!(health["read_only"], false);
+    assert_eq!(health["degraded"], false);
+
+    let member_id = status
+        .header
+        .expect("status should have a header")
+        .member_id;
+    let _ignore = maintenance_client
+        .alarm(AlarmRequest::new(
+            AlarmAction::Activate,
+            member_id,
+            AlarmType::Corrupt,
+        ))
+        .await?;
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let (_, health) = storage_health(&cluster, 0).await?;
+    assert_eq!(health["degraded"], true);
+    assert!(health["reasons"]
+        .as_array()
+        .is_some_and(|reasons| !reasons.is_empty()));
+    let (_, follower_health) = storage_health(&cluster, 1).await?;
+    assert_eq!(follower_health["degraded"], false);
+
+    Ok(())
+}
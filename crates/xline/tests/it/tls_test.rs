@@ -1,11 +1,16 @@
-use std::{fs, iter, path::PathBuf};
+use std::{fs, iter, path::PathBuf, sync::Arc};
 
 use etcd_client::ConnectOptions;
 use test_macros::abort_on_panic;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{pki_types::ServerName, version::TLS12, ClientConfig, RootCertStore},
+    TlsConnector,
+};
 use tonic::transport::{Certificate, ClientTlsConfig, Identity};
 use utils::config::{
-    AuthConfig, ClusterConfig, CompactConfig, LogConfig, MetricsConfig, StorageConfig, TlsConfig,
-    TraceConfig, XlineServerConfig,
+    AuthConfig, ClusterConfig, CompactConfig, KvConfig, LogConfig, MetricsConfig, StorageConfig,
+    TlsConfig, TlsVersion, TraceConfig, WatchConfig, XlineServerConfig,
 };
 use xline_client::types::kv::PutRequest;
 use xline_test_utils::{enable_auth, set_user, Cluster};
@@ -81,6 +86,50 @@ async fn test_certificate_authenticate() {
     assert!(res.is_ok());
 }
 
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_tls_min_version() {
+    let mut cluster = Cluster::new_with_configs(configs_with_tls_config(
+        3,
+        TlsConfig::new(
+            None,
+            Some(PathBuf::from("../../fixtures/server.crt")),
+            Some(PathBuf::from("../../fixtures/server.key")),
+            Some(PathBuf::from("../../fixtures/ca.crt")),
+            None,
+            None,
+            TlsVersion::Tls13,
+            vec![],
+        ),
+    ))
+    .await;
+    cluster.start().await;
+
+    let client = cluster
+        .client_with_tls_config(basic_tls_client_config())
+        .await;
+    let res = client.kv_client().put(PutRequest::new("foo", "bar")).await;
+    assert!(res.is_ok());
+
+    // a tls 1.2 handshake is refused
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut fs::read("../../fixtures/ca.crt").unwrap().as_slice()) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let tls12_config = ClientConfig::builder_with_protocol_versions(&[&TLS12])
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let url = cluster.get_client_url(0);
+    let addr = url
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let res = TlsConnector::from(Arc::new(tls12_config))
+        .connect(ServerName::try_from("localhost").unwrap(), stream)
+        .await;
+    assert!(res.is_err());
+}
+
 fn configs_with_tls_config(size: usize, tls_config: TlsConfig) -> Vec<XlineServerConfig> {
     iter::repeat(tls_config)
         .map(|tls_config| {
@@ -93,6 +142,8 @@ fn configs_with_tls_config(size: usize, tls_config: TlsConfig) -> Vec<XlineServe
                 CompactConfig::default(),
                 tls_config,
                 MetricsConfig::default(),
+                WatchConfig::default(),
+                KvConfig::default(),
             )
         })
         .take(size)
@@ -115,6 +166,8 @@ fn basic_tls_configs(size: usize) -> Vec<XlineServerConfig> {
             Some(PathBuf::from("../../fixtures/ca.crt")),
             None,
             None,
+            TlsVersion::default(),
+            vec![],
         ),
     )
 }
@@ -140,6 +193,8 @@ fn mtls_configs(size: usize) -> Vec<XlineServerConfig> {
             Some(PathBuf::from("../../fixtures/ca.crt")),
             Some(PathBuf::from("../../fixtures/root_client.crt")),
             Some(PathBuf::from("../../fixtures/root_client.key")),
+            TlsVersion::default(),
+            vec![],
         ),
     )
 }
@@ -1,14 +1,24 @@
-use std::error::Error;
+use std::{collections::HashMap, error::Error, iter};
 
+use futures::channel::mpsc::{channel, Sender};
 use test_macros::abort_on_panic;
+use utils::config::{
+    default_max_watch_buffer_depth, default_min_watch_buffer_depth, AuthConfig, ClientConfig,
+    ClusterConfig, CompactConfig, CurpConfig, InitialClusterState, KvConfig, LogConfig,
+    MessageSizeConfig, MetricsConfig, OversizedWatchEvent, ServerTimeout, StorageConfig, TlsConfig,
+    TraceConfig, WatchConfig, WatchHistoryReplay, XlineServerConfig,
+};
 use xline_test_utils::{
     types::{
-        kv::{DeleteRangeRequest, PutRequest},
+        kv::{DeleteRangeRequest, PutRequest, RangeRequest, ReserveRevisionsRequest},
         watch::WatchRequest,
     },
     Cluster,
 };
-use xlineapi::EventType;
+use xlineapi::{
+    admin::{AdminClient, WatchRegistryRequest},
+    EventType, RequestUnion, WatchClient, WatchCreateRequest,
+};
 
 fn event_type(event_type: i32) -> EventType {
     match event_type {
@@ -52,3 +62,602 @@ async fn test_watch() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_watch_with_prev_kv_should_carry_previous_values() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut watch_client = client.watch_client();
+    let kv_client = client.kv_client();
+
+    let (_watcher, mut stream) = watch_client
+        .watch(WatchRequest::new("foo").with_prev_kv())
+        .await?;
+    kv_client.put(PutRequest::new("foo", "old")).await?;
+    kv_client.put(PutRequest::new("foo", "new")).await?;
+    kv_client.delete(DeleteRangeRequest::new("foo")).await?;
+
+    let mut events = Vec::new();
+    while events.len() < 3 {
+        let res = stream.message().await?.unwrap();
+        events.extend(res.events);
+    }
+    let prev_value = |i: usize| events[i].prev_kv.as_ref().map(|kv| kv.value.clone());
+    assert_eq!(event_type(events[0].r#type), EventType::Put);
+    assert_eq!(prev_value(0), None);
+    assert_eq!(event_type(events[1].r#type), EventType::Put);
+    assert_eq!(prev_value(1), Some(b"old".to_vec()));
+    assert_eq!(event_type(events[2].r#type), EventType::Delete);
+    assert_eq!(prev_value(2), Some(b"new".to_vec()));
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_watch_should_skip_revisions_without_kv_changes() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client = cluster.client().await;
+    let mut watch_client = client.watch_client();
+    let kv_client = client.kv_client();
+
+    let first = kv_client.put(PutRequest::new("foo", "1")).await?;
+    let first_rev = first.header.unwrap().revision;
+    let reserved = kv_client
+        .reserve_revisions(ReserveRevisionsRequest::new(2))
+        .await?;
+    assert_eq!(
+        (reserved.first, reserved.last),
+        (first_rev + 1, first_rev + 2)
+    );
+    let second = kv_client.put(PutRequest::new("foo", "2")).await?;
+    let second_rev = second.header.unwrap().revision;
+    assert_eq!(second_rev, first_rev + 3);
+
+    // the reserved revisions hold the keys of the revision before them
+    let resp = kv_client
+        .range(RangeRequest::new("foo").with_revision(first_rev + 2))
+        .await?;
+    assert_eq!(resp.kvs[0].value, b"1");
+    let (_watcher, mut stream) = watch_client
+        .watch(WatchRequest::new("foo").with_start_revision(first_rev + 1))
+        .await?;
+    let res = stream.message().await?.unwrap();
+    assert_eq!(res.events.len(), 1);
+    let kv = res.events[0].kv.as_ref().unwrap();
+    assert_eq!(kv.value, b"2");
+    assert_eq!(kv.mod_revision, second_rev);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_linearizable_watch_create_on_follower() -> Result<(), Box<dyn Error>> {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::new(
+                true,
+                WatchHistoryReplay::Allow,
+                OversizedWatchEvent::default(),
+                default_min_watch_buffer_depth(),
+                default_max_watch_buffer_depth(),
+            ),
+            KvConfig::default(),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    let follower_ep = cluster.get_client_url(1);
+    let kv_client = cluster.client().await.kv_client();
+
+    for i in 0..10 {
+        kv_client.put(PutRequest::new("foo", i.to_string())).await?;
+    }
+    let read_revision = kv_client
+        .range(RangeRequest::new("foo"))
+        .await?
+        .header
+        .unwrap()
+        .revision;
+
+    let mut watch_client = WatchClient::connect(follower_ep).await?;
+    let (mut req_tx, req_rx) = channel(1);
+    req_tx.try_send(xlineapi::WatchRequest {
+        request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+            key: b"foo".to_vec(),
+            ..Default::default()
+        })),
+    })?;
+    let mut stream = watch_client.watch(req_rx).await?.into_inner();
+    let res = stream.message().await?.unwrap();
+    assert!(res.created);
+    assert!(res.header.unwrap().revision >= read_revision);
+
+    Ok(())
+}
+
+/// Start a cluster whose watches are created with the given history replay mode
+async fn cluster_with_history_replay(history_replay: WatchHistoryReplay) -> Cluster {
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            ClusterConfig::default(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::new(
+                false,
+                history_replay,
+                OversizedWatchEvent::default(),
+                default_min_watch_buffer_depth(),
+                default_max_watch_buffer_depth(),
+            ),
+            KvConfig::default(),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    cluster
+}
+
+/// Create a watch on `foo` from the given start revision, the returned request
+/// sender keeps the watch stream open
+async fn create_watch(
+    client_url: String,
+    start_revision: i64,
+) -> Result<
+    (
+        Sender<xlineapi::WatchRequest>,
+        tonic::Streaming<xlineapi::WatchResponse>,
+    ),
+    Box<dyn Error>,
+> {
+    let mut watch_client = WatchClient::connect(client_url).await?;
+    let (mut req_tx, req_rx) = channel(1);
+    req_tx.try_send(xlineapi::WatchRequest {
+        request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+            key: b"foo".to_vec(),
+            start_revision,
+            ..Default::default()
+        })),
+    })?;
+    let stream = watch_client.watch(req_rx).await?.into_inner();
+    Ok((req_tx, stream))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_historical_watch_should_be_rejected() -> Result<(), Box<dyn Error>> {
+    let mut cluster = cluster_with_history_replay(WatchHistoryReplay::Reject).await;
+    let leader_ep = cluster.get_client_url(0);
+    let kv_client = cluster.client().await.kv_client();
+    kv_client.put(PutRequest::new("foo", "old")).await?;
+
+    let (_req_tx, mut stream) = create_watch(leader_ep.clone(), 1).await?;
+    let res = stream.message().await?.unwrap();
+    assert!(res.created);
+    assert!(res.canceled);
+    assert!(!res.cancel_reason.is_empty());
+
+    // a watch from a future revision only delivers future events
+    let revision = kv_client
+        .range(RangeRequest::new("foo"))
+        .await?
+        .header
+        .unwrap()
+        .revision;
+    let (_req_tx, mut stream) = create_watch(leader_ep, revision + 1).await?;
+    let res = stream.message().await?.unwrap();
+    assert!(res.created);
+    assert!(!res.canceled);
+    kv_client.put(PutRequest::new("foo", "new")).await?;
+    let res = stream.message().await?.unwrap();
+    assert_eq!(res.events.len(), 1);
+    assert_eq!(res.events[0].kv.as_ref().unwrap().value, b"new");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_historical_watch_should_start_at_current_revision() -> Result<(), Box<dyn Error>> {
+    let mut cluster = cluster_with_history_replay(WatchHistoryReplay::Current).await;
+    let leader_ep = cluster.get_client_url(0);
+    let kv_client = cluster.client().await.kv_client();
+    kv_client.put(PutRequest::new("foo", "old")).await?;
+
+    let (_req_tx, mut stream) = create_watch(leader_ep, 1).await?;
+    let res = stream.message().await?.unwrap();
+    assert!(res.created);
+    assert!(!res.canceled);
+    kv_client.put(PutRequest::new("foo", "new")).await?;
+    let res = stream.message().await?.unwrap();
+    assert_eq!(
+        res.events.len(),
+        1,
+        "the historical event should not be replayed"
+    );
+    assert_eq!(res.events[0].kv.as_ref().unwrap().value, b"new");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_watch_with_projection_should_deliver_projected_values() -> Result<(), Box<dyn Error>>
+{
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let kv_client = cluster.client().await.kv_client();
+    let mut watch_client = WatchClient::connect(cluster.get_client_url(0)).await?;
+
+    let (mut req_tx, req_rx) = channel(3);
+    for watch_id in 1..=3 {
+        req_tx.try_send(xlineapi::WatchRequest {
+            request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                key: b"foo".to_vec(),
+                watch_id,
+                ..Default::default()
+            })),
+        })?;
+    }
+    let mut request = tonic::Request::new(req_rx);
+    let _prev = request.metadata_mut().insert(
+        "watch-projection",
+        "1=json:spec.replicas,2=prefix:8".parse()?,
+    );
+    let mut stream = watch_client.watch(request).await?.into_inner();
+    for _ in 1..=3 {
+        assert!(stream.message().await?.unwrap().created);
+    }
+
+    let value = r#"{"spec":{"replicas":3},"status":{"ready":true}}"#;
+    kv_client.put(PutRequest::new("foo", value)).await?;
+    let mut delivered = HashMap::new();
+    for _ in 1..=3 {
+        let res = stream.message().await?.unwrap();
+        assert_eq!(res.events.len(), 1);
+        let kv = res.events[0].kv.as_ref().unwrap();
+        assert_eq!(kv.key, b"foo");
+        let _prev = delivered.insert(res.watch_id, kv.value.clone());
+    }
+    assert_eq!(delivered[&1], b"3");
+    assert_eq!(delivered[&2], br#"{"spec":"#);
+    // a watch without a projection delivers the full value
+    assert_eq!(delivered[&3], value.as_bytes());
+
+    // an invalid projection is rejected
+    let (_req_tx, req_rx) = channel::<xlineapi::WatchRequest>(1);
+    let mut request = tonic::Request::new(req_rx);
+    let _prev = request
+        .metadata_mut()
+        .insert("watch-projection", "1=script:rm".parse()?);
+    let err = watch_client.watch(request).await.unwrap_err();
+    assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_watch_should_annotate_recreated_keys() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let kv_client = cluster.client().await.kv_client();
+    let mut watch_client = WatchClient::connect(cluster.get_client_url(0)).await?;
+
+    let mut streams = Vec::new();
+    for annotated in [true, false] {
+        let (mut req_tx, req_rx) = channel(1);
+        req_tx.try_send(xlineapi::WatchRequest {
+            request_union: Some(RequestUnion::CreateRequest(WatchCreateRequest {
+                key: b"foo".to_vec(),
+                ..Default::default()
+            })),
+        })?;
+        let mut request = tonic::Request::new(req_rx);
+        if annotated {
+            let _prev = request
+                .metadata_mut()
+                .insert("watch-recreation", "true".parse()?);
+        }
+        let mut stream = watch_client.watch(request).await?.into_inner();
+        assert!(stream.message().await?.unwrap().created);
+        streams.push((req_tx, stream));
+    }
+
+    kv_client.put(PutRequest::new("foo", "v1")).await?;
+    kv_client.put(PutRequest::new("foo", "v2")).await?;
+    let deleted = kv_client
+        .delete(DeleteRangeRequest::new("foo"))
+        .await?
+        .header
+        .unwrap()
+        .revision;
+    kv_client.put(PutRequest::new("foo", "v3")).await?;
+
+    for (annotated, (_req_tx, stream)) in [true, false].into_iter().zip(streams.iter_mut()) {
+        let mut events = Vec::new();
+        while events.len() < 4 {
+            events.extend(stream.message().await?.unwrap().events);
+        }
+        let (created, recreated) = (&events[0], &events[3]);
+        let first = created.kv.as_ref().unwrap();
+        let kv = recreated.kv.as_ref().unwrap();
+        assert!(created.is_create() && !created.is_recreate());
+        assert!(!events[1].is_create());
+        assert!(recreated.is_create());
+        // the recreated key starts a new generation
+        assert_eq!(kv.value, b"v3");
+        assert_eq!(kv.version, 1);
+        assert_eq!(kv.create_revision, kv.mod_revision);
+        assert!(kv.create_revision > first.create_revision);
+        assert!(created.prev_kv.is_none());
+        if annotated {
+            assert!(recreated.is_recreate());
+            let tombstone = recreated.prev_kv.as_ref().unwrap();
+            assert_eq!(tombstone.key, b"foo");
+            assert_eq!(tombstone.mod_revision, deleted);
+            assert_eq!(tombstone.version, 0);
+        } else {
+            assert!(!recreated.is_recreate());
+            assert!(recreated.prev_kv.is_none());
+        }
+    }
+
+    Ok(())
+}
+
+/// The max send message size to clients of the clusters delivering oversized events
+const WATCH_MESSAGE_SIZE: usize = 64 * 1024;
+
+/// Start a cluster which delivers the oversized watch events in the given way
+async fn cluster_with_oversized_event(oversized_event: OversizedWatchEvent) -> Cluster {
+    let message_size = MessageSizeConfig::new(
+        u64::try_from(WATCH_MESSAGE_SIZE).unwrap(),
+        4 * 1024 * 1024,
+        0,
+        4 * 1024 * 1024,
+    );
+    let cluster_config = ClusterConfig::new(
+        "default".to_owned(),
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        HashMap::new(),
+        false,
+        CurpConfig::default(),
+        ClientConfig::default(),
+        ServerTimeout::default(),
+        InitialClusterState::New,
+        message_size,
+        HashMap::new(),
+        Vec::new(),
+        Vec::new(),
+    );
+    let configs = iter::repeat_with(|| {
+        XlineServerConfig::new(
+            cluster_config.clone(),
+            StorageConfig::default(),
+            LogConfig::default(),
+            TraceConfig::default(),
+            AuthConfig::default(),
+            CompactConfig::default(),
+            TlsConfig::default(),
+            MetricsConfig::default(),
+            WatchConfig::new(
+                false,
+                WatchHistoryReplay::Allow,
+                oversized_event,
+                default_min_watch_buffer_depth(),
+                default_max_watch_buffer_depth(),
+            ),
+            KvConfig::default(),
+        )
+    })
+    .take(3)
+    .collect();
+    let mut cluster = Cluster::new_with_configs(configs).await;
+    cluster.start().await;
+    cluster
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_oversized_watch_event_should_be_fragmented() -> Result<(), Box<dyn Error>> {
+    let mut cluster = cluster_with_oversized_event(OversizedWatchEvent::Fragment).await;
+    let client = cluster.client().await;
+    let mut watch_client = client.watch_client();
+    let kv_client = client.kv_client();
+
+    let (_watcher, mut stream) = watch_client.watch(WatchRequest::new("foo")).await?;
+    let large_value: Vec<u8> = (0..=u8::MAX).cycle().take(3 * WATCH_MESSAGE_SIZE).collect();
+    kv_client
+        .put(PutRequest::new("foo", large_value.clone()))
+        .await?;
+    let res = stream.message().await?.unwrap();
+    assert!(!res.is_fragment());
+    assert_eq!(res.events.len(), 1);
+    assert_eq!(res.events[0].kv.as_ref().unwrap().value, large_value);
+
+    // the stream keeps working after the oversized event
+    kv_client.put(PutRequest::new("foo", "small")).await?;
+    let res = stream.message().await?.unwrap();
+    assert_eq!(res.events[0].kv.as_ref().unwrap().value, b"small");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_oversized_watch_event_should_be_truncated() -> Result<(), Box<dyn Error>> {
+    let mut cluster = cluster_with_oversized_event(OversizedWatchEvent::Truncate).await;
+    let client = cluster.client().await;
+    let mut watch_client = client.watch_client();
+    let kv_client = client.kv_client();
+
+    let (_watcher, mut stream) = watch_client
+        .watch(WatchRequest::new("foo").with_prev_kv())
+        .await?;
+    let old_value = vec![b'o'; WATCH_MESSAGE_SIZE * 2 / 3];
+    let new_value = vec![b'n'; WATCH_MESSAGE_SIZE * 2 / 3];
+    kv_client
+        .put(PutRequest::new("foo", old_value.clone()))
+        .await?;
+    let res = stream.message().await?.unwrap();
+    assert!(!res.is_truncated());
+    assert_eq!(res.events[0].kv.as_ref().unwrap().value, old_value);
+
+    // the event exceeds the limit with its previous value
+    kv_client
+        .put(PutRequest::new("foo", new_value.clone()))
+        .await?;
+    let res = stream.message().await?.unwrap();
+    assert!(res.is_truncated());
+    let kv = res.events[0].kv.as_ref().unwrap();
+    assert_eq!(kv.key, b"foo");
+    assert!(kv.value.is_empty());
+    let range_res = kv_client
+        .range(RangeRequest::new("foo").with_revision(kv.mod_revision))
+        .await?;
+    assert_eq!(range_res.kvs[0].value, new_value);
+
+    // the stream keeps working after the truncated event
+    kv_client.put(PutRequest::new("foo", "small")).await?;
+    let res = stream.message().await?.unwrap();
+    assert!(!res.is_truncated());
+    assert_eq!(res.events[0].kv.as_ref().unwrap().value, b"small");
+
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[abort_on_panic]
+async fn test_watch_registry_should_reflect_the_active_watches() -> Result<(), Box<dyn Error>> {
+    let mut cluster = Cluster::new(3).await;
+    cluster.start().await;
+    let client_url = cluster.get_client_url(0);
+    let kv_client = cluster.client().await.kv_client();
+    let revision = kv_client
+        .put(PutRequest::new("foo", "bar"))
+        .await?
+        .header
+        .unwrap()
+        .revision;
+
+    let mut watch_client = WatchClient::connect(client_url.clone()).await?;
+    let (mut req_tx, req_rx) = channel(3);
+    let creates = [
+        WatchCreateRequest {
+            key: b"foo".to_vec(),
+            start_revision: revision,
+            watch_id: 1,
+            ..Default::default()
+        },
+        WatchCreateRequest {
+            key: b"app/".to_vec(),
+            range_end: b"app0".to_vec(),
+            filters: vec![1],
+            watch_id: 2,
+            ..Default::default()
+        },
+        WatchCreateRequest {
+            key: b"bar".to_vec(),
+            filters: vec![0, 1],
+            watch_id: 3,
+            ..Default::default()
+        },
+    ];
+    for create in creates {
+        req_tx.try_send(xlineapi::WatchRequest {
+            request_union: Some(RequestUnion::CreateRequest(create)),
+        })?;
+    }
+    let mut stream = watch_client.watch(req_rx).await?.into_inner();
+    let mut created = 0;
+    let mut delivered = false;
+    while created < 3 || !delivered {
+        let res = stream.message().await?.unwrap();
+        if res.created {
+            created += 1;
+        } else {
+            delivered |= res.watch_id == 1 && !res.events.is_empty();
+        }
+    }
+
+    // the registry is listed by pages of 2 watches
+    let mut admin_client = AdminClient::connect(client_url).await?;
+    let mut watches = Vec::new();
+    let mut start_watch_id = 0;
+    loop {
+        let page = admin_client
+            .watch_registry(WatchRegistryRequest {
+                limit: 2,
+                start_watch_id,
+            })
+            .await?
+            .into_inner();
+        assert!(page.watches.len() <= 2);
+        watches.extend(page.watches);
+        if page.next_watch_id == 0 {
+            break;
+        }
+        start_watch_id = page.next_watch_id;
+    }
+    let summary: Vec<_> = watches
+        .iter()
+        .map(|watch| {
+            (
+                watch.watch_id,
+                watch.key.as_slice(),
+                watch.range_end.as_slice(),
+                watch.start_revision,
+                watch.filters.as_slice(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (
+                1,
+                b"foo".as_slice(),
+                b"".as_slice(),
+                revision,
+                [].as_slice()
+            ),
+            (2, b"app/".as_slice(), b"app0".as_slice(), 0, [1].as_slice()),
+            (3, b"bar".as_slice(), b"".as_slice(), 0, [0, 1].as_slice()),
+        ]
+    );
+    assert_eq!(watches[0].delivered_revision, revision);
+    assert_eq!(watches[1].delivered_revision, 0);
+    let owner = &watches[0].owner;
+    assert!(owner.contains(':'), "{owner}");
+    assert!(watches.iter().all(|watch| &watch.owner == owner));
+    assert!(watches.iter().all(|watch| !watch.victim));
+
+    drop(req_tx);
+    drop(stream);
+    Ok(())
+}
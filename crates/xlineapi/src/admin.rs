@@ -0,0 +1,567 @@
+//! Admin rpcs of the server which have no etcd counterpart.
+//!
+//! The rpcs are served by the grpc service `xlinepb.Admin` with the messages of this
+//! module, and they are only allowed to the root user when the auth is enabled. A
+//! read of the admin service is served by the node the client is connected to.
+
+use prost::Message;
+use tonic::codegen::{http, Body, Bytes, StdError};
+
+use crate::server_op::LeaseImport;
+
+/// The grpc service name of the admin rpcs
+pub const ADMIN_SERVICE_NAME: &str = "xlinepb.Admin";
+
+/// The grpc path of the listing of the lease-attached keys
+pub const ATTACHED_KEYS_PATH: &str = "/xlinepb.Admin/AttachedKeys";
+
+/// The grpc path of the inspection of the dedup cache
+pub const DEDUP_CACHE_PATH: &str = "/xlinepb.Admin/DedupCache";
+
+/// The grpc path of the clearing of the dedup cache
+pub const CLEAR_DEDUP_CACHE_PATH: &str = "/xlinepb.Admin/ClearDedupCache";
+
+/// The grpc path of the estimation of a key histogram
+pub const KEY_HISTOGRAM_PATH: &str = "/xlinepb.Admin/KeyHistogram";
+
+/// The grpc path of the report of the password hash rounds
+pub const PASSWORD_HASH_ROUNDS_PATH: &str = "/xlinepb.Admin/PasswordHashRounds";
+
+/// The grpc path of the listing of the active watches
+pub const WATCH_REGISTRY_PATH: &str = "/xlinepb.Admin/WatchRegistry";
+
+/// The grpc path of the listing of the request statistics of the users
+pub const USER_STATS_PATH: &str = "/xlinepb.Admin/UserStats";
+
+/// The grpc path of the reset of the request statistics of the users
+pub const RESET_USER_STATS_PATH: &str = "/xlinepb.Admin/ResetUserStats";
+
+/// The grpc path of the sweep of the expired leases
+pub const SWEEP_EXPIRED_LEASES_PATH: &str = "/xlinepb.Admin/SweepExpiredLeases";
+
+/// The grpc path of the export of the lease state
+pub const EXPORT_LEASES_PATH: &str = "/xlinepb.Admin/ExportLeases";
+
+/// The grpc path of the import of a lease state
+pub const IMPORT_LEASES_PATH: &str = "/xlinepb.Admin/ImportLeases";
+
+/// Lists the keys attached to any lease, grouped by lease id
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct AttachedKeysRequest {
+    /// The max number of leases of the page, 0 means the max allowed by the server
+    #[prost(uint64, tag = "1")]
+    pub limit: u64,
+    /// The token returned by the previous page, empty for the first page
+    #[prost(string, tag = "2")]
+    pub page_token: String,
+}
+
+/// The keys attached to a lease
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct LeaseKeys {
+    /// The lease id
+    #[prost(int64, tag = "1")]
+    pub id: i64,
+    /// The attached keys in ascending order
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub keys: Vec<Vec<u8>>,
+}
+
+/// A page of the keys attached to leases
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct AttachedKeysResponse {
+    /// The revision of the keyspace the page is read from, every page of a listing is
+    /// read from the revision of its first page
+    #[prost(int64, tag = "1")]
+    pub revision: i64,
+    /// The leases with their attached keys in ascending order of lease id
+    #[prost(message, repeated, tag = "2")]
+    pub leases: Vec<LeaseKeys>,
+    /// The token of the next page, empty if this is the last page
+    #[prost(string, tag = "3")]
+    pub next_page_token: String,
+}
+
+/// Inspects the dedup cache of the node, which holds the propose ids of the recent
+/// commands proposed by it as the leader, so that a retried command is not applied
+/// twice. The cache of a follower is empty.
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct DedupCacheRequest {}
+
+/// The occupancy of the dedup cache of the node
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct DedupCacheResponse {
+    /// The number of the propose ids in the cache
+    #[prost(uint64, tag = "1")]
+    pub len: u64,
+}
+
+/// Clears the dedup cache of the node, it's only meant for recovery.
+///
+/// WARNING: a retried command whose propose id is cleared is no longer recognized as
+/// a duplicate, so it may be applied again. The clear is node-local: the caches of
+/// the other nodes are kept, and a node elected as the leader later uses its own.
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct ClearDedupCacheRequest {}
+
+/// The result of a clear of the dedup cache
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct ClearDedupCacheResponse {
+    /// The number of the removed propose ids
+    #[prost(uint64, tag = "1")]
+    pub removed: u64,
+}
+
+/// Estimates how the live keys of a range are distributed, e.g. to choose split
+/// points. The estimation walks a bounded number of the keys of the range from the
+/// in-memory index of the node and samples them, so the counts are approximate once
+/// the range is large, and a range with more keys than the walk is estimated by pages.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct KeyHistogramRequest {
+    /// The first key of the range
+    #[prost(bytes = "vec", tag = "1")]
+    pub key: Vec<u8>,
+    /// The end of the range, with the same meaning as the one of a range request
+    #[prost(bytes = "vec", tag = "2")]
+    pub range_end: Vec<u8>,
+    /// The max number of buckets, it's capped by the server
+    #[prost(uint32, tag = "3")]
+    pub buckets: u32,
+}
+
+/// A bucket of an estimated key histogram
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct KeyBucket {
+    /// The start key of the bucket, inclusive
+    #[prost(bytes = "vec", tag = "1")]
+    pub start: Vec<u8>,
+    /// The end key of the bucket, exclusive
+    #[prost(bytes = "vec", tag = "2")]
+    pub end: Vec<u8>,
+    /// The estimated number of live keys in the bucket
+    #[prost(uint64, tag = "3")]
+    pub count: u64,
+}
+
+/// An estimated key histogram of a range
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct KeyHistogramResponse {
+    /// The buckets holding roughly the same number of keys in ascending order of key
+    #[prost(message, repeated, tag = "1")]
+    pub buckets: Vec<KeyBucket>,
+    /// The key to continue the estimation of the rest of the range from, which is
+    /// the end of the last bucket, empty if the buckets cover the range
+    #[prost(bytes = "vec", tag = "2")]
+    pub next_key: Vec<u8>,
+}
+
+/// Reports how many users have their password hashed by every number of pbkdf2
+/// rounds, e.g. to follow the upgrade of the hashes after the configured rounds are
+/// changed. The users without a password are not counted.
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct PasswordHashRoundsRequest {}
+
+/// The number of users of some pbkdf2 rounds
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct PasswordHashRoundsCount {
+    /// The pbkdf2 rounds
+    #[prost(uint32, tag = "1")]
+    pub rounds: u32,
+    /// The number of users whose password is hashed by the rounds
+    #[prost(uint64, tag = "2")]
+    pub users: u64,
+}
+
+/// The report of the password hash rounds
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct PasswordHashRoundsResponse {
+    /// The user counts in ascending order of rounds
+    #[prost(message, repeated, tag = "1")]
+    pub counts: Vec<PasswordHashRoundsCount>,
+}
+
+/// Lists the active watches of the node, e.g. to spot the stuck or runaway watches.
+/// The watches of the other nodes are not listed.
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct WatchRegistryRequest {
+    /// The max number of watches of the page, 0 means the max allowed by the server
+    #[prost(uint64, tag = "1")]
+    pub limit: u64,
+    /// The watch id to start the page from, 0 for the first page
+    #[prost(int64, tag = "2")]
+    pub start_watch_id: i64,
+}
+
+/// An active watch of the node
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct WatchEntry {
+    /// The watch id
+    #[prost(int64, tag = "1")]
+    pub watch_id: i64,
+    /// The start key of the watched range
+    #[prost(bytes = "vec", tag = "2")]
+    pub key: Vec<u8>,
+    /// The end key of the watched range
+    #[prost(bytes = "vec", tag = "3")]
+    pub range_end: Vec<u8>,
+    /// The start revision, 0 means the watch starts from the revision it is created at
+    #[prost(int64, tag = "4")]
+    pub start_revision: i64,
+    /// The revision of the last events sent to the watch stream, 0 if nothing is sent
+    #[prost(int64, tag = "5")]
+    pub delivered_revision: i64,
+    /// The filter types of the events filtered out
+    #[prost(int32, repeated, tag = "6")]
+    pub filters: Vec<i32>,
+    /// The remote address of the connection owning the watch
+    #[prost(string, tag = "7")]
+    pub owner: String,
+    /// Whether the watch stream doesn't keep up with the events, which are resent later
+    #[prost(bool, tag = "8")]
+    pub victim: bool,
+    /// Whether the start revision is compacted
+    #[prost(bool, tag = "9")]
+    pub compacted: bool,
+}
+
+/// A page of the active watches of the node
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct WatchRegistryResponse {
+    /// The watches in ascending order of watch id
+    #[prost(message, repeated, tag = "1")]
+    pub watches: Vec<WatchEntry>,
+    /// The watch id to start the next page from, 0 if this is the last page
+    #[prost(int64, tag = "2")]
+    pub next_watch_id: i64,
+}
+
+/// Lists the statistics of the kv requests of the users served by the node.
+///
+/// The statistics are node-local: they are kept in memory, so they are lost when the
+/// node restarts, and every node only counts the requests it has served. The requests
+/// without an authenticated user are counted under the empty user name.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct UserStatsRequest {
+    /// The max number of users of the page, 0 means the max allowed by the server
+    #[prost(uint64, tag = "1")]
+    pub limit: u64,
+    /// The user name to start the page from, empty for the first page
+    #[prost(string, tag = "2")]
+    pub start_user: String,
+}
+
+/// The request statistics of a user
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct UserRequestStats {
+    /// The user name
+    #[prost(string, tag = "1")]
+    pub user: String,
+    /// The number of the requests
+    #[prost(uint64, tag = "2")]
+    pub requests: u64,
+    /// The bytes of the keys and values returned to the requests
+    #[prost(uint64, tag = "3")]
+    pub bytes_read: u64,
+    /// The bytes of the keys and values put by the requests
+    #[prost(uint64, tag = "4")]
+    pub bytes_written: u64,
+    /// The number of the failed requests
+    #[prost(uint64, tag = "5")]
+    pub errors: u64,
+}
+
+/// A page of the request statistics of the users
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct UserStatsResponse {
+    /// The statistics in ascending order of user name
+    #[prost(message, repeated, tag = "1")]
+    pub users: Vec<UserRequestStats>,
+    /// The user name to start the next page from, empty if this is the last page
+    #[prost(string, tag = "2")]
+    pub next_user: String,
+}
+
+/// Resets the request statistics of a user of the node, or of all users. The other
+/// nodes keep theirs.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ResetUserStatsRequest {
+    /// The user to reset, it's ignored if `all` is set
+    #[prost(string, tag = "1")]
+    pub user: String,
+    /// Whether to reset all users
+    #[prost(bool, tag = "2")]
+    pub all: bool,
+}
+
+/// The result of a reset of the request statistics
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ResetUserStatsResponse {
+    /// The statistics of the user before the reset, so that none is lost between a
+    /// read and the reset. It's unset if all users are reset or the user has none.
+    #[prost(message, optional, tag = "1")]
+    pub stats: Option<UserRequestStats>,
+    /// The number of the reset users
+    #[prost(uint64, tag = "2")]
+    pub reset: u64,
+}
+
+/// Revokes all the leases which have expired right away instead of waiting for the
+/// next tick of the expiry task of the leader. It's only served by the leader, and
+/// it's safe to be retried since a lease is only revoked once.
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct SweepExpiredLeasesRequest {}
+
+/// The result of a sweep of the expired leases
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct SweepExpiredLeasesResponse {
+    /// The number of the leases revoked by the sweep, the ones revoked by the expiry
+    /// task in the meantime are not counted
+    #[prost(uint64, tag = "1")]
+    pub revoked: u64,
+}
+
+/// Exports the full lease state, i.e. every lease with its ttl, its remaining ttl
+/// and its attached keys, e.g. to restore it together with a kv snapshot. It's only
+/// served by the leader, where the remaining ttls are known.
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct ExportLeasesRequest {}
+
+/// The exported lease state
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ExportLeasesResponse {
+    /// The revision of the keyspace the attached keys match, so the state is restored
+    /// together with a kv snapshot taken at it
+    #[prost(int64, tag = "1")]
+    pub revision: i64,
+    /// The leases in ascending order of lease id
+    #[prost(message, repeated, tag = "2")]
+    pub leases: Vec<LeaseImport>,
+}
+
+/// Imports a lease state exported from another cluster, after the keyspace is
+/// restored. The import is applied as a whole at one revision, or nothing is changed,
+/// see `ImportLeasesOp` for how every lease is imported.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ImportLeasesRequest {
+    /// The leases to import, at most `MAX_IMPORTED_LEASES`
+    #[prost(message, repeated, tag = "1")]
+    pub leases: Vec<LeaseImport>,
+}
+
+/// The result of an import of a lease state
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ImportLeasesResponse {
+    /// The revision of the import
+    #[prost(int64, tag = "1")]
+    pub revision: i64,
+    /// The ids of the leases granted again
+    #[prost(int64, repeated, tag = "2")]
+    pub imported: Vec<i64>,
+    /// The ids of the expired leases whose keys are deleted
+    #[prost(int64, repeated, tag = "3")]
+    pub revoked: Vec<i64>,
+}
+
+/// Client of the admin rpcs
+#[derive(Debug, Clone)]
+pub struct AdminClient<T> {
+    /// The inner grpc client
+    inner: tonic::client::Grpc<T>,
+}
+
+impl AdminClient<tonic::transport::Channel> {
+    /// Connect to the admin service of a server
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::transport::Error` if the server can't be connected
+    #[inline]
+    pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+    where
+        D: TryInto<tonic::transport::Endpoint>,
+        D::Error: Into<StdError>,
+    {
+        let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+        Ok(Self::new(conn))
+    }
+}
+
+impl<T> AdminClient<T>
+where
+    T: tonic::client::GrpcService<tonic::body::BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    /// New `AdminClient` of a grpc service
+    #[inline]
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner: tonic::client::Grpc::new(inner),
+        }
+    }
+
+    /// Call a unary admin rpc
+    async fn unary<Req, Res>(
+        &mut self,
+        request: impl tonic::IntoRequest<Req>,
+        path: &'static str,
+    ) -> Result<tonic::Response<Res>, tonic::Status>
+    where
+        Req: Message + Send + Sync + 'static,
+        Res: Message + Default + Send + Sync + 'static,
+    {
+        self.inner.ready().await.map_err(|e| {
+            tonic::Status::new(
+                tonic::Code::Unknown,
+                format!("Service was not ready: {}", e.into()),
+            )
+        })?;
+        let codec = tonic::codec::ProstCodec::default();
+        let path = http::uri::PathAndQuery::from_static(path);
+        self.inner.unary(request.into_request(), path, codec).await
+    }
+
+    /// List a page of the keys attached to any lease
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the page can't be listed
+    #[inline]
+    pub async fn attached_keys(
+        &mut self,
+        request: impl tonic::IntoRequest<AttachedKeysRequest>,
+    ) -> Result<tonic::Response<AttachedKeysResponse>, tonic::Status> {
+        self.unary(request, ATTACHED_KEYS_PATH).await
+    }
+
+    /// Inspect the dedup cache of the node
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the cache can't be inspected
+    #[inline]
+    pub async fn dedup_cache(
+        &mut self,
+        request: impl tonic::IntoRequest<DedupCacheRequest>,
+    ) -> Result<tonic::Response<DedupCacheResponse>, tonic::Status> {
+        self.unary(request, DEDUP_CACHE_PATH).await
+    }
+
+    /// Clear the dedup cache of the node, see `ClearDedupCacheRequest` for its risk
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the cache can't be cleared
+    #[inline]
+    pub async fn clear_dedup_cache(
+        &mut self,
+        request: impl tonic::IntoRequest<ClearDedupCacheRequest>,
+    ) -> Result<tonic::Response<ClearDedupCacheResponse>, tonic::Status> {
+        self.unary(request, CLEAR_DEDUP_CACHE_PATH).await
+    }
+
+    /// Estimate the key histogram of a range
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the histogram can't be estimated
+    #[inline]
+    pub async fn key_histogram(
+        &mut self,
+        request: impl tonic::IntoRequest<KeyHistogramRequest>,
+    ) -> Result<tonic::Response<KeyHistogramResponse>, tonic::Status> {
+        self.unary(request, KEY_HISTOGRAM_PATH).await
+    }
+
+    /// Report the number of users of every password hash rounds
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the report can't be made
+    #[inline]
+    pub async fn password_hash_rounds(
+        &mut self,
+        request: impl tonic::IntoRequest<PasswordHashRoundsRequest>,
+    ) -> Result<tonic::Response<PasswordHashRoundsResponse>, tonic::Status> {
+        self.unary(request, PASSWORD_HASH_ROUNDS_PATH).await
+    }
+
+    /// List a page of the active watches of the node
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the page can't be listed
+    #[inline]
+    pub async fn watch_registry(
+        &mut self,
+        request: impl tonic::IntoRequest<WatchRegistryRequest>,
+    ) -> Result<tonic::Response<WatchRegistryResponse>, tonic::Status> {
+        self.unary(request, WATCH_REGISTRY_PATH).await
+    }
+
+    /// List a page of the request statistics of the users of the node
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the page can't be listed
+    #[inline]
+    pub async fn user_stats(
+        &mut self,
+        request: impl tonic::IntoRequest<UserStatsRequest>,
+    ) -> Result<tonic::Response<UserStatsResponse>, tonic::Status> {
+        self.unary(request, USER_STATS_PATH).await
+    }
+
+    /// Reset the request statistics of a user of the node, or of all users
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the statistics can't be reset
+    #[inline]
+    pub async fn reset_user_stats(
+        &mut self,
+        request: impl tonic::IntoRequest<ResetUserStatsRequest>,
+    ) -> Result<tonic::Response<ResetUserStatsResponse>, tonic::Status> {
+        self.unary(request, RESET_USER_STATS_PATH).await
+    }
+
+    /// Revoke all the expired leases, it must be sent to the leader
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the leases can't be swept
+    #[inline]
+    pub async fn sweep_expired_leases(
+        &mut self,
+        request: impl tonic::IntoRequest<SweepExpiredLeasesRequest>,
+    ) -> Result<tonic::Response<SweepExpiredLeasesResponse>, tonic::Status> {
+        self.unary(request, SWEEP_EXPIRED_LEASES_PATH).await
+    }
+
+    /// Export the full lease state, it must be sent to the leader
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the state can't be exported
+    #[inline]
+    pub async fn export_leases(
+        &mut self,
+        request: impl tonic::IntoRequest<ExportLeasesRequest>,
+    ) -> Result<tonic::Response<ExportLeasesResponse>, tonic::Status> {
+        self.unary(request, EXPORT_LEASES_PATH).await
+    }
+
+    /// Import a lease state exported from another cluster
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the state can't be imported
+    #[inline]
+    pub async fn import_leases(
+        &mut self,
+        request: impl tonic::IntoRequest<ImportLeasesRequest>,
+    ) -> Result<tonic::Response<ImportLeasesResponse>, tonic::Status> {
+        self.unary(request, IMPORT_LEASES_PATH).await
+    }
+}
@@ -8,10 +8,11 @@ use curp_external_api::cmd::{ConflictCheck, PbCodec, PbSerializeError};
 use itertools::Itertools;
 use prost::Message;
 use serde::{Deserialize, Serialize};
+use utils::redaction::redact_key;
 
 use crate::{
-    execute_error::ExecuteError, AuthInfo, PbCommand, PbCommandResponse, PbKeyRange,
-    PbSyncResponse, Request, RequestWrapper, ResponseWrapper,
+    execute_error::ExecuteError, server_op::ServerOp, AuthInfo, PbCommand, PbCommandResponse,
+    PbKeyRange, PbSyncResponse, Request, RequestWrapper, ResponseWrapper,
 };
 
 /// The curp client trait object on the command of xline
@@ -24,7 +25,7 @@ const UNBOUNDED: &[u8] = &[0_u8];
 const ONE_KEY: &[u8] = &[];
 
 /// Key Range for Command
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Hash)]
+#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
 pub struct KeyRange {
     /// Start of range
     key: Bound<Vec<u8>>,
@@ -32,6 +33,21 @@ pub struct KeyRange {
     range_end: Bound<Vec<u8>>,
 }
 
+impl std::fmt::Debug for KeyRange {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bound = |bound: &Bound<Vec<u8>>| match *bound {
+            Bound::Included(ref key) => format!("Included({})", redact_key(key)),
+            Bound::Excluded(ref key) => format!("Excluded({})", redact_key(key)),
+            Bound::Unbounded => "Unbounded".to_owned(),
+        };
+        f.debug_struct("KeyRange")
+            .field("key", &format_args!("{}", bound(&self.key)))
+            .field("range_end", &format_args!("{}", bound(&self.range_end)))
+            .finish()
+    }
+}
+
 impl KeyRange {
     /// New `KeyRange`
     #[inline]
@@ -230,6 +246,9 @@ pub fn get_lease_ids(wrapper: &RequestWrapper) -> HashSet<i64> {
             HashSet::from_iter(vec![req.lease])
         }
         RequestWrapper::TxnRequest(ref txn_req) => {
+            if let Some(ids) = ServerOp::lease_ids(txn_req) {
+                return ids.into_iter().collect();
+            }
             let mut lease_ids = HashSet::new();
             let mut reqs = txn_req
                 .success
@@ -417,6 +436,12 @@ impl Command {
         self.auth_info = Some(auth_info)
     }
 
+    /// Add the key ranges that the command conflicts with besides the ones of its request
+    #[inline]
+    pub fn extend_keys(&mut self, keys: impl IntoIterator<Item = KeyRange>) {
+        self.keys.extend(keys);
+    }
+
     /// need check quota
     #[must_use]
     #[inline]
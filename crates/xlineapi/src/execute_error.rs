@@ -7,6 +7,30 @@ use thiserror::Error;
 
 use crate::{PbExecuteError, PbExecuteErrorOuter, PbRevisions, PbUserRole};
 
+/// Marker of a `ResponseTooLarge` error carried by the protobuf `DbError`, since it
+/// has no dedicated protobuf variant
+const RESPONSE_TOO_LARGE_MARKER: &str = "response too large, budget: ";
+
+/// Marker of a `RoleQuotaExceeded` error carried by the protobuf `DbError`, since it
+/// has no dedicated protobuf variant
+const ROLE_QUOTA_EXCEEDED_MARKER: &str = "role quota exceeded, role: ";
+
+/// Marker of a `TooManyResults` error carried by the protobuf `DbError`, since it
+/// has no dedicated protobuf variant
+const TOO_MANY_RESULTS_MARKER: &str = "too many results, max: ";
+
+/// Marker of a `Rejected` error carried by the protobuf `DbError`, since it has no
+/// dedicated protobuf variant
+const REJECTED_MARKER: &str = "command rejected, reason: ";
+
+/// Marker of a `TooManyRoles` error carried by the protobuf `DbError`, since it has no
+/// dedicated protobuf variant
+const TOO_MANY_ROLES_MARKER: &str = "too many roles, max: ";
+
+/// Marker of a `TooManyPermissions` error carried by the protobuf `DbError`, since it
+/// has no dedicated protobuf variant
+const TOO_MANY_PERMISSIONS_MARKER: &str = "too many permissions, max: ";
+
 /// Error met when executing commands
 #[cfg_attr(test, derive(strum_macros::EnumIter))]
 #[derive(Error, Debug, Clone, Serialize, Deserialize)]
@@ -99,6 +123,36 @@ pub enum ExecuteError {
     /// no space left in quota
     #[error("no space left in quota")]
     Nospace,
+
+    /// The response exceeds the memory budget of a request
+    #[error("response exceeds the memory budget of {0} bytes, use a limit or paginate the range")]
+    ResponseTooLarge(u64),
+
+    /// The range returns more keys than the max result count
+    #[error("range returns more than {0} keys, add a limit to the range or paginate it")]
+    TooManyResults(usize),
+
+    /// The write exceeds the quota of a role
+    #[error("the quota of role {0} is exceeded")]
+    RoleQuotaExceeded(String),
+
+    /// The command is rejected, e.g. by a command hook or a savepoint
+    #[error("the command is rejected: {0}")]
+    Rejected(String),
+
+    /// The user already has the max number of roles
+    #[error("user {0} already has the max number of {1} roles")]
+    TooManyRoles(String, usize),
+
+    /// The role already has the max number of permissions
+    #[error("role {0} already has the max number of {1} permissions")]
+    TooManyPermissions(String, usize),
+}
+
+/// Parse the max and the name of a limit error carried by the protobuf `DbError`
+fn parse_limit(e: &str, marker: &str) -> Option<(String, usize)> {
+    let (max, name) = e.strip_prefix(marker)?.split_once(", name: ")?;
+    Some((name.to_owned(), max.parse().ok()?))
 }
 
 impl From<PbExecuteError> for ExecuteError {
@@ -137,7 +191,33 @@ impl From<PbExecuteError> for ExecuteError {
             PbExecuteError::TokenOldRevision(revs) => {
                 ExecuteError::TokenOldRevision(revs.required_revision, revs.current_revision)
             }
-            PbExecuteError::DbError(e) => ExecuteError::DbError(e),
+            PbExecuteError::DbError(e) => {
+                if let Some(role) = e.strip_prefix(ROLE_QUOTA_EXCEEDED_MARKER) {
+                    return ExecuteError::RoleQuotaExceeded(role.to_owned());
+                }
+                if let Some(reason) = e.strip_prefix(REJECTED_MARKER) {
+                    return ExecuteError::Rejected(reason.to_owned());
+                }
+                if let Some((user, max)) = parse_limit(&e, TOO_MANY_ROLES_MARKER) {
+                    return ExecuteError::TooManyRoles(user, max);
+                }
+                if let Some((role, max)) = parse_limit(&e, TOO_MANY_PERMISSIONS_MARKER) {
+                    return ExecuteError::TooManyPermissions(role, max);
+                }
+                if let Some(max) = e
+                    .strip_prefix(TOO_MANY_RESULTS_MARKER)
+                    .and_then(|max| max.parse().ok())
+                {
+                    return ExecuteError::TooManyResults(max);
+                }
+                match e
+                    .strip_prefix(RESPONSE_TOO_LARGE_MARKER)
+                    .and_then(|budget| budget.parse().ok())
+                {
+                    Some(budget) => ExecuteError::ResponseTooLarge(budget),
+                    None => ExecuteError::DbError(e),
+                }
+            }
             PbExecuteError::PermissionDenied(_) => ExecuteError::PermissionDenied,
             PbExecuteError::Nospace(_) => ExecuteError::Nospace,
         }
@@ -192,6 +272,24 @@ impl From<ExecuteError> for PbExecuteError {
             ExecuteError::DbError(e) => PbExecuteError::DbError(e),
             ExecuteError::PermissionDenied => PbExecuteError::PermissionDenied(()),
             ExecuteError::Nospace => PbExecuteError::Nospace(()),
+            ExecuteError::ResponseTooLarge(budget) => {
+                PbExecuteError::DbError(format!("{RESPONSE_TOO_LARGE_MARKER}{budget}"))
+            }
+            ExecuteError::RoleQuotaExceeded(role) => {
+                PbExecuteError::DbError(format!("{ROLE_QUOTA_EXCEEDED_MARKER}{role}"))
+            }
+            ExecuteError::TooManyResults(max) => {
+                PbExecuteError::DbError(format!("{TOO_MANY_RESULTS_MARKER}{max}"))
+            }
+            ExecuteError::Rejected(reason) => {
+                PbExecuteError::DbError(format!("{REJECTED_MARKER}{reason}"))
+            }
+            ExecuteError::TooManyRoles(user, max) => {
+                PbExecuteError::DbError(format!("{TOO_MANY_ROLES_MARKER}{max}, name: {user}"))
+            }
+            ExecuteError::TooManyPermissions(role, max) => {
+                PbExecuteError::DbError(format!("{TOO_MANY_PERMISSIONS_MARKER}{max}, name: {role}"))
+            }
         }
     }
 }
@@ -309,6 +407,16 @@ impl From<ExecuteError> for tonic::Status {
                 (tonic::Code::FailedPrecondition, err.to_string())
             }
             ExecuteError::TokenNotProvided => (tonic::Code::InvalidArgument, err.to_string()),
+            ExecuteError::ResponseTooLarge(_)
+            | ExecuteError::TooManyResults(_)
+            | ExecuteError::RoleQuotaExceeded(_) => {
+                (tonic::Code::ResourceExhausted, err.to_string())
+            }
+            ExecuteError::Rejected(_)
+            | ExecuteError::TooManyRoles(_, _)
+            | ExecuteError::TooManyPermissions(_, _) => {
+                (tonic::Code::FailedPrecondition, err.to_string())
+            }
             ExecuteError::DbError(_) => (tonic::Code::Internal, err.to_string()),
         };
 
@@ -330,4 +438,61 @@ mod test {
             assert!(matches!(err, _decoded_err));
         }
     }
+
+    #[test]
+    fn response_too_large_should_survive_serialization() {
+        let err = ExecuteError::ResponseTooLarge(1024);
+        let decoded = <ExecuteError as PbCodec>::decode(&err.encode()).unwrap();
+        assert!(matches!(decoded, ExecuteError::ResponseTooLarge(1024)));
+        assert_eq!(
+            tonic::Status::from(decoded).code(),
+            tonic::Code::ResourceExhausted
+        );
+    }
+
+    #[test]
+    fn too_many_results_should_survive_serialization() {
+        let err = ExecuteError::TooManyResults(100);
+        let decoded = <ExecuteError as PbCodec>::decode(&err.encode()).unwrap();
+        assert!(matches!(decoded, ExecuteError::TooManyResults(100)));
+        let status = tonic::Status::from(decoded);
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert!(status.message().contains("add a limit"));
+    }
+
+    #[test]
+    fn role_quota_exceeded_should_survive_serialization() {
+        let err = ExecuteError::RoleQuotaExceeded("tenant".to_owned());
+        let decoded = <ExecuteError as PbCodec>::decode(&err.encode()).unwrap();
+        assert!(matches!(decoded, ExecuteError::RoleQuotaExceeded(ref role) if role == "tenant"));
+        assert_eq!(
+            tonic::Status::from(decoded).code(),
+            tonic::Code::ResourceExhausted
+        );
+    }
+
+    #[test]
+    fn grant_limits_should_survive_serialization() {
+        let err = ExecuteError::TooManyRoles("u, name: x".to_owned(), 2);
+        let decoded = <ExecuteError as PbCodec>::decode(&err.encode()).unwrap();
+        assert!(matches!(decoded, ExecuteError::TooManyRoles(ref user, 2) if user == "u, name: x"));
+        let err = ExecuteError::TooManyPermissions("r".to_owned(), 3);
+        let decoded = <ExecuteError as PbCodec>::decode(&err.encode()).unwrap();
+        assert!(matches!(decoded, ExecuteError::TooManyPermissions(ref role, 3) if role == "r"));
+        assert_eq!(
+            tonic::Status::from(decoded).code(),
+            tonic::Code::FailedPrecondition
+        );
+    }
+
+    #[test]
+    fn rejected_should_survive_serialization() {
+        let err = ExecuteError::Rejected("read only".to_owned());
+        let decoded = <ExecuteError as PbCodec>::decode(&err.encode()).unwrap();
+        assert!(matches!(decoded, ExecuteError::Rejected(ref reason) if reason == "read only"));
+        assert_eq!(
+            tonic::Status::from(decoded).code(),
+            tonic::Code::FailedPrecondition
+        );
+    }
 }
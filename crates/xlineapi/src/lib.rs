@@ -172,10 +172,13 @@
     )
 )]
 
+pub mod admin;
 pub mod command;
 pub mod execute_error;
 pub mod interval;
+pub mod member_watch;
 pub mod request_validation;
+pub mod server_op;
 
 mod etcdserverpb {
     tonic::include_proto!("etcdserverpb");
@@ -208,7 +211,11 @@ mod errorpb {
 use std::fmt::Display;
 
 use command::KeyRange;
-use utils::write_vec;
+use server_op::ServerOp;
+use utils::{
+    redaction::{redact_key, redact_value},
+    write_vec,
+};
 
 pub use self::{
     authpb::{permission::Type, Permission, Role, User, UserAddOptions},
@@ -365,6 +372,7 @@ impl CommandKeys for TxnRequest {
         let mut keys: Vec<_> = self
             .compare
             .iter()
+            .filter(|cmp| !ServerOp::is_marker(cmp))
             .map(|cmp| KeyRange::new(cmp.key.as_slice(), cmp.range_end.as_slice()))
             .collect();
 
@@ -408,6 +416,15 @@ impl RequestWrapper {
     /// Get the backend of the request
     pub fn backend(&self) -> RequestBackend {
         match *self {
+            // a batch of lease grants or an import of leases is carried by a txn but applied
+            // by the lease store
+            RequestWrapper::TxnRequest(ref req) if ServerOp::lease_ids(req).is_some() => {
+                RequestBackend::Lease
+            }
+            // a password re-hash is applied by the auth store
+            RequestWrapper::TxnRequest(ref req) if ServerOp::password_rehash(req).is_some() => {
+                RequestBackend::Auth
+            }
             RequestWrapper::PutRequest(_)
             | RequestWrapper::RangeRequest(_)
             | RequestWrapper::DeleteRangeRequest(_)
@@ -485,12 +502,15 @@ impl RequestWrapper {
         )
     }
 
-    /// Check whether this auth request should skip the revision or not
+    /// Check whether this auth request should skip the revision or not, a password
+    /// re-hash is the only txn applied by the auth store
     pub fn skip_auth_revision(&self) -> bool {
         self.is_auth_read_request()
             || matches!(
                 *self,
-                RequestWrapper::AuthEnableRequest(_) | RequestWrapper::AuthenticateRequest(_)
+                RequestWrapper::AuthEnableRequest(_)
+                    | RequestWrapper::AuthenticateRequest(_)
+                    | RequestWrapper::TxnRequest(_)
             )
     }
 
@@ -500,7 +520,11 @@ impl RequestWrapper {
             RequestWrapper::RangeRequest(_)
             | RequestWrapper::LeaseGrantRequest(_)
             | RequestWrapper::CompactionRequest(_) => true,
-            RequestWrapper::TxnRequest(req) => req.is_read_only(),
+            RequestWrapper::TxnRequest(req) => {
+                req.is_read_only()
+                    || ServerOp::granted_leases(req).is_some()
+                    || ServerOp::sets_member_tags(req)
+            }
             _ => false,
         }
     }
@@ -528,10 +552,11 @@ impl RequestWrapper {
     }
 
     pub fn is_lease_write_request(&self) -> bool {
-        matches!(
-            *self,
-            RequestWrapper::LeaseGrantRequest(_) | RequestWrapper::LeaseRevokeRequest(_)
-        )
+        match *self {
+            RequestWrapper::LeaseGrantRequest(_) | RequestWrapper::LeaseRevokeRequest(_) => true,
+            RequestWrapper::TxnRequest(ref req) => ServerOp::lease_ids(req).is_some(),
+            _ => false,
+        }
     }
 
     pub fn is_alarm_request(&self) -> bool {
@@ -682,11 +707,63 @@ impl Event {
             .unwrap_or_else(|| panic!("kv must be Some"));
         matches!(self.r#type(), EventType::Put) && kv.create_revision == kv.mod_revision
     }
+
+    /// Checks whether the event creates a key deleted before. It is only known by the
+    /// watches annotating the recreations, where such an event carries the tombstone
+    /// of the deletion as its `prev_kv`.
+    pub fn is_recreate(&self) -> bool {
+        self.is_create() && self.prev_kv.as_ref().is_some_and(|prev| prev.version == 0)
+    }
+}
+
+/// The prefix of the savepoint keys, a savepoint pins the create revision of its key
+/// against compaction until the key is deleted, a compaction beyond the oldest
+/// savepoint is rejected
+pub const SAVEPOINT_PREFIX: &[u8] = b"\0savepoint/";
+
+/// The `cancel_reason` of a watch response which is not canceled, meaning its only
+/// event is a fragment of an event exceeding the message size limit, and the next
+/// response carries the rest of the value of the event
+pub const WATCH_FRAGMENT_MARKER: &str = "fragment";
+
+/// The `cancel_reason` of a watch response which is not canceled, meaning the values
+/// of its only event are dropped since the event exceeds the message size limit, and
+/// the value is to be fetched by a range at the revision of the event
+pub const WATCH_TRUNCATED_MARKER: &str = "truncated";
+
+impl WatchResponse {
+    /// Checks whether the response is a fragment of an oversized event
+    pub fn is_fragment(&self) -> bool {
+        !self.canceled && self.cancel_reason == WATCH_FRAGMENT_MARKER
+    }
+
+    /// Checks whether the event of the response is truncated
+    pub fn is_truncated(&self) -> bool {
+        !self.canceled && self.cancel_reason == WATCH_TRUNCATED_MARKER
+    }
+
+    /// Merge the next fragment of an oversized event into this fragment, the merged
+    /// response is complete when it is no longer a fragment
+    pub fn merge_fragment(&mut self, next: WatchResponse) {
+        let value = next
+            .events
+            .into_iter()
+            .filter_map(|ev| ev.kv)
+            .flat_map(|kv| kv.value);
+        if let Some(kv) = self.events.first_mut().and_then(|ev| ev.kv.as_mut()) {
+            kv.value.extend(value);
+        }
+        self.cancel_reason = next.cancel_reason;
+    }
 }
 
 impl TxnRequest {
     /// Checks whether a given `TxnRequest` is read-only or not.
     pub fn is_read_only(&self) -> bool {
+        // a server op writes whatever its footprint is
+        if self.compare.iter().any(ServerOp::is_marker) {
+            return false;
+        }
         let read_only_checker = |req: &RequestOp| {
             if let Some(ref request) = req.request {
                 match request {
@@ -774,9 +851,9 @@ impl Display for PutRequest {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "PutRequest {{ key: {:?}, value: {:?}, lease: {:?}, prev_kv: {:?}, ignore_value: {:?}, ignore_lease: {:?} }}",
-            String::from_utf8_lossy(&self.key),
-            String::from_utf8_lossy(&self.value),
+            "PutRequest {{ key: {}, value: {}, lease: {:?}, prev_kv: {:?}, ignore_value: {:?}, ignore_lease: {:?} }}",
+            redact_key(&self.key),
+            redact_value(&self.value),
             self.lease, self.prev_kv,
             self.ignore_value,
             self.ignore_lease)
@@ -805,9 +882,9 @@ impl Display for RangeRequest {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "RangeRequest {{ key: {:?}, range_end: {:?}, limit: {:?}, revision: {:?}, sort_order: {:?}, ",
-            String::from_utf8_lossy(&self.key),
-            String::from_utf8_lossy(&self.range_end),
+            "RangeRequest {{ key: {}, range_end: {}, limit: {:?}, revision: {:?}, sort_order: {:?}, ",
+            redact_key(&self.key),
+            redact_key(&self.range_end),
             self.limit,
             self.revision,
             self.sort_order,
@@ -853,9 +930,9 @@ impl Display for DeleteRangeRequest {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "DeleteRangeRequest {{ key: {:?}, range_end: {:?}, prev_kv: {:?} }}",
-            String::from_utf8_lossy(&self.key),
-            String::from_utf8_lossy(&self.range_end),
+            "DeleteRangeRequest {{ key: {}, range_end: {}, prev_kv: {:?} }}",
+            redact_key(&self.key),
+            redact_key(&self.range_end),
             self.prev_kv
         )
     }
@@ -913,10 +990,10 @@ impl Display for Permission {
         };
         write!(
             f,
-            "Permission {{ permType: {:?}, key: {:?}, range_end: {:?} }}",
+            "Permission {{ permType: {:?}, key: {}, range_end: {} }}",
             perm,
-            String::from_utf8_lossy(&self.key),
-            String::from_utf8_lossy(&self.range_end)
+            redact_key(&self.key),
+            redact_key(&self.range_end)
         )
     }
 }
@@ -940,13 +1017,19 @@ impl Display for Compare {
         };
         write!(
             f,
-            "Compare {{ result: {:?}, target: {:?}, key: {}, range_end: {}, target_union: {:?} }}",
+            "Compare {{ result: {:?}, target: {:?}, key: {}, range_end: {}, target_union: ",
             result,
             target,
-            String::from_utf8_lossy(&self.key),
-            String::from_utf8_lossy(&self.range_end),
-            self.target_union
-        )
+            redact_key(&self.key),
+            redact_key(&self.range_end),
+        )?;
+        match self.target_union {
+            Some(TargetUnion::Value(ref value)) => {
+                write!(f, "Some(Value({}))", redact_value(value))?
+            }
+            ref target_union => write!(f, "{target_union:?}")?,
+        }
+        write!(f, " }}")
     }
 }
 
@@ -988,8 +1071,8 @@ impl Display for AuthRoleRevokePermissionRequest {
             f,
             "AuthRoleRevokePermissionRequest {{ role: {}, key: {}, range_end: {} }}",
             self.role,
-            String::from_utf8_lossy(&self.key),
-            String::from_utf8_lossy(&self.key),
+            redact_key(&self.key),
+            redact_key(&self.range_end),
         )
     }
 }
@@ -1020,12 +1103,12 @@ impl Display for KeyValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "KeyValue {{ key: {:?}, create_revision: {}, mod_revision: {}, version: {}, value: {:?}, lease: {} }}",
-            String::from_utf8_lossy(&self.key),
+            "KeyValue {{ key: {}, create_revision: {}, mod_revision: {}, version: {}, value: {}, lease: {} }}",
+            redact_key(&self.key),
             self.create_revision,
             self.mod_revision,
             self.version,
-            String::from_utf8_lossy(&self.value),
+            redact_value(&self.value),
             self.lease,
         )
     }
@@ -1061,6 +1144,22 @@ impl Display for AuthRoleListResponse {
     }
 }
 
+impl Display for RequestWrapper {
+    #[allow(clippy::wildcard_enum_match_arm)] // the other requests don't carry kvs
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            RequestWrapper::RangeRequest(ref req) => write!(f, "{req}"),
+            RequestWrapper::PutRequest(ref req) => write!(f, "{req}"),
+            RequestWrapper::DeleteRangeRequest(ref req) => write!(f, "{req}"),
+            RequestWrapper::TxnRequest(ref req) => write!(f, "{req}"),
+            RequestWrapper::AuthUserAddRequest(ref req) => write!(f, "{req}"),
+            RequestWrapper::AuthRoleGrantPermissionRequest(ref req) => write!(f, "{req}"),
+            RequestWrapper::AuthRoleRevokePermissionRequest(ref req) => write!(f, "{req}"),
+            ref req => write!(f, "{req:?}"),
+        }
+    }
+}
+
 impl Display for AlarmResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "AlarmResponse {{ header: {:?}, ", self.header)?;
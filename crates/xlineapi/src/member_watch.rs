@@ -0,0 +1,128 @@
+//! Watch of the cluster members.
+//!
+//! The watch is served by the server-streaming grpc service `xlinepb.MemberWatch` with
+//! the messages of this module. The first response of a watch is a snapshot of all the
+//! members known by the node the client is connected to, and every later response is
+//! an update of a member, so a client keeps an up-to-date view of the members without
+//! polling the member list.
+
+use std::collections::HashMap;
+
+use prost::{Enumeration, Message};
+use tonic::codegen::{http, Body, Bytes, StdError};
+
+use crate::{Member, ResponseHeader};
+
+/// The grpc service name of the member watch
+pub const MEMBER_WATCH_SERVICE_NAME: &str = "xlinepb.MemberWatch";
+
+/// The grpc path of the member watch
+pub const MEMBER_WATCH_PATH: &str = "/xlinepb.MemberWatch/Watch";
+
+/// Watches the members of the cluster
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct MemberWatchRequest {}
+
+/// The type of a member update
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Enumeration)]
+#[repr(i32)]
+pub enum MemberUpdateType {
+    /// All the members, which is the first update of a watch
+    Snapshot = 0,
+    /// A member is added
+    Add = 1,
+    /// A member is removed, only the id of the member is set
+    Remove = 2,
+    /// A member is promoted, or its name or urls are updated
+    Update = 3,
+}
+
+/// The tags of a member
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct MemberTags {
+    /// The tags
+    #[prost(map = "string, string", tag = "1")]
+    pub tags: HashMap<String, String>,
+}
+
+/// An update of the members
+#[derive(Clone, PartialEq, Message)]
+pub struct MemberWatchResponse {
+    /// The header of the node serving the watch
+    #[prost(message, optional, tag = "1")]
+    pub header: Option<ResponseHeader>,
+    /// The type of the update
+    #[prost(enumeration = "MemberUpdateType", tag = "2")]
+    pub r#type: i32,
+    /// The members of the update, all the members in ascending order of id for a
+    /// snapshot and the updated member otherwise
+    #[prost(message, repeated, tag = "3")]
+    pub members: Vec<Member>,
+    /// The tags of the members of the update by member id, a member without tags
+    /// is absent
+    #[prost(map = "uint64, message", tag = "4")]
+    pub tags: HashMap<u64, MemberTags>,
+}
+
+/// Client of the member watch
+#[derive(Debug, Clone)]
+pub struct MemberWatchClient<T> {
+    /// The inner grpc client
+    inner: tonic::client::Grpc<T>,
+}
+
+impl MemberWatchClient<tonic::transport::Channel> {
+    /// Connect to the member watch service of a server
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::transport::Error` if the server can't be connected
+    #[inline]
+    pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+    where
+        D: TryInto<tonic::transport::Endpoint>,
+        D::Error: Into<StdError>,
+    {
+        let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+        Ok(Self::new(conn))
+    }
+}
+
+impl<T> MemberWatchClient<T>
+where
+    T: tonic::client::GrpcService<tonic::body::BoxBody>,
+    T::Error: Into<StdError>,
+    T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+    <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    /// New `MemberWatchClient` of a grpc service
+    #[inline]
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner: tonic::client::Grpc::new(inner),
+        }
+    }
+
+    /// Watch the members, the stream yields a snapshot first and then the updates
+    ///
+    /// # Errors
+    ///
+    /// Return `tonic::Status` if the watch can't be created
+    #[inline]
+    pub async fn watch(
+        &mut self,
+        request: impl tonic::IntoRequest<MemberWatchRequest>,
+    ) -> Result<tonic::Response<tonic::Streaming<MemberWatchResponse>>, tonic::Status> {
+        self.inner.ready().await.map_err(|e| {
+            tonic::Status::new(
+                tonic::Code::Unknown,
+                format!("Service was not ready: {}", e.into()),
+            )
+        })?;
+        let codec = tonic::codec::ProstCodec::default();
+        let path = http::uri::PathAndQuery::from_static(MEMBER_WATCH_PATH);
+        self.inner
+            .server_streaming(request.into_request(), path, codec)
+            .await
+    }
+}
@@ -2,11 +2,12 @@ use std::collections::HashSet;
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use utils::config::KeyValueEncoding;
 
 use crate::{
-    command::KeyRange, AuthRoleAddRequest, AuthRoleGrantPermissionRequest, AuthUserAddRequest,
-    DeleteRangeRequest, PutRequest, RangeRequest, Request, RequestOp, SortOrder, SortTarget,
-    TxnRequest,
+    command::KeyRange, server_op::ServerOp, AuthRoleAddRequest, AuthRoleGrantPermissionRequest,
+    AuthUserAddRequest, DeleteRangeRequest, PutRequest, RangeRequest, Request, RequestOp,
+    SortOrder, SortTarget, TargetUnion, TxnRequest,
 };
 
 /// Default max txn ops
@@ -92,6 +93,102 @@ impl RequestValidator for TxnRequest {
     }
 }
 
+/// Trait for rejecting puts with an empty value, used by the strict empty value
+/// mode to catch puts which are meant to be deletes
+pub trait EmptyValueValidator {
+    /// Validate that no put in the request has an empty value
+    fn validate_non_empty_value(&self) -> Result<(), ValidationError>;
+}
+
+impl EmptyValueValidator for PutRequest {
+    fn validate_non_empty_value(&self) -> Result<(), ValidationError> {
+        if !self.ignore_value && self.value.is_empty() {
+            return Err(ValidationError::EmptyValue);
+        }
+
+        Ok(())
+    }
+}
+
+impl EmptyValueValidator for TxnRequest {
+    fn validate_non_empty_value(&self) -> Result<(), ValidationError> {
+        for op in self.success.iter().chain(self.failure.iter()) {
+            match op.request {
+                Some(Request::RequestPut(ref r)) => r.validate_non_empty_value()?,
+                Some(Request::RequestTxn(ref r)) => r.validate_non_empty_value()?,
+                Some(Request::RequestRange(_) | Request::RequestDeleteRange(_)) | None => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Trait for rejecting requests whose keys or values are not in the configured
+/// encoding. Range ends are not checked since the end of a prefix is usually not
+/// valid UTF-8
+pub trait EncodingValidator {
+    /// Validate the keys and values of the request against the encoding
+    fn validate_encoding(&self, encoding: KeyValueEncoding) -> Result<(), ValidationError>;
+}
+
+/// Check the key against the encoding
+fn check_key_encoding(key: &[u8], encoding: KeyValueEncoding) -> Result<(), ValidationError> {
+    if encoding.requires_utf8_keys() && std::str::from_utf8(key).is_err() {
+        return Err(ValidationError::NonUtf8Key);
+    }
+    Ok(())
+}
+
+/// Check the value against the encoding
+fn check_value_encoding(value: &[u8], encoding: KeyValueEncoding) -> Result<(), ValidationError> {
+    if encoding.requires_utf8_values() && std::str::from_utf8(value).is_err() {
+        return Err(ValidationError::NonUtf8Value);
+    }
+    Ok(())
+}
+
+impl EncodingValidator for RangeRequest {
+    fn validate_encoding(&self, encoding: KeyValueEncoding) -> Result<(), ValidationError> {
+        check_key_encoding(&self.key, encoding)
+    }
+}
+
+impl EncodingValidator for PutRequest {
+    fn validate_encoding(&self, encoding: KeyValueEncoding) -> Result<(), ValidationError> {
+        check_key_encoding(&self.key, encoding)?;
+        check_value_encoding(&self.value, encoding)
+    }
+}
+
+impl EncodingValidator for DeleteRangeRequest {
+    fn validate_encoding(&self, encoding: KeyValueEncoding) -> Result<(), ValidationError> {
+        check_key_encoding(&self.key, encoding)
+    }
+}
+
+impl EncodingValidator for TxnRequest {
+    fn validate_encoding(&self, encoding: KeyValueEncoding) -> Result<(), ValidationError> {
+        for c in self.compare.iter().filter(|c| !ServerOp::is_marker(c)) {
+            check_key_encoding(&c.key, encoding)?;
+            if let Some(TargetUnion::Value(ref value)) = c.target_union {
+                check_value_encoding(value, encoding)?;
+            }
+        }
+        for op in self.success.iter().chain(self.failure.iter()) {
+            match op.request {
+                Some(Request::RequestRange(ref r)) => r.validate_encoding(encoding)?,
+                Some(Request::RequestPut(ref r)) => r.validate_encoding(encoding)?,
+                Some(Request::RequestDeleteRange(ref r)) => r.validate_encoding(encoding)?,
+                Some(Request::RequestTxn(ref r)) => r.validate_encoding(encoding)?,
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
 /// Check if puts and deletes overlap
 fn check_intervals(ops: &[RequestOp]) -> Result<(HashSet<&[u8]>, Vec<KeyRange>), ValidationError> {
     // TODO: use interval tree is better?
@@ -214,6 +311,24 @@ pub enum ValidationError {
     /// Permission not given
     #[error("permission not given")]
     PermissionNotGiven,
+    /// Value of a put is empty in the strict empty value mode
+    #[error("value is empty")]
+    EmptyValue,
+    /// Key is not valid UTF-8 while the keys must be
+    #[error("key is not valid UTF-8")]
+    NonUtf8Key,
+    /// Value is not valid UTF-8 while the values must be
+    #[error("value is not valid UTF-8")]
+    NonUtf8Value,
+    /// Value is rejected by the validator of a prefix of its key
+    #[error("value does not conform to the validator of its key prefix")]
+    NonConformingValue,
+    /// Server op writes values under a prefix with a validator
+    #[error("server op can't write the values under a validated key prefix")]
+    UnvalidatedServerOp,
+    /// Key contains a byte disallowed by the key charset of its prefix
+    #[error("key contains a character disallowed by the key charset of its prefix")]
+    DisallowedKeyCharacter,
 }
 
 // The etcd client relies on GRPC error messages for error type interpretation.
@@ -260,7 +375,14 @@ impl From<ValidationError> for tonic::Status {
                 tonic::Code::InvalidArgument,
                 "etcdserver: permission not given".to_owned(),
             ),
-            ValidationError::RequestNotProvided | ValidationError::PasswordEmpty => {
+            ValidationError::RequestNotProvided
+            | ValidationError::PasswordEmpty
+            | ValidationError::EmptyValue
+            | ValidationError::NonUtf8Key
+            | ValidationError::NonUtf8Value
+            | ValidationError::NonConformingValue
+            | ValidationError::UnvalidatedServerOp
+            | ValidationError::DisallowedKeyCharacter => {
                 (tonic::Code::InvalidArgument, err.to_string())
             }
         };
@@ -651,4 +773,92 @@ mod test {
 
         run_test(testcases);
     }
+
+    #[test]
+    fn empty_value_put_should_be_rejected_in_strict_mode() {
+        let put = PutRequest {
+            key: "k".into(),
+            ..Default::default()
+        };
+        assert!(put.validation().is_ok());
+        assert_eq!(
+            put.validate_non_empty_value().unwrap_err(),
+            ValidationError::EmptyValue
+        );
+        let ignore_value_put = PutRequest {
+            key: "k".into(),
+            ignore_value: true,
+            ..Default::default()
+        };
+        assert!(ignore_value_put.validate_non_empty_value().is_ok());
+        let txn = TxnRequest {
+            compare: vec![],
+            success: vec![RequestOp {
+                request: Some(Request::RequestTxn(TxnRequest {
+                    compare: vec![],
+                    success: vec![RequestOp {
+                        request: Some(Request::RequestPut(put)),
+                    }],
+                    failure: vec![],
+                })),
+            }],
+            failure: vec![],
+        };
+        assert_eq!(
+            txn.validate_non_empty_value().unwrap_err(),
+            ValidationError::EmptyValue
+        );
+    }
+
+    #[test]
+    fn non_utf8_key_should_be_rejected_in_utf8_mode() {
+        let put = PutRequest {
+            key: vec![b'k', 0xff],
+            value: vec![0xfe],
+            ..Default::default()
+        };
+        assert!(put.validate_encoding(KeyValueEncoding::Raw).is_ok());
+        assert_eq!(
+            put.validate_encoding(KeyValueEncoding::Utf8Keys)
+                .unwrap_err(),
+            ValidationError::NonUtf8Key
+        );
+        let value_put = PutRequest {
+            key: "k".into(),
+            value: vec![0xfe],
+            ..Default::default()
+        };
+        assert!(value_put
+            .validate_encoding(KeyValueEncoding::Utf8Keys)
+            .is_ok());
+        assert_eq!(
+            value_put
+                .validate_encoding(KeyValueEncoding::Utf8)
+                .unwrap_err(),
+            ValidationError::NonUtf8Value
+        );
+        let txn = TxnRequest {
+            compare: vec![],
+            success: vec![],
+            failure: vec![RequestOp {
+                request: Some(Request::RequestTxn(TxnRequest {
+                    compare: vec![],
+                    success: vec![RequestOp {
+                        request: Some(Request::RequestDeleteRange(DeleteRangeRequest {
+                            key: vec![0xc0],
+                            range_end: vec![0xc1],
+                            ..Default::default()
+                        })),
+                    }],
+                    failure: vec![],
+                })),
+            }],
+        };
+        assert!(txn.validate_encoding(KeyValueEncoding::Raw).is_ok());
+        assert_eq!(
+            txn.validate_encoding(KeyValueEncoding::Utf8Keys)
+                .unwrap_err(),
+            ValidationError::NonUtf8Key
+        );
+    }
 }
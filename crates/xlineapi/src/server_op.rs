@@ -0,0 +1,904 @@
+//! Operations applied by the server in a single command.
+//!
+//! An operation which reads a key and writes it back, e.g. an increment, can't be
+//! expressed by a txn without a retry loop on the client. Such an operation is
+//! carried by a txn instead, whose only compare is a value compare of
+//! `SERVER_OP_KEY` against the encoded operation. A server which knows the
+//! operations applies it as a whole, both when the txn is executed and when it is
+//! synced, and returns its result in the txn response. A server which doesn't know
+//! them evaluates the compare against the key, which never exists, so it takes the
+//! empty failure branch and the txn fails without any change.
+//!
+//! The success branch of the txn is the footprint of the operation: the ranges, puts
+//! and deletes of the keys it reads and writes. It's never applied, but it gives the keys
+//! of the command for the conflict checks and the permissions required by the
+//! operation.
+
+use std::collections::HashMap;
+
+use prost::{Enumeration, Message, Oneof};
+
+use crate::{
+    command::KeyRange, execute_error::ExecuteError, Compare, CompareResult, CompareTarget,
+    DeleteRangeRequest, KeyValue, PutRequest, RangeRequest, RangeResponse, Request, RequestOp,
+    Response, ResponseHeader, ResponseOp, TargetUnion, TxnRequest, TxnResponse,
+};
+
+/// The key of the compare carrying a server operation, it can't be written
+pub const SERVER_OP_KEY: &[u8] = b"\0server-op";
+
+/// Encoding of a counter value
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Enumeration)]
+#[repr(i32)]
+pub enum CounterEncoding {
+    /// Decimal string, e.g. `"42"`
+    Decimal = 0,
+    /// 8 bytes little-endian signed integer
+    LittleEndian = 1,
+    /// 8 bytes big-endian signed integer
+    BigEndian = 2,
+}
+
+impl CounterEncoding {
+    /// Decode a counter value, `None` means the value is not a counter in this encoding
+    #[must_use]
+    pub fn decode(self, value: &[u8]) -> Option<i64> {
+        match self {
+            Self::Decimal => std::str::from_utf8(value).ok()?.parse().ok(),
+            Self::LittleEndian => value.try_into().ok().map(i64::from_le_bytes),
+            Self::BigEndian => value.try_into().ok().map(i64::from_be_bytes),
+        }
+    }
+
+    /// Encode a counter value
+    #[must_use]
+    pub fn encode(self, value: i64) -> Vec<u8> {
+        match self {
+            Self::Decimal => value.to_string().into_bytes(),
+            Self::LittleEndian => value.to_le_bytes().to_vec(),
+            Self::BigEndian => value.to_be_bytes().to_vec(),
+        }
+    }
+}
+
+/// Adds a delta to the counter at a key, a missing key is created at 0
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct IncrementOp {
+    /// The key of the counter
+    #[prost(bytes = "vec", tag = "1")]
+    pub key: Vec<u8>,
+    /// The delta to add, it can be negative
+    #[prost(int64, tag = "2")]
+    pub delta: i64,
+    /// The encoding of the counter value
+    #[prost(enumeration = "CounterEncoding", tag = "3")]
+    pub encoding: i32,
+}
+
+impl IncrementOp {
+    /// New `IncrementOp`
+    #[must_use]
+    pub fn new(key: Vec<u8>, delta: i64, encoding: CounterEncoding) -> Self {
+        Self {
+            key,
+            delta,
+            encoding: encoding.into(),
+        }
+    }
+
+    /// Get the encoding of the counter, an unknown encoding is rejected
+    ///
+    /// # Errors
+    ///
+    /// Return `ExecuteError::Rejected` if the encoding is unknown
+    pub fn counter_encoding(&self) -> Result<CounterEncoding, ExecuteError> {
+        CounterEncoding::try_from(self.encoding).map_err(|_e| {
+            ExecuteError::Rejected(format!("unknown counter encoding {}", self.encoding))
+        })
+    }
+}
+
+/// The maximum size in bytes of a value built by an `AppendOp`, the same as the
+/// default request size limit of etcd
+pub const MAX_APPENDED_VALUE_SIZE: u64 = 1536 * 1024;
+
+/// Appends bytes to the value of a key, a missing key is created with the bytes
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct AppendOp {
+    /// The key to append to
+    #[prost(bytes = "vec", tag = "1")]
+    pub key: Vec<u8>,
+    /// The bytes to append
+    #[prost(bytes = "vec", tag = "2")]
+    pub bytes: Vec<u8>,
+    /// The maximum size of the value after the append, it can only lower
+    /// `MAX_APPENDED_VALUE_SIZE` and 0 means the value is only limited by it
+    #[prost(uint64, tag = "3")]
+    pub max_value_size: u64,
+}
+
+impl AppendOp {
+    /// New `AppendOp`
+    #[must_use]
+    pub fn new(key: Vec<u8>, bytes: Vec<u8>, max_value_size: u64) -> Self {
+        Self {
+            key,
+            bytes,
+            max_value_size,
+        }
+    }
+
+    /// Get the maximum size of the value after the append, the one of the op capped
+    /// by `MAX_APPENDED_VALUE_SIZE`
+    #[must_use]
+    pub fn value_size_limit(&self) -> u64 {
+        if self.max_value_size == 0 {
+            return MAX_APPENDED_VALUE_SIZE;
+        }
+        self.max_value_size.min(MAX_APPENDED_VALUE_SIZE)
+    }
+}
+
+/// Swaps the values of two keys, and optionally their leases
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct SwapOp {
+    /// The first key
+    #[prost(bytes = "vec", tag = "1")]
+    pub first: Vec<u8>,
+    /// The second key
+    #[prost(bytes = "vec", tag = "2")]
+    pub second: Vec<u8>,
+    /// Whether the leases of the keys are swapped with the values
+    #[prost(bool, tag = "3")]
+    pub swap_leases: bool,
+    /// Whether a missing key is swapped as an empty value instead of failing the swap
+    #[prost(bool, tag = "4")]
+    pub create_missing: bool,
+}
+
+impl SwapOp {
+    /// New `SwapOp`
+    #[must_use]
+    pub fn new(first: Vec<u8>, second: Vec<u8>, swap_leases: bool, create_missing: bool) -> Self {
+        Self {
+            first,
+            second,
+            swap_leases,
+            create_missing,
+        }
+    }
+}
+
+/// The result of a `SwapOp`, both keys are modified at the revision of the txn
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct SwapOpResult {}
+
+/// The maximum number of revisions reserved by a `ReserveRevisionsOp`
+pub const MAX_RESERVED_REVISIONS: u64 = 1_000_000;
+
+/// Reserves a block of revisions, it takes the revisions without any change, so the
+/// writes after it start above the block. It requires the admin permission.
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct ReserveRevisionsOp {
+    /// The number of revisions to reserve, at most `MAX_RESERVED_REVISIONS`
+    #[prost(uint64, tag = "1")]
+    pub count: u64,
+}
+
+impl ReserveRevisionsOp {
+    /// New `ReserveRevisionsOp`
+    #[must_use]
+    pub fn new(count: u64) -> Self {
+        Self { count }
+    }
+
+    /// Check the number of revisions to reserve
+    ///
+    /// # Errors
+    ///
+    /// Return `ExecuteError::Rejected` if it is 0 or more than `MAX_RESERVED_REVISIONS`
+    pub fn check_count(&self) -> Result<(), ExecuteError> {
+        if self.count == 0 || self.count > MAX_RESERVED_REVISIONS {
+            return Err(ExecuteError::Rejected(format!(
+                "the number of revisions to reserve must be in [1, {MAX_RESERVED_REVISIONS}]"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The maximum number of leases granted by a `GrantLeasesOp`
+pub const MAX_GRANTED_LEASES: usize = 10_000;
+
+/// A lease granted by a `GrantLeasesOp`
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct LeaseGrant {
+    /// The id of the lease, it must be specified
+    #[prost(int64, tag = "1")]
+    pub id: i64,
+    /// The ttl of the lease in seconds
+    #[prost(int64, tag = "2")]
+    pub ttl: i64,
+}
+
+/// Grants a batch of leases at once, either all of them are granted or none. It's
+/// carried by a txn but applied by the lease store, and every lease is validated
+/// as a single grant.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct GrantLeasesOp {
+    /// The leases to grant, at most `MAX_GRANTED_LEASES`
+    #[prost(message, repeated, tag = "1")]
+    pub leases: Vec<LeaseGrant>,
+}
+
+impl GrantLeasesOp {
+    /// New `GrantLeasesOp`
+    #[must_use]
+    pub fn new(leases: Vec<LeaseGrant>) -> Self {
+        Self { leases }
+    }
+
+    /// Check the number of leases to grant
+    ///
+    /// # Errors
+    ///
+    /// Return `ExecuteError::Rejected` if it is 0 or more than `MAX_GRANTED_LEASES`
+    pub fn check_count(&self) -> Result<(), ExecuteError> {
+        if self.leases.is_empty() || self.leases.len() > MAX_GRANTED_LEASES {
+            return Err(ExecuteError::Rejected(format!(
+                "the number of leases to grant must be in [1, {MAX_GRANTED_LEASES}]"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The result of a `GrantLeasesOp`
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct GrantLeasesOpResult {
+    /// The ids of the granted leases, in the order of the op
+    #[prost(int64, repeated, tag = "1")]
+    pub ids: Vec<i64>,
+}
+
+/// The maximum number of leases imported by an `ImportLeasesOp`
+pub const MAX_IMPORTED_LEASES: usize = 10_000;
+
+/// A lease of an exported lease state, which is imported by an `ImportLeasesOp`
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct LeaseImport {
+    /// The id of the lease
+    #[prost(int64, tag = "1")]
+    pub id: i64,
+    /// The granted ttl of the lease in seconds
+    #[prost(int64, tag = "2")]
+    pub ttl: i64,
+    /// The remaining ttl of the lease in seconds when it is exported, 0 if it has
+    /// expired
+    #[prost(int64, tag = "3")]
+    pub remaining_ttl: i64,
+    /// The keys attached to the lease in ascending order
+    #[prost(bytes = "vec", repeated, tag = "4")]
+    pub keys: Vec<Vec<u8>>,
+}
+
+/// Imports a lease state exported from another cluster, it's applied as a whole by
+/// the lease store at one revision. It requires the admin permission.
+///
+/// Every lease is validated as a single grant, and every attached key must exist.
+/// A lease which is still alive is granted again and expires in its remaining ttl,
+/// then its keys are reattached to it. A lease which had expired at the export is
+/// revoked right away, i.e. it isn't granted and its keys are deleted.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ImportLeasesOp {
+    /// The leases to import, at most `MAX_IMPORTED_LEASES`
+    #[prost(message, repeated, tag = "1")]
+    pub leases: Vec<LeaseImport>,
+}
+
+impl ImportLeasesOp {
+    /// New `ImportLeasesOp`
+    #[must_use]
+    pub fn new(leases: Vec<LeaseImport>) -> Self {
+        Self { leases }
+    }
+
+    /// Check the number of leases to import
+    ///
+    /// # Errors
+    ///
+    /// Return `ExecuteError::Rejected` if it is 0 or more than `MAX_IMPORTED_LEASES`
+    pub fn check_count(&self) -> Result<(), ExecuteError> {
+        if self.leases.is_empty() || self.leases.len() > MAX_IMPORTED_LEASES {
+            return Err(ExecuteError::Rejected(format!(
+                "the number of leases to import must be in [1, {MAX_IMPORTED_LEASES}]"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The result of an `ImportLeasesOp`
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ImportLeasesOpResult {
+    /// The ids of the leases granted again, in the order of the op
+    #[prost(int64, repeated, tag = "1")]
+    pub imported: Vec<i64>,
+    /// The ids of the expired leases whose keys are deleted, in the order of the op
+    #[prost(int64, repeated, tag = "2")]
+    pub revoked: Vec<i64>,
+}
+
+/// The maximum total size in bytes of the keys and values moved by a `MovePrefixOp`,
+/// the same as the default request size limit of etcd
+pub const MAX_MOVED_BYTES: u64 = 1536 * 1024;
+
+/// Moves all the keys under a source prefix to a destination prefix at one revision.
+///
+/// Every key is put under the destination with its value and lease, and the source
+/// prefix is deleted, so a watcher sees a put of every new key and a delete of every
+/// old key at the revision. The move fails without any change if the destination
+/// prefix is not empty, or the moved keys and values exceed `MAX_MOVED_BYTES`.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct MovePrefixOp {
+    /// The source prefix
+    #[prost(bytes = "vec", tag = "1")]
+    pub source: Vec<u8>,
+    /// The destination prefix
+    #[prost(bytes = "vec", tag = "2")]
+    pub destination: Vec<u8>,
+}
+
+impl MovePrefixOp {
+    /// New `MovePrefixOp`
+    #[must_use]
+    pub fn new(source: Vec<u8>, destination: Vec<u8>) -> Self {
+        Self {
+            source,
+            destination,
+        }
+    }
+
+    /// Check the prefixes of the move
+    ///
+    /// # Errors
+    ///
+    /// Return `ExecuteError::Rejected` if either prefix is empty or they overlap
+    pub fn check_prefixes(&self) -> Result<(), ExecuteError> {
+        if self.source.is_empty() || self.destination.is_empty() {
+            return Err(ExecuteError::Rejected(
+                "the prefixes of a move can't be empty".to_owned(),
+            ));
+        }
+        if self.destination.starts_with(&self.source) || self.source.starts_with(&self.destination)
+        {
+            return Err(ExecuteError::Rejected(
+                "the source and destination prefixes of a move overlap".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Get the key moved from a source key
+    #[must_use]
+    pub fn moved_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut moved = self.destination.clone();
+        moved.extend_from_slice(key.get(self.source.len()..).unwrap_or_default());
+        moved
+    }
+}
+
+/// The result of a `MovePrefixOp`
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct MovePrefixOpResult {
+    /// The number of the moved keys
+    #[prost(uint64, tag = "1")]
+    pub moved: u64,
+}
+
+/// Deletes a range only if there are fewer keys in it than a threshold.
+///
+/// The keys are counted and deleted at one revision, so no key put concurrently can
+/// be deleted without being counted. The delete is skipped without any change if the
+/// number of the keys is not less than the threshold.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct ConditionalDeleteOp {
+    /// The first key of the range
+    #[prost(bytes = "vec", tag = "1")]
+    pub key: Vec<u8>,
+    /// The end of the range, the same as the one of a `DeleteRangeRequest`
+    #[prost(bytes = "vec", tag = "2")]
+    pub range_end: Vec<u8>,
+    /// The range is deleted only if there are fewer keys in it than the threshold
+    #[prost(uint64, tag = "3")]
+    pub threshold: u64,
+}
+
+impl ConditionalDeleteOp {
+    /// New `ConditionalDeleteOp`
+    #[must_use]
+    pub fn new(key: Vec<u8>, range_end: Vec<u8>, threshold: u64) -> Self {
+        Self {
+            key,
+            range_end,
+            threshold,
+        }
+    }
+
+    /// Check the threshold of the delete
+    ///
+    /// # Errors
+    ///
+    /// Return `ExecuteError::Rejected` if the threshold is 0, which never deletes
+    pub fn check_threshold(&self) -> Result<(), ExecuteError> {
+        if self.threshold == 0 {
+            return Err(ExecuteError::Rejected(
+                "the threshold of a conditional delete should be positive".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The result of a `ConditionalDeleteOp`
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct ConditionalDeleteOpResult {
+    /// The number of the keys in the range when the op is applied
+    #[prost(uint64, tag = "1")]
+    pub count: u64,
+    /// Whether the range is deleted
+    #[prost(bool, tag = "2")]
+    pub deleted: bool,
+}
+
+/// Sets the tags of a member, which are kept by the server out of the keyspace.
+///
+/// The tags take no revision and fire no watch, and they are only read by the member
+/// list. It's proposed by the servers themselves and requires the admin permission.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct MemberTagsOp {
+    /// The id of the member
+    #[prost(uint64, tag = "1")]
+    pub member_id: u64,
+    /// The tags of the member, empty tags remove the ones set before
+    #[prost(map = "string, string", tag = "2")]
+    pub tags: HashMap<String, String>,
+}
+
+impl MemberTagsOp {
+    /// New `MemberTagsOp`
+    #[must_use]
+    pub fn new(member_id: u64, tags: HashMap<String, String>) -> Self {
+        Self { member_id, tags }
+    }
+}
+
+/// The result of a `MemberTagsOp`
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct MemberTagsOpResult {}
+
+/// Replaces the stored password hash of a user by a hash of the same password at
+/// other rounds.
+///
+/// It's applied by the auth store but keeps the auth revision, so the tokens issued
+/// before it stay valid. The hash is only replaced if the stored one is still the
+/// re-hashed one, so a concurrent password change is never undone. It's proposed by
+/// the servers themselves and requires the admin permission.
+#[derive(Clone, PartialEq, Eq, Message)]
+pub struct RehashPasswordOp {
+    /// The name of the user
+    #[prost(string, tag = "1")]
+    pub name: String,
+    /// The stored password hash which is re-hashed
+    #[prost(string, tag = "2")]
+    pub previous_hash: String,
+    /// The new password hash
+    #[prost(string, tag = "3")]
+    pub hash: String,
+}
+
+impl RehashPasswordOp {
+    /// New `RehashPasswordOp`
+    #[must_use]
+    pub fn new(name: String, previous_hash: String, hash: String) -> Self {
+        Self {
+            name,
+            previous_hash,
+            hash,
+        }
+    }
+}
+
+/// The result of a `RehashPasswordOp`
+#[derive(Clone, Copy, PartialEq, Eq, Message)]
+pub struct RehashPasswordOpResult {}
+
+/// An operation applied by the server
+#[derive(Clone, PartialEq, Eq, Oneof)]
+pub enum ServerOp {
+    /// Increment a counter
+    #[prost(message, tag = "1")]
+    Increment(IncrementOp),
+    /// Append to a value
+    #[prost(message, tag = "2")]
+    Append(AppendOp),
+    /// Swap two keys
+    #[prost(message, tag = "3")]
+    Swap(SwapOp),
+    /// Reserve a block of revisions
+    #[prost(message, tag = "4")]
+    ReserveRevisions(ReserveRevisionsOp),
+    /// Grant a batch of leases
+    #[prost(message, tag = "5")]
+    GrantLeases(GrantLeasesOp),
+    /// Import a lease state
+    #[prost(message, tag = "6")]
+    ImportLeases(ImportLeasesOp),
+    /// Move the keys under a prefix
+    #[prost(message, tag = "7")]
+    MovePrefix(MovePrefixOp),
+    /// Delete a range with fewer keys than a threshold
+    #[prost(message, tag = "8")]
+    ConditionalDelete(ConditionalDeleteOp),
+    /// Set the tags of a member
+    #[prost(message, tag = "9")]
+    MemberTags(MemberTagsOp),
+    /// Re-hash the password of a user
+    #[prost(message, tag = "10")]
+    RehashPassword(RehashPasswordOp),
+}
+
+/// The encoded form of a `ServerOp`
+#[derive(Clone, PartialEq, Eq, Message)]
+struct PbServerOp {
+    /// The operation
+    #[prost(oneof = "ServerOp", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10")]
+    op: Option<ServerOp>,
+}
+
+/// The result of a `ServerOp`
+#[derive(Clone, PartialEq, Eq, Oneof)]
+pub enum ServerOpResult {
+    /// The value of the counter after the increment
+    #[prost(int64, tag = "1")]
+    Increment(i64),
+    /// The length of the value after the append
+    #[prost(uint64, tag = "2")]
+    Append(u64),
+    /// The swap is applied
+    #[prost(message, tag = "3")]
+    Swap(SwapOpResult),
+    /// The number of the reserved revisions, the last one is the revision of the txn
+    #[prost(uint64, tag = "4")]
+    ReserveRevisions(u64),
+    /// The granted leases
+    #[prost(message, tag = "5")]
+    GrantLeases(GrantLeasesOpResult),
+    /// The imported and revoked leases
+    #[prost(message, tag = "6")]
+    ImportLeases(ImportLeasesOpResult),
+    /// The keys are moved
+    #[prost(message, tag = "7")]
+    MovePrefix(MovePrefixOpResult),
+    /// The count of the range and whether it is deleted
+    #[prost(message, tag = "8")]
+    ConditionalDelete(ConditionalDeleteOpResult),
+    /// The tags are set
+    #[prost(message, tag = "9")]
+    MemberTags(MemberTagsOpResult),
+    /// The password is re-hashed if it hasn't been changed
+    #[prost(message, tag = "10")]
+    RehashPassword(RehashPasswordOpResult),
+}
+
+/// The encoded form of a `ServerOpResult`
+#[derive(Clone, PartialEq, Eq, Message)]
+struct PbServerOpResult {
+    /// The result
+    #[prost(oneof = "ServerOpResult", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10")]
+    result: Option<ServerOpResult>,
+}
+
+impl ServerOp {
+    /// The ranges and puts of the keys read and written by the operation, which are
+    /// the success branch of its txn
+    #[must_use]
+    pub fn footprint(&self) -> Vec<RequestOp> {
+        let read_write = |key: &[u8]| {
+            [
+                Request::RequestRange(RangeRequest {
+                    key: key.to_vec(),
+                    ..Default::default()
+                }),
+                // a put without a value since the value is only known when applied
+                Request::RequestPut(PutRequest {
+                    key: key.to_vec(),
+                    ignore_value: true,
+                    ..Default::default()
+                }),
+            ]
+        };
+        let read_delete_range = |key: &[u8], range_end: &[u8]| {
+            [
+                Request::RequestRange(RangeRequest {
+                    key: key.to_vec(),
+                    range_end: range_end.to_vec(),
+                    ..Default::default()
+                }),
+                Request::RequestDeleteRange(DeleteRangeRequest {
+                    key: key.to_vec(),
+                    range_end: range_end.to_vec(),
+                    ..Default::default()
+                }),
+            ]
+        };
+        let read_write_prefix =
+            |prefix: &[u8]| read_delete_range(prefix, &KeyRange::get_prefix(prefix));
+        let requests = match *self {
+            ServerOp::Increment(ref op) => read_write(&op.key).to_vec(),
+            ServerOp::Append(ref op) => read_write(&op.key).to_vec(),
+            ServerOp::Swap(ref op) => [read_write(&op.first), read_write(&op.second)].concat(),
+            ServerOp::ReserveRevisions(_)
+            | ServerOp::GrantLeases(_)
+            | ServerOp::MemberTags(_)
+            | ServerOp::RehashPassword(_) => vec![],
+            ServerOp::ImportLeases(ref op) => op
+                .leases
+                .iter()
+                .flat_map(|lease| lease.keys.iter())
+                .flat_map(|key| read_write(key))
+                .collect(),
+            ServerOp::MovePrefix(ref op) => [
+                read_write_prefix(&op.source),
+                read_write_prefix(&op.destination),
+            ]
+            .concat(),
+            ServerOp::ConditionalDelete(ref op) => {
+                read_delete_range(&op.key, &op.range_end).to_vec()
+            }
+        };
+        requests
+            .into_iter()
+            .map(|request| RequestOp {
+                request: Some(request),
+            })
+            .collect()
+    }
+
+    /// Get the operation carried by a txn, `None` if it is a plain txn
+    ///
+    /// # Errors
+    ///
+    /// Return `ExecuteError::Rejected` if the txn carries an operation which can't be
+    /// decoded, it has other compares, or its branches are not the footprint of the
+    /// operation, which the permissions and the conflicts are checked by
+    pub fn from_txn(txn: &TxnRequest) -> Result<Option<Self>, ExecuteError> {
+        let Some(marker) = txn.compare.iter().find(|cmp| Self::is_marker(cmp)) else {
+            return Ok(None);
+        };
+        if txn.compare.len() != 1 {
+            return Err(ExecuteError::Rejected(
+                "a server op can't be compared with other keys".to_owned(),
+            ));
+        }
+        let Some(TargetUnion::Value(ref encoded)) = marker.target_union else {
+            return Err(ExecuteError::Rejected(
+                "the server op is missing".to_owned(),
+            ));
+        };
+        let op = PbServerOp::decode(encoded.as_slice())
+            .ok()
+            .and_then(|pb| pb.op)
+            .ok_or_else(|| ExecuteError::Rejected("the server op is unknown".to_owned()))?;
+        if txn.success != op.footprint() || !txn.failure.is_empty() {
+            return Err(ExecuteError::Rejected(
+                "the txn of a server op doesn't match its footprint".to_owned(),
+            ));
+        }
+        Ok(Some(op))
+    }
+
+    /// Check whether the operation requires the admin permission
+    #[must_use]
+    pub fn needs_admin(&self) -> bool {
+        match *self {
+            ServerOp::Increment(_)
+            | ServerOp::Append(_)
+            | ServerOp::Swap(_)
+            | ServerOp::GrantLeases(_)
+            | ServerOp::MovePrefix(_)
+            | ServerOp::ConditionalDelete(_) => false,
+            ServerOp::ReserveRevisions(_)
+            | ServerOp::ImportLeases(_)
+            | ServerOp::MemberTags(_)
+            | ServerOp::RehashPassword(_) => true,
+        }
+    }
+
+    /// Get the ids of the leases granted by a txn, `None` if it doesn't grant leases
+    #[must_use]
+    pub fn granted_leases(txn: &TxnRequest) -> Option<Vec<i64>> {
+        let Ok(Some(ServerOp::GrantLeases(op))) = Self::from_txn(txn) else {
+            return None;
+        };
+        Some(op.leases.iter().map(|lease| lease.id).collect())
+    }
+
+    /// Check whether a txn sets the tags of a member
+    #[must_use]
+    pub fn sets_member_tags(txn: &TxnRequest) -> bool {
+        matches!(Self::from_txn(txn), Ok(Some(ServerOp::MemberTags(_))))
+    }
+
+    /// Get the password re-hash carried by a txn, `None` if it doesn't re-hash a
+    /// password
+    #[must_use]
+    pub fn password_rehash(txn: &TxnRequest) -> Option<RehashPasswordOp> {
+        let Ok(Some(ServerOp::RehashPassword(op))) = Self::from_txn(txn) else {
+            return None;
+        };
+        Some(op)
+    }
+
+    /// Get the ids of the leases granted or imported by a txn, `None` if it is applied
+    /// by the kv store
+    #[must_use]
+    pub fn lease_ids(txn: &TxnRequest) -> Option<Vec<i64>> {
+        #[allow(clippy::wildcard_enum_match_arm)] // the other ops are applied by the kv store
+        let ids = match Self::from_txn(txn) {
+            Ok(Some(ServerOp::GrantLeases(op))) => {
+                Some(op.leases.iter().map(|lease| lease.id).collect())
+            }
+            Ok(Some(ServerOp::ImportLeases(op))) => {
+                Some(op.leases.iter().map(|lease| lease.id).collect())
+            }
+            _ => None,
+        };
+        ids
+    }
+
+    /// Check whether a compare is the marker of a server operation
+    #[must_use]
+    pub fn is_marker(cmp: &Compare) -> bool {
+        cmp.key == SERVER_OP_KEY
+    }
+}
+
+impl From<ServerOp> for TxnRequest {
+    fn from(op: ServerOp) -> Self {
+        let success = op.footprint();
+        let encoded = PbServerOp { op: Some(op) }.encode_to_vec();
+        TxnRequest {
+            compare: vec![Compare {
+                result: CompareResult::Equal.into(),
+                target: CompareTarget::Value.into(),
+                key: SERVER_OP_KEY.to_vec(),
+                range_end: vec![],
+                target_union: Some(TargetUnion::Value(encoded)),
+            }],
+            success,
+            failure: vec![],
+        }
+    }
+}
+
+impl ServerOpResult {
+    /// Build the txn response of an applied operation
+    #[must_use]
+    pub fn into_txn_response(self, header: Option<ResponseHeader>) -> TxnResponse {
+        let encoded = PbServerOpResult { result: Some(self) }.encode_to_vec();
+        TxnResponse {
+            header,
+            succeeded: true,
+            responses: vec![ResponseOp {
+                response: Some(Response::ResponseRange(RangeResponse {
+                    kvs: vec![KeyValue {
+                        key: SERVER_OP_KEY.to_vec(),
+                        value: encoded,
+                        ..Default::default()
+                    }],
+                    count: 1,
+                    ..Default::default()
+                })),
+            }],
+        }
+    }
+
+    /// Get the result of an operation from its txn response, `None` if the operation
+    /// isn't applied, i.e. the server doesn't know the operations
+    #[must_use]
+    pub fn from_txn_response(res: &TxnResponse) -> Option<Self> {
+        if !res.succeeded {
+            return None;
+        }
+        let Some(Response::ResponseRange(ref range)) = res.responses.first()?.response else {
+            return None;
+        };
+        let kv = range.kvs.first().filter(|kv| kv.key == SERVER_OP_KEY)?;
+        PbServerOpResult::decode(kv.value.as_slice()).ok()?.result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CommandKeys;
+
+    #[test]
+    fn server_op_should_be_carried_by_txn() {
+        let op = ServerOp::Increment(IncrementOp::new(
+            b"counter".to_vec(),
+            -2,
+            CounterEncoding::BigEndian,
+        ));
+        let txn = TxnRequest::from(op.clone());
+        assert_eq!(ServerOp::from_txn(&txn).unwrap(), Some(op));
+        let keys = txn.keys();
+        assert!(!keys.is_empty());
+        assert!(keys.iter().all(|key| key.contains_key(b"counter")));
+
+        let plain = TxnRequest::default();
+        assert_eq!(ServerOp::from_txn(&plain).unwrap(), None);
+
+        let mut broken = txn.clone();
+        broken.compare[0].target_union = Some(TargetUnion::Value(b"garbage".to_vec()));
+        assert!(ServerOp::from_txn(&broken).is_err());
+        let mut compared = txn.clone();
+        compared.compare.push(Compare {
+            key: b"other".to_vec(),
+            ..Default::default()
+        });
+        assert!(ServerOp::from_txn(&compared).is_err());
+        let mut with_failure = txn;
+        with_failure.failure = with_failure.success.clone();
+        assert!(ServerOp::from_txn(&with_failure).is_err());
+    }
+
+    #[test]
+    fn lease_ids_should_be_read_from_the_lease_ops() {
+        let grant = ServerOp::GrantLeases(GrantLeasesOp::new(vec![LeaseGrant { id: 1, ttl: 10 }]));
+        assert_eq!(ServerOp::lease_ids(&grant.into()), Some(vec![1]));
+        let import = ServerOp::ImportLeases(ImportLeasesOp::new(vec![LeaseImport {
+            id: 2,
+            ttl: 10,
+            remaining_ttl: 5,
+            keys: vec![b"key".to_vec()],
+        }]));
+        assert!(import.needs_admin());
+        let txn = TxnRequest::from(import);
+        assert_eq!(ServerOp::lease_ids(&txn), Some(vec![2]));
+        assert_eq!(ServerOp::granted_leases(&txn), None);
+        assert!(txn.keys().iter().all(|key| key.contains_key(b"key")));
+        let reserve = ServerOp::ReserveRevisions(ReserveRevisionsOp::new(1));
+        assert_eq!(ServerOp::lease_ids(&reserve.into()), None);
+    }
+
+    #[test]
+    fn prefix_move_should_cover_both_prefixes() {
+        let op = MovePrefixOp::new(b"a/".to_vec(), b"b/".to_vec());
+        assert!(op.check_prefixes().is_ok());
+        assert_eq!(op.moved_key(b"a/x/y"), b"b/x/y");
+        let txn = TxnRequest::from(ServerOp::MovePrefix(op));
+        let keys = txn.keys();
+        for key in [b"a/".as_slice(), b"a/x", b"b/", b"b/x"] {
+            assert!(keys.iter().any(|range| range.contains_key(key)));
+        }
+        assert!(!keys.iter().any(|range| range.contains_key(b"c")));
+        for (source, destination) in [("a/", "a/b/"), ("a/b/", "a/"), ("", "b/")] {
+            let op = MovePrefixOp::new(source.into(), destination.into());
+            assert!(op.check_prefixes().is_err());
+        }
+    }
+
+    #[test]
+    fn server_op_result_should_only_be_read_from_applied_txn() {
+        let res = ServerOpResult::Increment(42).into_txn_response(None);
+        assert_eq!(
+            ServerOpResult::from_txn_response(&res),
+            Some(ServerOpResult::Increment(42))
+        );
+        // a server unaware of the operations takes the empty failure branch
+        let failed = TxnResponse::default();
+        assert_eq!(ServerOpResult::from_txn_response(&failed), None);
+    }
+}
@@ -9,7 +9,7 @@ use engine::{Engine, EngineType, StorageEngine};
 use serde::Serialize;
 use tempfile::tempdir;
 use utils::table_names::{KV_TABLE, XLINE_TABLES};
-use xline::storage::Revision;
+use xline::storage::{etcd_snapshot::export_etcd_snapshot, Revision};
 
 use crate::printer::Printer;
 
@@ -28,6 +28,12 @@ pub(crate) fn command() -> Command {
                 .about("Gets backend snapshot status of a given file")
                 .arg(arg!(<filename> "Path to the snapshot file")),
         )
+        .subcommand(
+            Command::new("export-etcd")
+                .about("Exports an xline member snapshot to an etcd compatible snapshot file")
+                .arg(arg!(<filename> "Path to the snapshot file"))
+                .arg(arg!(--output <OUTPUT> "Path to the output etcd snapshot file")),
+        )
 }
 
 /// Execute the command
@@ -42,6 +48,11 @@ pub(crate) async fn execute(matches: &ArgMatches) -> Result<()> {
             let snapshot_path = sub_matches.get_one::<String>("filename").expect("required");
             handle_status(snapshot_path).await?;
         }
+        Some(("export-etcd", sub_matches)) => {
+            let snapshot_path = sub_matches.get_one::<String>("filename").expect("required");
+            let output = sub_matches.get_one::<String>("output").expect("required");
+            handle_export_etcd(snapshot_path, output).await?;
+        }
         _ => {}
     }
 
@@ -61,6 +72,25 @@ async fn handle_restore<P: AsRef<Path>, D: Into<PathBuf>>(
     Ok(())
 }
 
+/// handle export snapshot to an etcd snapshot file
+#[inline]
+async fn handle_export_etcd<P: AsRef<Path>, O: AsRef<Path>>(
+    snapshot_path: P,
+    output: O,
+) -> Result<()> {
+    let tempdir = tempdir()?;
+    let restore_rocks_engine = Engine::new(
+        EngineType::Rocks(tempdir.path().to_path_buf()),
+        &XLINE_TABLES,
+    )?;
+    restore_rocks_engine
+        .apply_snapshot_from_file(snapshot_path, &XLINE_TABLES)
+        .await?;
+    export_etcd_snapshot(&restore_rocks_engine, output)?;
+
+    Ok(())
+}
+
 /// Snapshot status
 #[derive(Debug, Default, Serialize)]
 struct Status {